@@ -18,13 +18,20 @@
 # along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 import argparse
+import concurrent.futures
+import hashlib
 import json
 import os
 import platform
+import queue
+import re
 import shutil
+import socket
+import statistics
 import subprocess
 import sys
 import tarfile
+import threading
 import time
 
 from typing import List
@@ -55,30 +62,156 @@ except ImportError:
     sys.exit(0)
 
 
-OVMF_URL = 'https://github.com/aero-os/ovmf-prebuilt'
-LIMINE_URL = 'https://github.com/limine-bootloader/limine'
+OVMF_URL = 'https://github.com/aero-os/ovmf-prebuilt/archive/HEAD.tar.gz'
+LIMINE_URL = 'https://github.com/limine-bootloader/limine/archive/refs/heads/v4.x-branch-binary.tar.gz'
 
 BUILD_DIR = 'build'
 BUNDLED_DIR = 'bundled'
+DIST_DIR = 'dist'
 SYSROOT_DIR = 'sysroot'
 EXTRA_FILES = 'extra-files'
 SYSROOT_CARGO_HOME = os.path.join(SYSROOT_DIR, 'cargo-home')
 BASE_FILES_DIR = 'base-files'
+LOGS_DIR = os.path.join(BUILD_DIR, 'logs')
+QMP_SOCKET = os.path.join(BUILD_DIR, 'qmp.sock')
+SIZE_REPORT = os.path.join(BUILD_DIR, 'size.json')
+KERNEL_DEBUG_INFO = os.path.join(BUILD_DIR, 'aero.debug')
+KERNEL_SYMBOL_MAP = os.path.join(BUILD_DIR, 'aero.map')
+
+# The MAC addresses of the NICs of aero and of the `--nettest` peer. They must differ, as
+# both are attached to the same multicast segment.
+AERO_MAC = '52:54:00:12:34:56'
+PEER_MAC = '52:54:00:12:34:57'
+
+# The static addresses of aero and of the `--nettest` peer, which has to be configured
+# with `NETTEST_PEER_ADDR` on its first NIC.
+NETTEST_ADDR = '10.0.0.1/24'
+NETTEST_PEER_ADDR = '10.0.0.2'
+
+# The markers printed by the kernel's network probes (see `net/nettest.rs`) that must all
+# be seen for `--nettest` to pass. Without a peer, only the loopback interface is probed.
+NETTEST_MARKERS = ['nettest: arp ok', 'nettest: ping ok', 'nettest: tcp ok']
+NETTEST_LOOPBACK_MARKERS = ['nettest: ping ok', 'nettest: tcp ok']
+NETTEST_DONE = 'nettest: done'
+
+# The default amount of seconds the network probes may run for.
+NETTEST_TIMEOUT = 300
+
+# Strings printed on the serial console when the kernel has crashed.
+PANIC_MARKERS = [' panicked at ', 'watchdog: soft lockup', 'watchdog: hard lockup']
+
+# The exit codes of QEMU when the kernel writes `ExitStatus::{Success, Failure}` to the
+# isa-debug-exit device (QEMU exits with `(code << 1) | 1`).
+QEMU_EXIT_SUCCESS = (0x10 << 1) | 1
+QEMU_EXIT_FAILURE = (0x11 << 1) | 1
+
+# Matches the `test <path> ... ok` lines printed by the kernel and userland test runners.
+TEST_PASSED = re.compile(r'test \S+ \.\.\. (\x1b\[[0-9;]*m)?ok')
+
+# The default amount of seconds the test suite may run for.
+TEST_TIMEOUT = 600
+
+# The directory the artifacts of the crashes found by `--fuzz` are saved in.
+FUZZ_CORPUS = 'fuzz-corpus'
+
+# How many of the last system calls made by the fuzzer are saved with a crash.
+FUZZ_LAST_CALLS = 32
+
+# The boot milestones timed by `--bench-boot`, as (name, kernel log message) pairs in the
+# order they are reached.
+BOOT_MILESTONES = [('handoff', 'loaded paging'),
+                   ('memory', 'loaded heap'),
+                   ('kernel', 'initialized kernel'),
+                   ('userspace', 'executing the init process')]
+
+# The amount of seconds a single `--bench-boot` boot may take.
+BENCH_TIMEOUT = 60
+
+BENCH_REPORT = os.path.join(BUILD_DIR, 'bench-boot.json')
+
+# The expected SHA-256 digests and sizes of the prebuilts in `bundled`.
+BUNDLED_CHECKSUMS = os.path.join('tools', 'bundled-checksums.json')
+
+# The prebuilts that end up in the built images.
+BUNDLED_FILES = [
+    'ovmf/ovmf-x86_64/OVMF.fd',
+    'ovmf/ovmf-aarch64/OVMF.fd',
+    'limine/limine.sys',
+    'limine/limine-cd.bin',
+    'limine/limine-cd-efi.bin',
+    'limine/limine-deploy.c',
+    'limine/limine-hdd.h',
+    'limine/BOOTX64.EFI',
+    'limine/BOOTAA64.EFI',
+]
+
+# The built-in build profiles, selected with `--profile`.
+BUILD_PROFILES = {
+    'dev': {'debug': True},
+    'release': {'debug': False},
+    'ci': {'features': ['ci'], 'bios': 'uefi', 'headless': True, 'serial_log': True},
+}
+
+# The files the defaults of the command line options are read from, in increasing order
+# of precedence.
+CONFIG_FILES = [os.path.join(os.path.expanduser('~'), '.config', 'aero', 'config.toml'),
+                'Aero.toml']
 
 LIMINE_TEMPLATE = """
-TIMEOUT=0
+TIMEOUT={timeout}
 VERBOSE=yes
+{splash}{entries}"""
 
-:aero
+LIMINE_ENTRY = """
+:{name}
 PROTOCOL=limine
 KASLR=no
-KERNEL_PATH=boot:///aero.elf
-CMDLINE=term-background=background theme-background=0x50000000
+KERNEL_PATH=boot:///{kernel}
+{resolution}CMDLINE=term-background=background theme-background=0x50000000 {cmdline}
 
 MODULE_PATH=boot:///term_background.bmp
 MODULE_CMDLINE=background
+{modules}"""
+
+LIMINE_CHAINLOAD_ENTRY = """
+:{name}
+PROTOCOL=chainload
+IMAGE_PATH=boot:///{image}
+"""
+
+GRUB_TEMPLATE = """insmod all_video
+
+set timeout={timeout}
+{resolution}{entries}"""
+
+GRUB_ENTRY = """
+menuentry "{name}" {{
+    multiboot2 /{kernel} term-background=background theme-background=0x50000000 {cmdline}
+    module2 /term_background.bmp background
+{modules}}}
+"""
+
+GRUB_MODULE = """    module2 /{path} {name}
+"""
+
+GRUB_CHAINLOAD_ENTRY = """
+menuentry "{name}" {{
+    chainloader /{image}
+}}
+"""
+
+LIMINE_MODULE = """
+MODULE_PATH=boot:///{path}
+MODULE_CMDLINE={name}
 """
 
+# The bootloaders the boot files can be packaged for, selected with `--bootloader`. With
+# `multiboot2`, the kernel is built with the `multiboot2` feature and booted from GRUB2.
+BOOTLOADERS = ['limine', 'multiboot2']
+
+# The directories created in the initramfs, on top of the ones in the sysroot.
+INITRAMFS_SKELETON = ['bin', 'dev', 'etc', 'home', 'mnt', 'proc', 'sys', 'tmp', 'usr/bin', 'var']
+
 
 class BuildInfo:
     args: argparse.Namespace
@@ -99,6 +232,59 @@ def remove_prefix(string: str, prefix: str):
         return string[:]
 
 
+def load_config(parser):
+    """
+    Loads the defaults of the command line options from the user configuration file and
+    then from the project's `Aero.toml`, which takes precedence. The keys are the names
+    of the options (for example `memory = "4G"` or `accel = "tcg"`) and the options
+    passed on the command line override them.
+
+    Returns the defaults and the build profiles, including the ones defined in the
+    `[profiles.<name>]` tables.
+    """
+    try:
+        import tomllib
+    except ImportError:
+        # Python versions older than 3.11 do not ship a TOML parser.
+        tomllib = None
+
+    options = {action.dest for action in parser._actions}
+    config = {}
+    profiles = dict(BUILD_PROFILES)
+
+    def parse_options(path, values):
+        options_values = {}
+
+        for key, value in values.items():
+            dest = key.replace('-', '_')
+
+            if dest not in options or dest in ['help', 'profile']:
+                log_error(f"{path}: unknown option `{key}`")
+                exit(1)
+
+            options_values[dest] = value
+
+        return options_values
+
+    for path in CONFIG_FILES:
+        if not os.path.exists(path):
+            continue
+
+        if not tomllib:
+            log_error(f"ignoring {path} (help: reading it requires Python 3.11 or later)")
+            continue
+
+        with open(path, 'rb') as file:
+            values = tomllib.load(file)
+
+        for name, profile in values.pop('profiles', {}).items():
+            profiles[name] = parse_options(path, profile)
+
+        config.update(parse_options(path, values))
+
+    return config, profiles
+
+
 def parse_args():
     parser = argparse.ArgumentParser(
         description="utility used to build aero kernel and userland")
@@ -108,43 +294,214 @@ def parse_args():
     check_test.add_argument('--clean',
                             default=False,
                             action='store_true',
-                            help='removes the build artifacts')
+                            help='removes the build artifacts (the `build` directory and the cargo target directories)')
 
     check_test.add_argument('--check',
                             default=False,
                             action='store_true',
                             help='checks if aero builds correctly without packaging and running it')
 
+    check_test.add_argument('--lint',
+                            default=False,
+                            action='store_true',
+                            help='runs rustfmt and clippy on the kernel, aero_syscall, aero_proc and the userland with their targets')
+
+    check_test.add_argument('--size',
+                            default=False,
+                            action='store_true',
+                            help='builds the kernel and reports the size of its sections, crates and largest functions')
+
     check_test.add_argument('--test',
                             default=False,
                             action='store_true',
-                            help='runs the aero test suite')
+                            help='runs the aero test suite headless and exits with a non-zero status if it fails')
+
+    check_test.add_argument('--fuzz',
+                            default=False,
+                            action='store_true',
+                            help=f'boots the system call fuzzer headless as the init process and saves the crashes in `{FUZZ_CORPUS}`')
+
+    check_test.add_argument('--bench-boot',
+                            type=int,
+                            default=None,
+                            metavar='RUNS',
+                            help='boots aero RUNS times headless and reports the timings of the boot milestones')
 
     check_test.add_argument('--document',
                             default=False,
                             action='store_true',
                             help='generates the documentation for the aero kernel')
 
+    parser.add_argument('--fuzz-time',
+                        type=int,
+                        default=300,
+                        metavar='SECONDS',
+                        help='how long `--fuzz` runs for')
+
+    parser.add_argument('--fuzz-seed',
+                        type=int,
+                        default=None,
+                        help='the seed of the `--fuzz` run (random by default), to reproduce a crash')
+
+    parser.add_argument('--clean-bundled',
+                        default=False,
+                        action='store_true',
+                        help='with `--clean`, also remove the downloaded OVMF and Limine prebuilts (they are downloaded again on the next build)')
+
+    parser.add_argument('--offline',
+                        default=False,
+                        action='store_true',
+                        help='do not access the network: use the previously downloaded prebuilts and sysroot and run cargo offline')
+
+    parser.add_argument('--download-retries',
+                        type=int,
+                        default=3,
+                        help='how many times a failed download of the prebuilts is retried (with an exponential backoff)')
+
+    parser.add_argument('--update-bundled-checksums',
+                        default=False,
+                        action='store_true',
+                        help=f'record the digests of the downloaded prebuilts in `{BUNDLED_CHECKSUMS}` (after bumping their versions)')
+
     parser.add_argument('--debug',
                         default=False,
                         action='store_true',
                         help='builds the kernel and userland in debug mode')
 
+    parser.add_argument('--kernel-profile',
+                        default=None,
+                        choices=['debug', 'release'],
+                        help='override the cargo profile used for the kernel (defaults to the one selected by `--debug`)')
+
+    parser.add_argument('--userland-profile',
+                        default=None,
+                        choices=['debug', 'release'],
+                        help='override the cargo profile used for the userland (defaults to the one selected by `--debug`)')
+
+    parser.add_argument('--parallel',
+                        default=False,
+                        action='store_true',
+                        help='build the kernel and userland at the same time, prefixing their output')
+
+    parser.add_argument('--sccache',
+                        default=False,
+                        action='store_true',
+                        help='cache the rustc invocations with sccache (it must be in PATH)')
+
+    parser.add_argument('--strip',
+                        default=False,
+                        action='store_true',
+                        help='boot a stripped kernel, writing its debug info to `build/aero.debug` and the symbol map used to symbolize the backtraces to `build/aero.map`')
+
+    parser.add_argument('--size-diff',
+                        default=None,
+                        metavar='REPORT',
+                        help='with `--size`, compare against a report saved by a previous `--size` run (`build/size.json`)')
+
+    parser.add_argument('--bench-diff',
+                        default=None,
+                        metavar='REPORT',
+                        help=f'with `--bench-boot`, compare against a report saved by a previous run (`{BENCH_REPORT}`)')
+
     parser.add_argument('--no-run',
                         default=False,
                         action='store_true',
                         help='doesn\'t run the built image in emulator when applicable')
 
+    parser.add_argument('--iso',
+                        default=None,
+                        metavar='PATH',
+                        help='copy the built hybrid BIOS/UEFI ISO to PATH instead of running it')
+
+    parser.add_argument('--disk-image',
+                        default=None,
+                        metavar='PATH',
+                        help='write a bootable GPT disk image (FAT32 ESP and ext2 root) to PATH instead of running aero')
+
+    parser.add_argument('--initramfs',
+                        default=False,
+                        action='store_true',
+                        help='pack the sysroot and the userland binaries into an initramfs, used as the root filesystem when no disk is attached')
+
+    parser.add_argument('--dist',
+                        default=False,
+                        action='store_true',
+                        help='write the versioned release artifacts (ISO, disk image, kernel, symbol map and checksums) to `dist` instead of running aero')
+
+    parser.add_argument('--flash',
+                        default=None,
+                        metavar='DEVICE',
+                        help='write the bootable disk image to a removable DEVICE (for example `/dev/sdb`), after asking for confirmation')
+
     parser.add_argument('--only-run',
                         default=False,
                         action='store_true',
                         help='runs aero without rebuilding. ignores any build-related flags')
 
-    parser.add_argument('--bios',
-                        type=str,
+    parser.add_argument('--bios', '--firmware',
+                        type=lambda x: 'legacy' if x == 'bios' else x,
+                        dest='bios',
                         default='legacy',
                         choices=['legacy', 'uefi'],
-                        help='run aero using the selected BIOS')
+                        help='run aero using the selected firmware: `legacy` (or `bios`) boots through SeaBIOS and the Limine BIOS stages, `uefi` through OVMF')
+
+    parser.add_argument('--ovmf-code',
+                        default=None,
+                        metavar='PATH',
+                        help='with `--bios=uefi`, the OVMF (or AAVMF) code image mapped as flash instead of the bundled OVMF build, for example a Secure Boot build')
+
+    parser.add_argument('--ovmf-vars',
+                        default=None,
+                        metavar='PATH',
+                        help='the UEFI variable store template used with `--ovmf-code`. It is copied once to `build/ovmf-vars-<arch>.fd`, which keeps the variables across runs')
+
+    parser.add_argument('--secure-boot',
+                        default=False,
+                        action='store_true',
+                        help='enable the SMM-protected variable store needed by Secure Boot builds of OVMF (x86_64 only, requires `--ovmf-code`)')
+
+    parser.add_argument('--limine-cfg',
+                        default=None,
+                        metavar='PATH',
+                        help='a custom `limine.cfg` template used instead of the built-in one. `{entries}` is replaced with the boot entries, `{cmdline}`, `{modules}` and `{resolution}` with the kernel command line, the module lines and the `RESOLUTION` option of the aero entry, `{timeout}` with the boot menu timeout and `{splash}` with the `TERM_WALLPAPER` options')
+
+    parser.add_argument('--boot-menu-timeout',
+                        type=int,
+                        default=0,
+                        metavar='SECONDS',
+                        help='show the boot menu for SECONDS before booting the default entry (it is skipped by default)')
+
+    parser.add_argument('--module',
+                        action='append',
+                        default=[],
+                        metavar='PATH[:NAME]',
+                        help='load an additional file (for example CPU microcode) into memory alongside the kernel, which finds it by NAME (the file name by default). Can be repeated')
+
+    parser.add_argument('--resolution',
+                        default=None,
+                        type=lambda x: x if re.fullmatch(r'\d+x\d+(x\d+)?', x) else parser.error(f'invalid resolution `{x}`'),
+                        metavar='WIDTHxHEIGHT[xBPP]',
+                        help='the video mode Limine sets before booting the kernel (the closest available mode is used, by default the preferred mode of the display)')
+
+    parser.add_argument('--fallback-kernel',
+                        default=None,
+                        metavar='PATH',
+                        help='add a boot menu entry booting this kernel (for example an older known-good build) with the same command line and modules')
+
+    parser.add_argument('--chainload',
+                        default=None,
+                        metavar='PATH',
+                        help='add a boot menu entry chainloading this EFI application (for example the loader of another operating system, UEFI only)')
+
+    parser.add_argument('--splash',
+                        default=None,
+                        metavar='PATH',
+                        help='a BMP image drawn by Limine behind its boot menu and messages')
+
+    parser.add_argument('--bootloader',
+                        default='limine',
+                        choices=BOOTLOADERS,
+                        help='the bootloader the boot files are packaged for. `multiboot2` boots the kernel from GRUB2 (requires `grub-mkrescue`, x86_64 only)')
 
     parser.add_argument('--features',
                         type=lambda x: x.split(','),
@@ -155,6 +512,11 @@ def parse_args():
                         default='x86_64-aero_os',
                         help='override the target triple the kernel will be built for')
 
+    parser.add_argument('--arch',
+                        default=None,
+                        choices=['x86_64', 'aarch64'],
+                        help='build and run aero for the architecture (shorthand for `--target=<arch>-aero_os`)')
+
     parser.add_argument('--la57',
                         default=False,
                         action='store_true',
@@ -165,10 +527,25 @@ def parse_args():
                         action='store_true',
                         help='build the full userland sysroot. If disabled, then the sysroot will only contain the aero_shell and the init binaries')
 
+    parser.add_argument('--userland',
+                        default=False,
+                        action='store_true',
+                        help='only build the crates in `userland/` and install the binaries into the sysroot')
+
+    parser.add_argument('--toolchain',
+                        default=False,
+                        action='store_true',
+                        help='build only the cross toolchain (binutils, gcc and rust) and the mlibc headers and libraries into the sysroot')
+
     parser.add_argument('--disable-kvm',
                         default=False,
                         action='store_true',
-                        help='disable KVM acceleration even if its available')
+                        help='disable KVM acceleration even if its available (same as `--accel=tcg`)')
+
+    parser.add_argument('--accel',
+                        default='auto',
+                        choices=['auto', 'kvm', 'hvf', 'whpx', 'tcg'],
+                        help='the accelerator used by the emulator. `auto` picks KVM or HVF when the host supports it and falls back to TCG')
 
     parser.add_argument('remaining',
                         nargs=argparse.REMAINDER,
@@ -178,34 +555,338 @@ def parse_args():
                         default='9800M',
                         help='amount of memory to allocate to QEMU')
 
+    parser.add_argument('--headless',
+                        default=False,
+                        action='store_true',
+                        help='run the emulator without a display or monitor (the serial console stays on stdio)')
+
+    parser.add_argument('--gdb',
+                        default=False,
+                        action='store_true',
+                        help='start the emulator paused with a gdb server listening on `:1234`')
+
+    parser.add_argument('--gdb-attach',
+                        default=False,
+                        action='store_true',
+                        help='like `--gdb`, but also spawns `rust-gdb` (or `gdb`) connected to the emulator with the kernel symbols loaded')
+
+    parser.add_argument('--serial-log',
+                        default=False,
+                        action='store_true',
+                        help='tee the serial console into a timestamped log file under `build/logs` and stop the emulator on a kernel panic')
+
+    parser.add_argument('--boot-marker',
+                        default=None,
+                        help='with `--serial-log`, stop the emulator successfully once this string is printed on the serial console')
+
+    parser.add_argument('--boot-timeout',
+                        type=int,
+                        default=None,
+                        help='with `--serial-log`, fail if the boot marker is not seen within this many seconds')
+
+    parser.add_argument('--qemu',
+                        default=None,
+                        metavar='PATH',
+                        help='the QEMU executable to use (defaults to `qemu-system-<arch>` from PATH)')
+
+    parser.add_argument('--qemu-device',
+                        action='append',
+                        default=[],
+                        metavar='DEVICE',
+                        help='an additional `-device` passed to QEMU (can be repeated)')
+
+    parser.add_argument('--qemu-netdev',
+                        action='append',
+                        default=[],
+                        metavar='NETDEV',
+                        help='an additional `-netdev` passed to QEMU (can be repeated)')
+
+    parser.add_argument('--qemu-drive',
+                        action='append',
+                        default=[],
+                        metavar='DRIVE',
+                        help='an additional `-drive` passed to QEMU (can be repeated)')
+
+    parser.add_argument('--disk',
+                        action='append',
+                        default=[],
+                        metavar='PATH[,format=raw|qcow2][,if=nvme|virtio|ahci|ide][,size=SIZE]',
+                        help='attach an additional disk (can be repeated). A missing image is created empty with `qemu-img` (1G by default)')
+
+    parser.add_argument('--net',
+                        default=None,
+                        choices=['user', 'tap', 'mcast', 'none'],
+                        help='the network backend of the emulator: `user` (SLIRP), `tap`, `mcast` (a socket multicast segment shared with other emulators) or `none` (defaults to the QEMU default NIC)')
+
+    parser.add_argument('--nic-model',
+                        default='e1000',
+                        help='the NIC model used with `--net` (for example `e1000`, `virtio-net-pci` or `rtl8139`)')
+
+    parser.add_argument('--hostfwd',
+                        action='append',
+                        default=[],
+                        metavar='RULE',
+                        help='with `--net=user`, forward a host port into aero (for example `tcp::8080-:80`, can be repeated)')
+
+    parser.add_argument('--tap',
+                        default='tap0',
+                        metavar='IFNAME',
+                        help='the host tap interface used with `--net=tap`')
+
+    parser.add_argument('--mcast',
+                        default='230.0.0.1:1234',
+                        metavar='ADDR:PORT',
+                        help='the multicast group of the segment used with `--net=mcast`')
+
+    parser.add_argument('--nettest',
+                        default=False,
+                        action='store_true',
+                        help=f'run aero on a multicast network segment shared with a peer emulator (see `--nettest-peer`) at {NETTEST_ADDR}, and fail unless its ARP, ping and TCP probes of the peer pass')
+
+    parser.add_argument('--nettest-peer',
+                        default=None,
+                        metavar='IMAGE',
+                        help=f'the disk image of the reference system (for example a Linux image) booted as the `--nettest` peer, which has to answer the pings at {NETTEST_PEER_ADDR}. Its serial output is written to `build/logs/nettest-peer.log`. Without a peer, only the loopback interface is probed')
+
+    parser.add_argument('--qmp',
+                        default=False,
+                        action='store_true',
+                        help=f'open a QMP socket at `{QMP_SOCKET}`, used by `--qmp-send` and to cleanly quit the emulator when a `--serial-log` run finishes')
+
+    parser.add_argument('--qmp-send',
+                        nargs='+',
+                        default=None,
+                        metavar='COMMAND',
+                        help='send a command to the emulator started with `--qmp` and exit: `screenshot <path>`, `savevm <name>`, `loadvm <name>` or `quit`')
+
+    parser.add_argument('--screenshot',
+                        default=None,
+                        metavar='PATH',
+                        help='with `--qmp` and `--serial-log`, save a screenshot (PPM) of the display to PATH when the run finishes')
+
+    parser.add_argument('--record',
+                        nargs='?',
+                        const=os.path.join(BUILD_DIR, 'replay.bin'),
+                        default=None,
+                        metavar='PATH',
+                        help='record the execution (including the disk I/O) for a deterministic replay, in `build/replay.bin` by default')
+
+    parser.add_argument('--replay',
+                        nargs='?',
+                        const=os.path.join(BUILD_DIR, 'replay.bin'),
+                        default=None,
+                        metavar='PATH',
+                        help='replay an execution recorded with `--record` (combine with `--gdb` to debug it)')
+
+    parser.add_argument('--smp',
+                        default='1',
+                        help='number of CPUs to emulate')
+
+    parser.add_argument('--cpu',
+                        default=None,
+                        help='override the CPU model passed to QEMU (for example `Skylake-Server`)')
+
+    parser.add_argument('--cmdline',
+                        default='',
+                        help='additional kernel command line options (for example `nosmp root=nvme0n1p1`)')
+
+    parser.add_argument('--profile',
+                        default=None,
+                        help=f'apply a named set of options ({", ".join(BUILD_PROFILES)} or a `[profiles.<name>]` table of the config file)')
+
+    config, profiles = load_config(parser)
+    parser.set_defaults(**config)
+
+    # The options of the profile are applied on top of the config file, but the ones
+    # passed on the command line still override them.
+    profile = parser.parse_known_args()[0].profile
+
+    if profile:
+        if profile not in profiles:
+            log_error(f"unknown profile `{profile}`")
+            exit(1)
+
+        parser.set_defaults(**profiles[profile])
+
     return parser.parse_args()
 
 
+def get_profile(args, component: str) -> str:
+    """
+    Returns the cargo profile (`debug` or `release`) selected for `component`.
+    """
+    profile = getattr(args, f'{component}_profile')
+
+    if profile:
+        return profile
+
+    return 'debug' if args.debug else 'release'
+
+
 def run_command(args, **kwargs):
     output = subprocess.run(args, **kwargs)
 
     return output.returncode, output.stdout, output.stderr
 
 
-def download_bundled():
+def fetch_bundled(url, path, retries=3):
+    """
+    Downloads the tarball of a prebuilt and extracts it into `path`, retrying with an
+    exponential backoff on failure. An interrupted download is kept next to `path` and
+    resumed with an HTTP range request, and the tarball is only extracted once it is
+    complete so that a partial download is never mistaken for a complete one.
+    """
+    archive_path = f'{path}.tar.gz.partial'
+    extract_path = f'{path}.partial'
+
+    for attempt in range(retries + 1):
+        try:
+            offset = os.path.getsize(archive_path) if os.path.exists(archive_path) else 0
+            headers = {'Range': f'bytes={offset}-'} if offset else {}
+
+            if offset:
+                log_info(f"resuming the download of {url} at {offset} bytes")
+            else:
+                log_info(f"downloading {url}")
+
+            with requests.get(url, headers=headers, stream=True, timeout=30) as response:
+                # 416 is returned if the partial download is already complete.
+                if response.status_code != 416:
+                    response.raise_for_status()
+
+                    # The server ignored the range and is sending the whole file.
+                    mode = 'ab' if response.status_code == 206 else 'wb'
+
+                    with open(archive_path, mode) as file:
+                        for chunk in response.iter_content(chunk_size=1 << 16):
+                            file.write(chunk)
+
+            if os.path.exists(extract_path):
+                shutil.rmtree(extract_path)
+
+            try:
+                with tarfile.open(archive_path) as archive:
+                    # Strip the `<repository>-<branch>` directory the tarball is rooted at.
+                    members = []
+
+                    for member in archive.getmembers():
+                        _, _, name = member.name.partition('/')
+
+                        if name:
+                            member.name = name
+                            members.append(member)
+
+                    archive.extractall(extract_path, members=members)
+            except (tarfile.TarError, EOFError):
+                # The tarball is complete but corrupted, download it again.
+                os.remove(archive_path)
+                raise
+
+            os.rename(extract_path, path)
+            os.remove(archive_path)
+            return
+        except (requests.RequestException, tarfile.TarError, EOFError, OSError) as error:
+            if attempt < retries:
+                delay = 2 ** attempt
+                log_error(f"failed to download {url} ({error}), retrying in {delay} seconds "
+                          f"({attempt + 1}/{retries})")
+                time.sleep(delay)
+
+    log_error(f"failed to download {url} after {retries + 1} attempts")
+    exit(1)
+
+
+def download_bundled(offline=False, verify=True, retries=3):
     if not os.path.exists(BUNDLED_DIR):
         os.makedirs(BUNDLED_DIR)
 
     ovmf_path = os.path.join(BUNDLED_DIR, 'ovmf')
     limine_path = os.path.join(BUNDLED_DIR, 'limine')
 
+    if offline:
+        missing = [path for path in [ovmf_path, limine_path, SYSROOT_DIR]
+                   if not os.path.exists(path)]
+
+        if missing:
+            log_error(f"running offline but {', '.join(missing)} have not been downloaded "
+                      "(help: run once without `--offline` to populate them)")
+            exit(1)
+
     if not os.path.exists(ovmf_path):
-        run_command(['git', 'clone', '--depth', '1', OVMF_URL, ovmf_path])
+        fetch_bundled(OVMF_URL, ovmf_path, retries=retries)
 
     if not os.path.exists(limine_path):
-        run_command(['git', 'clone', '--branch', 'v4.x-branch-binary',
-                    '--depth', '1', LIMINE_URL, limine_path])
+        fetch_bundled(LIMINE_URL, limine_path, retries=retries)
+
+    if verify:
+        verify_bundled()
 
     if not os.path.exists(SYSROOT_DIR):
         log_info("building minimal sysroot")
         build_userland_sysroot(True)
 
 
+def hash_bundled_file(path):
+    digest = hashlib.sha256()
+
+    with open(path, 'rb') as file:
+        for chunk in iter(lambda: file.read(1 << 16), b''):
+            digest.update(chunk)
+
+    return {'sha256': digest.hexdigest(), 'size': os.path.getsize(path)}
+
+
+def update_bundled_checksums():
+    checksums = {}
+
+    for name in BUNDLED_FILES:
+        path = os.path.join(BUNDLED_DIR, name)
+
+        if not os.path.exists(path):
+            log_error(f"bundled/{name} is missing, not recording the digests")
+            exit(1)
+
+        checksums[name] = hash_bundled_file(path)
+
+    with open(BUNDLED_CHECKSUMS, 'w') as file:
+        json.dump(checksums, file, indent=4, sort_keys=True)
+        file.write('\n')
+
+    log_info(f"recorded the digests of {len(checksums)} prebuilts in {BUNDLED_CHECKSUMS}")
+
+
+def verify_bundled():
+    """
+    Verifies the downloaded prebuilts against the digests and sizes recorded in
+    `BUNDLED_CHECKSUMS` and refuses to continue if any of them do not match or were not
+    recorded.
+    """
+    if not os.path.exists(BUNDLED_CHECKSUMS):
+        log_error(f"{BUNDLED_CHECKSUMS} not found, refusing to use unverified prebuilts "
+                  "(help: run `./aero.py --update-bundled-checksums` to record them)")
+        exit(1)
+
+    with open(BUNDLED_CHECKSUMS) as file:
+        checksums = json.load(file)
+
+    mismatched = []
+
+    for name in BUNDLED_FILES:
+        path = os.path.join(BUNDLED_DIR, name)
+        expected = checksums.get(name)
+
+        if expected is None or not os.path.exists(path) or hash_bundled_file(path) != expected:
+            mismatched.append(name)
+
+    if mismatched:
+        for name in mismatched:
+            log_error(f"bundled/{name} is missing, has no recorded digest or does not match it")
+
+        log_error("refusing to use corrupted or tampered prebuilts "
+                  "(help: run `./aero.py --clean --clean-bundled` to download them again)")
+        exit(1)
+
+
 def extract_artifacts(stdout):
     result = []
     lines = stdout.splitlines()
@@ -220,13 +901,40 @@ def extract_artifacts(stdout):
     return result
 
 
-def build_cargo_workspace(cwd, command, args, cargo="cargo"):
-    code, _, _ = run_command([cargo, command, *args], cwd=cwd)
+def run_prefixed(command, prefix, **kwargs):
+    """
+    Runs `command` with each line of its output prefixed with `[prefix]`, so that the
+    output of commands running concurrently can be told apart.
+    """
+    process = subprocess.Popen(command,
+                               stdout=subprocess.PIPE,
+                               stderr=subprocess.STDOUT,
+                               **kwargs)
+
+    for line in process.stdout:
+        sys.stdout.write(f"[{prefix}] {line.decode('utf-8', errors='replace')}")
+        sys.stdout.flush()
+
+    return process.wait()
+
+
+def build_cargo_workspace(cwd, command, args, cargo="cargo", prefix=None):
+    if prefix:
+        code = run_prefixed([cargo, command, *args], prefix, cwd=cwd)
+    else:
+        code, _, _ = run_command([cargo, command, *args], cwd=cwd)
 
     if code != 0:
         return None
 
-    _, stdout, _ = run_command([cargo, command, *args, '--message-format=json'],
+    # The arguments after `--` are passed to the tool that cargo runs.
+    if '--' in args:
+        split = args.index('--')
+        json_args = [*args[:split], '--message-format=json', *args[split:]]
+    else:
+        json_args = [*args, '--message-format=json']
+
+    _, stdout, _ = run_command([cargo, command, *json_args],
                                stdout=subprocess.PIPE,
                                stderr=subprocess.DEVNULL,
                                cwd=cwd)
@@ -234,26 +942,47 @@ def build_cargo_workspace(cwd, command, args, cargo="cargo"):
     return extract_artifacts(stdout)
 
 
-def build_kernel(args):
+def build_kernel(args, prefix=None):
     command = 'build'
     cmd_args = ['--package', 'aero_kernel',
                 '--target', f'.cargo/{args.target}.json']
 
-    if not args.debug:
+    if get_profile(args, 'kernel') == 'release':
         cmd_args += ['--release']
 
+    if args.offline:
+        cmd_args += ['--offline']
+
     if args.test:
         command = 'test'
         cmd_args += ['--no-run']
     elif args.check:
         command = 'check'
+    elif args.lint:
+        command = 'clippy'
+        cmd_args = ['--workspace', *cmd_args[2:]]
     elif args.document:
         command = 'doc'
 
-    if args.features:
-        cmd_args += ['--features', ','.join(args.features)]
+    features = list(args.features)
+
+    # The kernel only exits the emulator through isa-debug-exit with the `ci` feature,
+    # without it the test run would only end with the timeout.
+    if args.test and 'ci' not in features:
+        features.append('ci')
+
+    # The Multiboot2 header and entry point are only built with the `multiboot2` feature.
+    if args.bootloader == 'multiboot2' and 'multiboot2' not in features:
+        features.append('multiboot2')
+
+    if features:
+        cmd_args += ['--features', ','.join(features)]
 
-    return build_cargo_workspace('src', command, cmd_args)
+    if command == 'clippy':
+        # Make the lints fail the build, so that `--lint` fails in CI.
+        cmd_args += ['--', '-D', 'warnings']
+
+    return build_cargo_workspace('src', command, cmd_args, prefix=prefix)
 
 
 # Helper function for symlink since os.symlink uses path
@@ -263,7 +992,29 @@ def symlink_rel(src, dst):
     os.symlink(rel_path_src, dst)
 
 
-def build_userland_sysroot(minimal):
+TOOLCHAIN_TOOLS = ['host-binutils', 'host-gcc', 'host-rust']
+TOOLCHAIN_PACKAGES = ['mlibc-headers', 'mlibc']
+TOOLCHAIN_ENV = os.path.join(SYSROOT_DIR, 'toolchain.env')
+
+
+def write_toolchain_env():
+    """
+    Records the location of the cross toolchain and the sysroot in a shell script, which
+    can be sourced when porting software by hand.
+    """
+    tool_dir = os.path.abspath(get_userland_tool())
+    system_root = os.path.abspath(os.path.join(SYSROOT_DIR, 'system-root'))
+
+    with open(TOOLCHAIN_ENV, 'w') as env:
+        env.write(f'export AERO_SYSROOT="{system_root}"\n')
+        env.write(f'export PATH="{tool_dir}/host-gcc/bin:{tool_dir}/host-binutils/bin:{tool_dir}/host-rust/bin:$PATH"\n')
+        env.write('export CC=x86_64-aero-gcc\n')
+        env.write('export CXX=x86_64-aero-g++\n')
+
+    log_info(f"toolchain environment written to {TOOLCHAIN_ENV} (use `source {TOOLCHAIN_ENV}`)")
+
+
+def build_userland_sysroot(minimal, toolchain=False):
     if not os.path.exists(SYSROOT_DIR):
         os.mkdir(SYSROOT_DIR)
 
@@ -304,14 +1055,23 @@ def build_userland_sysroot(minimal):
     if minimal:
         command = ['install', '-u', 'bash', 'coreutils']
 
-    code, _, _ = run_xbstrap(command)
+    commands = [command]
 
-    if code != 0:
-        log_error(f"`xbstrap {' '.join(command)}` exited with a non-zero status code")
-        exit(1)
+    if toolchain:
+        commands = [['install-tool', *TOOLCHAIN_TOOLS],
+                    ['install', '-u', *TOOLCHAIN_PACKAGES]]
+
+    for command in commands:
+        code, _, _ = run_xbstrap(command)
+
+        if code != 0:
+            log_error(f"`xbstrap {' '.join(command)}` exited with a non-zero status code")
+            exit(1)
+
+    write_toolchain_env()
 
 
-def build_userland(args):
+def build_userland(args, prefix=None):
     # We need to check if we have host-rust in-order for us to build
     # our rust userland applications in `userland/`.
     host_cargo = os.path.join(SYSROOT_DIR, "tools/host-rust")
@@ -320,6 +1080,10 @@ def build_userland(args):
         log_error("host-rust not built as a part of the sysroot, skipping compilation of `userland/`")
         return []
 
+    if not args.target.startswith('x86_64'):
+        log_error("the userland can only be built for x86_64, skipping compilation of `userland/`")
+        return []
+
     HOST_RUST = "host-rust/bin/rustc"
     HOST_GCC = "host-gcc/bin/x86_64-aero-gcc"
     HOST_BINUTILS = "host-binutils/x86_64-aero/bin"
@@ -345,83 +1109,378 @@ def build_userland(args):
 
                 "-Z", "unstable-options"]
 
-    if not args.debug:
+    if get_profile(args, 'userland') == 'release':
         cmd_args += ['--release']
 
+    if args.offline:
+        cmd_args += ['--offline']
+
     if args.check:
         command = 'check'
+    elif args.lint:
+        command = 'clippy'
 
     if args.test:
-        return build_cargo_workspace('userland', 'build', ['--package', 'utest', *cmd_args])
+        return build_cargo_workspace('userland', 'build', ['--package', 'utest', *cmd_args],
+                                     prefix=prefix)
+    elif args.fuzz:
+        return build_cargo_workspace('userland', 'build', ['--package', 'fuzz', *cmd_args],
+                                     prefix=prefix)
+    elif command == 'clippy':
+        return build_cargo_workspace('userland', command, [*cmd_args, '--', '-D', 'warnings'],
+                                     prefix=prefix)
     else:
-        return build_cargo_workspace('userland', command, cmd_args)
+        return build_cargo_workspace('userland', command, cmd_args, prefix=prefix)
 
     # TODO: Userland check
     # elif args.check:
     #     command = 'check'
 
 
-def generate_docs(args):
-    doc_dir = os.path.join('src', 'target', args.target, 'doc')
-    out_dir = os.path.join(BUILD_DIR, 'web')
+def lint(args) -> bool:
+    """
+    Checks the formatting of the kernel and userland workspaces and runs clippy on them
+    with the same targets and features that they are built with.
+    """
+    success = True
 
-    if os.path.exists(out_dir):
-        shutil.rmtree(out_dir)
+    for workspace in ['src', 'userland']:
+        code, _, _ = run_command(['cargo', 'fmt', '--all', '--', '--check'], cwd=workspace)
 
-    shutil.copytree('web', out_dir, dirs_exist_ok=True)
-    shutil.copytree(doc_dir, out_dir, dirs_exist_ok=True)
+        if code != 0:
+            log_error(f"`{workspace}` is not formatted (help: run `cargo fmt --all` in `{workspace}`)")
+            success = False
 
+    if build_kernel(args) is None:
+        log_error("clippy failed on the kernel workspace")
+        success = False
 
-def prepare_iso(args, kernel_bin, user_bins):
-    log_info("preparing ISO")
+    if build_userland(args) is None:
+        log_error("clippy failed on the userland workspace")
+        success = False
 
-    if not os.path.exists(BUILD_DIR):
-        os.makedirs(BUILD_DIR)
+    return success
 
-    iso_path = os.path.join(BUILD_DIR, 'aero.iso')
-    iso_root = os.path.join(BUILD_DIR, 'iso_root')
-    limine_path = os.path.join(BUNDLED_DIR, 'limine')
 
-    if os.path.exists(iso_root):
-        shutil.rmtree(iso_root)
+def build_components(args):
+    """
+    Builds the userland and the kernel. They are independent cargo workspaces, so with
+    `--parallel` both are built at the same time with their output prefixed.
+    """
+    if not args.parallel:
+        return build_userland(args), build_kernel(args)
 
-    os.makedirs(iso_root)
+    with concurrent.futures.ThreadPoolExecutor(max_workers=2) as pool:
+        userland = pool.submit(build_userland, args, 'userland')
+        kernel = pool.submit(build_kernel, args, 'kernel')
 
-    shutil.copy(kernel_bin, os.path.join(iso_root, 'aero.elf'))
-    shutil.copy(os.path.join('src', '.cargo', 'term_background.bmp'), iso_root)
-    shutil.copy(os.path.join(limine_path, 'limine.sys'), iso_root)
-    shutil.copy(os.path.join(limine_path, 'limine-cd.bin'), iso_root)
-    shutil.copy(os.path.join(limine_path, 'limine-cd-efi.bin'), iso_root)
+        user_bins = userland.result()
+        kernel_bin = kernel.result()
 
-    efi_boot = os.path.join(iso_root, "EFI", "BOOT")
-    os.makedirs(efi_boot)
+    failed = [name for name, result in [('userland', user_bins), ('kernel', kernel_bin)]
+              if result is None]
 
-    shutil.copy(os.path.join(limine_path, 'BOOTAA64.EFI'), efi_boot)
-    shutil.copy(os.path.join(limine_path, 'BOOTX64.EFI'), efi_boot)
+    if failed:
+        log_error(f"failed to build: {', '.join(failed)}")
+        exit(1)
 
-    sysroot_dir = os.path.join(SYSROOT_DIR, 'system-root')
-    for file in user_bins:
-        bin_name = os.path.basename(file)
-        dest_dir = os.path.join(sysroot_dir, "usr", "bin")
-        os.makedirs(dest_dir, exist_ok=True)
-        shutil.copy(file, os.path.join(dest_dir, bin_name))
+    return user_bins, kernel_bin
+
+
+def get_crate_name(symbol: str) -> str:
+    """
+    Returns the crate a demangled symbol belongs to, for example `core` for
+    `core::fmt::write` or `alloc` for `<alloc::string::String as core::fmt::Write>::write_str`.
+    """
+    symbol = symbol.lstrip('<&*')
 
-    with open(os.path.join(iso_root, 'limine.cfg'), 'w') as limine_cfg:
-        limine_cfg.write(LIMINE_TEMPLATE)
+    if symbol.startswith('_') or '::' not in symbol:
+        return '[unknown]'
 
-    code, _, xorriso_stderr = run_command([
-        'xorriso', '-as', 'mkisofs', '-b', 'limine-cd.bin', '-no-emul-boot', '-boot-load-size', '4',
-        '-boot-info-table', '--efi-boot', 'limine-cd-efi.bin', '-efi-boot-part',
-        '--efi-boot-image', '--protective-msdos-label', iso_root, '-o', iso_path
-    ], stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    return symbol.split('::', 1)[0].split(' ')[-1]
+
+
+def get_size_report(kernel_bin) -> dict:
+    """
+    Returns the size of the allocated sections of the kernel, of the code of each crate
+    and of each function.
+    """
+    sections = {}
+    code, stdout, _ = run_command(['readelf', '--wide', '--section-headers', kernel_bin],
+                                  stdout=subprocess.PIPE)
 
     if code != 0:
-        log_error('failed to create the ISO image')
-        log_error(xorriso_stderr.decode('utf-8'))
+        log_error('failed to read the kernel sections')
+        exit(1)
 
-        return None
+    for line in stdout.decode('utf-8').splitlines():
+        # [Nr] Name Type Address Off Size ES Flg Lk Inf Al
+        fields = line.replace('[ ', '[').split()
 
-    limine_deploy = os.path.join(limine_path, 'limine-deploy')
+        if len(fields) > 7 and fields[0].startswith('[') and fields[0] != '[Nr]' and 'A' in fields[7]:
+            sections[fields[1]] = int(fields[5], 16)
+
+    functions = {}
+    code, stdout, _ = run_command(['nm', '--defined-only', '--print-size', '--demangle', kernel_bin],
+                                  stdout=subprocess.PIPE)
+
+    if code != 0:
+        log_error('failed to read the kernel symbols')
+        exit(1)
+
+    for line in stdout.decode('utf-8').splitlines():
+        fields = line.split(' ', 3)
+
+        if len(fields) == 4 and fields[2] in 'tT':
+            functions[fields[3]] = functions.get(fields[3], 0) + int(fields[1], 16)
+
+    crates = {}
+
+    for name, size in functions.items():
+        crate = get_crate_name(name)
+        crates[crate] = crates.get(crate, 0) + size
+
+    return {'sections': sections, 'crates': crates, 'functions': functions}
+
+
+def print_size_table(title, sizes, previous=None, limit=None):
+    print(f"\n{title}")
+
+    rows = sorted(sizes.items(), key=lambda item: item[1], reverse=True)
+
+    for name, size in rows[:limit]:
+        if previous is None:
+            print(f"  {size:>10}  {name}")
+        else:
+            delta = size - previous.get(name, 0)
+            print(f"  {size:>10} {f'{delta:+}' if delta else '':>8}  {name}")
+
+    if previous is not None:
+        for name in sorted(set(previous) - set(sizes)):
+            print(f"  {0:>10} {-previous[name]:>+8}  {name} (removed)")
+
+    print(f"  {sum(sizes.values()):>10}  total")
+
+
+def report_size(args, kernel_bin):
+    """
+    Prints the size of the kernel sections, crates and of the largest functions and saves
+    the report in `SIZE_REPORT`, so that it can be compared against with `--size-diff`.
+    """
+    report = get_size_report(kernel_bin)
+    previous = {}
+
+    if args.size_diff:
+        with open(args.size_diff) as file:
+            previous = json.load(file)
+
+    print_size_table('sections', report['sections'], previous.get('sections'))
+    print_size_table('crates', report['crates'], previous.get('crates'))
+    print_size_table('functions (largest 30)', report['functions'],
+                     previous.get('functions'), limit=30)
+
+    os.makedirs(BUILD_DIR, exist_ok=True)
+
+    with open(SIZE_REPORT, 'w') as file:
+        json.dump(report, file, indent=4, sort_keys=True)
+
+    log_info(f"saved the size report to {SIZE_REPORT}")
+
+
+def generate_docs(args):
+    doc_dir = os.path.join('src', 'target', args.target, 'doc')
+    out_dir = os.path.join(BUILD_DIR, 'web')
+
+    if os.path.exists(out_dir):
+        shutil.rmtree(out_dir)
+
+    shutil.copytree('web', out_dir, dirs_exist_ok=True)
+    shutil.copytree(doc_dir, out_dir, dirs_exist_ok=True)
+
+
+def install_userland(user_bins):
+    """
+    Copies the userland binaries into `/usr/bin` of the sysroot, from where they are
+    packed into the disk image and the initramfs.
+    """
+    dest_dir = os.path.join(SYSROOT_DIR, 'system-root', 'usr', 'bin')
+    os.makedirs(dest_dir, exist_ok=True)
+
+    for file in user_bins:
+        shutil.copy(file, os.path.join(dest_dir, os.path.basename(file)))
+
+
+def prepare_initramfs(archive_path, sysroot_dir):
+    """
+    Packs the base files and the sysroot (which contains the userland binaries) into a
+    ustar archive on top of the rootfs skeleton.
+    """
+    log_info("preparing initramfs")
+
+    with tarfile.open(archive_path, 'w', format=tarfile.USTAR_FORMAT) as archive:
+        for directory in INITRAMFS_SKELETON:
+            info = tarfile.TarInfo(directory)
+            info.type = tarfile.DIRTYPE
+            info.mode = 0o755
+            archive.addfile(info)
+
+        for root in [BASE_FILES_DIR, sysroot_dir]:
+            for entry in sorted(os.listdir(root)):
+                archive.add(os.path.join(root, entry), arcname=entry)
+
+
+def strip_kernel(kernel_bin, stripped_path):
+    """
+    Splits the debug info of the kernel into `KERNEL_DEBUG_INFO`, writes the sorted
+    symbol map used by the panic symbolizer into `KERNEL_SYMBOL_MAP` and writes the
+    stripped kernel to `stripped_path`.
+    """
+    log_info("stripping the kernel")
+
+    code, _, _ = run_command(['objcopy', '--only-keep-debug', kernel_bin, KERNEL_DEBUG_INFO])
+
+    if code != 0:
+        log_error('failed to extract the kernel debug info')
+        exit(1)
+
+    code, stdout, _ = run_command(['nm', '--defined-only', '--print-size', '--numeric-sort', kernel_bin],
+                                  stdout=subprocess.PIPE)
+
+    if code != 0:
+        log_error('failed to read the kernel symbols')
+        exit(1)
+
+    with open(KERNEL_SYMBOL_MAP, 'w') as symbol_map:
+        for line in stdout.decode('utf-8').splitlines():
+            fields = line.split()
+
+            # Symbols without a size only have the address, type and name fields.
+            if len(fields) == 3:
+                fields.insert(1, '0')
+
+            address, size, kind, name = fields
+
+            if kind in 'tT':
+                symbol_map.write(f'{int(address, 16):x} {int(size, 16):x} {name}\n')
+
+    code, _, _ = run_command(['objcopy', '--strip-all',
+                              f'--add-gnu-debuglink={KERNEL_DEBUG_INFO}',
+                              kernel_bin, stripped_path])
+
+    if code != 0:
+        log_error('failed to strip the kernel')
+        exit(1)
+
+
+def package_limine(args, boot_root, cmdline, modules):
+    """
+    Lays out the Limine stages in `boot_root` and generates `limine.cfg` (from the
+    `--limine-cfg` template if set), with an entry booting the kernel with `cmdline` and
+    loading `modules`. Limine reads it from the boot partition on every boot, so it can be
+    edited in a written image without rebuilding.
+    """
+    limine_path = os.path.join(BUNDLED_DIR, 'limine')
+
+    shutil.copy(os.path.join(limine_path, 'limine.sys'), boot_root)
+    shutil.copy(os.path.join(limine_path, 'limine-cd.bin'), boot_root)
+    shutil.copy(os.path.join(limine_path, 'limine-cd-efi.bin'), boot_root)
+
+    efi_boot = os.path.join(boot_root, "EFI", "BOOT")
+    os.makedirs(efi_boot)
+
+    shutil.copy(os.path.join(limine_path, 'BOOTAA64.EFI'), efi_boot)
+    shutil.copy(os.path.join(limine_path, 'BOOTX64.EFI'), efi_boot)
+
+    limine_modules = ''.join(LIMINE_MODULE.format(path=path, name=name)
+                             for path, name in modules)
+
+    template = LIMINE_TEMPLATE
+    resolution = f'RESOLUTION={args.resolution}\n' if args.resolution else ''
+    splash = ''
+
+    if args.splash:
+        shutil.copy(args.splash, os.path.join(boot_root, 'splash.bmp'))
+        splash = 'TERM_WALLPAPER=boot:///splash.bmp\nTERM_WALLPAPER_STYLE=centered\n'
+
+    def kernel_entry(name, kernel):
+        return LIMINE_ENTRY.format(name=name, kernel=kernel, resolution=resolution,
+                                   cmdline=cmdline, modules=limine_modules)
+
+    entries = kernel_entry('aero', 'aero.elf')
+    timeout = args.boot_menu_timeout
+
+    if args.fallback_kernel:
+        shutil.copy(args.fallback_kernel, os.path.join(boot_root, 'aero-fallback.elf'))
+        entries += kernel_entry('aero (fallback kernel)', 'aero-fallback.elf')
+
+    if args.chainload:
+        shutil.copy(args.chainload, os.path.join(efi_boot, 'chainload.efi'))
+        entries += LIMINE_CHAINLOAD_ENTRY.format(name='chainload',
+                                                 image='EFI/BOOT/chainload.efi')
+
+    # The other entries can only be picked from the menu.
+    if (args.fallback_kernel or args.chainload) and timeout == 0:
+        timeout = 5
+
+    if args.limine_cfg:
+        with open(args.limine_cfg) as template_file:
+            template = template_file.read()
+
+    with open(os.path.join(boot_root, 'limine.cfg'), 'w') as limine_cfg:
+        limine_cfg.write(template.format(entries=entries, cmdline=cmdline,
+                                         modules=limine_modules, timeout=timeout,
+                                         resolution=resolution, splash=splash))
+
+
+def package_grub(args, boot_root, cmdline, modules):
+    """
+    Generates `boot/grub/grub.cfg` in `boot_root`, with an entry booting the kernel with
+    `cmdline` through Multiboot2 and loading `modules`. `grub-mkrescue` adds the GRUB
+    images when it creates the ISO.
+    """
+    grub_dir = os.path.join(boot_root, 'boot', 'grub')
+    os.makedirs(grub_dir)
+
+    grub_modules = ''.join(GRUB_MODULE.format(path=path, name=name)
+                           for path, name in modules)
+
+    resolution = f'set gfxmode={args.resolution}\n' if args.resolution else ''
+
+    def kernel_entry(name, kernel):
+        return GRUB_ENTRY.format(name=name, kernel=kernel, cmdline=cmdline,
+                                 modules=grub_modules)
+
+    entries = kernel_entry('aero', 'aero.elf')
+    timeout = args.boot_menu_timeout
+
+    if args.fallback_kernel:
+        shutil.copy(args.fallback_kernel, os.path.join(boot_root, 'aero-fallback.elf'))
+        entries += kernel_entry('aero (fallback kernel)', 'aero-fallback.elf')
+
+    if args.chainload:
+        efi_boot = os.path.join(boot_root, "EFI", "BOOT")
+        os.makedirs(efi_boot)
+
+        shutil.copy(args.chainload, os.path.join(efi_boot, 'chainload.efi'))
+        entries += GRUB_CHAINLOAD_ENTRY.format(name='chainload',
+                                               image='EFI/BOOT/chainload.efi')
+
+    # The other entries can only be picked from the menu.
+    if (args.fallback_kernel or args.chainload) and timeout == 0:
+        timeout = 5
+
+    with open(os.path.join(grub_dir, 'grub.cfg'), 'w') as grub_cfg:
+        grub_cfg.write(GRUB_TEMPLATE.format(entries=entries, timeout=timeout,
+                                            resolution=resolution))
+
+
+def deploy_limine(image_path) -> bool:
+    """
+    Installs the Limine BIOS stages into the ISO or disk image at `image_path`, building
+    `limine-deploy` first if needed.
+    """
+    limine_path = os.path.join(BUNDLED_DIR, 'limine')
+    limine_deploy = os.path.join(limine_path, 'limine-deploy')
 
     if not os.path.exists(limine_deploy):
         code, _, limine_build_stderr = run_command(['make', '-C', limine_path],
@@ -432,42 +1491,863 @@ def prepare_iso(args, kernel_bin, user_bins):
             log_error(limine_build_stderr.decode('utf8'))
             exit(1)
 
-    code, _, limine_deploy_stderr = run_command([limine_deploy, iso_path],
+    code, _, limine_deploy_stderr = run_command([limine_deploy, image_path],
                                                 stdout=subprocess.PIPE,
                                                 stderr=subprocess.PIPE)
 
     if code != 0:
         log_error('failed to install Limine')
-        log_error(limine_deploy_stderr)
+        log_error(limine_deploy_stderr.decode('utf-8'))
+        return False
+
+    return True
+
+
+def prepare_iso(args, kernel_bin, user_bins):
+    if not os.path.exists(BUILD_DIR):
+        os.makedirs(BUILD_DIR)
+
+    iso_path = os.path.join(BUILD_DIR, 'aero.iso')
+    iso_root = os.path.join(BUILD_DIR, 'iso_root')
+
+    # The ISO is only rebuilt when its inputs changed. The initramfs depends on the whole
+    # sysroot, so it is always rebuilt.
+    stamp_path = f'{iso_path}.inputs'
+    inputs_hash = hash_inputs([kernel_bin, *user_bins,
+                               *filter(None, [args.limine_cfg, args.splash,
+                                              args.fallback_kernel, args.chainload]),
+                               *[spec.partition(':')[0] for spec in args.module],
+                               *[os.path.join(BUNDLED_DIR, name) for name in BUNDLED_FILES
+                                 if name.startswith('limine/')]],
+                              extra=f'{args.cmdline} strip={args.strip} bootloader={args.bootloader} '
+                                    f'timeout={args.boot_menu_timeout} resolution={args.resolution} '
+                                    f'modules={args.module}')
+
+    if not args.initramfs and os.path.exists(iso_path) and os.path.exists(stamp_path):
+        with open(stamp_path) as stamp:
+            if stamp.read() == inputs_hash:
+                log_info("ISO is up to date")
+
+                install_userland(user_bins)
+                ensure_disk_image()
+                return iso_path
+
+    log_info("preparing ISO")
+
+    if os.path.exists(iso_root):
+        shutil.rmtree(iso_root)
+
+    os.makedirs(iso_root)
+
+    if args.strip:
+        strip_kernel(kernel_bin, os.path.join(iso_root, 'aero.elf'))
+        shutil.copy(KERNEL_SYMBOL_MAP, os.path.join(iso_root, 'aero.map'))
+    else:
+        shutil.copy(kernel_bin, os.path.join(iso_root, 'aero.elf'))
+    shutil.copy(os.path.join('src', '.cargo', 'term_background.bmp'), iso_root)
+
+    sysroot_dir = os.path.join(SYSROOT_DIR, 'system-root')
+    install_userland(user_bins)
+
+    # The modules loaded alongside the kernel, as (file name, module name) pairs.
+    modules = []
+    cmdline = args.cmdline
+
+    if args.initramfs:
+        prepare_initramfs(os.path.join(iso_root, 'initramfs.tar'), sysroot_dir)
+        modules.append(('initramfs.tar', 'initramfs'))
+        cmdline += ' initrd=initramfs'
+
+    if args.strip:
+        modules.append(('aero.map', 'symbols'))
+        cmdline += ' symbols=symbols'
+
+    if args.module:
+        os.makedirs(os.path.join(iso_root, 'modules'))
+
+    for spec in args.module:
+        path, _, name = spec.partition(':')
+        file_name = os.path.basename(path)
+
+        shutil.copy(path, os.path.join(iso_root, 'modules', file_name))
+        modules.append((f'modules/{file_name}', name or file_name))
+
+    if args.bootloader == 'multiboot2':
+        package_grub(args, iso_root, cmdline, modules)
+
+        # GRUB is installed into the ISO by `grub-mkrescue` itself.
+        mkisofs_command = ['grub-mkrescue', '-o', iso_path, iso_root]
+    else:
+        package_limine(args, iso_root, cmdline, modules)
+
+        mkisofs_command = [
+            'xorriso', '-as', 'mkisofs', '-b', 'limine-cd.bin', '-no-emul-boot', '-boot-load-size', '4',
+            '-boot-info-table', '--efi-boot', 'limine-cd-efi.bin', '-efi-boot-part',
+            '--efi-boot-image', '--protective-msdos-label', iso_root, '-o', iso_path
+        ]
+
+    code, _, mkisofs_stderr = run_command(mkisofs_command, stdout=subprocess.PIPE,
+                                          stderr=subprocess.PIPE)
+
+    if code != 0:
+        log_error('failed to create the ISO image')
+        log_error(mkisofs_stderr.decode('utf-8'))
 
         return None
 
-    # create the disk image
+    if args.bootloader == 'limine' and not deploy_limine(iso_path):
+        return None
+
+    with open(stamp_path, 'w') as stamp:
+        stamp.write(inputs_hash)
+
+    ensure_disk_image()
+    return iso_path
+
+
+def ensure_disk_image():
     disk_path = os.path.join(BUILD_DIR, 'disk.img')
 
     if not os.path.exists(disk_path):
         log_info('creating disk image')
         os.system('bash ./tools/mkimage.sh')
 
-    return iso_path
+
+def hash_inputs(paths, extra='') -> str:
+    """
+    Returns a digest of the contents of `paths` (the missing ones are skipped) and of
+    `extra`.
+    """
+    digest = hashlib.sha256(extra.encode('utf-8'))
+
+    for path in paths:
+        if os.path.exists(path):
+            digest.update(hash_bundled_file(path)['sha256'].encode('utf-8'))
+
+    return digest.hexdigest()
+
+
+def get_accelerator(args) -> str:
+    """
+    Returns the accelerator selected with `--accel`, resolving `auto` to the one
+    supported by the host.
+    """
+    # Record and replay is only supported by TCG.
+    if args.disable_kvm or args.record or args.replay:
+        return 'tcg'
+
+    if args.accel != 'auto':
+        return args.accel
+
+    # The hardware accelerators can only run guests of the host architecture.
+    host_arch = {'AMD64': 'x86_64', 'arm64': 'aarch64'}.get(platform.machine(), platform.machine())
+
+    if host_arch == args.target.split('-')[0] and is_kvm_supported():
+        return 'hvf' if platform.system() == 'Darwin' else 'kvm'
+
+    return 'tcg'
+
+
+def spawn_gdb(kernel_elf):
+    """
+    Spawns `rust-gdb` (falling back to `gdb`) with the kernel symbols loaded, connected to
+    the gdb server of the emulator and with breakpoints on the kernel entry point and the
+    panic handler.
+    """
+    gdb = shutil.which('rust-gdb') or shutil.which('gdb')
+
+    if not gdb:
+        log_error("neither `rust-gdb` nor `gdb` were found in PATH")
+        return None
+
+    return subprocess.Popen([gdb, kernel_elf,
+                             '-ex', 'target remote :1234',
+                             '-ex', 'break arch_aero_main',
+                             '-ex', 'break rust_begin_unwind',
+                             '-ex', 'continue'])
+
+
+def prepare_disk_image(image_path) -> bool:
+    """
+    Creates a bootable GPT disk image from the ISO root prepared by `prepare_iso` and
+    installs the Limine BIOS stages into it.
+    """
+    log_info("preparing disk image")
+
+    iso_root = os.path.join(BUILD_DIR, 'iso_root')
+    code, _, _ = run_command(['bash', './tools/mkdisk.sh', image_path, iso_root])
+
+    if code != 0:
+        log_error('failed to create the disk image')
+        return False
+
+    if not deploy_limine(image_path):
+        return False
+
+    log_info(f"wrote the bootable disk image to {image_path}")
+    return True
+
+
+class Qmp:
+    """
+    A minimal client of the QEMU Machine Protocol.
+    """
+
+    def __init__(self, path):
+        self.socket = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        self.socket.connect(path)
+        self.file = self.socket.makefile('rw')
+
+        # Read the greeting and leave the capabilities negotiation mode.
+        self.file.readline()
+        self.execute('qmp_capabilities')
+
+    def execute(self, command, **arguments):
+        self.file.write(json.dumps({'execute': command, 'arguments': arguments}) + '\n')
+        self.file.flush()
+
+        while True:
+            response = json.loads(self.file.readline())
+
+            # Skip over the asynchronous events.
+            if 'return' in response:
+                return response['return']
+            elif 'error' in response:
+                raise RuntimeError(response['error']['desc'])
+
+    def human_command(self, command):
+        return self.execute('human-monitor-command', **{'command-line': command})
+
+    def close(self):
+        self.socket.close()
+
+
+def qmp_send(command: List[str]):
+    """
+    Sends a command to the emulator listening on the QMP socket.
+    """
+    if not os.path.exists(QMP_SOCKET):
+        log_error(f"{QMP_SOCKET} not found (help: run aero with `--qmp`)")
+        exit(1)
+
+    qmp = Qmp(QMP_SOCKET)
+    name, *arguments = command
+
+    try:
+        if name == 'screenshot' and len(arguments) == 1:
+            qmp.execute('screendump', filename=os.path.abspath(arguments[0]))
+        elif name in ['savevm', 'loadvm'] and len(arguments) == 1:
+            # Snapshots are only supported by qcow2 images.
+            output = qmp.human_command(f'{name} {arguments[0]}')
+
+            if output:
+                log_error(output.strip())
+                exit(1)
+        elif name == 'quit' and not arguments:
+            qmp.execute('quit')
+        else:
+            log_error(f"invalid QMP command `{' '.join(command)}`")
+            exit(1)
+    except RuntimeError as error:
+        log_error(f"QMP: {error}")
+        exit(1)
+    finally:
+        qmp.close()
+
+
+def stop_emulator(emulator, args):
+    """
+    Stops the emulator, through QMP when available so that QEMU shuts down cleanly.
+    """
+    if emulator.poll() is not None:
+        return
+
+    if args.qmp and os.path.exists(QMP_SOCKET):
+        try:
+            qmp = Qmp(QMP_SOCKET)
+
+            if args.screenshot:
+                qmp.execute('screendump', filename=os.path.abspath(args.screenshot))
+                log_info(f"saved a screenshot to {args.screenshot}")
+
+            qmp.execute('quit')
+            qmp.close()
+            emulator.wait(timeout=10)
+            return
+        except (OSError, RuntimeError, subprocess.TimeoutExpired) as error:
+            log_error(f"failed to quit the emulator through QMP: {error}")
+
+    emulator.terminate()
+    emulator.wait()
+
+
+def check_flash_target(device) -> int:
+    """
+    Makes sure that `device` is a removable block device that is not mounted and returns
+    its size in bytes.
+    """
+    name = os.path.basename(os.path.realpath(device))
+    sysfs_path = os.path.join('/sys/class/block', name)
+
+    if not os.path.exists(sysfs_path) or not os.path.exists(os.path.join(sysfs_path, 'removable')):
+        log_error(f"{device} is not a whole block device (help: pass the disk, not one of its partitions)")
+        exit(1)
+
+    with open(os.path.join(sysfs_path, 'removable')) as removable:
+        if removable.read().strip() != '1':
+            log_error(f"refusing to write to {device} as it is not a removable device")
+            exit(1)
+
+    with open('/proc/mounts') as mounts:
+        for line in mounts:
+            source = os.path.basename(line.split()[0])
+
+            if source.startswith(name):
+                log_error(f"{line.split()[0]} is mounted at {line.split()[1]} (help: unmount it first)")
+                exit(1)
+
+    # The size is in 512-byte sectors.
+    with open(os.path.join(sysfs_path, 'size')) as size:
+        return int(size.read()) * 512
+
+
+def flash(image_path, device):
+    """
+    Writes the disk image to `device`, after checking that it is a large enough removable
+    device and asking for confirmation.
+    """
+    device_size = check_flash_target(device)
+    image_size = os.path.getsize(image_path)
+
+    if image_size > device_size:
+        log_error(f"{device} is too small ({device_size} bytes) for the image ({image_size} bytes)")
+        exit(1)
+
+    answer = input(f"all of the data on {device} will be lost, type its name to continue: ")
+
+    if answer.strip() != device:
+        log_error("aborted")
+        exit(1)
+
+    chunk_size = 4 << 20
+    written = 0
+
+    try:
+        with open(image_path, 'rb') as image, open(device, 'wb') as target:
+            while chunk := image.read(chunk_size):
+                target.write(chunk)
+                written += len(chunk)
+
+                print(f"\rwriting {device}: {written * 100 // image_size}% "
+                      f"({written >> 20}/{image_size >> 20} MiB)", end='', flush=True)
+
+            target.flush()
+            os.fsync(target.fileno())
+    except PermissionError:
+        log_error(f"permission denied while opening {device} (help: run as root)")
+        exit(1)
+
+    print()
+    log_info(f"wrote the disk image to {device}")
+
+
+def get_version() -> str:
+    """
+    Returns the version of the kernel crate, suffixed with the git revision unless it is
+    a tagged release.
+    """
+    version = '0.0.0'
+
+    with open(os.path.join('src', 'aero_kernel', 'Cargo.toml')) as manifest:
+        for line in manifest:
+            if line.startswith('version'):
+                version = line.split('=', 1)[1].strip().strip('"')
+                break
+
+    code, stdout, _ = run_command(['git', 'describe', '--tags', '--always', '--dirty'],
+                                  stdout=subprocess.PIPE,
+                                  stderr=subprocess.DEVNULL)
+
+    if code == 0:
+        revision = stdout.decode('utf-8').strip()
+
+        if revision.lstrip('v') != version:
+            version = f'{version}+{revision}'
+
+    return version
+
+
+def make_dist(args, kernel_bin, iso_path):
+    """
+    Writes the release artifacts to `DIST_DIR`, together with a `SHA256SUMS` manifest.
+    """
+    version = get_version()
+    prefix = os.path.join(DIST_DIR, f'aero-{version}')
+
+    if os.path.exists(DIST_DIR):
+        shutil.rmtree(DIST_DIR)
+
+    os.makedirs(DIST_DIR)
+
+    if not args.strip:
+        strip_kernel(kernel_bin, os.path.join(BUILD_DIR, 'aero.stripped.elf'))
+
+    shutil.copy(iso_path, f'{prefix}.iso')
+    shutil.copy(kernel_bin, f'{prefix}.elf')
+    shutil.copy(KERNEL_SYMBOL_MAP, f'{prefix}.map')
+
+    # The disk images only have the Limine stages installed.
+    if args.bootloader != 'limine' or not prepare_disk_image(f'{prefix}.img'):
+        log_error("the release does not include a disk image")
+
+    with open(os.path.join(DIST_DIR, 'SHA256SUMS'), 'w') as manifest:
+        for name in sorted(os.listdir(DIST_DIR)):
+            if name == 'SHA256SUMS':
+                continue
+
+            digest = hash_bundled_file(os.path.join(DIST_DIR, name))['sha256']
+            manifest.write(f'{digest}  {name}\n')
+
+    log_info(f"wrote the release artifacts of aero {version} to {DIST_DIR}")
+
+
+def run_with_serial_log(qemu_command, args, log_path=None) -> int:
+    """
+    Runs the emulator while copying its serial output to stdout and to a timestamped log
+    file, scanning it for kernel panics and the boot marker.
+
+    Returns 0 if the boot marker was seen (or the emulator exited cleanly without one
+    being requested), 1 on a kernel panic and 2 on a timeout. When running the test
+    suite, the exit status written to the isa-debug-exit device decides the result. With
+    `--nettest`, all the probe markers must be seen once the probes are done.
+    """
+    os.makedirs(LOGS_DIR, exist_ok=True)
+    log_path = log_path or os.path.join(LOGS_DIR, f'serial-{time.strftime("%Y%m%d-%H%M%S")}.log')
+
+    log_info(f"logging the serial console to {log_path}")
+
+    emulator = subprocess.Popen(qemu_command,
+                                stdout=subprocess.PIPE,
+                                stderr=subprocess.STDOUT)
+
+    timeout = args.boot_timeout or (TEST_TIMEOUT if args.test else None)
+
+    if args.nettest:
+        timeout = args.boot_timeout or NETTEST_TIMEOUT
+        pending_markers = set(NETTEST_MARKERS if args.nettest_peer else NETTEST_LOOPBACK_MARKERS)
+
+    if args.fuzz:
+        timeout = args.fuzz_time
+    deadline = time.time() + timeout if timeout else None
+    passed = 0
+    status = None
+    lines = queue.Queue()
+
+    def read_lines():
+        for line in emulator.stdout:
+            lines.put(line)
+        lines.put(None)
+
+    threading.Thread(target=read_lines, daemon=True).start()
+
+    with open(log_path, 'wb') as log_file:
+        while status is None:
+            try:
+                line = lines.get(timeout=1)
+            except queue.Empty:
+                line = b''
+
+            if line is None:
+                # The emulator exited by itself.
+                emulator.wait()
+
+                if args.test:
+                    status = 0 if emulator.returncode == QEMU_EXIT_SUCCESS else 1
+                elif args.fuzz:
+                    # The fuzzer never exits, so the machine reset (see `-no-reboot`).
+                    status = 1
+                else:
+                    status = 1 if args.boot_marker or args.nettest else emulator.returncode
+                break
+
+            sys.stdout.buffer.write(line)
+            sys.stdout.flush()
+            log_file.write(line)
+
+            text = line.decode('utf-8', errors='replace')
+
+            if args.test and TEST_PASSED.search(text):
+                passed += 1
+
+            if args.nettest:
+                pending_markers -= {marker for marker in pending_markers if marker in text}
+
+            if any(marker in text for marker in PANIC_MARKERS):
+                log_error("kernel panic detected")
+                status = 1
+            elif args.boot_marker and args.boot_marker in text:
+                log_info("boot marker detected")
+                status = 0
+            elif args.nettest and NETTEST_DONE in text:
+                for marker in sorted(pending_markers):
+                    log_error(f"`{marker}` not seen")
+
+                status = 1 if pending_markers else 0
+            elif deadline and time.time() > deadline and args.fuzz:
+                log_info(f"no crash found within {timeout} seconds")
+                status = 0
+            elif deadline and time.time() > deadline and args.nettest:
+                log_error(f"the network probes did not finish within {timeout} seconds")
+                status = 2
+            elif deadline and time.time() > deadline:
+                log_error(f"boot marker not seen within {timeout} seconds")
+                status = 2
+
+    stop_emulator(emulator, args)
+
+    if args.nettest:
+        if status == 0:
+            log_info("the network probes passed")
+        else:
+            log_error("the network probes failed")
+
+    if args.test:
+        if status == 0:
+            log_info(f"test suite passed ({passed} tests ok)")
+        else:
+            log_error(f"test suite failed after {passed} passing tests")
+
+    return status
+
+
+def save_fuzz_crash(log_path, seed):
+    """
+    Saves the artifacts of a crash found by the fuzzer in a new directory of `FUZZ_CORPUS`:
+    the serial log, the panic message with the register dump and backtrace that follow it,
+    the last system calls made by the fuzzer and the seed reproducing the run.
+    """
+    crash_dir = os.path.join(FUZZ_CORPUS, f'crash-{time.strftime("%Y%m%d-%H%M%S")}')
+    os.makedirs(crash_dir)
+
+    with open(log_path, 'r', errors='replace') as log_file:
+        lines = log_file.readlines()
+
+    calls = [line for line in lines if line.startswith('fuzz: ')][-FUZZ_LAST_CALLS:]
+    panic_at = next((index for index, line in enumerate(lines)
+                     if any(marker in line for marker in PANIC_MARKERS)), None)
+
+    shutil.copy(log_path, os.path.join(crash_dir, 'serial.log'))
+
+    with open(os.path.join(crash_dir, 'calls.txt'), 'w') as calls_file:
+        calls_file.writelines(calls)
+
+    if panic_at is not None:
+        with open(os.path.join(crash_dir, 'panic.txt'), 'w') as panic_file:
+            panic_file.writelines(lines[panic_at:])
+
+    with open(os.path.join(crash_dir, 'seed'), 'w') as seed_file:
+        seed_file.write(f'{seed}\n')
+
+    log_error(f"saved the crash to {crash_dir} (reproduce with `--fuzz --fuzz-seed={seed}`)")
+
+
+def time_boot(qemu_command, args) -> dict:
+    """
+    Boots aero once and returns the seconds, since the emulator was started, after which
+    each of the `BOOT_MILESTONES` was printed on the serial console. The milestones that
+    were not reached within `BENCH_TIMEOUT` seconds are missing.
+    """
+    start = time.monotonic()
+    emulator = subprocess.Popen(qemu_command,
+                                stdout=subprocess.PIPE,
+                                stderr=subprocess.STDOUT)
+
+    timings = {}
+    lines = queue.Queue()
+
+    def read_lines():
+        for line in emulator.stdout:
+            lines.put((time.monotonic(), line))
+        lines.put((time.monotonic(), None))
+
+    threading.Thread(target=read_lines, daemon=True).start()
+
+    while len(timings) < len(BOOT_MILESTONES) and time.monotonic() < start + BENCH_TIMEOUT:
+        try:
+            timestamp, line = lines.get(timeout=1)
+        except queue.Empty:
+            continue
+
+        if line is None:
+            break
+
+        text = line.decode('utf-8', errors='replace')
+
+        for name, message in BOOT_MILESTONES:
+            if name not in timings and message in text:
+                timings[name] = timestamp - start
+
+    stop_emulator(emulator, args)
+    return timings
+
+
+def percentile(values, percent):
+    """
+    Returns the nearest-rank percentile of `values`.
+    """
+    values = sorted(values)
+    return values[max(0, -(-len(values) * percent // 100) - 1)]
+
+
+def bench_boot(qemu_command, args) -> int:
+    """
+    Boots aero `--bench-boot` times, prints the median and percentiles of the timings of
+    each milestone (compared against the `--bench-diff` report) and saves them in
+    `BENCH_REPORT`.
+    """
+    runs = []
+
+    for run in range(args.bench_boot):
+        timings = time_boot(qemu_command, args)
+        missing = [name for name, _ in BOOT_MILESTONES if name not in timings]
+
+        if missing:
+            log_error(f"boot {run + 1} did not reach the `{missing[0]}` milestone")
+            return 1
+
+        log_info(f"boot {run + 1}/{args.bench_boot}: {timings[BOOT_MILESTONES[-1][0]]:.3f}s")
+        runs.append(timings)
+
+    previous = {}
+
+    if args.bench_diff:
+        with open(args.bench_diff) as file:
+            previous = json.load(file)
+
+    report = {}
+
+    print(f"\n  {'milestone':<12}{'median':>10}{'p90':>10}{'p99':>10}")
+
+    for name, _ in BOOT_MILESTONES:
+        values = [timings[name] for timings in runs]
+        report[name] = {'median': statistics.median(values),
+                        'p90': percentile(values, 90),
+                        'p99': percentile(values, 99),
+                        'runs': values}
+
+        row = f"  {name:<12}" + ''.join(f"{report[name][key] * 1000:>8.1f}ms"
+                                       for key in ['median', 'p90', 'p99'])
+
+        if name in previous:
+            delta = report[name]['median'] - previous[name]['median']
+            row += f"  {delta * 1000:+.1f}ms ({delta / previous[name]['median']:+.1%})"
+
+        print(row)
+
+    os.makedirs(BUILD_DIR, exist_ok=True)
+
+    with open(BENCH_REPORT, 'w') as file:
+        json.dump(report, file, indent=4)
+
+    log_info(f"saved the boot timings to {BENCH_REPORT}")
+    return 0
+
+
+def get_drive_args(args, drive_id, options) -> List[str]:
+    """
+    Returns the `-drive` options of a drive with the id `drive_id`. When recording or
+    replaying, the drive is wrapped in a `blkreplay` driver so that its I/O is part of
+    the recording, and its writes are discarded so that the replay starts from the same
+    disk contents as the recording.
+    """
+    if not args.record and not args.replay:
+        return ['-drive', f'{options},if=none,id={drive_id}']
+
+    return ['-drive', f'{options},if=none,snapshot=on,id={drive_id}-direct',
+            '-drive', f'driver=blkreplay,if=none,image={drive_id}-direct,id={drive_id}']
+
+
+def get_disk_args(args, index, spec) -> List[str]:
+    """
+    Returns the QEMU options attaching the disk described by `spec` (see `--disk`),
+    creating the disk image if it does not exist.
+    """
+    path, *options = spec.split(',')
+    options = dict(option.split('=', 1) for option in options)
+
+    image_format = options.get('format', 'raw')
+    interface = options.get('if', 'nvme')
+    drive_id = f'DISK{index}'
+
+    if not os.path.exists(path):
+        size = options.get('size', '1G')
+        code, _, _ = run_command(['qemu-img', 'create', '-f', image_format, path, size],
+                                 stdout=subprocess.DEVNULL)
+
+        if code != 0:
+            log_error(f"failed to create the disk image {path}")
+            exit(1)
+
+        log_info(f"created the empty {size} disk image {path}")
+
+    drive = get_drive_args(args, drive_id, f'file={path},format={image_format}')
+
+    if interface == 'nvme':
+        return [*drive, '-device', f'nvme,drive={drive_id},serial=disk{index}']
+    elif interface == 'virtio':
+        return [*drive, '-device', f'virtio-blk-pci,drive={drive_id}']
+    elif interface == 'ahci':
+        return [*drive, '-device', f'ahci,id=AHCI{index}',
+                '-device', f'ide-hd,drive={drive_id},bus=AHCI{index}.0']
+    elif interface == 'ide':
+        return [*drive, '-device', f'ide-hd,drive={drive_id}']
+
+    log_error(f"unknown disk interface `{interface}`")
+    exit(1)
+
+
+def get_network_args(args) -> List[str]:
+    """
+    Returns the QEMU options configuring the network backend and the NIC selected with
+    `--net`.
+    """
+    if not args.net:
+        return []
+
+    if args.net == 'none':
+        return ['-nic', 'none']
+
+    if args.net == 'user':
+        netdev = 'user,id=NET0' + ''.join(f',hostfwd={rule}' for rule in args.hostfwd)
+    elif args.net == 'mcast':
+        netdev = f'socket,id=NET0,mcast={args.mcast}'
+    else:
+        netdev = f'tap,id=NET0,ifname={args.tap},script=no,downscript=no'
+
+    return ['-netdev', netdev, '-device', f'{args.nic_model},netdev=NET0,mac={AERO_MAC}']
+
+
+def start_nettest_peer(args, qemu_binary):
+    """
+    Boots the `--nettest-peer` image headless on the multicast segment aero is attached
+    to. The image is not modified, the writes are discarded when the peer exits.
+    """
+    os.makedirs(LOGS_DIR, exist_ok=True)
+    log_path = os.path.join(LOGS_DIR, 'nettest-peer.log')
+
+    qemu_args = ['-m', '512M',
+                 '-display', 'none', '-monitor', 'none',
+                 '-serial', f'file:{log_path}',
+                 '-drive', f'file={args.nettest_peer},if=virtio,snapshot=on',
+                 '-netdev', f'socket,id=NET0,mcast={args.mcast}',
+                 '-device', f'{args.nic_model},netdev=NET0,mac={PEER_MAC}']
+
+    accel = get_accelerator(args)
+
+    if accel != 'tcg':
+        qemu_args += ['-accel', accel]
+
+    log_info(f"starting the nettest peer (serial log in {log_path})")
+    return subprocess.Popen([qemu_binary, *qemu_args])
+
+
+def get_firmware_args(args, target_arch) -> List[str]:
+    """
+    Returns the QEMU options loading the UEFI firmware. Unless `--ovmf-code` is set, the
+    bundled OVMF build is used, with its variables kept in the image and reset on every run.
+    """
+    if not args.ovmf_code:
+        return ['-bios', f'bundled/ovmf/ovmf-{target_arch}/OVMF.fd']
+
+    qemu_args = ['-drive', f'if=pflash,format=raw,unit=0,readonly=on,file={args.ovmf_code}']
+
+    if args.ovmf_vars:
+        vars_path = os.path.join(BUILD_DIR, f'ovmf-vars-{target_arch}.fd')
+
+        # The writable copy is per project, so the variables persist across the runs
+        # without modifying the template.
+        if not os.path.exists(vars_path):
+            os.makedirs(BUILD_DIR, exist_ok=True)
+            shutil.copy(args.ovmf_vars, vars_path)
+            log_info(f"created the UEFI variable store {vars_path}")
+
+        qemu_args += ['-drive', f'if=pflash,format=raw,unit=1,file={vars_path}']
+
+    if args.secure_boot:
+        # Secure Boot builds only allow SMM code to write to the variable store.
+        qemu_args += ['-machine', 'q35,smm=on',
+                      '-global', 'driver=cfi.pflash01,property=secure,value=on']
+
+    return qemu_args
 
 
 def run_in_emulator(build_info: BuildInfo, iso_path):
-    is_kvm_available = is_kvm_supported()
     args = build_info.args
+    accel = get_accelerator(args)
 
-    qemu_args = ['-cdrom', iso_path,
-                 '-m', args.memory,
-                 '-smp', '1',
+    qemu_args = ['-m', args.memory,
+                 '-smp', args.smp,
                  '-serial', 'stdio',
-                 '-drive', 'file=build/disk.img,if=none,id=NVME1,format=raw', '-device', 'nvme,drive=NVME1,serial=nvme',
-                 # Specify the boot order (where `d` is the first CD-ROM drive)
-                 '--boot', 'd', 
+                 *get_drive_args(args, 'NVME1', 'file=build/disk.img,format=raw'),
+                 '-device', 'nvme,drive=NVME1,serial=nvme',
                  '-s']
 
+    cdrom = get_drive_args(args, 'CD1', f'file={iso_path},format=raw,media=cdrom')
+
+    if args.record or args.replay:
+        replay_file = args.record or args.replay
+        mode = 'record' if args.record else 'replay'
+
+        if args.net and args.net != 'none':
+            log_error(f"`--net={args.net}` cannot be used with `--{mode}`")
+            exit(1)
+
+        log_info(f"{mode}ing the execution in {replay_file}")
+
+        # The execution is only deterministic with a virtual instruction counter and
+        # clock and without host devices feeding nondeterministic input.
+        qemu_args += ['-icount', f'shift=auto,rr={mode},rrfile={replay_file}',
+                      '-rtc', 'clock=vm', '-nic', 'none']
+
+    if build_info.target_arch == "aarch64":
+        # The virt machine has no IDE controller for `-cdrom`.
+        qemu_args += ['-M', 'virt', '-device', 'ramfb',
+                      '-device', 'virtio-scsi-pci',
+                      *cdrom, '-device', 'scsi-cd,drive=CD1']
+    elif args.record or args.replay:
+        qemu_args += [*cdrom, '-device', 'ide-cd,drive=CD1,bootindex=0']
+    else:
+        # Specify the boot order (where `d` is the first CD-ROM drive)
+        qemu_args += ['-cdrom', iso_path, '--boot', 'd']
+
+    if args.test and build_info.target_arch == "x86_64":
+        # The test runner reports the result through the isa-debug-exit device.
+        qemu_args += ['-device', 'isa-debug-exit,iobase=0xf4,iosize=0x04']
+
+    if args.headless or args.test or args.fuzz or args.bench_boot:
+        qemu_args += ['-display', 'none', '-monitor', 'none']
+
+    if args.fuzz:
+        # Keep the disk image intact and exit on a triple fault instead of rebooting.
+        qemu_args += ['-snapshot', '-no-reboot']
+
     if args.bios == 'uefi':
-        qemu_args += ['-bios',
-                      f'bundled/ovmf/ovmf-{build_info.target_arch}/OVMF.fd']
+        qemu_args += get_firmware_args(args, build_info.target_arch)
+
+    qemu_args += get_network_args(args)
+
+    if args.qmp:
+        os.makedirs(BUILD_DIR, exist_ok=True)
+        qemu_args += ['-qmp', f'unix:{QMP_SOCKET},server,nowait']
+
+    for index, spec in enumerate(args.disk):
+        qemu_args += get_disk_args(args, index, spec)
+
+    for option, values in [('-device', args.qemu_device),
+                           ('-netdev', args.qemu_netdev),
+                           ('-drive', args.qemu_drive)]:
+        for value in values:
+            qemu_args += [option, value]
 
     cmdline = args.remaining
 
@@ -477,26 +2357,77 @@ def run_in_emulator(build_info: BuildInfo, iso_path):
     if cmdline:
         qemu_args += cmdline
 
-    if is_kvm_available and not args.disable_kvm:
-        log_info("running with KVM acceleration enabled")
-
-        if platform.system() == 'Darwin':
-            qemu_args += ['-accel', 'hvf', '-cpu',
-                          'qemu64,+la57' if args.la57 else 'qemu64']
-        else:
-            qemu_args += ['-enable-kvm', '-cpu',
-                          'host,+la57' if args.la57 else 'host']
+    if build_info.target_arch == "aarch64":
+        cpu = 'cortex-a72'
+    elif build_info.target_arch == "x86_64":
+        cpu = 'qemu64'
     else:
-        if build_info.target_arch == "aarch64":
-            qemu_args += ['-device', 'ramfb',
-                          '-M', 'virt', '-cpu', 'cortex-a72']
-        elif build_info.target_arch == "x86_64":
-            qemu_args += ["-cpu", "qemu64,+la57" if args.la57 else "qemu64"]
-        else:
-            log_error("unknown target architecture")
-            exit(1)
+        log_error("unknown target architecture")
+        exit(1)
+
+    if accel != 'tcg':
+        log_info(f"running with {accel.upper()} acceleration enabled")
+        qemu_args += ['-accel', accel]
+
+        # HVF and WHPX do not support passing through the host CPU model.
+        if accel == 'kvm':
+            cpu = 'host'
+
+    if args.cpu:
+        cpu = args.cpu
+
+    if args.la57 and build_info.target_arch == "x86_64":
+        cpu += ',+la57'
+
+    qemu_args += ['-cpu', cpu]
+
+    if args.gdb or args.gdb_attach:
+        # `-s` is always passed, `-S` makes the CPU wait for the debugger.
+        qemu_args += ['-S']
+        log_info("waiting for the debugger on :1234")
+
+    qemu_binary = args.qemu or f'qemu-system-{build_info.target_arch}'
+
+    if args.gdb_attach:
+        emulator = subprocess.Popen([qemu_binary, *qemu_args])
+        kernel_elf = KERNEL_DEBUG_INFO if args.strip else os.path.join(BUILD_DIR, 'iso_root', 'aero.elf')
+        gdb = spawn_gdb(kernel_elf)
+
+        if gdb:
+            gdb.wait()
+
+        emulator.terminate()
+        emulator.wait()
+        return
+
+    if args.nettest:
+        peer = start_nettest_peer(args, qemu_binary) if args.nettest_peer else None
+        status = run_with_serial_log([qemu_binary, *qemu_args], args)
+
+        if peer:
+            peer.terminate()
+            peer.wait()
+
+        sys.exit(status)
+
+    if args.bench_boot:
+        if accel == 'tcg':
+            log_info("no hardware acceleration, the boot timings are not representative")
+
+        sys.exit(bench_boot([qemu_binary, *qemu_args], args))
+
+    if args.fuzz:
+        log_path = os.path.join(LOGS_DIR, f'fuzz-{time.strftime("%Y%m%d-%H%M%S")}.log')
+        status = run_with_serial_log([qemu_binary, *qemu_args], args, log_path)
+
+        if status != 0:
+            save_fuzz_crash(log_path, args.fuzz_seed)
+
+        sys.exit(status)
+
+    if args.serial_log or args.test:
+        sys.exit(run_with_serial_log([qemu_binary, *qemu_args], args))
 
-    qemu_binary = f'qemu-system-{build_info.target_arch}'
     run_command([qemu_binary, *qemu_args])
 
 
@@ -523,15 +2454,20 @@ def is_kvm_supported() -> bool:
     """
 
     platform = sys.platform
+    machine = os.uname().machine
 
     if platform == "darwin":
+        # Check for HVF support
+        hv_support = get_sysctl("kern.hv_support") == "1"
+
+        # Apple Silicon has no VMX, HVF support is all that is needed.
+        if machine == "arm64":
+            return hv_support
+
         # Check for VMX support
         cpu_features = get_sysctl("machdep.cpu.features")
         vmx_support = "VMX" in cpu_features.split(' ')
 
-        # Check for HVF support
-        hv_support = get_sysctl("kern.hv_support") == "1"
-
         return hv_support and vmx_support
 
     if platform == "linux":
@@ -541,6 +2477,10 @@ def is_kvm_supported() -> bool:
         if not os.path.exists(kvm_path):
             return False
 
+        # The virtualization extensions are only listed in the flags on x86.
+        if machine not in ("x86_64", "i686"):
+            return True
+
         # Read out the cpuinfo from `/proc/cpuinfo`
         fd = open("/proc/cpuinfo")
         cpuinfo = fd.read()
@@ -566,8 +2506,10 @@ def is_kvm_supported() -> bool:
 
         for processor in processors_info:
             if processor["processor"] == "0":
-                # KVM acceleration can be used
-                if "vmx" in processor["flags"]:
+                # KVM acceleration can be used (VT-x on Intel, AMD-V on AMD)
+                flags = processor.get("flags", "").split()
+
+                if "vmx" in flags or "svm" in flags:
                     return True
                 # KVM acceleration cannot be used
                 else:
@@ -583,57 +2525,183 @@ def main():
     t0 = time.time()
     args = parse_args()
 
+    if args.arch:
+        args.target = f'{args.arch}-aero_os'
+
+    if args.sccache:
+        sccache = shutil.which('sccache')
+
+        if not sccache:
+            log_error("sccache not found in PATH")
+            exit(1)
+
+        os.environ['RUSTC_WRAPPER'] = sccache
+
     # arch-aero_os
     target_arch = args.target.split('-')[0]
     build_info = BuildInfo(target_arch, args)
 
+    if args.fuzz:
+        if args.fuzz_seed is None:
+            args.fuzz_seed = int.from_bytes(os.urandom(4), 'little')
+
+        args.cmdline += f' init=/usr/bin/fuzz fuzz.seed={args.fuzz_seed}'
+        log_info(f"fuzzing with the seed {args.fuzz_seed}")
+
+    if args.nettest:
+        args.net = 'mcast'
+        args.headless = True
+
+        if args.nettest_peer:
+            args.cmdline += f' nettest={NETTEST_ADDR},{NETTEST_PEER_ADDR}'
+        else:
+            args.cmdline += f' nettest={NETTEST_ADDR}'
+            log_info("no `--nettest-peer`, only probing the loopback interface")
+
     if build_info.target_arch == "aarch64" and not args.bios == "uefi":
-        log_error("aarch64 requires UEFI (help: run again with `--bios=uefi`)")
-        return
+        # AAVMF (the aarch64 build of OVMF) is the only firmware for the virt machine.
+        log_info("aarch64 only boots with UEFI, using `--bios=uefi`")
+        args.bios = 'uefi'
+
+    if args.splash and not args.splash.lower().endswith('.bmp'):
+        log_error("Limine only draws BMP images, convert the splash image first")
+        exit(1)
+
+    if args.bootloader == 'multiboot2':
+        if build_info.target_arch != "x86_64":
+            log_error("`--bootloader=multiboot2` is only supported on x86_64")
+            exit(1)
 
-    download_bundled()
+        if args.limine_cfg or args.splash:
+            log_error("`--limine-cfg` and `--splash` are only supported with Limine")
+            exit(1)
 
-    if args.only_run:
+        if args.disk_image or args.flash:
+            log_error("the disk images only boot with Limine, use the ISO with `--bootloader=multiboot2`")
+            exit(1)
+
+    if args.secure_boot and (build_info.target_arch != "x86_64" or not args.ovmf_code):
+        log_error("`--secure-boot` requires `--ovmf-code` and is only supported on x86_64")
+        exit(1)
+
+    # There is no need to download the prebuilts just to remove them.
+    if not args.clean and not args.qmp_send:
+        download_bundled(offline=args.offline,
+                         verify=not args.update_bundled_checksums,
+                         retries=args.download_retries)
+
+    if args.qmp_send:
+        qmp_send(args.qmp_send)
+    elif args.update_bundled_checksums:
+        update_bundled_checksums()
+    elif args.only_run:
         iso_path = os.path.join(BUILD_DIR, 'aero.iso')
 
         if not os.path.exists(iso_path):
             user_bins = build_userland(args)
             kernel_bin = build_kernel(args)
 
-            if not kernel_bin or args.check:
+            if kernel_bin is None or user_bins is None:
+                log_error("failed to build aero")
+                exit(1)
+
+            if args.check:
                 return
 
+            if not kernel_bin:
+                log_error("the kernel build did not produce an executable")
+                exit(1)
+
             kernel_bin = kernel_bin[0]
             iso_path = prepare_iso(args, kernel_bin, user_bins)
+
+            if not iso_path:
+                exit(1)
         run_in_emulator(build_info, iso_path)
     elif args.clean:
-        src_target = os.path.join('src', 'target', args.target)
+        # The host artifacts (build scripts and proc macros) are next to the target
+        # directories, so the whole cargo target directory is removed.
+        src_target = os.path.join('src', 'target')
         userland_target = os.path.join('userland', 'target')
 
-        if os.path.exists(src_target):
-            shutil.rmtree(src_target)
+        paths = [BUILD_DIR, src_target, userland_target]
 
-        if os.path.exists(userland_target):
-            shutil.rmtree(userland_target)
+        if args.clean_bundled:
+            paths.append(BUNDLED_DIR)
+
+        for path in paths:
+            if os.path.exists(path):
+                log_info(f"removing {path}")
+                shutil.rmtree(path)
     elif args.sysroot:
         build_userland_sysroot(False)
+    elif args.userland:
+        user_bins = build_userland(args)
+
+        if user_bins is None:
+            log_error("failed to build the userland")
+            exit(1)
+
+        install_userland(user_bins)
+        log_info(f"installed {len(user_bins)} userland binaries into the sysroot")
+    elif args.toolchain:
+        build_userland_sysroot(False, toolchain=True)
+    elif args.lint:
+        if not lint(args):
+            exit(1)
+    elif args.size:
+        kernel_bin = build_kernel(args)
+
+        if not kernel_bin:
+            exit(1)
+
+        report_size(args, kernel_bin[0])
     elif args.document:
         build_kernel(args)
 
         generate_docs(args)
     else:
-        user_bins = build_userland(args)
-        kernel_bin = build_kernel(args)
+        user_bins, kernel_bin = build_components(args)
+
+        # The build failures must fail the script, so that `--test` runs in CI do not
+        # pass when aero does not build.
+        if kernel_bin is None or user_bins is None:
+            log_error("failed to build aero")
+            exit(1)
 
-        if not kernel_bin or args.check:
+        if args.check:
             return
 
+        if not kernel_bin:
+            log_error("the kernel build did not produce an executable")
+            exit(1)
+
         kernel_bin = kernel_bin[0]
         iso_path = prepare_iso(args, kernel_bin, user_bins)
 
+        if not iso_path:
+            exit(1)
+
         t1 = time.time()
         log_info(f"build completed in {t1 - t0:.2f} seconds")
-        if not args.no_run:
+
+        if args.iso:
+            shutil.copy(iso_path, args.iso)
+            log_info(f"wrote the bootable ISO to {args.iso}")
+
+        if args.disk_image:
+            prepare_disk_image(args.disk_image)
+
+        if args.dist:
+            make_dist(args, kernel_bin, iso_path)
+
+        if args.flash:
+            image_path = os.path.join(BUILD_DIR, 'aero.img')
+
+            if prepare_disk_image(image_path):
+                flash(image_path, args.flash)
+
+        if not args.no_run and not (args.iso or args.disk_image or args.dist or args.flash):
             run_in_emulator(build_info, iso_path)
 
 
@@ -19,13 +19,73 @@
 
 use std::fs;
 
-use std::error::Error;
+use std::collections::hash_map::DefaultHasher;
 use std::ffi::OsString;
+use std::fmt;
 use std::fs::DirEntry;
-use std::path::Path;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::{env, io, thread};
 
 //this is all magic, yes dont ever let anyone see this shit
 
+/// The errors of the build script, reported with the failing command and a hint on how to
+/// fix it.
+enum BuildError {
+    /// A required environment variable was not set by cargo.
+    MissingEnv(&'static str),
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// NASM failed to assemble a source file.
+    Assemble {
+        command: String,
+        output: String,
+    },
+}
+
+impl BuildError {
+    fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |error| Self::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    fn hint(&self) -> &'static str {
+        match self {
+            Self::MissingEnv(_) => "the build script must be run by cargo",
+            Self::Io { .. } => "check that the file exists and is readable",
+            Self::Assemble { .. } => "check that `nasm` is installed and in your `PATH`",
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingEnv(name) => write!(f, "environment variable `{}` is not set", name)?,
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error)?,
+            Self::Assemble { command, output } => write!(
+                f,
+                "failed to assemble: `{}`\n{}",
+                command,
+                output.trim_end()
+            )?,
+        }
+
+        write!(f, "\nhint: {}", self.hint())
+    }
+}
+
+// `main` reports the errors with their debug representation.
+impl fmt::Debug for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 /// Helper function of walking the provided `dir`, only visiting files and calling
 /// `cb` on each file.
 fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&DirEntry)) -> std::io::Result<()> {
@@ -45,75 +105,207 @@ fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&DirEntry)) -> std::io::Result<()>
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let target = std::env::var("TARGET").expect("target triple is not set");
+/// The prefix of the header comment used to pass extra flags to NASM for a single source
+/// file, for example `; nasm-flags: -fbin`.
+const FLAGS_DIRECTIVE: &str = "; nasm-flags:";
+
+/// The NASM configuration shared by all of the assembly source files.
+struct AsmConfig {
+    target: String,
+    /// The default object format, selected from the target triple.
+    format: &'static str,
+    /// The directories of the files that are included using `%include`.
+    include_dirs: Vec<String>,
+    /// The preprocessor defines, in the `NAME[=VALUE]` form.
+    defines: Vec<String>,
+}
+
+impl AsmConfig {
+    /// Returns the configuration for `target` or `None` if NASM cannot assemble for the
+    /// architecture of the target triple.
+    fn new(target: String, include_dirs: Vec<String>, defines: Vec<String>) -> Option<Self> {
+        let format = match target.split('-').next()? {
+            "x86_64" => "elf64",
+            "i386" | "i486" | "i586" | "i686" => "elf32",
+
+            _ => return None,
+        };
+
+        Some(Self {
+            target,
+            format,
+            include_dirs,
+            defines,
+        })
+    }
+}
+
+/// Returns the extra flags of the source file, specified with [`FLAGS_DIRECTIVE`] in its
+/// header comment. A `-f` flag overrides the object format of the target.
+fn file_flags(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .take_while(|line| line.starts_with(';') || line.trim().is_empty())
+        .filter_map(|line| line.strip_prefix(FLAGS_DIRECTIVE))
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns a hash of the contents of `files` and of the flags, used to skip reassembling
+/// the source files whose inputs did not change.
+fn hash_inputs(files: &[&Path], flags: &[String]) -> Result<u64, BuildError> {
+    let mut hasher = DefaultHasher::new();
+
+    for flag in flags {
+        hasher.write(flag.as_bytes());
+    }
+
+    for file in files {
+        hasher.write(&fs::read(file).map_err(BuildError::io(file))?);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Assembles `path` into the `lib<name>.a` static library in `out_dir`, unless the hash of
+/// the source file, of the included files and of the flags matches the one of the previous
+/// build.
+fn assemble(
+    path: &Path,
+    inc_files: &[PathBuf],
+    config: &AsmConfig,
+    out_dir: &Path,
+) -> Result<(), BuildError> {
+    let object_file = path.file_name().and_then(|name| name.to_str()).unwrap();
 
-    if target.contains("aarch64") {
+    let inputs = core::iter::once(path)
+        .chain(inc_files.iter().map(PathBuf::as_path))
+        .collect::<Vec<_>>();
+
+    let source = fs::read_to_string(path).map_err(BuildError::io(path))?;
+    let mut flags = file_flags(&source);
+
+    if !flags.iter().any(|flag| flag.starts_with("-f")) {
+        flags.push(format!("-f{}", config.format));
+    }
+
+    flags.extend(config.defines.iter().map(|define| format!("-D{}", define)));
+
+    let hash = hash_inputs(&inputs, &flags)?;
+    let hash_file = out_dir.join(format!("{}.hash", object_file));
+    let library = out_dir.join(format!("lib{}.a", object_file));
+
+    let previous_hash = fs::read_to_string(&hash_file).ok();
+
+    if library.exists() && previous_hash == Some(hash.to_string()) {
         return Ok(());
     }
 
+    let mut build = nasm_rs::Build::new();
+
+    build.file(path).target(&config.target);
+
+    for flag in &flags {
+        build.flag(flag);
+    }
+
+    for include in &config.include_dirs {
+        build.include(include);
+    }
+
+    build.compile(object_file).map_err(|output| {
+        let mut command = vec![String::from("nasm")];
+
+        command.extend(config.include_dirs.iter().map(|dir| format!("-I{}", dir)));
+        command.extend(flags.iter().cloned());
+        command.push(path.display().to_string());
+
+        BuildError::Assemble {
+            command: command.join(" "),
+            output,
+        }
+    })?;
+
+    fs::write(&hash_file, hash.to_string()).map_err(BuildError::io(&hash_file))
+}
+
+fn main() -> Result<(), BuildError> {
+    let target = env::var("TARGET").map_err(|_| BuildError::MissingEnv("TARGET"))?;
+    let out_dir = env::var("OUT_DIR").map_err(|_| BuildError::MissingEnv("OUT_DIR"))?;
+    let out_dir = PathBuf::from(out_dir);
+
     let mut inc_files = vec![];
+    let mut asm_files = vec![];
 
-    // Assemble all of the assembly real files first as they will be included in the
-    // source files using `incbin`.
     visit_dirs(Path::new("src"), &mut |entry| {
         let path = entry.path();
 
         match path.extension() {
-            Some(ext) if ext.eq(&OsString::from("inc")) => {
-                let path = path
-                    .to_str()
-                    .expect("invalud UTF-8 for file path (skill issue)");
-                inc_files.push(path.to_string())
-            }
+            Some(ext) if ext.eq(&OsString::from("inc")) => inc_files.push(path),
+            Some(ext) if ext.eq(&OsString::from("asm")) => asm_files.push(path),
 
-            _ => (),
+            _ => return,
         }
-    })?;
 
-    // more magic
-    inc_files = inc_files
+        // Only rerun the build script if one of the assembly files changed, instead of
+        // on every change in the crate.
+        println!("cargo:rerun-if-changed={}", entry.path().display());
+    })
+    .map_err(BuildError::io(Path::new("src")))?;
+
+    // The directories of the files that are included in the source files using `%include`.
+    let mut include_dirs = inc_files
         .iter()
-        .map(|e| {
-            let e = e.split("/").collect::<Vec<_>>();
-            e[..e.len() - 1].join("/").to_string()
+        .map(|path| {
+            let dir = path
+                .parent()
+                .expect("include file without a parent directory");
+            dir.display().to_string()
         })
         .collect::<Vec<_>>();
 
-    // Now that we have assembled all of the real files, we can go ahead and assemble the source
-    // files.
-    visit_dirs(Path::new("src"), &mut |entry: &DirEntry| {
-        let path = entry.path();
+    include_dirs.dedup();
 
-        let object_os = path.file_name().expect("Failed to get file name");
-        let object_file = object_os.to_str().expect("Invalid UTF-8 for file name");
+    // Extra preprocessor defines, separated by whitespace (for example `DEBUG VERBOSE=1`).
+    println!("cargo:rerun-if-env-changed=AERO_NASM_DEFINES");
 
-        match path.extension() {
-            Some(ext) if ext.eq(&OsString::from("asm")) => {
-                let mut build = nasm_rs::Build::new();
+    let mut defines: Vec<String> = env::var("AERO_NASM_DEFINES")
+        .map(|defines| defines.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default();
 
-                build
-                    .file(&path)
-                    .flag("-felf64")
-                    .target("x86_64-unknown-none");
+    // The assembly of the optional features is wrapped in `%ifdef FEATURE_<NAME>`.
+    if env::var_os("CARGO_FEATURE_MULTIBOOT2").is_some() {
+        defines.push(String::from("FEATURE_MULTIBOOT2"));
+    }
 
-                println!("{:?}", inc_files);
+    let config = match AsmConfig::new(target, include_dirs, defines) {
+        Some(config) => config,
+        // There is no assembly to build for the other architectures.
+        None => return Ok(()),
+    };
 
-                for include in &inc_files {
-                    build.include(include);
-                }
+    // Assemble the source files in parallel.
+    thread::scope(|scope| {
+        let (inc_files, config, out_dir) = (&inc_files, &config, &out_dir);
+        let handles = asm_files
+            .iter()
+            .map(|path| scope.spawn(move || assemble(path, inc_files, config, out_dir)))
+            .collect::<Vec<_>>();
 
-                build
-                    .compile(object_file)
-                    .expect("failed to compile assembly: skill issue");
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
 
-                // Link it as a static library.
-                println!("cargo:rustc-link-lib=static={}", object_file);
-            }
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
 
-            _ => (),
-        }
-    })?;
+    for path in &asm_files {
+        let object_file = path.file_name().and_then(|name| name.to_str()).unwrap();
+
+        // Link it as a static library.
+        println!("cargo:rustc-link-lib=static={}", object_file);
+    }
 
     println!("cargo:rerun-if-changed=.cargo/kernel.ld");
 
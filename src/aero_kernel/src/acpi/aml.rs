@@ -5,6 +5,8 @@ use spin::Once;
 /// * [ACPI Sleeping States](https://uefi.org/specs/ACPI/6.4/16_Waking_and_Sleeping/sleeping-states.html)
 #[repr(u8)]
 pub enum SleepState {
+    /// Suspend-to-RAM. The memory stays powered and the CPU context is lost.
+    S3 = 3,
     S5 = 5,
 }
 
@@ -0,0 +1,234 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The ACPI DMAR (DMA Remapping) table describes the Intel VT-d remapping hardware units
+//! and the memory regions that have to stay identity mapped for some devices.
+//!
+//! **Notes**: Intel VT-d Specification, Chapter 8 "BIOS Considerations"
+
+use core::mem;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::mem::paging::PhysAddr;
+
+use super::sdt::Sdt;
+
+pub(super) const SIGNATURE: &str = "DMAR";
+
+const DRHD_INCLUDE_PCI_ALL: u8 = 1 << 0;
+
+static DMAR: Once<DmarInfo> = Once::new();
+
+#[repr(C, packed)]
+pub(super) struct Dmar {
+    header: Sdt,
+    host_address_width: u8,
+    flags: u8,
+    reserved: [u8; 10],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct RemappingHeader {
+    typ: u16,
+    length: u16,
+}
+
+/// DMA Remapping Hardware Unit Definition structure.
+#[repr(C, packed)]
+struct RawDrhd {
+    header: RemappingHeader,
+    flags: u8,
+    size: u8,
+    segment: u16,
+    register_base: u64,
+}
+
+/// Reserved Memory Region Reporting structure.
+#[repr(C, packed)]
+struct RawRmrr {
+    header: RemappingHeader,
+    reserved: u16,
+    segment: u16,
+    base: u64,
+    limit: u64,
+}
+
+#[repr(C, packed)]
+struct RawDeviceScope {
+    typ: u8,
+    length: u8,
+    flags: u8,
+    reserved: u8,
+    enumeration_id: u8,
+    start_bus: u8,
+}
+
+/// A device (or a bridge, for [`DeviceScopeType::Bridge`]) in the scope of a remapping
+/// structure. The path is a list of (device, function) pairs, starting from the start
+/// bus and going through the bridges.
+#[derive(Debug, Clone)]
+pub struct DeviceScope {
+    pub typ: DeviceScopeType,
+    pub start_bus: u8,
+    pub path: Vec<(u8, u8)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceScopeType {
+    Endpoint,
+    Bridge,
+    Other(u8),
+}
+
+/// A DMA remapping hardware unit.
+#[derive(Debug)]
+pub struct Drhd {
+    pub register_base: PhysAddr,
+    pub segment: u16,
+    /// If set, the unit handles all of the devices of the segment that are not in the
+    /// scope of the other units.
+    pub include_all: bool,
+    pub devices: Vec<DeviceScope>,
+}
+
+/// A memory region that is used by the firmware for DMA and has to be identity mapped
+/// for the devices in its scope.
+#[derive(Debug)]
+pub struct Rmrr {
+    pub segment: u16,
+    pub base: PhysAddr,
+    /// The last address of the region (inclusive).
+    pub limit: PhysAddr,
+    pub devices: Vec<DeviceScope>,
+}
+
+pub struct DmarInfo {
+    /// The maximum DMA physical addressability (in bits) of the platform.
+    pub host_address_width: u8,
+    pub units: Vec<Drhd>,
+    pub reserved_regions: Vec<Rmrr>,
+}
+
+/// Parses the device scopes between `start` and `end`.
+unsafe fn parse_device_scopes(mut start: *const u8, end: *const u8) -> Vec<DeviceScope> {
+    let mut scopes = Vec::new();
+
+    while start < end {
+        let raw = &*(start as *const RawDeviceScope);
+        let length = raw.length as usize;
+
+        if length < mem::size_of::<RawDeviceScope>() {
+            log::warn!("dmar: invalid device scope length {}", length);
+            break;
+        }
+
+        let path_len = (length - mem::size_of::<RawDeviceScope>()) / 2;
+        let path = start.add(mem::size_of::<RawDeviceScope>());
+
+        let typ = match raw.typ {
+            1 => DeviceScopeType::Endpoint,
+            2 => DeviceScopeType::Bridge,
+            typ => DeviceScopeType::Other(typ),
+        };
+
+        scopes.push(DeviceScope {
+            typ,
+            start_bus: raw.start_bus,
+            path: (0..path_len)
+                .map(|i| (*path.add(i * 2), *path.add(i * 2 + 1)))
+                .collect(),
+        });
+
+        start = start.add(length);
+    }
+
+    scopes
+}
+
+impl Dmar {
+    pub(super) fn init(&'static self) {
+        let mut info = DmarInfo {
+            host_address_width: self.host_address_width + 1,
+            units: Vec::new(),
+            reserved_regions: Vec::new(),
+        };
+
+        unsafe {
+            let mut current = (self as *const _ as *const u8).add(mem::size_of::<Self>());
+            let limit = (self as *const _ as *const u8).add(self.header.length as usize);
+
+            while current < limit {
+                let header = *(current as *const RemappingHeader);
+                let end = current.add(header.length as usize);
+
+                if header.length == 0 {
+                    log::warn!("dmar: invalid remapping structure length");
+                    break;
+                }
+
+                match header.typ {
+                    0 => {
+                        let raw = &*(current as *const RawDrhd);
+                        let scopes = current.add(mem::size_of::<RawDrhd>());
+
+                        info.units.push(Drhd {
+                            register_base: PhysAddr::new(raw.register_base),
+                            segment: raw.segment,
+                            include_all: raw.flags & DRHD_INCLUDE_PCI_ALL != 0,
+                            devices: parse_device_scopes(scopes, end),
+                        });
+                    }
+
+                    1 => {
+                        let raw = &*(current as *const RawRmrr);
+                        let scopes = current.add(mem::size_of::<RawRmrr>());
+
+                        info.reserved_regions.push(Rmrr {
+                            segment: raw.segment,
+                            base: PhysAddr::new(raw.base),
+                            limit: PhysAddr::new(raw.limit),
+                            devices: parse_device_scopes(scopes, end),
+                        });
+                    }
+
+                    // The ATSR, RHSA, ANDD and SATC structures are not used.
+                    _ => {}
+                }
+
+                current = end;
+            }
+        }
+
+        log::debug!(
+            "dmar: {} remapping units, {} reserved regions",
+            info.units.len(),
+            info.reserved_regions.len()
+        );
+
+        DMAR.call_once(|| info);
+    }
+}
+
+/// Returns the parsed DMAR table, if the platform has one.
+pub fn get() -> Option<&'static DmarInfo> {
+    DMAR.get()
+}
@@ -21,7 +21,10 @@
 //!
 //! **Notes**: <https://wiki.osdev.org/FADT>
 
+use crate::mem::paging::PhysAddr;
+
 use super::sdt::Sdt;
+use super::GenericAddressStructure;
 
 pub const SIGNATURE: &str = "FACP";
 
@@ -72,4 +75,72 @@ pub struct Fadt {
     reserved2: u8,
 
     pub flags: u32,
+
+    // Fields below are only present in the ACPI 2.0+ FADT. Use [`Fadt::reset_register`]
+    // and [`Fadt::facs_address`] to access them.
+    reset_reg: GenericAddressStructure,
+    reset_value: u8,
+    arm_boot_arch: u16,
+    fadt_minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+}
+
+impl Fadt {
+    /// Returns whether the table is long enough to contain the field ending at `end`.
+    fn has_field(&self, end: usize) -> bool {
+        self.header.length as usize >= end
+    }
+
+    /// Returns the reset register and the value to write to it, if the firmware
+    /// provides one.
+    pub fn reset_register(&self) -> Option<(GenericAddressStructure, u8)> {
+        const RESET_REG_SUP: u32 = 1 << 10;
+
+        // `reset_value` is at offset 128.
+        if !self.has_field(129) || self.flags & RESET_REG_SUP == 0 {
+            return None;
+        }
+
+        Some((self.reset_reg, self.reset_value))
+    }
+
+    /// Returns the physical address of the firmware ACPI control structure (FACS).
+    pub fn facs_address(&self) -> Option<PhysAddr> {
+        // `x_firmware_ctrl` is at offset 132.
+        let address = if self.has_field(140) && self.x_firmware_ctrl != 0 {
+            self.x_firmware_ctrl
+        } else {
+            self.firmware_ctrl as u64
+        };
+
+        if address == 0 {
+            None
+        } else {
+            Some(PhysAddr::new(address))
+        }
+    }
+}
+
+/// The firmware ACPI control structure (FACS) is a structure in read/write memory
+/// that the OS uses to pass the waking vector to the firmware.
+///
+/// **Notes**: <https://uefi.org/specs/ACPI/6.4/05_ACPI_Software_Programming_Model/ACPI_Software_Programming_Model.html#firmware-acpi-control-structure-facs>
+#[repr(C, packed)]
+pub struct Facs {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub hardware_signature: u32,
+    /// The 32-bit real mode address of the waking vector. The firmware jumps to it
+    /// with CS set to `address >> 4` and IP set to `address & 0xf`.
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    /// The 64-bit physical address of the waking vector. If non-zero, it is used
+    /// instead of `firmware_waking_vector` (and the firmware may enter it in
+    /// protected mode), so it is cleared by the kernel.
+    pub x_firmware_waking_vector: u64,
+    pub version: u8,
+    reserved: [u8; 3],
+    pub ospm_flags: u32,
 }
@@ -19,11 +19,22 @@
 
 use core::ptr;
 
+use spin::Once;
+
+use crate::mem::paging::PhysAddr;
+
 use super::sdt::Sdt;
 use super::GenericAddressStructure;
 
 pub const SIGNATURE: &str = "HPET";
 
+static HPET_ADDRESS: Once<PhysAddr> = Once::new();
+
+/// Returns the physical address of the HPET registers, if the HPET table was found.
+pub fn get_base_address() -> Option<PhysAddr> {
+    HPET_ADDRESS.get().copied()
+}
+
 #[repr(C, packed)]
 pub(super) struct Hpet {
     header: Sdt,
@@ -39,6 +50,12 @@ pub(super) struct Hpet {
 impl Hpet {
     pub fn new(sdt: &'static Sdt) -> Self {
         let this = unsafe { ptr::read((sdt as *const Sdt) as *const Self) };
+        let base_address = this.base_address;
+
+        // The registers have to be memory mapped (address space 0).
+        if base_address.address_space == 0 {
+            HPET_ADDRESS.call_once(|| PhysAddr::new(base_address.address));
+        }
 
         this
     }
@@ -29,15 +29,18 @@ use crate::{
     utils::sync::{Mutex, MutexGuard},
 };
 
-use self::{hpet::Hpet, madt::Madt, mcfg::Mcfg, sdt::Sdt};
+use self::{dmar::Dmar, hpet::Hpet, madt::Madt, mcfg::Mcfg, sdt::Sdt, slit::Slit, srat::Srat};
 
 pub mod aml;
+pub mod dmar;
 pub mod fadt;
 pub mod hpet;
 pub mod madt;
 pub mod mcfg;
 pub mod rsdp;
 pub mod sdt;
+pub mod slit;
+pub mod srat;
 
 enum AcpiHeader {
     Rsdt(&'static rsdp::Rsdt<u32>),
@@ -140,4 +143,25 @@ pub fn init(rsdp_address: VirtAddr) {
     }
 
     init_table!(hpet::SIGNATURE => Hpet);
+
+    if let Some(header) = acpi_table.lookup_entry(dmar::SIGNATURE) {
+        unsafe {
+            let dmar: &'static Dmar = header.as_ref();
+            dmar.init();
+        }
+    }
+
+    if let Some(header) = acpi_table.lookup_entry(srat::SIGNATURE) {
+        unsafe {
+            let srat: &'static Srat = header.as_ref();
+            srat.init();
+        }
+    }
+
+    if let Some(header) = acpi_table.lookup_entry(slit::SIGNATURE) {
+        unsafe {
+            let slit: &'static Slit = header.as_ref();
+            slit.init();
+        }
+    }
 }
@@ -0,0 +1,106 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The ACPI SLIT (System Locality Information Table) provides the relative distances
+//! between the proximity domains (NUMA nodes) of the system. The distance from a domain
+//! to itself is normalized to 10.
+//!
+//! **Notes**: ACPI Specification, Section 5.2.17 "System Locality Information Table"
+
+use core::mem;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::sdt::Sdt;
+
+pub(super) const SIGNATURE: &str = "SLIT";
+
+/// The distance from a proximity domain to itself.
+pub const LOCAL_DISTANCE: u8 = 10;
+/// The distance between two proximity domains, when the platform has no SLIT.
+pub const REMOTE_DISTANCE: u8 = 20;
+
+static SLIT: Once<SlitInfo> = Once::new();
+
+#[repr(C, packed)]
+pub(super) struct Slit {
+    header: Sdt,
+    localities: u64,
+}
+
+pub struct SlitInfo {
+    localities: usize,
+    distances: Vec<u8>,
+}
+
+impl SlitInfo {
+    /// Returns the relative distance between the proximity domains `from` and `to`.
+    pub fn distance(&self, from: u32, to: u32) -> u8 {
+        let (from, to) = (from as usize, to as usize);
+
+        if from >= self.localities || to >= self.localities {
+            return if from == to {
+                LOCAL_DISTANCE
+            } else {
+                REMOTE_DISTANCE
+            };
+        }
+
+        self.distances[from * self.localities + to]
+    }
+}
+
+impl Slit {
+    pub(super) fn init(&'static self) {
+        let localities = self.localities as usize;
+        let available = self.header.length as usize - mem::size_of::<Self>();
+
+        if localities * localities > available {
+            log::warn!("slit: invalid number of localities {}", localities);
+            return;
+        }
+
+        let distances = unsafe {
+            let matrix = (self as *const _ as *const u8).add(mem::size_of::<Self>());
+            core::slice::from_raw_parts(matrix, localities * localities).to_vec()
+        };
+
+        log::debug!("slit: {} localities", localities);
+
+        SLIT.call_once(|| SlitInfo {
+            localities,
+            distances,
+        });
+    }
+}
+
+/// Returns the parsed SLIT table, if the platform has one.
+pub fn get() -> Option<&'static SlitInfo> {
+    SLIT.get()
+}
+
+/// Returns the relative distance between the proximity domains `from` and `to`.
+pub fn distance(from: u32, to: u32) -> u8 {
+    match get() {
+        Some(slit) => slit.distance(from, to),
+        None if from == to => LOCAL_DISTANCE,
+        None => REMOTE_DISTANCE,
+    }
+}
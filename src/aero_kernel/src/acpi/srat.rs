@@ -0,0 +1,197 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The ACPI SRAT (System Resource Affinity Table) associates the processors and the
+//! memory ranges with the proximity domains (NUMA nodes) of the system.
+//!
+//! **Notes**: ACPI Specification, Section 5.2.16 "System Resource Affinity Table"
+
+use core::mem;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::mem::paging::PhysAddr;
+
+use super::sdt::Sdt;
+
+pub(super) const SIGNATURE: &str = "SRAT";
+
+const AFFINITY_ENABLED: u32 = 1 << 0;
+const MEMORY_HOT_PLUGGABLE: u32 = 1 << 1;
+
+static SRAT: Once<SratInfo> = Once::new();
+
+#[repr(C, packed)]
+pub(super) struct Srat {
+    header: Sdt,
+    reserved1: u32,
+    reserved2: u64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct AffinityHeader {
+    typ: u8,
+    length: u8,
+}
+
+/// Processor Local APIC/SAPIC Affinity structure.
+#[repr(C, packed)]
+struct RawLocalApicAffinity {
+    header: AffinityHeader,
+    proximity_domain_low: u8,
+    apic_id: u8,
+    flags: u32,
+    local_sapic_eid: u8,
+    proximity_domain_high: [u8; 3],
+    clock_domain: u32,
+}
+
+/// Memory Affinity structure.
+#[repr(C, packed)]
+struct RawMemoryAffinity {
+    header: AffinityHeader,
+    proximity_domain: u32,
+    reserved1: u16,
+    base: u64,
+    length: u64,
+    reserved2: u32,
+    flags: u32,
+    reserved3: u64,
+}
+
+/// Processor Local x2APIC Affinity structure.
+#[repr(C, packed)]
+struct RawX2ApicAffinity {
+    header: AffinityHeader,
+    reserved1: u16,
+    proximity_domain: u32,
+    x2apic_id: u32,
+    flags: u32,
+    clock_domain: u32,
+    reserved2: u32,
+}
+
+/// The proximity domain of a processor.
+#[derive(Debug, Copy, Clone)]
+pub struct CpuAffinity {
+    pub apic_id: u32,
+    pub domain: u32,
+}
+
+/// The proximity domain of a memory range.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryAffinity {
+    pub domain: u32,
+    pub base: PhysAddr,
+    /// The end of the range (exclusive).
+    pub end: PhysAddr,
+    pub hot_pluggable: bool,
+}
+
+pub struct SratInfo {
+    pub cpus: Vec<CpuAffinity>,
+    pub memory: Vec<MemoryAffinity>,
+}
+
+impl Srat {
+    pub(super) fn init(&'static self) {
+        let mut info = SratInfo {
+            cpus: Vec::new(),
+            memory: Vec::new(),
+        };
+
+        unsafe {
+            let mut current = (self as *const _ as *const u8).add(mem::size_of::<Self>());
+            let limit = (self as *const _ as *const u8).add(self.header.length as usize);
+
+            while current < limit {
+                let header = *(current as *const AffinityHeader);
+
+                if header.length == 0 {
+                    log::warn!("srat: invalid affinity structure length");
+                    break;
+                }
+
+                match header.typ {
+                    0 => {
+                        let raw = &*(current as *const RawLocalApicAffinity);
+                        let high = raw.proximity_domain_high;
+
+                        if raw.flags & AFFINITY_ENABLED != 0 {
+                            info.cpus.push(CpuAffinity {
+                                apic_id: raw.apic_id as u32,
+                                domain: u32::from_le_bytes([
+                                    raw.proximity_domain_low,
+                                    high[0],
+                                    high[1],
+                                    high[2],
+                                ]),
+                            });
+                        }
+                    }
+
+                    1 => {
+                        let raw = &*(current as *const RawMemoryAffinity);
+
+                        if raw.flags & AFFINITY_ENABLED != 0 && raw.length != 0 {
+                            info.memory.push(MemoryAffinity {
+                                domain: raw.proximity_domain,
+                                base: PhysAddr::new(raw.base),
+                                end: PhysAddr::new(raw.base + raw.length),
+                                hot_pluggable: raw.flags & MEMORY_HOT_PLUGGABLE != 0,
+                            });
+                        }
+                    }
+
+                    2 => {
+                        let raw = &*(current as *const RawX2ApicAffinity);
+
+                        if raw.flags & AFFINITY_ENABLED != 0 {
+                            info.cpus.push(CpuAffinity {
+                                apic_id: raw.x2apic_id,
+                                domain: raw.proximity_domain,
+                            });
+                        }
+                    }
+
+                    // The GICC, GIC ITS and generic initiator affinity structures are
+                    // not used.
+                    _ => {}
+                }
+
+                current = current.add(header.length as usize);
+            }
+        }
+
+        log::debug!(
+            "srat: {} processors, {} memory ranges",
+            info.cpus.len(),
+            info.memory.len()
+        );
+
+        SRAT.call_once(|| info);
+    }
+}
+
+/// Returns the parsed SRAT table, if the platform has one.
+pub fn get() -> Option<&'static SratInfo> {
+    SRAT.get()
+}
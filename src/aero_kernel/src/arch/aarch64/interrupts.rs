@@ -37,11 +37,28 @@ pub struct InterruptStack {
     x0: u64,
 }
 
-pub fn allocate_vector() -> u8 {
+pub fn allocate_vector() -> Result<u8, IrqError> {
     unimplemented!()
 }
 
-pub fn register_handler(vector: u8, handler: fn(&mut InterruptStack)) {
+bitflags::bitflags! {
+    pub struct IrqFlags: u32 {
+        const SHARED = 1 << 0;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqError {
+    Busy,
+    NoVectors,
+}
+
+pub fn request_irq(
+    vector: u8,
+    name: &'static str,
+    handler: fn(&mut InterruptStack),
+    flags: IrqFlags,
+) -> Result<(), IrqError> {
     unimplemented!()
 }
 
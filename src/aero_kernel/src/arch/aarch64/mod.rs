@@ -19,6 +19,8 @@
 
 pub mod dtb;
 pub mod interrupts;
+pub mod ptrace;
+pub mod reboot;
 pub mod task;
 pub mod time;
 pub mod tls;
@@ -0,0 +1,30 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use aero_syscall::prelude::PtraceRegs;
+
+use super::interrupts::InterruptStack;
+
+pub fn validate_regs(_regs: &PtraceRegs) -> bool {
+    unimplemented!()
+}
+
+pub fn signal_stop(_stack: &mut InterruptStack, _signal: usize) -> usize {
+    unimplemented!()
+}
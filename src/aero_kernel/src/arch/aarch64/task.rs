@@ -41,6 +41,7 @@ impl ArchTask {
         &self,
         entry: usize,
         usr_stack: usize,
+        tls: usize,
     ) -> Result<Self, MapToError<Size4KiB>> {
         unimplemented!()
     }
@@ -52,6 +53,7 @@ impl ArchTask {
 
         argv: Option<ExecArgs>,
         envv: Option<ExecArgs>,
+        stack_size: u64,
     ) -> Result<(), MapToError<Size4KiB>> {
         unimplemented!()
     }
@@ -59,6 +61,10 @@ impl ArchTask {
     pub fn fork(&self) -> Result<Self, MapToError<Size4KiB>> {
         unimplemented!()
     }
+
+    pub fn is_user(&self) -> bool {
+        unimplemented!()
+    }
 }
 
 pub fn userland_last_address() -> VirtAddr {
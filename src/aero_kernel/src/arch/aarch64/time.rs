@@ -27,6 +27,19 @@ pub fn get_realtime_clock() -> TimeSpec {
     unimplemented!()
 }
 
+pub fn set_realtime_clock(_time: &TimeSpec) {
+    unimplemented!()
+}
+
+/// Returns the time elapsed since boot.
+pub fn get_monotonic_clock() -> TimeSpec {
+    // TODO: aarch64 port (the logger requires this to not panic)
+    TimeSpec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    }
+}
+
 pub fn init() {
     unimplemented!()
 }
@@ -23,12 +23,13 @@ use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use crate::arch::interrupts::InterruptStack;
 use crate::arch::{interrupts, tls};
 use crate::mem::paging::{PhysAddr, VirtAddr};
+use alloc::vec::Vec;
 use raw_cpuid::{CpuId, FeatureInfo};
 use spin::Once;
 
 use crate::utils::sync::{Mutex, MutexGuard};
 
-use super::{io, time};
+use super::{hpet, io, time};
 
 use crate::acpi::madt;
 use crate::PHYSICAL_MEMORY_OFFSET;
@@ -53,6 +54,15 @@ const XAPIC_TPR: u32 = 0x080;
 /// Local APIC ID register. Read-only. See Section 10.12.5.1 for initial values.
 const XAPIC_ID: u32 = 0x020;
 
+/// LVT Performance Monitoring Counters register. Read/write.
+const XAPIC_LVT_PERF: u32 = 0x340;
+
+/// LVT LINT0 register. Read/write.
+const XAPIC_LVT_LINT0: u32 = 0x350;
+
+/// LVT LINT1 register. Read/write.
+const XAPIC_LVT_LINT1: u32 = 0x360;
+
 /// LVT Timer register. Read/write. See Figure 10-8 for reserved bits.
 const XAPIC_LVT_TIMER: u32 = 0x320;
 
@@ -63,12 +73,38 @@ const XAPIC_TIMER_INIT_COUNT: u32 = 0x380;
 /// Figure 10-10 for reserved bits.
 const XAPIC_TIMER_DIV_CONF: u32 = 0x3E0;
 
+/// Interrupt Command Register (ICR). Read/write. In the XAPIC mode, the destination is
+/// in the high half of the register, which is not used with a destination shorthand.
+const XAPIC_ICR_LOW: u32 = 0x300;
+
+/// The NMI delivery mode of the LVT entries.
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+
+/// The "self" destination shorthand of the ICR.
+const ICR_DEST_SELF: u32 = 0b01 << 18;
+
 /// Current Count register (for Timer). Read-only.
 pub const XAPIC_TIMER_CURRENT_COUNT: u32 = 0x390;
 
 const X2APIC_BASE_MSR: u32 = 0x800;
 
+/// The local APIC registers that are saved across a suspend to RAM, in the order they
+/// are restored in. The initial count is restored last as writing it starts the timer.
+const SAVED_REGISTERS: [u32; 9] = [
+    XAPIC_TPR,
+    XAPIC_SVR,
+    XAPIC_LVT_ERROR,
+    XAPIC_LVT_PERF,
+    XAPIC_LVT_LINT0,
+    XAPIC_LVT_LINT1,
+    XAPIC_TIMER_DIV_CONF,
+    XAPIC_LVT_TIMER,
+    XAPIC_TIMER_INIT_COUNT,
+];
+
 static LOCAL_APIC: Once<Mutex<LocalApic>> = Once::new();
+/// The handle of the local APIC used by the NMI handlers (see [`get_local_apic_nmi`]).
+static LOCAL_APIC_NMI: Once<LocalApic> = Once::new();
 static BSP_APIC_ID: AtomicU64 = AtomicU64::new(0xFFFF_FFFF_FFFF_FFFF);
 
 /// The count of all the active CPUs.
@@ -76,6 +112,19 @@ pub static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 static BSP_READY: AtomicBool = AtomicBool::new(false);
 
+/// Maps the CPU IDs to the IDs of their local APICs.
+static CPU_APIC_IDS: Mutex<Vec<(usize, u32)>> = Mutex::new(Vec::new());
+
+/// Redirection entry bits of the I/O APIC.
+const IOREDTBL_ACTIVE_LOW: u64 = 1 << 13;
+const IOREDTBL_LEVEL_TRIGGERED: u64 = 1 << 15;
+const IOREDTBL_MASKED: u64 = 1 << 16;
+
+/// MPS INTI flags of the interrupt source overrides (see the ACPI specification,
+/// Section 5.2.12.5 "Interrupt Source Override Structure").
+const MPS_INTI_POLARITY_ACTIVE_LOW: u16 = 0b11;
+const MPS_INTI_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ApicType {
     Xapic,
@@ -110,6 +159,7 @@ fn lapic_error_handler(_stack: &mut InterruptStack) {
     log::error!("ESR={:#0x}", self::get_local_apic().get_esr());
 }
 
+#[derive(Clone)]
 pub struct LocalApic {
     address: VirtAddr,
     apic_type: ApicType,
@@ -143,8 +193,15 @@ impl LocalApic {
             // Enable local APIC; set spurious interrupt vector.
             self.write(XAPIC_SVR, 0x100 | APIC_SPURIOUS_VECTOR);
 
-            let lvt_err_vector = interrupts::allocate_vector();
-            interrupts::register_handler(lvt_err_vector, lapic_error_handler);
+            let lvt_err_vector = interrupts::allocate_vector()
+                .expect("apic: failed to allocate the LVT error vector");
+            interrupts::request_irq(
+                lvt_err_vector,
+                "lapic-error",
+                lapic_error_handler,
+                interrupts::IrqFlags::empty(),
+            )
+            .expect("apic: failed to request the LVT error IRQ");
 
             // Set up LVT (Local Vector Table) error.
             self.write(XAPIC_LVT_ERROR, lvt_err_vector as u32);
@@ -185,6 +242,32 @@ impl LocalApic {
         }
     }
 
+    /// Delivers the performance counter overflow interrupts to the provided vector. The
+    /// local APIC masks the entry when the interrupt is delivered, so this has to be called
+    /// again by the interrupt handler.
+    pub fn set_perf_vector(&mut self, vector: u8) {
+        unsafe {
+            self.write(XAPIC_LVT_PERF, vector as u32);
+        }
+    }
+
+    /// Delivers the performance counter overflow interrupts as NMIs. Like with
+    /// [`Self::set_perf_vector`], this has to be called again by the NMI handler.
+    pub fn set_perf_nmi(&mut self) {
+        unsafe {
+            self.write(XAPIC_LVT_PERF, LVT_DELIVERY_NMI);
+        }
+    }
+
+    /// Sends the interrupt with the provided vector to the current CPU.
+    pub fn send_self_ipi(&mut self, vector: u8) {
+        // The ICR is a single 64-bit MSR in the X2APIC mode, which the 32-bit write
+        // clears the destination of. The destination is not used with a shorthand.
+        unsafe {
+            self.write(XAPIC_ICR_LOW, ICR_DEST_SELF | vector as u32);
+        }
+    }
+
     /// Stops the APIC timer.
     pub fn timer_stop(&mut self) {
         unsafe {
@@ -216,6 +299,21 @@ impl LocalApic {
             self.write(XAPIC_LVT_TIMER, (1 << 16) | 0xff); // vector 0xff, masked
             self.write(XAPIC_TIMER_DIV_CONF, 1);
 
+            // Prefer the HPET as the reference as it is a lot more precise than the PIT.
+            if let Some(hpet) = hpet::get() {
+                let start = hpet.nanoseconds();
+                self.write(XAPIC_TIMER_INIT_COUNT, SAMPLES);
+
+                while self.read(XAPIC_TIMER_CURRENT_COUNT) != 0 {}
+
+                let elapsed = hpet.nanoseconds() - start;
+                let timer_frequency = SAMPLES as u64 * 1_000_000_000 / elapsed;
+
+                tls::get_percpu().lapic_timer_frequency = timer_frequency as u32;
+                self.timer_stop();
+                return;
+            }
+
             time::set_reload_value(0xffff);
 
             let initial_pit_tick = time::get_current_count();
@@ -310,6 +408,61 @@ impl LocalApic {
     }
 }
 
+/// The state of the local APIC and the I/O APICs, which is lost when the system is
+/// suspended to RAM.
+pub struct ApicState {
+    local: [u32; SAVED_REGISTERS.len()],
+    /// The (I/O APIC, register, value) triples of the redirection entries.
+    redirects: Vec<(usize, u32, u32)>,
+}
+
+/// Saves the state of the local APIC of the current CPU and of the I/O APICs.
+pub fn suspend() -> ApicState {
+    let local_apic = get_local_apic();
+    let mut local = [0; SAVED_REGISTERS.len()];
+
+    for (value, register) in local.iter_mut().zip(SAVED_REGISTERS) {
+        *value = unsafe { local_apic.read(register) };
+    }
+
+    let mut redirects = Vec::new();
+
+    for io_apic in 0..madt::IO_APICS.read().len() {
+        for entry in 0..=io_apic_get_max_redirect(io_apic) {
+            let ioredtbl = entry * 2 + 16;
+
+            // The destination (in the upper half) is restored before the entry is
+            // unmasked by restoring the lower half.
+            for register in [ioredtbl + 1, ioredtbl] {
+                let value = unsafe { io_apic_read(io_apic, register) };
+                redirects.push((io_apic, register, value));
+            }
+        }
+    }
+
+    ApicState { local, redirects }
+}
+
+/// Restores the state saved by [`suspend`].
+pub fn resume(state: &ApicState) {
+    let mut local_apic = get_local_apic();
+
+    unsafe {
+        // The firmware resets the local APIC to the XAPIC mode.
+        if local_apic.apic_type == ApicType::X2apic {
+            io::wrmsr(io::IA32_APIC_BASE, io::rdmsr(io::IA32_APIC_BASE) | 1 << 10);
+        }
+
+        for (value, register) in state.local.iter().zip(SAVED_REGISTERS) {
+            local_apic.write(register, *value);
+        }
+
+        for (io_apic, register, value) in state.redirects.iter() {
+            io_apic_write(*io_apic, *register, *value);
+        }
+    }
+}
+
 /// Get a mutable reference to the local apic.
 pub fn get_local_apic() -> MutexGuard<'static, LocalApic> {
     LOCAL_APIC
@@ -318,6 +471,19 @@ pub fn get_local_apic() -> MutexGuard<'static, LocalApic> {
         .lock()
 }
 
+/// Returns a handle to the local APIC that is not behind its lock, for the NMI handlers
+/// (which can interrupt the holder of the lock).
+///
+/// ## Safety
+/// Only the registers that are written at once (the LVT entries and the ICR with a
+/// destination shorthand) may be written through the handle.
+pub unsafe fn get_local_apic_nmi() -> LocalApic {
+    LOCAL_APIC_NMI
+        .get()
+        .expect("Attempted to get the local apic before it was initialized")
+        .clone()
+}
+
 /// Get the local BSP's id.
 #[inline]
 pub fn get_bsp_id() -> u64 {
@@ -330,6 +496,20 @@ pub fn get_cpu_count() -> usize {
     CPU_COUNT.load(Ordering::Relaxed)
 }
 
+/// Records the ID of the local APIC of the provided CPU.
+pub fn register_cpu_apic_id(cpu: usize, apic_id: u32) {
+    CPU_APIC_IDS.lock_irq().push((cpu, apic_id));
+}
+
+/// Returns the ID of the local APIC of the provided CPU.
+pub fn get_cpu_apic_id(cpu: usize) -> Option<u32> {
+    CPU_APIC_IDS
+        .lock_irq()
+        .iter()
+        .find(|(id, _)| *id == cpu)
+        .map(|(_, apic_id)| *apic_id)
+}
+
 #[inline]
 pub fn is_bsp_ready() -> bool {
     BSP_READY.load(Ordering::SeqCst)
@@ -370,9 +550,9 @@ pub fn io_apic_from_redirect(gsi: u32) -> Option<usize> {
     let io_apics = madt::IO_APICS.read();
 
     for (i, entry) in io_apics.iter().enumerate() {
-        let max_redirect = entry.global_system_interrupt_base + io_apic_get_max_redirect(i) > gsi;
+        let base = entry.global_system_interrupt_base;
 
-        if entry.global_system_interrupt_base <= gsi || max_redirect {
+        if base <= gsi && gsi <= base + io_apic_get_max_redirect(i) {
             return Some(i);
         }
     }
@@ -380,55 +560,54 @@ pub fn io_apic_from_redirect(gsi: u32) -> Option<usize> {
     None
 }
 
-pub fn io_apic_set_redirect(vec: u8, gsi: u32, flags: u16, status: i32) {
+/// Programs the redirection entry of the GSI to deliver the interrupt to `vec` on the
+/// local APIC with the ID `dest`. The `flags` are the MPS INTI flags of the interrupt
+/// source override, which describe the polarity and trigger mode.
+pub fn io_apic_set_redirect(vec: u8, gsi: u32, flags: u16, dest: u32, masked: bool) {
     if let Some(io_apic) = io_apic_from_redirect(gsi) {
-        let mut redirect = 0x00;
+        let mut redirect = vec as u64;
 
-        // Active high(0) or low(1)
-        if flags & 2 == 1 {
-            redirect |= (1 << 13) as u8;
+        if flags & MPS_INTI_POLARITY_ACTIVE_LOW == MPS_INTI_POLARITY_ACTIVE_LOW {
+            redirect |= IOREDTBL_ACTIVE_LOW;
         }
 
-        // Edge(0) or level(1) triggered
-        if flags & 8 == 1 {
-            redirect |= (1 << 15) as u8;
+        if flags & MPS_INTI_TRIGGER_LEVEL == MPS_INTI_TRIGGER_LEVEL {
+            redirect |= IOREDTBL_LEVEL_TRIGGERED;
         }
 
-        if status == 1 {
-            // Set the mask bit
-            redirect |= (1 << 16) as u8;
+        if masked {
+            redirect |= IOREDTBL_MASKED;
         }
 
-        redirect |= vec;
-        redirect |= (crate::arch::tls::get_cpuid() << 56) as u8; // Set the target APIC ID.
+        redirect |= (dest as u64) << 56; // Set the target APIC ID.
 
         let entry = madt::IO_APICS.read()[io_apic];
         let ioredtbl = (gsi - entry.global_system_interrupt_base) * 2 + 16;
 
         unsafe {
-            io_apic_write(io_apic, ioredtbl + 0, redirect as _);
-            io_apic_write(io_apic, ioredtbl + 1, (redirect as u64 >> 32) as _);
+            io_apic_write(io_apic, ioredtbl, redirect as u32);
+            io_apic_write(io_apic, ioredtbl + 1, (redirect >> 32) as u32);
         }
 
-        log::info!("registered redirect (vec={}, gsi={})", vec, gsi);
+        log::info!("registered redirect (vec={vec}, gsi={gsi}, dest={dest})");
     } else {
         log::warn!("unable to register redirect (vec={}, gsi={})", vec, gsi);
     }
 }
 
-pub fn io_apic_setup_legacy_irq(irq: u8, vec: u8, status: i32) {
-    // Redirect will handle weather IRQ is masked or not, we just need to
-    // search the MADT ISOs for a corrosponsing IRQ.
+/// Returns the GSI and the MPS INTI flags that the legacy ISA IRQ is connected to, as
+/// described by the interrupt source overrides of the MADT.
+pub fn legacy_irq_to_gsi(irq: u8) -> (u32, u16) {
     let isos_entries = madt::ISOS.read();
 
     for entry in isos_entries.iter() {
         if entry.irq == irq {
-            io_apic_set_redirect(vec, entry.global_system_interrupt, entry.flags, status);
-            return;
+            return (entry.global_system_interrupt, entry.flags);
         }
     }
 
-    io_apic_set_redirect(vec, irq as u32, 0, status)
+    // ISA IRQs are identity mapped to the GSIs unless overridden.
+    (irq as u32, 0)
 }
 
 /// Initialize the local apic.
@@ -457,6 +636,7 @@ pub fn init() -> ApicType {
     let bsp_id = local_apic.bsp_id();
 
     BSP_APIC_ID.store(bsp_id as u64, Ordering::SeqCst);
+    LOCAL_APIC_NMI.call_once(|| local_apic.clone());
     LOCAL_APIC.call_once(move || Mutex::new(local_apic));
 
     #[cfg(target_arch = "x86_64")]
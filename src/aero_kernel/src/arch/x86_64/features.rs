@@ -0,0 +1,192 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The registry of the CPU features, detected with `cpuid` once on the BSP during boot.
+//! The code paths that depend on an optional feature query it with [`cpu_has`] instead
+//! of assuming the features of the default QEMU CPU.
+//!
+//! The application processors are assumed to support the same features as the BSP.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use raw_cpuid::CpuId;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Feature {
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Avx,
+    Avx2,
+    Avx512f,
+    /// The `xsave`/`xrstor` instructions and the XCR0 register.
+    Xsave,
+    /// The `xsaveopt` instruction, which skips the unmodified state components.
+    Xsaveopt,
+    /// The `{rd,wr}{fs,gs}base` instructions.
+    Fsgsbase,
+    /// Supervisor mode execution prevention.
+    Smep,
+    /// Supervisor mode access prevention.
+    Smap,
+    Pcid,
+    Invpcid,
+    /// 1GiB pages.
+    Page1Gb,
+    /// The no-execute page protection.
+    Nx,
+    /// The TSC runs at a constant rate, regardless of the power state of the CPU.
+    InvariantTsc,
+    TscDeadline,
+    Rdtscp,
+    Rdrand,
+    Rdseed,
+    X2apic,
+}
+
+impl Feature {
+    const ALL: &'static [(Feature, &'static str)] = &[
+        (Feature::Sse3, "sse3"),
+        (Feature::Ssse3, "ssse3"),
+        (Feature::Sse41, "sse4.1"),
+        (Feature::Sse42, "sse4.2"),
+        (Feature::Avx, "avx"),
+        (Feature::Avx2, "avx2"),
+        (Feature::Avx512f, "avx512f"),
+        (Feature::Xsave, "xsave"),
+        (Feature::Xsaveopt, "xsaveopt"),
+        (Feature::Fsgsbase, "fsgsbase"),
+        (Feature::Smep, "smep"),
+        (Feature::Smap, "smap"),
+        (Feature::Pcid, "pcid"),
+        (Feature::Invpcid, "invpcid"),
+        (Feature::Page1Gb, "pdpe1gb"),
+        (Feature::Nx, "nx"),
+        (Feature::InvariantTsc, "invariant_tsc"),
+        (Feature::TscDeadline, "tsc_deadline"),
+        (Feature::Rdtscp, "rdtscp"),
+        (Feature::Rdrand, "rdrand"),
+        (Feature::Rdseed, "rdseed"),
+        (Feature::X2apic, "x2apic"),
+    ];
+
+    const fn bit(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the CPU supports the feature.
+pub fn has(feature: Feature) -> bool {
+    FEATURES.load(Ordering::Relaxed) & feature.bit() != 0
+}
+
+/// Returns whether the CPU supports the feature, for example `cpu_has!(Fsgsbase)`.
+pub macro cpu_has($feature:ident) {
+    $crate::arch::features::has($crate::arch::features::Feature::$feature)
+}
+
+/// Returns an iterator over the names of the features supported by the CPU.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    Feature::ALL
+        .iter()
+        .filter(|(feature, _)| has(*feature))
+        .map(|(_, name)| *name)
+}
+
+fn detect() -> u64 {
+    let cpuid = CpuId::new();
+    let mut features = 0;
+
+    let has_xsave = cpuid.get_feature_info().map_or(false, |i| i.has_xsave());
+
+    let mut set = |feature: Feature, present: bool| {
+        if present {
+            features |= feature.bit();
+        }
+    };
+
+    if let Some(info) = cpuid.get_feature_info() {
+        set(Feature::Sse3, info.has_sse3());
+        set(Feature::Ssse3, info.has_ssse3());
+        set(Feature::Sse41, info.has_sse41());
+        set(Feature::Sse42, info.has_sse42());
+        set(Feature::Avx, info.has_avx());
+        set(Feature::Xsave, has_xsave);
+        set(Feature::Pcid, info.has_pcid());
+        set(Feature::TscDeadline, info.has_tsc_deadline());
+        set(Feature::Rdrand, info.has_rdrand());
+        set(Feature::X2apic, info.has_x2apic());
+    }
+
+    if let Some(info) = cpuid.get_extended_feature_info() {
+        set(Feature::Avx2, info.has_avx2());
+        set(Feature::Avx512f, info.has_avx512f());
+        set(Feature::Fsgsbase, info.has_fsgsbase());
+        set(Feature::Smep, info.has_smep());
+        set(Feature::Smap, info.has_smap());
+        set(Feature::Invpcid, info.has_invpcid());
+        set(Feature::Rdseed, info.has_rdseed());
+    }
+
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        set(Feature::Page1Gb, info.has_1gib_pages());
+        set(Feature::Nx, info.has_execute_disable());
+        set(Feature::Rdtscp, info.has_rdtscp());
+    }
+
+    if let Some(info) = cpuid.get_advanced_power_mgmt_info() {
+        set(Feature::InvariantTsc, info.has_invariant_tsc());
+    }
+
+    // The extended state leaf is only valid when `xsave` is supported.
+    if has_xsave {
+        if let Some(info) = cpuid.get_extended_state_info() {
+            set(Feature::Xsaveopt, info.has_xsaveopt());
+        }
+    }
+
+    features
+}
+
+/// Detects the features of the CPU. Called on the BSP before the other CPU specific
+/// initialization (and before the heap is initialized).
+pub fn init() {
+    let features = detect();
+
+    FEATURES.store(features, Ordering::Relaxed);
+    log::info!("cpu: detected features (mask={:#x})", features);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_bits_fit() {
+        // The features are stored as a bitmask in an `AtomicU64`.
+        assert!(Feature::ALL.len() <= 64);
+
+        let last = Feature::ALL.last().unwrap().0;
+        assert_eq!(last as usize, Feature::ALL.len() - 1);
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Management of the x87, SSE and AVX register state of the userland tasks.
+//!
+//! The kernel itself is built with soft-float and never touches these registers, so the
+//! state of a task only has to be saved and restored when switching between userland
+//! tasks. The state is switched eagerly on every context switch, using `xsave`/`xrstor`
+//! (or `xsaveopt` when available) if the CPU supports it, and `fxsave`/`fxrstor`
+//! otherwise.
+//!
+//! **Notes**: Intel SDM Volume 1, Chapter 13 "Managing State Using the XSAVE Feature Set"
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+
+use super::controlregs::{self, Cr0Flags, Cr4Flags};
+use super::features::cpu_has;
+
+/// The size of the legacy (`fxsave`) region.
+const LEGACY_AREA_SIZE: usize = 512;
+/// The size of the XSAVE header, which follows the legacy region.
+const XSAVE_HEADER_SIZE: usize = 64;
+/// The alignment required by `xsave` (`fxsave` only requires 16 bytes).
+const STATE_ALIGN: usize = 64;
+
+const XCR0_X87: u64 = 1;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+/// The AVX-512 state components (the opmask registers and the upper halves of the ZMM
+/// registers), which can only be enabled together.
+const XCR0_AVX512: u64 = (1 << 5) | (1 << 6) | (1 << 7);
+
+const DEFAULT_FCW: u16 = 0x37f;
+const DEFAULT_MXCSR: u32 = 0x1f80;
+
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+const MXCSR_MASK_OFFSET: usize = 28;
+
+/// The state components enabled in XCR0, or zero if XSAVE is not used.
+static XCR0: AtomicU64 = AtomicU64::new(0);
+/// The size of the state area, set on the BSP.
+static STATE_SIZE: AtomicUsize = AtomicUsize::new(LEGACY_AREA_SIZE);
+/// The MXCSR bits supported by the CPU. Loading an unsupported bit raises `#GP`.
+static MXCSR_MASK: AtomicU32 = AtomicU32::new(0xffbf);
+
+fn uses_xsave() -> bool {
+    XCR0.load(Ordering::Relaxed) != 0
+}
+
+/// Returns the size of the FPU state area.
+pub fn state_size() -> usize {
+    STATE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Returns the alignment of the FPU state area.
+pub const fn state_align() -> usize {
+    STATE_ALIGN
+}
+
+fn state_layout() -> Layout {
+    Layout::from_size_align(state_size(), STATE_ALIGN).unwrap()
+}
+
+unsafe fn xsetbv(xcr: u32, value: u64) {
+    asm!(
+        "xsetbv",
+        in("ecx") xcr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nomem, nostack)
+    );
+}
+
+/// Saves the FPU registers of the current CPU into the state area at `area`.
+///
+/// ## Safety
+/// The area must be [`state_size`] bytes long and aligned to [`state_align`].
+pub unsafe fn save_to(area: *mut u8) {
+    let mask = XCR0.load(Ordering::Relaxed);
+
+    if mask == 0 {
+        asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+    } else {
+        asm!(
+            "xsave64 [{}]",
+            in(reg) area,
+            in("eax") mask as u32,
+            in("edx") (mask >> 32) as u32,
+            options(nostack)
+        );
+    }
+}
+
+/// Loads the FPU registers of the current CPU from the state area at `area`.
+///
+/// ## Safety
+/// The area must be [`state_size`] bytes long, aligned to [`state_align`] and contain a
+/// valid state (see [`FpuState::load_from`]).
+pub unsafe fn restore_from(area: *const u8) {
+    let mask = XCR0.load(Ordering::Relaxed);
+
+    if mask == 0 {
+        asm!("fxrstor64 [{}]", in(reg) area, options(nostack));
+    } else {
+        asm!(
+            "xrstor64 [{}]",
+            in(reg) area,
+            in("eax") mask as u32,
+            in("edx") (mask >> 32) as u32,
+            options(nostack)
+        );
+    }
+}
+
+/// The saved FPU state of a userland task.
+pub struct FpuState {
+    area: NonNull<u8>,
+}
+
+impl FpuState {
+    /// Creates a new FPU state, in its initial configuration (all exceptions masked and
+    /// the registers cleared).
+    pub fn new() -> Self {
+        // SAFETY: The layout has a non-zero size.
+        let area = unsafe { alloc_zeroed(state_layout()) };
+        let mut state = Self {
+            area: NonNull::new(area).expect("fpu: failed to allocate the state area"),
+        };
+
+        let bytes = state.as_bytes_mut();
+
+        bytes[FCW_OFFSET..FCW_OFFSET + 2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+        bytes[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+
+        // The XSAVE header is left zeroed, so `xrstor` puts the extended state components
+        // in their initial configuration.
+        state
+    }
+
+    /// Creates a new FPU state with the FPU registers of the current CPU.
+    pub fn from_current() -> Self {
+        let state = Self::new();
+
+        // SAFETY: The area is `state_size()` bytes long and properly aligned.
+        unsafe { save_to(state.area.as_ptr()) }
+        state
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: The area is `state_size()` bytes long.
+        unsafe { core::slice::from_raw_parts(self.area.as_ptr(), state_size()) }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The area is `state_size()` bytes long.
+        unsafe { core::slice::from_raw_parts_mut(self.area.as_ptr(), state_size()) }
+    }
+
+    /// Saves the FPU registers of the current CPU, which must have been loaded from this
+    /// state with [`Self::restore`].
+    pub fn save(&mut self) {
+        let mask = XCR0.load(Ordering::Relaxed);
+
+        // `xsaveopt` skips the components that were not modified since they were loaded
+        // by `xrstor`, which is only valid if the state was restored from the same area.
+        if mask != 0 && cpu_has!(Xsaveopt) {
+            unsafe {
+                asm!(
+                    "xsaveopt64 [{}]",
+                    in(reg) self.area.as_ptr(),
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack)
+                );
+            }
+        } else {
+            unsafe { save_to(self.area.as_ptr()) }
+        }
+    }
+
+    /// Loads the FPU registers of the current CPU from the saved state.
+    pub fn restore(&self) {
+        unsafe { restore_from(self.area.as_ptr()) }
+    }
+
+    /// Replaces the saved state with the state provided by userland (for example on
+    /// `sigreturn`). The fields that would make `xrstor` or `fxrstor` fault are
+    /// sanitized.
+    pub fn load_from(&mut self, bytes: &[u8]) {
+        let xsave = uses_xsave();
+        let xcr0 = XCR0.load(Ordering::Relaxed);
+        let mxcsr_mask = MXCSR_MASK.load(Ordering::Relaxed);
+
+        let area = self.as_bytes_mut();
+        let len = core::cmp::min(area.len(), bytes.len());
+
+        area[..len].copy_from_slice(&bytes[..len]);
+
+        let mxcsr = u32::from_le_bytes(area[MXCSR_OFFSET..MXCSR_OFFSET + 4].try_into().unwrap());
+        area[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&(mxcsr & mxcsr_mask).to_le_bytes());
+
+        if xsave {
+            let header = &mut area[LEGACY_AREA_SIZE..LEGACY_AREA_SIZE + XSAVE_HEADER_SIZE];
+            let xstate_bv = u64::from_le_bytes(header[..8].try_into().unwrap());
+
+            // Only the enabled components can be restored and the compacted format
+            // (XCOMP_BV) and the reserved bytes of the header must be zero.
+            header.fill(0);
+            header[..8].copy_from_slice(&(xstate_bv & xcr0).to_le_bytes());
+        }
+    }
+}
+
+impl Clone for FpuState {
+    fn clone(&self) -> Self {
+        let mut state = Self::new();
+
+        state.as_bytes_mut().copy_from_slice(self.as_bytes());
+        state
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), state_layout()) }
+    }
+}
+
+// SAFETY: The state area is owned by the `FpuState`.
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+/// Enables the FPU, SSE and (when supported) the XSAVE managed state components on the
+/// current CPU. The size of the state area is computed on the BSP.
+pub fn init(bsp: bool) {
+    unsafe {
+        let mut cr0 = controlregs::read_cr0();
+
+        cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
+        cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+
+        controlregs::write_cr0(cr0);
+
+        let mut cr4 = controlregs::read_cr4();
+
+        cr4.insert(Cr4Flags::OSFXSR);
+        cr4.insert(Cr4Flags::OSXMMEXCPT_ENABLE);
+
+        if cpu_has!(Xsave) {
+            cr4.insert(Cr4Flags::OSXSAVE);
+        }
+
+        controlregs::write_cr4(cr4);
+    }
+
+    if cpu_has!(Xsave) {
+        // SAFETY: `cpuid` leaf 0xd is valid when XSAVE is supported.
+        let supported = unsafe {
+            let leaf = core::arch::x86_64::__cpuid_count(0xd, 0);
+            leaf.eax as u64 | (leaf.edx as u64) << 32
+        };
+
+        let mut xcr0 = XCR0_X87 | XCR0_SSE;
+
+        if cpu_has!(Avx) && supported & XCR0_AVX != 0 {
+            xcr0 |= XCR0_AVX;
+
+            if supported & XCR0_AVX512 == XCR0_AVX512 {
+                xcr0 |= XCR0_AVX512;
+            }
+        }
+
+        unsafe { xsetbv(0, xcr0) }
+
+        if bsp {
+            // EBX contains the size of the area required by the enabled components.
+            let size = unsafe { core::arch::x86_64::__cpuid_count(0xd, 0).ebx as usize };
+
+            XCR0.store(xcr0, Ordering::Relaxed);
+            STATE_SIZE.store(size, Ordering::Relaxed);
+        }
+    }
+
+    if bsp {
+        #[repr(C, align(16))]
+        struct LegacyArea([u8; LEGACY_AREA_SIZE]);
+
+        let mut legacy = LegacyArea([0; LEGACY_AREA_SIZE]);
+
+        // SAFETY: The legacy region is 512 bytes long and aligned to 16 bytes.
+        unsafe { asm!("fxsave64 [{}]", in(reg) legacy.0.as_mut_ptr(), options(nostack)) }
+
+        let mask_bytes = &legacy.0[MXCSR_MASK_OFFSET..MXCSR_MASK_OFFSET + 4];
+        let mask = u32::from_le_bytes(mask_bytes.try_into().unwrap());
+
+        // A zero mask means that the default mask is used.
+        if mask != 0 {
+            MXCSR_MASK.store(mask, Ordering::Relaxed);
+        }
+
+        log::info!(
+            "fpu: using {} (xcr0={:#x}, size={})",
+            if uses_xsave() { "xsave" } else { "fxsave" },
+            XCR0.load(Ordering::Relaxed),
+            state_size()
+        );
+    }
+}
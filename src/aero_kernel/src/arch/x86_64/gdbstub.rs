@@ -0,0 +1,607 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! In-kernel implementation of the GDB remote serial protocol, reachable over the
+//! second serial port (COM2). The stub is enabled with the `gdbstub` kernel command
+//! line flag, in which case the kernel stops right after the IDT is loaded and waits
+//! for the debugger to attach:
+//!
+//! ```text
+//! $ ./aero.py -- -serial tcp::1234,server,nowait
+//! (gdb) target remote :1234
+//! ```
+//!
+//! While the kernel is stopped, the CPU that trapped spins in the stub with interrupts
+//! disabled. The other CPUs keep running until they hit a breakpoint themselves.
+//!
+//! **Notes**: <https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html>
+
+use spin::Once;
+
+use crate::drivers::uart::SerialPort;
+use crate::mem::paging::{Translate, VirtAddr};
+use crate::mem::AddressSpace;
+use crate::utils::sync::Mutex;
+
+use super::controlregs::RFlags;
+use super::interrupts::InterruptErrorStack;
+
+const COM_2: u16 = 0x2F8;
+
+/// The maximum size of a packet, advertised to the debugger in `qSupported`.
+const MAX_PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+
+const INT3: u8 = 0xCC;
+const SIGTRAP: u8 = 5;
+
+/// The number of registers in the `g` packet, in the order expected by GDB for amd64:
+/// `rax`, `rbx`, `rcx`, `rdx`, `rsi`, `rdi`, `rbp`, `rsp`, `r8`-`r15`, `rip`, `eflags`,
+/// `cs`, `ss`, `ds`, `es`, `fs` and `gs`.
+const REGISTER_COUNT: usize = 24;
+
+static GDBSTUB: Once<Mutex<GdbStub>> = Once::new();
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn hex_digits(byte: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xF) as usize]]
+}
+
+fn parse_hex_byte(high: u8, low: u8) -> Option<u8> {
+    Some(hex_digit(high)? << 4 | hex_digit(low)?)
+}
+
+/// Parses a big-endian hexadecimal number, as used for addresses and lengths.
+fn parse_hex(bytes: &[u8]) -> Option<usize> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+
+    bytes.iter().try_fold(0usize, |value, &byte| {
+        Some(value << 4 | hex_digit(byte)? as usize)
+    })
+}
+
+/// Parses a little-endian hexadecimal register value, as used in the `G` and `P` packets.
+fn parse_register(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() % 2 != 0 || bytes.len() > 16 {
+        return None;
+    }
+
+    bytes
+        .chunks(2)
+        .enumerate()
+        .try_fold(0u64, |value, (i, pair)| {
+            Some(value | (parse_hex_byte(pair[0], pair[1])? as u64) << (i * 8))
+        })
+}
+
+/// Splits the bytes at the first occurrence of `separator`.
+fn split_once(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = bytes.iter().position(|&byte| byte == separator)?;
+    Some((&bytes[..position], &bytes[position + 1..]))
+}
+
+/// Parses the `addr,length` pair used by the memory and breakpoint packets.
+fn parse_address_length(bytes: &[u8]) -> Option<(usize, usize)> {
+    let (address, length) = split_once(bytes, b',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+fn register_size(index: usize) -> usize {
+    if index <= 16 {
+        8
+    } else {
+        4
+    }
+}
+
+/// Returns the register with the provided GDB index from the interrupted frame. The data
+/// segment registers are not saved on interrupt entry and are reported as zero.
+fn register_mut(stack: &mut InterruptErrorStack, index: usize) -> Option<&mut u64> {
+    let stack = &mut stack.stack;
+
+    Some(match index {
+        0 => &mut stack.scratch.rax,
+        1 => &mut stack.preserved.rbx,
+        2 => &mut stack.scratch.rcx,
+        3 => &mut stack.scratch.rdx,
+        4 => &mut stack.scratch.rsi,
+        5 => &mut stack.scratch.rdi,
+        6 => &mut stack.preserved.rbp,
+        7 => &mut stack.iret.rsp,
+        8 => &mut stack.scratch.r8,
+        9 => &mut stack.scratch.r9,
+        10 => &mut stack.scratch.r10,
+        11 => &mut stack.scratch.r11,
+        12 => &mut stack.preserved.r12,
+        13 => &mut stack.preserved.r13,
+        14 => &mut stack.preserved.r14,
+        15 => &mut stack.preserved.r15,
+        16 => &mut stack.iret.rip,
+        17 => &mut stack.iret.rflags,
+        18 => &mut stack.iret.cs,
+        19 => &mut stack.iret.ss,
+        _ => return None,
+    })
+}
+
+/// Returns a pointer to the byte at `address` through the higher half direct map, so that
+/// read-only kernel text can be patched without touching `CR0.WP`. Returns [`None`] if the
+/// address is not mapped in the current address space.
+fn translate(address: usize) -> Option<*mut u8> {
+    let mut address_space = AddressSpace::this();
+    let offset_table = address_space.offset_page_table();
+
+    offset_table
+        .translate_addr(VirtAddr::new(address as u64))
+        .map(|phys| phys.as_hhdm_virt().as_mut_ptr())
+}
+
+fn read_memory(address: usize) -> Option<u8> {
+    translate(address).map(|ptr| unsafe { ptr.read_volatile() })
+}
+
+fn write_memory(address: usize, value: u8) -> Option<()> {
+    translate(address).map(|ptr| unsafe { ptr.write_volatile(value) })
+}
+
+/// A response packet that is built up before being sent, so that it can be retransmitted
+/// if the debugger did not receive it correctly.
+struct Response {
+    buffer: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Response {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(MAX_PACKET_SIZE - self.len);
+
+        self.buffer[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        self.push(&hex_digits(byte));
+    }
+
+    fn ok(&mut self) {
+        self.push(b"OK");
+    }
+
+    fn error(&mut self, code: u8) {
+        self.push(b"E");
+        self.push_hex(code);
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: usize,
+    /// The instruction byte that was replaced with `int3`.
+    original: u8,
+}
+
+struct Breakpoints([Option<Breakpoint>; MAX_BREAKPOINTS]);
+
+impl Breakpoints {
+    fn contains(&self, address: usize) -> bool {
+        self.0
+            .iter()
+            .flatten()
+            .any(|breakpoint| breakpoint.address == address)
+    }
+
+    fn insert(&mut self, address: usize) -> Option<()> {
+        if self.contains(address) {
+            return Some(());
+        }
+
+        let slot = self.0.iter_mut().find(|slot| slot.is_none())?;
+        let original = read_memory(address)?;
+
+        write_memory(address, INT3)?;
+        *slot = Some(Breakpoint { address, original });
+
+        Some(())
+    }
+
+    fn remove(&mut self, address: usize) -> Option<()> {
+        let slot = self.0.iter_mut().find(|slot| {
+            slot.map(|breakpoint| breakpoint.address == address)
+                .unwrap_or(false)
+        })?;
+
+        let breakpoint = slot.take()?;
+        write_memory(breakpoint.address, breakpoint.original)
+    }
+
+    fn clear(&mut self) {
+        for slot in self.0.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                let _ = write_memory(breakpoint.address, breakpoint.original);
+            }
+        }
+    }
+}
+
+struct GdbStub {
+    port: SerialPort,
+    packet: [u8; MAX_PACKET_SIZE],
+    response: Response,
+    breakpoints: Breakpoints,
+}
+
+impl GdbStub {
+    fn new(port: SerialPort) -> Self {
+        Self {
+            port,
+            packet: [0; MAX_PACKET_SIZE],
+            response: Response::new(),
+            breakpoints: Breakpoints([None; MAX_BREAKPOINTS]),
+        }
+    }
+
+    /// Waits for a packet with a valid checksum, acknowledges it and returns its length.
+    fn receive_packet(&mut self) -> usize {
+        loop {
+            while self.port.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut checksum = 0u8;
+            let mut overflow = false;
+
+            loop {
+                let byte = self.port.read_byte();
+
+                if byte == b'#' {
+                    break;
+                }
+
+                if len < MAX_PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                } else {
+                    overflow = true;
+                }
+
+                checksum = checksum.wrapping_add(byte);
+            }
+
+            let high = self.port.read_byte();
+            let low = self.port.read_byte();
+
+            if !overflow && parse_hex_byte(high, low) == Some(checksum) {
+                self.port.send_raw(b'+');
+                return len;
+            }
+
+            self.port.send_raw(b'-');
+        }
+    }
+
+    /// Sends the response, retransmitting it until the debugger acknowledges it.
+    fn send_response(&mut self) {
+        loop {
+            let mut checksum = 0u8;
+
+            self.port.send_raw(b'$');
+
+            for &byte in self.response.as_bytes() {
+                checksum = checksum.wrapping_add(byte);
+                self.port.send_raw(byte);
+            }
+
+            self.port.send_raw(b'#');
+
+            for byte in hex_digits(checksum) {
+                self.port.send_raw(byte);
+            }
+
+            loop {
+                match self.port.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    fn read_registers(&mut self, stack: &mut InterruptErrorStack) {
+        for index in 0..REGISTER_COUNT {
+            let value = register_mut(stack, index).map(|value| *value).unwrap_or(0);
+
+            for byte in &value.to_le_bytes()[..register_size(index)] {
+                self.response.push_hex(*byte);
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, stack: &mut InterruptErrorStack, len: usize) -> bool {
+        let (command, arguments) = (self.packet[0], &self.packet[1..len]);
+
+        match command {
+            b'?' => {
+                self.response.push(b"S");
+                self.response.push_hex(SIGTRAP);
+            }
+
+            b'g' => self.read_registers(stack),
+
+            b'G' => {
+                let mut offset = 0;
+
+                for index in 0..REGISTER_COUNT {
+                    let size = register_size(index) * 2;
+
+                    if offset + size > arguments.len() {
+                        break;
+                    }
+
+                    if let Some(value) = parse_register(&arguments[offset..offset + size]) {
+                        if let Some(register) = register_mut(stack, index) {
+                            *register = value;
+                        }
+                    }
+
+                    offset += size;
+                }
+
+                self.response.ok();
+            }
+
+            b'p' => match parse_hex(arguments) {
+                Some(index) if index < REGISTER_COUNT => {
+                    let value = register_mut(stack, index).map(|value| *value).unwrap_or(0);
+
+                    for byte in &value.to_le_bytes()[..register_size(index)] {
+                        self.response.push_hex(*byte);
+                    }
+                }
+
+                _ => self.response.error(0x16),
+            },
+
+            b'P' => {
+                let register = split_once(arguments, b'=')
+                    .and_then(|(index, value)| Some((parse_hex(index)?, parse_register(value)?)));
+
+                match register {
+                    Some((index, value)) if index < REGISTER_COUNT => {
+                        if let Some(register) = register_mut(stack, index) {
+                            *register = value;
+                        }
+
+                        self.response.ok();
+                    }
+
+                    _ => self.response.error(0x16),
+                }
+            }
+
+            b'm' => match parse_address_length(arguments) {
+                Some((address, length)) if length * 2 <= MAX_PACKET_SIZE => {
+                    for i in 0..length {
+                        match read_memory(address.wrapping_add(i)) {
+                            Some(byte) => self.response.push_hex(byte),
+                            // Report a fault only if nothing could be read.
+                            None if i == 0 => self.response.error(0x0E),
+                            None => break,
+                        }
+                    }
+                }
+
+                _ => self.response.error(0x16),
+            },
+
+            b'M' => {
+                let request = split_once(arguments, b':')
+                    .and_then(|(header, data)| Some((parse_address_length(header)?, data)));
+
+                match request {
+                    Some(((address, length), data)) if data.len() == length * 2 => {
+                        let written = data.chunks(2).enumerate().try_for_each(|(i, pair)| {
+                            let byte = parse_hex_byte(pair[0], pair[1])?;
+                            write_memory(address.wrapping_add(i), byte)
+                        });
+
+                        match written {
+                            Some(()) => self.response.ok(),
+                            None => self.response.error(0x0E),
+                        }
+                    }
+
+                    _ => self.response.error(0x16),
+                }
+            }
+
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(arguments) {
+                    stack.stack.iret.rip = address as u64;
+                }
+
+                let mut rflags = RFlags::from_bits_truncate(stack.stack.iret.rflags);
+                rflags.set(RFlags::TRAP_FLAG, command == b's');
+
+                stack.stack.iret.rflags = rflags.bits();
+                return false;
+            }
+
+            b'Z' | b'z' => {
+                // Only software breakpoints (type 0) are supported.
+                let address = match arguments.split_first() {
+                    Some((b'0', rest)) => split_once(rest, b',')
+                        .and_then(|(_, rest)| split_once(rest, b','))
+                        .and_then(|(address, _)| parse_hex(address)),
+
+                    _ => None,
+                };
+
+                if let Some(address) = address {
+                    let result = if command == b'Z' {
+                        self.breakpoints.insert(address)
+                    } else {
+                        self.breakpoints.remove(address)
+                    };
+
+                    match result {
+                        Some(()) => self.response.ok(),
+                        None => self.response.error(0x0E),
+                    }
+                }
+            }
+
+            b'D' | b'k' => {
+                self.breakpoints.clear();
+
+                let mut rflags = RFlags::from_bits_truncate(stack.stack.iret.rflags);
+                rflags.remove(RFlags::TRAP_FLAG);
+                stack.stack.iret.rflags = rflags.bits();
+
+                // The kill packet does not expect a reply.
+                if command == b'D' {
+                    self.response.ok();
+                    self.send_response();
+                }
+
+                return false;
+            }
+
+            b'H' => self.response.ok(),
+
+            b'q' => {
+                if arguments.starts_with(b"Supported") {
+                    self.response.push(b"PacketSize=1000");
+                } else if arguments.starts_with(b"Attached") {
+                    self.response.push(b"1");
+                } else if arguments == b"C" {
+                    self.response.push(b"QC1");
+                }
+            }
+
+            // Unsupported packets are replied to with an empty response.
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Reports the stop to the debugger and serves its requests until it resumes the
+    /// kernel.
+    fn enter(&mut self, stack: &mut InterruptErrorStack) {
+        self.response.clear();
+        self.response.push(b"S");
+        self.response.push_hex(SIGTRAP);
+        self.send_response();
+
+        loop {
+            let len = self.receive_packet();
+
+            if len == 0 {
+                continue;
+            }
+
+            self.response.clear();
+
+            if !self.handle_packet(stack, len) {
+                return;
+            }
+
+            self.send_response();
+        }
+    }
+}
+
+/// Returns whether the GDB stub has been enabled on the kernel command line.
+pub fn is_enabled() -> bool {
+    GDBSTUB.get().is_some()
+}
+
+/// Handles an `int3` trap raised in kernel mode. If the trap was caused by a breakpoint
+/// inserted by the debugger, the instruction pointer is moved back to the breakpoint
+/// address so that execution resumes at the original instruction.
+pub fn handle_breakpoint(stack: &mut InterruptErrorStack) {
+    let mut stub = GDBSTUB.get().expect("gdbstub: not initialized").lock_irq();
+    let address = stack.stack.iret.rip as usize - 1;
+
+    if stub.breakpoints.contains(address) {
+        stack.stack.iret.rip -= 1;
+    }
+
+    stub.enter(stack);
+}
+
+/// Handles a debug exception raised in kernel mode (for example, after single stepping).
+pub fn handle_debug(stack: &mut InterruptErrorStack) {
+    let mut stub = GDBSTUB.get().expect("gdbstub: not initialized").lock_irq();
+    stub.enter(stack);
+}
+
+/// Initializes the GDB stub on the second serial port and stops the kernel until the
+/// debugger attaches.
+pub fn init() {
+    let mut port = unsafe { SerialPort::new(COM_2).init() };
+    port.disable_interrupts();
+
+    GDBSTUB.call_once(|| Mutex::new(GdbStub::new(port)));
+
+    log::info!("gdbstub: waiting for the debugger on COM2");
+
+    unsafe { asm!("int3") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_packet_fields() {
+        assert_eq!(parse_hex(b"ffffffff80000000"), Some(0xffffffff80000000));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_register(b"3412000000000000"), Some(0x1234));
+        assert_eq!(parse_address_length(b"1000,4"), Some((0x1000, 4)));
+        assert_eq!(parse_hex_byte(b'a', b'G'), None);
+    }
+}
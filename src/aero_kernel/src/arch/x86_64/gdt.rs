@@ -29,9 +29,8 @@
 use core::alloc::Layout;
 use core::mem;
 
-use alloc::alloc::alloc_zeroed;
-
 use crate::arch::tls::PerCpuData;
+use crate::mem::numa;
 
 use super::{io, tls};
 
@@ -338,7 +337,7 @@ pub fn init() {
         let gdt_size = gdt_ent_size * GDT_ENTRY_COUNT;
         let layout = Layout::from_size_align_unchecked(gdt_size, gdt_ent_align);
 
-        let ptr = alloc_zeroed(layout) as *mut GdtEntry;
+        let ptr = numa::alloc_percpu(tls::get_cpuid(), layout) as *mut GdtEntry;
         core::slice::from_raw_parts_mut::<GdtEntry>(ptr, GDT_ENTRY_COUNT)
     };
 
@@ -377,6 +376,32 @@ pub fn init() {
     tls::get_percpu().gdt = gdt;
 }
 
+/// Reloads the per-CPU GDT and the TSS of the current CPU, after the descriptor table
+/// registers were lost (eg. when resuming from a suspend to RAM).
+pub fn reload() {
+    let gdt = &mut tls::get_percpu().gdt;
+
+    // Loading the TSS marks its descriptor as busy and a busy TSS cannot be loaded again.
+    gdt[GdtEntryType::TSS as usize].access_byte =
+        GdtAccessFlags::PRESENT | GdtAccessFlags::RING_3 | GdtAccessFlags::TSS_AVAIL;
+
+    unsafe {
+        let gdt_descriptor = GdtDescriptor::new(
+            (mem::size_of::<[GdtEntry; GDT_ENTRY_COUNT]>() - 1) as u16,
+            gdt.as_ptr() as u64,
+        );
+
+        load_gdt(&gdt_descriptor);
+
+        load_cs(SegmentSelector::new(GdtEntryType::KERNEL_CODE, Ring::Ring0));
+        load_ds(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
+        load_es(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
+        load_ss(SegmentSelector::new(GdtEntryType::KERNEL_DATA, Ring::Ring0));
+
+        load_tss(SegmentSelector::new(GdtEntryType::TSS, Ring::Ring0));
+    }
+}
+
 #[inline(always)]
 unsafe fn load_cs(selector: SegmentSelector) {
     /*
@@ -0,0 +1,207 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The HPET (High Precision Event Timer) consists of a free running up-counter and a set
+//! of comparators. The counter is used as the calibration reference for the local APIC
+//! timer and the first comparator drives the periodic timer tick (in legacy replacement
+//! mode, where it is routed to IRQ 0 in place of the PIT).
+//!
+//! **Notes**: <https://wiki.osdev.org/HPET>
+
+use spin::Once;
+
+use crate::acpi::hpet;
+use crate::mem::paging::VirtAddr;
+
+/// General Capabilities and ID register. Read-only.
+const HPET_GCAP_ID: u64 = 0x000;
+/// General Configuration register. Read/write.
+const HPET_GEN_CONF: u64 = 0x010;
+/// Main Counter Value register. Read/write.
+const HPET_MAIN_COUNTER: u64 = 0x0F0;
+
+const GEN_CONF_ENABLE: u64 = 1;
+const GEN_CONF_LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+const GCAP_64BIT: u64 = 1 << 13;
+const GCAP_LEGACY_REPLACEMENT: u64 = 1 << 15;
+
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+
+/// The amount of femtoseconds in a nanosecond.
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+static HPET: Once<Hpet> = Once::new();
+
+/// Returns the offset of the configuration and capabilities register of the timer.
+const fn timer_config(timer: u64) -> u64 {
+    0x100 + 0x20 * timer
+}
+
+/// Returns the offset of the comparator value register of the timer.
+const fn timer_comparator(timer: u64) -> u64 {
+    0x108 + 0x20 * timer
+}
+
+pub struct Hpet {
+    address: VirtAddr,
+    /// The period of the main counter, in femtoseconds.
+    period: u64,
+    capabilities: u64,
+}
+
+impl Hpet {
+    unsafe fn read(&self, register: u64) -> u64 {
+        (self.address + register).as_ptr::<u64>().read_volatile()
+    }
+
+    unsafe fn write(&self, register: u64, value: u64) {
+        (self.address + register)
+            .as_mut_ptr::<u64>()
+            .write_volatile(value)
+    }
+
+    /// Returns the current value of the main counter.
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(HPET_MAIN_COUNTER) }
+    }
+
+    /// Returns the frequency of the main counter, in hertz.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period
+    }
+
+    /// Returns the time elapsed since the HPET was enabled, in nanoseconds.
+    pub fn nanoseconds(&self) -> u64 {
+        (self.counter() as u128 * self.period as u128 / FEMTOS_PER_NANO as u128) as u64
+    }
+
+    /// Returns whether the main counter is 64 bits wide (instead of 32 bits, in which
+    /// case it wraps around in a matter of minutes).
+    pub fn is_64bit(&self) -> bool {
+        self.capabilities & GCAP_64BIT != 0
+    }
+
+    /// Spins until the provided amount of nanoseconds have elapsed.
+    pub fn busy_wait(&self, nanoseconds: u64) {
+        let target = self.nanoseconds() + nanoseconds;
+
+        while self.nanoseconds() < target {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Restarts the main counter from zero after it was reset by a suspend to RAM.
+    pub fn resume(&self) {
+        unsafe {
+            self.write(HPET_GEN_CONF, 0);
+            self.write(HPET_MAIN_COUNTER, 0);
+            self.write(HPET_GEN_CONF, GEN_CONF_ENABLE);
+        }
+    }
+
+    /// Returns whether the first comparator can fire periodically on IRQ 0 (and so
+    /// replace the PIT as the timer tick).
+    pub fn can_replace_pit(&self) -> bool {
+        let timer = unsafe { self.read(timer_config(0)) };
+
+        self.capabilities & GCAP_LEGACY_REPLACEMENT != 0 && timer & TIMER_PERIODIC_CAPABLE != 0
+    }
+
+    /// Programs the first comparator to fire periodically at the provided frequency and
+    /// routes it to IRQ 0 (and the second comparator to IRQ 8) using the legacy
+    /// replacement mode.
+    pub fn start_periodic(&self, frequency: u64) {
+        let ticks = self.frequency() / frequency;
+
+        unsafe {
+            let config = self.read(HPET_GEN_CONF);
+            self.write(HPET_GEN_CONF, config & !GEN_CONF_ENABLE);
+
+            let timer = self.read(timer_config(0));
+            let timer = timer | TIMER_INT_ENABLE | TIMER_PERIODIC | TIMER_VALUE_SET;
+
+            self.write(timer_config(0), timer);
+
+            // With the value set bit, the first write sets the comparator and the
+            // second write sets the period.
+            self.write(timer_comparator(0), self.counter() + ticks);
+            self.write(timer_comparator(0), ticks);
+
+            self.write(
+                HPET_GEN_CONF,
+                config | GEN_CONF_ENABLE | GEN_CONF_LEGACY_REPLACEMENT,
+            );
+        }
+    }
+}
+
+/// Returns the HPET, if there is one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+pub fn init() {
+    let address = match hpet::get_base_address() {
+        Some(address) => address.as_hhdm_virt(),
+        None => {
+            log::warn!("hpet: not found");
+            return;
+        }
+    };
+
+    let hpet = {
+        let mut hpet = Hpet {
+            address,
+            period: 0,
+            capabilities: 0,
+        };
+
+        unsafe {
+            hpet.capabilities = hpet.read(HPET_GCAP_ID);
+            hpet.period = hpet.capabilities >> 32;
+        }
+
+        hpet
+    };
+
+    // The specification limits the period to 100 nanoseconds.
+    if hpet.period == 0 || hpet.period > 100 * FEMTOS_PER_NANO {
+        log::warn!("hpet: invalid counter period ({} fs)", hpet.period);
+        return;
+    }
+
+    let hpet = HPET.call_once(|| {
+        unsafe {
+            // Start the main counter from zero.
+            hpet.write(HPET_GEN_CONF, 0);
+            hpet.write(HPET_MAIN_COUNTER, 0);
+            hpet.write(HPET_GEN_CONF, GEN_CONF_ENABLE);
+        }
+
+        hpet
+    });
+
+    let width = if hpet.is_64bit() { 64 } else { 32 };
+
+    log::info!("hpet: {} Hz, {}-bit counter", hpet.frequency(), width);
+}
@@ -19,14 +19,28 @@
 
 use super::{io, InterruptErrorStack};
 
-use crate::arch::controlregs;
+use crate::arch::{controlregs, gdbstub};
 use crate::mem::paging::PageFaultErrorCode;
 
+use crate::trace::{self, TraceEvent};
 use crate::unwind;
 use crate::userland::scheduler;
 
 const LOG_PF_PTABLE: bool = true;
 
+/// Logs the faulting instruction and the backtrace of the interrupted kernel code.
+fn log_exception_backtrace(stack: &InterruptErrorStack) {
+    let iret = &stack.stack.iret;
+
+    if iret.is_user() {
+        unwind::unwind_stack_trace();
+        return;
+    }
+
+    log::error!("RIP: {}", unwind::SymbolizedAddress(iret.rip as usize));
+    unwind::unwind_stack_trace_from(stack.stack.preserved.rbp as usize);
+}
+
 macro interrupt_exception(fn $name:ident() => $message:expr) {
     pub fn $name(stack: &mut InterruptErrorStack) {
         unwind::prepare_panic();
@@ -34,7 +48,7 @@ macro interrupt_exception(fn $name:ident() => $message:expr) {
         log::error!("EXCEPTION: {}", $message);
         log::error!("Stack: {:#x?}", stack);
 
-        unwind::unwind_stack_trace();
+        log_exception_backtrace(stack);
 
         unsafe {
             loop {
@@ -45,8 +59,7 @@ macro interrupt_exception(fn $name:ident() => $message:expr) {
 }
 
 interrupt_exception!(fn divide_by_zero() => "Division by zero");
-interrupt_exception!(fn debug() => "Debug");
-interrupt_exception!(fn non_maskable() => "Non Maskable");
+interrupt_exception!(fn unhandled_debug() => "Debug");
 interrupt_exception!(fn overflow() => "Stack Overflow");
 interrupt_exception!(fn bound_range() => "Out of Bounds");
 interrupt_exception!(fn device_not_available() => "Device not Avaliable");
@@ -62,6 +75,26 @@ interrupt_exception!(fn simd() => "SIMD floating point fault");
 interrupt_exception!(fn virtualization() => "Virtualization fault");
 interrupt_exception!(fn security() => "Security exception");
 
+pub fn non_maskable(stack: &mut InterruptErrorStack) {
+    // The PMU overflows, including the watchdog counter, are delivered as NMIs.
+    if crate::watchdog::handle_nmi(&stack.stack) {
+        return;
+    }
+
+    unwind::prepare_panic();
+
+    log::error!("EXCEPTION: Non Maskable");
+    log::error!("Stack: {:#x?}", stack);
+
+    log_exception_backtrace(stack);
+
+    unsafe {
+        loop {
+            super::halt();
+        }
+    }
+}
+
 pub fn invalid_opcode(stack: &mut InterruptErrorStack) {
     // Catch SYSENTER on AMD CPUs.
     //
@@ -88,7 +121,7 @@ pub fn invalid_opcode(stack: &mut InterruptErrorStack) {
     log::error!("EXCEPTION: Invalid Opcode");
     log::error!("Stack: {:#x?}", stack);
 
-    unwind::unwind_stack_trace();
+    log_exception_backtrace(stack);
 
     unsafe {
         loop {
@@ -97,7 +130,20 @@ pub fn invalid_opcode(stack: &mut InterruptErrorStack) {
     }
 }
 
+pub fn debug(stack: &mut InterruptErrorStack) {
+    if gdbstub::is_enabled() && !stack.stack.iret.is_user() {
+        gdbstub::handle_debug(stack);
+    } else {
+        unhandled_debug(stack);
+    }
+}
+
 pub fn breakpoint(stack: &mut InterruptErrorStack) {
+    if gdbstub::is_enabled() && !stack.stack.iret.is_user() {
+        gdbstub::handle_breakpoint(stack);
+        return;
+    }
+
     // We will need to prevent RIP from going out of sync with
     // instructions.
     //
@@ -110,6 +156,11 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
     let accessed_address = controlregs::read_cr2();
     let reason = PageFaultErrorCode::from_bits_truncate(stack.code);
 
+    trace::trace(
+        TraceEvent::PageFault,
+        [accessed_address.as_u64(), stack.code],
+    );
+
     // We cannot directly check if we want to handle the page fault by checking
     // if the CS register contains the RPL_3 flag since, we also want to handle the
     // situation where we are trying to access a user provided buffer in the kernel and
@@ -168,6 +219,12 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
             let task = scheduler::get_scheduler().current_task();
             task.signal(aero_syscall::signal::SIGSEGV);
         } else if !signal {
+            // The kernel faulted while copying to or from userland, so the copy is
+            // aborted and returns `EFAULT` (see `mem::uaccess`).
+            if let Some(fixup) = super::super::uaccess::fixup(stack.stack.iret.rip) {
+                stack.stack.iret.rip = fixup;
+                return;
+            }
         } else {
             return;
         }
@@ -178,7 +235,7 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
     log::error!("Page fault");
     print_info();
 
-    unwind::unwind_stack_trace();
+    log_exception_backtrace(stack);
 
     unsafe {
         loop {
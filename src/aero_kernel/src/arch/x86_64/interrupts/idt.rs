@@ -159,13 +159,12 @@ pub struct InterruptErrorStack {
 #[derive(Copy, Clone)]
 pub(super) enum IrqHandler {
     ErrorHandler(fn(&mut InterruptErrorStack)),
-    Handler(fn(&mut InterruptStack)),
 
     None,
 }
 
-pub(super) static INTERRUPT_HANDLERS: Mutex<[IrqHandler; IDT_ENTRIES]> =
-    Mutex::new([IrqHandler::None; IDT_ENTRIES]);
+/// The handlers of the exceptions. The IRQs are handled by the [`irq`](super::irq) module.
+pub(super) static INTERRUPT_HANDLERS: Mutex<[IrqHandler; 32]> = Mutex::new([IrqHandler::None; 32]);
 
 /// Initialize the IDT.
 pub fn init() {
@@ -214,14 +213,9 @@ pub fn init() {
     // INTERRUPT_HANDLERS.lock()[21..29] are reserved.
     INTERRUPT_HANDLERS.lock()[30] = IrqHandler::ErrorHandler(exceptions::security);
 
-    unsafe {
-        let idt_descriptor = IdtDescriptor::new(
-            ((IDT.len() * size_of::<IdtEntry>()) - 1) as u16,
-            (&IDT as *const _) as u64,
-        );
-
-        load_idt(&idt_descriptor);
+    reload_idt();
 
+    unsafe {
         /*
          * Since lazy statics are initialized on the their first dereference, we have to
          * manually initialize the static as the first dereference happen in an IRQ interrupt.
@@ -234,6 +228,18 @@ pub fn init() {
     }
 }
 
+/// Loads the IDT on the current CPU.
+pub fn reload_idt() {
+    unsafe {
+        let idt_descriptor = IdtDescriptor::new(
+            ((IDT.len() * size_of::<IdtEntry>()) - 1) as u16,
+            (&IDT as *const _) as u64,
+        );
+
+        load_idt(&idt_descriptor);
+    }
+}
+
 #[inline(always)]
 unsafe fn load_idt(idt_descriptor: &IdtDescriptor) {
     asm!("lidt [{}]", in(reg) idt_descriptor, options(nostack));
@@ -0,0 +1,369 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! IRQ management. Drivers request an IRQ either by vector (for the local APIC and
+//! MSI-X interrupts, see [`allocate_vector`] and [`request_irq`]) or by GSI (for the
+//! interrupts routed through the I/O APIC, see [`request_gsi`] and
+//! [`request_legacy_irq`]) and free it with [`free_irq`].
+//!
+//! A vector can be shared by multiple handlers if all of them are requested with
+//! [`IrqFlags::SHARED`], in which case every handler is run when the interrupt fires
+//! and each of them has to check whether its device raised it.
+//!
+//! The amount of times each IRQ fired on each CPU is exposed in `/proc/interrupts`.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::{apic, tls};
+use crate::utils::sync::Mutex;
+
+use super::InterruptStack;
+
+pub type IrqHandlerFn = fn(&mut InterruptStack);
+
+/// The first vector available to the IRQs, as vectors 0-31 are reserved for the
+/// exceptions.
+const FIRST_VECTOR: usize = 32;
+/// The vectors starting from this one are reserved for the spurious and IPI vectors.
+const LAST_VECTOR: usize = 0xf0;
+
+/// The maximum amount of handlers sharing a vector.
+const MAX_SHARED_HANDLERS: usize = 4;
+
+bitflags::bitflags! {
+    pub struct IrqFlags: u32 {
+        /// The vector may be shared with other handlers that also set this flag.
+        const SHARED = 1 << 0;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqError {
+    /// The vector already has a handler that does not allow sharing it.
+    Busy,
+    /// The vector already has [`MAX_SHARED_HANDLERS`] handlers.
+    TooManyHandlers,
+    /// The handler is not registered on the vector.
+    NotFound,
+    /// The vector is reserved for the exceptions.
+    InvalidVector,
+    /// The CPU does not exist.
+    InvalidCpu,
+    /// The affinity can only be changed for the IRQs routed through the I/O APIC.
+    NotRouted,
+    /// All of the vectors are allocated.
+    NoVectors,
+}
+
+#[derive(Copy, Clone)]
+struct IrqAction {
+    name: &'static str,
+    handler: IrqHandlerFn,
+    flags: IrqFlags,
+}
+
+/// A GSI routed to a vector through the I/O APIC.
+#[derive(Debug, Copy, Clone)]
+struct GsiRoute {
+    gsi: u32,
+    /// The MPS INTI flags of the GSI.
+    flags: u16,
+    /// The CPU that the GSI is delivered to.
+    cpu: usize,
+}
+
+struct IrqDescriptor {
+    actions: [Option<IrqAction>; MAX_SHARED_HANDLERS],
+    route: Option<GsiRoute>,
+    /// The amount of times the IRQ fired on each CPU.
+    counts: Vec<AtomicU64>,
+}
+
+impl IrqDescriptor {
+    const fn new() -> Self {
+        Self {
+            actions: [None; MAX_SHARED_HANDLERS],
+            route: None,
+            counts: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.actions.iter().all(Option::is_none)
+    }
+
+    fn add_action(&mut self, action: IrqAction) -> Result<(), IrqError> {
+        let shareable = self
+            .actions
+            .iter()
+            .flatten()
+            .all(|other| other.flags.contains(IrqFlags::SHARED));
+
+        if !self.is_empty() && (!shareable || !action.flags.contains(IrqFlags::SHARED)) {
+            return Err(IrqError::Busy);
+        }
+
+        let slot = self
+            .actions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::TooManyHandlers)?;
+
+        *slot = Some(action);
+
+        if self.counts.is_empty() {
+            self.counts = (0..apic::get_cpu_count())
+                .map(|_| AtomicU64::new(0))
+                .collect();
+        }
+
+        Ok(())
+    }
+}
+
+const EMPTY_DESCRIPTOR: IrqDescriptor = IrqDescriptor::new();
+
+static IRQ_DESCRIPTORS: Mutex<[IrqDescriptor; 256]> = Mutex::new([EMPTY_DESCRIPTOR; 256]);
+/// Bitmap of the allocated vectors.
+static ALLOCATED_VECTORS: Mutex<[u64; 4]> = Mutex::new([0; 4]);
+
+/// Allocates a free vector.
+pub fn allocate_vector() -> Result<u8, IrqError> {
+    let mut allocated = ALLOCATED_VECTORS.lock_irq();
+
+    for vector in FIRST_VECTOR..LAST_VECTOR {
+        if allocated[vector / 64] & (1 << (vector % 64)) == 0 {
+            allocated[vector / 64] |= 1 << (vector % 64);
+            return Ok(vector as u8);
+        }
+    }
+
+    Err(IrqError::NoVectors)
+}
+
+/// Frees a vector allocated with [`allocate_vector`].
+pub fn free_vector(vector: u8) {
+    let vector = vector as usize;
+    ALLOCATED_VECTORS.lock_irq()[vector / 64] &= !(1 << (vector % 64));
+}
+
+/// Registers `handler` on the provided vector.
+pub fn request_irq(
+    vector: u8,
+    name: &'static str,
+    handler: IrqHandlerFn,
+    flags: IrqFlags,
+) -> Result<(), IrqError> {
+    if (vector as usize) < FIRST_VECTOR {
+        return Err(IrqError::InvalidVector);
+    }
+
+    let action = IrqAction {
+        name,
+        handler,
+        flags,
+    };
+
+    IRQ_DESCRIPTORS.lock_irq()[vector as usize].add_action(action)
+}
+
+/// Registers `handler` on the GSI and routes the GSI to the BSP. If the GSI is already
+/// routed (and both handlers are shared), the handler is added to its vector. Returns
+/// the vector of the GSI.
+pub fn request_gsi(
+    gsi: u32,
+    gsi_flags: u16,
+    name: &'static str,
+    handler: IrqHandlerFn,
+    flags: IrqFlags,
+) -> Result<u8, IrqError> {
+    let action = IrqAction {
+        name,
+        handler,
+        flags,
+    };
+
+    let mut descriptors = IRQ_DESCRIPTORS.lock_irq();
+
+    let routed = descriptors
+        .iter()
+        .position(|desc| desc.route.map_or(false, |route| route.gsi == gsi));
+
+    if let Some(vector) = routed {
+        descriptors[vector].add_action(action)?;
+        return Ok(vector as u8);
+    }
+
+    let vector = allocate_vector()?;
+    let descriptor = &mut descriptors[vector as usize];
+
+    if let Err(err) = descriptor.add_action(action) {
+        free_vector(vector);
+        return Err(err);
+    }
+
+    descriptor.route = Some(GsiRoute {
+        gsi,
+        flags: gsi_flags,
+        cpu: 0,
+    });
+
+    apic::io_apic_set_redirect(vector, gsi, gsi_flags, apic::get_bsp_id() as u32, false);
+    Ok(vector)
+}
+
+/// Registers `handler` on the legacy ISA IRQ. See [`request_gsi`] for more information.
+pub fn request_legacy_irq(
+    irq: u8,
+    name: &'static str,
+    handler: IrqHandlerFn,
+    flags: IrqFlags,
+) -> Result<u8, IrqError> {
+    let (gsi, gsi_flags) = apic::legacy_irq_to_gsi(irq);
+    request_gsi(gsi, gsi_flags, name, handler, flags)
+}
+
+/// Unregisters `handler` from the provided vector. If it was the last handler of a GSI,
+/// the GSI is masked and its vector is freed.
+pub fn free_irq(vector: u8, handler: IrqHandlerFn) -> Result<(), IrqError> {
+    let mut descriptors = IRQ_DESCRIPTORS.lock_irq();
+    let descriptor = &mut descriptors[vector as usize];
+
+    let slot = descriptor
+        .actions
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |action| action.handler as usize == handler as usize))
+        .ok_or(IrqError::NotFound)?;
+
+    *slot = None;
+
+    if descriptor.is_empty() {
+        if let Some(route) = descriptor.route.take() {
+            let dest = apic::get_cpu_apic_id(route.cpu).unwrap_or(0);
+
+            apic::io_apic_set_redirect(vector, route.gsi, route.flags, dest, true);
+            free_vector(vector);
+        }
+    }
+
+    Ok(())
+}
+
+/// Delivers the GSI routed to the provided vector to `cpu`.
+pub fn set_affinity(vector: u8, cpu: usize) -> Result<(), IrqError> {
+    let dest = apic::get_cpu_apic_id(cpu).ok_or(IrqError::InvalidCpu)?;
+
+    let mut descriptors = IRQ_DESCRIPTORS.lock_irq();
+    let route = descriptors[vector as usize]
+        .route
+        .as_mut()
+        .ok_or(IrqError::NotRouted)?;
+
+    route.cpu = cpu;
+
+    apic::io_apic_set_redirect(vector, route.gsi, route.flags, dest, false);
+    Ok(())
+}
+
+/// Runs the handlers registered on the vector. Called from the generic interrupt handler.
+pub(super) fn handle_irq(vector: u8, stack: &mut InterruptStack) {
+    // The handlers are copied out, as they may not return right away (for example, if
+    // they preempt the current task).
+    let actions = {
+        let descriptors = IRQ_DESCRIPTORS.lock_irq();
+        let descriptor = &descriptors[vector as usize];
+
+        if let Some(count) = descriptor.counts.get(tls::get_cpuid()) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        descriptor.actions
+    };
+
+    crate::random::add_interrupt_entropy(vector as u64);
+
+    if actions.iter().all(Option::is_none) {
+        log::warn!("unhandled interrupt {}", vector);
+        return;
+    }
+
+    for action in actions.iter().flatten() {
+        (action.handler)(stack);
+    }
+}
+
+/// Statistics of a requested IRQ, see [`irq_stats`].
+pub struct IrqStats {
+    pub vector: u8,
+    pub gsi: Option<u32>,
+    /// The CPU that the GSI is delivered to.
+    pub cpu: Option<usize>,
+    pub names: Vec<&'static str>,
+    /// The amount of times the IRQ fired on each CPU.
+    pub counts: Vec<u64>,
+}
+
+/// Returns the statistics of the IRQs that have at least one handler.
+pub fn irq_stats() -> Vec<IrqStats> {
+    let descriptors = IRQ_DESCRIPTORS.lock_irq();
+
+    descriptors
+        .iter()
+        .enumerate()
+        .filter(|(_, desc)| !desc.is_empty())
+        .map(|(vector, desc)| IrqStats {
+            vector: vector as u8,
+            gsi: desc.route.map(|route| route.gsi),
+            cpu: desc.route.map(|route| route.cpu),
+            names: desc.actions.iter().flatten().map(|a| a.name).collect(),
+            counts: desc
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler(_stack: &mut InterruptStack) {}
+
+    #[test]
+    fn irq_sharing_requires_shared_flag() {
+        let action = |flags| IrqAction {
+            name: "test",
+            handler,
+            flags,
+        };
+
+        let mut descriptor = IrqDescriptor::new();
+
+        descriptor.add_action(action(IrqFlags::SHARED)).unwrap();
+        descriptor.add_action(action(IrqFlags::SHARED)).unwrap();
+
+        assert_eq!(
+            descriptor.add_action(action(IrqFlags::empty())),
+            Err(IrqError::Busy)
+        );
+    }
+}
@@ -19,13 +19,15 @@
 
 mod exceptions;
 mod idt;
+mod irq;
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub use idt::*;
+pub use irq::*;
 
 use crate::arch::apic;
-use crate::utils::sync::Mutex;
+use crate::trace::{self, TraceEvent};
 
 use super::{controlregs, io};
 
@@ -220,22 +222,22 @@ impl PicController {
 #[no_mangle]
 extern "C" fn generic_interrupt_handler(isr: usize, stack_frame: *mut InterruptErrorStack) {
     let stack_frame = unsafe { &mut *stack_frame };
-    let handlers = idt::INTERRUPT_HANDLERS.lock();
 
-    match &handlers[isr] {
-        IrqHandler::Handler(handler) => {
-            let handler = *handler;
-            core::mem::drop(handlers); // drop the lock
-            handler(&mut stack_frame.stack);
-        }
+    if isr >= 32 {
+        // Exceptions have their own tracepoints.
+        trace::trace(TraceEvent::Irq, [isr as u64, 0]);
+        irq::handle_irq(isr as u8, &mut stack_frame.stack);
+    } else {
+        let handlers = idt::INTERRUPT_HANDLERS.lock();
+
+        match handlers[isr] {
+            IrqHandler::ErrorHandler(handler) => {
+                core::mem::drop(handlers); // drop the lock
+                handler(stack_frame);
+            }
 
-        IrqHandler::ErrorHandler(handler) => {
-            let handler = *handler;
-            core::mem::drop(handlers); // drop the lock
-            handler(stack_frame);
+            IrqHandler::None => log::warn!("unhandled exception {}", isr),
         }
-
-        IrqHandler::None => log::warn!("unhandled interrupt {}", isr),
     }
 
     // Check and evaluate any pending signals.
@@ -243,34 +245,6 @@ extern "C" fn generic_interrupt_handler(isr: usize, stack_frame: *mut InterruptE
     INTERRUPT_CONTROLLER.eoi();
 }
 
-/// ## Panics
-/// * If another handler is already installed in the provided interrupt vector.
-pub fn register_handler(vector: u8, handler: fn(&mut InterruptStack)) {
-    let mut handlers = idt::INTERRUPT_HANDLERS.lock_irq();
-
-    // SAFETY: ensure there is no handler already installed.
-    match handlers[vector as usize] {
-        IrqHandler::None => {}
-        _ => unreachable!("register_handler: handler has already been registered"),
-    }
-
-    handlers[vector as usize] = idt::IrqHandler::Handler(handler);
-}
-
-pub fn allocate_vector() -> u8 {
-    static IDT_FREE_VECTOR: Mutex<u8> = Mutex::new(32);
-
-    let mut fvector = IDT_FREE_VECTOR.lock();
-    let fcopy = fvector.clone();
-
-    if fcopy == 0xf0 {
-        panic!("allocate_vector: vector allocation exhausted")
-    }
-
-    *fvector += 1;
-    fcopy
-}
-
 /// Wrapper function to the `hlt` assembly instruction used to halt
 /// the CPU.
 #[inline(always)]
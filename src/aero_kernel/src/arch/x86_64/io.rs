@@ -39,6 +39,9 @@ pub const IA32_LSTAR: u32 = 0xc0000082;
 /// System Call Flag Mask (R/W).
 pub const IA32_FMASK: u32 = 0xc0000084;
 
+/// Auxiliary TSC signature, returned by `rdtscp` (R/W).
+pub const IA32_TSC_AUX: u32 = 0xc0000103;
+
 pub const IA32_SYSENTER_CS: u32 = 0x174;
 pub const IA32_SYSENTER_ESP: u32 = 0x175;
 pub const IA32_SYSENTER_EIP: u32 = 0x176;
@@ -52,6 +55,29 @@ pub const IA32_SYSENTER_EIP: u32 = 0x176;
 /// ```
 pub const IA32_APIC_BASE: u32 = 0x1b;
 
+/// General-purpose performance counter 0 (R/W). The other counters follow it.
+pub const IA32_PMC0: u32 = 0xc1;
+
+/// Event select register of the general-purpose performance counter 0 (R/W). The event
+/// select registers of the other counters follow it.
+pub const IA32_PERFEVTSEL0: u32 = 0x186;
+
+/// Fixed-function performance counter 0, counting the instructions retired (R/W). The
+/// other fixed-function counters follow it.
+pub const IA32_FIXED_CTR0: u32 = 0x309;
+
+/// Fixed-function performance counters control (R/W).
+pub const IA32_FIXED_CTR_CTRL: u32 = 0x38d;
+
+/// Global performance counter overflow status (RO).
+pub const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+
+/// Global performance counter enable (R/W).
+pub const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+
+/// Global performance counter overflow clear (R/W).
+pub const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
 /// Wrapper function to the `outb` assembly instruction used to do the
 /// 8-bit low level port output.
 #[inline]
@@ -164,6 +190,72 @@ pub unsafe fn rdmsr(msr: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
+/// Wrapper function to the `wrfsbase` assembly instruction used to write
+/// the FS base. Requires [`Cr4Flags::FSGSBASE`](super::controlregs::Cr4Flags::FSGSBASE)
+/// to be set.
+#[inline]
+pub unsafe fn wrfsbase(value: u64) {
+    asm!("wrfsbase {}", in(reg) value, options(nomem, nostack));
+}
+
+/// Wrapper function to the `rdfsbase` assembly instruction used to read
+/// the FS base. Requires [`Cr4Flags::FSGSBASE`](super::controlregs::Cr4Flags::FSGSBASE)
+/// to be set.
+#[inline]
+pub unsafe fn rdfsbase() -> u64 {
+    let value: u64;
+
+    asm!("rdfsbase {}", out(reg) value, options(nomem, nostack));
+    value
+}
+
+/// Wrapper function to the `rdtsc` assembly instruction used to read the
+/// time-stamp counter.
+#[inline]
+pub fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Returns a random 64-bit value from the `rdrand` instruction, or [`None`] if it is not
+/// supported or failed to return a value.
+pub fn rdrand64() -> Option<u64> {
+    if !super::features::cpu_has!(Rdrand) {
+        return None;
+    }
+
+    let mut value = 0u64;
+
+    // SAFETY: We have verified above that `rdrand` is supported. The instruction can
+    // fail if the hardware random number generator is exhausted, so retry a few times.
+    for _ in 0..10 {
+        if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Returns a random 64-bit value from the `rdseed` instruction, or [`None`] if it is not
+/// supported or failed to return a value. Unlike `rdrand`, the value comes directly
+/// from the entropy source of the CPU.
+pub fn rdseed64() -> Option<u64> {
+    if !super::features::cpu_has!(Rdseed) {
+        return None;
+    }
+
+    let mut value = 0u64;
+
+    // SAFETY: We have verified above that `rdseed` is supported. See `rdrand64`.
+    for _ in 0..10 {
+        if unsafe { core::arch::x86_64::_rdseed64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
 #[inline]
 pub fn delay(cycles: usize) {
     unsafe {
@@ -19,14 +19,26 @@
 
 pub mod apic;
 pub mod controlregs;
+pub mod features;
+pub mod fpu;
+pub mod gdbstub;
 pub mod gdt;
+pub mod hpet;
 pub mod interrupts;
 pub mod io;
+#[cfg(feature = "multiboot2")]
+pub mod multiboot2;
+pub mod pmu;
+pub mod ptrace;
+pub mod reboot;
 pub mod signals;
+pub mod suspend;
 pub mod syscall;
 pub mod task;
 pub mod time;
 pub mod tls;
+pub mod tsc;
+pub mod uaccess;
 
 use core::sync::atomic::Ordering;
 
@@ -36,11 +48,12 @@ use crate::cmdline;
 
 use crate::mem;
 use crate::mem::paging;
-use crate::mem::paging::VirtAddr;
+use crate::mem::paging::{MemoryRegion, VirtAddr};
 
 use crate::drivers;
 use crate::logger;
 use crate::rendy;
+use crate::rendy::{PixelFormat, RendyInfo};
 
 use raw_cpuid::CpuId;
 
@@ -108,14 +121,7 @@ extern "C" fn arch_aero_main() -> ! {
         UnwindInfo::new(elf)
     });
 
-    // Now that we have unwind info, we can initialize the COM ports. This
-    // will be used to print panic messages/logs before the debug renderer is
-    // initialized to the serial output (if avaliable).
-    drivers::uart::init();
-    logger::init();
-
-    // Initialize the CPU specific features.
-    init_cpu();
+    init_early();
 
     let modules = MODULES
         .get_response()
@@ -134,33 +140,42 @@ extern "C" fn arch_aero_main() -> ! {
 
     let command_line = cmdline::parse(
         command_line.to_str().expect("cmdline: invalid utf8"),
-        modules,
+        cmdline::Modules::Limine(modules),
     );
 
-    paging::init(memmap).unwrap();
-    log::info!("loaded paging");
+    let memory_map = memmap
+        .iter()
+        .filter(|entry| entry.typ == LimineMemoryMapEntryType::Usable)
+        .map(|entry| MemoryRegion {
+            base: entry.base,
+            len: entry.len,
+        });
 
-    mem::alloc::init_heap();
-    log::info!("loaded heap");
+    init_memory(memory_map);
 
     // SMP initialization.
     let smp_response = SMP.get_response().get_mut().unwrap();
     let bsp_lapic_id = smp_response.bsp_lapic_id;
 
     for cpu in smp_response.cpus().iter_mut() {
-        apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
-
         if cpu.lapic_id == bsp_lapic_id {
+            // The BSP always has the CPU ID 0 (see `tls::init`).
+            apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+            apic::register_cpu_apic_id(0, cpu.lapic_id);
             continue;
         }
 
-        cpu.goto_address = x86_64_aero_ap_main;
-    }
+        // The application processors are left parked in the bootloader.
+        if command_line.nosmp {
+            continue;
+        }
 
-    gdt::init_boot();
-    log::info!("loaded bootstrap GDT");
+        apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
 
-    paging::init_vm_frames();
+        apic::register_cpu_apic_id(cpu.processor_id as usize, cpu.lapic_id);
+
+        cpu.goto_address = x86_64_aero_ap_main;
+    }
 
     let framebuffer = FRAMEBUFFER
         .get_response()
@@ -170,20 +185,118 @@ extern "C" fn arch_aero_main() -> ! {
         .first()
         .expect("limine: no framebuffer found!");
 
-    rendy::init(&*framebuffer, &command_line);
+    let framebuffer_info = RendyInfo {
+        byte_len: framebuffer.size(),
+        bits_per_pixel: framebuffer.bpp as usize,
+        horizontal_resolution: framebuffer.width as usize,
+        vertical_resolution: framebuffer.height as usize,
+        pixel_format: PixelFormat::BGR,
+        stride: framebuffer.pitch as usize,
+
+        red_mask_shift: framebuffer.red_mask_shift,
+        red_mask_size: framebuffer.red_mask_size,
+
+        green_mask_shift: framebuffer.green_mask_shift,
+        green_mask_size: framebuffer.green_mask_size,
+
+        blue_mask_shift: framebuffer.blue_mask_shift,
+        blue_mask_size: framebuffer.blue_mask_size,
+    };
+
+    let rsdp = VirtAddr::new(RSDP.get_response().get().unwrap().address.as_ptr().unwrap() as u64);
+    let boot_time = BOOT_TIME.get_response().get().unwrap();
+
+    init_platform(
+        command_line,
+        BootInfo {
+            framebuffer: framebuffer.address.as_ptr().unwrap() as *mut u32,
+            framebuffer_info,
+            rsdp,
+            boot_time: boot_time.boot_time as usize,
+        },
+    )
+}
+
+/// The boot information used after the memory is initialized, gathered from the boot
+/// protocol that the kernel was loaded with.
+struct BootInfo {
+    framebuffer: *mut u32,
+    framebuffer_info: RendyInfo,
+    rsdp: VirtAddr,
+    /// The UNIX timestamp of the boot, or zero if the bootloader did not report it.
+    boot_time: usize,
+}
+
+/// Initializes the COM ports and the CPU specific features. The kernel is mapped and the
+/// interrupts are disabled.
+fn init_early() {
+    // Now that we have unwind info, we can initialize the COM ports. This
+    // will be used to print panic messages/logs before the debug renderer is
+    // initialized to the serial output (if avaliable).
+    drivers::uart::init();
+    logger::init();
+
+    // Initialize the CPU specific features.
+    init_cpu();
+}
+
+/// Initializes the frame allocator with the usable regions of the memory map, and the
+/// kernel heap.
+fn init_memory(memory_map: impl Iterator<Item = MemoryRegion> + Clone) {
+    paging::init(memory_map).unwrap();
+    log::info!("loaded paging");
+
+    mem::alloc::init_heap();
+    log::info!("loaded heap");
+}
+
+/// Initializes the rest of the architecture specific parts of the BSP and jumps to the
+/// architecture independent initialization.
+fn init_platform(command_line: &'static cmdline::CommandLine, boot_info: BootInfo) -> ! {
+    gdt::init_boot();
+    log::info!("loaded bootstrap GDT");
+
+    paging::init_vm_frames();
+
+    logger::set_console_level(command_line.log_level);
+    logger::set_serial_console(command_line.serial_console);
+
+    // SAFETY: The bootloader maps the framebuffer in the higher half direct map.
+    let framebuffer = unsafe {
+        core::slice::from_raw_parts_mut(boot_info.framebuffer, boot_info.framebuffer_info.byte_len)
+    };
+
+    rendy::init(framebuffer, boot_info.framebuffer_info, command_line);
     logger::set_rendy_debug(command_line.rendy_debug);
 
+    // Print the messages that were logged before the framebuffer was available.
+    if command_line.rendy_debug {
+        logger::replay_to_rendy();
+    }
+
     interrupts::init();
     log::info!("loaded IDT");
 
+    // Stop and wait for the debugger now that breakpoint and debug exceptions can be
+    // handled.
+    if command_line.gdbstub {
+        gdbstub::init();
+    }
+
     apic::init();
     log::info!("loaded APIC");
 
-    let rsdp = VirtAddr::new(RSDP.get_response().get().unwrap().address.as_ptr().unwrap() as u64);
+    pmu::init();
 
-    acpi::init(rsdp);
+    acpi::init(boot_info.rsdp);
     log::info!("loaded ACPI");
 
+    if command_line.iommu {
+        drivers::vtd::init();
+    }
+
+    mem::numa::init();
+
     tls::init(0);
     log::info!("loaded TLS");
 
@@ -194,8 +307,7 @@ extern "C" fn arch_aero_main() -> ! {
 
     syscall::init();
 
-    let boot_time = BOOT_TIME.get_response().get().unwrap();
-    time::EPOCH.store(boot_time.boot_time as _, Ordering::SeqCst);
+    time::EPOCH.store(boot_info.boot_time, Ordering::SeqCst);
 
     // Architecture init is done. Now we can initialize and start the init
     // process in the non-architecture specific part of the kernel.
@@ -215,6 +327,8 @@ extern "C" fn x86_64_aero_ap_main(boot_info: *const LimineSmpInfo) -> ! {
     tls::init(ap_id);
     log::info!("AP{}: loaded TLS", ap_id);
 
+    fpu::init(false);
+
     gdt::init();
     log::info!("AP{}: loaded GDT", ap_id);
 
@@ -235,7 +349,11 @@ pub fn enable_acpi() {
     aml::get_subsystem().enable_acpi(INTERRUPT_CONTROLLER.method() as _);
 }
 
+crate::late_initcall!(enable_acpi);
+
 pub fn init_cpu() {
+    features::init();
+
     unsafe {
         // Enable the no-execute page protection feature.
         io::wrmsr(io::IA32_EFER, io::rdmsr(io::IA32_EFER) | 1 << 11);
@@ -246,23 +364,7 @@ pub fn init_cpu() {
             .map_or(false, |i| i.has_sse());
 
         assert!(has_sse);
-
-        {
-            let mut cr0 = controlregs::read_cr0();
-
-            cr0.remove(controlregs::Cr0Flags::EMULATE_COPROCESSOR);
-            cr0.insert(controlregs::Cr0Flags::MONITOR_COPROCESSOR);
-
-            controlregs::write_cr0(cr0);
-        }
-
-        {
-            let mut cr4 = controlregs::read_cr4();
-
-            cr4.insert(controlregs::Cr4Flags::OSFXSR);
-            cr4.insert(controlregs::Cr4Flags::OSXMMEXCPT_ENABLE);
-
-            controlregs::write_cr4(cr4);
-        }
     }
+
+    fpu::init(true);
 }
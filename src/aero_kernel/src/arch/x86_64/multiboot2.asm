@@ -0,0 +1,263 @@
+; Copyright (C) 2021-2022 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; The Multiboot2 header and entry point, used to boot the kernel from GRUB2 (see
+; `multiboot2.rs`). The bootloader loads the segments at their physical address and jumps
+; to `x86_64_multiboot2_entry` in 32-bit protected mode with paging disabled, so the entry
+; point maps the kernel and the higher half direct map itself before entering long mode.
+;
+; The entry point is only assembled with the `multiboot2` feature.
+
+%ifdef FEATURE_MULTIBOOT2
+
+global x86_64_multiboot2_entry
+extern x86_64_multiboot2_main
+extern __kernel_end
+
+; They have to match `kernel.ld`.
+%define KERNEL_VMA 0xffffffff80000000
+%define KERNEL_LMA 0x200000
+
+; Converts the address of a symbol of the kernel into its physical address, used before
+; the kernel is mapped.
+%define PHYS(addr) ((addr) - KERNEL_VMA + KERNEL_LMA)
+
+%define MULTIBOOT2_HEADER_MAGIC 0xe85250d6
+%define MULTIBOOT2_BOOTLOADER_MAGIC 0x36d76289
+%define MULTIBOOT2_ARCH_I386 0
+
+%define HEADER_TAG_END 0
+%define HEADER_TAG_INFORMATION_REQUEST 1
+%define HEADER_TAG_ENTRY_ADDRESS 3
+%define HEADER_TAG_FRAMEBUFFER 5
+%define HEADER_TAG_MODULE_ALIGN 6
+
+%define PAGE_PRESENT (1 << 0)
+%define PAGE_WRITABLE (1 << 1)
+%define PAGE_HUGE (1 << 7)
+
+; The offset of the higher half direct map. It covers the first 4GiB of the physical
+; memory (see `multiboot2::PHYSICAL_MEMORY_OFFSET`).
+%define HHDM_PML4_INDEX 256
+
+section .multiboot2 progbits alloc noexec nowrite align=8
+
+multiboot2_header:
+    dd MULTIBOOT2_HEADER_MAGIC
+    dd MULTIBOOT2_ARCH_I386
+    dd multiboot2_header_end - multiboot2_header
+    dd 0x100000000 - (MULTIBOOT2_HEADER_MAGIC + MULTIBOOT2_ARCH_I386 + (multiboot2_header_end - multiboot2_header))
+
+align 8
+.information_request:
+    dw HEADER_TAG_INFORMATION_REQUEST
+    dw 0
+    dd .information_request_end - .information_request
+    dd 1  ; The command line.
+    dd 3  ; The modules.
+    dd 6  ; The memory map.
+    dd 8  ; The framebuffer.
+    dd 9  ; The ELF sections.
+.information_request_end:
+
+align 8
+.entry_address:
+    dw HEADER_TAG_ENTRY_ADDRESS
+    dw 0
+    dd 12
+    dd PHYS(x86_64_multiboot2_entry)
+
+; The framebuffer console draws 32-bit pixels.
+align 8
+.framebuffer:
+    dw HEADER_TAG_FRAMEBUFFER
+    dw 0
+    dd 20
+    dd 0  ; Any width.
+    dd 0  ; Any height.
+    dd 32
+
+align 8
+.module_align:
+    dw HEADER_TAG_MODULE_ALIGN
+    dw 0
+    dd 8
+
+align 8
+.end:
+    dw HEADER_TAG_END
+    dw 0
+    dd 8
+multiboot2_header_end:
+
+section .text
+
+bits 32
+
+; Parameters: eax = the bootloader magic, ebx = the physical address of the boot
+; information
+x86_64_multiboot2_entry:
+    cli
+    cld
+
+    cmp eax, MULTIBOOT2_BOOTLOADER_MAGIC
+    jne .halt
+
+    mov esp, PHYS(boot_stack_top)
+    mov edi, ebx
+
+    ; Check that the CPU supports long mode.
+    mov eax, 0x80000000
+    cpuid
+    cmp eax, 0x80000001
+    jb .halt
+
+    mov eax, 0x80000001
+    cpuid
+    test edx, 1 << 29
+    jz .halt
+
+    ; The identity map (used until the jump to the higher half) and the higher half direct
+    ; map share the same tables, mapping the first 4GiB with 2MiB pages.
+    mov eax, PHYS(boot_pdpt_low) + (PAGE_PRESENT | PAGE_WRITABLE)
+    mov [PHYS(boot_pml4)], eax
+    mov [PHYS(boot_pml4) + HHDM_PML4_INDEX * 8], eax
+
+    mov eax, PHYS(boot_pdpt_kernel) + (PAGE_PRESENT | PAGE_WRITABLE)
+    mov [PHYS(boot_pml4) + 511 * 8], eax
+
+    mov eax, PHYS(boot_pd_low) + (PAGE_PRESENT | PAGE_WRITABLE)
+    xor ecx, ecx
+.map_pdpt_low:
+    mov [PHYS(boot_pdpt_low) + ecx * 8], eax
+    add eax, 0x1000
+    inc ecx
+    cmp ecx, 4
+    jne .map_pdpt_low
+
+    mov eax, PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE
+    xor ecx, ecx
+.map_pd_low:
+    mov [PHYS(boot_pd_low) + ecx * 8], eax
+    add eax, 0x200000
+    inc ecx
+    cmp ecx, 4 * 512
+    jne .map_pd_low
+
+    ; Map the kernel image at `KERNEL_VMA` (the 510th entry of the last PDPT).
+    mov eax, PHYS(boot_pd_kernel) + (PAGE_PRESENT | PAGE_WRITABLE)
+    mov [PHYS(boot_pdpt_kernel) + 510 * 8], eax
+
+    ; EDX = the amount of 2MiB pages the kernel image spans.
+    mov edx, PHYS(__kernel_end) - KERNEL_LMA + 0x1fffff
+    shr edx, 21
+
+    mov eax, KERNEL_LMA | PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE
+    xor ecx, ecx
+.map_kernel:
+    mov [PHYS(boot_pd_kernel) + ecx * 8], eax
+    add eax, 0x200000
+    inc ecx
+    cmp ecx, edx
+    jb .map_kernel
+
+    mov eax, cr4
+    or eax, 1 << 5 ; Physical address extension.
+    mov cr4, eax
+
+    mov eax, PHYS(boot_pml4)
+    mov cr3, eax
+
+    ; Enable long mode.
+    mov ecx, 0xc0000080
+    rdmsr
+    or eax, 1 << 8
+    wrmsr
+
+    mov eax, cr0
+    or eax, (1 << 31) | (1 << 16) ; Paging and write protect.
+    mov cr0, eax
+
+    lgdt [PHYS(boot_gdtr)]
+    jmp 0x08:PHYS(.long_mode)
+
+.halt:
+    hlt
+    jmp .halt
+
+bits 64
+
+.long_mode:
+    xor eax, eax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov rax, .higher_half
+    jmp rax
+
+.higher_half:
+    mov rsp, boot_stack_top
+
+    ; Load the higher half address of the GDT and remove the identity map.
+    lgdt [boot_gdtr64]
+
+    mov qword [boot_pml4], 0
+    mov rax, cr3
+    mov cr3, rax
+
+    mov edi, edi ; Clear the upper half of RDI.
+    xor ebp, ebp
+    call x86_64_multiboot2_main
+
+section .rodata
+
+align 16
+boot_gdt:
+    dq 0                      ; Null descriptor.
+    dq 0x00af9a000000ffff     ; 64-bit code descriptor.
+
+boot_gdtr:
+    dw boot_gdtr - boot_gdt - 1
+    dd PHYS(boot_gdt)
+
+boot_gdtr64:
+    dw boot_gdtr - boot_gdt - 1
+    dq boot_gdt
+
+section .bss
+
+align 4096
+boot_pml4:
+    resb 4096
+boot_pdpt_low:
+    resb 4096
+boot_pdpt_kernel:
+    resb 4096
+boot_pd_low:
+    resb 4096 * 4
+boot_pd_kernel:
+    resb 4096
+
+; The stack of the BSP, as big as the one requested from Limine.
+boot_stack:
+    resb 0x20000
+boot_stack_top:
+
+%endif
@@ -0,0 +1,523 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The Multiboot2 boot protocol, used to boot the kernel from GRUB2.
+//!
+//! The entry point in `multiboot2.asm` maps the kernel image and the first 4GiB of the
+//! physical memory at [`PHYSICAL_MEMORY_OFFSET`], enters long mode and calls
+//! [`x86_64_multiboot2_main`] with the physical address of the boot information. The
+//! boot information is a list of tags, which replace the Limine requests:
+//!
+//! * The command line and the modules (`module2 <path> <name>` in `grub.cfg`).
+//! * The memory map. Only the memory below 4GiB is used, as the rest of it is not mapped.
+//! * The framebuffer, which has to be a 32-bit RGB framebuffer.
+//! * The section headers of the kernel, to find the symbol table used for the backtraces.
+//! * A copy of the RSDP.
+//!
+//! Only the BSP is brought up, as the application processors have to be started with the
+//! INIT-SIPI-SIPI sequence, which is not implemented.
+
+use core::ffi::{c_char, CStr};
+use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::Ordering;
+
+use raw_cpuid::CpuId;
+use xmas_elf::symbol_table::Entry64;
+
+use crate::cmdline;
+use crate::mem::paging::{MemoryRegion, PhysAddr, VirtAddr};
+use crate::rendy::{PixelFormat, RendyInfo};
+
+use super::{apic, interrupts, BootInfo as ArchBootInfo};
+
+/// The offset of the higher half direct map set up by the entry point. It has to match
+/// `HHDM_PML4_INDEX` in `multiboot2.asm`.
+const PHYSICAL_MEMORY_OFFSET: u64 = 0xffff800000000000;
+/// The amount of physical memory mapped by the higher half direct map.
+const PHYSICAL_MEMORY_MAPPED: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The virtual and physical (load) address of the kernel image. They have to match
+/// `kernel.ld`.
+const KERNEL_VMA: u64 = 0xffffffff80000000;
+const KERNEL_LMA: u64 = 0x200000;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOTLOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_RSDP_V1: u32 = 14;
+const TAG_RSDP_V2: u32 = 15;
+
+const MEMORY_AVAILABLE: u32 = 1;
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHF_ALLOC: u64 = 1 << 1;
+
+#[repr(C)]
+struct Tag {
+    typ: u32,
+    size: u32,
+}
+
+#[repr(C)]
+struct ModuleTag {
+    tag: Tag,
+    mod_start: u32,
+    mod_end: u32,
+    // Followed by the NUL-terminated command line of the module.
+}
+
+#[repr(C)]
+struct MemoryMapTag {
+    tag: Tag,
+    entry_size: u32,
+    entry_version: u32,
+    // Followed by the entries.
+}
+
+#[repr(C)]
+struct MemoryMapEntry {
+    base: u64,
+    len: u64,
+    typ: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+struct FramebufferTag {
+    tag: Tag,
+    addr: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bpp: u8,
+    typ: u8,
+    reserved: u16,
+
+    // The color info of the RGB framebuffers.
+    red_field_position: u8,
+    red_mask_size: u8,
+    green_field_position: u8,
+    green_mask_size: u8,
+    blue_field_position: u8,
+    blue_mask_size: u8,
+}
+
+#[repr(C)]
+struct ElfSectionsTag {
+    tag: Tag,
+    num: u32,
+    entsize: u32,
+    shndx: u32,
+    // Followed by the section headers, which are not 8-byte aligned.
+}
+
+/// An ELF64 section header of the kernel. The bootloader sets the address of the sections
+/// without the `SHF_ALLOC` flag to the physical address it loaded them at, or to zero if
+/// it did not load them.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SectionHeader {
+    name: u32,
+    typ: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+}
+
+/// A module loaded by the bootloader.
+pub struct Module {
+    start: u64,
+    end: u64,
+    cmdline: &'static str,
+}
+
+impl Module {
+    /// Returns the name of the module, its command line in `grub.cfg`.
+    pub fn cmdline(&self) -> &'static str {
+        self.cmdline
+    }
+
+    pub fn data(&self) -> &'static [u8] {
+        let start = PhysAddr::new(self.start).as_hhdm_virt();
+
+        // SAFETY: The module is mapped by the higher half direct map and its memory is not
+        // given to the frame allocator.
+        unsafe { core::slice::from_raw_parts(start.as_ptr(), (self.end - self.start) as usize) }
+    }
+}
+
+/// The boot information passed by the bootloader, accessed through the higher half direct
+/// map.
+#[derive(Copy, Clone)]
+pub struct BootInfo {
+    addr: VirtAddr,
+}
+
+impl BootInfo {
+    /// ## Safety
+    /// The caller must ensure that `addr` is the physical address of the boot information
+    /// and that it is mapped by the higher half direct map.
+    unsafe fn new(addr: PhysAddr) -> Self {
+        Self {
+            addr: addr.as_hhdm_virt(),
+        }
+    }
+
+    /// Returns the physical memory used by the boot information.
+    fn range(&self) -> Range<u64> {
+        let start = self.addr.as_hhdm_phys().as_u64();
+        let total_size = unsafe { *self.addr.as_ptr::<u32>() };
+
+        start..start + total_size as u64
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &'static Tag> + Clone {
+        let mut current = self.addr + 8u64;
+
+        core::iter::from_fn(move || {
+            // SAFETY: The tags are 8-byte aligned and the list ends with the end tag.
+            let tag = unsafe { &*current.as_ptr::<Tag>() };
+
+            if tag.typ == TAG_END {
+                return None;
+            }
+
+            current = (current + tag.size as u64).align_up(8u64);
+            Some(tag)
+        })
+    }
+
+    fn find_tag(&self, typ: u32) -> Option<&'static Tag> {
+        self.tags().find(|tag| tag.typ == typ)
+    }
+
+    /// Returns the kernel command line, or an empty string if there is none.
+    pub fn cmdline(&self) -> &'static str {
+        self.find_tag(TAG_CMDLINE)
+            .map(|tag| unsafe { tag_str(tag, size_of::<Tag>()) })
+            .unwrap_or("")
+    }
+
+    fn bootloader_name(&self) -> &'static str {
+        self.find_tag(TAG_BOOTLOADER_NAME)
+            .map(|tag| unsafe { tag_str(tag, size_of::<Tag>()) })
+            .unwrap_or("unknown")
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = Module> + Clone {
+        self.tags().filter(|tag| tag.typ == TAG_MODULE).map(|tag| {
+            let module = unsafe { &*(tag as *const Tag as *const ModuleTag) };
+
+            Module {
+                start: module.mod_start as u64,
+                end: module.mod_end as u64,
+                cmdline: unsafe { tag_str(tag, size_of::<ModuleTag>()) },
+            }
+        })
+    }
+
+    fn memory_map(&self) -> impl Iterator<Item = &'static MemoryMapEntry> + Clone {
+        let (entries, count, entry_size) = match self.find_tag(TAG_MEMORY_MAP) {
+            Some(tag) => {
+                let memory_map = unsafe { &*(tag as *const Tag as *const MemoryMapTag) };
+                let entries = tag as *const Tag as usize + size_of::<MemoryMapTag>();
+                let entry_size = memory_map.entry_size as usize;
+                let count = (tag.size as usize - size_of::<MemoryMapTag>()) / entry_size;
+
+                (entries, count, entry_size)
+            }
+
+            None => (0, 0, 0),
+        };
+
+        (0..count).map(move |i| unsafe { &*((entries + i * entry_size) as *const MemoryMapEntry) })
+    }
+
+    fn framebuffer(&self) -> Option<&'static FramebufferTag> {
+        self.find_tag(TAG_FRAMEBUFFER)
+            .map(|tag| unsafe { &*(tag as *const Tag as *const FramebufferTag) })
+    }
+
+    fn elf_sections(&self) -> impl Iterator<Item = SectionHeader> + Clone {
+        let (headers, count) = match self.find_tag(TAG_ELF_SECTIONS) {
+            Some(tag) => {
+                let sections = unsafe { &*(tag as *const Tag as *const ElfSectionsTag) };
+                let headers = tag as *const Tag as usize + size_of::<ElfSectionsTag>();
+
+                if sections.entsize as usize == size_of::<SectionHeader>() {
+                    (headers, sections.num as usize)
+                } else {
+                    (0, 0)
+                }
+            }
+
+            None => (0, 0),
+        };
+
+        (0..count).map(move |i| unsafe {
+            ((headers + i * size_of::<SectionHeader>()) as *const SectionHeader).read_unaligned()
+        })
+    }
+
+    /// Returns the symbol table and the string table of the kernel, if the bootloader
+    /// loaded them.
+    fn symbol_tables(&self) -> Option<(&'static [Entry64], &'static [u8])> {
+        let symtab = self
+            .elf_sections()
+            .find(|section| section.typ == SHT_SYMTAB && section.addr != 0)?;
+
+        let strtab = self.elf_sections().nth(symtab.link as usize)?;
+
+        if strtab.addr == 0 {
+            return None;
+        }
+
+        let symtab_addr = PhysAddr::new(symtab.addr).as_hhdm_virt();
+        let strtab_addr = PhysAddr::new(strtab.addr).as_hhdm_virt();
+
+        // SAFETY: The sections are mapped by the higher half direct map and their memory
+        // is not given to the frame allocator.
+        unsafe {
+            Some((
+                core::slice::from_raw_parts(
+                    symtab_addr.as_ptr(),
+                    symtab.size as usize / size_of::<Entry64>(),
+                ),
+                core::slice::from_raw_parts(strtab_addr.as_ptr(), strtab.size as usize),
+            ))
+        }
+    }
+
+    /// Returns the address of the copy of the RSDP, preferring the ACPI 2.0 one.
+    fn rsdp(&self) -> Option<VirtAddr> {
+        self.find_tag(TAG_RSDP_V2)
+            .or_else(|| self.find_tag(TAG_RSDP_V1))
+            .map(|tag| VirtAddr::new(tag as *const Tag as u64 + size_of::<Tag>() as u64))
+    }
+
+    /// Returns the physical memory that is used by the kernel, the boot information and
+    /// the modules, rounded to the page boundaries.
+    fn reserved_memory(&self) -> impl Iterator<Item = Range<u64>> + Clone {
+        extern "C" {
+            static __kernel_end: u8;
+        }
+
+        let kernel_end = unsafe { &__kernel_end as *const u8 as u64 } - KERNEL_VMA + KERNEL_LMA;
+
+        // The sections that are not part of the kernel image, such as the symbol table.
+        let sections = self
+            .elf_sections()
+            .filter(|section| section.flags & SHF_ALLOC == 0 && section.addr != 0)
+            .map(|section| section.addr..section.addr + section.size);
+
+        // The real mode IVT and BDA.
+        core::iter::once(0..0x1000)
+            .chain(core::iter::once(KERNEL_LMA..kernel_end))
+            .chain(core::iter::once(self.range()))
+            .chain(self.modules().map(|module| module.start..module.end))
+            .chain(sections)
+            .map(|range| (range.start & !0xfff)..((range.end + 0xfff) & !0xfff))
+    }
+
+    /// Returns the usable memory regions below 4GiB.
+    fn usable_memory(&self) -> impl Iterator<Item = MemoryRegion> + Clone {
+        let memory_map = self
+            .memory_map()
+            .filter(|entry| entry.typ == MEMORY_AVAILABLE)
+            .filter_map(|entry| {
+                let end = (entry.base + entry.len).min(PHYSICAL_MEMORY_MAPPED);
+
+                (entry.base < end).then(|| MemoryRegion {
+                    base: entry.base,
+                    len: end - entry.base,
+                })
+            });
+
+        UsableMemoryIter {
+            memory_map,
+            reserved: self.reserved_memory(),
+
+            cursor: 0,
+            end: 0,
+        }
+    }
+}
+
+/// Returns the NUL-terminated string at `offset` in `tag`.
+///
+/// ## Safety
+/// The caller must ensure that the tag contains a NUL-terminated string at `offset`.
+unsafe fn tag_str(tag: &'static Tag, offset: usize) -> &'static str {
+    let ptr = (tag as *const Tag as *const u8).add(offset);
+
+    CStr::from_ptr(ptr as *const c_char)
+        .to_str()
+        .expect("multiboot2: invalid utf8")
+}
+
+/// Splits the regions of the memory map around the reserved memory.
+#[derive(Clone)]
+struct UsableMemoryIter<M, R> {
+    memory_map: M,
+    reserved: R,
+
+    cursor: u64,
+    end: u64,
+}
+
+impl<M, R> Iterator for UsableMemoryIter<M, R>
+where
+    M: Iterator<Item = MemoryRegion>,
+    R: Iterator<Item = Range<u64>> + Clone,
+{
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.cursor >= self.end {
+                let region = self.memory_map.next()?;
+
+                self.cursor = region.base;
+                self.end = region.base + region.len;
+                continue;
+            }
+
+            let cursor = self.cursor;
+
+            // Skip the reserved range that the cursor is in.
+            if let Some(range) = self.reserved.clone().find(|range| range.contains(&cursor)) {
+                self.cursor = range.end;
+                continue;
+            }
+
+            // The region ends at the next reserved range.
+            let end = self
+                .reserved
+                .clone()
+                .map(|range| range.start)
+                .filter(|&start| start > cursor)
+                .fold(self.end, u64::min);
+
+            self.cursor = end;
+
+            return Some(MemoryRegion {
+                base: cursor,
+                len: end - cursor,
+            });
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn x86_64_multiboot2_main(info: u64) -> ! {
+    unsafe {
+        interrupts::disable_interrupts();
+    }
+
+    unsafe {
+        crate::PHYSICAL_MEMORY_OFFSET = VirtAddr::new(PHYSICAL_MEMORY_OFFSET);
+    }
+
+    // SAFETY: The entry point passes the address of the boot information, which is in the
+    // first 4GiB of the physical memory.
+    let info = unsafe { BootInfo::new(PhysAddr::new(info)) };
+
+    // Before we start the initialization process, we need to make sure
+    // the unwind info is avaliable; just in case if there is a kernel
+    // panic, it will be able to unwind the stack.
+    if let Some((symbol_table, string_table)) = info.symbol_tables() {
+        crate::unwind::UNWIND_INFO
+            .call_once(|| crate::unwind::UnwindInfo::from_tables(symbol_table, string_table));
+    }
+
+    super::init_early();
+    log::info!("multiboot2: booted by {}", info.bootloader_name());
+
+    let command_line = cmdline::parse(info.cmdline(), cmdline::Modules::Multiboot2(info));
+
+    super::init_memory(info.usable_memory());
+
+    // The BSP always has the CPU ID 0 (see `tls::init`).
+    let bsp_apic_id = CpuId::new()
+        .get_feature_info()
+        .map_or(0, |info| info.initial_local_apic_id() as u32);
+
+    apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+    apic::register_cpu_apic_id(0, bsp_apic_id);
+
+    log::info!("multiboot2: the application processors are not started");
+
+    let framebuffer = info
+        .framebuffer()
+        .filter(|framebuffer| framebuffer.typ == FRAMEBUFFER_TYPE_RGB && framebuffer.bpp == 32)
+        .expect("multiboot2: no framebuffer found!");
+
+    let byte_len = framebuffer.pitch as usize * framebuffer.height as usize;
+
+    assert!(
+        framebuffer.addr + byte_len as u64 <= PHYSICAL_MEMORY_MAPPED,
+        "multiboot2: the framebuffer is not mapped"
+    );
+
+    let framebuffer_info = RendyInfo {
+        byte_len,
+        bits_per_pixel: framebuffer.bpp as usize,
+        horizontal_resolution: framebuffer.width as usize,
+        vertical_resolution: framebuffer.height as usize,
+        pixel_format: if framebuffer.red_field_position == 0 {
+            PixelFormat::RGB
+        } else {
+            PixelFormat::BGR
+        },
+        stride: framebuffer.pitch as usize,
+
+        red_mask_shift: framebuffer.red_field_position,
+        red_mask_size: framebuffer.red_mask_size,
+
+        green_mask_shift: framebuffer.green_field_position,
+        green_mask_size: framebuffer.green_mask_size,
+
+        blue_mask_shift: framebuffer.blue_field_position,
+        blue_mask_size: framebuffer.blue_mask_size,
+    };
+
+    let rsdp = info.rsdp().expect("multiboot2: no RSDP found!");
+
+    super::init_platform(
+        command_line,
+        ArchBootInfo {
+            framebuffer: PhysAddr::new(framebuffer.addr).as_hhdm_virt().as_mut_ptr(),
+            framebuffer_info,
+            rsdp,
+            // Multiboot2 does not report the boot time, so the RTC is read instead.
+            boot_time: 0,
+        },
+    )
+}
@@ -0,0 +1,702 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Architectural performance monitoring (version 2 or later). The fixed-function
+//! counters are used to count the instructions retired and the core cycles when they are
+//! available, and the general-purpose counters are used otherwise (and for the cache
+//! misses).
+//!
+//! A counter with a sample period is programmed to overflow after that many events, at
+//! which point the overflow interrupt records the interrupted instruction pointer and
+//! thread and reloads the counter.
+//!
+//! Each counter belongs to the task that opened it and only counts while that task is
+//! running: the scheduler calls [`switch_task`] on every context switch, which saves the
+//! count of the counters of the previous task and programs the counters of the next one
+//! on the CPU it runs on.
+//!
+//! The last general-purpose counter is reserved for the NMI watchdog (see
+//! [`start_nmi_watchdog`]). On the CPUs it runs on, the overflow interrupt is delivered as
+//! an NMI and the NMI handler forwards the overflows of the other counters to the regular
+//! overflow interrupt, as they are behind a lock.
+//!
+//! **Note**: The overflow interrupt is only delivered to the BSP.
+//!
+//! **Notes**: Intel SDM Volume 3, Chapter 20 "Performance Monitoring"
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aero_syscall::PerfSample;
+use alloc::boxed::Box;
+use raw_cpuid::CpuId;
+use spin::Once;
+
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+use super::tls;
+
+use super::apic;
+use super::interrupts::{self, InterruptStack, IrqFlags};
+use super::{io, tsc};
+
+/// The maximum amount of counters (fixed-function and general-purpose) that can be
+/// managed.
+const MAX_COUNTERS: usize = 16;
+/// The amount of samples each counter can hold until they are read.
+const SAMPLE_BUFFER_SIZE: usize = 1024;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+const FIXED_CTRL_OS: u64 = 1;
+const FIXED_CTRL_USR: u64 = 1 << 1;
+const FIXED_CTRL_PMI: u64 = 1 << 3;
+
+/// The maximum amount of unhalted core cycles between the NMIs of the watchdog. Writing
+/// a general-purpose counter only sets its low 32 bits and sign extends them, so it has
+/// to fit in 31 bits (about a second at 2 GHz).
+const NMI_WATCHDOG_MAX_PERIOD: u64 = i32::MAX as u64;
+
+static PMU: Once<Mutex<Pmu>> = Once::new();
+static PMU_VECTOR: Once<u8> = Once::new();
+
+/// The general-purpose counter reserved for the NMI watchdog, if there is one.
+static NMI_WATCHDOG: Once<NmiWatchdog> = Once::new();
+/// The mask of the CPUs the overflow interrupt is delivered as an NMI to.
+static NMI_CPUS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PmuEvent {
+    Cycles,
+    Instructions,
+    CacheMisses,
+}
+
+impl PmuEvent {
+    /// Returns the fixed-function counter that counts this event, if any.
+    fn fixed_counter(&self) -> Option<usize> {
+        match self {
+            PmuEvent::Instructions => Some(0),
+            PmuEvent::Cycles => Some(1),
+            PmuEvent::CacheMisses => None,
+        }
+    }
+
+    /// Returns the architectural event number and unit mask of this event, used to
+    /// program a general-purpose counter.
+    fn event_select(&self) -> u64 {
+        match self {
+            PmuEvent::Cycles => 0x3c,
+            PmuEvent::Instructions => 0xc0,
+            PmuEvent::CacheMisses => 0x41 << 8 | 0x2e,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum HwCounter {
+    Fixed(usize),
+    General(usize),
+}
+
+impl HwCounter {
+    /// Returns the bit of this counter in the global control and status registers.
+    fn global_bit(&self) -> u64 {
+        match self {
+            HwCounter::Fixed(i) => 1 << (32 + i),
+            HwCounter::General(i) => 1 << i,
+        }
+    }
+
+    fn msr(&self) -> u32 {
+        match self {
+            HwCounter::Fixed(i) => io::IA32_FIXED_CTR0 + *i as u32,
+            HwCounter::General(i) => io::IA32_PMC0 + *i as u32,
+        }
+    }
+}
+
+struct SampleBuffer {
+    samples: Box<[PerfSample]>,
+    head: usize,
+    len: usize,
+    lost: usize,
+}
+
+impl SampleBuffer {
+    fn new() -> Self {
+        Self {
+            samples: alloc::vec![PerfSample::default(); SAMPLE_BUFFER_SIZE].into_boxed_slice(),
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    fn push(&mut self, sample: PerfSample) {
+        if self.len == self.samples.len() {
+            self.lost += 1;
+            return;
+        }
+
+        let index = (self.head + self.len) % self.samples.len();
+
+        self.samples[index] = sample;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<PerfSample> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let sample = self.samples[self.head];
+
+        self.head = (self.head + 1) % self.samples.len();
+        self.len -= 1;
+
+        Some(sample)
+    }
+}
+
+struct Counter {
+    hw: HwCounter,
+    event: PmuEvent,
+    period: u64,
+    /// The value the hardware counter was last loaded with.
+    start: u64,
+    /// The amount of events counted before the hardware counter was last loaded.
+    base: u64,
+    samples: SampleBuffer,
+    /// The thread ID of the task that opened the counter.
+    owner: usize,
+    /// Whether the counter was enabled by its owner.
+    enabled: bool,
+    /// The CPU the hardware counter is programmed on, while the owner is running.
+    active: Option<usize>,
+}
+
+impl Counter {
+    fn is_active_here(&self) -> bool {
+        self.active == Some(tls::get_cpuid())
+    }
+}
+
+struct Pmu {
+    fixed_count: usize,
+    general_count: usize,
+    fixed_width: u32,
+    general_width: u32,
+    counters: [Option<Counter>; MAX_COUNTERS],
+}
+
+impl Pmu {
+    fn width(&self, hw: HwCounter) -> u32 {
+        match hw {
+            HwCounter::Fixed(_) => self.fixed_width,
+            HwCounter::General(_) => self.general_width,
+        }
+    }
+
+    fn mask(&self, hw: HwCounter) -> u64 {
+        (1u64 << self.width(hw)) - 1
+    }
+
+    /// Returns the slot of the hardware counter in the counters table. The fixed-function
+    /// counters come first.
+    fn slot(&self, hw: HwCounter) -> usize {
+        match hw {
+            HwCounter::Fixed(i) => i,
+            HwCounter::General(i) => self.fixed_count + i,
+        }
+    }
+
+    fn allocate(&self, event: PmuEvent) -> Option<HwCounter> {
+        let is_free = |hw: HwCounter| self.counters[self.slot(hw)].is_none();
+
+        event
+            .fixed_counter()
+            .filter(|i| *i < self.fixed_count)
+            .map(HwCounter::Fixed)
+            .filter(|hw| is_free(*hw))
+            .or_else(|| {
+                (0..self.general_count)
+                    .map(HwCounter::General)
+                    .find(|hw| is_free(*hw))
+            })
+    }
+
+    /// Loads the hardware counter so that it overflows after `period` events (or counts
+    /// from zero if sampling is not enabled).
+    fn load(&mut self, slot: usize) {
+        let counter = self.counters[slot].as_ref().unwrap();
+        let hw = counter.hw;
+
+        let start = if counter.period != 0 {
+            self.mask(hw).wrapping_sub(counter.period - 1) & self.mask(hw)
+        } else {
+            0
+        };
+
+        unsafe { io::wrmsr(hw.msr(), start) }
+        self.counters[slot].as_mut().unwrap().start = start;
+    }
+
+    /// Returns the amount of events counted. The hardware counter can only be read on the
+    /// CPU it is programmed on, so the count saved on the last context switch is returned
+    /// on the other CPUs.
+    fn read(&self, slot: usize) -> u64 {
+        let counter = self.counters[slot].as_ref().unwrap();
+
+        if !counter.is_active_here() {
+            return counter.base;
+        }
+
+        let value = unsafe { io::rdmsr(counter.hw.msr()) };
+        counter.base + (value.wrapping_sub(counter.start) & self.mask(counter.hw))
+    }
+
+    /// Starts counting on the current CPU.
+    fn activate(&mut self, slot: usize) {
+        self.load(slot);
+        self.program(slot, true);
+        self.counters[slot].as_mut().unwrap().active = Some(tls::get_cpuid());
+    }
+
+    /// Stops counting on the current CPU and saves the count.
+    fn deactivate(&mut self, slot: usize) {
+        let count = self.read(slot);
+        self.program(slot, false);
+
+        let counter = self.counters[slot].as_mut().unwrap();
+
+        counter.base = count;
+        counter.active = None;
+    }
+
+    /// Enables or disables the hardware counter on the current CPU.
+    fn program(&mut self, slot: usize, enabled: bool) {
+        let counter = self.counters[slot].as_mut().unwrap();
+        let sampling = counter.period != 0;
+
+        unsafe {
+            match counter.hw {
+                HwCounter::Fixed(i) => {
+                    let mut control = io::rdmsr(io::IA32_FIXED_CTR_CTRL);
+                    control &= !(0xf << (i * 4));
+
+                    if enabled {
+                        let mut bits = FIXED_CTRL_OS | FIXED_CTRL_USR;
+
+                        if sampling {
+                            bits |= FIXED_CTRL_PMI;
+                        }
+
+                        control |= bits << (i * 4);
+                    }
+
+                    io::wrmsr(io::IA32_FIXED_CTR_CTRL, control);
+                }
+
+                HwCounter::General(i) => {
+                    let mut select = 0;
+
+                    if enabled {
+                        select = counter.event.event_select() | EVTSEL_OS | EVTSEL_USR | EVTSEL_EN;
+
+                        if sampling {
+                            select |= EVTSEL_INT;
+                        }
+                    }
+
+                    io::wrmsr(io::IA32_PERFEVTSEL0 + i as u32, select);
+                }
+            }
+
+            let mut global = io::rdmsr(io::IA32_PERF_GLOBAL_CTRL);
+
+            if enabled {
+                global |= counter.hw.global_bit();
+            } else {
+                global &= !counter.hw.global_bit();
+            }
+
+            io::wrmsr(io::IA32_PERF_GLOBAL_CTRL, global);
+        }
+    }
+
+    /// Accounts for the overflow of the counter, records a sample and reloads it.
+    fn overflow(&mut self, slot: usize, stack: &InterruptStack) {
+        let width = self.width(self.counters[slot].as_ref().unwrap().hw);
+        let counter = self.counters[slot].as_mut().unwrap();
+
+        counter.base += (1u64 << width) - counter.start;
+
+        if counter.period != 0 {
+            let tid = scheduler::get_scheduler()
+                .inner
+                .current_task_optional()
+                .map(|task| task.tid().as_usize() as u64)
+                .unwrap_or(0);
+
+            counter.samples.push(PerfSample {
+                ip: stack.iret.rip,
+                tid,
+            });
+        }
+
+        self.load(slot);
+    }
+}
+
+struct NmiWatchdog {
+    hw: HwCounter,
+    /// The mask of the bits implemented by the counter.
+    mask: u64,
+    /// The value the counter is loaded with so that it overflows after the period.
+    start: AtomicU64,
+}
+
+impl NmiWatchdog {
+    fn load(&self) {
+        unsafe { io::wrmsr(self.hw.msr(), self.start.load(Ordering::Relaxed)) }
+    }
+}
+
+/// Returns whether the overflow interrupt is delivered as an NMI to the current CPU.
+fn is_nmi_here() -> bool {
+    let cpu = tls::get_cpuid();
+    cpu < 64 && NMI_CPUS.load(Ordering::Acquire) & (1 << cpu) != 0
+}
+
+fn pmu_overflow_handler(stack: &mut InterruptStack) {
+    if let Some(pmu) = PMU.get() {
+        let mut pmu = pmu.lock();
+        let status = unsafe { io::rdmsr(io::IA32_PERF_GLOBAL_STATUS) };
+
+        for slot in 0..MAX_COUNTERS {
+            let overflowed = pmu.counters[slot]
+                .as_ref()
+                .map(|counter| counter.is_active_here() && status & counter.hw.global_bit() != 0)
+                .unwrap_or(false);
+
+            if overflowed {
+                pmu.overflow(slot, stack);
+            }
+        }
+
+        // The NMI handler reloads the watchdog counter and clears its overflow.
+        let watchdog_bit = NMI_WATCHDOG.get().map(|w| w.hw.global_bit()).unwrap_or(0);
+        unsafe { io::wrmsr(io::IA32_PERF_GLOBAL_OVF_CTRL, status & !watchdog_bit) }
+    }
+
+    // The local APIC masks the LVT entry on delivery. If the overflows are delivered as
+    // NMIs, the NMI handler unmasked it already.
+    if !is_nmi_here() {
+        apic::get_local_apic().set_perf_vector(*PMU_VECTOR.get().unwrap());
+    }
+}
+
+/// Starts the NMI watchdog counter on the current CPU, which then sends an NMI about once
+/// a second (see [`handle_nmi`]). Returns `false` if there is no counter reserved for it.
+///
+/// The core cycles are assumed to tick at the frequency of the TSC, or the period is
+/// [`NMI_WATCHDOG_MAX_PERIOD`] if the TSC is not calibrated.
+pub fn start_nmi_watchdog() -> bool {
+    let watchdog = match NMI_WATCHDOG.get() {
+        Some(watchdog) => watchdog,
+        None => return false,
+    };
+
+    let cpu = tls::get_cpuid();
+
+    if cpu >= 64 {
+        return false;
+    }
+
+    let index = match watchdog.hw {
+        HwCounter::General(i) => i as u32,
+        HwCounter::Fixed(_) => unreachable!(),
+    };
+
+    let period = tsc::frequency()
+        .unwrap_or(NMI_WATCHDOG_MAX_PERIOD)
+        .min(NMI_WATCHDOG_MAX_PERIOD);
+
+    let start = watchdog.mask.wrapping_sub(period - 1) & watchdog.mask;
+    watchdog.start.store(start, Ordering::Relaxed);
+
+    let select = PmuEvent::Cycles.event_select() | EVTSEL_OS | EVTSEL_USR | EVTSEL_INT | EVTSEL_EN;
+
+    unsafe {
+        watchdog.load();
+        io::wrmsr(io::IA32_PERFEVTSEL0 + index, select);
+
+        let global = io::rdmsr(io::IA32_PERF_GLOBAL_CTRL);
+        io::wrmsr(io::IA32_PERF_GLOBAL_CTRL, global | watchdog.hw.global_bit());
+
+        NMI_CPUS.fetch_or(1 << cpu, Ordering::AcqRel);
+        apic::get_local_apic_nmi().set_perf_nmi();
+    }
+
+    true
+}
+
+/// Returns whether a counter is reserved for the NMI watchdog.
+pub fn has_nmi_watchdog() -> bool {
+    NMI_WATCHDOG.get().is_some()
+}
+
+/// Called from the NMI handler. Returns [`None`] if no counter overflowed, or whether the
+/// NMI watchdog counter did, in which case it is reloaded. The overflows of the other
+/// counters are handled by the regular overflow interrupt, which is sent to the current
+/// CPU.
+pub fn handle_nmi() -> Option<bool> {
+    if !is_nmi_here() {
+        return None;
+    }
+
+    let watchdog = NMI_WATCHDOG.get()?;
+    let status = unsafe { io::rdmsr(io::IA32_PERF_GLOBAL_STATUS) };
+
+    let watchdog_bit = watchdog.hw.global_bit();
+    // The bits above 47 are not counter overflows.
+    let others = status & !watchdog_bit & ((1 << 48) - 1);
+
+    if status & watchdog_bit == 0 && others == 0 {
+        return None;
+    }
+
+    unsafe {
+        let mut local_apic = apic::get_local_apic_nmi();
+
+        if status & watchdog_bit != 0 {
+            watchdog.load();
+            io::wrmsr(io::IA32_PERF_GLOBAL_OVF_CTRL, watchdog_bit);
+        }
+
+        if others != 0 {
+            local_apic.send_self_ipi(*PMU_VECTOR.get().unwrap());
+        }
+
+        // The local APIC masks the LVT entry on delivery.
+        local_apic.set_perf_nmi();
+    }
+
+    Some(status & watchdog_bit != 0)
+}
+
+/// Returns whether architectural performance monitoring is supported.
+pub fn is_supported() -> bool {
+    PMU.get().is_some()
+}
+
+/// Returns the thread ID of the current task, or zero for the idle task.
+fn current_tid() -> usize {
+    scheduler::get_scheduler()
+        .inner
+        .current_task_optional()
+        .map(|task| task.tid().as_usize())
+        .unwrap_or(0)
+}
+
+/// Allocates a counter for the event that counts while the current task is running,
+/// returning its handle. If the sample period is non-zero, a sample is recorded each time
+/// the period elapses.
+pub fn open(event: PmuEvent, period: u64, enabled: bool) -> Option<usize> {
+    let mut pmu = PMU.get()?.lock_irq();
+    let hw = pmu.allocate(event)?;
+    let slot = pmu.slot(hw);
+
+    // Writing a general-purpose counter only sets its low 32 bits and sign extends them,
+    // so the period has to fit in 31 bits.
+    if period > i32::MAX as u64 {
+        return None;
+    }
+
+    pmu.counters[slot] = Some(Counter {
+        hw,
+        event,
+        period,
+        start: 0,
+        base: 0,
+        samples: SampleBuffer::new(),
+        owner: current_tid(),
+        enabled,
+        active: None,
+    });
+
+    if enabled {
+        pmu.activate(slot);
+    }
+
+    Some(slot)
+}
+
+pub fn set_enabled(handle: usize, enabled: bool) {
+    let mut pmu = PMU.get().unwrap().lock_irq();
+    let counter = pmu.counters[handle].as_mut().unwrap();
+
+    counter.enabled = enabled;
+
+    let active = counter.is_active_here();
+    let owned = counter.owner == current_tid();
+
+    if enabled && owned && counter.active.is_none() {
+        pmu.activate(handle);
+    } else if !enabled && active {
+        pmu.deactivate(handle);
+    }
+}
+
+/// Returns the amount of events counted.
+pub fn read(handle: usize) -> u64 {
+    PMU.get().unwrap().lock_irq().read(handle)
+}
+
+/// Resets the count of the counter to zero.
+pub fn reset(handle: usize) {
+    let mut pmu = PMU.get().unwrap().lock_irq();
+
+    pmu.counters[handle].as_mut().unwrap().base = 0;
+
+    if pmu.counters[handle].as_ref().unwrap().is_active_here() {
+        pmu.load(handle);
+    }
+}
+
+/// Moves the recorded samples into `samples`, returning the amount moved and the
+/// amount of samples that were dropped so far.
+pub fn take_samples(handle: usize, samples: &mut [PerfSample]) -> (usize, usize) {
+    let mut pmu = PMU.get().unwrap().lock_irq();
+    let buffer = &mut pmu.counters[handle].as_mut().unwrap().samples;
+
+    let mut count = 0;
+
+    while count < samples.len() {
+        match buffer.pop() {
+            Some(sample) => samples[count] = sample,
+            None => break,
+        }
+
+        count += 1;
+    }
+
+    (count, buffer.lost)
+}
+
+/// Disables and frees the counter.
+pub fn close(handle: usize) {
+    let mut pmu = PMU.get().unwrap().lock_irq();
+
+    if pmu.counters[handle].as_ref().unwrap().is_active_here() {
+        pmu.program(handle, false);
+    }
+
+    pmu.counters[handle] = None;
+}
+
+/// Saves the counters of the task that is switched out on the current CPU and starts the
+/// enabled counters of the task with the thread ID `next`.
+pub fn switch_task(next: usize) {
+    let pmu = match PMU.get() {
+        Some(pmu) => pmu,
+        None => return,
+    };
+
+    let mut pmu = pmu.lock_irq();
+
+    for slot in 0..MAX_COUNTERS {
+        let (active, idle, wanted) = match pmu.counters[slot].as_ref() {
+            Some(counter) => (
+                counter.is_active_here(),
+                counter.active.is_none(),
+                counter.owner == next && counter.enabled,
+            ),
+            None => continue,
+        };
+
+        if active && !wanted {
+            pmu.deactivate(slot);
+        } else if idle && wanted {
+            pmu.activate(slot);
+        }
+    }
+}
+
+pub fn init() {
+    let info = match CpuId::new().get_performance_monitoring_info() {
+        // The global control registers are only available since version 2.
+        Some(info) if info.version_id() >= 2 => info,
+        _ => {
+            log::warn!("pmu: architectural performance monitoring is not supported");
+            return;
+        }
+    };
+
+    let fixed_count = (info.fixed_function_counters() as usize).min(MAX_COUNTERS);
+    let mut general_count = (info.number_of_counters() as usize).min(MAX_COUNTERS - fixed_count);
+
+    let general_width = info.counter_bit_width() as u32;
+
+    // Reserve the last general-purpose counter for the NMI watchdog, unless it is the only
+    // one.
+    if general_count >= 2 {
+        general_count -= 1;
+
+        NMI_WATCHDOG.call_once(|| NmiWatchdog {
+            hw: HwCounter::General(general_count),
+            mask: (1u64 << general_width) - 1,
+            start: AtomicU64::new(0),
+        });
+    }
+
+    let pmu = Pmu {
+        fixed_count,
+        general_count,
+        fixed_width: info.fixed_function_counters_bit_width() as u32,
+        general_width,
+        counters: Default::default(),
+    };
+
+    log::info!(
+        "pmu: version {}, {} fixed-function and {} general-purpose counters",
+        info.version_id(),
+        pmu.fixed_count,
+        pmu.general_count
+    );
+
+    // Start with all of the counters disabled.
+    unsafe {
+        io::wrmsr(io::IA32_PERF_GLOBAL_CTRL, 0);
+        io::wrmsr(io::IA32_FIXED_CTR_CTRL, 0);
+    }
+
+    let vector = interrupts::allocate_vector().expect("pmu: failed to allocate a vector");
+    interrupts::request_irq(vector, "pmu", pmu_overflow_handler, IrqFlags::empty())
+        .expect("pmu: failed to request the overflow IRQ");
+
+    PMU_VECTOR.call_once(|| vector);
+    PMU.call_once(|| Mutex::new(pmu));
+
+    apic::get_local_apic().set_perf_vector(vector);
+}
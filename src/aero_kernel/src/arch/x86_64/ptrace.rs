@@ -0,0 +1,168 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The ptrace stops of the current task, converting between its interrupt stack and
+//! the [`PtraceRegs`] seen by the tracer.
+
+use aero_syscall::prelude::PtraceRegs;
+use aero_syscall::SyscallError;
+
+use crate::mem::paging::VirtAddr;
+use crate::userland::ptrace;
+use crate::userland::scheduler;
+
+use super::interrupts::InterruptStack;
+use super::task::userland_last_address;
+
+/// The RFLAGS bits that can be changed by the tracer (CF, PF, AF, ZF, SF, TF, DF, OF and
+/// AC).
+const USER_RFLAGS_MASK: u64 = 0x40dd5;
+
+fn get_regs(stack: &InterruptStack, orig_rax: u64) -> PtraceRegs {
+    let task = scheduler::get_scheduler().current_task();
+    let arch_task = task.arch_task();
+
+    PtraceRegs {
+        r15: stack.preserved.r15,
+        r14: stack.preserved.r14,
+        r13: stack.preserved.r13,
+        r12: stack.preserved.r12,
+        rbp: stack.preserved.rbp,
+        rbx: stack.preserved.rbx,
+        r11: stack.scratch.r11,
+        r10: stack.scratch.r10,
+        r9: stack.scratch.r9,
+        r8: stack.scratch.r8,
+        rax: stack.scratch.rax,
+        rcx: stack.scratch.rcx,
+        rdx: stack.scratch.rdx,
+        rsi: stack.scratch.rsi,
+        rdi: stack.scratch.rdi,
+        orig_rax,
+        rip: stack.iret.rip,
+        cs: stack.iret.cs,
+        eflags: stack.iret.rflags,
+        rsp: stack.iret.rsp,
+        ss: stack.iret.ss,
+        // SAFETY: The current task is the tracee.
+        fs_base: unsafe { arch_task.get_fs_base() }.as_u64(),
+        gs_base: arch_task.get_gs_base().as_u64(),
+        ..Default::default()
+    }
+}
+
+/// Loads the `regs` modified by the tracer. The segment selectors and the privileged
+/// RFLAGS bits cannot be changed.
+fn set_regs(stack: &mut InterruptStack, regs: &PtraceRegs) {
+    stack.preserved.r15 = regs.r15;
+    stack.preserved.r14 = regs.r14;
+    stack.preserved.r13 = regs.r13;
+    stack.preserved.r12 = regs.r12;
+    stack.preserved.rbp = regs.rbp;
+    stack.preserved.rbx = regs.rbx;
+    stack.scratch.r11 = regs.r11;
+    stack.scratch.r10 = regs.r10;
+    stack.scratch.r9 = regs.r9;
+    stack.scratch.r8 = regs.r8;
+    stack.scratch.rax = regs.rax;
+    stack.scratch.rcx = regs.rcx;
+    stack.scratch.rdx = regs.rdx;
+    stack.scratch.rsi = regs.rsi;
+    stack.scratch.rdi = regs.rdi;
+    stack.iret.rip = regs.rip;
+    stack.iret.rsp = regs.rsp;
+    stack.iret.rflags = (stack.iret.rflags & !USER_RFLAGS_MASK) | (regs.eflags & USER_RFLAGS_MASK);
+
+    let task = scheduler::get_scheduler().current_task();
+    let arch_task = task.arch_task_mut();
+
+    // SAFETY: The current task is the tracee.
+    unsafe {
+        if arch_task.get_fs_base().as_u64() != regs.fs_base {
+            arch_task.set_fs_base(VirtAddr::new(regs.fs_base));
+        }
+
+        if arch_task.get_gs_base().as_u64() != regs.gs_base {
+            arch_task.set_gs_base(VirtAddr::new(regs.gs_base));
+        }
+    }
+}
+
+/// Returns whether the `regs` set by the tracer can be loaded (the instruction pointer,
+/// the stack pointer and the segment bases have to be userland addresses).
+pub fn validate_regs(regs: &PtraceRegs) -> bool {
+    let max_user_addr = userland_last_address().as_u64();
+
+    [regs.rip, regs.rsp, regs.fs_base, regs.gs_base]
+        .iter()
+        .all(|address| *address <= max_user_addr)
+}
+
+fn traces_syscalls() -> bool {
+    scheduler::get_scheduler()
+        .current_task()
+        .ptrace()
+        .traces_syscalls()
+}
+
+/// Stops the current task at the entry of the syscall on the `stack` if the tracer asked
+/// for syscall-stops. The tracer can change the syscall number and its arguments.
+pub fn syscall_entry_stop(stack: &mut InterruptStack) {
+    if !traces_syscalls() {
+        return;
+    }
+
+    let mut regs = get_regs(stack, stack.scratch.rax);
+    regs.rax = aero_syscall::syscall_result_as_usize(Err(SyscallError::ENOSYS)) as u64;
+
+    ptrace::stop(ptrace::syscall_stop_status(), &mut regs);
+
+    set_regs(stack, &regs);
+    stack.scratch.rax = regs.orig_rax;
+}
+
+/// Stops the current task at the exit of the syscall `number` if the tracer asked for
+/// syscall-stops. Returns the syscall result, which the tracer can change.
+pub fn syscall_exit_stop(stack: &mut InterruptStack, number: usize, result: usize) -> usize {
+    if !traces_syscalls() {
+        return result;
+    }
+
+    stack.scratch.rax = result as u64;
+
+    let mut regs = get_regs(stack, number as u64);
+    ptrace::stop(ptrace::syscall_stop_status(), &mut regs);
+
+    set_regs(stack, &regs);
+
+    // The syscall number is expected in RAX when checking whether the syscall has to be
+    // restarted after a signal.
+    stack.scratch.rax = number as u64;
+    regs.rax as usize
+}
+
+/// Stops the current task before the `signal` is delivered to it. Returns the signal to
+/// deliver instead, chosen by the tracer (0 if none).
+pub fn signal_stop(stack: &mut InterruptStack, signal: usize) -> usize {
+    let mut regs = get_regs(stack, u64::MAX);
+    let signal = ptrace::stop(ptrace::stop_status(signal), &mut regs);
+
+    set_regs(stack, &regs);
+    signal
+}
@@ -0,0 +1,226 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Resets the system. Not every method works on every machine (for example, the QEMU
+//! `microvm` machine has no 8042 controller and older firmware does not provide the ACPI
+//! reset register), so they are tried in turn until one of them works.
+//!
+//! The methods and the order they are tried in are set with `reboot=<method>,...` on the
+//! kernel command line, where the method is one of:
+//!
+//! * `acpi`: writes the reset value to the reset register described by the FADT.
+//! * `kbd`: pulses the reset line of the CPU through the 8042 keyboard controller.
+//! * `triple`: loads an empty IDT and raises an exception, which triple faults.
+//!
+//! By default, they are tried in the order listed above. The triple fault is always
+//! tried last, as it cannot fail.
+
+use alloc::vec::Vec;
+
+use crate::acpi::{fadt, get_acpi_table};
+use crate::cmdline;
+use crate::mem::paging::PhysAddr;
+
+use super::interrupts;
+use super::io;
+
+/// The 8042 keyboard controller command and status port.
+const KBD_STATUS_PORT: u16 = 0x64;
+/// Set in the status register while the input buffer of the controller is full.
+const KBD_STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Pulses the reset line of the CPU.
+const KBD_CMD_RESET: u8 = 0xfe;
+
+/// The amount of `io::delay` cycles waited for a method to take effect (about 100ms).
+const RESET_DELAY: usize = 100_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RebootMethod {
+    Acpi,
+    Kbd,
+    TripleFault,
+}
+
+impl RebootMethod {
+    const DEFAULT_ORDER: [RebootMethod; 3] = [Self::Acpi, Self::Kbd, Self::TripleFault];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "acpi" => Some(Self::Acpi),
+            "kbd" => Some(Self::Kbd),
+            "triple" => Some(Self::TripleFault),
+            _ => None,
+        }
+    }
+
+    /// Tries to reset the system. Returns if the method is not supported by the machine
+    /// or did not take effect.
+    fn reboot(self) {
+        match self {
+            Self::Acpi => reboot_acpi(),
+            Self::Kbd => reboot_kbd(),
+            Self::TripleFault => reboot_triple_fault(),
+        }
+
+        io::delay(RESET_DELAY);
+    }
+}
+
+/// Parses the `reboot=` option into the methods to try, in order. The unknown methods
+/// are ignored and the triple fault is appended if it is missing.
+///
+/// A method that is listed more than once is only tried once, and the triple fault is
+/// always tried last, as nothing runs after it.
+fn parse_order(option: Option<&str>) -> Vec<RebootMethod> {
+    let option = match option {
+        Some(option) => option,
+        None => return Vec::from(RebootMethod::DEFAULT_ORDER),
+    };
+
+    let mut order = Vec::new();
+
+    for name in option.split(',') {
+        match RebootMethod::from_name(name) {
+            Some(RebootMethod::TripleFault) => {}
+            Some(method) if !order.contains(&method) => order.push(method),
+            Some(_) => {}
+            None => log::warn!("reboot: unknown method '{name}'"),
+        }
+    }
+
+    order.push(RebootMethod::TripleFault);
+    order
+}
+
+fn reboot_acpi() {
+    let reset = get_acpi_table()
+        .lookup_entry(fadt::SIGNATURE)
+        .and_then(|header| {
+            let fadt: &'static fadt::Fadt = unsafe { header.as_ref() };
+            fadt.reset_register()
+        });
+
+    let (register, value) = match reset {
+        Some(reset) => reset,
+        None => {
+            log::warn!("reboot: no ACPI reset register");
+            return;
+        }
+    };
+
+    let address = register.address;
+
+    unsafe {
+        match register.address_space {
+            // System memory.
+            0 => PhysAddr::new(address)
+                .as_hhdm_virt()
+                .as_mut_ptr::<u8>()
+                .write_volatile(value),
+
+            // System I/O.
+            1 => io::outb(address as u16, value),
+
+            space => log::warn!("reboot: unsupported reset register address space {space}"),
+        }
+    }
+}
+
+fn reboot_kbd() {
+    unsafe {
+        // Wait for the controller to be ready to accept a command.
+        for _ in 0..RESET_DELAY {
+            if io::inb(KBD_STATUS_PORT) & KBD_STATUS_INPUT_FULL == 0 {
+                break;
+            }
+
+            io::delay(1);
+        }
+
+        io::outb(KBD_STATUS_PORT, KBD_CMD_RESET);
+    }
+}
+
+fn reboot_triple_fault() {
+    #[repr(C, packed)]
+    struct EmptyIdt {
+        limit: u16,
+        base: u64,
+    }
+
+    let idt = EmptyIdt { limit: 0, base: 0 };
+
+    // Without an IDT, the breakpoint exception cannot be delivered, which raises a double
+    // fault that cannot be delivered either.
+    unsafe {
+        asm!("lidt [{}]", "int3", in(reg) &idt, options(nostack));
+    }
+}
+
+/// Resets the system, trying the methods set on the kernel command line in order.
+pub fn reboot() -> ! {
+    unsafe { interrupts::disable_interrupts() };
+
+    for method in parse_order(cmdline::get_option("reboot")) {
+        log::info!("reboot: trying {method:?}");
+        method.reboot();
+    }
+
+    unreachable!("reboot: the triple fault did not reset the system")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reboot_order() {
+        assert_eq!(
+            parse_order(None),
+            [
+                RebootMethod::Acpi,
+                RebootMethod::Kbd,
+                RebootMethod::TripleFault
+            ]
+        );
+
+        assert_eq!(
+            parse_order(Some("kbd,bogus,acpi")),
+            [
+                RebootMethod::Kbd,
+                RebootMethod::Acpi,
+                RebootMethod::TripleFault
+            ]
+        );
+
+        assert_eq!(
+            parse_order(Some("triple,kbd")),
+            [RebootMethod::Kbd, RebootMethod::TripleFault]
+        );
+
+        assert_eq!(
+            parse_order(Some("kbd,acpi,kbd")),
+            [
+                RebootMethod::Kbd,
+                RebootMethod::Acpi,
+                RebootMethod::TripleFault
+            ]
+        );
+    }
+}
@@ -20,29 +20,35 @@
 use aero_syscall::signal::{SigProcMask, SignalFlags};
 use aero_syscall::SyscallError;
 
+use crate::mem::paging::{align_down, VirtAddr};
+use crate::mem::uaccess;
 use crate::userland;
 use crate::userland::scheduler;
 use crate::utils::StackHelper;
 
+use super::fpu::{self, FpuState};
 use super::interrupts::InterruptStack;
 
 const REDZONE_SIZE: u64 = 128;
 const SYSCALL_INSTRUCTION_SIZE: u64 = 2;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SignalFrame {
     restart_syscall: u64,
     frame: InterruptStack,
     sigmask: u64,
+    /// The address of the FPU state of the interrupted code, saved on the user stack.
+    fpu_state: u64,
 }
 
 impl SignalFrame {
-    fn from_interrupt(frame: &mut InterruptStack, sigmask: u64) -> SignalFrame {
+    fn from_interrupt(frame: &mut InterruptStack, sigmask: u64, fpu_state: u64) -> SignalFrame {
         SignalFrame {
             restart_syscall: u64::MAX,
             frame: *frame,
             sigmask,
+            fpu_state,
         }
     }
 
@@ -51,6 +57,7 @@ impl SignalFrame {
         syscall_result: u64,
         frame: &mut InterruptStack,
         sigmask: u64,
+        fpu_state: u64,
     ) -> SignalFrame {
         SignalFrame {
             restart_syscall: if restart {
@@ -60,10 +67,33 @@ impl SignalFrame {
             },
             frame: *frame,
             sigmask,
+            fpu_state,
         }
     }
 }
 
+/// Pushes `value` on the user stack.
+fn push_user<T: Copy>(writer: &mut StackHelper, value: &T) -> Result<(), SyscallError> {
+    writer.skip_by(core::mem::size_of::<T>() as u64);
+    uaccess::write_user(VirtAddr::new(writer.top()), value)
+}
+
+/// Saves the FPU state of the interrupted code on the user stack and resets the FPU for
+/// the signal handler. Returns the address of the saved state.
+fn push_fpu_state(writer: &mut StackHelper) -> Result<u64, SyscallError> {
+    writer.skip_by(fpu::state_size() as u64);
+
+    let address = align_down(writer.top(), fpu::state_align() as u64);
+    writer.skip_by(writer.top() - address);
+
+    // The state is saved into a kernel buffer first, as the user stack may not be mapped.
+    let state = FpuState::from_current();
+    uaccess::copy_to_user(VirtAddr::new(address), state.as_bytes())?;
+
+    FpuState::new().restore();
+    Ok(address)
+}
+
 pub fn interrupt_check_signals(stack: &mut InterruptStack) {
     // SAFTEY: If this interrupt did not originate from userland then we cannot
     // check for signals since the scheduler might not be initialized.
@@ -71,14 +101,13 @@ pub fn interrupt_check_signals(stack: &mut InterruptStack) {
         return;
     }
 
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
+    if let Some((signal, entry)) = userland::signals::check_for_signals(stack) {
         if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
             let task = scheduler::get_scheduler().current_task();
 
             let signals = task.signals();
             let old_mask = signals.blocked_mask();
 
-            let signal_frame = SignalFrame::from_interrupt(stack, old_mask);
             signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
 
             // We cannot straight away update the stack pointer from the stack
@@ -95,9 +124,16 @@ pub fn interrupt_check_signals(stack: &mut InterruptStack) {
             // stack space by subtracting from the stack pointer.
             writer.skip_by(REDZONE_SIZE);
 
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
+            let pushed = push_fpu_state(&mut writer).and_then(|fpu_state| {
+                let signal_frame = SignalFrame::from_interrupt(stack, old_mask, fpu_state);
+
+                push_user(&mut writer, &signal_frame)?;
+                push_user(&mut writer, &entry.sigreturn())
+            });
+
+            if pushed.is_err() {
+                log::warn!("signal: failed to push the signal frame of signal {signal}");
+                userland::signals::force_sigsegv();
             }
 
             stack.iret.rsp = ptr;
@@ -108,7 +144,7 @@ pub fn interrupt_check_signals(stack: &mut InterruptStack) {
 }
 
 pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack) {
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
+    if let Some((signal, entry)) = userland::signals::check_for_signals(stack) {
         if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
             let task = scheduler::get_scheduler().current_task();
 
@@ -122,8 +158,6 @@ pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack)
             #[cfg(feature = "syslog")]
             log::warn!("syscall routine signaled: (restart={restart_syscall})");
 
-            let signal_frame =
-                SignalFrame::from_syscall(restart_syscall, syscall_result as _, stack, old_mask);
             signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
 
             // We cannot straight away update the stack pointer from the stack
@@ -140,9 +174,22 @@ pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack)
             // stack space by subtracting from the stack pointer.
             writer.skip_by(REDZONE_SIZE);
 
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
+            let pushed = push_fpu_state(&mut writer).and_then(|fpu_state| {
+                let signal_frame = SignalFrame::from_syscall(
+                    restart_syscall,
+                    syscall_result as _,
+                    stack,
+                    old_mask,
+                    fpu_state,
+                );
+
+                push_user(&mut writer, &signal_frame)?;
+                push_user(&mut writer, &entry.sigreturn())
+            });
+
+            if pushed.is_err() {
+                log::warn!("signal: failed to push the signal frame of signal {signal}");
+                userland::signals::force_sigsegv();
             }
 
             stack.iret.rsp = ptr;
@@ -153,8 +200,13 @@ pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack)
 }
 
 pub fn sigreturn(stack: &mut InterruptStack) -> usize {
-    let mut writer = StackHelper::new(&mut stack.iret.rsp);
-    let signal_frame = unsafe { writer.get::<SignalFrame>() };
+    let frame_address = VirtAddr::new(stack.iret.rsp);
+
+    // SAFETY: Any bit pattern is a valid signal frame.
+    let signal_frame = match unsafe { uaccess::read_user::<SignalFrame>(frame_address) } {
+        Ok(signal_frame) => signal_frame,
+        Err(_) => userland::signals::force_sigsegv(),
+    };
 
     let current_task = scheduler::get_scheduler().current_task();
 
@@ -164,7 +216,18 @@ pub fn sigreturn(stack: &mut InterruptStack) -> usize {
         None,
     );
 
-    writer.get_by(REDZONE_SIZE);
+    // Restore the FPU state of the interrupted code. The state was saved on the user
+    // stack so it is sanitized before being loaded.
+    if let Some(fpu_storage) = current_task.arch_task_mut().fpu_storage_mut() {
+        let mut bytes = alloc::vec![0; fpu::state_size()];
+        let fpu_state = VirtAddr::new(signal_frame.fpu_state);
+
+        if uaccess::copy_from_user(&mut bytes, fpu_state).is_ok() {
+            fpu_storage.load_from(&bytes);
+        }
+
+        fpu_storage.restore();
+    }
 
     let result = signal_frame.frame.scratch.rax;
     *stack = signal_frame.frame;
@@ -0,0 +1,318 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! ACPI S3 (suspend-to-RAM) support.
+//!
+//! The memory stays powered while the system is suspended, but the CPU, the interrupt
+//! controllers, the timers and the devices lose their state. The system is suspended by:
+//!
+//! 1. Freezing userland. Only a single CPU is supported, so running the rest with the
+//!    interrupts disabled is enough to stop every other task.
+//! 2. Quiescing the devices (see [`device::suspend_all`]) and saving the APIC and CPU
+//!    state.
+//! 3. Setting the firmware waking vector in the FACS to a real mode trampoline (see
+//!    `wakeup.asm`), which switches back to long mode and jumps to [`x86_64_resume`].
+//! 4. Entering S3 through the AML subsystem, which writes the sleep type to the PM1
+//!    control registers described by the FADT.
+//!
+//! On wake, the saved state is restored in the reverse order. The drivers that
+//! reinitialize their devices on resume are NVMe and the PS/2 keyboard; the AHCI and IDE
+//! disks do not work after a resume yet.
+
+use crate::acpi::{aml, fadt, get_acpi_table};
+use crate::drivers::device;
+use crate::mem::paging::{PhysAddr, FRAME_ALLOCATOR};
+
+use super::controlregs::{self, Cr0Flags, Cr4Flags};
+use super::features::cpu_has;
+use super::fpu::FpuState;
+use super::interrupts::{self, INTERRUPT_CONTROLLER};
+use super::{apic, gdt, io, syscall, time};
+
+extern "C" {
+    fn x86_64_suspend_lowlevel(context: *mut SuspendContext, enter: extern "C" fn() -> u64) -> u64;
+    fn x86_64_resume();
+
+    static x86_64_wakeup_start: u8;
+    static x86_64_wakeup_end: u8;
+    static x86_64_wakeup_cr3: u8;
+    static x86_64_wakeup_efer: u8;
+    static x86_64_wakeup_context: u8;
+    static x86_64_wakeup_entry: u8;
+}
+
+const IA32_PAT: u32 = 0x277;
+
+/// The trampoline is entered in real mode, so it has to be below 1MiB.
+const TRAMPOLINE_LIMIT: u64 = 0x100000;
+/// The page tables are loaded in protected mode, so they have to be below 4GiB.
+const PAGE_TABLE_LIMIT: u64 = 0x100000000;
+
+const PAGE_PRESENT: u64 = 1;
+const PAGE_WRITABLE: u64 = 1 << 1;
+const PAGE_HUGE: u64 = 1 << 7;
+
+const EFER_LMA: u64 = 1 << 10;
+
+#[derive(Debug)]
+pub enum SuspendError {
+    /// Suspending is only supported with a single CPU online.
+    SmpNotSupported,
+    /// The FACS (which holds the waking vector) was not found.
+    NoFacs,
+    /// The trampoline or its page tables could not be allocated.
+    OutOfMemory,
+    /// The firmware did not put the system to sleep.
+    Failed,
+}
+
+/// The registers saved by `x86_64_suspend_lowlevel` and restored by `x86_64_resume`.
+/// The layout has to match the offsets in `wakeup.asm`.
+#[derive(Default)]
+#[repr(C)]
+struct SuspendContext {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rip: u64,
+    cr3: u64,
+}
+
+/// The CPU state that is not saved by `x86_64_suspend_lowlevel`.
+struct CpuState {
+    cr0: Cr0Flags,
+    cr4: Cr4Flags,
+    gs_base: u64,
+    kernel_gs_base: u64,
+    fs_base: u64,
+    tsc_aux: u64,
+    pat: u64,
+    fpu: FpuState,
+}
+
+impl CpuState {
+    fn save() -> Self {
+        unsafe {
+            Self {
+                cr0: controlregs::read_cr0(),
+                cr4: controlregs::read_cr4(),
+                gs_base: io::rdmsr(io::IA32_GS_BASE),
+                kernel_gs_base: io::rdmsr(io::IA32_KERNEL_GSBASE),
+                fs_base: io::rdmsr(io::IA32_FS_BASE),
+                tsc_aux: cpu_has!(Rdtscp)
+                    .then(|| io::rdmsr(io::IA32_TSC_AUX))
+                    .unwrap_or(0),
+                pat: io::rdmsr(IA32_PAT),
+                fpu: FpuState::from_current(),
+            }
+        }
+    }
+
+    /// Restores the CPU state. The GS base is restored first as accessing the per-CPU
+    /// data requires it.
+    fn restore(&self) {
+        unsafe {
+            io::wrmsr(io::IA32_GS_BASE, self.gs_base);
+            io::wrmsr(io::IA32_KERNEL_GSBASE, self.kernel_gs_base);
+            io::wrmsr(io::IA32_FS_BASE, self.fs_base);
+            io::wrmsr(IA32_PAT, self.pat);
+
+            if cpu_has!(Rdtscp) {
+                io::wrmsr(io::IA32_TSC_AUX, self.tsc_aux);
+            }
+
+            controlregs::write_cr4(self.cr4);
+            controlregs::write_cr0(self.cr0);
+        }
+
+        super::fpu::init(false);
+
+        gdt::reload();
+        interrupts::reload_idt();
+        syscall::init();
+    }
+}
+
+/// The real mode trampoline and its page tables, allocated for a single suspend.
+struct Trampoline {
+    code: PhysAddr,
+    tables: [PhysAddr; 3],
+}
+
+impl Trampoline {
+    fn new(context: &SuspendContext) -> Result<Self, SuspendError> {
+        let allocate = |limit| {
+            FRAME_ALLOCATOR
+                .allocate_contiguous(0, Some(PhysAddr::new(limit)))
+                .ok_or(SuspendError::OutOfMemory)
+        };
+
+        let code = allocate(TRAMPOLINE_LIMIT)?;
+        let tables = [
+            allocate(PAGE_TABLE_LIMIT)?,
+            allocate(PAGE_TABLE_LIMIT)?,
+            allocate(PAGE_TABLE_LIMIT)?,
+        ];
+
+        let this = Self { code, tables };
+
+        unsafe {
+            let start = &x86_64_wakeup_start as *const u8;
+            let size = &x86_64_wakeup_end as *const u8 as usize - start as usize;
+
+            let dest = code.as_hhdm_virt().as_mut_ptr::<u8>();
+            core::ptr::copy_nonoverlapping(start, dest, size);
+
+            let patch = |label: &u8, value: u64| {
+                let offset = label as *const u8 as usize - start as usize;
+                (dest.add(offset) as *mut u64).write_unaligned(value);
+            };
+
+            patch(&x86_64_wakeup_cr3, this.build_page_tables(context.cr3));
+            patch(&x86_64_wakeup_efer, io::rdmsr(io::IA32_EFER) & !EFER_LMA);
+            patch(&x86_64_wakeup_context, context as *const _ as u64);
+            patch(&x86_64_wakeup_entry, x86_64_resume as u64);
+        }
+
+        Ok(this)
+    }
+
+    /// Builds the page tables used by the trampoline to enter long mode: the kernel half
+    /// is shared with the current page tables and the 2MiB page containing the
+    /// trampoline is identity mapped. Returns the address of the PML4.
+    unsafe fn build_page_tables(&self, cr3: u64) -> u64 {
+        let table = |address: PhysAddr| {
+            let table = address.as_hhdm_virt().as_mut_ptr::<u64>();
+            core::ptr::write_bytes(table, 0, 512);
+            core::slice::from_raw_parts_mut(table, 512)
+        };
+
+        let [pml4, pdpt, pd] = self.tables;
+        let kernel = PhysAddr::new(cr3 & 0x000f_ffff_ffff_f000).as_hhdm_virt();
+        let kernel = core::slice::from_raw_parts(kernel.as_ptr::<u64>(), 512);
+
+        // The trampoline is below 1MiB, so it is covered by the first entry of each
+        // table.
+        let entries = table(pml4);
+        entries[256..].copy_from_slice(&kernel[256..]);
+        entries[0] = pdpt.as_u64() | PAGE_PRESENT | PAGE_WRITABLE;
+
+        table(pdpt)[0] = pd.as_u64() | PAGE_PRESENT | PAGE_WRITABLE;
+        table(pd)[0] = PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE;
+
+        pml4.as_u64()
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR.deallocate_contiguous(self.code, 0);
+
+        for table in self.tables {
+            FRAME_ALLOCATOR.deallocate_contiguous(table, 0);
+        }
+    }
+}
+
+/// Sets the waking vector in the FACS to the real mode address of the trampoline.
+fn set_waking_vector(address: PhysAddr) -> Result<(), SuspendError> {
+    let facs = get_acpi_table()
+        .lookup_entry(fadt::SIGNATURE)
+        .and_then(|header| {
+            let fadt: &'static fadt::Fadt = unsafe { header.as_ref() };
+            fadt.facs_address()
+        })
+        .ok_or(SuspendError::NoFacs)?;
+
+    let facs = unsafe { &mut *facs.as_hhdm_virt().as_mut_ptr::<fadt::Facs>() };
+
+    facs.firmware_waking_vector = address.as_u64() as u32;
+    facs.x_firmware_waking_vector = 0;
+
+    Ok(())
+}
+
+/// Enters S3. Called by `x86_64_suspend_lowlevel` after the registers were saved, so
+/// it only returns if entering S3 failed.
+extern "C" fn enter_sleep() -> u64 {
+    unsafe { asm!("wbinvd", options(nomem, nostack)) }
+
+    aml::get_subsystem().enter_state(aml::SleepState::S3);
+    1
+}
+
+/// Suspends the system to RAM and returns after it was woken up.
+pub fn suspend() -> Result<(), SuspendError> {
+    if apic::get_cpu_count() > 1 {
+        return Err(SuspendError::SmpNotSupported);
+    }
+
+    log::info!("suspend: entering S3");
+
+    let rflags = controlregs::read_rflags();
+    unsafe { interrupts::disable_interrupts() };
+
+    let mut context = SuspendContext {
+        cr3: controlregs::read_cr3_raw(),
+        ..Default::default()
+    };
+
+    let result = Trampoline::new(&context).and_then(|trampoline| {
+        set_waking_vector(trampoline.code)?;
+
+        device::suspend_all();
+        let apic_state = apic::suspend();
+        let cpu = CpuState::save();
+
+        time::suspend();
+
+        let resumed = unsafe { x86_64_suspend_lowlevel(&mut context, enter_sleep) } == 0;
+
+        if resumed {
+            cpu.restore();
+            apic::resume(&apic_state);
+
+            // The firmware reinitializes the PIC, so it has to be masked again.
+            INTERRUPT_CONTROLLER.switch_to_apic();
+
+            time::resume();
+            crate::watchdog::touch_all();
+        }
+
+        cpu.fpu.restore();
+        device::resume_all();
+
+        if resumed {
+            log::info!("suspend: resumed from S3");
+            Ok(())
+        } else {
+            Err(SuspendError::Failed)
+        }
+    });
+
+    if rflags.contains(controlregs::RFlags::INTERRUPT_FLAG) {
+        unsafe { interrupts::enable_interrupts() };
+    }
+
+    result
+}
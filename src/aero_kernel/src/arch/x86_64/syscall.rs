@@ -32,11 +32,13 @@ fn arch_prctl(command: usize, address: usize) -> Result<usize, SyscallError> {
             Ok(0x00)
         },
 
-        ARCH_GET_FS => Ok(scheduler::get_scheduler()
-            .current_task()
-            .arch_task()
-            .get_fs_base()
-            .as_u64() as usize),
+        ARCH_GET_FS => unsafe {
+            Ok(scheduler::get_scheduler()
+                .current_task()
+                .arch_task()
+                .get_fs_base()
+                .as_u64() as usize)
+        },
 
         ARCH_SET_GS => unsafe {
             let _guard = IrqGuard::new();
@@ -81,6 +83,9 @@ pub(super) extern "sysv64" fn x86_64_check_sysenter(stack: &mut InterruptErrorSt
 pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
     let stack = &mut stack.stack;
 
+    // Let the tracer inspect (and modify) the syscall before it is dispatched.
+    super::ptrace::syscall_entry_stop(stack);
+
     let syscall_number = stack.scratch.rax as usize; // syscall number
     let a = stack.scratch.rdi as usize; // argument 1
     let b = stack.scratch.rsi as usize; // argument 2
@@ -89,10 +94,21 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
     let e = stack.scratch.r8 as usize; // argument 5
     let f = stack.scratch.r9 as usize; // argument 6
 
+    // The syscall filters are checked before any syscall is dispatched (including the
+    // arch-specific ones below), so the arguments of a rejected syscall are never touched.
+    if let Some(result) = crate::userland::seccomp::check(syscall_number) {
+        let result = super::ptrace::syscall_exit_stop(stack, syscall_number, result);
+
+        stack.scratch.rax = result as _;
+        return;
+    }
+
     match syscall_number {
         // handle arch-specific syscalls (`sigreturn` and `arch_prctl`):
         aero_syscall::prelude::SYS_SIGRETURN => {
             let result = super::signals::sigreturn(stack);
+            let result = super::ptrace::syscall_exit_stop(stack, syscall_number, result);
+
             stack.scratch.rax = result as u64;
             return;
         }
@@ -100,6 +116,8 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
         aero_syscall::prelude::SYS_ARCH_PRCTL => {
             let result = self::arch_prctl(a, b);
             let result_usize = aero_syscall::syscall_result_as_usize(result);
+            let result_usize =
+                super::ptrace::syscall_exit_stop(stack, syscall_number, result_usize);
 
             stack.scratch.rax = result_usize as _;
             return;
@@ -110,6 +128,7 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
     }
 
     let result_usize = crate::syscall::generic_do_syscall(syscall_number, a, b, c, d, e, f);
+    let result_usize = super::ptrace::syscall_exit_stop(stack, syscall_number, result_usize);
 
     super::signals::syscall_check_signals(result_usize as isize, stack);
     stack.scratch.rax = result_usize as _;
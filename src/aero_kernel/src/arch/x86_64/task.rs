@@ -47,6 +47,8 @@ use crate::syscall::ExecArgs;
 use crate::userland::vm::Vm;
 use crate::utils::StackHelper;
 
+use super::features::cpu_has;
+use super::fpu::FpuState;
 use super::{controlregs, io};
 
 use crate::mem::AddressSpace;
@@ -74,7 +76,11 @@ pub enum AuxvType {
     AtPhdr = 3,
     AtPhEnt = 4,
     AtPhNum = 5,
+    AtPageSz = 6,
+    AtBase = 7,
     AtEntry = 9,
+    AtRandom = 25,
+    AtAeroVdso = aero_syscall::vdso::AT_AERO_VDSO as u64,
 }
 
 /// Returns the first address outside the user range.
@@ -106,11 +112,12 @@ pub fn userland_last_address() -> VirtAddr {
     })
 }
 
-const USERLAND_STACK_SIZE: u64 = 0x64000;
+/// The bounds of the size of the userland stack, which is set by `RLIMIT_STACK`.
+const USERLAND_STACK_MIN_SIZE: u64 = 0x64000;
+const USERLAND_STACK_MAX_SIZE: u64 = 0x10000000;
 
 //(1 << 47) - (Size4KiB::SIZE * 2)
 const USERLAND_STACK_TOP: VirtAddr = VirtAddr::new(0x7fffffffe000);
-const USERLAND_STACK_BOTTOM: VirtAddr = USERLAND_STACK_TOP.const_sub_u64(USERLAND_STACK_SIZE);
 
 pub struct ArchTask {
     context: Unique<Context>,
@@ -121,6 +128,10 @@ pub struct ArchTask {
 
     fs_base: VirtAddr,
     gs_base: VirtAddr,
+
+    /// The saved FPU state of the task. Kernel tasks do not use the FPU (see the
+    /// [`fpu`](super::fpu) module).
+    fpu_storage: Option<FpuState>,
 }
 
 impl ArchTask {
@@ -136,6 +147,8 @@ impl ArchTask {
 
             fs_base: VirtAddr::zero(),
             gs_base: VirtAddr::zero(),
+
+            fpu_storage: None,
         }
     }
 
@@ -178,6 +191,8 @@ impl ArchTask {
 
             fs_base: VirtAddr::zero(),
             gs_base: VirtAddr::zero(),
+
+            fpu_storage: None,
         }
     }
 
@@ -185,8 +200,11 @@ impl ArchTask {
         &self,
         entry: usize,
         usr_stack: usize,
+        tls: usize,
     ) -> Result<Self, MapToError<Size4KiB>> {
-        log::trace!("ArchTask::clone_process(entry={entry:#x}, stack={usr_stack:#x})");
+        log::trace!(
+            "ArchTask::clone_process(entry={entry:#x}, stack={usr_stack:#x}, tls={tls:#x})"
+        );
 
         assert!(self.user, "cannot clone a kernel task");
 
@@ -227,9 +245,17 @@ impl ArchTask {
             address_space,
             user: true,
 
-            // The FS and GS bases are inherited from the parent process.
-            fs_base: self.fs_base.clone(),
+            // The FS base is set to the provided TLS pointer (if any), else the FS and GS
+            // bases are inherited from the parent process.
+            fs_base: if tls != 0 {
+                VirtAddr::new(tls as u64)
+            } else {
+                read_fs_base()
+            },
             gs_base: self.gs_base.clone(),
+
+            // The FPU state is inherited from the parent process.
+            fpu_storage: Some(FpuState::from_current()),
         })
     }
 
@@ -279,8 +305,11 @@ impl ArchTask {
             user: true,
 
             // The FS and GS bases are inherited from the parent process.
-            fs_base: self.fs_base.clone(),
+            fs_base: read_fs_base(),
             gs_base: self.gs_base.clone(),
+
+            // The FPU state is inherited from the parent process.
+            fpu_storage: Some(FpuState::from_current()),
         })
     }
 
@@ -291,6 +320,7 @@ impl ArchTask {
 
         argv: Option<ExecArgs>,
         envv: Option<ExecArgs>,
+        stack_size: u64,
     ) -> Result<(), MapToError<Size4KiB>> {
         let address_space = if self.user {
             self.unref_pt();
@@ -306,16 +336,24 @@ impl ArchTask {
         // a kernel task can only execute a user executable
         self.user = true;
 
-        // mmap the userland stack...
+        // mmap the userland stack. Its pages are only allocated once the stack grows into
+        // them, so the whole limit is reserved up front.
+        let stack_size = align_up(
+            stack_size.clamp(USERLAND_STACK_MIN_SIZE, USERLAND_STACK_MAX_SIZE),
+            Size4KiB::SIZE,
+        );
+
         vm.mmap(
-            USERLAND_STACK_BOTTOM,
-            USERLAND_STACK_SIZE as usize,
+            USERLAND_STACK_TOP - stack_size,
+            stack_size as usize,
             MMapProt::PROT_WRITE | MMapProt::PROT_READ,
             MMapFlags::MAP_FIXED | MMapFlags::MAP_PRIVATE | MMapFlags::MAP_ANONYOMUS,
             0,
             None,
         );
 
+        let vdso_base = crate::userland::vdso::map(vm);
+
         vm.log();
 
         address_space.switch(); // Perform the address space switch
@@ -323,8 +361,16 @@ impl ArchTask {
         self.context = Unique::dangling();
         self.address_space = address_space; // Update the address space reference
 
-        self.fs_base = VirtAddr::zero();
-        self.gs_base = VirtAddr::zero();
+        unsafe {
+            self.set_fs_base(VirtAddr::zero());
+            self.set_gs_base(VirtAddr::zero());
+        }
+
+        // Start the program with the FPU in its initial configuration.
+        let fpu_storage = FpuState::new();
+        fpu_storage.restore();
+
+        self.fpu_storage = Some(fpu_storage);
 
         extern "C" {
             fn jump_userland_exec(stack: VirtAddr, rip: VirtAddr, rflags: u64);
@@ -344,6 +390,12 @@ impl ArchTask {
             .argv
             .map(|argv| argp = argv.push_into_stack(&mut stack));
 
+        // 16 random bytes, used by libc for the stack protector and pointer guard values.
+        let random_ptr = unsafe {
+            stack.write(random_bytes());
+            stack.top()
+        };
+
         stack.align_down();
 
         let size = envp.len() + 1 + argp.len() + 1 + 1;
@@ -356,20 +408,32 @@ impl ArchTask {
 
         let p2_header = loaded_binary.elf.header.pt2;
 
+        let mut auxv: Vec<(AuxvType, usize)> = alloc::vec![
+            (AuxvType::AtPhdr, loaded_binary.phdr.as_u64() as usize),
+            (AuxvType::AtPhEnt, p2_header.ph_entry_size() as usize),
+            (AuxvType::AtPhNum, p2_header.ph_count() as usize),
+            (AuxvType::AtPageSz, Size4KiB::SIZE as usize),
+            (
+                AuxvType::AtBase,
+                loaded_binary
+                    .interp_base
+                    .map_or(0, |base| base.as_u64() as usize),
+            ),
+            (
+                AuxvType::AtEntry,
+                loaded_binary.program_entry.as_u64() as usize
+            ),
+            (AuxvType::AtRandom, random_ptr as usize),
+        ];
+
+        if let Some(vdso_base) = vdso_base {
+            auxv.push((AuxvType::AtAeroVdso, vdso_base.as_u64() as usize));
+        }
+
+        // Each auxiliary vector entry is 16 bytes, so the stack alignment is preserved.
         unsafe {
-            let hdr: [(AuxvType, usize); 4] = [
-                (
-                    AuxvType::AtPhdr,
-                    (p2_header.ph_offset() + loaded_binary.base_addr.as_u64()) as usize,
-                ),
-                (AuxvType::AtPhEnt, p2_header.ph_entry_size() as usize),
-                (AuxvType::AtPhNum, p2_header.ph_count() as usize),
-                (AuxvType::AtEntry, p2_header.entry_point() as usize),
-            ];
-
-            stack.write(0usize); // Make it 16 bytes aligned
-            stack.write(AuxvType::AtNull);
-            stack.write(hdr);
+            stack.write((AuxvType::AtNull, 0usize));
+            stack.write_slice(auxv.as_slice());
         }
 
         // struct ExecStackData {
@@ -385,6 +449,7 @@ impl ArchTask {
             stack.write(argp.len());
         }
 
+        core::mem::drop(auxv);
         core::mem::drop(envp);
         core::mem::drop(argp);
 
@@ -425,6 +490,11 @@ impl ArchTask {
             .expect("dealloc: failed to unref the page table");
     }
 
+    /// Returns whether this is a userland task.
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+
     /// Deallocates the architecture-specific task resources. This function is called
     /// when the process is turned into a zombie.
     pub fn dealloc(&mut self) {
@@ -458,9 +528,14 @@ impl ArchTask {
         self.gs_base = base;
     }
 
-    /// Returns the saved FS base for this task.
-    pub fn get_fs_base(&self) -> VirtAddr {
-        self.fs_base
+    /// Returns the FS base for this task.
+    ///
+    /// ## Safety
+    /// This function **must** be called by the process that this [`ArchTask`] instance
+    /// belongs to, since userland is able to update the FS base register directly
+    /// (using `wrfsbase`) and the saved FS base is only updated on a switch.
+    pub unsafe fn get_fs_base(&self) -> VirtAddr {
+        read_fs_base()
     }
 
     /// Sets the FS base to the provided `base`.
@@ -469,12 +544,45 @@ impl ArchTask {
     /// This function **must** be called by the process that this [`ArchTask`] instance
     /// belongs to. This is required since we also update the FS base register with the
     /// `base` immediately (not waiting for a switch).
+    /// Returns the saved FPU state of the task, or [`None`] for kernel tasks.
+    pub fn fpu_storage_mut(&mut self) -> Option<&mut FpuState> {
+        self.fpu_storage.as_mut()
+    }
+
     pub unsafe fn set_fs_base(&mut self, base: VirtAddr) {
-        io::wrmsr(io::IA32_FS_BASE, base.as_u64());
+        write_fs_base(base);
         self.fs_base = base;
     }
 }
 
+/// Returns 16 random bytes for the `AT_RANDOM` auxiliary vector entry.
+fn random_bytes() -> [u8; 16] {
+    let mut result = [0u8; 16];
+
+    crate::random::fill_bytes(&mut result);
+    result
+}
+
+/// Returns the FS base of the current CPU.
+fn read_fs_base() -> VirtAddr {
+    unsafe {
+        if cpu_has!(Fsgsbase) {
+            VirtAddr::new(io::rdfsbase())
+        } else {
+            VirtAddr::new(io::rdmsr(io::IA32_FS_BASE))
+        }
+    }
+}
+
+/// Updates the FS base of the current CPU to the provided `base`.
+unsafe fn write_fs_base(base: VirtAddr) {
+    if cpu_has!(Fsgsbase) {
+        io::wrfsbase(base.as_u64());
+    } else {
+        io::wrmsr(io::IA32_FS_BASE, base.as_u64());
+    }
+}
+
 /// Check out the module level documentation for more information.
 pub fn arch_task_spinup(from: &mut ArchTask, to: &ArchTask) {
     extern "C" {
@@ -487,8 +595,22 @@ pub fn arch_task_spinup(from: &mut ArchTask, to: &ArchTask) {
         super::gdt::get_task_state_segement().rsp[0] = kstackp;
         io::wrmsr(io::IA32_SYSENTER_ESP, kstackp);
 
-        // switch to the new FS base.
-        io::wrmsr(io::IA32_FS_BASE, to.fs_base.as_u64());
+        // Save the FS base of the previous task, since it could have been updated by
+        // userland (using `wrfsbase`) and switch to the new FS base.
+        if from.user {
+            from.fs_base = read_fs_base();
+        }
+
+        write_fs_base(to.fs_base);
+
+        // Switch the FPU state eagerly.
+        if let Some(fpu_storage) = from.fpu_storage.as_mut() {
+            fpu_storage.save();
+        }
+
+        if let Some(fpu_storage) = to.fpu_storage.as_ref() {
+            fpu_storage.restore();
+        }
 
         // update the swap GS target to point to the new GS base.
         io::wrmsr(io::IA32_KERNEL_GSBASE, to.gs_base.as_u64());
@@ -21,38 +21,89 @@
 //! a prescaler and 3 independent frequency dividers and it is used to create time intervals
 //! and calculate *estimate* time since epoch.
 //!
+//! The monotonic clock is read from the first available clocksource out of the invariant
+//! TSC, the HPET (if its counter is 64 bits wide) and the timer tick count.
+//!
 //! **Notes**: <https://wiki.osdev.org/Programmable_Interval_Timer>
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use aero_syscall::TimeSpec;
 
-use super::apic;
+use super::{apic, hpet, tsc};
 
 use crate::arch::interrupts;
-use crate::arch::interrupts::InterruptStack;
+use crate::arch::interrupts::{InterruptStack, IrqFlags};
 
 use crate::arch::io;
-use crate::utils::sync::Mutex;
+use crate::drivers::rtc;
 
 const PIT_FREQUENCY_HZ: usize = 1000;
 pub const PIT_DIVIDEND: usize = 1193182;
 
 static UPTIME_RAW: AtomicUsize = AtomicUsize::new(0);
-static UPTIME_SEC: AtomicUsize = AtomicUsize::new(0);
 
 pub static EPOCH: AtomicUsize = AtomicUsize::new(usize::MAX);
-pub static REALTIME_CLOCK: Mutex<aero_syscall::TimeSpec> = Mutex::new(aero_syscall::TimeSpec {
-    tv_sec: 0,
-    tv_nsec: 0,
-});
 
+/// The offset of the realtime clock from the monotonic clock, in nanoseconds. The
+/// realtime clock is only stored as this offset so that it advances with the monotonic
+/// clock.
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// The (wrapping) offset added to the clocksource so that the monotonic clock keeps
+/// going up across a suspend to RAM, which resets the TSC and the HPET counter.
+static MONOTONIC_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// The monotonic clock (in nanoseconds) and the RTC (in seconds) when the system was
+/// suspended.
+static SUSPEND_TIME: AtomicU64 = AtomicU64::new(0);
+static SUSPEND_RTC: AtomicU64 = AtomicU64::new(0);
+
+fn timespec_from_nanoseconds(nanoseconds: u64) -> TimeSpec {
+    TimeSpec {
+        tv_sec: (nanoseconds / 1000000000) as isize,
+        tv_nsec: (nanoseconds % 1000000000) as isize,
+    }
+}
+
+/// Returns the time elapsed since boot, in seconds.
 pub fn get_uptime_ticks() -> usize {
-    UPTIME_SEC.load(Ordering::SeqCst)
+    get_monotonic_clock().tv_sec as usize
 }
 
+/// Returns the time elapsed since the Unix epoch.
 pub fn get_realtime_clock() -> TimeSpec {
-    REALTIME_CLOCK.lock_irq().clone()
+    let offset = REALTIME_OFFSET.load(Ordering::SeqCst);
+    timespec_from_nanoseconds(get_monotonic_nanoseconds() + offset)
+}
+
+/// Sets the time elapsed since the Unix epoch.
+pub fn set_realtime_clock(time: &TimeSpec) {
+    let nanoseconds = time.tv_sec as u64 * 1000000000 + time.tv_nsec as u64;
+    let offset = nanoseconds.saturating_sub(get_monotonic_nanoseconds());
+
+    REALTIME_OFFSET.store(offset, Ordering::SeqCst);
+}
+
+fn get_monotonic_nanoseconds() -> u64 {
+    let offset = MONOTONIC_OFFSET.load(Ordering::SeqCst);
+    get_clocksource_nanoseconds().wrapping_add(offset)
+}
+
+fn get_clocksource_nanoseconds() -> u64 {
+    if let Some(nanoseconds) = tsc::nanoseconds() {
+        nanoseconds
+    } else if let Some(hpet) = hpet::get().filter(|hpet| hpet.is_64bit()) {
+        hpet.nanoseconds()
+    } else {
+        let ticks = UPTIME_RAW.load(Ordering::SeqCst);
+        (ticks * (1000000000 / PIT_FREQUENCY_HZ)) as u64
+    }
+}
+
+/// Returns the time elapsed since boot.
+pub fn get_monotonic_clock() -> TimeSpec {
+    timespec_from_nanoseconds(get_monotonic_nanoseconds())
 }
 
 /// Returns the current amount of PIT ticks.
@@ -87,45 +138,71 @@ pub fn set_frequency(frequency: usize) {
 }
 
 fn pit_irq_handler(_stack: &mut InterruptStack) {
-    {
-        let interval = aero_syscall::TimeSpec {
-            tv_sec: 0,
-            tv_nsec: (1000000000 / PIT_FREQUENCY_HZ) as isize,
-        };
+    let value = UPTIME_RAW.fetch_add(1, Ordering::Relaxed); // Increment uptime raw ticks.
+    crate::userland::vdso::update_clocks(&get_realtime_clock(), &get_monotonic_clock());
+    crate::timer::run_expired();
 
-        let mut this = REALTIME_CLOCK.lock_irq();
+    if value % PIT_FREQUENCY_HZ == 0 {
+        crate::syscall::check_timers();
+    }
+}
 
-        if this.tv_nsec + interval.tv_nsec > 999999999 {
-            let diff = (this.tv_nsec + interval.tv_nsec) - 1000000000;
+/// Starts the timer tick.
+fn start_tick() {
+    // The tick is driven by the HPET when it can stand in for the PIT on IRQ 0 and by
+    // the PIT otherwise.
+    match hpet::get() {
+        Some(hpet) if hpet.can_replace_pit() => hpet.start_periodic(PIT_FREQUENCY_HZ as u64),
+        _ => set_frequency(PIT_FREQUENCY_HZ),
+    }
+}
 
-            this.tv_nsec = diff;
-            this.tv_sec += 1;
-        } else {
-            this.tv_nsec += interval.tv_nsec
-        }
+/// Records the time the system is suspended to RAM at.
+pub fn suspend() {
+    SUSPEND_TIME.store(get_monotonic_nanoseconds(), Ordering::SeqCst);
+    SUSPEND_RTC.store(rtc::read(), Ordering::SeqCst);
+}
 
-        this.tv_sec += interval.tv_sec;
+/// Restarts the clocksources and the timer tick after a suspend to RAM. The time spent
+/// suspended is measured with the RTC and added to the monotonic clock (and so to the
+/// realtime clock).
+pub fn resume() {
+    if let Some(hpet) = hpet::get() {
+        hpet.resume();
     }
 
-    let value = UPTIME_RAW.fetch_add(1, Ordering::Relaxed); // Increment uptime raw ticks.
+    tsc::resume();
+    start_tick();
 
-    if value % PIT_FREQUENCY_HZ == 0 {
-        UPTIME_SEC.fetch_add(1, Ordering::Relaxed); // Increment uptime seconds
-        crate::syscall::check_timers();
-    }
+    let slept = rtc::read().saturating_sub(SUSPEND_RTC.load(Ordering::SeqCst));
+    let now = SUSPEND_TIME.load(Ordering::SeqCst) + slept * 1000000000;
+
+    MONOTONIC_OFFSET.store(
+        now.wrapping_sub(get_clocksource_nanoseconds()),
+        Ordering::SeqCst,
+    );
+
+    log::info!("time: resumed after {slept} seconds");
 }
 
 /// This function is responsible for initializing the PIT chip and setting
 /// up the IRQ.
 pub fn init() {
+    hpet::init();
     apic::get_local_apic().timer_calibrate();
+    tsc::init();
 
-    REALTIME_CLOCK.lock().tv_sec = EPOCH.load(Ordering::SeqCst) as _;
+    // The boot time reported by the bootloader is used instead of the RTC if the RTC is
+    // behind it (eg. its battery is dead).
+    let epoch = core::cmp::max(rtc::read(), EPOCH.load(Ordering::SeqCst) as u64);
 
-    set_frequency(PIT_FREQUENCY_HZ);
+    set_realtime_clock(&TimeSpec {
+        tv_sec: epoch as isize,
+        tv_nsec: 0,
+    });
 
-    let pit_vector = interrupts::allocate_vector();
-    interrupts::register_handler(pit_vector, pit_irq_handler);
+    start_tick();
 
-    apic::io_apic_setup_legacy_irq(0, pit_vector, 1); // Set up the IRQ.
+    interrupts::request_legacy_irq(0, "timer", pit_irq_handler, IrqFlags::empty())
+        .expect("time: failed to request the timer IRQ");
 }
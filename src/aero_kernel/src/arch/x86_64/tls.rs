@@ -27,12 +27,14 @@
 
 use core::alloc::Layout;
 
-use alloc::alloc::alloc_zeroed;
 use alloc::vec::Vec;
 
+use super::controlregs::{self, Cr4Flags};
+use super::features::cpu_has;
 use super::gdt::*;
 use super::io;
 
+use crate::mem::numa;
 use crate::utils::sync::Mutex;
 
 use raw_cpuid::FeatureInfo;
@@ -135,7 +137,7 @@ pub fn init(cpuid: usize) {
     unsafe {
         let kpcr_layout = Layout::new::<Kpcr>();
 
-        let kpcr_ptr = alloc_zeroed(kpcr_layout) as *mut Kpcr;
+        let kpcr_ptr = numa::alloc_percpu(cpuid, kpcr_layout) as *mut Kpcr;
         io::wrmsr(io::IA32_GS_BASE, kpcr_ptr as u64);
     }
 
@@ -143,6 +145,22 @@ pub fn init(cpuid: usize) {
 
     let cpuid = raw_cpuid::CpuId::new();
 
+    // The vDSO uses `rdtscp` to get the CPU ID of the calling thread.
+    if cpu_has!(Rdtscp) {
+        unsafe { io::wrmsr(io::IA32_TSC_AUX, get_cpuid() as u64) }
+    }
+
+    // Enable the FS and GS base instructions, so the FS base can be switched without
+    // the overhead of `wrmsr`.
+    if cpu_has!(Fsgsbase) {
+        unsafe {
+            let mut cr4 = controlregs::read_cr4();
+
+            cr4.insert(Cr4Flags::FSGSBASE);
+            controlregs::write_cr4(cr4);
+        }
+    }
+
     let features = cpuid
         .get_feature_info()
         .map(|cpu_features| {
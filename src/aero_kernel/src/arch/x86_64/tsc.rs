@@ -0,0 +1,128 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The TSC (Time Stamp Counter) counts the cycles of the CPU since reset. It is only used
+//! as a clocksource if it is invariant (runs at a constant rate in all of the ACPI P-, C-
+//! and T-states), in which case its frequency is calibrated against the HPET (or the PIT
+//! if there is no HPET).
+//!
+//! **Notes**: Intel SDM Volume 3, Section 17.17 "Time-Stamp Counter"
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::features::cpu_has;
+use super::{hpet, io, time};
+
+/// The amount of time the TSC is calibrated for, in milliseconds.
+const CALIBRATION_MS: u64 = 10;
+
+/// The frequency of the TSC in hertz, or zero if the TSC is not used as a clocksource.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The value of the TSC when it was calibrated.
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether the TSC runs at a constant rate, regardless of the power state
+/// of the CPU.
+fn is_invariant() -> bool {
+    cpu_has!(InvariantTsc)
+}
+
+/// Measures the frequency of the TSC against the HPET.
+fn calibrate_hpet(hpet: &hpet::Hpet) -> u64 {
+    let start_ns = hpet.nanoseconds();
+    let start = io::rdtsc();
+
+    hpet.busy_wait(CALIBRATION_MS * 1_000_000);
+
+    let elapsed = io::rdtsc().wrapping_sub(start);
+    let elapsed_ns = hpet.nanoseconds() - start_ns;
+
+    (elapsed as u128 * 1_000_000_000 / elapsed_ns as u128) as u64
+}
+
+/// Measures the frequency of the TSC against the PIT.
+fn calibrate_pit() -> u64 {
+    let pit_ticks = (time::PIT_DIVIDEND as u64 * CALIBRATION_MS / 1000) as u16;
+
+    time::set_reload_value(0xffff);
+
+    let initial_pit_tick = time::get_current_count();
+    let start = io::rdtsc();
+
+    while initial_pit_tick - time::get_current_count() < pit_ticks {
+        core::hint::spin_loop();
+    }
+
+    let final_pit_tick = time::get_current_count();
+    let elapsed = io::rdtsc().wrapping_sub(start);
+
+    elapsed * time::PIT_DIVIDEND as u64 / (initial_pit_tick - final_pit_tick) as u64
+}
+
+/// Returns the time elapsed since the TSC was calibrated in nanoseconds, or [`None`] if
+/// the TSC is not used as a clocksource.
+pub fn nanoseconds() -> Option<u64> {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+
+    if frequency == 0 {
+        return None;
+    }
+
+    // The TSC of a CPU that is not synchronized with the boot CPU, or that was reset
+    // across a suspend to RAM before `resume` ran, can be behind the base. Treat it as
+    // no time having elapsed rather than as a huge interval.
+    let elapsed = io::rdtsc().wrapping_sub(TSC_BASE.load(Ordering::Relaxed));
+    let elapsed = if (elapsed as i64) < 0 { 0 } else { elapsed };
+
+    Some((elapsed as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
+
+/// Returns the frequency of the TSC in hertz, or [`None`] if the TSC is not used as
+/// a clocksource.
+pub fn frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+/// Restarts the clocksource after a suspend to RAM, which resets the TSC.
+pub fn resume() {
+    TSC_BASE.store(io::rdtsc(), Ordering::SeqCst);
+}
+
+/// Calibrates the TSC and makes it the monotonic clocksource if it is invariant. Must
+/// be called after the HPET has been initialized and before the PIT is programmed as
+/// the timer tick.
+pub fn init() {
+    if !is_invariant() {
+        log::warn!("tsc: not invariant, not using it as a clocksource");
+        return;
+    }
+
+    let frequency = match hpet::get() {
+        Some(hpet) => calibrate_hpet(hpet),
+        None => calibrate_pit(),
+    };
+
+    log::info!("tsc: {} kHz", frequency / 1000);
+
+    TSC_BASE.store(io::rdtsc(), Ordering::SeqCst);
+    TSC_FREQUENCY.store(frequency, Ordering::SeqCst);
+}
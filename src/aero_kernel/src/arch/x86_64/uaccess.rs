@@ -0,0 +1,53 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The architecture specific part of the user memory access layer (see
+//! [`crate::mem::uaccess`]).
+
+extern "C" {
+    fn user_copy(dest: *mut u8, src: *const u8, len: usize) -> usize;
+
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+    static user_copy_fixup: u8;
+}
+
+/// Copies `len` bytes from `src` to `dest`, where either of them is a userland address.
+/// Returns the amount of bytes that were not copied because of a page fault.
+///
+/// ## Safety
+/// The kernel side of the copy must be valid for `len` bytes.
+pub unsafe fn copy_raw(dest: *mut u8, src: *const u8, len: usize) -> usize {
+    user_copy(dest, src, len)
+}
+
+/// Returns the address to resume the execution at, if the kernel page fault at `rip`
+/// occurred while copying to or from userland.
+pub fn fixup(rip: u64) -> Option<u64> {
+    // SAFETY: The symbols are only used for their address.
+    let (start, end, fixup) = unsafe {
+        (
+            &user_copy_start as *const u8 as u64,
+            &user_copy_end as *const u8 as u64,
+            &user_copy_fixup as *const u8 as u64,
+        )
+    };
+
+    (rip >= start && rip < end).then(|| fixup)
+}
@@ -0,0 +1,46 @@
+; Copyright (C) 2021-2022 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+bits 64
+
+global user_copy
+global user_copy_start
+global user_copy_end
+global user_copy_fixup
+
+; Copies `len` bytes from `src` to `dest`, where either of them is a userland address.
+;
+; If the copy faults on an address that cannot be mapped, the page fault handler resumes
+; the execution at `user_copy_fixup` (see `uaccess::fixup`), which returns the amount of
+; bytes that were not copied.
+;
+; Parameters: rdi = dest, rsi = src, rdx = len
+; Returns: rax = the amount of bytes that were not copied
+user_copy:
+    mov rcx, rdx
+
+user_copy_start:
+    rep movsb
+
+user_copy_end:
+    xor eax, eax
+    ret
+
+user_copy_fixup:
+    ; RCX contains the amount of bytes left to be copied when the fault occured.
+    mov rax, rcx
+    ret
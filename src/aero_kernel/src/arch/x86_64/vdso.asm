@@ -0,0 +1,126 @@
+; Copyright (C) 2021-2022 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; The vDSO image. This section is copied into its own frame by the kernel and mapped
+; into every user address space, right after the vvar page. So, all of the code in
+; here must be position independent and must only access the vvar page relative to
+; the start of the image. The layout must match the `VdsoHeader` and `VdsoData`
+; structures in `aero_syscall::vdso`.
+
+bits 64
+
+%define VVAR_SIZE           0x1000
+
+%define VVAR_SEQ            0x00
+%define VVAR_FLAGS          0x04
+%define VVAR_CLOCKS         0x08
+%define TIMESPEC_SIZE       0x10
+
+%define CLOCK_MAX           1   ; CLOCK_REALTIME and CLOCK_MONOTONIC are in the vvar page.
+%define SYS_GETTIME         30
+%define SYS_GETCPU          91
+
+%define VDSO_FLAG_RDTSCP    1
+
+global vdso_image_start
+global vdso_image_end
+
+section .vdso progbits alloc exec nowrite align=4096
+
+vdso_image_start:
+    dq 0x4f534456_4f524541  ; magic ("AEROVDSO")
+    dq 1                    ; version
+    dq vdso_clock_gettime - vdso_image_start
+    dq vdso_getcpu - vdso_image_start
+
+; fn vdso_clock_gettime(clock: usize, timespec: *mut TimeSpec) -> usize
+vdso_clock_gettime:
+    cmp rdi, CLOCK_MAX
+    ja .fallback
+
+    lea r8, [rel vdso_image_start - VVAR_SIZE]
+
+    ; r9 = &vvar.clocks[clock]
+    mov r9, rdi
+    shl r9, 4
+    lea r9, [r8 + r9 + VVAR_CLOCKS]
+
+.retry:
+    mov eax, dword [r8 + VVAR_SEQ]
+    test eax, 1             ; the kernel is updating the page
+    jnz .spin
+
+    mov rdx, qword [r9]
+    mov rcx, qword [r9 + 8]
+
+    ; x86 does not reorder loads with other loads, so we only need to make sure
+    ; that the sequence counter did not change.
+    cmp eax, dword [r8 + VVAR_SEQ]
+    jne .retry
+
+    mov qword [rsi], rdx
+    mov qword [rsi + 8], rcx
+
+    xor eax, eax
+    ret
+
+.spin:
+    pause
+    jmp .retry
+
+.fallback:
+    ; The arguments are already in the registers the kernel reads them from.
+    mov rax, SYS_GETTIME
+    syscall
+    ret
+
+; fn vdso_getcpu(cpu: *mut u32) -> usize
+;
+; The kernel stores the CPU ID in the `IA32_TSC_AUX` MSR if the CPU supports `rdtscp`,
+; otherwise it has to be asked for it.
+vdso_getcpu:
+    lea r8, [rel vdso_image_start - VVAR_SIZE]
+    test dword [r8 + VVAR_FLAGS], VDSO_FLAG_RDTSCP
+    jz .syscall
+
+    rdtscp
+
+.store:
+    test rdi, rdi
+    jz .done
+
+    mov dword [rdi], ecx
+
+.done:
+    xor eax, eax
+    ret
+
+.syscall:
+    mov rax, SYS_GETCPU
+    syscall
+
+    ; The errors are returned as negative values.
+    test rax, rax
+    js .error
+
+    mov ecx, eax
+    jmp .store
+
+.error:
+    ret
+
+vdso_image_end:
@@ -0,0 +1,199 @@
+; Copyright (C) 2021-2022 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; Suspend-to-RAM (ACPI S3) support. See `suspend.rs` for the high level description.
+
+bits 64
+
+global x86_64_suspend_lowlevel
+global x86_64_resume
+global x86_64_wakeup_start
+global x86_64_wakeup_end
+global x86_64_wakeup_cr3
+global x86_64_wakeup_efer
+global x86_64_wakeup_context
+global x86_64_wakeup_entry
+
+; The offsets of the fields of `SuspendContext`.
+%define CTX_RBX 0x00
+%define CTX_RBP 0x08
+%define CTX_R12 0x10
+%define CTX_R13 0x18
+%define CTX_R14 0x20
+%define CTX_R15 0x28
+%define CTX_RSP 0x30
+%define CTX_RIP 0x38
+%define CTX_CR3 0x40
+
+; Saves the callee-saved registers into the context and calls `enter`, which puts the
+; system to sleep. If the system wakes up, the execution continues at `x86_64_resume`
+; and this function returns zero. Otherwise, the value returned by `enter` is returned.
+;
+; Parameters: rdi = the context, rsi = enter
+; Returns: rax = zero if the system was resumed
+x86_64_suspend_lowlevel:
+    mov [rdi + CTX_RBX], rbx
+    mov [rdi + CTX_RBP], rbp
+    mov [rdi + CTX_R12], r12
+    mov [rdi + CTX_R13], r13
+    mov [rdi + CTX_R14], r14
+    mov [rdi + CTX_R15], r15
+
+    mov rax, cr3
+    mov [rdi + CTX_CR3], rax
+
+    lea rax, [rel .resumed]
+    mov [rdi + CTX_RIP], rax
+
+    ; RSP points to the return address of this function.
+    mov [rdi + CTX_RSP], rsp
+
+    sub rsp, 8 ; Align the stack to 16 bytes for the call.
+    call rsi
+    add rsp, 8
+    ret
+
+.resumed:
+    xor eax, eax
+    ret
+
+; Jumped to by the wakeup trampoline in long mode, with the page tables of the
+; trampoline (which map the kernel).
+;
+; Parameters: rdi = the context
+x86_64_resume:
+    mov rax, [rdi + CTX_CR3]
+    mov cr3, rax
+
+    mov rbx, [rdi + CTX_RBX]
+    mov rbp, [rdi + CTX_RBP]
+    mov r12, [rdi + CTX_R12]
+    mov r13, [rdi + CTX_R13]
+    mov r14, [rdi + CTX_R14]
+    mov r15, [rdi + CTX_R15]
+
+    mov rsp, [rdi + CTX_RSP]
+    jmp [rdi + CTX_RIP]
+
+; The wakeup trampoline. The firmware jumps to it in real mode, with CS set to the
+; address of the trampoline shifted right by 4 and IP set to zero. It is copied to a
+; page below 1MiB, so only offsets relative to `x86_64_wakeup_start` are used and the
+; absolute addresses are patched at runtime. The data fields at the end are filled in
+; by the kernel before suspending.
+%define OFF(label) (label - x86_64_wakeup_start)
+
+bits 16
+
+x86_64_wakeup_start:
+    cli
+    cld
+
+    mov ax, cs
+    mov ds, ax
+
+    ; EBX = the physical address of the trampoline.
+    xor ebx, ebx
+    mov bx, cs
+    shl ebx, 4
+
+    lea eax, [ebx + OFF(wakeup_gdt)]
+    mov [OFF(wakeup_gdtr) + 2], eax
+
+    lea eax, [ebx + OFF(wakeup_protected)]
+    mov [OFF(wakeup_protected_ptr)], eax
+
+    lea eax, [ebx + OFF(wakeup_long)]
+    mov [OFF(wakeup_long_ptr)], eax
+
+    lgdt [OFF(wakeup_gdtr)]
+
+    mov eax, cr0
+    or eax, 1 ; Protection enable.
+    mov cr0, eax
+
+    jmp dword far [OFF(wakeup_protected_ptr)]
+
+bits 32
+
+wakeup_protected:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov eax, cr4
+    or eax, 1 << 5 ; Physical address extension.
+    mov cr4, eax
+
+    mov eax, [ebx + OFF(x86_64_wakeup_cr3)]
+    mov cr3, eax
+
+    ; Restore EFER, which enables long mode.
+    mov ecx, 0xc0000080
+    mov eax, [ebx + OFF(x86_64_wakeup_efer)]
+    xor edx, edx
+    wrmsr
+
+    mov eax, cr0
+    or eax, 1 << 31 ; Paging.
+    mov cr0, eax
+
+    jmp far [ebx + OFF(wakeup_long_ptr)]
+
+bits 64
+
+wakeup_long:
+    mov ebx, ebx ; Clear the upper half of RBX.
+
+    mov rdi, [rbx + OFF(x86_64_wakeup_context)]
+    mov rax, [rbx + OFF(x86_64_wakeup_entry)]
+    jmp rax
+
+align 16
+wakeup_gdt:
+    dq 0                      ; Null descriptor.
+    dq 0x00cf9a000000ffff     ; 32-bit code descriptor.
+    dq 0x00cf92000000ffff     ; 32-bit data descriptor.
+    dq 0x00af9a000000ffff     ; 64-bit code descriptor.
+
+wakeup_gdtr:
+    dw wakeup_gdtr - wakeup_gdt - 1
+    dd 0
+
+align 8
+wakeup_protected_ptr:
+    dd 0
+    dw 0x08
+
+align 8
+wakeup_long_ptr:
+    dd 0
+    dw 0x18
+
+align 8
+x86_64_wakeup_cr3:
+    dq 0
+x86_64_wakeup_efer:
+    dq 0
+x86_64_wakeup_context:
+    dq 0
+x86_64_wakeup_entry:
+    dq 0
+
+x86_64_wakeup_end:
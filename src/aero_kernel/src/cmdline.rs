@@ -1,11 +1,54 @@
 use core::num::ParseIntError;
+use core::str::FromStr;
 
 use limine::{LimineFile, NonNullPtr};
+use log::LevelFilter;
 use spin::Once;
 
 use crate::rendy;
 
 static RAW_CMDLINE_STR: Once<&'static str> = Once::new();
+static COMMAND_LINE: Once<CommandLine> = Once::new();
+static MODULES: Once<Modules> = Once::new();
+
+/// The modules loaded by the bootloader, in the format of the boot protocol.
+#[derive(Copy, Clone)]
+pub enum Modules {
+    Limine(&'static [NonNullPtr<LimineFile>]),
+    #[cfg(feature = "multiboot2")]
+    Multiboot2(crate::arch::multiboot2::BootInfo),
+}
+
+impl Modules {
+    /// Calls `f` with the name (its command line) and the contents of each module.
+    fn for_each(&self, mut f: impl FnMut(&'static str, &'static [u8])) {
+        match *self {
+            Self::Limine(modules) => {
+                for module in modules {
+                    let data = unsafe {
+                        core::slice::from_raw_parts(
+                            module.base.as_ptr().unwrap(),
+                            module.length as usize,
+                        )
+                    };
+
+                    f(module_name(module), data);
+                }
+            }
+
+            #[cfg(feature = "multiboot2")]
+            Self::Multiboot2(info) => {
+                for module in info.modules() {
+                    f(module.cmdline(), module.data());
+                }
+            }
+        }
+    }
+}
+
+// SAFETY: The modules are not modified after the bootloader hands off.
+unsafe impl Send for Modules {}
+unsafe impl Sync for Modules {}
 
 pub struct CommandLine {
     /// If set, then the kernel logs will be redirected onto the framebuffer until
@@ -15,6 +58,39 @@ pub struct CommandLine {
     pub rendy_debug: bool,
     pub term_background: Option<&'static [u8]>,
     pub theme_background: u32,
+    /// The PSF font (version 1 or 2) used by the framebuffer console, loaded from the
+    /// module provided by `font=<module>`. By default, the built-in 8x16 font is used.
+    pub font: Option<&'static [u8]>,
+    /// The maximum level of the kernel log records printed on the console, set with
+    /// `loglevel=<error|warn|info|debug|trace>`. By default, all of the records are printed.
+    pub log_level: LevelFilter,
+    /// If set, the GDB remote stub is started on the second serial port and the kernel
+    /// waits for the debugger to attach during boot.
+    pub gdbstub: bool,
+    /// If set, the DMA requests of the devices are remapped through the Intel VT-d IOMMU
+    /// (when present), so that each device only accesses its own buffers.
+    pub iommu: bool,
+    /// If set, the kernel logs are printed on the first serial port. Set with
+    /// `console=ttyS0` (and `console=tty0` for the framebuffer, see [`Self::rendy_debug`]).
+    ///
+    /// By default, the kernel logs are printed on the serial port.
+    pub serial_console: bool,
+    /// The name of the block device partition mounted as the root filesystem, set with
+    /// `root=<partition>` (for example `root=nvme0n1p1`). By default, the first ext2
+    /// partition found is mounted.
+    pub root: Option<&'static str>,
+    /// The initramfs module, set with `initrd=<module>`. It is unpacked and used as the
+    /// root filesystem if no root partition is found (see [`crate::fs::initramfs`]).
+    pub initrd: Option<&'static [u8]>,
+    /// The symbol map of the kernel, set with `symbols=<module>`. It is used to symbolize
+    /// the backtraces when the kernel image is stripped (see [`crate::unwind`]).
+    pub symbols: Option<&'static [u8]>,
+    /// The path of the first userland program, set with `init=<path>`.
+    pub init: &'static str,
+    /// If set, the application processors are not started.
+    pub nosmp: bool,
+    /// If set, the address space layout of the userland programs is not randomized.
+    pub nokaslr: bool,
 }
 
 impl CommandLine {
@@ -23,21 +99,39 @@ impl CommandLine {
             rendy_debug: false,
             term_background: None,
             theme_background: rendy::DEFAULT_THEME_BACKGROUND,
+            font: None,
+            log_level: LevelFilter::Trace,
+            gdbstub: false,
+            iommu: false,
+            serial_console: true,
+            root: None,
+            initrd: None,
+            symbols: None,
+            init: "/usr/bin/init",
+            nosmp: false,
+            nokaslr: false,
         }
     }
 }
 
-fn resolve_module(modules: &[NonNullPtr<LimineFile>], name: &str) -> &'static [u8] {
-    modules
-        .iter()
-        .find(|m| {
-            let n = m.cmdline.to_str().unwrap().to_str().unwrap();
-            n == name
-        })
-        .map(|m| unsafe {
-            core::slice::from_raw_parts(m.base.as_ptr().unwrap(), m.length as usize)
-        })
-        .expect("resolve_module: invalid operand")
+fn module_name(module: &'static LimineFile) -> &'static str {
+    module.cmdline.to_str().unwrap().to_str().unwrap()
+}
+
+fn find_module(modules: Modules, name: &str) -> Option<&'static [u8]> {
+    let mut found = None;
+
+    modules.for_each(|module, data| {
+        if module == name && found.is_none() {
+            found = Some(data);
+        }
+    });
+
+    found
+}
+
+fn resolve_module(modules: Modules, name: &str) -> &'static [u8] {
+    find_module(modules, name).expect("resolve_module: invalid operand")
 }
 
 fn parse_number(mut string: &str) -> Result<usize, ParseIntError> {
@@ -55,31 +149,79 @@ fn parse_number(mut string: &str) -> Result<usize, ParseIntError> {
     }
 }
 
-pub fn parse(cmdline: &'static str, modules: &[NonNullPtr<LimineFile>]) -> CommandLine {
+pub fn parse(cmdline: &'static str, modules: Modules) -> &'static CommandLine {
     RAW_CMDLINE_STR.call_once(|| cmdline);
+    MODULES.call_once(|| modules);
+
+    modules.for_each(|name, data| {
+        log::debug!(
+            "module '{}': {:#x} ({} bytes)",
+            name,
+            data.as_ptr() as usize,
+            data.len()
+        );
+    });
 
     // Chew up the leading spaces.
     let cmdline = cmdline.trim();
     let mut result = CommandLine::new();
+    let mut console_set = false;
 
-    let bail = |argument| log::warn!("unknown kernel command line option: '{}'", argument);
+    // The options unknown here are left for the subsystems to query with [`get_option`]
+    // and [`has_flag`].
+    let bail = |argument| log::debug!("kernel command line option left unparsed: '{}'", argument);
 
     for argument in cmdline.split_whitespace() {
         match argument {
             "rendy-dbg" => result.rendy_debug = true,
+            "gdbstub" => result.gdbstub = true,
+            "iommu" => result.iommu = true,
+            "nosmp" => result.nosmp = true,
+            "nokaslr" => result.nokaslr = true,
 
             _ => {
                 let mut pair = argument.splitn(2, '=');
 
                 match pair.next() {
                     Some(name) => {
-                        let value = pair.next().expect("missing operand");
+                        let value = match pair.next() {
+                            Some(value) => value,
+                            None => {
+                                bail(argument);
+                                continue;
+                            }
+                        };
 
                         match name {
                             "term-background" => {
                                 result.term_background = Some(resolve_module(modules, value))
                             }
 
+                            "font" => result.font = Some(resolve_module(modules, value)),
+                            "root" => result.root = Some(value),
+                            "initrd" => result.initrd = Some(resolve_module(modules, value)),
+                            "symbols" => result.symbols = Some(resolve_module(modules, value)),
+                            "init" => result.init = value,
+
+                            "console" => {
+                                // The first `console=` option replaces the default console.
+                                if !console_set {
+                                    result.serial_console = false;
+                                    console_set = true;
+                                }
+
+                                match value {
+                                    "ttyS0" => result.serial_console = true,
+                                    "tty0" => result.rendy_debug = true,
+                                    _ => log::warn!("unknown console: '{}'", value),
+                                }
+                            }
+
+                            "loglevel" => match LevelFilter::from_str(value) {
+                                Ok(level) => result.log_level = level,
+                                Err(_) => log::warn!("invalid log level: '{}'", value),
+                            },
+
                             "theme-background" => {
                                 let theme_bg = parse_number(value).unwrap_or_else(|e| {
                                     log::warn!(
@@ -104,7 +246,53 @@ pub fn parse(cmdline: &'static str, modules: &[NonNullPtr<LimineFile>]) -> Comma
         }
     }
 
-    result
+    COMMAND_LINE.call_once(|| result)
+}
+
+/// Returns the parsed command line, or `None` if it has not been parsed yet (for example
+/// when panicking early during boot).
+pub fn try_get() -> Option<&'static CommandLine> {
+    COMMAND_LINE.get()
+}
+
+/// Returns the parsed kernel command line.
+///
+/// ## Panics
+/// * If this function was invoked before the kernel command line was
+/// parsed using [`self::parse`].
+pub fn get() -> &'static CommandLine {
+    COMMAND_LINE
+        .get()
+        .expect("cmdline::get: called before cmdline was parsed")
+}
+
+fn find_option<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    // The last occurrence of the option takes precedence.
+    cmdline
+        .split_whitespace()
+        .filter_map(|argument| argument.split_once('='))
+        .filter(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+        .last()
+}
+
+/// Returns the contents of the module that was loaded with `name` as its command line (for
+/// example the ones added with `./aero.py --module`), if any.
+#[allow(dead_code)] // Nothing loads its data from a named module yet.
+pub fn get_module(name: &str) -> Option<&'static [u8]> {
+    find_module(*MODULES.get()?, name)
+}
+
+/// Returns the value of the last `key=value` option on the kernel command line, if any.
+pub fn get_option(key: &str) -> Option<&'static str> {
+    find_option(get_raw_cmdline(), key)
+}
+
+/// Returns whether the flag (an option without a value) is set on the kernel command line.
+pub fn has_flag(name: &str) -> bool {
+    get_raw_cmdline()
+        .split_whitespace()
+        .any(|argument| argument == name)
 }
 
 /// Returns the raw kernel command line string.
@@ -132,4 +320,13 @@ mod tests {
         assert!(parse_number("0xinvalid").is_err());
         assert!(parse_number("0oinvalid").is_err());
     }
+
+    #[test]
+    fn find_option_test() {
+        let cmdline = "nosmp root=nvme0n1p1 init=/bin/sh root=nvme0n1p2";
+
+        assert_eq!(find_option(cmdline, "root"), Some("nvme0n1p2"));
+        assert_eq!(find_option(cmdline, "init"), Some("/bin/sh"));
+        assert_eq!(find_option(cmdline, "nosmp"), None);
+    }
 }
@@ -23,6 +23,7 @@ use alloc::vec::Vec;
 use bit_field::BitField;
 use spin::Once;
 
+use crate::mem::dma::{dma_alloc_coherent, DmaFlags, DmaRegion};
 use crate::mem::{paging::*, AddressSpace};
 
 use crate::utils::sync::Mutex;
@@ -194,23 +195,22 @@ enum DmaCommand {
 }
 
 pub struct DmaBuffer {
-    /// The start address of the DMA buffer.
-    start: PhysAddr,
-    /// The data size of the DMA buffer.
-    data_size: usize,
+    region: DmaRegion,
 }
 
 impl DmaBuffer {
     pub fn sectors(&self) -> usize {
-        self.data_size.ceil_div(512)
+        self.data_size().ceil_div(512)
     }
 
+    /// Returns the start (bus) address of the DMA buffer.
     pub fn start(&self) -> PhysAddr {
-        self.start
+        self.region.bus_addr()
     }
 
+    /// Returns the data size of the DMA buffer.
     pub fn data_size(&self) -> usize {
-        self.data_size
+        self.region.size()
     }
 }
 
@@ -229,15 +229,12 @@ impl DmaRequest {
 
         while size > 0 {
             let data_size = core::cmp::min(size, 0x2000);
-            let ordering = if size > 0x1000 {
-                BuddyOrdering::Size8KiB
-            } else {
-                BuddyOrdering::Size4KiB
-            };
 
-            let start = pmm_alloc(ordering);
+            // The PRD entries of the IDE controller only hold 32-bit addresses.
+            let region = dma_alloc_coherent(data_size, DmaFlags::DMA32)
+                .expect("ahci: failed to allocate a DMA buffer");
 
-            buffer.push(DmaBuffer { start, data_size });
+            buffer.push(DmaBuffer { region });
             size -= data_size; // Subtract the data size from the total size.
         }
 
@@ -260,9 +257,7 @@ impl DmaRequest {
 
         for buffer in self.buffer.iter() {
             let count = core::cmp::min(remaning, 0x2000);
-
-            let buffer_pointer = buffer.start.as_hhdm_virt().as_ptr();
-            let buffer = unsafe { core::slice::from_raw_parts::<u8>(buffer_pointer, count) };
+            let buffer = &buffer.region.as_slice()[..count];
 
             // Copy the data from the buffer into the given buffer with the
             // calculated offset.
@@ -679,8 +674,8 @@ impl HbaPort {
         for pri in 0..length {
             let prdt = command_table.prdt_entry_mut(pri);
 
-            prdt.dba.set(buffer[pri].start);
-            prdt.set_data_byte_count(buffer[pri].data_size - 1);
+            prdt.dba.set(buffer[pri].start());
+            prdt.set_data_byte_count(buffer[pri].data_size() - 1);
             prdt.set_interrupt_on_completion(pri == length - 1);
         }
 
@@ -938,6 +933,10 @@ struct AhciDriver {
 }
 
 impl PciDeviceHandle for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         match (vendor_id, device_id) {
             (Vendor::Intel, DeviceType::SataController) => true,
@@ -985,4 +984,4 @@ pub fn ahci_init() {
     register_device_driver(get_ahci().clone());
 }
 
-crate::module_init!(ahci_init, ModuleType::Block);
+crate::core_initcall!(ahci_init);
@@ -23,6 +23,7 @@ use bit_field::BitField;
 use super::registers::*;
 
 use crate::drivers::block::ahci::{AtaCommand, DmaBuffer, DmaRequest};
+use crate::mem::dma::{dma_alloc_coherent, DmaFlags, DmaRegion};
 use crate::mem::paging::*;
 
 use crate::arch::io::delay;
@@ -94,7 +95,7 @@ struct IdeChannelData {
     ctrl: DevCtrlReg,
     bmide: BusMasterReg,
     interrupt_nr: usize,
-    prdt_addr: PhysAddr,
+    prdt: Option<DmaRegion>,
     active_cmd: Option<Arc<DmaRequest>>,
 }
 
@@ -105,7 +106,7 @@ impl IdeChannelData {
             ctrl: DevCtrlReg::new(ctrl),
             bmide: BusMasterReg::new(bmide),
             interrupt_nr,
-            prdt_addr: PhysAddr::new(0),
+            prdt: None,
             active_cmd: None,
         }
     }
@@ -160,10 +161,12 @@ impl IdeChannelData {
     }
 
     pub fn setup_prdt(&mut self) {
-        let prdt = pmm_alloc(BuddyOrdering::Size4KiB);
+        // The PRDT address register of the bus master IDE is 32 bits wide.
+        let prdt = dma_alloc_coherent(Size4KiB::SIZE as usize, DmaFlags::DMA32)
+            .expect("ide: failed to allocate the PRDT");
 
-        self.bmide.load_prdt(prdt);
-        self.prdt_addr = prdt;
+        self.bmide.load_prdt(prdt.bus_addr());
+        self.prdt = Some(prdt);
     }
 
     pub fn enable_interrupts(&mut self) {
@@ -179,7 +182,10 @@ impl IdeChannelData {
     }
 
     pub fn get_prdt(&mut self) -> PrdTable {
-        PrdTable::new(self.prdt_addr, 8192 / 8)
+        let prdt = self.prdt.as_ref().expect("ide: PRDT not set up");
+        let entries = prdt.size() / core::mem::size_of::<PrdEntry>();
+
+        PrdTable::new(prdt.bus_addr(), entries)
     }
 
     pub fn run_ata_command(
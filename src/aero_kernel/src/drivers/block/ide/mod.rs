@@ -80,7 +80,8 @@ impl BlockDeviceInterface for IdeDrive {
     }
 
     fn write_block(&self, _sector: usize, _buf: &[u8]) -> Option<usize> {
-        unimplemented!()
+        // Writing is not supported yet. The page cache keeps the written pages dirty.
+        None
     }
 }
 
@@ -200,6 +201,10 @@ struct Ide {
 }
 
 impl PciDeviceHandle for Ide {
+    fn name(&self) -> &'static str {
+        "ide"
+    }
+
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         match (vendor_id, device_id) {
             (Vendor::Intel, DeviceType::IdeController) => true,
@@ -229,4 +234,4 @@ fn init() {
     register_device_driver(get_device().clone());
 }
 
-crate::module_init!(init, ModuleType::Block);
+crate::core_initcall!(init);
@@ -18,13 +18,11 @@
  */
 
 mod command;
-mod dma;
 mod queue;
 
 use core::mem::MaybeUninit;
 
 use command::*;
-use dma::*;
 use queue::*;
 
 use alloc::sync::Arc;
@@ -32,9 +30,11 @@ use alloc::vec::Vec;
 
 use bit_field::BitField;
 
-use crate::arch::interrupts::{self, InterruptStack};
+use crate::arch::interrupts::{self, InterruptStack, IrqError, IrqFlags};
 use crate::drivers::pci::*;
+use crate::drivers::vtd;
 use crate::fs::block::{install_block_device, BlockDevice, BlockDeviceInterface};
+use crate::mem::dma::*;
 use crate::mem::paging::*;
 
 use crate::utils::sync::Mutex;
@@ -46,6 +46,7 @@ enum Error {
     NotSupported,
     ControllerFatal,
     NotMsixCapable,
+    Irq(IrqError),
 }
 
 #[repr(transparent)]
@@ -159,6 +160,14 @@ impl ControllerConfig {
         self.0.get().get_bit(0)
     }
 
+    /// Requests a normal shutdown of the controller. See the documentation for
+    /// [`ControllerStatus::is_shutdown_complete`] for more information.
+    fn set_shutdown(&mut self) {
+        let mut cfg = self.0.get();
+        cfg.set_bits(14..16, 0b01);
+        self.0.set(cfg);
+    }
+
     /// Sets the enable bit if `enable` is true, otherwise clears the
     /// enable bit.
     ///
@@ -185,6 +194,12 @@ impl ControllerStatus {
     fn get_cfs(&self) -> bool {
         self.0.get().get_bit(1)
     }
+
+    /// Returns whether the controller completed the shutdown requested with
+    /// [`ControllerConfig::set_shutdown`], after which it is safe to power it off.
+    fn is_shutdown_complete(&self) -> bool {
+        self.0.get().get_bits(2..4) == 0b10
+    }
 }
 
 #[repr(C)]
@@ -218,6 +233,15 @@ impl Registers {
             Ok(())
         }
     }
+
+    fn shutdown(&mut self) {
+        log::trace!("nvme: shutting down the controller");
+        self.cc.set_shutdown();
+
+        while !self.controller_status.is_shutdown_complete() {
+            core::hint::spin_loop();
+        }
+    }
 }
 
 struct Namespace<'a> {
@@ -265,6 +289,13 @@ impl<'a> Namespace<'a> {
 }
 
 struct Controller<'a> {
+    header: PciHeader,
+    registers_addr: PhysAddr,
+    /// The interrupt vector of the MSI-X message.
+    vector: u8,
+    /// Allocates the buffers mapped into the IOMMU domain of the controller.
+    dma: DmaAllocator,
+
     identity: Dma<IdentifyController>,
     namespaces: Mutex<Vec<Namespace<'a>>>,
 
@@ -279,6 +310,13 @@ impl<'a> Controller<'a> {
         header.enable_bus_mastering();
         header.enable_mmio();
 
+        // Isolate the controller before it is given any buffer.
+        let dma = if vtd::attach(header) {
+            DmaAllocator::for_device(*header)
+        } else {
+            DmaAllocator::default()
+        };
+
         let bar0 = header.get_bar(0).ok_or(Error::UnknownBar)?;
 
         // All NVMe registers are accessible via BAR0.
@@ -301,8 +339,9 @@ impl<'a> Controller<'a> {
 
         let mut msix = header.msix().ok_or(Error::NotMsixCapable)?;
 
-        let vector = interrupts::allocate_vector();
-        interrupts::register_handler(vector, irq_handler);
+        let vector = interrupts::allocate_vector().map_err(Error::Irq)?;
+        interrupts::request_irq(vector, "nvme", irq_handler, IrqFlags::empty())
+            .map_err(Error::Irq)?;
 
         msix.set(vector);
 
@@ -319,27 +358,10 @@ impl<'a> Controller<'a> {
 
         let queue_size = registers.capability.max_queue_entries() as usize;
 
-        let mut admin = QueuePair::new(&registers, queue_size)?;
+        let mut admin = QueuePair::new(&registers, queue_size, dma)?;
+        Self::enable(registers, &admin)?;
 
-        registers
-            .aqa
-            // 28..32 = Reserved
-            // 16..28 = Admin Completion Queue Size (ACQS)
-            // 0..12  = Admin Submission Queue Size (ASQS)
-            .set(((queue_size - 1) << 16 | (queue_size - 1)) as u32);
-
-        registers.asq.set(admin.submission_addr().as_u64());
-        registers.acq.set(admin.completion_addr().as_u64());
-
-        // Set the controller configuration and admin queue base addresses.
-        registers.cc.set_css(CommandSet::NVM);
-        registers.cc.set_ams(ArbitrationMechanism::RoundRobin);
-        registers.cc.set_iosqes(6); // 64 bytes
-        registers.cc.set_iocqes(4); // 16 bytes
-
-        registers.set_enable(true)?;
-
-        let identity = Dma::<IdentifyController>::new();
+        let identity = Dma::<IdentifyController>::new_in(dma);
         let mut identify_command = IdentifyCommand::default();
 
         identify_command.opcode = AdminOpcode::Identify as u8;
@@ -355,29 +377,8 @@ impl<'a> Controller<'a> {
         );
 
         // Create and initialize the I/O queues.
-        let io_queue = QueuePair::new(&registers, queue_size)?;
-
-        let mut io_cq_cmd = CreateCQCommand::default();
-
-        io_cq_cmd.opcode = AdminOpcode::CreateCq as u8;
-        io_cq_cmd.prp1 = io_queue.completion_addr().as_u64();
-        io_cq_cmd.cqid = io_queue.id();
-        io_cq_cmd.q_size = (io_queue.len() - 1) as u16;
-        io_cq_cmd.irq_vector = 0;
-        io_cq_cmd.cq_flags = CommandFlags::QUEUE_PHYS_CONTIG.bits();
-
-        admin.submit_command(io_cq_cmd);
-
-        let mut io_sq_cmd = CreateSQCommand::default();
-
-        io_sq_cmd.opcode = AdminOpcode::CreateSq as u8;
-        io_sq_cmd.prp1 = io_queue.submission_addr().as_u64();
-        io_sq_cmd.cqid = io_queue.id();
-        io_sq_cmd.sqid = io_queue.id();
-        io_sq_cmd.q_size = (io_queue.len() - 1) as u16;
-        io_sq_cmd.sq_flags = CommandFlags::QUEUE_PHYS_CONTIG.bits();
-
-        admin.submit_command(io_sq_cmd);
+        let io_queue = QueuePair::new(&registers, queue_size, dma)?;
+        Self::create_io_queue(&mut admin, &io_queue);
 
         let shift = 12 + registers.capability.mpsmin() as usize;
         let max_transfer_shift = if identity.mdts != 0 {
@@ -387,6 +388,11 @@ impl<'a> Controller<'a> {
         };
 
         let this = Arc::new(Self {
+            header: *header,
+            registers_addr,
+            vector,
+            dma,
+
             identity,
             namespaces: Mutex::new(alloc::vec![]),
 
@@ -396,7 +402,7 @@ impl<'a> Controller<'a> {
 
         // Discover and initialize the namespaces.
         let nsids = {
-            let nsid_list = Dma::<u32>::new_uninit_slice(this.identity.nn as usize);
+            let nsid_list = Dma::<u32>::new_uninit_slice_in(this.identity.nn as usize, dma);
             let mut nsid_command = IdentifyCommand::default();
 
             nsid_command.opcode = AdminOpcode::Identify as u8;
@@ -417,7 +423,7 @@ impl<'a> Controller<'a> {
                 continue;
             }
 
-            let identity = Dma::<IdentifyNamespace>::new();
+            let identity = Dma::<IdentifyNamespace>::new_in(dma);
             let mut identify_command = IdentifyCommand::default();
 
             identify_command.opcode = AdminOpcode::Identify as u8;
@@ -442,7 +448,7 @@ impl<'a> Controller<'a> {
                 block_size,
                 size: blocks * block_size,
                 max_prps,
-                prps: Mutex::new(Dma::new_uninit_slice(max_prps)),
+                prps: Mutex::new(Dma::new_uninit_slice_in(max_prps, dma)),
             };
 
             log::trace!(
@@ -460,16 +466,113 @@ impl<'a> Controller<'a> {
         log::trace!("nvme: successfully initialized NVMe controller");
         Ok(this)
     }
+
+    /// Sets the admin queue and the configuration of the disabled controller and enables
+    /// it.
+    fn enable(registers: &mut Registers, admin: &QueuePair) -> Result<(), Error> {
+        let queue_size = admin.len();
+
+        registers
+            .aqa
+            // 28..32 = Reserved
+            // 16..28 = Admin Completion Queue Size (ACQS)
+            // 0..12  = Admin Submission Queue Size (ASQS)
+            .set(((queue_size - 1) << 16 | (queue_size - 1)) as u32);
+
+        registers.asq.set(admin.submission_addr().as_u64());
+        registers.acq.set(admin.completion_addr().as_u64());
+
+        // Set the controller configuration and admin queue base addresses.
+        registers.cc.set_css(CommandSet::NVM);
+        registers.cc.set_ams(ArbitrationMechanism::RoundRobin);
+        registers.cc.set_iosqes(6); // 64 bytes
+        registers.cc.set_iocqes(4); // 16 bytes
+
+        registers.set_enable(true)
+    }
+
+    fn create_io_queue(admin: &mut QueuePair, io_queue: &QueuePair) {
+        let mut io_cq_cmd = CreateCQCommand::default();
+
+        io_cq_cmd.opcode = AdminOpcode::CreateCq as u8;
+        io_cq_cmd.prp1 = io_queue.completion_addr().as_u64();
+        io_cq_cmd.cqid = io_queue.id();
+        io_cq_cmd.q_size = (io_queue.len() - 1) as u16;
+        io_cq_cmd.irq_vector = 0;
+        io_cq_cmd.cq_flags = CommandFlags::QUEUE_PHYS_CONTIG.bits();
+
+        admin.submit_command(io_cq_cmd);
+
+        let mut io_sq_cmd = CreateSQCommand::default();
+
+        io_sq_cmd.opcode = AdminOpcode::CreateSq as u8;
+        io_sq_cmd.prp1 = io_queue.submission_addr().as_u64();
+        io_sq_cmd.cqid = io_queue.id();
+        io_sq_cmd.sqid = io_queue.id();
+        io_sq_cmd.q_size = (io_queue.len() - 1) as u16;
+        io_sq_cmd.sq_flags = CommandFlags::QUEUE_PHYS_CONTIG.bits();
+
+        admin.submit_command(io_sq_cmd);
+    }
+
+    /// Shuts the controller down before the system is suspended. The queues are locked,
+    /// so that no command is in flight.
+    fn suspend(&self) {
+        let _admin = self.admin.lock_irq();
+        let _io_queue = self.io_queue.lock_irq();
+
+        let registers = self
+            .registers_addr
+            .as_hhdm_virt()
+            .read_mut::<Registers>()
+            .unwrap();
+
+        registers.shutdown();
+    }
+
+    /// Reinitializes the controller after the system is resumed, which lost its
+    /// configuration, its queues and its MSI-X table. The namespaces are unchanged.
+    fn resume(&self) -> Result<(), Error> {
+        let registers = self
+            .registers_addr
+            .as_hhdm_virt()
+            .read_mut::<Registers>()
+            .unwrap();
+
+        self.header
+            .msix()
+            .ok_or(Error::NotMsixCapable)?
+            .set(self.vector);
+
+        let mut admin = self.admin.lock_irq();
+        let mut io_queue = self.io_queue.lock_irq();
+
+        registers.set_enable(false)?;
+
+        admin.reset();
+        io_queue.reset();
+
+        Self::enable(registers, &admin)?;
+        Self::create_io_queue(&mut admin, &io_queue);
+
+        log::trace!("nvme: resumed the controller");
+        Ok(())
+    }
 }
 
 impl<'a> BlockDeviceInterface for Controller<'a> {
     fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        // The destination is not allocated by the DMA API, so it has to be mapped into the
+        // domain of the controller for the duration of the transfer.
+        vtd::map(&self.header, start, size, true);
         self.namespaces.lock()[0].read(sector, start, size);
+        vtd::unmap(&self.header, start, size);
+
         Some(size)
     }
 
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
-        let buffer = Dma::<u8>::new_uninit_slice(dest.len());
+        let buffer = Dma::<u8>::new_uninit_slice_in(dest.len(), self.dma);
         self.namespaces.lock()[0].read(sector, buffer.addr(), dest.len());
 
         // SAFETY: The buffer is initialized above.
@@ -482,7 +585,8 @@ impl<'a> BlockDeviceInterface for Controller<'a> {
     }
 
     fn write_block(&self, _sector: usize, _buf: &[u8]) -> Option<usize> {
-        unimplemented!()
+        // Writing is not supported yet. The page cache keeps the written pages dirty.
+        None
     }
 }
 
@@ -497,9 +601,21 @@ impl<'admin> Handler<'admin> {
             controllers: Mutex::new(Vec::new()),
         })
     }
+
+    fn controller(&self, header: &PciHeader) -> Option<Arc<Controller<'admin>>> {
+        self.controllers
+            .lock_irq()
+            .iter()
+            .find(|controller| controller.header == *header)
+            .cloned()
+    }
 }
 
 impl PciDeviceHandle for Handler<'static> {
+    fn name(&self) -> &'static str {
+        "nvme"
+    }
+
     fn handles(&self, _vendor_id: Vendor, device_id: DeviceType) -> bool {
         device_id == DeviceType::NvmeController
     }
@@ -523,6 +639,20 @@ impl PciDeviceHandle for Handler<'static> {
 
         self.controllers.lock().push(controller);
     }
+
+    fn suspend(&self, header: &PciHeader) {
+        if let Some(controller) = self.controller(header) {
+            controller.suspend();
+        }
+    }
+
+    fn resume(&self, header: &PciHeader) {
+        if let Some(controller) = self.controller(header) {
+            if let Err(err) = controller.resume() {
+                log::error!("nvme: failed to resume the controller: {err:?}");
+            }
+        }
+    }
 }
 
 fn irq_handler(_stack: &mut InterruptStack) {
@@ -534,4 +664,4 @@ fn nvme_init() {
     register_device_driver(Handler::new())
 }
 
-crate::module_init!(nvme_init, ModuleType::Block);
+crate::core_initcall!(nvme_init);
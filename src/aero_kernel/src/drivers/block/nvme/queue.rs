@@ -1,10 +1,10 @@
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicU16, Ordering};
 
+use crate::mem::dma::{Dma, DmaAllocator};
 use crate::mem::paging::PhysAddr;
 
 use super::command::{Command, CompletionEntry};
-use super::dma::Dma;
 use super::*;
 
 const fn calculate_doorbell_offset(queue_id: u16, multiplier: usize, dstrd: usize) -> usize {
@@ -43,7 +43,12 @@ pub(super) struct Queue<'bell, T: QueueType> {
 }
 
 impl<'bell, T: QueueType> Queue<'bell, T> {
-    pub fn new(registers: &Registers, size: usize, queue_id: u16) -> Result<Self, Error> {
+    pub fn new(
+        registers: &Registers,
+        size: usize,
+        queue_id: u16,
+        dma: DmaAllocator,
+    ) -> Result<Self, Error> {
         let dstrd = registers.capability.get_doorbell_stride() as usize;
         let doorbell_offset = calculate_doorbell_offset(queue_id, T::DOORBELL_OFFSET, dstrd);
 
@@ -53,7 +58,7 @@ impl<'bell, T: QueueType> Queue<'bell, T> {
 
         Ok(Self {
             doorbell,
-            queue: unsafe { Dma::new_uninit_slice(size).assume_init() },
+            queue: unsafe { Dma::new_uninit_slice_in(size, dma).assume_init() },
             index: 0,
             phase: true,
         })
@@ -62,6 +67,18 @@ impl<'bell, T: QueueType> Queue<'bell, T> {
     pub fn addr(&self) -> PhysAddr {
         self.queue.addr()
     }
+
+    /// Clears the entries and rewinds the queue, whose doorbell was reset with the
+    /// controller.
+    fn reset(&mut self) {
+        for entry in self.queue.iter_mut() {
+            // SAFETY: The commands and the completion entries are plain old data.
+            unsafe { core::ptr::write_bytes(entry.get(), 0, 1) }
+        }
+
+        self.index = 0;
+        self.phase = true;
+    }
 }
 
 impl Queue<'_, Completion> {
@@ -116,7 +133,7 @@ pub(super) struct QueuePair<'a> {
 }
 
 impl<'a> QueuePair<'a> {
-    pub fn new(registers: &Registers, size: usize) -> Result<Self, Error> {
+    pub fn new(registers: &Registers, size: usize, dma: DmaAllocator) -> Result<Self, Error> {
         let queue_id = QUEUE_PAIR_ID.fetch_add(1, Ordering::SeqCst);
 
         Ok(Self {
@@ -125,8 +142,8 @@ impl<'a> QueuePair<'a> {
 
             cid: 0,
 
-            submission: Queue::new(registers, size, queue_id)?,
-            completion: Queue::new(registers, size, queue_id)?,
+            submission: Queue::new(registers, size, queue_id, dma)?,
+            completion: Queue::new(registers, size, queue_id, dma)?,
         })
     }
 
@@ -147,6 +164,14 @@ impl<'a> QueuePair<'a> {
         self.completion.next_cmd_result().unwrap();
     }
 
+    /// Empties the queues after the controller was reset.
+    pub fn reset(&mut self) {
+        self.cid = 0;
+
+        self.submission.reset();
+        self.completion.reset();
+    }
+
     /// Returns the physical address of the submission queue.
     pub fn submission_addr(&self) -> PhysAddr {
         self.submission.addr()
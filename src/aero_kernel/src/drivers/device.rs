@@ -0,0 +1,326 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The device model. The discovered devices form a tree (for example, the PCI functions
+//! are the children of the PCI bus) and each of them is bound to at most one driver:
+//!
+//! * When a device is added, the registered drivers are probed in the order they were
+//!   registered in, until one of them binds to it.
+//! * When a driver is registered, it is probed with the devices that are not bound yet.
+//! * When a device is removed, its children are removed first and its driver is told
+//!   to release it.
+//!
+//! The bound devices are suspended in the reverse order they were bound in and resumed
+//! in the order they were bound in, so a device is never running while the devices it
+//! depends on are suspended.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use core::fmt::Write;
+
+#[cfg(target_arch = "x86_64")]
+use super::pci::PciHeader;
+use crate::utils::sync::Mutex;
+
+/// Identifies a device for the drivers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeviceId {
+    /// A device that is not on a discoverable bus, such as the root of a bus.
+    Platform,
+    #[cfg(target_arch = "x86_64")]
+    Pci(PciHeader),
+}
+
+pub struct DeviceNode {
+    name: String,
+    id: DeviceId,
+    parent: Option<Arc<DeviceNode>>,
+    driver: Mutex<Option<Arc<dyn Driver>>>,
+}
+
+impl DeviceNode {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    pub fn parent(&self) -> Option<&Arc<DeviceNode>> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the driver bound to the device.
+    pub fn driver(&self) -> Option<Arc<dyn Driver>> {
+        self.driver.lock_irq().clone()
+    }
+
+    fn depth(&self) -> usize {
+        core::iter::successors(self.parent.as_deref(), |device| device.parent.as_deref()).count()
+    }
+}
+
+pub trait Driver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Starts the driver for the `device` if it handles it. Returns whether the driver
+    /// is bound to the device.
+    fn probe(&self, device: &DeviceNode) -> bool;
+
+    /// Releases the `device`, which is being removed.
+    fn remove(&self, _device: &DeviceNode) {}
+
+    /// Quiesces the `device` before the system is suspended.
+    fn suspend(&self, _device: &DeviceNode) {}
+
+    /// Reinitializes the `device` after the system is resumed.
+    fn resume(&self, _device: &DeviceNode) {}
+}
+
+struct DeviceTree {
+    /// The devices, in the order they were added in.
+    devices: Vec<Arc<DeviceNode>>,
+    drivers: Vec<Arc<dyn Driver>>,
+    /// The bound devices, in the order they were bound in.
+    bound: Vec<Arc<DeviceNode>>,
+}
+
+/// The drivers are probed without the lock held, since they may add devices themselves.
+struct Devices(Mutex<DeviceTree>);
+
+impl Devices {
+    const fn new() -> Self {
+        Self(Mutex::new(DeviceTree {
+            devices: Vec::new(),
+            drivers: Vec::new(),
+            bound: Vec::new(),
+        }))
+    }
+
+    fn probe(&self, driver: &Arc<dyn Driver>, device: &Arc<DeviceNode>) -> bool {
+        if device.driver().is_some() || !driver.probe(device) {
+            return false;
+        }
+
+        log::debug!("device: bound {} to {}", device.name, driver.name());
+
+        *device.driver.lock_irq() = Some(driver.clone());
+        self.0.lock_irq().bound.push(device.clone());
+
+        true
+    }
+
+    fn add_device(
+        &self,
+        parent: Option<&Arc<DeviceNode>>,
+        name: &str,
+        id: DeviceId,
+    ) -> Arc<DeviceNode> {
+        let device = Arc::new(DeviceNode {
+            name: String::from(name),
+            id,
+            parent: parent.cloned(),
+            driver: Mutex::new(None),
+        });
+
+        let drivers = {
+            let mut tree = self.0.lock_irq();
+
+            tree.devices.push(device.clone());
+            tree.drivers.clone()
+        };
+
+        for driver in drivers {
+            if self.probe(&driver, &device) {
+                break;
+            }
+        }
+
+        device
+    }
+
+    fn register_driver(&self, driver: Arc<dyn Driver>) {
+        let devices = {
+            let mut tree = self.0.lock_irq();
+
+            tree.drivers.push(driver.clone());
+            tree.devices.clone()
+        };
+
+        for device in devices {
+            self.probe(&driver, &device);
+        }
+    }
+
+    fn remove_device(&self, device: &Arc<DeviceNode>) {
+        let children = self
+            .0
+            .lock_irq()
+            .devices
+            .iter()
+            .filter(|child| {
+                child
+                    .parent
+                    .as_ref()
+                    .map_or(false, |p| Arc::ptr_eq(p, device))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for child in children.iter().rev() {
+            self.remove_device(child);
+        }
+
+        if let Some(driver) = device.driver.lock_irq().take() {
+            driver.remove(device);
+        }
+
+        let mut tree = self.0.lock_irq();
+
+        tree.devices.retain(|other| !Arc::ptr_eq(other, device));
+        tree.bound.retain(|other| !Arc::ptr_eq(other, device));
+    }
+
+    fn bound(&self) -> Vec<Arc<DeviceNode>> {
+        self.0.lock_irq().bound.clone()
+    }
+}
+
+static DEVICES: Devices = Devices::new();
+
+/// Adds a discovered device to the tree and binds a driver to it.
+pub fn add_device(parent: Option<&Arc<DeviceNode>>, name: &str, id: DeviceId) -> Arc<DeviceNode> {
+    DEVICES.add_device(parent, name, id)
+}
+
+/// Registers a driver and binds it to the devices it handles.
+pub fn register_driver(driver: Arc<dyn Driver>) {
+    DEVICES.register_driver(driver)
+}
+
+/// Removes the device and its children from the tree.
+pub fn remove_device(device: &Arc<DeviceNode>) {
+    DEVICES.remove_device(device)
+}
+
+/// Suspends the bound devices, in the reverse order they were bound in.
+pub fn suspend_all() {
+    for device in DEVICES.bound().iter().rev() {
+        if let Some(driver) = device.driver() {
+            driver.suspend(device);
+        }
+    }
+}
+
+/// Resumes the devices suspended by [`suspend_all`], in the order they were bound in.
+pub fn resume_all() {
+    for device in DEVICES.bound().iter() {
+        if let Some(driver) = device.driver() {
+            driver.resume(device);
+        }
+    }
+}
+
+/// Returns the device tree, one device per line, indented by its depth and followed by
+/// the name of its driver.
+pub fn describe() -> String {
+    let devices = DEVICES.0.lock_irq().devices.clone();
+    let mut output = String::new();
+
+    fn describe_device(device: &Arc<DeviceNode>, devices: &[Arc<DeviceNode>], output: &mut String) {
+        let driver = device.driver();
+        let driver = driver.as_ref().map_or("-", |driver| driver.name());
+
+        let _ = writeln!(
+            output,
+            "{:indent$}{} {driver}",
+            "",
+            device.name,
+            indent = device.depth() * 2
+        );
+
+        for child in devices.iter().filter(|child| {
+            child
+                .parent
+                .as_ref()
+                .map_or(false, |p| Arc::ptr_eq(p, device))
+        }) {
+            describe_device(child, devices, output);
+        }
+    }
+
+    for root in devices.iter().filter(|device| device.parent.is_none()) {
+        describe_device(root, &devices, &mut output);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TestDriver {
+        handles: &'static str,
+        removed: AtomicUsize,
+    }
+
+    impl Driver for TestDriver {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn probe(&self, device: &DeviceNode) -> bool {
+            device.name().starts_with(self.handles)
+        }
+
+        fn remove(&self, _device: &DeviceNode) {
+            self.removed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn device_driver_binding() {
+        let devices = Devices::new();
+        let driver = Arc::new(TestDriver {
+            handles: "disk",
+            removed: AtomicUsize::new(0),
+        });
+
+        let bus = devices.add_device(None, "bus", DeviceId::Platform);
+        let early = devices.add_device(Some(&bus), "disk0", DeviceId::Platform);
+
+        devices.register_driver(driver.clone());
+        let late = devices.add_device(Some(&bus), "disk1", DeviceId::Platform);
+
+        assert!(bus.driver().is_none());
+        assert!(early.driver().is_some() && late.driver().is_some());
+        assert_eq!(devices.bound().len(), 2);
+
+        devices.remove_device(&bus);
+
+        assert_eq!(driver.removed.load(Ordering::SeqCst), 2);
+        assert!(devices.bound().is_empty());
+    }
+}
@@ -0,0 +1,616 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Driver for the Intel 8254x (e1000) ethernet controllers, the NIC emulated by QEMU
+//! with `-device e1000`.
+//!
+//! The frames are received into a ring of 2KiB buffers. The interrupt handler only
+//! acknowledges the interrupt, the received frames are handed to the networking stack
+//! from a work item (see [`crate::workqueue`]).
+//!
+//! ## Notes
+//! * <https://www.intel.com/content/dam/doc/manual/pci-pci-x-family-gbe-controllers-software-dev-manual.pdf>
+
+use core::alloc::AllocError;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::interrupts::{self, InterruptStack, IrqError, IrqFlags};
+use crate::drivers::pci::*;
+use crate::drivers::vtd;
+use crate::mem::dma::*;
+use crate::mem::paging::*;
+use crate::net::{self, Interface, MacAddr, NetDevice, NetError};
+use crate::utils::sync::Mutex;
+use crate::workqueue;
+
+/// The device IDs of the supported controllers: the 82540EM (emulated by QEMU) and the
+/// 82545EM (emulated by VMware).
+const SUPPORTED_DEVICES: [u16; 2] = [0x100e, 0x100f];
+
+const REG_CTRL: u64 = 0x0000;
+const REG_EERD: u64 = 0x0014;
+const REG_ICR: u64 = 0x00c0;
+const REG_IMS: u64 = 0x00d0;
+const REG_IMC: u64 = 0x00d8;
+const REG_RCTL: u64 = 0x0100;
+const REG_TCTL: u64 = 0x0400;
+const REG_TIPG: u64 = 0x0410;
+const REG_RDBAL: u64 = 0x2800;
+const REG_RDBAH: u64 = 0x2804;
+const REG_RDLEN: u64 = 0x2808;
+const REG_RDH: u64 = 0x2810;
+const REG_RDT: u64 = 0x2818;
+const REG_TDBAL: u64 = 0x3800;
+const REG_TDBAH: u64 = 0x3804;
+const REG_TDLEN: u64 = 0x3808;
+const REG_TDH: u64 = 0x3810;
+const REG_TDT: u64 = 0x3818;
+const REG_MTA: u64 = 0x5200;
+const REG_RAL0: u64 = 0x5400;
+const REG_RAH0: u64 = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+
+const RAH_AV: u32 = 1 << 31;
+
+const ICR_LSC: u32 = 1 << 2;
+const ICR_RXDMT0: u32 = 1 << 4;
+const ICR_RXO: u32 = 1 << 6;
+const ICR_RXT0: u32 = 1 << 7;
+const ICR_RX: u32 = ICR_RXDMT0 | ICR_RXO | ICR_RXT0;
+
+/// Receive enable, accept the broadcast frames and strip the CRC. The buffers are 2KiB.
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+
+/// Transmit enable, pad the short packets and the recommended collision parameters.
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x0f << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+
+/// The recommended inter packet gap for the IEEE 802.3 standard.
+const TIPG_DEFAULT: u32 = 10 | (8 << 10) | (6 << 20);
+
+const DESC_DD: u8 = 1 << 0;
+const RX_EOP: u8 = 1 << 1;
+
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+/// The size of the rings must be a multiple of 128 bytes (8 descriptors).
+const RX_DESC_COUNT: usize = 32;
+const TX_DESC_COUNT: usize = 32;
+const BUFFER_SIZE: usize = 2048;
+const MTU: usize = 1500;
+
+/// The amount of times the reset and the EEPROM reads are polled for completion.
+const POLL_ITERATIONS: usize = 100_000;
+
+#[derive(Debug)]
+enum Error {
+    UnknownBar,
+    OutOfMemory,
+    Irq(IrqError),
+    NoMacAddress,
+    ResetTimeout,
+}
+
+impl From<AllocError> for Error {
+    fn from(_: AllocError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct RxDescriptor {
+    address: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct TxDescriptor {
+    address: u64,
+    length: u16,
+    cso: u8,
+    command: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// A ring of descriptors, each with its own buffer.
+struct Ring<T: Copy> {
+    descriptors: DmaRegion,
+    buffers: DmaRegion,
+    /// The next descriptor to be processed by the driver.
+    index: usize,
+    _descriptor: PhantomData<T>,
+}
+
+impl<T: Copy> Ring<T> {
+    fn new(header: &PciHeader, iommu: bool, count: usize) -> Result<Self, Error> {
+        let alloc = |size| {
+            if iommu {
+                dma_alloc_coherent_for(header, size, DmaFlags::empty())
+            } else {
+                dma_alloc_coherent(size, DmaFlags::empty())
+            }
+        };
+
+        Ok(Self {
+            descriptors: alloc(count * core::mem::size_of::<T>())?,
+            buffers: alloc(count * BUFFER_SIZE)?,
+            index: 0,
+            _descriptor: PhantomData,
+        })
+    }
+
+    fn count(&self) -> usize {
+        self.descriptors.size() / core::mem::size_of::<T>()
+    }
+
+    fn read(&self, index: usize) -> T {
+        let descriptors = self.descriptors.virt_addr().as_ptr::<T>();
+
+        // SAFETY: The index is in bounds and the device may update the descriptor.
+        unsafe { descriptors.add(index).read_volatile() }
+    }
+
+    fn write(&mut self, index: usize, descriptor: T) {
+        let descriptors = self.descriptors.virt_addr().as_mut_ptr::<T>();
+
+        // SAFETY: The index is in bounds and the device may read the descriptor.
+        unsafe { descriptors.add(index).write_volatile(descriptor) }
+    }
+
+    fn buffer_addr(&self, index: usize) -> PhysAddr {
+        self.buffers.bus_addr() + (index * BUFFER_SIZE) as u64
+    }
+
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_slice_mut()[index * BUFFER_SIZE..(index + 1) * BUFFER_SIZE]
+    }
+
+    fn base(&self) -> (u32, u32) {
+        let address = self.descriptors.bus_addr().as_u64();
+        (address as u32, (address >> 32) as u32)
+    }
+}
+
+impl Ring<RxDescriptor> {
+    /// Gives all of the buffers to the device.
+    fn reset(&mut self) {
+        for i in 0..self.count() {
+            let address = self.buffer_addr(i).as_u64();
+
+            self.write(
+                i,
+                RxDescriptor {
+                    address,
+                    ..Default::default()
+                },
+            );
+        }
+
+        self.index = 0;
+    }
+
+    /// Takes the frames received by the device and gives their buffers back to it.
+    fn take_frames(&mut self, registers: &Registers) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+
+        loop {
+            let index = self.index;
+            let descriptor = self.read(index);
+
+            if descriptor.status & DESC_DD == 0 {
+                break;
+            }
+
+            // The long packets are not enabled, so every frame fits in a buffer. Drop the
+            // frames received with errors.
+            if descriptor.status & RX_EOP != 0 && descriptor.errors == 0 {
+                let length = core::cmp::min(descriptor.length as usize, BUFFER_SIZE);
+                frames.push(self.buffer(index)[..length].to_vec());
+            }
+
+            self.write(
+                index,
+                RxDescriptor {
+                    address: descriptor.address,
+                    ..Default::default()
+                },
+            );
+
+            // The tail points to the last descriptor owned by the driver.
+            registers.write(REG_RDT, index as u32);
+            self.index = (index + 1) % self.count();
+        }
+
+        frames
+    }
+}
+
+impl Ring<TxDescriptor> {
+    /// Marks all of the descriptors as free.
+    fn reset(&mut self) {
+        for i in 0..self.count() {
+            self.write(
+                i,
+                TxDescriptor {
+                    status: DESC_DD,
+                    ..Default::default()
+                },
+            );
+        }
+
+        self.index = 0;
+    }
+}
+
+struct Registers(VirtAddr);
+
+impl Registers {
+    fn read(&self, register: u64) -> u32 {
+        // SAFETY: The register is in the MMIO range of BAR0.
+        unsafe { (self.0 + register).as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        // SAFETY: The register is in the MMIO range of BAR0.
+        unsafe {
+            (self.0 + register)
+                .as_mut_ptr::<u32>()
+                .write_volatile(value)
+        }
+    }
+
+    /// Resets the controller, which also masks all of the interrupts.
+    fn reset(&self) -> Result<(), Error> {
+        self.write(REG_IMC, u32::MAX);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_RST);
+
+        for _ in 0..POLL_ITERATIONS {
+            if self.read(REG_CTRL) & CTRL_RST == 0 {
+                self.write(REG_IMC, u32::MAX);
+                self.read(REG_ICR);
+                return Ok(());
+            }
+
+            core::hint::spin_loop();
+        }
+
+        Err(Error::ResetTimeout)
+    }
+
+    fn read_eeprom(&self, word: u8) -> Option<u16> {
+        self.write(REG_EERD, EERD_START | (word as u32) << 8);
+
+        for _ in 0..POLL_ITERATIONS {
+            let value = self.read(REG_EERD);
+
+            if value & EERD_DONE != 0 {
+                return Some((value >> 16) as u16);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        None
+    }
+
+    /// Reads the MAC address from the receive address registers, which are loaded from
+    /// the EEPROM on reset, or from the EEPROM itself.
+    fn read_mac_address(&self) -> Option<MacAddr> {
+        let low = self.read(REG_RAL0);
+        let high = self.read(REG_RAH0);
+
+        let mut mac = [0; 6];
+
+        if high & RAH_AV != 0 {
+            mac[..4].copy_from_slice(&low.to_le_bytes());
+            mac[4..].copy_from_slice(&high.to_le_bytes()[..2]);
+        } else {
+            for (i, bytes) in mac.chunks_mut(2).enumerate() {
+                bytes.copy_from_slice(&self.read_eeprom(i as u8)?.to_le_bytes());
+            }
+        }
+
+        Some(MacAddr(mac))
+    }
+}
+
+struct E1000 {
+    header: PciHeader,
+    registers: Registers,
+    mac: MacAddr,
+
+    rx: Mutex<Ring<RxDescriptor>>,
+    tx: Mutex<Ring<TxDescriptor>>,
+    /// Whether a work item is already queued to handle the received frames.
+    rx_pending: AtomicBool,
+
+    interface: Once<Arc<Interface>>,
+}
+
+impl E1000 {
+    fn new(header: &PciHeader) -> Result<Arc<Self>, Error> {
+        header.enable_bus_mastering();
+        header.enable_mmio();
+
+        // Isolate the controller before it is given any buffer.
+        let iommu = vtd::attach(header);
+
+        let registers_addr = match header.get_bar(0).ok_or(Error::UnknownBar)? {
+            Bar::Memory32 { address, .. } => PhysAddr::new(address as u64),
+            Bar::Memory64 { address, .. } => PhysAddr::new(address),
+            _ => return Err(Error::UnknownBar),
+        };
+
+        let registers = Registers(registers_addr.as_hhdm_virt());
+        registers.reset()?;
+
+        let mac = registers.read_mac_address().ok_or(Error::NoMacAddress)?;
+
+        Ok(Arc::new(Self {
+            header: *header,
+            registers,
+            mac,
+
+            rx: Mutex::new(Ring::new(header, iommu, RX_DESC_COUNT)?),
+            tx: Mutex::new(Ring::new(header, iommu, TX_DESC_COUNT)?),
+            rx_pending: AtomicBool::new(false),
+
+            interface: Once::new(),
+        }))
+    }
+
+    /// Sets up the rings, brings the link up and enables the interrupts. The controller
+    /// must have been reset.
+    fn configure(&self) {
+        let registers = &self.registers;
+
+        registers.write(REG_CTRL, registers.read(REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+
+        // Only accept the frames addressed to us or broadcasted.
+        let [a, b, c, d, e, f] = self.mac.0;
+
+        registers.write(REG_RAL0, u32::from_le_bytes([a, b, c, d]));
+        registers.write(REG_RAH0, u32::from_le_bytes([e, f, 0, 0]) | RAH_AV);
+
+        for i in 0..128 {
+            registers.write(REG_MTA + i * 4, 0);
+        }
+
+        let mut rx = self.rx.lock_irq();
+        let (low, high) = rx.base();
+
+        rx.reset();
+        registers.write(REG_RDBAL, low);
+        registers.write(REG_RDBAH, high);
+        registers.write(REG_RDLEN, (RX_DESC_COUNT * 16) as u32);
+        registers.write(REG_RDH, 0);
+        registers.write(REG_RDT, RX_DESC_COUNT as u32 - 1);
+        registers.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+        let mut tx = self.tx.lock_irq();
+        let (low, high) = tx.base();
+
+        tx.reset();
+        registers.write(REG_TDBAL, low);
+        registers.write(REG_TDBAH, high);
+        registers.write(REG_TDLEN, (TX_DESC_COUNT * 16) as u32);
+        registers.write(REG_TDH, 0);
+        registers.write(REG_TDT, 0);
+        registers.write(REG_TIPG, TIPG_DEFAULT);
+        registers.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+
+        registers.write(REG_IMS, ICR_RX | ICR_LSC);
+        registers.read(REG_ICR);
+    }
+
+    /// Stops the controller, before the system is suspended.
+    fn stop(&self) {
+        self.registers.write(REG_IMC, u32::MAX);
+        self.registers.write(REG_RCTL, 0);
+        self.registers.write(REG_TCTL, 0);
+    }
+
+    fn handle_irq(self: &Arc<Self>) {
+        let cause = self.registers.read(REG_ICR);
+
+        if cause & ICR_LSC != 0 {
+            log::debug!("e1000: link status changed");
+        }
+
+        if cause & ICR_RX != 0 && !self.rx_pending.swap(true, Ordering::AcqRel) {
+            let this = self.clone();
+            workqueue::schedule(move || this.receive());
+        }
+    }
+
+    /// Hands the received frames to the networking stack. Runs from a work item.
+    fn receive(&self) {
+        // Cleared first, so the frames received meanwhile queue the work item again.
+        self.rx_pending.store(false, Ordering::Release);
+
+        let frames = self.rx.lock_irq().take_frames(&self.registers);
+
+        if let Some(interface) = self.interface.get() {
+            for frame in frames {
+                interface.receive(&frame);
+            }
+        }
+    }
+}
+
+impl NetDevice for E1000 {
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        if frame.len() > BUFFER_SIZE {
+            return Err(NetError::PacketTooBig);
+        }
+
+        let mut tx = self.tx.lock_irq();
+        let index = tx.index;
+
+        // The device sets the done bit once it sent the frame of the descriptor.
+        if tx.read(index).status & DESC_DD == 0 {
+            return Err(NetError::DeviceBusy);
+        }
+
+        tx.buffer(index)[..frame.len()].copy_from_slice(frame);
+
+        let address = tx.buffer_addr(index).as_u64();
+
+        tx.write(
+            index,
+            TxDescriptor {
+                address,
+                length: frame.len() as u16,
+                command: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS,
+                ..Default::default()
+            },
+        );
+
+        tx.index = (index + 1) % TX_DESC_COUNT;
+
+        // Make sure the frame is written before the device is told about it.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        self.registers.write(REG_TDT, tx.index as u32);
+
+        Ok(())
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn mac_address(&self) -> MacAddr {
+        self.mac
+    }
+}
+
+static DEVICES: Mutex<Vec<Arc<E1000>>> = Mutex::new(Vec::new());
+
+fn irq_handler(_stack: &mut InterruptStack) {
+    // The IRQ may be shared, each controller checks whether it raised it.
+    for device in DEVICES.lock_irq().iter() {
+        device.handle_irq();
+    }
+}
+
+struct Handler;
+
+impl Handler {
+    fn device(header: &PciHeader) -> Option<Arc<E1000>> {
+        DEVICES
+            .lock_irq()
+            .iter()
+            .find(|device| device.header == *header)
+            .cloned()
+    }
+
+    fn start_device(header: &PciHeader) -> Result<(), Error> {
+        let device = E1000::new(header)?;
+
+        let interface = net::register_device(device.clone());
+        device.interface.call_once(|| interface);
+
+        DEVICES.lock_irq().push(device.clone());
+
+        // The interrupt line is edge triggered on the I/O APIC unless overridden, so the
+        // interrupts are only enabled (see `configure`) once the handler is registered.
+        match header.interrupt_line() {
+            Some(irq) => {
+                interrupts::request_legacy_irq(irq, "e1000", irq_handler, IrqFlags::SHARED)
+                    .map_err(Error::Irq)?;
+            }
+
+            None => log::warn!("e1000: the interrupt pin is not routed"),
+        }
+
+        device.configure();
+
+        log::info!("e1000: started controller (mac={})", device.mac);
+        Ok(())
+    }
+}
+
+impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Intel && device_id == DeviceType::EthernetController
+    }
+
+    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+        let device_id = header.get_device_id();
+
+        if !SUPPORTED_DEVICES.contains(&device_id) {
+            log::warn!("e1000: unsupported controller (device_id={device_id:#x})");
+            return;
+        }
+
+        if let Err(err) = Self::start_device(header) {
+            log::error!("e1000: failed to start the controller: {err:?}");
+        }
+    }
+
+    fn suspend(&self, header: &PciHeader) {
+        if let Some(device) = Self::device(header) {
+            device.stop();
+        }
+    }
+
+    fn resume(&self, header: &PciHeader) {
+        if let Some(device) = Self::device(header) {
+            match device.registers.reset() {
+                Ok(()) => device.configure(),
+                Err(err) => log::error!("e1000: failed to resume the controller: {err:?}"),
+            }
+        }
+    }
+}
+
+fn e1000_init() {
+    register_device_driver(Arc::new(Handler))
+}
+
+crate::core_initcall!(e1000_init);
@@ -21,13 +21,14 @@ use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use spin::RwLock;
 
-use crate::arch::interrupts::{self, InterruptStack};
+use crate::arch::interrupts::{self, InterruptStack, IrqFlags};
 use crate::fs;
 
-use crate::arch::{apic, io};
+use crate::arch::io;
+use crate::drivers::device::{self, DeviceId, DeviceNode, Driver};
 use crate::fs::devfs::{self, Device};
 use crate::fs::inode::{INodeInterface, PollFlags};
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
 pub trait KeyboardListener: Send + Sync {
     fn on_key(&self, key: KeyCode, released: bool);
@@ -189,7 +190,7 @@ struct KeyboardDevice {
     marker: usize,
     buffer: Mutex<Vec<u8>>,
     sref: Weak<Self>,
-    wq: BlockQueue,
+    wq: WaitQueue,
 }
 
 impl KeyboardDevice {
@@ -198,7 +199,7 @@ impl KeyboardDevice {
             marker: devfs::alloc_device_marker(),
             buffer: Mutex::new(Vec::new()),
             sref: this.clone(),
-            wq: BlockQueue::new(),
+            wq: WaitQueue::new(),
         })
     }
 }
@@ -225,7 +226,7 @@ impl KeyboardListener for KeyboardDevice {
             self.buffer.lock_irq().push(keycode as u8);
         }
 
-        self.wq.notify_complete()
+        self.wq.wake_all()
     }
 }
 
@@ -257,8 +258,41 @@ impl INodeInterface for KeyboardDevice {
     }
 }
 
-/// This function is responsible for initializing PS2 keyboard driver.
-pub fn ps2_keyboard_init() {
+/// The name of the keyboard in the device tree.
+const DEVICE_NAME: &str = "ps2-keyboard";
+
+/// Binds to the keyboard in the device tree, to reinitialize it on resume.
+struct Ps2KeyboardDriver;
+
+impl Driver for Ps2KeyboardDriver {
+    fn name(&self) -> &'static str {
+        "ps2-keyboard"
+    }
+
+    fn probe(&self, device: &DeviceNode) -> bool {
+        device.id() == DeviceId::Platform && device.name() == DEVICE_NAME
+    }
+
+    fn suspend(&self, _device: &DeviceNode) {
+        let lock = PS2_KEYBOARD_STATE.lock_irq();
+
+        unsafe {
+            io::outb(0x60, 0xF5); // command: disable scanning
+        }
+
+        lock.flush();
+    }
+
+    fn resume(&self, _device: &DeviceNode) {
+        // The keyboard and the controller are reset, so a scancode that was cut in half
+        // is not completed either.
+        *PS2_KEYBOARD_STATE.lock_irq() = Ps2KeyboardState::new();
+        configure();
+    }
+}
+
+/// Enables the keyboard and its interrupt in the controller.
+fn configure() {
     let lock = PS2_KEYBOARD_STATE.lock_irq();
 
     unsafe {
@@ -289,16 +323,22 @@ pub fn ps2_keyboard_init() {
 
         lock.flush();
     }
+}
 
-    let keyboard_vector = interrupts::allocate_vector();
-    interrupts::register_handler(keyboard_vector, keyboard_irq_handler);
+/// This function is responsible for initializing PS2 keyboard driver.
+pub fn ps2_keyboard_init() {
+    configure();
 
-    apic::io_apic_setup_legacy_irq(1, keyboard_vector, 1);
+    interrupts::request_legacy_irq(1, "keyboard", keyboard_irq_handler, IrqFlags::empty())
+        .expect("ps2: failed to request the keyboard IRQ");
 
     // TODO: Move this into /dev/input instead
     // TODO: Add support for multiple keyboards
     register_keyboard_listener(KEYBOARD.as_ref().clone());
     devfs::install_device(KEYBOARD.clone()).expect("failed to install keyboard device");
+
+    device::register_driver(Arc::new(Ps2KeyboardDriver));
+    device::add_device(None, DEVICE_NAME, DeviceId::Platform);
 }
 
 pub fn register_keyboard_listener(listner: &'static dyn KeyboardListener) {
@@ -441,4 +481,4 @@ pub fn keyboard_irq_handler(_stack: &mut InterruptStack) {
     }
 }
 
-crate::module_init!(ps2_keyboard_init, ModuleType::Other);
+crate::device_initcall!(ps2_keyboard_init);
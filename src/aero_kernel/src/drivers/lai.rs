@@ -26,7 +26,6 @@ use crate::acpi::get_acpi_table;
 use crate::mem::paging::PhysAddr;
 
 use crate::arch::io;
-use crate::userland::scheduler;
 
 use super::pci::PciHeader;
 
@@ -54,10 +53,7 @@ impl lai::Host for LaiHost {
     }
 
     fn sleep(&self, ms: u64) {
-        scheduler::get_scheduler()
-            .inner
-            .sleep(Some(ms as usize / 1000))
-            .expect("lai: unexpected signal during sleep")
+        crate::timer::sleep(ms * 1_000_000).expect("lai: unexpected signal during sleep")
     }
 
     // Port I/O functions:
@@ -134,4 +130,4 @@ pub fn init_lai() {
     aml::init(subsystem);
 }
 
-crate::module_init!(init_lai, ModuleType::Other);
+crate::device_initcall!(init_lai);
@@ -19,8 +19,11 @@
 
 #[cfg(target_arch = "x86_64")]
 pub mod block;
+pub mod device;
 #[cfg(target_arch = "x86_64")]
 pub mod drm;
+#[cfg(target_arch = "x86_64")]
+pub mod e1000;
 // FIXME: aarch64 port
 #[cfg(target_arch = "x86_64")]
 pub mod keyboard;
@@ -31,7 +34,12 @@ pub mod lai;
 #[cfg(target_arch = "x86_64")]
 pub mod pci;
 pub mod pty;
+// FIXME: aarch64 port
+#[cfg(target_arch = "x86_64")]
+pub mod rtc;
 pub mod tty;
+#[cfg(target_arch = "x86_64")]
+pub mod vtd;
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
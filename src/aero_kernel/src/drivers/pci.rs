@@ -26,13 +26,14 @@ use crate::utils::sync::Mutex;
 
 use crate::acpi::mcfg;
 use crate::mem::paging::OffsetPageTable;
+use crate::mem::AddressSpace;
 use crate::utils::VolatileCell;
 
 use crate::arch::{apic, io};
 
-use bit_field::BitField;
+use super::device::{add_device, register_driver, DeviceId, DeviceNode, Driver};
 
-static PCI_TABLE: Mutex<PciTable> = Mutex::new(PciTable::new());
+use bit_field::BitField;
 
 const PCI_CONFIG_ADDRESS_PORT: u16 = 0xCF8;
 const PCI_CONFIG_DATA_PORT: u16 = 0xCFC;
@@ -142,6 +143,10 @@ impl<'a> Msix<'a> {
         self.table.set(msix_vector, true);
 
         let message = &mut self.messages[msix_vector];
+
+        // The table is programmed again on resume, where the message is still unmasked if
+        // the system failed to enter the sleep state.
+        message.set_masked(true);
         message.set(vector);
         message.set_masked(false);
 
@@ -552,6 +557,7 @@ impl Vendor {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PciHeader(u32);
 
 impl PciHeader {
@@ -639,6 +645,23 @@ impl PciHeader {
         unsafe { Vendor::new(self.read::<u16>(0x00)) }
     }
 
+    /// Returns the value stored in the PCI device ID register, which identifies the model
+    /// of the device.
+    pub fn get_device_id(&self) -> u16 {
+        unsafe { self.read::<u32>(0x00) }.get_bits(16..32) as u16
+    }
+
+    /// Returns the legacy IRQ that the interrupt pin of the device is routed to, as
+    /// programmed by the firmware. Returns [`None`] if the device does not use an
+    /// interrupt pin or if it is not routed.
+    pub fn interrupt_line(&self) -> Option<u8> {
+        let value = unsafe { self.read::<u32>(0x3C) };
+        let line = value.get_bits(0..8) as u8;
+        let pin = value.get_bits(8..16);
+
+        (pin != 0 && line != 0xff).then(|| line)
+    }
+
     pub unsafe fn get_device(&self) -> DeviceType {
         let id = self.read::<u32>(0x08);
 
@@ -761,6 +784,8 @@ impl PciHeader {
 }
 
 pub trait PciDeviceHandle: Sync + Send {
+    fn name(&self) -> &'static str;
+
     /// Returns true if the PCI device driver handles the device with
     /// the provided `vendor_id` and `device_id`.
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool;
@@ -768,38 +793,128 @@ pub trait PciDeviceHandle: Sync + Send {
     /// This function is responsible for initializing the device driver
     /// and starting it.
     fn start(&self, header: &PciHeader, offset_table: &mut OffsetPageTable);
+
+    /// Stops the device, which is being removed.
+    fn remove(&self, _header: &PciHeader) {}
+
+    /// Quiesces the device before the system is suspended to RAM. The configuration
+    /// space of the device is saved after this function returns.
+    fn suspend(&self, _header: &PciHeader) {}
+
+    /// Reinitializes the device after the system is resumed from a suspend to RAM. The
+    /// configuration space of the device is restored before this function is called.
+    fn resume(&self, _header: &PciHeader) {}
 }
 
-struct PciDevice {
+/// Binds a [`PciDeviceHandle`] to the PCI devices in the device tree.
+struct PciDriver {
     handle: Arc<dyn PciDeviceHandle>,
+    /// The configuration space headers (the first 64 bytes) of the suspended devices,
+    /// which are lost when the system is suspended to RAM.
+    saved: Mutex<Vec<(PciHeader, [u32; 16])>>,
 }
 
-struct PciTable {
-    inner: Vec<PciDevice>,
+impl PciDriver {
+    fn header(device: &DeviceNode) -> Option<PciHeader> {
+        match device.id() {
+            DeviceId::Pci(header) => Some(header),
+            _ => None,
+        }
+    }
 }
 
-impl PciTable {
-    const fn new() -> Self {
-        Self { inner: Vec::new() }
+impl Driver for PciDriver {
+    fn name(&self) -> &'static str {
+        self.handle.name()
+    }
+
+    fn probe(&self, device: &DeviceNode) -> bool {
+        let header = match Self::header(device) {
+            Some(header) => header,
+            None => return false,
+        };
+
+        if !self
+            .handle
+            .handles(header.get_vendor(), unsafe { header.get_device() })
+        {
+            return false;
+        }
+
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+
+        self.handle.start(&header, &mut offset_table);
+        true
+    }
+
+    fn remove(&self, device: &DeviceNode) {
+        if let Some(header) = Self::header(device) {
+            self.handle.remove(&header);
+        }
+    }
+
+    fn suspend(&self, device: &DeviceNode) {
+        if let Some(header) = Self::header(device) {
+            self.handle.suspend(&header);
+
+            let mut config = [0; 16];
+
+            for (i, value) in config.iter_mut().enumerate() {
+                *value = unsafe { header.read::<u32>(i as u32 * 4) };
+            }
+
+            self.saved.lock_irq().push((header, config));
+        }
+    }
+
+    fn resume(&self, device: &DeviceNode) {
+        let header = match Self::header(device) {
+            Some(header) => header,
+            None => return,
+        };
+
+        let mut saved = self.saved.lock_irq();
+
+        if let Some(index) = saved.iter().position(|(other, _)| *other == header) {
+            let (_, config) = saved.remove(index);
+
+            // The command register (in the first dword after the IDs) is restored last, so
+            // that the BARs are programmed before the device decodes them.
+            unsafe {
+                for (i, value) in config.iter().enumerate().skip(2) {
+                    header.write::<u32>(i as u32 * 4, *value);
+                }
+
+                header.write::<u32>(0x04, config[1]);
+            }
+        }
+
+        core::mem::drop(saved);
+        self.handle.resume(&header);
     }
 }
 
 pub fn register_device_driver(handle: Arc<dyn PciDeviceHandle>) {
-    PCI_TABLE.lock().inner.push(PciDevice { handle })
+    register_driver(Arc::new(PciDriver {
+        handle,
+        saved: Mutex::new(Vec::new()),
+    }))
 }
 
-/// Lookup and initialize all PCI devices.
-pub fn init(offset_table: &mut OffsetPageTable) {
+/// Adds the PCI devices to the device tree, which binds the drivers to them.
+pub fn init() {
     // Check if the MCFG table is avaliable.
     if mcfg::is_avaliable() {
         let mcfg_table = mcfg::get_mcfg_table();
         let _entry_count = mcfg_table.entry_count();
     }
 
+    let root = add_device(None, "pci", DeviceId::Platform);
+
     /*
      * Use the brute force method to go through each possible bus,
-     * device, function ID and check if we have a driver for it. If a driver
-     * for the PCI device is found then initialize it.
+     * device, function ID and add the devices that exist.
      */
     for bus in 0..255 {
         for device in 0..32 {
@@ -824,14 +939,8 @@ pub fn init(offset_table: &mut OffsetPageTable) {
                         device.get_vendor()
                     );
 
-                    for driver in &mut PCI_TABLE.lock().inner {
-                        if driver
-                            .handle
-                            .handles(device.get_vendor(), device.get_device())
-                        {
-                            driver.handle.start(&device, offset_table)
-                        }
-                    }
+                    let name = alloc::format!("{:02x}:{:02x}.{}", bus, device.device(), function);
+                    add_device(Some(&root), &name, DeviceId::Pci(device));
                 }
             }
         }
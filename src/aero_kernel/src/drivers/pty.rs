@@ -19,8 +19,6 @@
 
 use core::sync::atomic::{AtomicU32, Ordering};
 
-use aero_syscall::Termios;
-use aero_syscall::WinSize;
 use alloc::collections::BTreeMap;
 use alloc::string::ToString;
 use alloc::sync::Arc;
@@ -39,12 +37,13 @@ use crate::fs::inode::PollFlags;
 use crate::fs::inode::{DirEntry, INodeInterface};
 use crate::fs::FileSystem;
 use crate::fs::Path;
-use crate::fs::MOUNT_MANAGER;
+use crate::fs::INIT_MOUNT_NAMESPACE;
 use crate::fs::{self, FileSystemError};
 
 use crate::mem::paging::VirtAddr;
-use crate::utils::sync::BlockQueue;
+use crate::userland::terminal::Terminal;
 use crate::utils::sync::Mutex;
+use crate::utils::sync::WaitQueue;
 
 lazy_static::lazy_static! {
     static ref PTMX: Arc<Ptmx> = Arc::new(Ptmx::new());
@@ -55,9 +54,8 @@ static PTY_ID: AtomicU32 = AtomicU32::new(0);
 
 struct Master {
     id: u32,
-    wq: BlockQueue,
-    window_size: Mutex<WinSize>,
-    slave_buffer: Mutex<Vec<u8>>,
+    wq: WaitQueue,
+    terminal: Terminal,
     buffer: Mutex<Vec<u8>>,
 }
 
@@ -65,9 +63,8 @@ impl Master {
     pub fn new() -> Self {
         Self {
             id: PTY_ID.fetch_add(1, Ordering::SeqCst),
-            wq: BlockQueue::new(),
-            window_size: Mutex::new(WinSize::default()),
-            slave_buffer: Mutex::new(Vec::new()),
+            wq: WaitQueue::new(),
+            terminal: Terminal::new(),
             buffer: Mutex::new(Vec::new()),
         }
     }
@@ -87,10 +84,14 @@ impl INodeInterface for Master {
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        let mut pty_buffer = self.slave_buffer.lock_irq();
-        pty_buffer.extend_from_slice(buffer);
+        let mut echo = Vec::new();
+        self.terminal.receive(buffer, &mut echo);
+
+        if !echo.is_empty() {
+            self.buffer.lock_irq().extend_from_slice(&echo);
+            self.wq.wake_all();
+        }
 
-        self.wq.notify_complete();
         Ok(buffer.len())
     }
 
@@ -113,51 +114,22 @@ impl INodeInterface for Master {
                     .ok_or(FileSystemError::NotSupported)?;
 
                 *id = self.id;
+                Ok(0)
             }
 
-            aero_syscall::TIOCSWINSZ => {
-                let winsize = VirtAddr::new(arg as u64)
-                    .read_mut::<WinSize>()
-                    .ok_or(FileSystemError::NotSupported)?;
-
-                *self.window_size.lock_irq() = *winsize;
-            }
-
-            _ => {
-                log::warn!("ptmx: unknown ioctl (command={command:#x})")
-            }
+            // The rest of the terminal ioctls are shared with the slave.
+            _ => self.terminal.ioctl(command, arg),
         }
-
-        Ok(0)
     }
 }
 
-struct SlaveInner {
-    termios: Termios,
-}
-
 struct Slave {
     master: Arc<Master>,
-    inner: Mutex<SlaveInner>,
 }
 
 impl Slave {
     pub fn new(master: Arc<Master>) -> Self {
-        Self {
-            master,
-            inner: Mutex::new(SlaveInner {
-                termios: Termios {
-                    c_iflag: aero_syscall::TermiosIFlag::empty(),
-                    c_oflag: aero_syscall::TermiosOFlag::ONLCR,
-                    c_cflag: aero_syscall::TermiosCFlag::empty(),
-                    c_lflag: aero_syscall::TermiosLFlag::ECHO | aero_syscall::TermiosLFlag::ICANON,
-                    c_line: 0,
-                    c_cc: [0; 32],
-                    c_ispeed: 0,
-                    c_ospeed: 0,
-                },
-            }),
-        }
+        Self { master }
     }
 }
 
@@ -176,52 +148,15 @@ impl INodeInterface for Slave {
     }
 
     fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
-        let mut inner = self.inner.lock_irq();
-
-        match command {
-            aero_syscall::TIOCGWINSZ => {
-                let winsize = VirtAddr::new(arg as u64)
-                    .read_mut::<WinSize>()
-                    .ok_or(FileSystemError::NotSupported)?;
-
-                *winsize = *self.master.window_size.lock_irq();
-                Ok(0)
-            }
-
-            aero_syscall::TCGETS => {
-                let termios = VirtAddr::new(arg as u64)
-                    .read_mut::<Termios>()
-                    .ok_or(FileSystemError::NotSupported)?;
-
-                *termios = inner.termios;
-                Ok(0)
-            }
-
-            aero_syscall::TCSETSF => {
-                let termios = VirtAddr::new(arg as u64)
-                    .read_mut::<Termios>()
-                    .ok_or(FileSystemError::NotSupported)?;
-
-                inner.termios = *termios;
-                Ok(0)
-            }
-
-            aero_syscall::TIOCSCTTY => {
-                // TODO(alacritty): stub!
-                log::error!("TIOCSCTTY is a stub!");
-                Ok(0)
-            }
-
-            _ => Err(FileSystemError::NotSupported),
-        }
+        self.master.terminal.ioctl(command, arg)
     }
 
     fn poll(&self, table: Option<&mut fs::inode::PollTable>) -> fs::Result<PollFlags> {
-        table.map(|e| e.insert(&self.master.wq));
+        table.map(|e| e.insert(self.master.terminal.read_queue()));
 
         let mut flags = PollFlags::OUT;
 
-        if !self.master.slave_buffer.lock_irq().is_empty() {
+        if self.master.terminal.can_read() {
             flags |= PollFlags::IN;
         }
 
@@ -229,42 +164,15 @@ impl INodeInterface for Slave {
     }
 
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
-        let mut pty_buffer = self
-            .master
-            .wq
-            .block_on(&self.master.slave_buffer, |e| !e.is_empty())?;
-
-        let size = core::cmp::min(pty_buffer.len(), buffer.len());
-
-        buffer[..size].copy_from_slice(&pty_buffer.drain(..size).collect::<Vec<_>>());
-        Ok(size)
+        self.master.terminal.read(buffer)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        if self
-            .inner
-            .lock_irq()
-            .termios
-            .c_oflag
-            .contains(aero_syscall::TermiosOFlag::ONLCR)
-        {
-            let mut master = self.master.buffer.lock_irq();
-
-            for b in buffer.iter() {
-                if *b == b'\n' {
-                    // ONLCR: Convert NL to CR + NL
-                    master.extend_from_slice(&[b'\r', b'\n']);
-                    continue;
-                }
-
-                master.push(*b);
-            }
-        } else {
-            let mut pty_buffer = self.master.buffer.lock_irq();
-            pty_buffer.extend_from_slice(buffer);
-        }
+        let output = self.master.terminal.process_output(buffer)?;
+
+        self.master.buffer.lock_irq().extend_from_slice(&output);
+        self.master.wq.wake_all();
 
-        self.master.wq.notify_complete();
         Ok(buffer.len())
     }
 }
@@ -426,7 +334,7 @@ fn pty_init() {
     root.mkdir("pts").unwrap();
 
     let pts_dir = fs::lookup_path(Path::new("/dev/pts")).unwrap();
-    MOUNT_MANAGER.mount(pts_dir, fs.clone()).unwrap();
+    INIT_MOUNT_NAMESPACE.mount(pts_dir, fs.clone()).unwrap();
 }
 
-crate::module_init!(pty_init, ModuleType::Other);
+crate::device_initcall!(pty_init);
@@ -0,0 +1,305 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The CMOS RTC (Real Time Clock) keeps the wall-clock time while the computer is
+//! powered off. It is read once at boot to set the realtime clock (which is then advanced
+//! by the monotonic clock) and written back when the realtime clock is set.
+//!
+//! The RTC is assumed to hold the time in UTC.
+//!
+//! **Notes**: <https://wiki.osdev.org/CMOS>
+
+use crate::acpi::{fadt, get_acpi_table};
+use crate::arch::io;
+use crate::utils::sync::Mutex;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Disables NMIs while a CMOS register is selected.
+const CMOS_NMI_DISABLE: u8 = 1 << 7;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_SET: u8 = 1 << 7;
+
+/// Set in the hours register if the time is PM (in 12 hour mode).
+const HOURS_PM: u8 = 1 << 7;
+
+/// Serializes the accesses to the CMOS, as selecting a register and accessing it are
+/// two separate port writes.
+static CMOS_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct DateTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u16,
+}
+
+impl DateTime {
+    /// Converts the date and time into the amount of seconds since the Unix epoch.
+    fn to_unix(self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as u32, self.day as u32);
+        let seconds = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+
+        (days * 86400 + seconds) as u64
+    }
+
+    /// Converts the amount of seconds since the Unix epoch into a date and time.
+    fn from_unix(timestamp: u64) -> Self {
+        let (year, month, day) = civil_from_days((timestamp / 86400) as i64);
+        let seconds = timestamp % 86400;
+
+        Self {
+            second: (seconds % 60) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            hour: (seconds / 3600) as u8,
+            day: day as u8,
+            month: month as u8,
+            year: year as u16,
+        }
+    }
+}
+
+/// Returns the amount of days between the Unix epoch and the provided date in the
+/// proleptic Gregorian calendar.
+///
+/// **Notes**: <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+/// The inverse of [`days_from_civil`]. Returns the year, month and day.
+///
+/// **Notes**: <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400;
+
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0f) + (value >> 4) * 10
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    io::outb(CMOS_ADDRESS, CMOS_NMI_DISABLE | register);
+    io::inb(CMOS_DATA)
+}
+
+unsafe fn write_register(register: u8, value: u8) {
+    io::outb(CMOS_ADDRESS, CMOS_NMI_DISABLE | register);
+    io::outb(CMOS_DATA, value);
+}
+
+/// Returns the CMOS register holding the century, if the FADT has one.
+fn century_register() -> Option<u8> {
+    let fadt = get_acpi_table().lookup_entry(fadt::SIGNATURE)?;
+    let fadt: &'static fadt::Fadt = unsafe { fadt.as_ref() };
+
+    match fadt.century {
+        0 => None,
+        register => Some(register),
+    }
+}
+
+/// Reads the raw values of the time registers, in the order of [`DateTime`] followed by
+/// the century (if `century` is not [`None`]).
+unsafe fn read_raw(century: Option<u8>) -> [u8; 7] {
+    while read_register(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    [
+        read_register(RTC_SECONDS),
+        read_register(RTC_MINUTES),
+        read_register(RTC_HOURS),
+        read_register(RTC_DAY),
+        read_register(RTC_MONTH),
+        read_register(RTC_YEAR),
+        century.map_or(0, |register| read_register(register)),
+    ]
+}
+
+/// Reads the RTC and returns the amount of seconds since the Unix epoch.
+pub fn read() -> u64 {
+    let century_register = century_register();
+    let _guard = CMOS_LOCK.lock_irq();
+
+    let (raw, status) = unsafe {
+        // The registers are read until the same values are read twice in a row, so
+        // that they were not read halfway through an update.
+        let mut raw = read_raw(century_register);
+
+        loop {
+            let again = read_raw(century_register);
+
+            if again == raw {
+                break;
+            }
+
+            raw = again;
+        }
+
+        (raw, read_register(RTC_STATUS_B))
+    };
+
+    let [second, minute, hour, day, month, year, century] = raw;
+
+    let pm = hour & HOURS_PM != 0;
+    let hour = hour & !HOURS_PM;
+
+    let convert = |value: u8| {
+        if status & STATUS_B_BINARY == 0 {
+            bcd_to_binary(value)
+        } else {
+            value
+        }
+    };
+
+    let mut hour = convert(hour);
+
+    // Convert the 12 hour clock (where midnight is 12 AM) to the 24 hour clock.
+    if status & STATUS_B_24_HOUR == 0 {
+        hour %= 12;
+
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let century = match century_register {
+        Some(_) => convert(century) as u16,
+        None => 20,
+    };
+
+    let time = DateTime {
+        second: convert(second),
+        minute: convert(minute),
+        hour,
+        day: convert(day),
+        month: convert(month),
+        year: century * 100 + convert(year) as u16,
+    };
+
+    time.to_unix()
+}
+
+/// Writes the provided amount of seconds since the Unix epoch into the RTC.
+pub fn write(timestamp: u64) {
+    let time = DateTime::from_unix(timestamp);
+    let century_register = century_register();
+
+    let _guard = CMOS_LOCK.lock_irq();
+
+    unsafe {
+        let status = read_register(RTC_STATUS_B);
+
+        let convert = |value: u8| {
+            if status & STATUS_B_BINARY == 0 {
+                binary_to_bcd(value)
+            } else {
+                value
+            }
+        };
+
+        let hour = if status & STATUS_B_24_HOUR == 0 {
+            let pm = time.hour >= 12;
+            let hour = match time.hour % 12 {
+                0 => 12,
+                hour => hour,
+            };
+
+            convert(hour) | if pm { HOURS_PM } else { 0 }
+        } else {
+            convert(time.hour)
+        };
+
+        // Stop the updates while the registers are written.
+        write_register(RTC_STATUS_B, status | STATUS_B_SET);
+
+        write_register(RTC_SECONDS, convert(time.second));
+        write_register(RTC_MINUTES, convert(time.minute));
+        write_register(RTC_HOURS, hour);
+        write_register(RTC_DAY, convert(time.day));
+        write_register(RTC_MONTH, convert(time.month));
+        write_register(RTC_YEAR, convert((time.year % 100) as u8));
+
+        if let Some(register) = century_register {
+            write_register(register, convert((time.year / 100) as u8));
+        }
+
+        write_register(RTC_STATUS_B, status & !STATUS_B_SET);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_time_unix_round_trip() {
+        let time = DateTime {
+            second: 7,
+            minute: 6,
+            hour: 5,
+            day: 29,
+            month: 2,
+            year: 2024,
+        };
+
+        assert_eq!(time.to_unix(), 1709183167);
+        assert_eq!(DateTime::from_unix(time.to_unix()), time);
+        assert_eq!(bcd_to_binary(binary_to_bcd(59)), 59);
+    }
+}
@@ -27,7 +27,8 @@ use crate::rendy;
 
 use crate::fs::inode::INodeInterface;
 use crate::mem::paging::VirtAddr;
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::userland::terminal::Terminal;
+use crate::utils::sync::Mutex;
 
 #[cfg(target_arch = "x86_64")]
 use super::keyboard::KeyCode;
@@ -36,17 +37,6 @@ use super::keyboard::KeyboardListener;
 
 lazy_static::lazy_static! {
     static ref TTY: Arc<Tty> = Tty::new();
-
-    static ref TERMIOS: Mutex<aero_syscall::Termios> = Mutex::new(aero_syscall::Termios {
-        c_iflag: aero_syscall::TermiosIFlag::empty(),
-        c_oflag: aero_syscall::TermiosOFlag::empty(),
-        c_cflag: aero_syscall::TermiosCFlag::empty(),
-        c_lflag: aero_syscall::TermiosLFlag::ECHO | aero_syscall::TermiosLFlag::ICANON,
-        c_line: 0,
-        c_cc: [0; 32],
-        c_ispeed: 0,
-        c_ospeed: 0,
-    });
 }
 
 // From the linux kernel: https://github.com/torvalds/linux/blob/master/drivers/tty/vt/defkeymap.c_shipped
@@ -148,38 +138,6 @@ const CTRL_ALT_MAP: &[u16; 128] = &[
     0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
 ];
 
-struct StdinBuffer {
-    back_buffer: Vec<u8>,
-    front_buffer: Vec<u8>, // more like a queue
-
-    cursor: usize,
-}
-
-impl StdinBuffer {
-    fn new() -> Self {
-        Self {
-            back_buffer: Vec::new(),
-            front_buffer: Vec::new(),
-
-            cursor: 0,
-        }
-    }
-
-    fn swap_buffer(&mut self) {
-        for c in self.back_buffer.drain(..) {
-            self.front_buffer.push(c);
-        }
-    }
-
-    fn is_complete(&self) -> bool {
-        self.back_buffer.len() > 0 || self.front_buffer.len() > 0
-    }
-
-    fn advance_cursor(&mut self) {
-        self.cursor += 1;
-    }
-}
-
 struct TtyState {
     lshift: bool,
     rshift: bool,
@@ -190,6 +148,52 @@ struct TtyState {
     caps: bool,
 
     parser: vte::Parser,
+    performer: AnsiEscape,
+}
+
+impl TtyState {
+    /// Parses the `bytes` and renders them on the console.
+    fn advance(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.parser.advance(&mut self.performer, *byte);
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl TtyState {
+    /// Translates the key into the bytes that are sent to the line discipline, using
+    /// the keymap of the current modifiers.
+    fn translate(&self, key: KeyCode) -> Option<[u8; 2]> {
+        let mut shift = self.lshift || self.rshift;
+        let ctrl = self.lctrl || self.rctrl;
+
+        if self.caps {
+            shift = !shift;
+        }
+
+        let map = match (shift, ctrl, self.lalt, self.altgr) {
+            (false, false, false, false) => PLAIN_MAP,
+            (true, false, false, false) => SHIFT_MAP,
+            (false, true, false, false) => CTRL_MAP,
+            (false, false, true, false) => ALT_MAP,
+            (false, false, false, true) => ALTGR_MAP,
+            (true, true, false, false) => SHIFT_CTRL_MAP,
+            (false, true, true, false) => CTRL_ALT_MAP,
+            _ => PLAIN_MAP,
+        };
+
+        let keysym = map[key as usize];
+        let value = (keysym & 0xff) as u8;
+
+        // The upper byte of the keysym is its type: 0xf0 (KT_LATIN) and 0xfb (KT_LETTER)
+        // are plain characters and 0xf8 (KT_META) are characters prefixed with ESC.
+        match keysym >> 8 {
+            0xf0 | 0xfb => Some([value, 0]),
+            0xf8 => Some([0x1b, value]),
+            _ => None,
+        }
+    }
 }
 
 struct Tty {
@@ -197,8 +201,7 @@ struct Tty {
     state: Mutex<TtyState>,
     sref: Weak<Self>,
 
-    stdin: Mutex<StdinBuffer>,
-    block_queue: BlockQueue,
+    terminal: Terminal,
 }
 
 impl Tty {
@@ -215,45 +218,34 @@ impl Tty {
                 caps: false,
 
                 parser: vte::Parser::new(),
+                performer: AnsiEscape::default(),
             }),
-            block_queue: BlockQueue::new(),
-            stdin: Mutex::new(StdinBuffer::new()),
+            terminal: Terminal::new(),
             sref: sref.clone(),
         })
     }
 }
 
-impl INodeInterface for Tty {
-    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
-        self.block_queue
-            .block_on(&self.stdin, |future| future.is_complete())?;
-
-        let mut stdin = self.stdin.lock_irq();
-
-        // record the back buffer size before swapping
-        stdin.swap_buffer();
-        let back_len = stdin.front_buffer.len();
+#[cfg(target_arch = "x86_64")]
+impl Tty {
+    /// Passes the `bytes` through the line discipline and prints the echoed bytes.
+    fn receive(&self, state: &mut TtyState, bytes: &[u8]) {
+        let mut echo = Vec::new();
+        self.terminal.receive(bytes, &mut echo);
 
-        if buffer.len() > stdin.front_buffer.len() {
-            for (i, c) in stdin.front_buffer.drain(..).enumerate() {
-                buffer[i] = c;
-            }
-        } else {
-            for (i, c) in stdin.front_buffer.drain(..buffer.len()).enumerate() {
-                buffer[i] = c;
-            }
-        }
+        state.advance(&echo);
+    }
+}
 
-        Ok(core::cmp::min(buffer.len(), back_len))
+impl INodeInterface for Tty {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        self.terminal.read(buffer)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        let mut state = self.state.lock_irq();
-        let mut performer = AnsiEscape;
+        let output = self.terminal.process_output(buffer)?;
 
-        for character in buffer.iter() {
-            state.parser.advance(&mut performer, *character);
-        }
+        self.state.lock_irq().advance(&output);
 
         log::debug!("TTY::write_at(): {}", unsafe {
             core::str::from_utf8_unchecked(buffer)
@@ -263,10 +255,10 @@ impl INodeInterface for Tty {
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
-        table.map(|e| e.insert(&self.block_queue));
-        let mut events = PollFlags::empty();
+        table.map(|e| e.insert(self.terminal.read_queue()));
+        let mut events = PollFlags::OUT;
 
-        if self.stdin.lock_irq().is_complete() {
+        if self.terminal.can_read() {
             events.insert(PollFlags::IN);
         }
 
@@ -292,35 +284,7 @@ impl INodeInterface for Tty {
                 Ok(0x00)
             }
 
-            aero_syscall::TCGETS => {
-                let termios = VirtAddr::new(arg as u64);
-                let termios = unsafe { &mut *(termios.as_mut_ptr::<aero_syscall::Termios>()) };
-
-                let lock = TERMIOS.lock_irq();
-                let this = &*lock;
-
-                *termios = this.clone();
-                Ok(0x00)
-            }
-
-            aero_syscall::TCSETSF => {
-                // Allow the output buffer to drain, discard pending input.
-                let mut stdin = self.stdin.lock_irq();
-                stdin.back_buffer.clear();
-                stdin.cursor = 0;
-                core::mem::drop(stdin);
-
-                let termios = VirtAddr::new(arg as u64);
-                let termios = unsafe { &*(termios.as_mut_ptr::<aero_syscall::Termios>()) };
-
-                let mut lock = TERMIOS.lock_irq();
-                let this = &mut *lock;
-
-                *this = termios.clone();
-                Ok(0x00)
-            }
-
-            _ => Err(fs::FileSystemError::NotSupported),
+            _ => self.terminal.ioctl(command, arg),
         }
     }
 }
@@ -343,117 +307,8 @@ impl devfs::Device for Tty {
 impl KeyboardListener for Tty {
     fn on_key(&self, key: KeyCode, released: bool) {
         let mut state = self.state.lock();
-        let termios = TERMIOS.lock_irq();
-
-        let push_str = |k: &str| {
-            // TODO: decckm
-            let mut stdin = self.stdin.lock_irq();
-
-            for each in k.bytes() {
-                stdin.back_buffer.push(each);
-            }
-        };
-
-        let lchar = || {
-            let mut shift = state.lshift || state.rshift;
-            let ctrl = state.lctrl || state.rctrl;
-
-            if state.caps && state.caps {
-                shift = !shift;
-            }
-
-            let map = match (shift, ctrl, state.lalt, state.altgr) {
-                (false, false, false, false) => PLAIN_MAP,
-                (true, false, false, false) => SHIFT_MAP,
-                (false, true, false, false) => CTRL_MAP,
-                (false, false, true, false) => ALT_MAP,
-                (false, false, false, true) => ALTGR_MAP,
-                (true, true, false, false) => SHIFT_CTRL_MAP,
-                (false, true, true, false) => CTRL_ALT_MAP,
-                _ => PLAIN_MAP,
-            };
-
-            let character =
-                unsafe { core::char::from_u32_unchecked((map[key as usize] & 0xff) as _) };
-
-            // Check if the character is actually printable printable.
-            if !(0x20..0x7e).contains(&(character as u32)) {
-                return;
-            }
-
-            {
-                let mut stdin = self.stdin.lock_irq();
-
-                stdin.back_buffer.push(character as u8);
-                stdin.advance_cursor();
-            }
-
-            if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
-                rendy::print!("{}", character);
-            }
-        };
-
-        let backspace = || {
-            if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ICANON) {
-                let mut stdin = self.stdin.lock_irq();
-
-                if stdin.back_buffer.pop().is_some() {
-                    if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
-                        rendy::backspace();
-                        stdin.cursor -= 1;
-                    }
-                }
-            } else {
-                push_str("\x08");
-            }
-        };
-
-        if !termios.c_lflag.contains(aero_syscall::TermiosLFlag::ICANON) && !released {
-            match key {
-                KeyCode::KEY_BACKSPACE if !released => backspace(),
-                KeyCode::KEY_CAPSLOCK if !released => state.caps = !state.caps,
-
-                KeyCode::KEY_LEFTSHIFT => state.lshift = !released,
-                KeyCode::KEY_RIGHTSHIFT => state.rshift = !released,
-
-                KeyCode::KEY_LEFTCTRL => state.lctrl = !released,
-                KeyCode::KEY_RIGHTCTRL => state.rctrl = !released,
-
-                KeyCode::KEY_LEFTALT => state.lalt = !released,
-                KeyCode::KEY_RIGHTALT => state.altgr = !released,
-                KeyCode::KEY_ENTER => push_str("\n"),
-
-                KeyCode::KEY_UP => push_str("\x1b[A"),
-                KeyCode::KEY_LEFT => push_str("\x1b[D"),
-                KeyCode::KEY_DOWN => push_str("\x1b[B"),
-                KeyCode::KEY_RIGHT => push_str("\x1b[C"),
-
-                _ if !released => lchar(),
-                _ => {}
-            }
-
-            self.stdin.lock_irq().cursor = 0;
-            self.block_queue.notify_complete();
-            return;
-        }
 
         match key {
-            KeyCode::KEY_CAPSLOCK if !released => state.caps = !state.caps,
-            KeyCode::KEY_ENTER | KeyCode::KEY_KPENTER if !released => {
-                let mut stdin = self.stdin.lock_irq();
-
-                stdin.back_buffer.push('\n' as u8);
-                stdin.cursor = 0;
-
-                if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
-                    rendy::print!("\n");
-                }
-
-                self.block_queue.notify_complete();
-            }
-
-            KeyCode::KEY_BACKSPACE if !released => backspace(),
-
             KeyCode::KEY_LEFTSHIFT => state.lshift = !released,
             KeyCode::KEY_RIGHTSHIFT => state.rshift = !released,
 
@@ -463,39 +318,39 @@ impl KeyboardListener for Tty {
             KeyCode::KEY_LEFTALT => state.lalt = !released,
             KeyCode::KEY_RIGHTALT => state.altgr = !released,
 
-            KeyCode::KEY_LEFT if !released => {
-                let mut stdin = self.stdin.lock_irq();
+            _ if released => {}
 
-                // We are at the start of the input so, we cannot shift
-                // the cursor to the left anymore.
-                if stdin.cursor == 0 {
-                    return;
-                }
+            KeyCode::KEY_CAPSLOCK => state.caps = !state.caps,
 
-                let (x, y) = rendy::get_cursor_position();
-                rendy::set_cursor_position(x - 1, y);
+            // Shift+PgUp and Shift+PgDn scroll the console through the scrollback buffer.
+            KeyCode::KEY_PAGEUP | KeyCode::KEY_PAGEDOWN if state.lshift || state.rshift => {
+                let (rows, _) = rendy::get_rows_cols();
+                let lines = (rows / 2) as isize;
 
-                stdin.cursor -= 1;
+                if key == KeyCode::KEY_PAGEUP {
+                    rendy::scroll_view(lines);
+                } else {
+                    rendy::scroll_view(-lines);
+                }
             }
 
-            KeyCode::KEY_RIGHT if !released => {
-                let mut stdin = self.stdin.lock_irq();
+            // The line discipline translates CR to NL (ICRNL) and handles the
+            // erase character, like on a real terminal.
+            KeyCode::KEY_ENTER | KeyCode::KEY_KPENTER => self.receive(&mut state, b"\r"),
+            KeyCode::KEY_BACKSPACE => self.receive(&mut state, b"\x7f"),
 
-                // We are at the end of the input so, we cannot shift
-                // the cursor to the right anymore.
-                if stdin.cursor == stdin.back_buffer.len() {
-                    return;
+            // TODO: decckm
+            KeyCode::KEY_UP => self.receive(&mut state, b"\x1b[A"),
+            KeyCode::KEY_LEFT => self.receive(&mut state, b"\x1b[D"),
+            KeyCode::KEY_DOWN => self.receive(&mut state, b"\x1b[B"),
+            KeyCode::KEY_RIGHT => self.receive(&mut state, b"\x1b[C"),
+
+            _ => {
+                if let Some(bytes) = state.translate(key) {
+                    let len = if bytes[1] == 0 { 1 } else { 2 };
+                    self.receive(&mut state, &bytes[..len]);
                 }
-
-                let (x, y) = rendy::get_cursor_position();
-                rendy::set_cursor_position(x + 1, y);
-
-                stdin.advance_cursor();
             }
-
-            _ if !released => lchar(),
-
-            _ => {}
         }
     }
 }
@@ -537,7 +392,19 @@ fn fixed_to_rgb(fixed: u16) -> u32 {
     fixed as u32
 }
 
-struct AnsiEscape;
+/// Returns the first parameter of the sequence, or `default` if it is missing or zero.
+fn first_param(params: &vte::Params, default: usize) -> usize {
+    match params.iter().next() {
+        Some(&[n, ..]) if n != 0 => n as usize,
+        _ => default,
+    }
+}
+
+#[derive(Default)]
+struct AnsiEscape {
+    /// Set by the bold SGR attribute, selects the bright variants of the colors.
+    bright: bool,
+}
 
 impl vte::Perform for AnsiEscape {
     fn print(&mut self, char: char) {
@@ -555,14 +422,34 @@ impl vte::Perform for AnsiEscape {
                 rendy::set_cursor_position(0, y)
             }
 
+            // Backspace moves the cursor one column to the left, without erasing.
             '\u{8}' => {
-                rendy::backspace();
+                let (x, y) = rendy::get_cursor_position();
+
+                if x > 0 {
+                    rendy::set_cursor_position(x - 1, y);
+                }
             }
 
             _ => {}
         }
     }
 
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        match byte {
+            // DECSC: Save the cursor position.
+            b'7' => rendy::save_cursor_position(),
+            // DECRC: Restore the cursor position.
+            b'8' => rendy::restore_cursor_position(),
+
+            _ => log::debug!("unknown escape: {}", byte as char),
+        }
+    }
+
     fn csi_dispatch(
         &mut self,
         params: &vte::Params,
@@ -580,25 +467,16 @@ impl vte::Perform for AnsiEscape {
             'H' | 'f' => {
                 let mut iter = params.iter();
 
-                let x = iter.next().unwrap_or(&[1])[0] as usize;
                 let y = iter.next().unwrap_or(&[1])[0] as usize;
+                let x = iter.next().unwrap_or(&[1])[0] as usize;
 
-                let mut x = if x != 0 { x - 1 } else { x };
-                let mut y = if y != 0 { y - 1 } else { y };
+                let x = x.saturating_sub(1);
+                let y = y.saturating_sub(1);
 
                 let (rows, cols) = rendy::get_term_info();
 
                 // Make sure the provided coordinates are valid.
-                if x >= cols {
-                    x = cols - 1;
-                }
-
-                if y >= rows {
-                    y = rows - 1;
-                }
-
-                // Move the cursor to the position.
-                rendy::set_cursor_position(x, y);
+                rendy::set_cursor_position(x.min(cols - 1), y.min(rows - 1));
             }
 
             'l' | 'h' => match params.iter().next() {
@@ -608,135 +486,111 @@ impl vte::Perform for AnsiEscape {
                     rendy::set_cursor_visibility(action == 'h')
                 }
 
-                _ => unimplemented!(),
+                mode => log::debug!("unknown mode: {:?} (action={})", mode, action),
             },
 
-            // Clears parts of the screen.
-            'J' => {
-                // If `n` is missing, it defaults to 0.
-                let n = params.iter().next().unwrap_or(&[0])[0] as usize;
-
-                match n {
-                    // If `n` is 0 (or missing), clear from cursor to end of screen.
-                    0 => {
-                        let (x, y) = rendy::get_cursor_position();
-                        let (term_rows, term_cols) = rendy::get_rows_cols();
+            // Moves the cursor `n` (default 1) cells in the given direction. If the
+            // cursor is already at the edge of the screen, this has no effect.
+            'A' | 'B' | 'C' | 'D' | 'E' | 'F' => {
+                let n = first_param(params, 1);
 
-                        let rows_remaining = term_rows - (y + 1);
-                        let cols_diff = term_cols - (x + 1);
-                        let to_clear = rows_remaining * term_cols + cols_diff;
+                let (x, y) = rendy::get_cursor_position();
+                let (rows, cols) = rendy::get_rows_cols();
 
-                        rendy::set_auto_flush(false);
+                let (x, y) = match action {
+                    'A' => (x, y.saturating_sub(n)),
+                    'B' => (x, (y + n).min(rows - 1)),
+                    'C' => ((x + n).min(cols - 1), y),
+                    'D' => (x.saturating_sub(n), y),
+                    // Moves the cursor to the beginning of the line `n` lines down.
+                    'E' => (0, (y + n).min(rows - 1)),
+                    // Moves the cursor to the beginning of the line `n` lines up.
+                    _ => (0, y.saturating_sub(n)),
+                };
 
-                        for _ in 0..to_clear {
-                            rendy::print!(" ");
-                        }
+                rendy::set_cursor_position(x, y);
+            }
 
-                        rendy::set_cursor_position(x, y);
-                        rendy::double_buffer_flush();
-                        rendy::set_auto_flush(true);
-                    }
+            // Moves the cursor to column `n` (default 1).
+            'G' => {
+                let (_, y) = rendy::get_cursor_position();
+                let (_, cols) = rendy::get_rows_cols();
 
-                    1 => unimplemented!(),
+                rendy::set_cursor_position((first_param(params, 1) - 1).min(cols - 1), y);
+            }
 
-                    // If `n` is 2 or 3, clear the entire screen.
-                    //
-                    // TODO(Andy-Python-Programmer): When we support scrollback buffer, if `n` is
-                    // 3, clear the entire scrollback buffer as well.
-                    2 | 3 => rendy::clear_screen(false),
+            // Moves the cursor to row `n` (default 1).
+            'd' => {
+                let (x, _) = rendy::get_cursor_position();
+                let (rows, _) = rendy::get_rows_cols();
 
-                    // Unknown value, do nothing.
-                    _ => unimplemented!(),
-                }
+                rendy::set_cursor_position(x, (first_param(params, 1) - 1).min(rows - 1));
             }
 
-            'C' => {
-                // If `n` is missing, it defaults to 1.
-                let mut n = params.iter().next().unwrap_or(&[1])[0] as usize;
-
+            // Clears parts of the screen. The cursor position does not change.
+            'J' => {
                 let (x, y) = rendy::get_cursor_position();
-                let (_, term_cols) = rendy::get_rows_cols();
+                let (rows, cols) = rendy::get_rows_cols();
 
-                if x + n > term_cols - 1 {
-                    n = (term_cols - 1) - x;
-                }
+                let cursor = y * cols + x;
 
-                if n == 0 {
-                    n = 1;
-                }
+                // If `n` is missing, it defaults to 0.
+                match first_param(params, 0) {
+                    // If `n` is 0 (or missing), clear from cursor to end of screen.
+                    0 => rendy::erase(cursor, rows * cols),
+                    // If `n` is 1, clear from the beginning of the screen to the cursor.
+                    1 => rendy::erase(0, cursor + 1),
+                    // If `n` is 2, clear the entire screen.
+                    2 => rendy::erase(0, rows * cols),
+
+                    // If `n` is 3, clear the entire screen and the scrollback buffer.
+                    3 => {
+                        rendy::erase(0, rows * cols);
+                        rendy::clear_scrollback();
+                    }
 
-                rendy::set_cursor_position(x + n, y);
+                    n => log::debug!("unknown erase mode: {}", n),
+                }
             }
 
+            // Clears parts of the line. The cursor position does not change.
             'K' => {
-                // If `n` is missing, it defaults to 0.
-                let n = params.iter().next().unwrap_or(&[0])[0] as usize;
-
-                // NOTE: The cursor position does not change.
-                match n {
-                    // If `n` is 0 (or missing), clear from cursor to the end of the line.
-                    0 => {
-                        let (x, y) = rendy::get_cursor_position();
-                        let (_, term_cols) = rendy::get_rows_cols();
-
-                        for _ in x..term_cols {
-                            rendy::print!(" ");
-                        }
+                let (x, y) = rendy::get_cursor_position();
+                let (_, cols) = rendy::get_rows_cols();
 
-                        rendy::set_cursor_position(x, y);
-                    }
+                let start = y * cols;
+                let cursor = start + x;
 
+                // If `n` is missing, it defaults to 0.
+                match first_param(params, 0) {
+                    // If `n` is 0 (or missing), clear from cursor to the end of the line.
+                    0 => rendy::erase(cursor, start + cols),
                     // If `n` is 1, clear from cursor to beginning of the line.
-                    1 => {
-                        let (x, y) = rendy::get_cursor_position();
-
-                        rendy::set_cursor_position(0, y);
-
-                        for _ in 0..x {
-                            rendy::print!(" ")
-                        }
-                    }
-
+                    1 => rendy::erase(start, cursor + 1),
                     // If `n` is 2, clear entire line.
-                    2 => {
-                        let (_, term_cols) = rendy::get_rows_cols();
-                        let (x, y) = rendy::get_cursor_position();
-
-                        rendy::set_cursor_position(0, y);
+                    2 => rendy::erase(start, start + cols),
 
-                        for _ in 0..term_cols {
-                            rendy::print!(" ")
-                        }
-
-                        rendy::set_cursor_position(x, y);
-                    }
-
-                    _ => unimplemented!(),
+                    n => log::debug!("unknown erase mode: {}", n),
                 }
             }
 
-            'D' => {
-                // If `n` is missing, it defaults to 1.
-                let mut n = params.iter().next().unwrap_or(&[1])[0] as usize;
-
+            // Erases `n` (default 1) characters from the cursor.
+            'X' => {
                 let (x, y) = rendy::get_cursor_position();
+                let (_, cols) = rendy::get_rows_cols();
 
-                // If the cursor is already at the edge of the screen, this has no effect.
-                if n > x {
-                    n = x;
-                }
-
-                if n == 0 {
-                    n = 1;
-                }
+                let cursor = y * cols + x;
+                let end = y * cols + cols;
 
-                rendy::set_cursor_position(x - n, y);
+                rendy::erase(cursor, (cursor + first_param(params, 1)).min(end));
             }
 
+            's' => rendy::save_cursor_position(),
+            'u' => rendy::restore_cursor_position(),
+
             // Sets colors and style of the characters following this code.
             'm' => {
                 let mut piter = params.iter();
-                let mut bright = false;
 
                 while let Some(param) = piter.next() {
                     if !param.is_empty() {
@@ -745,7 +599,7 @@ impl vte::Perform for AnsiEscape {
                         match p1 {
                             // Reset or normal. All attributes off.
                             0 => {
-                                bright = false;
+                                self.bright = false;
                                 // TODO: Turn off dim.
 
                                 rendy::reset_default();
@@ -753,16 +607,21 @@ impl vte::Perform for AnsiEscape {
 
                             // Bold or increased intensity:
                             1 => {
-                                bright = true;
+                                self.bright = true;
                                 // TODO: Turn off dim.
                             }
 
                             // Faint, decreased intensity, or dim.
-                            2 => {
+                            2 | 22 => {
                                 // TODO: Turn on dim.
-                                bright = false;
+                                self.bright = false;
                             }
 
+                            // Default foreground color.
+                            39 => rendy::set_text_fg(rendy::DEFAULT_TEXT_FOREGROUND),
+                            // Default background color.
+                            49 => rendy::set_text_bg(rendy::DEFAULT_TEXT_BACKGROUND),
+
                             code => {
                                 let parsed_color = if code >= 30 && code <= 37 {
                                     ParsedColor::Foreground(code - SGR_FOREGROUND_OFFSET_1)
@@ -778,7 +637,7 @@ impl vte::Perform for AnsiEscape {
 
                                 match parsed_color {
                                     ParsedColor::Foreground(color) => {
-                                        let ccode = if bright {
+                                        let ccode = if self.bright {
                                             ANSI_BRIGHT_COLORS[color as usize]
                                         } else {
                                             ANSI_COLORS[color as usize]
@@ -788,7 +647,7 @@ impl vte::Perform for AnsiEscape {
                                     }
 
                                     ParsedColor::Background(color) => {
-                                        let ccode = if bright {
+                                        let ccode = if self.bright {
                                             ANSI_BRIGHT_COLORS[color as usize]
                                         } else {
                                             ANSI_COLORS[color as usize]
@@ -872,4 +731,4 @@ fn init_tty() {
     devfs::install_device(TTY.clone()).expect("failed to register tty as a device");
 }
 
-crate::module_init!(init_tty, ModuleType::Other);
+crate::device_initcall!(init_tty);
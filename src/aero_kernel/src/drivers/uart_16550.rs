@@ -95,6 +95,24 @@ impl SerialPort {
         }
     }
 
+    /// Disables the interrupts raised by the serial port, for ports that are polled
+    /// instead (for example, by the GDB stub).
+    pub fn disable_interrupts(&mut self) {
+        unsafe { io::outb(self.0 + 1, 0x00) }
+    }
+
+    /// Blocks until a byte has been received and returns it.
+    pub fn read_byte(&mut self) -> u8 {
+        self.wait_for_line_status(LineStatus::INPUT_FULL);
+        unsafe { io::inb(self.0) }
+    }
+
+    /// Sends the byte as is, without translating the backspace characters.
+    pub fn send_raw(&mut self, byte: u8) {
+        self.wait_for_line_status(LineStatus::OUTPUT_EMPTY);
+        unsafe { io::outb(self.0, byte) }
+    }
+
     pub fn send_byte(&mut self, byte: u8) {
         unsafe {
             match byte {
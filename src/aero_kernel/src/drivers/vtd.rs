@@ -0,0 +1,594 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Intel VT-d DMA remapping. Each remapping unit translates the DMA requests of the
+//! devices in its scope using a root table (indexed by bus) that points to context tables
+//! (indexed by device and function), which in turn point to the page tables of the domain
+//! the device belongs to.
+//!
+//! The devices start in pass-through mode, so the drivers that do not know about the
+//! IOMMU keep working. A driver isolates its device with [`attach`], after which the
+//! device can only access the buffers mapped with [`map`] (which is done by the DMA API,
+//! see [`crate::mem::dma`]) and the reserved memory regions of the firmware. The buffers
+//! are mapped at their physical address, so the bus address of a buffer does not depend
+//! on whether the device is attached.
+//!
+//! Remapping is enabled with the `iommu` kernel command line option. The units that do
+//! not support pass-through are left disabled.
+//!
+//! **Notes**: Intel Virtualization Technology for Directed I/O Architecture Specification
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::acpi::dmar::{self, DeviceScope, DeviceScopeType};
+use crate::drivers::pci::PciHeader;
+use crate::mem::dma::{dma_alloc_coherent, DmaFlags, DmaRegion};
+use crate::mem::paging::{PageSize, PhysAddr, Size4KiB, VirtAddr};
+use crate::utils::sync::Mutex;
+
+/// Capability register. Read-only.
+const VTD_CAP: u64 = 0x08;
+/// Extended Capability register. Read-only.
+const VTD_ECAP: u64 = 0x10;
+/// Global Command register. Write-only.
+const VTD_GCMD: u64 = 0x18;
+/// Global Status register. Read-only.
+const VTD_GSTS: u64 = 0x1c;
+/// Root Table Address register. Read/write.
+const VTD_RTADDR: u64 = 0x20;
+/// Context Command register. Read/write.
+const VTD_CCMD: u64 = 0x28;
+
+const CAP_RWBF: u64 = 1 << 4;
+const CAP_SAGAW_39BIT: u64 = 1 << 9;
+const CAP_SAGAW_48BIT: u64 = 1 << 10;
+
+const ECAP_COHERENT: u64 = 1 << 0;
+const ECAP_PASS_THROUGH: u64 = 1 << 6;
+
+const GCMD_TE: u32 = 1 << 31;
+const GCMD_SRTP: u32 = 1 << 30;
+const GCMD_WBF: u32 = 1 << 27;
+
+/// The one-shot bits of the global status register, which must not be written back to
+/// the global command register.
+const GSTS_ONE_SHOT_MASK: u32 = 0x96ff_ffff;
+
+const CCMD_ICC: u64 = 1 << 63;
+const CCMD_GLOBAL: u64 = 1 << 61;
+
+const IOTLB_IVT: u64 = 1 << 63;
+const IOTLB_GLOBAL: u64 = 1 << 60;
+const IOTLB_DRAIN_READS: u64 = 1 << 49;
+const IOTLB_DRAIN_WRITES: u64 = 1 << 48;
+
+const ENTRY_PRESENT: u64 = 1 << 0;
+const ENTRY_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const CONTEXT_TT_PASS_THROUGH: u64 = 0b10 << 2;
+
+const PTE_READ: u64 = 1 << 0;
+const PTE_WRITE: u64 = 1 << 1;
+
+/// The domain ID of the pass-through devices. The isolated domains start after it.
+const PASS_THROUGH_DOMAIN: u16 = 1;
+
+static UNITS: Once<Vec<Mutex<RemappingUnit>>> = Once::new();
+
+/// An isolated address space of a device.
+struct Domain {
+    /// The top level page table.
+    root: DmaRegion,
+    /// The lower level page tables.
+    tables: Vec<DmaRegion>,
+}
+
+struct RemappingUnit {
+    registers: VirtAddr,
+    cap: u64,
+    ecap: u64,
+
+    include_all: bool,
+    devices: Vec<PciHeader>,
+
+    /// The amount of page table levels (3 for 39-bit and 4 for 48-bit addresses).
+    levels: usize,
+
+    root_table: DmaRegion,
+    context_tables: BTreeMap<u8, DmaRegion>,
+
+    domains: BTreeMap<PciHeader, Domain>,
+    next_domain: u16,
+}
+
+/// Flushes the cache line containing `ptr`, for the units that do not snoop the caches
+/// when walking the tables.
+fn flush_cache_line(ptr: *const u64) {
+    unsafe { core::arch::x86_64::_mm_clflush(ptr as *const u8) }
+}
+
+/// Returns the PCI address of the device (or bridge) described by the device scope, by
+/// following the path through the bridges.
+fn resolve_device_scope(scope: &DeviceScope) -> Option<PciHeader> {
+    let (last, bridges) = scope.path.split_last()?;
+    let mut bus = scope.start_bus;
+
+    for &(device, function) in bridges {
+        let bridge = PciHeader::new(bus, device, function);
+        // Secondary Bus Number register of the PCI-to-PCI bridge.
+        bus = unsafe { (bridge.read::<u32>(0x18) >> 8) as u8 };
+    }
+
+    Some(PciHeader::new(bus, last.0, last.1))
+}
+
+impl RemappingUnit {
+    unsafe fn read32(&self, register: u64) -> u32 {
+        (self.registers + register).as_ptr::<u32>().read_volatile()
+    }
+
+    unsafe fn read64(&self, register: u64) -> u64 {
+        (self.registers + register).as_ptr::<u64>().read_volatile()
+    }
+
+    unsafe fn write32(&self, register: u64, value: u32) {
+        (self.registers + register)
+            .as_mut_ptr::<u32>()
+            .write_volatile(value)
+    }
+
+    unsafe fn write64(&self, register: u64, value: u64) {
+        (self.registers + register)
+            .as_mut_ptr::<u64>()
+            .write_volatile(value)
+    }
+
+    fn is_coherent(&self) -> bool {
+        self.ecap & ECAP_COHERENT != 0
+    }
+
+    /// Writes an entry of a table that is walked by the hardware.
+    fn write_entry(&self, entry: *mut u64, value: u64) {
+        unsafe { entry.write_volatile(value) }
+
+        if !self.is_coherent() {
+            flush_cache_line(entry);
+        }
+    }
+
+    /// Sets a bit of the global command register and waits until the global status
+    /// register reflects it.
+    fn global_command(&self, command: u32) {
+        unsafe {
+            let status = self.read32(VTD_GSTS) & GSTS_ONE_SHOT_MASK;
+            self.write32(VTD_GCMD, status | command);
+
+            while self.read32(VTD_GSTS) & command == 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Invalidates the context cache and the IOTLB of the unit.
+    fn invalidate(&self) {
+        if self.cap & CAP_RWBF != 0 {
+            unsafe {
+                let status = self.read32(VTD_GSTS) & GSTS_ONE_SHOT_MASK;
+                self.write32(VTD_GCMD, status | GCMD_WBF);
+
+                // Unlike the other commands, the status bit is cleared once the write
+                // buffer is flushed.
+                while self.read32(VTD_GSTS) & GCMD_WBF != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+
+        unsafe {
+            self.write64(VTD_CCMD, CCMD_ICC | CCMD_GLOBAL);
+
+            while self.read64(VTD_CCMD) & CCMD_ICC != 0 {
+                core::hint::spin_loop();
+            }
+
+            // The IOTLB registers are at the offset given by the IRO field.
+            let iotlb = ((self.ecap >> 8) & 0x3ff) * 16 + 8;
+            let command = IOTLB_IVT | IOTLB_GLOBAL | IOTLB_DRAIN_READS | IOTLB_DRAIN_WRITES;
+
+            self.write64(iotlb, command);
+
+            while self.read64(iotlb) & IOTLB_IVT != 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Returns whether the device is in the scope of this unit.
+    fn handles(&self, device: PciHeader) -> bool {
+        self.devices.contains(&device)
+    }
+
+    /// Returns the context entry of the device, allocating the context table of its bus
+    /// if needed.
+    fn context_entry(&mut self, device: PciHeader) -> *mut u64 {
+        let bus = device.bus();
+
+        if !self.context_tables.contains_key(&bus) {
+            let table = dma_alloc_coherent(Size4KiB::SIZE as usize, DmaFlags::empty())
+                .expect("vtd: failed to allocate a context table");
+
+            let root_entry = unsafe {
+                self.root_table
+                    .virt_addr()
+                    .as_mut_ptr::<u64>()
+                    .add(bus as usize * 2)
+            };
+
+            self.write_entry(root_entry, table.bus_addr().as_u64() | ENTRY_PRESENT);
+            self.context_tables.insert(bus, table);
+        }
+
+        let index = (device.device() as usize) << 3 | device.function() as usize;
+        let table = &self.context_tables[&bus];
+
+        unsafe { table.virt_addr().as_mut_ptr::<u64>().add(index * 2) }
+    }
+
+    /// Programs the context entry of the device.
+    fn set_context(&mut self, device: PciHeader, low: u64, domain: u16) {
+        let address_width = if self.levels == 4 { 2 } else { 1 };
+        let entry = self.context_entry(device);
+
+        unsafe {
+            // The high half is written first, as the entry becomes valid when the present
+            // bit of the low half is set.
+            self.write_entry(entry.add(1), address_width | (domain as u64) << 8);
+            self.write_entry(entry, low);
+        }
+    }
+
+    /// Maps the page at `iova` to `phys` in the domain of the device.
+    fn map_page(&mut self, device: PciHeader, iova: u64, phys: PhysAddr, writable: bool) {
+        let levels = self.levels;
+        let coherent = self.is_coherent();
+
+        let domain = match self.domains.get_mut(&device) {
+            Some(domain) => domain,
+            None => return,
+        };
+
+        let write_entry = |entry: *mut u64, value: u64| unsafe {
+            entry.write_volatile(value);
+
+            if !coherent {
+                flush_cache_line(entry);
+            }
+        };
+
+        let mut table = domain.root.virt_addr().as_mut_ptr::<u64>();
+
+        for level in (1..levels).rev() {
+            let index = (iova >> (12 + 9 * level)) as usize & 0x1ff;
+            let entry = unsafe { table.add(index) };
+            let value = unsafe { entry.read_volatile() };
+
+            let next = if value & (PTE_READ | PTE_WRITE) == 0 {
+                let next = dma_alloc_coherent(Size4KiB::SIZE as usize, DmaFlags::empty())
+                    .expect("vtd: failed to allocate a page table");
+
+                let address = next.bus_addr();
+
+                write_entry(entry, address.as_u64() | PTE_READ | PTE_WRITE);
+                domain.tables.push(next);
+
+                address
+            } else {
+                PhysAddr::new(value & ENTRY_ADDRESS_MASK)
+            };
+
+            table = next.as_hhdm_virt().as_mut_ptr::<u64>();
+        }
+
+        let index = (iova >> 12) as usize & 0x1ff;
+        let flags = if writable {
+            PTE_READ | PTE_WRITE
+        } else {
+            PTE_READ
+        };
+
+        write_entry(unsafe { table.add(index) }, phys.as_u64() | flags);
+    }
+
+    /// Unmaps the page at `iova` from the domain of the device. The page tables are kept
+    /// around until the domain is destroyed.
+    fn unmap_page(&mut self, device: PciHeader, iova: u64) {
+        let levels = self.levels;
+        let coherent = self.is_coherent();
+
+        let domain = match self.domains.get(&device) {
+            Some(domain) => domain,
+            None => return,
+        };
+
+        let mut table = domain.root.virt_addr().as_mut_ptr::<u64>();
+
+        for level in (1..levels).rev() {
+            let index = (iova >> (12 + 9 * level)) as usize & 0x1ff;
+            let value = unsafe { table.add(index).read_volatile() };
+
+            if value & (PTE_READ | PTE_WRITE) == 0 {
+                return;
+            }
+
+            table = PhysAddr::new(value & ENTRY_ADDRESS_MASK)
+                .as_hhdm_virt()
+                .as_mut_ptr::<u64>();
+        }
+
+        let entry = unsafe { table.add((iova >> 12) as usize & 0x1ff) };
+
+        unsafe { entry.write_volatile(0) }
+
+        if !coherent {
+            flush_cache_line(entry);
+        }
+    }
+
+    /// Moves the device into a new isolated domain, with the reserved memory regions of
+    /// the device identity mapped.
+    fn attach(&mut self, device: PciHeader) {
+        if self.domains.contains_key(&device) {
+            return;
+        }
+
+        let root = dma_alloc_coherent(Size4KiB::SIZE as usize, DmaFlags::empty())
+            .expect("vtd: failed to allocate a page table");
+
+        let id = self.next_domain;
+        self.next_domain += 1;
+
+        let root_address = root.bus_addr();
+
+        self.domains.insert(
+            device,
+            Domain {
+                root,
+                tables: Vec::new(),
+            },
+        );
+
+        for region in dmar::get().map_or(&[][..], |dmar| &dmar.reserved_regions) {
+            let in_scope = region
+                .devices
+                .iter()
+                .filter_map(resolve_device_scope)
+                .any(|scoped| scoped == device);
+
+            if !in_scope {
+                continue;
+            }
+
+            let mut page = region.base.align_down(Size4KiB::SIZE);
+
+            while page <= region.limit {
+                self.map_page(device, page.as_u64(), page, true);
+                page += Size4KiB::SIZE;
+            }
+        }
+
+        self.set_context(device, root_address.as_u64() | ENTRY_PRESENT, id);
+        self.invalidate();
+
+        log::debug!("vtd: attached {:?} to domain {}", device, id);
+    }
+
+    fn enable(&self) {
+        unsafe { self.write64(VTD_RTADDR, self.root_table.bus_addr().as_u64()) }
+
+        self.global_command(GCMD_SRTP);
+        self.invalidate();
+        self.global_command(GCMD_TE);
+    }
+}
+
+/// Returns the unit that handles the device: the unit that lists it in its scope or the
+/// unit that includes all of the remaining devices.
+fn unit_for(device: PciHeader) -> Option<&'static Mutex<RemappingUnit>> {
+    let units = UNITS.get()?;
+
+    units
+        .iter()
+        .find(|unit| unit.lock_irq().handles(device))
+        .or_else(|| units.iter().find(|unit| unit.lock_irq().include_all))
+}
+
+/// Isolates the device into its own domain. Returns whether the DMA requests of the
+/// device are remapped.
+pub fn attach(device: &PciHeader) -> bool {
+    match unit_for(*device) {
+        Some(unit) => {
+            unit.lock_irq().attach(*device);
+            true
+        }
+
+        None => false,
+    }
+}
+
+/// Maps the physical memory range into the domain of the device (at the same address),
+/// if the device is attached.
+pub fn map(device: &PciHeader, phys: PhysAddr, size: usize, writable: bool) {
+    if let Some(unit) = unit_for(*device) {
+        let mut unit = unit.lock_irq();
+        let mut page = phys.align_down(Size4KiB::SIZE);
+
+        while page < phys + size as u64 {
+            unit.map_page(*device, page.as_u64(), page, writable);
+            page += Size4KiB::SIZE;
+        }
+    }
+}
+
+/// Unmaps the physical memory range from the domain of the device, if the device is
+/// attached.
+pub fn unmap(device: &PciHeader, phys: PhysAddr, size: usize) {
+    if let Some(unit) = unit_for(*device) {
+        let mut unit = unit.lock_irq();
+
+        if !unit.domains.contains_key(device) {
+            return;
+        }
+
+        let mut page = phys.align_down(Size4KiB::SIZE);
+
+        while page < phys + size as u64 {
+            unit.unmap_page(*device, page.as_u64());
+            page += Size4KiB::SIZE;
+        }
+
+        unit.invalidate();
+    }
+}
+
+/// Returns the devices on segment 0, found by scanning the PCI buses.
+fn scan_devices() -> Vec<PciHeader> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let function_count = if PciHeader::new(bus, device, 0).has_multiple_functions() {
+                8
+            } else {
+                1
+            };
+
+            for function in 0..function_count {
+                let header = PciHeader::new(bus, device, function);
+
+                if header.get_vendor().is_valid() {
+                    devices.push(header);
+                }
+            }
+        }
+    }
+
+    devices
+}
+
+pub fn init() {
+    let dmar = match dmar::get() {
+        Some(dmar) => dmar,
+        None => {
+            log::warn!("vtd: DMAR table not found");
+            return;
+        }
+    };
+
+    let devices = scan_devices();
+    let mut units = Vec::new();
+
+    for drhd in dmar.units.iter() {
+        // Only the devices on the first segment are supported.
+        if drhd.segment != 0 {
+            continue;
+        }
+
+        let registers = drhd.register_base.as_hhdm_virt();
+        let (cap, ecap) = unsafe {
+            (
+                (registers + VTD_CAP).as_ptr::<u64>().read_volatile(),
+                (registers + VTD_ECAP).as_ptr::<u64>().read_volatile(),
+            )
+        };
+
+        let levels = if cap & CAP_SAGAW_48BIT != 0 {
+            4
+        } else if cap & CAP_SAGAW_39BIT != 0 {
+            3
+        } else {
+            log::warn!(
+                "vtd: unit at {:?} has no supported address width",
+                registers
+            );
+            continue;
+        };
+
+        if ecap & ECAP_PASS_THROUGH == 0 {
+            log::warn!("vtd: unit at {:?} does not support pass-through", registers);
+            continue;
+        }
+
+        let root_table = dma_alloc_coherent(Size4KiB::SIZE as usize, DmaFlags::empty())
+            .expect("vtd: failed to allocate the root table");
+
+        let mut unit = RemappingUnit {
+            registers,
+            cap,
+            ecap,
+            include_all: drhd.include_all,
+            devices: drhd
+                .devices
+                .iter()
+                .filter(|scope| scope.typ == DeviceScopeType::Endpoint)
+                .filter_map(resolve_device_scope)
+                .collect(),
+            levels,
+            root_table,
+            context_tables: BTreeMap::new(),
+            domains: BTreeMap::new(),
+            next_domain: PASS_THROUGH_DOMAIN + 1,
+        };
+
+        // Start all of the devices of the unit in pass-through mode.
+        for &device in devices.iter() {
+            let handled = unit.handles(device)
+                || (unit.include_all
+                    && !dmar.units.iter().any(|other| {
+                        other
+                            .devices
+                            .iter()
+                            .filter_map(resolve_device_scope)
+                            .any(|scoped| scoped == device)
+                    }));
+
+            if handled {
+                unit.set_context(
+                    device,
+                    CONTEXT_TT_PASS_THROUGH | ENTRY_PRESENT,
+                    PASS_THROUGH_DOMAIN,
+                );
+            }
+        }
+
+        unit.enable();
+
+        log::info!(
+            "vtd: enabled unit at {:?} ({}-level tables)",
+            drhd.register_base,
+            levels
+        );
+
+        units.push(Mutex::new(unit));
+    }
+
+    UNITS.call_once(|| units);
+}
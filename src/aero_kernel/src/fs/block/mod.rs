@@ -22,6 +22,7 @@ mod gpt;
 use gpt::Gpt;
 
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
@@ -32,7 +33,8 @@ use crate::fs::{FileSystem, Result};
 
 use crate::fs::ext2::Ext2;
 use crate::mem::paging::*;
-use crate::utils::sync::Mutex;
+use crate::mem::reclaim::{self, Shrinker};
+use crate::utils::sync::{BlockingMutex, Mutex};
 
 use super::cache::{Cache, CacheArc, CacheItem, Cacheable};
 use super::devfs::{alloc_device_marker, Device};
@@ -45,6 +47,8 @@ struct CachedPage {
     device: Weak<dyn CachedAccess>,
     offset: usize,
     page: PhysFrame,
+    /// Whether the page was modified since it was last written to the disk.
+    dirty: AtomicBool,
 }
 
 impl CachedPage {
@@ -55,9 +59,17 @@ impl CachedPage {
             page: FRAME_ALLOCATOR
                 .allocate_frame()
                 .expect("page_cache: out of memory"),
+            dirty: AtomicBool::new(false),
         }
     }
 
+    fn data(&self) -> &[u8] {
+        let data_ptr = self.page.start_address().as_hhdm_virt().as_ptr::<u8>();
+
+        // SAFETY: The page is initialized with the data on the disk when it is cached.
+        unsafe { core::slice::from_raw_parts(data_ptr, Size4KiB::SIZE as usize) }
+    }
+
     fn data_mut(&self) -> &mut [MaybeUninit<u8>] {
         let data_ptr = self
             .page
@@ -77,6 +89,46 @@ impl CachedPage {
     fn make_key(device: Weak<dyn CachedAccess>, offset: usize) -> PageCacheKey {
         (device.as_ptr() as *const u8 as usize, offset)
     }
+
+    /// Marks the page as dirty. The page is kept in the cache until it is written back.
+    fn mark_dirty(this: &PageCacheItem) {
+        if !this.dirty.swap(true, Ordering::AcqRel) {
+            DIRTY_PAGES.lock_irq().push(this.clone());
+        }
+    }
+
+    /// Writes the page back to the disk. Returns `false` if the write failed, in which
+    /// case the page is still dirty.
+    fn sync(&self) -> bool {
+        // The page is marked clean before it is written, so a concurrent write marks it
+        // dirty again.
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return true;
+        }
+
+        // The page is dropped along with the device.
+        let device = match self.device.upgrade() {
+            Some(device) => device,
+            None => return true,
+        };
+
+        let sector = (self.offset * Size4KiB::SIZE as usize) / device.block_size();
+
+        if device.write_block(sector, self.data()).is_some() {
+            true
+        } else {
+            self.dirty.store(true, Ordering::Release);
+            false
+        }
+    }
+}
+
+impl Drop for CachedPage {
+    fn drop(&mut self) {
+        // The 4KiB frame deallocation path of the frame allocator is disabled, as the
+        // frames may still be shared. The page cache owns its frames exclusively.
+        FRAME_ALLOCATOR.deallocate_contiguous(self.page.start_address(), 0);
+    }
 }
 
 impl Cacheable<PageCacheKey> for CachedPage {
@@ -89,6 +141,54 @@ lazy_static::lazy_static! {
     static ref PAGE_CACHE: Arc<Cache<PageCacheKey, CachedPage>> = Cache::new();
 }
 
+/// The locks of the pages that are being read in from the disk, so that two tasks do not
+/// read in the same page. A lock is removed once nobody is waiting on it.
+static PAGE_CACHE_FILL: Mutex<BTreeMap<PageCacheKey, Arc<BlockingMutex<()>>>> =
+    Mutex::new(BTreeMap::new());
+
+/// The pages that were modified since they were last written back. The list holds a
+/// strong reference to the pages, so they are not evicted before being written back.
+static DIRTY_PAGES: Mutex<Vec<PageCacheItem>> = Mutex::new(Vec::new());
+
+/// The shrinker of the page cache. The dirty pages are written back by `kswapd` and the
+/// clean pages without active references are evicted under memory pressure.
+struct PageCacheShrinker;
+
+impl Shrinker for PageCacheShrinker {
+    fn name(&self) -> &'static str {
+        "page_cache"
+    }
+
+    fn count(&self) -> usize {
+        PAGE_CACHE.unused_len()
+    }
+
+    fn scan(&self, nr: usize) -> usize {
+        PAGE_CACHE.evict_unused(nr)
+    }
+
+    fn writeback(&self) -> usize {
+        let pages = core::mem::take(&mut *DIRTY_PAGES.lock_irq());
+        let mut written = 0;
+        let mut failed = Vec::new();
+
+        for page in pages {
+            if page.sync() {
+                written += 1;
+            } else {
+                failed.push(page);
+            }
+        }
+
+        if !failed.is_empty() {
+            log::debug!("page_cache: failed to write back {} pages", failed.len());
+            DIRTY_PAGES.lock_irq().extend(failed);
+        }
+
+        written
+    }
+}
+
 impl Cache<PageCacheKey, CachedPage> {
     /// Returns the cached page at the given offset, if not present, it will be allocated,
     /// initialized with the data on the disk and placed in the page cache.
@@ -107,17 +207,40 @@ impl Cache<PageCacheKey, CachedPage> {
             return page;
         }
 
-        let page = CachedPage::new(device.clone(), cache_offset);
-        let device = device.upgrade().expect("page_cache: device dropped");
+        // Serialize the misses on the same page. The lock is held while reading from the
+        // disk, so it has to be a sleeping lock.
+        let fill = PAGE_CACHE_FILL
+            .lock_irq()
+            .entry(cache_key)
+            .or_insert_with(|| Arc::new(BlockingMutex::new(())))
+            .clone();
+
+        let guard = fill.lock();
+
+        let page = PAGE_CACHE.get(cache_key).unwrap_or_else(|| {
+            let page = CachedPage::new(device.clone(), cache_offset);
+            let device = device.upgrade().expect("page_cache: device dropped");
+
+            let aligned_offset = align_down(offset as u64, Size4KiB::SIZE) as usize;
+            let sector = aligned_offset / device.block_size();
+
+            device
+                .read_dma(sector, page.data_addr(), Size4KiB::SIZE as usize)
+                .expect("page_cache: failed to read block");
+
+            PAGE_CACHE.make_item_cached(page)
+        });
 
-        let aligned_offset = align_down(offset as u64, Size4KiB::SIZE) as usize;
-        let sector = aligned_offset / device.block_size();
+        core::mem::drop(guard);
 
-        device
-            .read_dma(sector, page.data_addr(), Size4KiB::SIZE as usize)
-            .expect("page_cache: failed to read block");
+        // The lock is only referenced by the map and us if nobody else is waiting on it.
+        let mut fills = PAGE_CACHE_FILL.lock_irq();
 
-        PAGE_CACHE.make_item_cached(page)
+        if Arc::strong_count(&fill) == 2 {
+            fills.remove(&cache_key);
+        }
+
+        page
     }
 }
 
@@ -145,8 +268,6 @@ pub trait CachedAccess: BlockDeviceInterface {
             let data = &page.data_mut()[page_offset..page_offset + size];
             dest[loc..loc + size].copy_from_slice(data);
 
-            core::mem::forget(page);
-
             loc += size;
             offset = align_down(offset as u64 + Size4KiB::SIZE, Size4KiB::SIZE) as usize;
         }
@@ -159,7 +280,8 @@ pub trait CachedAccess: BlockDeviceInterface {
     ///
     /// ## Notes
     ///
-    /// * This function does **not** sync the written data to the disk.
+    /// * This function does **not** sync the written data to the disk. The modified
+    ///   pages are written back by `kswapd` (see [`crate::mem::reclaim`]).
     fn write(&self, mut offset: usize, buffer: &[u8]) -> Option<usize> {
         let mut loc = 0;
 
@@ -174,7 +296,7 @@ pub trait CachedAccess: BlockDeviceInterface {
                 &buffer[loc..loc + size],
             );
 
-            core::mem::forget(page);
+            CachedPage::mark_dirty(&page);
 
             loc += size;
             offset = align_down(offset as u64 + Size4KiB::SIZE, Size4KiB::SIZE) as usize;
@@ -306,6 +428,8 @@ impl BlockDeviceInterface for PartitionBlockDevice {
 }
 
 pub fn launch() -> Result<()> {
+    reclaim::register_shrinker(Arc::new(PageCacheShrinker));
+
     let mut blocks_copy = Vec::<Arc<BlockDevice>>::new();
 
     for (_, device) in BLOCK_DEVS.lock().iter() {
@@ -338,6 +462,16 @@ pub fn launch() -> Result<()> {
 
                 install_block_device(device.clone())?;
 
+                // The root partition can be selected with the `root=` kernel command
+                // line option, otherwise the first ext2 partition found is mounted.
+                let is_root = crate::cmdline::get()
+                    .root
+                    .map_or(true, |root| root == device.name());
+
+                if !is_root {
+                    continue;
+                }
+
                 // Check what filesystem is on this partition and mount it.
                 if let Some(ext2) = Ext2::new(device.clone()) {
                     log::info!("gpt: found ext2 filesystem on {}!", device.name());
@@ -349,11 +483,25 @@ pub fn launch() -> Result<()> {
         }
     }
 
+    // Fall back to the initramfs when no root partition was found.
+    if super::ROOT_DIR.get().is_none() {
+        if let Some(archive) = crate::cmdline::get().initrd {
+            let ramfs = super::initramfs::unpack(archive)?;
+            log::info!("initramfs: unpacked {} bytes", archive.len());
+
+            super::ROOT_FS.call_once(|| ramfs.clone());
+            super::ROOT_DIR.call_once(|| ramfs.root_dir());
+        }
+    }
+
     super::devfs::init()?;
     log::info!("installed devfs");
 
     super::procfs::init()?;
     log::info!("installed procfs");
 
+    super::sysfs::init()?;
+    log::info!("installed sysfs");
+
     Ok(())
 }
@@ -28,7 +28,6 @@
 use core::borrow::Borrow;
 use core::fmt::Debug;
 use core::hash::Hash;
-use core::num::NonZeroUsize;
 use core::ops;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
@@ -40,6 +39,7 @@ use alloc::vec::Vec;
 use spin::Once;
 
 use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::mem::reclaim::{self, Shrinker};
 use crate::utils::sync::Mutex;
 
 use super::FileSystem;
@@ -191,6 +191,9 @@ struct CacheIndex<K: CacheKey, V: Cacheable<K>> {
     /// with them. These are stored in the cache index so, if the item is
     /// accessed again, we can re-use it; reducing required memory allocation
     /// and I/O (if applicable).
+    ///
+    /// The list is not bounded, the least recently used items are freed by the
+    /// shrinker of the cache under memory pressure (see [`reclaim`]).
     unused: lru::LruCache<K, Arc<CacheItem<K, V>>>,
 }
 
@@ -204,7 +207,7 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
         Arc::new_cyclic(|this| Cache::<K, V> {
             index: Mutex::new(CacheIndex {
                 used: hashbrown::HashMap::new(),
-                unused: lru::LruCache::new(NonZeroUsize::new(4096).unwrap()),
+                unused: lru::LruCache::unbounded(),
             }),
             self_ref: this.clone(),
         })
@@ -274,6 +277,27 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
         }
     }
 
+    /// Returns the number of cache items without active strong references.
+    pub fn unused_len(&self) -> usize {
+        self.index.lock_irq().unused.len()
+    }
+
+    /// Frees up to `nr` of the least recently used cache items without active strong
+    /// references and returns the number of items freed.
+    pub fn evict_unused(&self, nr: usize) -> usize {
+        let evicted = {
+            let mut index = self.index.lock_irq();
+
+            (0..nr)
+                .map_while(|_| index.unused.pop_lru().map(|(_, item)| item))
+                .collect::<Vec<_>>()
+        };
+
+        // The items are dropped after the lock is released, as dropping them may
+        // drop other cache items.
+        evicted.len()
+    }
+
     fn mark_item_unused(&self, item: CacheArc<CacheItem<K, V>>) {
         item.set_used(false);
 
@@ -285,6 +309,23 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
     }
 }
 
+impl<K: CacheKey, V: Cacheable<K>> Shrinker for Cache<K, V>
+where
+    Self: Send + Sync,
+{
+    fn name(&self) -> &'static str {
+        core::any::type_name::<V>()
+    }
+
+    fn count(&self) -> usize {
+        self.unused_len()
+    }
+
+    fn scan(&self, nr: usize) -> usize {
+        self.evict_unused(nr)
+    }
+}
+
 impl<K: CacheKey, T: Cacheable<K>> CacheDropper for CacheItem<K, T> {
     fn drop_this(&self, this: Arc<Self>) {
         if let Some(cache) = self.cache.upgrade() {
@@ -358,9 +399,17 @@ impl DirCacheImpl for DirCacheItem {
         let mut path_nodes = Vec::new();
         let mut result = String::new();
 
+        let root = super::current_root();
+
         // We need to collect all of the path nodes, reverse them and then join them
-        // with the path separator.
+        // with the path separator. The path is relative to the root directory of the
+        // current task.
         while let Some(entry) = current_entry {
+            if entry.cache_key() == root.cache_key() {
+                path_nodes.push(String::from("/"));
+                break;
+            }
+
             path_nodes.push(entry.name());
             current_entry = entry.data.lock().parent.clone();
         }
@@ -404,4 +453,7 @@ pub fn dcache() -> &'static Arc<DirCache> {
 pub fn init() {
     INODE_CACHE.call_once(|| INodeCache::new());
     DIR_CACHE.call_once(|| DirCache::new());
+
+    reclaim::register_shrinker(icache().clone());
+    reclaim::register_shrinker(dcache().clone());
 }
@@ -37,7 +37,7 @@ use super::cache::{DirCacheItem, INodeCacheItem};
 use super::inode::{INodeInterface, PollFlags, PollTable};
 use super::ramfs::RamFs;
 use super::FileSystemError;
-use super::{FileSystem, Result, MOUNT_MANAGER};
+use super::{FileSystem, Result, INIT_MOUNT_NAMESPACE};
 
 use aero_syscall::{prelude::*, MMapFlags};
 
@@ -190,6 +190,117 @@ impl INodeInterface for DevNull {
     }
 }
 
+struct DevZero(usize);
+
+impl DevZero {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for DevZero {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("zero")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        DEV_ZERO.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for DevZero {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        Ok(buffer.len())
+    }
+
+    // Every page of the mapping is a newly allocated zeroed frame, so that mapping
+    // `/dev/zero` is the same as an anonymous mapping.
+    fn mmap(&self, _offset: usize, _size: usize, _flags: MMapFlags) -> Result<PhysFrame> {
+        FRAME_ALLOCATOR
+            .allocate_frame()
+            .ok_or(FileSystemError::NotSupported)
+    }
+}
+
+struct DevFull(usize);
+
+impl DevFull {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for DevFull {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("full")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        DEV_FULL.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for DevFull {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        buffer.fill(0);
+        Ok(buffer.len())
+    }
+
+    // The device is always full.
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> Result<usize> {
+        Err(FileSystemError::NoSpace)
+    }
+}
+
+struct DevRandom(usize);
+
+impl DevRandom {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for DevRandom {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("random")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        DEV_RANDOM.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for DevRandom {
+    // Unlike `/dev/urandom`, reading from `/dev/random` blocks until the CSPRNG is
+    // seeded.
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        crate::random::fill_bytes_blocking(buffer)?;
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        crate::random::add_data(buffer);
+        Ok(buffer.len())
+    }
+}
+
 struct DevKmsg(usize);
 
 impl DevKmsg {
@@ -216,7 +327,11 @@ impl INodeInterface for DevKmsg {
     fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
         let buf = logger::get_log_buffer();
 
-        let size = core::cmp::min(buffer.len(), buf.len());
+        if offset >= buf.len() {
+            return Ok(0);
+        }
+
+        let size = core::cmp::min(buffer.len(), buf.len() - offset);
         buffer[..size].copy_from_slice(&buf.as_bytes()[offset..offset + size]);
 
         Ok(size)
@@ -363,21 +478,24 @@ impl INodeInterface for DevFb {
     fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
         match command {
             FBIOGET_VSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferVScreenInfo) };
+                let struc = crate::utils::validate_mut_ptr(arg as *mut FramebufferVScreenInfo)
+                    .ok_or(FileSystemError::BadAddress)?;
 
                 *struc = self.vinfo.read().clone();
                 Ok(0x00)
             }
 
             FBIOPUT_VSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferVScreenInfo) };
+                let struc = crate::utils::validate_mut_ptr(arg as *mut FramebufferVScreenInfo)
+                    .ok_or(FileSystemError::BadAddress)?;
                 *self.vinfo.write() = struc.clone();
 
                 Ok(0x00)
             }
 
             FBIOGET_FSCREENINFO => {
-                let struc = unsafe { &mut *(arg as *mut FramebufferFScreenInfo) };
+                let struc = crate::utils::validate_mut_ptr(arg as *mut FramebufferFScreenInfo)
+                    .ok_or(FileSystemError::BadAddress)?;
 
                 *struc = self.finfo.clone();
                 Ok(0x00)
@@ -430,16 +548,23 @@ impl Device for DevUrandom {
 }
 
 impl INodeInterface for DevUrandom {
+    // Reading from `/dev/urandom` never blocks, even if the CSPRNG is not seeded yet.
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
-        for (_, b) in buffer.iter_mut().enumerate() {
-            *b = 0;
-        }
+        crate::random::fill_bytes(buffer);
+        Ok(buffer.len())
+    }
 
+    // The written data is mixed into the entropy pool, without being credited.
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        crate::random::add_data(buffer);
         Ok(buffer.len())
     }
 }
 
 static DEV_NULL: Once<Arc<DevNull>> = Once::new();
+static DEV_ZERO: Once<Arc<DevZero>> = Once::new();
+static DEV_FULL: Once<Arc<DevFull>> = Once::new();
+static DEV_RANDOM: Once<Arc<DevRandom>> = Once::new();
 static DEV_KMSG: Once<Arc<DevKmsg>> = Once::new();
 static DEV_FB: Once<Arc<DevFb>> = Once::new();
 static DEV_URANDOM: Once<Arc<DevUrandom>> = Once::new();
@@ -449,17 +574,23 @@ pub(super) fn init() -> Result<()> {
     lazy_static::initialize(&DEV_FILESYSTEM);
 
     let inode = lookup_path(Path::new("/dev"))?;
-    MOUNT_MANAGER.mount(inode, DEV_FILESYSTEM.clone())?;
+    INIT_MOUNT_NAMESPACE.mount(inode, DEV_FILESYSTEM.clone())?;
 
     let rendy_info = crate::rendy::get_rendy_info();
 
     {
         let null = DEV_NULL.call_once(|| DevNull::new());
+        let zero = DEV_ZERO.call_once(|| DevZero::new());
+        let full = DEV_FULL.call_once(|| DevFull::new());
+        let random = DEV_RANDOM.call_once(|| DevRandom::new());
         let kmsg = DEV_KMSG.call_once(|| DevKmsg::new());
         let fb = DEV_FB.call_once(|| DevFb::new(rendy_info));
         let urandom = DEV_URANDOM.call_once(|| DevUrandom::new());
 
         install_device(null.clone())?;
+        install_device(zero.clone())?;
+        install_device(full.clone())?;
+        install_device(random.clone())?;
         install_device(kmsg.clone())?;
         install_device(fb.clone())?;
         install_device(urandom.clone())?;
@@ -23,14 +23,36 @@ use aero_syscall::SyscallError;
 use alloc::sync::Arc;
 use hashbrown::HashMap;
 
+use crate::timer::{self, Timer};
 use crate::userland::scheduler;
 use crate::utils::sync::Mutex;
 
 use super::inode::{INodeInterface, PollTable};
 use super::FileSystemError;
 
+/// An entry of the interest list.
+struct EPollItem {
+    event: EPollEvent,
+    /// The events reported by the last wait, used to only report the new events of the
+    /// edge triggered (`EPOLLET`) items.
+    reported: EPollEventFlags,
+    /// The wake up counter of the file when it was last polled (see
+    /// [`PollTable::wakeups_since`]).
+    wakeups: usize,
+}
+
+impl EPollItem {
+    fn new(event: EPollEvent) -> Self {
+        Self {
+            event,
+            reported: EPollEventFlags::empty(),
+            wakeups: 0,
+        }
+    }
+}
+
 pub struct EPoll {
-    events: Mutex<HashMap<usize, EPollEvent>>,
+    events: Mutex<HashMap<usize, EPollItem>>,
 }
 
 impl EPoll {
@@ -59,7 +81,7 @@ impl EPoll {
             return Err(SyscallError::EEXIST);
         }
 
-        events.insert(fd, event);
+        events.insert(fd, EPollItem::new(event));
         Ok(())
     }
 
@@ -90,10 +112,92 @@ impl EPoll {
             return Err(SyscallError::ENOENT);
         }
 
-        events.insert(fd, event);
+        events.insert(fd, EPollItem::new(event));
         Ok(())
     }
 
+    /// Polls the items of the interest list and delivers up to `max_events` ready events.
+    /// The current task is registered on the wait queues of the polled files, so it is
+    /// woken up by any change that happens after its file was polled.
+    fn collect(
+        &self,
+        ret_events: &mut [EPollEvent],
+        max_events: usize,
+        table: &mut PollTable,
+    ) -> Result<usize, FileSystemError> {
+        let current_task = scheduler::get_scheduler().current_task();
+        let file_table = &current_task.file_table;
+
+        let mut items = self.events.lock();
+        let mut n = 0;
+
+        for (fd, item) in items.iter_mut() {
+            if n == max_events {
+                break;
+            }
+
+            let flags = item.event.events;
+
+            // If the event mask does not contain any poll(2) events, the event
+            // descriptor is disabled.
+            if (flags & !Self::PRIVATE_BITS).is_empty() {
+                continue;
+            }
+
+            // The file descriptor was closed without being removed from the interest
+            // list.
+            let handle = match file_table.get_handle(*fd) {
+                Some(handle) => handle,
+                None => continue,
+            };
+
+            let start = table.queues.len();
+            let ready: EPollEventFlags = handle.inode().poll(Some(table))?.into();
+            let wakeups = table.wakeups_since(start);
+
+            // The error and hang up events are always reported.
+            let mut events = ready & (flags | EPollEventFlags::ERR | EPollEventFlags::HUP);
+
+            if flags.contains(EPollEventFlags::ET) {
+                // A wake up of the file (for example, more data arriving on a readable
+                // pipe) is a new edge, so the events that are still ready are reported
+                // again.
+                if wakeups != item.wakeups {
+                    item.reported = EPollEventFlags::empty();
+                }
+
+                item.wakeups = wakeups;
+
+                // Only report the events that became ready since the last wait. The
+                // events that are no longer ready are forgotten, so they are reported
+                // again once they become ready.
+                let new_events = events & !item.reported;
+
+                item.reported = events;
+                events = new_events;
+            }
+
+            if events.is_empty() {
+                continue;
+            }
+
+            ret_events[n] = EPollEvent {
+                events,
+                data: item.event.data,
+            };
+
+            if flags.contains(EPollEventFlags::ONESHOT) {
+                // The `EPOLLONESHOT` bit that disables the descriptor when an event is
+                // received, until the next `EPOLL_CTL_MOD` will be issued.
+                item.event.events = flags & Self::PRIVATE_BITS;
+            }
+
+            n += 1;
+        }
+
+        Ok(n)
+    }
+
     /// Retrieves ready events, and delivers them to the caller-supplied event buffer and
     /// returns the number of ready events if the call was successful.
     ///
@@ -121,99 +225,34 @@ impl EPoll {
         max_events: usize,
         timeout: usize,
     ) -> Result<usize, FileSystemError> {
-        let current_task = scheduler::get_scheduler().current_task();
-        let file_table = &current_task.file_table;
-
-        let mut table = self.events.lock();
-        let mut n = 0;
-
-        let mut fds = alloc::vec![];
-        let mut poll_table = PollTable::default();
-
-        // Iterate over all the registered events and check if they are ready.
-        for (fd, epoll_event) in table.iter_mut() {
-            if n == max_events {
-                break;
-            }
-
-            ret_events[n].events = EPollEventFlags::empty();
-            ret_events[n].data = unsafe { core::mem::zeroed() };
-
-            let fd = file_table
-                .get_handle(*fd)
-                .ok_or(FileSystemError::NotSupported)?; // EINVAL
-
-            let flags = epoll_event.events;
-            let ready: EPollEventFlags = fd.inode().poll(None)?.into();
-
-            // If the event mask does not contain any poll(2) events, the event
-            // descriptor is disabled.
-            if flags == Self::PRIVATE_BITS {
-                continue;
+        // A negative timeout blocks indefinitely.
+        let timer = if (timeout as isize) > 0 {
+            Some(Timer::wake_current(
+                timer::now() + timeout as u64 * 1_000_000,
+            ))
+        } else {
+            None
+        };
+
+        loop {
+            // The task is removed from the wait queues when the table is dropped, and
+            // registered again on the next iteration.
+            let mut poll_table = PollTable::default();
+            let n = self.collect(ret_events, max_events, &mut poll_table)?;
+
+            // If the timeout is zero, then we have to return without blocking.
+            if n > 0 || timeout == 0 {
+                return Ok(n);
             }
 
-            if !(ready & flags).is_empty() {
-                ret_events[n].events = ready & flags;
-                ret_events[n].data = epoll_event.data;
-
-                if flags.contains(EPollEventFlags::ONESHOT) {
-                    // The `EPOLLONESHOT` bit that disables the descriptor when an event is
-                    // received, until the next `EPOLL_CTL_MOD` will be issued.
-                    epoll_event.events = Self::PRIVATE_BITS;
+            if let Some(timer) = timer.as_ref() {
+                if timer::now() >= timer.deadline() {
+                    return Ok(0);
                 }
-
-                n += 1;
-                continue;
             }
 
-            // Not ready; add the event to the poll table.
-            fd.inode().poll(Some(&mut poll_table))?;
-            fds.push((fd, epoll_event, flags));
-        }
-
-        // If all events are ready, we can return now.
-        if n > 0 {
-            debug_assert!(fds.len() == 0);
-            return Ok(n);
-        }
-
-        // Start the timer if timeout specified, if not, we can block indefinitely.
-        // If the timeout is zero, then we have to return without blocking.
-        if timeout == 0 {
-            return Ok(0);
-        }
-
-        'search: loop {
             scheduler::get_scheduler().inner.await_io()?;
-
-            for (fd, event, flags) in fds.iter_mut() {
-                // If the event mask does not contain any poll(2) events, the event
-                // descriptor is disabled.
-                if *flags == Self::PRIVATE_BITS {
-                    continue;
-                }
-
-                let ready: EPollEventFlags = fd.inode().poll(None)?.into();
-
-                if !(ready & *flags).is_empty() {
-                    // The event is ready; break out of the search loop and set ready
-                    // events to 1.
-                    ret_events[n].events = ready & *flags;
-                    ret_events[n].data = event.data;
-
-                    if flags.contains(EPollEventFlags::ONESHOT) {
-                        // The `EPOLLONESHOT` bit that disables the descriptor when an event is
-                        // received, until the next `EPOLL_CTL_MOD` will be issued.
-                        event.events = Self::PRIVATE_BITS;
-                    }
-
-                    n = 1;
-                    break 'search;
-                }
-            }
         }
-
-        Ok(n)
     }
 }
 
@@ -20,17 +20,17 @@
 use alloc::sync::Arc;
 
 use super::inode::{INodeInterface, PollFlags, PollTable};
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
 pub struct EventFd {
-    wq: BlockQueue,
+    wq: WaitQueue,
     count: Mutex<u64>,
 }
 
 impl EventFd {
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            wq: BlockQueue::new(),
+            wq: WaitQueue::new(),
             count: Mutex::new(0),
         })
     }
@@ -44,12 +44,12 @@ impl INodeInterface for EventFd {
         // SAFETY: We have above verified that it is safe to dereference
         //         the value.
         let value = unsafe { &mut *(buffer.as_mut_ptr() as *mut u64) };
-        let mut count = self.wq.block_on(&self.count, |e| **e != 0)?;
+        let mut count = self.wq.wait_until(&self.count, |e| **e != 0)?;
 
         *value = *count;
         *count = 0; // reset the counter
 
-        self.wq.notify_complete();
+        self.wq.wake_all();
         Ok(size)
     }
 
@@ -62,7 +62,7 @@ impl INodeInterface for EventFd {
         let value = unsafe { *(buffer.as_ptr() as *const u64) };
 
         *self.count.lock_irq() += value;
-        self.wq.notify_complete();
+        self.wq.wake_all();
         Ok(size)
     }
 
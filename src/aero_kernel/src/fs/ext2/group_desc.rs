@@ -23,14 +23,14 @@ use alloc::boxed::Box;
 use alloc::sync::{Arc, Weak};
 
 use bit_field::BitField;
-use spin::RwLock;
 
 use crate::fs::block::{BlockDevice, CachedAccess};
+use crate::utils::sync::BlockingRwLock;
 
 use super::{disk, Ext2};
 
 pub struct GroupDescriptors {
-    descriptors: RwLock<Box<[disk::GroupDescriptor]>>,
+    descriptors: BlockingRwLock<Box<[disk::GroupDescriptor]>>,
     ext2: Weak<Ext2>,
 }
 
@@ -63,7 +63,7 @@ impl GroupDescriptors {
         // SAFETY: We have initialized the BGD (Block Group Descriptor Table) above.
         let bgdt = unsafe { bgdt.assume_init() };
         Some(Self {
-            descriptors: RwLock::new(bgdt),
+            descriptors: BlockingRwLock::new(bgdt),
             ext2,
         })
     }
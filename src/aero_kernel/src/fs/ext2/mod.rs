@@ -27,15 +27,14 @@ use aero_syscall::{MMapFlags, SyscallError};
 use alloc::boxed::Box;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
-use spin::RwLock;
 
 use crate::fs::block::BlockDeviceInterface;
 use crate::fs::cache::CachedINode;
 use crate::fs::ext2::disk::{FileType, SuperBlock};
 use crate::mem::paging::{FrameAllocator, PhysFrame, VirtAddr, FRAME_ALLOCATOR};
 
-use crate::socket::unix::UnixSocket;
 use crate::socket::SocketAddr;
+use crate::utils::sync::BlockingRwLock;
 use crate::utils::CeilDiv;
 
 use self::group_desc::GroupDescriptors;
@@ -51,7 +50,7 @@ use super::FileSystem;
 pub struct INode {
     id: usize,
     fs: Weak<Ext2>,
-    inode: RwLock<Box<disk::INode>>,
+    inode: BlockingRwLock<Box<disk::INode>>,
     // Forwards all of the inode operations to the proxy inode. Note that the
     // proxy inode is not saved on the disk. (e.g. This is useful for binding
     // a socket inode to a file).
@@ -81,7 +80,7 @@ impl INode {
 
             Some(
                 icache.make_item_cached(CachedINode::new(Arc::new_cyclic(|sref| Self {
-                    inode: RwLock::new(inode),
+                    inode: BlockingRwLock::new(inode),
                     id,
                     fs: ext2,
                     proxy,
@@ -539,7 +538,10 @@ impl INodeInterface for INode {
         return Err(FileSystemError::NotSupported);
     }
 
-    fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> super::Result<Arc<UnixSocket>> {
+    fn accept(
+        &self,
+        address: Option<(VirtAddr, &mut u32)>,
+    ) -> super::Result<Arc<dyn INodeInterface>> {
         if let Some(proxy) = self.proxy.as_ref() {
             return proxy.accept(address);
         }
@@ -184,15 +184,29 @@ impl FileHandle {
     }
 }
 
-#[repr(transparent)]
-pub struct FileTable(pub RwLock<Vec<Option<Arc<FileHandle>>>>);
+pub struct FileTable(
+    pub RwLock<Vec<Option<Arc<FileHandle>>>>,
+    /// The maximum amount of file descriptors (the soft `RLIMIT_NOFILE` limit).
+    AtomicUsize,
+);
 
 impl FileTable {
     pub fn new() -> Self {
         let mut table = Vec::new();
         table.resize(256, None);
 
-        Self(RwLock::new(table))
+        Self(RwLock::new(table), AtomicUsize::new(usize::MAX))
+    }
+
+    /// Returns the maximum amount of file descriptors.
+    pub fn max_files(&self) -> usize {
+        self.1.load(Ordering::SeqCst)
+    }
+
+    /// Sets the maximum amount of file descriptors. The file descriptors already open
+    /// above it are kept open.
+    pub fn set_max_files(&self, max_files: usize) {
+        self.1.store(max_files, Ordering::SeqCst)
     }
 
     pub fn get_handle(&self, fd: usize) -> Option<Arc<FileHandle>> {
@@ -251,28 +265,48 @@ impl FileTable {
             .get_handle(fd)
             .ok_or(aero_syscall::SyscallError::EINVAL)?;
 
+        let max_files = self.max_files();
+
         let find_from = |files: &mut Vec<Option<Arc<FileHandle>>>, start: usize| {
-            let array = &mut files[start..];
+            if start >= max_files {
+                return Err(aero_syscall::SyscallError::EINVAL);
+            }
+
+            let end = core::cmp::min(files.len(), max_files);
 
             // Loop over the current file descriptor table and find the first
             // avaliable file descriptor.
-            for (i, file) in array.iter_mut().enumerate() {
-                if file.is_none() {
-                    *file = Some(handle.duplicate(i, flags)?);
-                    return Ok(start + i);
+            for i in start..end {
+                if files[i].is_none() {
+                    files[i] = Some(handle.duplicate(i, flags)?);
+                    return Ok(i);
                 }
             }
 
             // We ran out of file descriptors. Grow the FD table and insert the FD.
-            let fd = files.len();
-            files.push(Some(handle.duplicate(fd, flags)?));
+            let fd = core::cmp::max(files.len(), start);
+
+            if fd >= max_files {
+                return Err(aero_syscall::SyscallError::EMFILE);
+            }
+
+            files.resize(fd + 1, None);
+            files[fd] = Some(handle.duplicate(fd, flags)?);
             Ok(fd)
         };
 
         match hint {
             DuplicateHint::Exact(new_fd) => {
+                if new_fd >= max_files {
+                    return Err(aero_syscall::SyscallError::EBADF);
+                }
+
                 let mut files = self.0.write();
 
+                if new_fd >= files.len() {
+                    files.resize(new_fd + 1, None);
+                }
+
                 // Ensure the file descriptor is available.
                 if files[new_fd].is_none() {
                     files[new_fd] = Some(handle.duplicate(new_fd, flags)?);
@@ -317,18 +351,27 @@ impl FileTable {
             }
         }
 
-        Self(RwLock::new(files.clone()))
+        Self(
+            RwLock::new(files.clone()),
+            AtomicUsize::new(self.max_files()),
+        )
     }
 
     pub fn open_file(&self, dentry: DirCacheItem, mut flags: OpenFlags) -> super::Result<usize> {
         let mut files = self.0.write();
+        let max_files = self.max_files();
 
         // Remove all of the unneccessary flags.
         flags.remove(OpenFlags::O_CREAT);
         flags.remove(OpenFlags::O_DIRECTORY);
 
         // Check if a file handle was removed, if so re-use the file handle.
-        if let Some((i, f)) = files.iter_mut().enumerate().find(|e| e.1.is_none()) {
+        if let Some((i, f)) = files
+            .iter_mut()
+            .take(max_files)
+            .enumerate()
+            .find(|e| e.1.is_none())
+        {
             let mut handle = Arc::new(FileHandle::new(i, dentry, flags));
 
             if let Some(inode) = handle.inode.inode().open(flags, handle.clone())? {
@@ -339,7 +382,7 @@ impl FileTable {
             *f = Some(handle);
 
             Ok(i)
-        } else if files.len() < 256 {
+        } else if files.len() < max_files {
             let fd = files.len();
             let mut handle = Arc::new(FileHandle::new(fd, dentry, flags));
 
@@ -352,7 +395,7 @@ impl FileTable {
 
             Ok(fd)
         } else {
-            Err(FileSystemError::Busy)
+            Err(FileSystemError::TooManyFiles)
         }
     }
 
@@ -0,0 +1,218 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The initramfs is a ustar archive loaded by the bootloader as a module and selected
+//! with the `initrd=<module>` kernel command line option. If no ext2 root partition is
+//! found, the archive is unpacked into a ramfs which is then used as the root filesystem.
+//!
+//! Only regular files and directories are supported; the other entries (for example the
+//! symbolic links) are skipped.
+
+use alloc::sync::Arc;
+
+use crate::utils::CeilDiv;
+
+use super::cache::DirCacheItem;
+use super::ramfs::RamFs;
+use super::{FileSystem, FileSystemError, Result};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, PartialEq)]
+enum EntryKind {
+    File,
+    Directory,
+    Other(u8),
+}
+
+struct Entry<'a> {
+    prefix: &'a str,
+    name: &'a str,
+    kind: EntryKind,
+    data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the components of the path of the entry.
+    fn components(&self) -> impl Iterator<Item = &'a str> {
+        self.prefix
+            .split('/')
+            .chain(self.name.split('/'))
+            .filter(|component| !component.is_empty() && *component != ".")
+    }
+}
+
+/// Returns the NUL terminated string stored in `field`.
+fn parse_str(field: &[u8]) -> Option<&str> {
+    let length = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..length]).ok()
+}
+
+/// Parses an octal number field, which is padded with spaces or NULs.
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let string = parse_str(field)?.trim_matches(' ');
+    usize::from_str_radix(string, 8).ok()
+}
+
+struct Entries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let archive = self.archive;
+        let header = archive.get(self.offset..self.offset + BLOCK_SIZE)?;
+
+        // The archive ends with zeroed blocks.
+        if header.iter().all(|b| *b == 0) {
+            return None;
+        }
+
+        if &header[257..262] != b"ustar" {
+            return Some(Err(FileSystemError::NotSupported));
+        }
+
+        let entry = (|| {
+            let size = parse_octal(&header[124..136])?;
+            let start = self.offset + BLOCK_SIZE;
+            let data = archive.get(start..start + size)?;
+
+            let kind = match header[156] {
+                b'0' | 0 => EntryKind::File,
+                b'5' => EntryKind::Directory,
+                kind => EntryKind::Other(kind),
+            };
+
+            self.offset = start + size.ceil_div(BLOCK_SIZE) * BLOCK_SIZE;
+
+            Some(Entry {
+                prefix: parse_str(&header[345..500])?,
+                name: parse_str(&header[0..100])?,
+                kind,
+                data,
+            })
+        })();
+
+        match entry {
+            Some(entry) => Some(Ok(entry)),
+            None => {
+                // Stop at the malformed header.
+                self.offset = self.archive.len();
+                Some(Err(FileSystemError::InvalidPath))
+            }
+        }
+    }
+}
+
+fn entries(archive: &[u8]) -> Entries {
+    Entries { archive, offset: 0 }
+}
+
+/// Returns the directory `name` in `dir`, creating it if it does not exist.
+fn make_dir(dir: DirCacheItem, name: &str) -> Result<DirCacheItem> {
+    match dir.inode().lookup(dir.clone(), name) {
+        Ok(entry) => Ok(entry),
+        Err(FileSystemError::EntryNotFound) => {
+            dir.inode().mkdir(name)?;
+            dir.inode().lookup(dir.clone(), name)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Unpacks the ustar `archive` into a new ramfs.
+pub fn unpack(archive: &[u8]) -> Result<Arc<RamFs>> {
+    let ramfs = RamFs::new();
+
+    for entry in entries(archive) {
+        let entry = entry?;
+        let mut components = entry.components().peekable();
+        let mut dir = ramfs.root_dir();
+
+        while let Some(component) = components.next() {
+            if components.peek().is_some() || entry.kind == EntryKind::Directory {
+                dir = make_dir(dir, component)?;
+                continue;
+            }
+
+            match entry.kind {
+                EntryKind::File => {
+                    let file = dir.inode().touch(dir.clone(), component)?;
+                    file.inode().write_at(0, entry.data)?;
+                }
+
+                EntryKind::Other(kind) => {
+                    log::warn!(
+                        "initramfs: skipping '{}' (unsupported type {:?})",
+                        component,
+                        kind as char
+                    );
+                }
+
+                EntryKind::Directory => unreachable!(),
+            }
+        }
+    }
+
+    Ok(ramfs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn header(name: &str, kind: u8, size: usize) -> Vec<u8> {
+        let mut header = vec![0; BLOCK_SIZE];
+
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(alloc::format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    #[test]
+    fn initramfs_entries() {
+        let mut archive = header("./usr/", b'5', 0);
+
+        archive.extend(header("./usr/init", b'0', 5));
+        archive.extend(b"hello");
+        archive.resize(BLOCK_SIZE * 3, 0);
+        archive.extend(header("./usr/sh", b'2', 0));
+        archive.resize(archive.len() + BLOCK_SIZE * 2, 0);
+
+        let parsed = entries(&archive).collect::<Result<Vec<_>>>().unwrap();
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].kind, EntryKind::Directory);
+        assert_eq!(parsed[0].components().collect::<Vec<_>>(), ["usr"]);
+        assert_eq!(parsed[1].kind, EntryKind::File);
+        assert_eq!(parsed[1].components().collect::<Vec<_>>(), ["usr", "init"]);
+        assert_eq!(parsed[1].data, b"hello");
+        assert_eq!(parsed[2].kind, EntryKind::Other(b'2'));
+
+        assert!(entries(&[1; BLOCK_SIZE]).next().unwrap().is_err());
+    }
+}
@@ -31,11 +31,10 @@ use intrusive_collections::UnsafeRef;
 use spin::Once;
 
 use crate::mem::paging::{PhysFrame, VirtAddr};
-use crate::socket::unix::UnixSocket;
-use crate::socket::SocketAddr;
+use crate::socket::{SocketAddr, SocketName};
 use crate::userland::scheduler;
-use crate::utils::sync::BlockQueue;
 use crate::utils::sync::Mutex;
+use crate::utils::sync::WaitQueue;
 
 use super::cache;
 use super::cache::Cacheable;
@@ -49,14 +48,22 @@ static DIR_CACHE_MARKER: AtomicUsize = AtomicUsize::new(0x00);
 
 #[derive(Default)]
 pub struct PollTable {
-    pub queues: Vec<UnsafeRef<BlockQueue>>,
+    pub queues: Vec<UnsafeRef<WaitQueue>>,
 }
 
 impl PollTable {
-    pub fn insert(&mut self, queue: &BlockQueue) {
+    pub fn insert(&mut self, queue: &WaitQueue) {
         queue.insert(scheduler::get_scheduler().current_task());
         unsafe { self.queues.push(UnsafeRef::from_raw(queue as *const _)) }
     }
+
+    /// Returns the sum of the wake up counters of the queues registered after the first
+    /// `start` ones. It changes whenever one of those queues is woken up.
+    pub fn wakeups_since(&self, start: usize) -> usize {
+        self.queues[start..]
+            .iter()
+            .fold(0, |sum, queue| sum.wrapping_add(queue.wakeups()))
+    }
 }
 
 impl Drop for PollTable {
@@ -76,6 +83,9 @@ bitflags::bitflags! {
         const OUT = 1 << 2;
         /// Error condition happened on the associated file descriptor.
         const ERR = 1 << 3;
+        /// The peer of the associated file closed its end (for example, all of the
+        /// writers of a pipe are closed).
+        const HUP = 1 << 4;
     }
 }
 
@@ -92,6 +102,9 @@ impl From<PollFlags> for EPollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -110,6 +123,9 @@ impl From<PollFlags> for PollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -240,7 +256,7 @@ pub trait INodeInterface: Send + Sync {
         Err(SyscallError::ENOTSOCK)
     }
 
-    fn accept(&self, _address: Option<(VirtAddr, &mut u32)>) -> Result<Arc<UnixSocket>> {
+    fn accept(&self, _address: Option<(VirtAddr, &mut u32)>) -> Result<Arc<dyn INodeInterface>> {
         Err(FileSystemError::NotSocket)
     }
 
@@ -248,6 +264,29 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSocket)
     }
 
+    fn send(&self, _message_header: &MessageHeader, _non_block: bool) -> Result<usize> {
+        Err(FileSystemError::NotSocket)
+    }
+
+    /// Reads the socket option into `value`. Returns the size of the option.
+    fn get_option(&self, _level: u32, _name: u32, _value: &mut [u8]) -> Result<usize> {
+        Err(FileSystemError::NotSocket)
+    }
+
+    fn set_option(&self, _level: u32, _name: u32, _value: &[u8]) -> Result<()> {
+        Err(FileSystemError::NotSocket)
+    }
+
+    /// Returns the address the socket is bound to.
+    fn local_name(&self) -> Result<SocketName> {
+        Err(FileSystemError::NotSocket)
+    }
+
+    /// Returns the address of the peer the socket is connected to.
+    fn peer_name(&self) -> Result<SocketName> {
+        Err(FileSystemError::NotSocket)
+    }
+
     /// Returns the inner UNIX socket inode if bound to one.
     fn as_unix_socket(&self) -> Result<Arc<dyn INodeInterface>> {
         Err(FileSystemError::NotSocket)
@@ -37,16 +37,23 @@ pub mod epoll;
 pub mod eventfd;
 pub mod ext2;
 pub mod file_table;
+pub mod initramfs;
 pub mod inode;
+// FIXME: aarch64 port
+#[cfg(target_arch = "x86_64")]
+pub mod perf_event;
 pub mod pipe;
 pub mod procfs;
 pub mod ramfs;
+pub mod sysfs;
 
 static ROOT_FS: Once<Arc<dyn FileSystem>> = Once::new();
 static ROOT_DIR: Once<DirCacheItem> = Once::new();
 
 lazy_static::lazy_static! {
-    pub static ref MOUNT_MANAGER: MountManager = MountManager::new();
+    /// The mount namespace of the kernel tasks, which the userland tasks inherit until
+    /// they unshare it.
+    pub static ref INIT_MOUNT_NAMESPACE: Arc<MountNamespace> = Arc::new(MountNamespace::new());
 }
 
 pub type Result<T> = core::result::Result<T, FileSystemError>;
@@ -110,6 +117,62 @@ impl MountManager {
             Err(FileSystemError::EntryNotFound)
         }
     }
+
+    fn fork(&self) -> Self {
+        Self(Mutex::new(self.0.lock().clone()))
+    }
+}
+
+/// A mount namespace holds the mount table. A task shares the namespace of its parent
+/// until it unshares it (`CLONE_NEWNS`), which gives it a private copy of the mount table.
+pub struct MountNamespace {
+    mounts: MountManager,
+}
+
+impl MountNamespace {
+    fn new() -> Self {
+        Self {
+            mounts: MountManager::new(),
+        }
+    }
+
+    /// Returns a copy of the namespace, with its own mount table.
+    pub fn fork(&self) -> Self {
+        Self {
+            mounts: self.mounts.fork(),
+        }
+    }
+
+    pub fn mount(&self, directory: DirCacheItem, filesystem: Arc<dyn FileSystem>) -> Result<()> {
+        self.mounts.mount(directory, filesystem)
+    }
+}
+
+/// Returns the root directory of the current task (see `chroot`), that the absolute paths
+/// are resolved against, or the global root directory if there is no current task yet.
+/// The task is confined to it, including `..` lookups and the paths reported back to it.
+pub fn current_root() -> DirCacheItem {
+    if scheduler::is_initialized() {
+        if let Some(task) = scheduler::get_scheduler().inner.current_task_optional() {
+            if let Some(root) = task.root_dirent() {
+                return root;
+            }
+        }
+    }
+
+    root_dir().clone()
+}
+
+/// Returns the mount namespace of the current task or the initial mount namespace if
+/// there is no current task yet.
+pub fn mount_namespace() -> Arc<MountNamespace> {
+    if scheduler::is_initialized() {
+        if let Some(task) = scheduler::get_scheduler().inner.current_task_optional() {
+            return task.mount_namespace();
+        }
+    }
+
+    INIT_MOUNT_NAMESPACE.clone()
 }
 
 pub trait FileSystem: Send + Sync {
@@ -134,6 +197,18 @@ pub enum FileSystemError {
     ConnectionRefused,
     NotConnected,
     WouldBlock,
+    AddressInUse,
+    DestinationRequired,
+    MessageTooLong,
+    NetworkUnreachable,
+    ConnectionReset,
+    TimedOut,
+    InvalidOption,
+    NotPermitted,
+    NoSpace,
+    BadAddress,
+    TooManyFiles,
+    NotTty,
 }
 
 impl From<FileSystemError> for SyscallError {
@@ -153,6 +228,18 @@ impl From<FileSystemError> for SyscallError {
             FileSystemError::IsDir => Self::EISDIR,
             FileSystemError::NotConnected => Self::ENOTCONN,
             FileSystemError::WouldBlock => Self::EAGAIN,
+            FileSystemError::AddressInUse => Self::EADDRINUSE,
+            FileSystemError::DestinationRequired => Self::EDESTADDRREQ,
+            FileSystemError::MessageTooLong => Self::EMSGSIZE,
+            FileSystemError::NetworkUnreachable => Self::ENETUNREACH,
+            FileSystemError::ConnectionReset => Self::ECONNRESET,
+            FileSystemError::TimedOut => Self::ETIMEDOUT,
+            FileSystemError::InvalidOption => Self::ENOPROTOOPT,
+            FileSystemError::NotPermitted => Self::EPERM,
+            FileSystemError::NoSpace => Self::ENOSPC,
+            FileSystemError::BadAddress => Self::EFAULT,
+            FileSystemError::TooManyFiles => Self::EMFILE,
+            FileSystemError::NotTty => Self::ENOTTY,
         }
     }
 }
@@ -221,12 +308,20 @@ pub fn lookup_path_with(
     path: &Path,
     mode: LookupMode,
 ) -> Result<DirCacheItem> {
+    let namespace = mount_namespace();
+    let root = current_root();
+
     // Iterate and resolve each component. For example `a`, `b`, and `c` in `a/b/c`.
     for (i, component) in path.components().enumerate() {
         match component {
             // Handle some special cases that might occur in a relative path.
             "." => continue,
             ".." => {
+                // The root directory of the task cannot be escaped.
+                if cwd.cache_key() == root.cache_key() {
+                    continue;
+                }
+
                 let current = cwd.data.lock();
 
                 if let Some(parent) = current.parent.clone() {
@@ -281,7 +376,7 @@ pub fn lookup_path_with(
 
                     return lookup_path_with(parent, resolved_path, LookupMode::None);
                 } else if metadata.is_directory() {
-                    if let Ok(mount_point) = MOUNT_MANAGER.find_mount(cwd.clone()) {
+                    if let Ok(mount_point) = namespace.mounts.find_mount(cwd.clone()) {
                         cwd = mount_point.root_entry;
                     }
                 }
@@ -296,7 +391,7 @@ pub fn lookup_path_with_mode(path: &Path, mode: LookupMode) -> Result<DirCacheIt
     let cwd = if !path.is_absolute() {
         scheduler::get_scheduler().current_task().cwd_dirent()
     } else {
-        root_dir().clone()
+        current_root()
     };
 
     lookup_path_with(cwd, path, mode)
@@ -306,7 +401,7 @@ pub fn lookup_path(path: &Path) -> Result<DirCacheItem> {
     let cwd = if !path.is_absolute() {
         scheduler::get_scheduler().current_task().cwd_dirent()
     } else {
-        root_dir().clone()
+        current_root()
     };
 
     lookup_path_with(cwd, path, LookupMode::None)
@@ -0,0 +1,92 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use aero_syscall::*;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::arch::pmu::{self, PmuEvent};
+
+use super::inode::INodeInterface;
+use super::{FileSystemError, Result};
+
+/// A hardware performance counter opened with the `perf_event_open` syscall. The
+/// counter is freed when the file is closed.
+pub struct PerfEvent {
+    handle: usize,
+}
+
+impl PerfEvent {
+    /// Allocates a counter for the event. Returns [`None`] if all of the counters that
+    /// can count the event are in use or if the sample period is too large.
+    pub fn new(event: PmuEvent, sample_period: u64, enabled: bool) -> Option<Arc<Self>> {
+        let handle = pmu::open(event, sample_period, enabled)?;
+        Some(Arc::new(Self { handle }))
+    }
+}
+
+impl INodeInterface for PerfEvent {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let header_size = core::mem::size_of::<PerfReadFormat>();
+        let sample_size = core::mem::size_of::<PerfSample>();
+
+        if buffer.len() < header_size {
+            return Err(FileSystemError::TooSmall);
+        }
+
+        let mut samples = Vec::new();
+        samples.resize(
+            (buffer.len() - header_size) / sample_size,
+            PerfSample::default(),
+        );
+
+        let (count, lost) = pmu::take_samples(self.handle, &mut samples);
+
+        let header = PerfReadFormat {
+            value: pmu::read(self.handle),
+            samples: count as u64,
+            lost: lost as u64,
+        };
+
+        let header = crate::utils::slice_into_bytes(core::slice::from_ref(&header));
+        let samples = crate::utils::slice_into_bytes(&samples[..count]);
+
+        buffer[..header_size].copy_from_slice(header);
+        buffer[header_size..header_size + samples.len()].copy_from_slice(samples);
+
+        Ok(header_size + samples.len())
+    }
+
+    fn ioctl(&self, command: usize, _arg: usize) -> Result<usize> {
+        match command {
+            PERF_EVENT_IOC_ENABLE => pmu::set_enabled(self.handle, true),
+            PERF_EVENT_IOC_DISABLE => pmu::set_enabled(self.handle, false),
+            PERF_EVENT_IOC_RESET => pmu::reset(self.handle),
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+impl Drop for PerfEvent {
+    fn drop(&mut self) {
+        pmu::close(self.handle);
+    }
+}
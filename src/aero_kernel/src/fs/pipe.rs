@@ -5,7 +5,7 @@ use alloc::sync::Arc;
 use spin::Once;
 
 use crate::utils::buffer::Buffer;
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
 use super::cache::DirCacheItem;
 use super::file_table::FileHandle;
@@ -15,8 +15,8 @@ use super::FileSystemError;
 pub struct Pipe {
     queue: Mutex<Buffer>,
 
-    readers: BlockQueue,
-    writers: BlockQueue,
+    readers: WaitQueue,
+    writers: WaitQueue,
 
     /// The number of writers currently connected to the pipe.
     num_writers: AtomicUsize,
@@ -29,8 +29,8 @@ impl Pipe {
         Arc::new(Self {
             queue: Mutex::new(Buffer::new()),
 
-            readers: BlockQueue::new(),
-            writers: BlockQueue::new(),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
 
             num_writers: AtomicUsize::new(0),
 
@@ -66,7 +66,7 @@ impl INodeInterface for Pipe {
 
             // There are no active writers and no data to read (reached EOF).
             if active_writers == 0 {
-                self.readers.notify_complete();
+                self.readers.wake_all();
             }
         }
     }
@@ -84,7 +84,7 @@ impl INodeInterface for Pipe {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.readers.block_on(&self.queue, |lock| {
+        let mut buffer = self.readers.wait_until(&self.queue, |lock| {
             lock.has_data() || self.active_writers() == 0
         })?;
 
@@ -92,7 +92,7 @@ impl INodeInterface for Pipe {
 
         if read > 0 {
             // TODO: Notify only the first process
-            self.writers.notify_complete();
+            self.writers.wake_all();
         }
 
         Ok(read)
@@ -100,7 +100,7 @@ impl INodeInterface for Pipe {
 
     fn write_at(&self, _offset: usize, buf: &[u8]) -> super::Result<usize> {
         let res = self.queue.lock_irq().write_data(buf);
-        self.readers.notify_complete();
+        self.readers.wake_all();
 
         Ok(res)
     }
@@ -117,6 +117,10 @@ impl INodeInterface for Pipe {
             flags |= PollFlags::IN;
         }
 
+        if self.active_writers() == 0 {
+            flags |= PollFlags::HUP;
+        }
+
         Ok(flags)
     }
 }
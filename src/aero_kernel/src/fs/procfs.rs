@@ -3,12 +3,14 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
 use spin::{Once, RwLock};
 
 use crate::fs::inode::FileType;
 
 use crate::arch::tls;
+use crate::logger;
 
 use super::cache;
 use super::cache::*;
@@ -78,6 +80,120 @@ fn get_cpuinfo_cached() -> &'static str {
     })
 }
 
+fn get_interrupts() -> String {
+    use alloc::vec;
+    use serde_json::*;
+
+    #[allow(unused_mut)]
+    let mut interrupts: Vec<Value> = vec![];
+
+    #[cfg(target_arch = "x86_64")]
+    for irq in crate::arch::interrupts::irq_stats() {
+        let mut interrupt = json!({
+            "vector": irq.vector,
+            "handlers": irq.names,
+            "counts": irq.counts,
+        });
+
+        if let Some(gsi) = irq.gsi {
+            interrupt["gsi"] = Value::Number(Number::from(gsi));
+        }
+
+        if let Some(cpu) = irq.cpu {
+            interrupt["affinity"] = Value::Number(Number::from(cpu));
+        }
+
+        interrupts.push(interrupt);
+    }
+
+    json!({ "interrupts": interrupts }).to_string()
+}
+
+fn get_firewall() -> String {
+    use crate::net::filter::{self, Chain};
+    use serde_json::*;
+
+    let mut data = json!({});
+
+    for chain in Chain::ALL {
+        let (policy, rules) = filter::rules(chain);
+
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let mut entry = json!({
+                    "verdict": rule.verdict.name(),
+                    "matches": rule.matches,
+                });
+
+                if let Some(protocol) = rule.protocol {
+                    entry["protocol"] = Value::Number(Number::from(u8::from(protocol)));
+                }
+
+                push_string_if_some(&mut entry, "src", rule.src.map(|src| src.to_string()));
+                push_string_if_some(&mut entry, "dst", rule.dest.map(|dest| dest.to_string()));
+
+                if let Some(port) = rule.src_port {
+                    entry["sport"] = Value::Number(Number::from(port));
+                }
+
+                if let Some(port) = rule.dest_port {
+                    entry["dport"] = Value::Number(Number::from(port));
+                }
+
+                entry
+            })
+            .collect();
+
+        data[chain.name()] = json!({
+            "policy": policy.name(),
+            "rules": Value::Array(rules),
+        });
+    }
+
+    data.to_string()
+}
+
+fn get_zoneinfo() -> String {
+    use crate::mem::numa;
+    use serde_json::*;
+
+    let nodes = numa::nodes()
+        .iter()
+        .map(|node| {
+            let zones = node
+                .zones
+                .iter()
+                .map(|zone| {
+                    json!({
+                        "kind": zone.kind.name(),
+                        "start": zone.start.as_u64(),
+                        "end": zone.end.as_u64(),
+                        "free": zone.free_frames(),
+                        "allocated": zone.allocated_frames(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let distances = numa::nodes()
+                .iter()
+                .map(|other| numa::distance(node.id, other.id))
+                .collect::<Vec<_>>();
+
+            json!({
+                "id": node.id,
+                "cpus": node.cpus,
+                "distances": distances,
+                "hits": node.hits(),
+                "misses": node.misses(),
+                "zones": zones,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "nodes": nodes }).to_string()
+}
+
 #[derive(Default)]
 struct ProcINode {
     id: usize,
@@ -92,6 +208,10 @@ struct ProcINode {
 enum FileContents {
     CpuInfo,
     CmdLine,
+    Kmsg,
+    Interrupts,
+    Firewall,
+    ZoneInfo,
 
     None,
 }
@@ -164,19 +284,70 @@ impl INodeInterface for LockedProcINode {
     fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
         let this = self.0.read();
 
+        if let FileContents::Kmsg = this.contents {
+            // Reading from `/proc/kmsg` consumes the unread kernel log records, so the
+            // offset is ignored.
+            let records = logger::read_unread(buffer.len());
+            let records = records.as_bytes();
+
+            let count = core::cmp::min(buffer.len(), records.len());
+            buffer[..count].copy_from_slice(&records[..count]);
+
+            return Ok(count);
+        }
+
+        let contents;
+
         let data = match &this.contents {
             FileContents::CpuInfo => Ok(get_cpuinfo_cached()),
             FileContents::CmdLine => Ok(get_cmdline_cached()),
+            FileContents::Interrupts => {
+                contents = get_interrupts();
+                Ok(contents.as_str())
+            }
+
+            FileContents::Firewall => {
+                contents = get_firewall();
+                Ok(contents.as_str())
+            }
+
+            FileContents::ZoneInfo => {
+                contents = get_zoneinfo();
+                Ok(contents.as_str())
+            }
 
             _ => Err(FileSystemError::NotSupported),
         }?;
 
+        // The statistics may have shrunk since the previous read.
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
         let count = core::cmp::min(buffer.len(), data.len() - offset);
         buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
 
         Ok(count)
     }
 
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        let this = self.0.read();
+
+        match this.contents {
+            // Writing to `/proc/firewall` runs the packet filter commands (see
+            // [`crate::net::filter`]).
+            FileContents::Firewall => {
+                let commands =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidPath)?;
+
+                crate::net::filter::configure(commands).ok_or(FileSystemError::InvalidPath)?;
+                Ok(buffer.len())
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
     fn lookup(&self, dir: DirCacheItem, name: &str) -> Result<DirCacheItem> {
         let this = self.0.read();
         let child = this
@@ -279,6 +450,10 @@ impl ProcFs {
 
         inode.make_inode("cpuinfo", FileType::File, FileContents::CpuInfo)?;
         inode.make_inode("cmdline", FileType::File, FileContents::CmdLine)?;
+        inode.make_inode("kmsg", FileType::File, FileContents::Kmsg)?;
+        inode.make_inode("interrupts", FileType::File, FileContents::Interrupts)?;
+        inode.make_inode("firewall", FileType::File, FileContents::Firewall)?;
+        inode.make_inode("zoneinfo", FileType::File, FileContents::ZoneInfo)?;
 
         Ok(ramfs)
     }
@@ -310,7 +485,7 @@ pub fn init() -> Result<()> {
     let fs = PROC_FS.call_once(|| fs);
 
     let inode = super::lookup_path(Path::new("/proc"))?;
-    MOUNT_MANAGER.mount(inode, fs.clone())?;
+    INIT_MOUNT_NAMESPACE.mount(inode, fs.clone())?;
 
     Ok(())
 }
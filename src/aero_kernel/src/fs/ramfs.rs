@@ -25,10 +25,9 @@ use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
 
 use alloc::vec::Vec;
-use spin::RwLock;
 
 use crate::mem::paging::*;
-use crate::utils::sync::Mutex;
+use crate::utils::sync::{BlockingRwLock, Mutex};
 
 use super::cache::{self, CacheWeak};
 use super::cache::{CachedINode, DirCacheItem, INodeCacheItem, INodeCacheWeakItem};
@@ -48,12 +47,12 @@ pub struct RamINode {
     contents: FileContents,
 }
 
-pub struct LockedRamINode(RwLock<RamINode>);
+pub struct LockedRamINode(BlockingRwLock<RamINode>);
 
 impl LockedRamINode {
     #[inline]
     fn new(node: RamINode) -> Self {
-        Self(RwLock::new(node))
+        Self(BlockingRwLock::new(node))
     }
 
     fn init(
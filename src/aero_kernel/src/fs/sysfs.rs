@@ -0,0 +1,338 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The `/sys` directory exports kernel knobs and data as files. The files are devices
+//! (see [`Device`]) installed in the directories of a ram filesystem:
+//!
+//! * `/sys/kernel/trace/enable`: lists the tracepoint events and whether they are
+//!   enabled. Writing `<event> <0|1>` (or `all <0|1>`) disables or enables them.
+//! * `/sys/kernel/trace/buffer`: reading consumes the recorded [`TraceRecord`]s in their
+//!   binary form. Writing anything discards them.
+//! * `/sys/kernel/devices`: lists the device tree and the drivers bound to the devices
+//!   (see [`crate::drivers::device`]).
+//! * `/sys/kernel/cgroups`: lists the task groups. Writing runs the commands that
+//!   configure them (see [`crate::userland::cgroup`]).
+//! * `/sys/power/state`: lists the supported sleep states. Writing `mem` suspends the
+//!   system to RAM (ACPI S3).
+//!
+//! [`TraceRecord`]: crate::trace::TraceRecord
+
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use core::fmt::Write;
+
+use spin::Once;
+
+use crate::drivers::device;
+use crate::fs::{lookup_path, Path};
+use crate::trace::{self, TraceEvent};
+use crate::userland::cgroup;
+
+use super::cache::DirCacheItem;
+use super::devfs::{alloc_device_marker, install_device_at, Device};
+use super::inode::INodeInterface;
+use super::ramfs::RamFs;
+use super::{FileSystem, FileSystemError, Result, INIT_MOUNT_NAMESPACE};
+
+lazy_static::lazy_static! {
+    pub static ref SYS_FILESYSTEM: Arc<SysFs> = SysFs::new();
+}
+
+/// Implementation of sys filesystem. (See the module-level documentation for more
+/// information).
+pub struct SysFs(Arc<RamFs>);
+
+impl SysFs {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(RamFs::new()))
+    }
+}
+
+impl FileSystem for SysFs {
+    fn root_dir(&self) -> DirCacheItem {
+        self.0.root_dir()
+    }
+}
+
+/// Copies the part of `contents` starting at `offset` into `buffer`.
+fn read_string(contents: &str, offset: usize, buffer: &mut [u8]) -> usize {
+    let contents = contents.as_bytes();
+
+    if offset >= contents.len() {
+        return 0;
+    }
+
+    let size = core::cmp::min(buffer.len(), contents.len() - offset);
+    buffer[..size].copy_from_slice(&contents[offset..offset + size]);
+
+    size
+}
+
+struct TraceEnable(usize);
+
+impl TraceEnable {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for TraceEnable {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("enable")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        TRACE_ENABLE.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for TraceEnable {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let mut contents = String::new();
+
+        for event in TraceEvent::ALL {
+            let _ = writeln!(
+                contents,
+                "{} {}",
+                event.name(),
+                trace::is_enabled(event) as u8
+            );
+        }
+
+        Ok(read_string(&contents, offset, buffer))
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        let input = core::str::from_utf8(buffer).map_err(|_| FileSystemError::NotSupported)?;
+
+        for line in input.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+
+            let (name, enabled) = match (words.next(), words.next()) {
+                (Some(name), Some("1")) => (name, true),
+                (Some(name), Some("0")) => (name, false),
+                _ => return Err(FileSystemError::NotSupported),
+            };
+
+            if name == "all" {
+                TraceEvent::ALL
+                    .iter()
+                    .for_each(|event| trace::set_enabled(*event, enabled));
+            } else {
+                let event = TraceEvent::from_name(name).ok_or(FileSystemError::EntryNotFound)?;
+                trace::set_enabled(event, enabled);
+            }
+        }
+
+        Ok(buffer.len())
+    }
+}
+
+struct TraceBuffer(usize);
+
+impl TraceBuffer {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for TraceBuffer {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("buffer")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        TRACE_BUFFER.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for TraceBuffer {
+    // The records are consumed as they are read, so the offset is ignored.
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        Ok(trace::read_records(buffer))
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        trace::clear();
+        Ok(buffer.len())
+    }
+}
+
+struct Devices(usize);
+
+impl Devices {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for Devices {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("devices")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        DEVICES.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for Devices {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        Ok(read_string(&device::describe(), offset, buffer))
+    }
+}
+
+struct Cgroups(usize);
+
+impl Cgroups {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for Cgroups {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("cgroups")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        CGROUPS.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for Cgroups {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        Ok(read_string(&cgroup::describe(), offset, buffer))
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        let commands = core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidPath)?;
+
+        cgroup::configure(commands).ok_or(FileSystemError::InvalidPath)?;
+        Ok(buffer.len())
+    }
+}
+
+struct PowerState(usize);
+
+impl PowerState {
+    fn new() -> Arc<Self> {
+        Arc::new(Self(alloc_device_marker()))
+    }
+}
+
+impl Device for PowerState {
+    fn device_marker(&self) -> usize {
+        self.0
+    }
+
+    fn device_name(&self) -> String {
+        String::from("state")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        POWER_STATE.get().expect("device not initialized").clone()
+    }
+}
+
+impl INodeInterface for PowerState {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let contents = if cfg!(target_arch = "x86_64") {
+            "mem\n"
+        } else {
+            "\n"
+        };
+
+        Ok(read_string(contents, offset, buffer))
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> Result<usize> {
+        let input = core::str::from_utf8(buffer).map_err(|_| FileSystemError::NotSupported)?;
+
+        match input.trim() {
+            #[cfg(target_arch = "x86_64")]
+            "mem" => crate::arch::suspend::suspend().map_err(|err| {
+                log::warn!("sysfs: failed to suspend: {err:?}");
+                FileSystemError::Busy
+            })?,
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(buffer.len())
+    }
+}
+
+static TRACE_ENABLE: Once<Arc<TraceEnable>> = Once::new();
+static TRACE_BUFFER: Once<Arc<TraceBuffer>> = Once::new();
+static DEVICES: Once<Arc<Devices>> = Once::new();
+static CGROUPS: Once<Arc<Cgroups>> = Once::new();
+static POWER_STATE: Once<Arc<PowerState>> = Once::new();
+
+/// Initializes the sys filesystem. (See the module-level documentation for more information).
+pub(super) fn init() -> Result<()> {
+    lazy_static::initialize(&SYS_FILESYSTEM);
+
+    let inode = lookup_path(Path::new("/sys"))?;
+    INIT_MOUNT_NAMESPACE.mount(inode, SYS_FILESYSTEM.clone())?;
+
+    let root = SYS_FILESYSTEM.root_dir().inode();
+    let kernel_dir = root.mkdir("kernel")?;
+    let trace_dir = kernel_dir.mkdir("trace")?;
+
+    {
+        let enable = TRACE_ENABLE.call_once(|| TraceEnable::new());
+        let buffer = TRACE_BUFFER.call_once(|| TraceBuffer::new());
+
+        install_device_at(trace_dir.clone(), enable.clone())?;
+        install_device_at(trace_dir, buffer.clone())?;
+    }
+
+    {
+        let devices = DEVICES.call_once(|| Devices::new());
+        let cgroups = CGROUPS.call_once(|| Cgroups::new());
+
+        install_device_at(kernel_dir.clone(), devices.clone())?;
+        install_device_at(kernel_dir, cgroups.clone())?;
+    }
+
+    {
+        let state = POWER_STATE.call_once(|| PowerState::new());
+        install_device_at(root.mkdir("power")?, state.clone())?;
+    }
+
+    Ok(())
+}
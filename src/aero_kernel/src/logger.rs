@@ -17,22 +17,154 @@
  * along with Aero. If not, see <https://www.gnu.org/licenses/>.
  */
 
+//! The kernel log. Every log record is stored in a fixed-size ring buffer of
+//! (timestamp, level, message) records, which retains the messages logged before the
+//! console or the heap are available. The records at or above the console log level are
+//! also printed to the serial port (and the framebuffer if rendy debug is enabled).
+//!
+//! The ring buffer is read by the `syslog` syscall, `/dev/kmsg` and `/proc/kmsg`.
+
 use core::fmt::Write;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use alloc::string::String;
 use log::{Level, LevelFilter, Metadata, Record};
-use spin::Once;
 
 use crate::userland::scheduler;
-use crate::utils::buffer::RingBuffer;
 use crate::utils::sync::Mutex;
 
-const DEFAULT_LOG_RING_BUFFER_SIZE: usize = 4096;
+/// The amount of records the log ring buffer can hold.
+const LOG_RECORDS: usize = 256;
+/// The maximum length of a log message, longer messages are truncated.
+const LOG_MESSAGE_SIZE: usize = 120;
 
-static LOG_RING_BUFFER: Once<Mutex<RingBuffer<[u8; DEFAULT_LOG_RING_BUFFER_SIZE]>>> = Once::new();
+static LOG_BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
 static LOGGER: AeroLogger = AeroLogger;
 
 static RENDY_DEBUG: AtomicBool = AtomicBool::new(false);
+static SERIAL_CONSOLE: AtomicBool = AtomicBool::new(true);
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+#[derive(Clone, Copy)]
+struct LogRecord {
+    /// The time elapsed since boot, in nanoseconds.
+    timestamp: u64,
+    level: Level,
+    message: [u8; LOG_MESSAGE_SIZE],
+    len: usize,
+}
+
+impl LogRecord {
+    const EMPTY: Self = Self {
+        timestamp: 0,
+        level: Level::Trace,
+        message: [0; LOG_MESSAGE_SIZE],
+        len: 0,
+    };
+
+    fn message(&self) -> &str {
+        // SAFETY: The message is only ever written by `write_str` which makes sure that
+        // it is truncated at a character boundary.
+        unsafe { core::str::from_utf8_unchecked(&self.message[..self.len]) }
+    }
+
+    /// Returns the syslog priority of the record's level.
+    fn priority(&self) -> usize {
+        match self.level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    /// Formats the record in the same format as the linux kernel
+    /// (`<priority>[seconds.microseconds] message`).
+    fn format(&self, output: &mut String) {
+        let _ = writeln!(
+            output,
+            "<{}>[{:>5}.{:06}] {}",
+            self.priority(),
+            self.timestamp / 1_000_000_000,
+            (self.timestamp % 1_000_000_000) / 1000,
+            self.message()
+        );
+    }
+}
+
+impl Write for LogRecord {
+    fn write_str(&mut self, string: &str) -> core::fmt::Result {
+        let mut size = core::cmp::min(string.len(), LOG_MESSAGE_SIZE - self.len);
+
+        // Truncate the message at a character boundary.
+        while !string.is_char_boundary(size) {
+            size -= 1;
+        }
+
+        self.message[self.len..self.len + size].copy_from_slice(&string.as_bytes()[..size]);
+        self.len += size;
+
+        Ok(())
+    }
+}
+
+/// Ring buffer of log records. Each record is identified by a sequence number, the record
+/// with the sequence number `n` is stored at index `n % LOG_RECORDS`.
+struct LogBuffer {
+    records: [LogRecord; LOG_RECORDS],
+    /// The sequence number of the next record.
+    next: usize,
+    /// The sequence number of the first record that has not been cleared.
+    first: usize,
+    /// The sequence number of the first record that has not been read by
+    /// [`read_unread`].
+    unread: usize,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self {
+            records: [LogRecord::EMPTY; LOG_RECORDS],
+            next: 0,
+            first: 0,
+            unread: 0,
+        }
+    }
+
+    fn push(&mut self, record: LogRecord) {
+        self.records[self.next % LOG_RECORDS] = record;
+        self.next += 1;
+    }
+
+    /// Returns the sequence number of the oldest record that is still available.
+    fn oldest(&self) -> usize {
+        core::cmp::max(self.first, self.next.saturating_sub(LOG_RECORDS))
+    }
+
+    fn records(&self, from: usize) -> impl Iterator<Item = &LogRecord> {
+        let from = core::cmp::max(from, self.oldest());
+        (from..self.next).map(move |seq| &self.records[seq % LOG_RECORDS])
+    }
+
+    fn format(&self, from: usize) -> String {
+        let mut output = String::new();
+
+        for record in self.records(from) {
+            record.format(&mut output);
+        }
+
+        output
+    }
+
+    fn clear(&mut self) {
+        self.first = self.next;
+        self.unread = self.next;
+    }
+
+    fn unread(&self) -> usize {
+        self.next - core::cmp::max(self.unread, self.oldest())
+    }
+}
 
 struct AeroLogger;
 
@@ -60,40 +192,57 @@ impl log::Log for AeroLogger {
             let level = record.level();
             let rendy_dbg = RENDY_DEBUG.load(Ordering::Relaxed);
 
+            // Append the log record to the log ring buffer.
+            let clock = crate::arch::time::get_monotonic_clock();
+
+            let mut log_record = LogRecord {
+                timestamp: clock.tv_sec as u64 * 1_000_000_000 + clock.tv_nsec as u64,
+                level,
+                ..LogRecord::EMPTY
+            };
+
+            let _ = write!(log_record, "{}", record.args());
+            LOG_BUFFER.lock_irq().push(log_record);
+
+            if level as usize > CONSOLE_LEVEL.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let serial = SERIAL_CONSOLE.load(Ordering::Relaxed);
+
             macro log_ln($($arg:tt)*) {
-                serial_println!("{}", format_args!($($arg)*));
+                if serial { serial_println!("{}", format_args!($($arg)*)); }
                 if rendy_dbg { $crate::rendy::println!("{}", format_args!($($arg)*)); }
             }
 
-            // Append the log message to the log ring buffer.
-            let mut log_ring = LOG_RING_BUFFER.get().unwrap().lock_irq();
-            let _ = writeln!(log_ring, "[{}] {}", level, record.args());
-
-            serial_print!("\x1b[37;1m{file}:{line} ");
-
-            if scheduler::is_initialized() {
-                // fetch the current task, grab the TID and PID.
-                scheduler::get_scheduler()
-                    .inner
-                    .current_task_optional()
-                    .map(|task| {
-                        serial_print!(
-                            "(tid={}, pid={}) ",
-                            task.tid().as_usize(),
-                            task.pid().as_usize()
-                        );
-                    });
-            }
+            if serial {
+                serial_print!("\x1b[37;1m{file}:{line} ");
+
+                if scheduler::is_initialized() {
+                    // fetch the current task, grab the TID and PID.
+                    scheduler::get_scheduler()
+                        .inner
+                        .current_task_optional()
+                        .map(|task| {
+                            serial_print!(
+                                "(tid={}, pid={}) ",
+                                task.tid().as_usize(),
+                                task.pid().as_usize()
+                            );
+                        });
+                }
 
-            match record.level() {
-                Level::Info => serial_print!("\x1b[32;1minfo "), // green info
-                Level::Warn => serial_print!("\x1b[33;1mwarn "), // yellow warn
-                Level::Error => serial_print!("\x1b[32;1merror "), // red error
-                Level::Debug => serial_print!("\x1b[35;1mdebug "), // gray debug
-                Level::Trace => serial_print!("\x1b[34;1mtrace "), // blue trace
+                match record.level() {
+                    Level::Info => serial_print!("\x1b[32;1minfo "), // green info
+                    Level::Warn => serial_print!("\x1b[33;1mwarn "), // yellow warn
+                    Level::Error => serial_print!("\x1b[32;1merror "), // red error
+                    Level::Debug => serial_print!("\x1b[35;1mdebug "), // gray debug
+                    Level::Trace => serial_print!("\x1b[34;1mtrace "), // blue trace
+                }
+
+                serial_print!("\x1b[0m");
             }
 
-            serial_print!("\x1b[0m");
             log_ln!("{}", record.args());
         }
     }
@@ -107,15 +256,99 @@ impl log::Log for AeroLogger {
 /// This method is not memory safe and should be only used when absolutely necessary.
 #[inline]
 pub unsafe fn force_unlock() {
-    LOG_RING_BUFFER.get().map(|l| l.force_unlock());
+    LOG_BUFFER.force_unlock();
+}
+
+/// Returns all of the records in the log ring buffer, formatted.
+pub fn get_log_buffer() -> String {
+    LOG_BUFFER.lock_irq().format(0)
+}
+
+/// Returns the records that have not been read yet, up to `size` bytes, without marking
+/// them as read. The returned sequence number is passed to [`mark_read`] once the records
+/// have been consumed.
+pub fn peek_unread(size: usize) -> (String, usize) {
+    let buffer = LOG_BUFFER.lock_irq();
+
+    let mut output = String::new();
+    let mut read = core::cmp::max(buffer.unread, buffer.oldest());
+
+    for record in buffer.records(read) {
+        let mut formatted = String::new();
+        record.format(&mut formatted);
+
+        if output.len() + formatted.len() > size {
+            break;
+        }
+
+        output.push_str(&formatted);
+        read += 1;
+    }
+
+    (output, read)
 }
 
-pub fn get_log_buffer<'a>() -> String {
-    LOG_RING_BUFFER
-        .get()
-        .map(|l| String::from(l.lock_irq().extract()))
-        .expect("log: attempted to get the log ring buffer before it was initialized")
-        .clone()
+/// Marks the records before the sequence number `until`, as returned by [`peek_unread`],
+/// as read.
+pub fn mark_read(until: usize) {
+    let mut buffer = LOG_BUFFER.lock_irq();
+    buffer.unread = core::cmp::max(buffer.unread, until);
+}
+
+/// Returns the records that have not been read yet, up to `size` bytes, and marks them
+/// as read.
+pub fn read_unread(size: usize) -> String {
+    let (records, until) = peek_unread(size);
+    mark_read(until);
+
+    records
+}
+
+/// Returns the size of the formatted records that have not been read by [`read_unread`]
+/// yet.
+pub fn read_unread_size() -> usize {
+    let buffer = LOG_BUFFER.lock_irq();
+    buffer.format(buffer.unread).len()
+}
+
+/// Clears the log ring buffer.
+pub fn clear() {
+    LOG_BUFFER.lock_irq().clear();
+}
+
+/// Sets the maximum level of the log records that are printed on the console. All
+/// of the records are still stored in the log ring buffer.
+pub fn set_console_level(level: LevelFilter) {
+    CONSOLE_LEVEL.store(level as usize, Ordering::SeqCst);
+}
+
+/// Sets whether the log records are printed on the serial port (`console=ttyS0`). The
+/// serial port is used by default.
+pub fn set_serial_console(enabled: bool) {
+    SERIAL_CONSOLE.store(enabled, Ordering::SeqCst);
+}
+
+pub fn console_level() -> LevelFilter {
+    match CONSOLE_LEVEL.load(Ordering::SeqCst) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Prints the records that were logged before the framebuffer was initialized.
+pub fn replay_to_rendy() {
+    let buffer = LOG_BUFFER.lock_irq();
+    let level = CONSOLE_LEVEL.load(Ordering::SeqCst);
+
+    for record in buffer.records(0) {
+        if record.level as usize <= level {
+            crate::rendy::println!("{}", record.message());
+        }
+    }
 }
 
 #[inline]
@@ -129,9 +362,51 @@ pub fn set_rendy_debug(yes: bool) {
 }
 
 pub fn init() {
-    LOG_RING_BUFFER.call_once(|| Mutex::new(RingBuffer::new([0; DEFAULT_LOG_RING_BUFFER_SIZE])));
-
     log::set_logger(&LOGGER)
         .map(|()| log::set_max_level(LevelFilter::Trace))
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        let mut record = LogRecord::EMPTY;
+        write!(record, "{}", message).unwrap();
+        record
+    }
+
+    #[test]
+    fn log_buffer_wraps_around() {
+        let mut buffer = LogBuffer::new();
+
+        for i in 0..LOG_RECORDS + 2 {
+            buffer.push(record(&alloc::format!("{}", i)));
+        }
+
+        let mut records = buffer.records(0);
+
+        assert_eq!(records.next().unwrap().message(), "2");
+        assert_eq!(records.count(), LOG_RECORDS - 1);
+        assert_eq!(buffer.unread(), LOG_RECORDS);
+    }
+
+    #[test]
+    fn log_buffer_clear() {
+        let mut buffer = LogBuffer::new();
+
+        buffer.push(record("first"));
+        buffer.clear();
+        buffer.push(record("second"));
+
+        assert_eq!(buffer.unread(), 1);
+        assert_eq!(buffer.records(0).next().unwrap().message(), "second");
+    }
+
+    #[test]
+    fn log_record_truncates() {
+        let long = "x".repeat(LOG_MESSAGE_SIZE + 10);
+        assert_eq!(record(&long).len, LOG_MESSAGE_SIZE);
+    }
+}
@@ -44,6 +44,7 @@
     maybe_uninit_write_slice,
     maybe_uninit_as_bytes
 )]
+#![cfg_attr(feature = "lockdep", feature(const_caller_location))]
 #![deny(trivial_numeric_casts, unused_allocation)]
 #![test_runner(crate::tests::test_runner)]
 #![no_std]
@@ -82,14 +83,22 @@ mod fs;
 mod logger;
 mod mem;
 mod modules;
+mod net;
+mod random;
 mod rendy;
 mod socket;
 mod syscall;
 #[cfg(test)]
 mod tests;
+mod timer;
+mod trace;
 mod unwind;
 mod userland;
 mod utils;
+// FIXME: aarch64 port
+#[cfg(target_arch = "x86_64")]
+mod watchdog;
+mod workqueue;
 
 use self::mem::alloc::LockedHeap;
 use self::mem::paging::VirtAddr;
@@ -113,12 +122,25 @@ fn aero_main() -> ! {
     fs::init().unwrap();
     log::info!("loaded filesystem");
 
+    trace::init();
+    log::info!("loaded tracepoints");
+
     crate::arch::time::init();
     log::info!("loaded timer");
 
+    random::init();
+    log::info!("loaded random");
+
+    #[cfg(target_arch = "x86_64")]
+    userland::vdso::init();
+    log::info!("loaded vdso");
+
     userland::scheduler::init();
     log::info!("loaded scheduler");
 
+    #[cfg(feature = "lockdep")]
+    utils::lockdep::init();
+
     #[cfg(target_arch = "x86_64")]
     crate::arch::apic::mark_bsp_ready(true);
 
@@ -144,9 +166,6 @@ fn kernel_main_thread() {
     modules::init();
     log::info!("loaded kernel modules");
 
-    #[cfg(target_arch = "x86_64")]
-    arch::enable_acpi();
-
     #[cfg(test)]
     test_main();
 
@@ -0,0 +1,279 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! DMA (Direct Memory Access) buffers. A device transfers data using the physical (bus)
+//! addresses, so a DMA buffer that is bigger than a page must be made of physically
+//! contiguous pages. The buffers are allocated from the buddy allocator, which limits
+//! their size to the biggest buddy (2MiB).
+//!
+//! The buffers are accessed through the higher half direct map, which is cacheable. This
+//! is fine on x86_64 as the PCI(e) DMA transfers are snooped by the caches.
+//!
+//! If the device is attached to the IOMMU (see [`crate::drivers::vtd`]), its buffers have
+//! to be allocated with [`dma_alloc_coherent_for`] (or with [`DmaAllocator::for_device`]),
+//! which maps them into the domain of the device.
+
+use alloc::boxed::Box;
+
+use core::alloc::{AllocError, Allocator, Layout};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+#[cfg(target_arch = "x86_64")]
+use crate::drivers::{pci::PciHeader, vtd};
+use crate::mem::paging::*;
+
+bitflags::bitflags! {
+    pub struct DmaFlags: u32 {
+        /// The buffer must be below 4GiB, for devices that can only address 32 bits.
+        const DMA32 = 1 << 0;
+    }
+}
+
+/// A physically contiguous and zeroed DMA buffer, returned by [`dma_alloc_coherent`].
+/// The buffer is freed when dropped.
+pub struct DmaRegion {
+    phys: PhysAddr,
+    size: usize,
+    order: usize,
+    /// The device that the buffer is mapped into, if it was allocated for one.
+    #[cfg(target_arch = "x86_64")]
+    device: Option<PciHeader>,
+}
+
+impl DmaRegion {
+    /// Returns the address the device uses to access the buffer.
+    pub fn bus_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Returns the address the kernel uses to access the buffer.
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.phys.as_hhdm_virt()
+    }
+
+    /// Returns the requested size of the buffer, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: The buffer is mapped and owned by this region.
+        unsafe { core::slice::from_raw_parts(self.virt_addr().as_ptr(), self.size) }
+    }
+
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        // SAFETY: The buffer is mapped and owned by this region.
+        unsafe { core::slice::from_raw_parts_mut(self.virt_addr().as_mut_ptr(), self.size) }
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(device) = self.device.as_ref() {
+            vtd::unmap(device, self.phys, self.size);
+        }
+
+        FRAME_ALLOCATOR.deallocate_contiguous(self.phys, self.order);
+    }
+}
+
+impl core::fmt::Debug for DmaRegion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DmaRegion")
+            .field("bus_addr", &self.phys)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// Allocates a physically contiguous, page aligned and zeroed DMA buffer of `size` bytes.
+pub fn dma_alloc_coherent(size: usize, flags: DmaFlags) -> Result<DmaRegion, AllocError> {
+    let order = LockedFrameAllocator::order_for_size(size as u64).ok_or(AllocError)?;
+    let limit = flags
+        .contains(DmaFlags::DMA32)
+        .then(|| PhysAddr::new(1 << 32));
+
+    let phys = FRAME_ALLOCATOR
+        .allocate_contiguous(order, limit)
+        .ok_or(AllocError)?;
+
+    let mut region = DmaRegion {
+        phys,
+        size,
+        order,
+        #[cfg(target_arch = "x86_64")]
+        device: None,
+    };
+
+    region.as_slice_mut().fill(0);
+    Ok(region)
+}
+
+/// Allocates a DMA buffer (see [`dma_alloc_coherent`]) and maps it into the IOMMU domain
+/// of the device. The buffer is unmapped when dropped.
+#[cfg(target_arch = "x86_64")]
+pub fn dma_alloc_coherent_for(
+    device: &PciHeader,
+    size: usize,
+    flags: DmaFlags,
+) -> Result<DmaRegion, AllocError> {
+    let mut region = dma_alloc_coherent(size, flags)?;
+
+    vtd::map(device, region.phys, region.size, true);
+    region.device = Some(*device);
+
+    Ok(region)
+}
+
+#[derive(Default, Copy, Clone)]
+pub struct DmaAllocator {
+    #[cfg(target_arch = "x86_64")]
+    device: Option<PciHeader>,
+}
+
+impl DmaAllocator {
+    /// Returns an allocator whose buffers are mapped into the IOMMU domain of the device.
+    #[cfg(target_arch = "x86_64")]
+    pub fn for_device(device: PciHeader) -> Self {
+        Self {
+            device: Some(device),
+        }
+    }
+
+    fn alloc_region(&self, size: usize) -> Result<DmaRegion, AllocError> {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(device) = self.device.as_ref() {
+            return dma_alloc_coherent_for(device, size, DmaFlags::empty());
+        }
+
+        dma_alloc_coherent(size, DmaFlags::empty())
+    }
+}
+
+unsafe impl Allocator for DmaAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // The buffer is aligned to a page boundary, which satisfies the alignment of the
+        // layout as long as it is not bigger than a page.
+        if layout.align() > Size4KiB::SIZE as usize {
+            return Err(AllocError);
+        }
+
+        let region = self.alloc_region(layout.size())?;
+        let size = region.size();
+
+        // SAFETY: The buffer is page aligned and non-null.
+        let ptr = unsafe { NonNull::new_unchecked(region.virt_addr().as_mut_ptr::<u8>()) };
+
+        // The buffer is freed in `deallocate`.
+        core::mem::forget(region);
+        Ok(NonNull::slice_from_raw_parts(ptr, size))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let virt = VirtAddr::new(ptr.as_ptr() as u64);
+        let order = LockedFrameAllocator::order_for_size(layout.size() as u64)
+            .expect("dma: invalid layout");
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(device) = self.device.as_ref() {
+            vtd::unmap(device, virt.as_hhdm_phys(), layout.size());
+        }
+
+        FRAME_ALLOCATOR.deallocate_contiguous(virt.as_hhdm_phys(), order);
+    }
+}
+
+pub type DmaBuffer<T> = Box<T, DmaAllocator>;
+
+#[repr(C)]
+pub struct Dma<T: ?Sized>(DmaBuffer<T>);
+
+impl<T> Dma<T> {
+    /// Creates a new DMA (Direct Memory Access) buffer and is initialized
+    /// with zeros.
+    ///
+    /// ## Examples
+    /// ```rust,no_run
+    /// let dma: Command = Dma::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::new_in(DmaAllocator::default())
+    }
+
+    /// Creates a new zeroed DMA buffer using the provided allocator.
+    pub fn new_in(allocator: DmaAllocator) -> Self {
+        let mut buffer = DmaBuffer::new_uninit_in(allocator);
+
+        // SAFETY: Box returns a non-null and aligned pointer.
+        unsafe {
+            core::ptr::write_bytes(buffer.as_mut_ptr(), 0, 1);
+        }
+
+        // SAFETY: We have initialized the buffer above.
+        Dma(unsafe { buffer.assume_init() })
+    }
+
+    pub fn new_uninit_slice(len: usize) -> Dma<[MaybeUninit<T>]> {
+        Self::new_uninit_slice_in(len, DmaAllocator::default())
+    }
+
+    pub fn new_uninit_slice_in(len: usize, allocator: DmaAllocator) -> Dma<[MaybeUninit<T>]> {
+        Dma(DmaBuffer::new_uninit_slice_in(len, allocator))
+    }
+}
+
+impl<T> Dma<[MaybeUninit<T>]> {
+    /// ## Safety
+    ///
+    /// As with [`MaybeUninit::assume_init`], it is up to the caller to guarantee
+    /// that the value really is in an initialized state. Calling this when the
+    /// content is not yet fully initialized causes immediate undefined behavior.
+    pub unsafe fn assume_init(self) -> Dma<[T]> {
+        Dma(self.0.assume_init())
+    }
+}
+
+impl<T: ?Sized> core::ops::Deref for Dma<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> core::ops::DerefMut for Dma<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: ?Sized + core::fmt::Debug> core::fmt::Debug for Dma<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("Dma").field(&self.0).finish()
+    }
+}
+
+impl<T: ?Sized> Dma<T> {
+    pub fn addr(&self) -> PhysAddr {
+        let virt = VirtAddr::new((&*self.0 as *const T as *const u8) as u64);
+        virt.as_hhdm_phys()
+    }
+}
@@ -18,8 +18,12 @@
  */
 
 pub mod alloc;
+pub mod dma;
+pub mod numa;
 pub mod paging;
 pub mod pti;
+pub mod reclaim;
+pub mod uaccess;
 mod vmalloc;
 
 use core::alloc::Layout;
@@ -0,0 +1,334 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Memory zones and NUMA nodes.
+//!
+//! The physical memory is split into nodes, using the memory affinity structures of the
+//! ACPI SRAT (or a single node covering all of the memory if the platform has none).
+//! The memory of each node is split into a `Normal` zone and a `DMA32` zone (the memory
+//! below 4GiB, which is also used by the devices that can only address 32 bits).
+//!
+//! The per-CPU data is allocated from the node of the CPU (see [`alloc_percpu`]) and
+//! falls back to the other nodes, nearest first (using the distances of the ACPI SLIT).
+//! Within a node, the `Normal` zone is preferred over the `DMA32` zone.
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::alloc::alloc_zeroed;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::acpi::{slit, srat};
+use crate::mem::paging::*;
+
+/// The end of the memory addressable by 32-bit DMA.
+const DMA32_LIMIT: u64 = 1 << 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZoneKind {
+    Dma32,
+    Normal,
+}
+
+impl ZoneKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZoneKind::Dma32 => "dma32",
+            ZoneKind::Normal => "normal",
+        }
+    }
+}
+
+/// A range of physical memory of a node.
+pub struct Zone {
+    pub kind: ZoneKind,
+    pub start: PhysAddr,
+    /// The end of the zone (exclusive).
+    pub end: PhysAddr,
+    /// The number of 4KiB frames allocated from the zone through [`allocate_on_node`].
+    allocated: AtomicUsize,
+}
+
+impl Zone {
+    fn new(kind: ZoneKind, start: PhysAddr, end: PhysAddr) -> Self {
+        Self {
+            kind,
+            start,
+            end,
+            allocated: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of free 4KiB frames in the zone.
+    pub fn free_frames(&self) -> usize {
+        FRAME_ALLOCATOR.free_frames_in(self.start, self.end)
+    }
+
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Node {
+    pub id: usize,
+    /// The ACPI proximity domain of the node.
+    pub domain: u32,
+    pub cpus: Vec<usize>,
+    /// The zones of the node, `Normal` zones first.
+    pub zones: Vec<Zone>,
+    /// The IDs of the nodes to allocate from, nearest first (starting with this node).
+    fallback: Vec<usize>,
+    /// The number of allocations for this node that were satisfied by this node.
+    hits: AtomicUsize,
+    /// The number of allocations for this node that were satisfied by another node.
+    misses: AtomicUsize,
+}
+
+impl Node {
+    fn new(id: usize, domain: u32) -> Self {
+        Self {
+            id,
+            domain,
+            cpus: Vec::new(),
+            zones: Vec::new(),
+            fallback: Vec::new(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds the `start..end` memory range to the node, split at the DMA32 limit.
+    fn add_range(&mut self, start: PhysAddr, end: PhysAddr) {
+        let limit = PhysAddr::new(DMA32_LIMIT);
+
+        if start < limit {
+            let dma_end = core::cmp::min(end, limit);
+            self.zones.push(Zone::new(ZoneKind::Dma32, start, dma_end));
+        }
+
+        if end > limit {
+            let normal_start = core::cmp::max(start, limit);
+            self.zones
+                .push(Zone::new(ZoneKind::Normal, normal_start, end));
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+static NODES: Once<Vec<Node>> = Once::new();
+/// Maps the CPU IDs to their node IDs.
+static CPU_NODES: Once<Vec<(usize, usize)>> = Once::new();
+
+/// Returns the NUMA nodes, or an empty slice before [`init`] is called.
+pub fn nodes() -> &'static [Node] {
+    NODES.get().map_or(&[], |nodes| nodes.as_slice())
+}
+
+/// Returns the ID of the node of the provided CPU.
+pub fn cpu_node(cpu: usize) -> Option<usize> {
+    CPU_NODES
+        .get()?
+        .iter()
+        .find(|(id, _)| *id == cpu)
+        .map(|(_, node)| *node)
+}
+
+/// Returns the relative distance between the nodes `from` and `to`.
+pub fn distance(from: usize, to: usize) -> u8 {
+    let nodes = nodes();
+    slit::distance(nodes[from].domain, nodes[to].domain)
+}
+
+/// Allocates a physically contiguous chunk of the provided `order` (see
+/// [`LockedFrameAllocator::order_for_size`]), preferably from the `node`. The chunk is not
+/// zeroed.
+pub fn allocate_on_node(node: usize, order: usize) -> Option<PhysAddr> {
+    let nodes = nodes();
+    let preferred = nodes.get(node)?;
+
+    let frames = match order {
+        0 => 1,
+        1 => 4,
+        _ => (Size2MiB::SIZE / Size4KiB::SIZE) as usize,
+    };
+
+    for &id in preferred.fallback.iter() {
+        for zone in nodes[id].zones.iter() {
+            if let Some(addr) = FRAME_ALLOCATOR.allocate_in(order, zone.start, zone.end) {
+                zone.allocated.fetch_add(frames, Ordering::Relaxed);
+
+                if id == node {
+                    preferred.hits.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    preferred.misses.fetch_add(1, Ordering::Relaxed);
+                }
+
+                return Some(addr);
+            }
+        }
+    }
+
+    None
+}
+
+/// Allocates zeroed memory for the per-CPU data of the provided `cpu`, from the node of
+/// the CPU if possible. The memory is never freed.
+pub fn alloc_percpu(cpu: usize, layout: Layout) -> *mut u8 {
+    let fits =
+        layout.size() <= Size4KiB::SIZE as usize && layout.align() <= Size4KiB::SIZE as usize;
+
+    if fits {
+        if let Some(addr) = cpu_node(cpu).and_then(|node| allocate_on_node(node, 0)) {
+            let ptr = addr.as_hhdm_virt().as_mut_ptr::<u8>();
+
+            // SAFETY: The frame was just allocated and is mapped in the HHDM.
+            unsafe { ptr.write_bytes(0, Size4KiB::SIZE as usize) }
+            return ptr;
+        }
+    }
+
+    // SAFETY: The per-CPU data types are not zero sized.
+    unsafe { alloc_zeroed(layout) }
+}
+
+/// Builds the nodes from the memory ranges of the SRAT, clamped to the memory managed
+/// by the frame allocator.
+fn build_nodes(affinity: &[srat::MemoryAffinity], memory_end: PhysAddr) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::new();
+
+    for range in affinity.iter().filter(|range| range.base < memory_end) {
+        let id = match nodes.iter().position(|node| node.domain == range.domain) {
+            Some(id) => id,
+            None => {
+                nodes.push(Node::new(nodes.len(), range.domain));
+                nodes.len() - 1
+            }
+        };
+
+        let end = core::cmp::min(range.end, memory_end);
+        nodes[id].add_range(range.base, end);
+    }
+
+    if nodes.is_empty() {
+        let mut node = Node::new(0, 0);
+
+        node.add_range(PhysAddr::zero(), memory_end);
+        nodes.push(node);
+    }
+
+    for node in nodes.iter_mut() {
+        // The zones of a node are sorted by address, then the `Normal` zones are moved
+        // first (the sort is stable).
+        node.zones.sort_by_key(|zone| zone.start);
+        node.zones.sort_by_key(|zone| zone.kind != ZoneKind::Normal);
+    }
+
+    nodes
+}
+
+/// Sets up the nodes and their zones. Called on the BSP after the ACPI tables are parsed.
+pub fn init() {
+    let memory_end = PhysAddr::new(FRAME_ALLOCATOR.total_frames() as u64 * Size4KiB::SIZE);
+    let affinity = srat::get().map_or(&[][..], |srat| srat.memory.as_slice());
+
+    let mut nodes = build_nodes(affinity, memory_end);
+    let domains = nodes.iter().map(|node| node.domain).collect::<Vec<_>>();
+
+    for node in nodes.iter_mut() {
+        let mut fallback = (0..domains.len()).collect::<Vec<_>>();
+
+        // The node itself always comes first, as the distance to itself is the smallest.
+        fallback.sort_by_key(|&id| (id != node.id, slit::distance(node.domain, domains[id])));
+        node.fallback = fallback;
+    }
+
+    let mut cpu_nodes = Vec::new();
+
+    #[cfg(target_arch = "x86_64")]
+    for cpu in 0..crate::arch::apic::get_cpu_count() {
+        let apic_id = match crate::arch::apic::get_cpu_apic_id(cpu) {
+            Some(apic_id) => apic_id,
+            None => continue,
+        };
+
+        let domain = srat::get()
+            .and_then(|srat| srat.cpus.iter().find(|aff| aff.apic_id == apic_id))
+            .map_or(0, |aff| aff.domain);
+
+        // The CPUs of a domain without memory belong to the first node.
+        let node = domains.iter().position(|&d| d == domain).unwrap_or(0);
+
+        nodes[node].cpus.push(cpu);
+        cpu_nodes.push((cpu, node));
+    }
+
+    for node in nodes.iter() {
+        log::info!(
+            "numa: node {} (domain={}, cpus={:?}, zones={})",
+            node.id,
+            node.domain,
+            node.cpus,
+            node.zones.len()
+        );
+    }
+
+    CPU_NODES.call_once(|| cpu_nodes);
+    NODES.call_once(|| nodes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones_are_split_at_dma32_limit() {
+        let affinity = [
+            srat::MemoryAffinity {
+                domain: 1,
+                base: PhysAddr::new(0),
+                end: PhysAddr::new(DMA32_LIMIT * 2),
+                hot_pluggable: false,
+            },
+            srat::MemoryAffinity {
+                domain: 2,
+                base: PhysAddr::new(DMA32_LIMIT * 2),
+                end: PhysAddr::new(DMA32_LIMIT * 4),
+                hot_pluggable: false,
+            },
+        ];
+
+        let nodes = build_nodes(&affinity, PhysAddr::new(DMA32_LIMIT * 3));
+        assert_eq!(nodes.len(), 2);
+
+        let kinds = nodes[0].zones.iter().map(|z| z.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [ZoneKind::Normal, ZoneKind::Dma32]);
+        assert_eq!(nodes[0].zones[1].end, PhysAddr::new(DMA32_LIMIT));
+
+        // The second node is clamped to the end of the memory.
+        assert_eq!(nodes[1].zones[0].end, PhysAddr::new(DMA32_LIMIT * 3));
+    }
+}
@@ -22,7 +22,6 @@ use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::vec::Vec;
-use limine::{LimineMemmapEntry, LimineMemoryMapEntryType, NonNullPtr};
 use spin::Once;
 
 use super::mapper::*;
@@ -33,9 +32,17 @@ use super::addr::PhysAddr;
 use crate::mem::paging::align_up;
 use crate::utils::bitmap::Bitmap;
 use crate::utils::sync::Mutex;
+use crate::utils::CeilDiv;
 
 static BUDDY_SIZE: [u64; 3] = [Size4KiB::SIZE, Size4KiB::SIZE * 4, Size2MiB::SIZE];
 
+/// A range of usable physical memory, from the memory map provided by the bootloader.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub len: u64,
+}
+
 pub struct LockedFrameAllocator(Once<Mutex<GlobalFrameAllocator>>);
 
 impl LockedFrameAllocator {
@@ -46,7 +53,7 @@ impl LockedFrameAllocator {
     }
 
     /// Initializes the inner locked global frame allocator.
-    pub(super) fn init(&self, memory_map: &mut [NonNullPtr<LimineMemmapEntry>]) {
+    pub(super) fn init(&self, memory_map: impl Iterator<Item = MemoryRegion> + Clone) {
         self.0
             .call_once(|| Mutex::new(GlobalFrameAllocator::new(memory_map)));
     }
@@ -58,7 +65,8 @@ unsafe impl FrameAllocator<Size4KiB> for LockedFrameAllocator {
         // let caller = core::panic::Location::caller();
         // log::debug!("allocation request of 4KiB by {:?}", caller);
 
-        self.0
+        let frame = self
+            .0
             .get()
             .map(|m| {
                 m.lock_irq()
@@ -69,7 +77,11 @@ unsafe impl FrameAllocator<Size4KiB> for LockedFrameAllocator {
             .map(|frame| {
                 frame.as_slice_mut().fill(0);
                 frame
-            })
+            });
+
+        // The lock is released at this point, so the reclaim thread can be woken up.
+        crate::mem::reclaim::check_watermark();
+        frame
     }
 
     #[track_caller]
@@ -119,33 +131,75 @@ unsafe impl FrameAllocator<Size2MiB> for LockedFrameAllocator {
     }
 }
 
-struct RangeMemoryIter<'a> {
-    iter: core::slice::Iter<'a, NonNullPtr<LimineMemmapEntry>>,
+impl LockedFrameAllocator {
+    /// Returns the order of the smallest buddy that fits `size` bytes, or [`None`] if
+    /// `size` is bigger than the biggest buddy.
+    pub fn order_for_size(size: u64) -> Option<usize> {
+        BUDDY_SIZE.iter().position(|&bsize| bsize >= size)
+    }
+
+    /// Allocates a physically contiguous chunk of the provided `order` that ends below
+    /// `limit` (if any). The chunk is not zeroed.
+    pub fn allocate_contiguous(&self, order: usize, limit: Option<PhysAddr>) -> Option<PhysAddr> {
+        self.0
+            .get()?
+            .lock_irq()
+            .allocate_frame_in(order, PhysAddr::zero(), limit)
+    }
+
+    /// Allocates a physically contiguous chunk of the provided `order` inside of the
+    /// `start..end` range. The chunk is not zeroed. Used to allocate from a memory zone
+    /// (see [`crate::mem::numa`]).
+    pub fn allocate_in(&self, order: usize, start: PhysAddr, end: PhysAddr) -> Option<PhysAddr> {
+        self.0
+            .get()?
+            .lock_irq()
+            .allocate_frame_in(order, start, Some(end))
+    }
+
+    /// Returns the number of free 4KiB frames inside of the `start..end` range.
+    pub fn free_frames_in(&self, start: PhysAddr, end: PhysAddr) -> usize {
+        self.0
+            .get()
+            .map_or(0, |m| m.lock_irq().free_frames_in(start, end))
+    }
+
+    /// Returns the number of free 4KiB frames.
+    pub fn free_frames(&self) -> usize {
+        self.0.get().map_or(0, |m| m.lock_irq().free_frames())
+    }
+
+    /// Returns the number of 4KiB frames managed by the allocator.
+    pub fn total_frames(&self) -> usize {
+        self.0.get().map_or(0, |m| m.lock_irq().frame_count())
+    }
+
+    /// Frees a chunk allocated with [`LockedFrameAllocator::allocate_contiguous`].
+    pub fn deallocate_contiguous(&self, addr: PhysAddr, order: usize) {
+        if let Some(allocator) = self.0.get() {
+            allocator.lock_irq().deallocate_frame_inner(addr, order);
+        }
+    }
+}
+
+struct RangeMemoryIter<I> {
+    iter: I,
 
     cursor_base: PhysAddr,
     cursor_end: PhysAddr,
 }
 
-impl<'a> Iterator for RangeMemoryIter<'a> {
+impl<I: Iterator<Item = MemoryRegion>> Iterator for RangeMemoryIter<I> {
     type Item = MemoryRange;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cursor_base >= self.cursor_end {
-            if let Some(entry) = loop {
-                // We need to find out the next useable memory range from
-                // the memory map and set the cursor to the start of it.
-                let next = self.iter.next()?;
-
-                if next.typ == LimineMemoryMapEntryType::Usable {
-                    break Some(next);
-                }
-            } {
-                self.cursor_base = PhysAddr::new(entry.base).align_up(Size4KiB::SIZE);
-                self.cursor_end = PhysAddr::new(entry.base + entry.len);
-            } else {
-                // We reached the end of the memory map.
-                return None;
-            }
+        while self.cursor_base >= self.cursor_end {
+            // We need to find out the next useable memory range from
+            // the memory map and set the cursor to the start of it.
+            let entry = self.iter.next()?;
+
+            self.cursor_base = PhysAddr::new(entry.base).align_up(Size4KiB::SIZE);
+            self.cursor_end = PhysAddr::new(entry.base + entry.len);
         }
 
         let typee = MemoryRangeType::Usable;
@@ -297,53 +351,38 @@ pub struct GlobalFrameAllocator {
 
 impl GlobalFrameAllocator {
     /// Create a new global frame allocator from the memory map provided by the bootloader.
-    fn new(memory_map: &mut [NonNullPtr<LimineMemmapEntry>]) -> Self {
+    fn new(memory_map: impl Iterator<Item = MemoryRegion> + Clone) -> Self {
         // Find a memory map entry that is big enough to fit all of the items in
         // range memory iter.
-        let requested_size = (core::mem::size_of::<MemoryRange>() * memory_map.len()) as u64;
-        let mut region = None;
+        let count = memory_map.clone().count();
+        let requested_size = (core::mem::size_of::<MemoryRange>() * count) as u64;
 
-        for i in 0..memory_map.len() {
-            let entry = &mut memory_map[i];
+        let region = memory_map
+            .clone()
+            .find(|entry| entry.len >= requested_size)
+            .expect("pmm: out of memory");
 
-            // Make sure that the memory map entry is marked as usable.
-            if entry.typ != LimineMemoryMapEntryType::Usable {
-                continue;
-            }
+        let ranges = unsafe {
+            let virt_addr = PhysAddr::new(region.base).as_hhdm_virt();
 
-            // Found a big enough memory map entry.
-            if entry.len >= requested_size {
-                let base = entry.base;
+            core::slice::from_raw_parts_mut::<MemoryRange>(virt_addr.as_mut_ptr(), count)
+        };
 
+        // The ranges themselves are stored at the start of the entry.
+        let memory_map = memory_map.map(|mut entry| {
+            if entry.base == region.base {
                 entry.base += requested_size;
                 entry.len -= requested_size;
-
-                region = Some(PhysAddr::new(base));
-
-                break;
             }
-        }
-
-        let mut iter = memory_map.iter();
-
-        let cursor = iter
-            .next()
-            .expect("stivale2: unexpected end of the memory map");
-
-        let ranges = unsafe {
-            let virt_addr = region.expect("stivale2: out of memory").as_hhdm_virt();
 
-            core::slice::from_raw_parts_mut::<MemoryRange>(
-                virt_addr.as_mut_ptr(),
-                requested_size as usize,
-            )
-        };
+            entry
+        });
 
         let range_iter = RangeMemoryIter {
-            iter,
+            iter: memory_map,
 
-            cursor_base: PhysAddr::new(cursor.base),
-            cursor_end: PhysAddr::new(cursor.base + cursor.len),
+            cursor_base: PhysAddr::zero(),
+            cursor_end: PhysAddr::zero(),
         };
 
         // Lets goo! Now lets initialize the bootstrap allocator so we can initialize
@@ -396,6 +435,37 @@ impl GlobalFrameAllocator {
         (self.end.as_u64() / Size4KiB::SIZE) as usize
     }
 
+    fn free_frames_in(&self, start: PhysAddr, end: PhysAddr) -> usize {
+        let mut count = 0;
+
+        for (order, buddy) in self.buddies.iter().enumerate() {
+            let size = BUDDY_SIZE[order];
+            let base = self.base.align_up(size);
+            let mut idx = self.chunk_idx_from(start, order);
+
+            while let Some(free) = buddy.find_next_set(idx) {
+                let addr = base + size * free as u64;
+
+                if addr + size > end {
+                    break;
+                }
+
+                count += (size / Size4KiB::SIZE) as usize;
+                idx = free + 1;
+            }
+        }
+
+        count
+    }
+
+    fn free_frames(&self) -> usize {
+        self.free
+            .iter()
+            .zip(BUDDY_SIZE.iter())
+            .map(|(&free, &size)| free * (size / Size4KiB::SIZE) as usize)
+            .sum()
+    }
+
     /// Find the perfect buddy order for the provided address range.
     fn find_order(&self, address: PhysAddr, chunk_size: u64) -> usize {
         for order in (0..BUDDY_SIZE.len()).rev() {
@@ -461,15 +531,42 @@ impl GlobalFrameAllocator {
         }
     }
 
-    /// Finds a free chunk with the provided `order`.
-    fn find_free(&mut self, order: usize) -> Option<PhysAddr> {
+    /// Returns the index of the first chunk with the provided `order` that starts at or
+    /// after `start`.
+    fn chunk_idx_from(&self, start: PhysAddr, order: usize) -> usize {
+        let base = self.base.align_up(BUDDY_SIZE[order]);
+
+        if start <= base {
+            0
+        } else {
+            (start - base).ceil_div(BUDDY_SIZE[order]) as usize
+        }
+    }
+
+    /// Finds a free chunk with the provided `order` that starts at or after `start` and
+    /// ends below `limit` (if any).
+    fn find_free(
+        &mut self,
+        order: usize,
+        start: PhysAddr,
+        limit: Option<PhysAddr>,
+    ) -> Option<PhysAddr> {
+        let start_idx = self.chunk_idx_from(start, order);
+
         let buddy = &mut self.buddies[order];
-        let first_free = buddy.find_first_set()?;
+        let first_free = buddy.find_next_set(start_idx)?;
+
+        let addr = self.base.align_up(BUDDY_SIZE[order]) + (BUDDY_SIZE[order] * first_free as u64);
+
+        // The first free chunk is the lowest one, so none of them are below the limit.
+        if limit.map_or(false, |limit| addr + BUDDY_SIZE[order] > limit) {
+            return None;
+        }
 
         buddy.set(first_free, false);
         self.free[order] -= 1;
 
-        Some(self.base.align_up(BUDDY_SIZE[order]) + (BUDDY_SIZE[order] * first_free as u64))
+        Some(addr)
     }
 
     fn clear_bit(&mut self, addr: PhysAddr, order: usize) -> bool {
@@ -517,6 +614,17 @@ impl GlobalFrameAllocator {
     }
 
     fn allocate_frame_inner(&mut self, order: usize) -> Option<PhysAddr> {
+        self.allocate_frame_in(order, PhysAddr::zero(), None)
+    }
+
+    /// Allocates a chunk with the provided `order` that starts at or after `start` and
+    /// ends below `limit` (if any).
+    fn allocate_frame_in(
+        &mut self,
+        order: usize,
+        start: PhysAddr,
+        limit: Option<PhysAddr>,
+    ) -> Option<PhysAddr> {
         let size = BUDDY_SIZE[order];
 
         // Loop through the list of buddies until we can find one that can give us
@@ -525,7 +633,11 @@ impl GlobalFrameAllocator {
             let i = i + order;
 
             if self.free[i] > 0 {
-                let result = self.find_free(i)?;
+                let result = match self.find_free(i, start, limit) {
+                    Some(result) => result,
+                    None => continue,
+                };
+
                 let mut remaning = bsize - size;
 
                 if remaning > 0 {
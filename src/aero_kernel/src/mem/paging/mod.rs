@@ -30,9 +30,6 @@ pub use self::mapper::*;
 pub use self::page::*;
 pub use self::page_table::*;
 
-use limine::LimineMemmapEntry;
-use limine::NonNullPtr;
-
 pub use frame::LockedFrameAllocator;
 
 use crate::PHYSICAL_MEMORY_OFFSET;
@@ -79,9 +76,9 @@ pub const fn level_5_paging_enabled() -> bool {
     false
 }
 
-/// Initialize paging.
+/// Initialize paging, with the usable memory regions of the memory map.
 pub fn init(
-    memory_regions: &mut [NonNullPtr<LimineMemmapEntry>],
+    memory_regions: impl Iterator<Item = MemoryRegion> + Clone,
 ) -> Result<OffsetPageTable<'static>, MapToError<Size4KiB>> {
     let active_level_4 = unsafe { active_level_4_table() };
     let offset_table = unsafe { OffsetPageTable::new(active_level_4, PHYSICAL_MEMORY_OFFSET) };
@@ -0,0 +1,224 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reclaim of the memory held by the kernel caches.
+//!
+//! The caches (the page cache, the inode and directory caches) register a [`Shrinker`]
+//! and keep the objects that are no longer in use around until they are asked to free
+//! them. The `kswapd` thread periodically writes back the dirty objects and, when the
+//! number of free frames drops below the low watermark, asks the shrinkers to free
+//! objects (least recently used first) until the high watermark is reached.
+//!
+//! Anonymous pages cannot be reclaimed as there is no swap device to write them to, so
+//! only the clean, unused cache objects are ever freed.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::mem::paging::FRAME_ALLOCATOR;
+use crate::timer;
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+use crate::utils::sync::Mutex;
+
+/// The interval at which the dirty objects are written back, in nanoseconds.
+const WRITEBACK_INTERVAL: u64 = 5_000_000_000;
+/// The number of objects a shrinker is asked to free at once.
+const SCAN_BATCH: usize = 128;
+
+pub trait Shrinker: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the number of objects that can be freed.
+    fn count(&self) -> usize;
+
+    /// Frees up to `nr` objects and returns the number of objects freed.
+    fn scan(&self, nr: usize) -> usize;
+
+    /// Writes back the dirty objects, so they can be freed, and returns the number of
+    /// objects written back.
+    fn writeback(&self) -> usize {
+        0
+    }
+}
+
+static SHRINKERS: Mutex<Vec<Arc<dyn Shrinker>>> = Mutex::new(Vec::new());
+
+static KSWAPD: Once<Arc<Task>> = Once::new();
+static KSWAPD_PENDING: AtomicBool = AtomicBool::new(false);
+
+static LOW_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+static HIGH_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers the `shrinker`, which will be asked to free objects under memory pressure.
+pub fn register_shrinker(shrinker: Arc<dyn Shrinker>) {
+    log::debug!("reclaim: registered shrinker {}", shrinker.name());
+    SHRINKERS.lock().push(shrinker);
+}
+
+fn shrinkers() -> Vec<Arc<dyn Shrinker>> {
+    // The shrinkers are called without the lock held, as they may allocate.
+    SHRINKERS.lock().clone()
+}
+
+/// Wakes up `kswapd` if the number of free frames dropped below the low watermark.
+/// Called by the frame allocator after every allocation.
+pub fn check_watermark() {
+    let kswapd = match KSWAPD.get() {
+        Some(kswapd) => kswapd,
+        None => return,
+    };
+
+    if FRAME_ALLOCATOR.free_frames() >= LOW_WATERMARK.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if !KSWAPD_PENDING.swap(true, Ordering::AcqRel) {
+        scheduler::get_scheduler().inner.wake_up(kswapd.clone());
+    }
+}
+
+/// Asks the shrinkers to free objects until there are at least `target` free frames
+/// or nothing can be freed anymore. Returns the number of objects freed.
+fn shrink(target: usize) -> usize {
+    let shrinkers = shrinkers();
+    let mut total = 0;
+
+    while FRAME_ALLOCATOR.free_frames() < target {
+        let freed = shrinkers
+            .iter()
+            .filter(|shrinker| shrinker.count() != 0)
+            .map(|shrinker| shrinker.scan(SCAN_BATCH))
+            .sum::<usize>();
+
+        if freed == 0 {
+            break;
+        }
+
+        total += freed;
+    }
+
+    total
+}
+
+fn writeback() -> usize {
+    shrinkers()
+        .iter()
+        .map(|shrinker| shrinker.writeback())
+        .sum()
+}
+
+fn kswapd() {
+    loop {
+        let deadline = timer::now() + WRITEBACK_INTERVAL;
+        let _timer = timer::Timer::wake_current(deadline);
+
+        while !KSWAPD_PENDING.load(Ordering::Acquire) && timer::now() < deadline {
+            // Signals are not delivered to kernel threads.
+            let _ = scheduler::get_scheduler().inner.await_io();
+        }
+
+        let written = writeback();
+
+        if written != 0 {
+            log::trace!("kswapd: wrote back {} objects", written);
+        }
+
+        let low = LOW_WATERMARK.load(Ordering::Relaxed);
+        let high = HIGH_WATERMARK.load(Ordering::Relaxed);
+
+        if FRAME_ALLOCATOR.free_frames() < low {
+            let freed = shrink(high);
+            let free = FRAME_ALLOCATOR.free_frames();
+
+            log::debug!("kswapd: freed {} objects ({} free frames)", freed, free);
+
+            if free < low {
+                log::warn!(
+                    "kswapd: unable to reach the low watermark ({} free frames)",
+                    free
+                );
+            }
+        }
+
+        KSWAPD_PENDING.store(false, Ordering::Release);
+    }
+}
+
+/// Computes the watermarks and spawns the `kswapd` thread.
+pub fn init() {
+    let total = FRAME_ALLOCATOR.total_frames();
+    // Reclaim when less than ~1.5% of the memory is free, until ~3% is free.
+    let low = core::cmp::max(total / 64, 256);
+
+    LOW_WATERMARK.store(low, Ordering::Relaxed);
+    HIGH_WATERMARK.store(low * 2, Ordering::Relaxed);
+
+    let task = Task::new_kernel(kswapd, true);
+
+    KSWAPD.call_once(|| task.clone());
+    scheduler::get_scheduler().register_task(task);
+
+    log::info!("reclaim: started kswapd (low={}, high={})", low, low * 2);
+}
+
+crate::early_initcall!(init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(AtomicUsize);
+
+    impl Shrinker for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+
+        fn scan(&self, nr: usize) -> usize {
+            let count = self.count();
+            let freed = core::cmp::min(count, nr);
+
+            self.0.store(count - freed, Ordering::SeqCst);
+            freed
+        }
+    }
+
+    #[test]
+    fn shrink_stops_when_nothing_is_freed() {
+        let counter = Arc::new(Counter(AtomicUsize::new(SCAN_BATCH + 1)));
+        register_shrinker(counter.clone());
+
+        // The target can never be reached, so the shrinkers are drained (including the
+        // ones registered by the kernel caches).
+        assert!(shrink(usize::MAX) >= SCAN_BATCH + 1);
+        assert_eq!(counter.count(), 0);
+
+        SHRINKERS
+            .lock()
+            .retain(|shrinker| shrinker.name() != "counter");
+    }
+}
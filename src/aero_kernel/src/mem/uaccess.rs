@@ -0,0 +1,186 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Access to the memory of the current userland task.
+//!
+//! The syscall handlers must only touch userland pointers through this module (directly,
+//! or through the validated arguments of the `#[syscall]` macro). The provided range is
+//! checked to be below the end of the userland address space and to be covered by the
+//! mappings of the task with the required protection. The copies themselves are fault
+//! tolerant: if a page cannot be mapped in (for example because another thread unmapped
+//! it concurrently), [`SyscallError::EFAULT`] is returned instead of panicking.
+
+use core::mem::MaybeUninit;
+
+use alloc::vec::Vec;
+
+use aero_syscall::{MMapProt, SyscallError};
+
+use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler;
+
+/// Checks that the range of `len` bytes at `address` belongs to the current userland
+/// task and is mapped with the `protection`.
+pub fn check_range(
+    address: VirtAddr,
+    len: usize,
+    protection: MMapProt,
+) -> Result<(), SyscallError> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let start = address.as_u64();
+    let end = start.checked_add(len as u64).ok_or(SyscallError::EFAULT)?;
+
+    if start == 0 || end > crate::arch::task::userland_last_address().as_u64() {
+        return Err(SyscallError::EFAULT);
+    }
+
+    let mapped = scheduler::get_scheduler()
+        .current_task()
+        .vm
+        .is_range_mapped(address, VirtAddr::new(end), protection);
+
+    if mapped {
+        Ok(())
+    } else {
+        Err(SyscallError::EFAULT)
+    }
+}
+
+unsafe fn copy_raw(dest: *mut u8, src: *const u8, len: usize) -> Result<(), SyscallError> {
+    #[cfg(target_arch = "x86_64")]
+    let left = crate::arch::uaccess::copy_raw(dest, src, len);
+
+    #[cfg(not(target_arch = "x86_64"))]
+    let left = {
+        dest.copy_from(src, len);
+        0
+    };
+
+    if left == 0 {
+        Ok(())
+    } else {
+        Err(SyscallError::EFAULT)
+    }
+}
+
+/// Copies `dest.len()` bytes from the userland address `src` into `dest`.
+pub fn copy_from_user(dest: &mut [u8], src: VirtAddr) -> Result<(), SyscallError> {
+    check_range(src, dest.len(), MMapProt::PROT_READ)?;
+
+    // SAFETY: The source range was validated above and `dest` is a valid kernel buffer.
+    unsafe { copy_raw(dest.as_mut_ptr(), src.as_ptr(), dest.len()) }
+}
+
+/// Copies the bytes of `src` to the userland address `dest`.
+pub fn copy_to_user(dest: VirtAddr, src: &[u8]) -> Result<(), SyscallError> {
+    check_range(dest, src.len(), MMapProt::PROT_WRITE)?;
+
+    // SAFETY: The destination range was validated above and `src` is a valid kernel
+    // buffer.
+    unsafe { copy_raw(dest.as_mut_ptr(), src.as_ptr(), src.len()) }
+}
+
+/// Reads a value of type `T` from the userland address `src`.
+///
+/// ## Safety
+/// Any bit pattern must be a valid value of type `T`.
+pub unsafe fn read_user<T: Copy>(src: VirtAddr) -> Result<T, SyscallError> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes =
+        core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>());
+
+    copy_from_user(bytes, src)?;
+    Ok(value.assume_init())
+}
+
+/// Writes the `value` to the userland address `dest`.
+pub fn write_user<T: Copy>(dest: VirtAddr, value: &T) -> Result<(), SyscallError> {
+    // SAFETY: The value is valid for `size_of::<T>()` bytes.
+    let bytes = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+
+    copy_to_user(dest, bytes)
+}
+
+/// A kernel copy of `len` values of type `T` at a userland address. The `#[syscall]`
+/// macro passes references to these copies to the syscall handlers instead of
+/// references to userland memory, and copies the mutable ones back once the handler
+/// returns.
+pub struct UserBuffer<T> {
+    address: VirtAddr,
+    data: Vec<T>,
+}
+
+impl<T> UserBuffer<T> {
+    /// Copies `len` values from the userland address `address`, which must be mapped
+    /// with the `protection`.
+    ///
+    /// ## Safety
+    /// Any bit pattern must be a valid value of type `T`.
+    pub unsafe fn copy_in(
+        address: usize,
+        len: usize,
+        protection: MMapProt,
+    ) -> Result<Self, SyscallError> {
+        let address = VirtAddr::new(address as u64);
+        let size = core::mem::size_of::<T>()
+            .checked_mul(len)
+            .ok_or(SyscallError::EFAULT)?;
+
+        let mut data = Vec::<T>::new();
+        data.try_reserve_exact(len)
+            .map_err(|_| SyscallError::ENOMEM)?;
+
+        // An empty slice may be passed as a null pointer.
+        if size != 0 {
+            check_range(address, size, protection)?;
+
+            let bytes = core::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, size);
+            copy_from_user(bytes, address)?;
+        }
+
+        data.set_len(len);
+        Ok(Self { address, data })
+    }
+
+    /// Copies the values back to the userland address they were copied from.
+    pub fn copy_out(&self) -> Result<(), SyscallError> {
+        let size = core::mem::size_of::<T>() * self.data.len();
+
+        if size == 0 {
+            return Ok(());
+        }
+
+        // SAFETY: The buffer is valid for `size` bytes.
+        let bytes = unsafe { core::slice::from_raw_parts(self.data.as_ptr() as *const u8, size) };
+        copy_to_user(self.address, bytes)
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
@@ -21,53 +21,105 @@
 //! the kernel functionality at runtime. When a kernel module is no longer needed,
 //! it can be unloaded. Most of the device drivers are used in the form of kernel modules.
 //!
+//! The modules are initialized by initcalls, which run in the order of their level
+//! (see [`InitLevel`]) and in link order within a level:
+//!
+//! * [`early_initcall`]: the kernel services that the drivers rely on.
+//! * [`core_initcall`]: the drivers register themselves (see [`crate::drivers::device`]),
+//!   after which the buses are probed and the root filesystem is mounted.
+//! * [`device_initcall`]: the devices that are not discovered on a bus.
+//! * [`late_initcall`]: the services that rely on the devices.
+//!
 //! ## Example
 //!
 //! ```rust,no_run
 //! fn hello_init() {}
-//! fn hello_exit() {}
 //!
-//! aero_kernel::module_init!(hello_init);
-//! aero_kernel::module_exit!(hello_exit);
+//! aero_kernel::device_initcall!(hello_init);
 //! ```
 
-use crate::{drivers, fs};
+use crate::fs;
 
-/// Inner helper function to make sure the function provided to the [module_init] macro
-/// has a valid function signature. This function returns the passed module init function as
-/// a const void pointer.
-
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord)]
 #[repr(C)]
-pub enum ModuleType {
-    Block = 0,
-    Other = 1,
+pub enum InitLevel {
+    Early = 0,
+    Core = 1,
+    Device = 2,
+    Late = 3,
+}
+
+impl InitLevel {
+    const ALL: [InitLevel; 4] = [
+        InitLevel::Early,
+        InitLevel::Core,
+        InitLevel::Device,
+        InitLevel::Late,
+    ];
 }
 
 #[derive(Debug)]
 #[repr(C)]
 pub struct Module {
     pub init: *const fn() -> (),
-    pub ty: ModuleType,
+    pub level: InitLevel,
 }
 
 unsafe impl Sync for Module {}
 
 #[macro_export]
-macro_rules! module_init {
-    ($init_function:expr, $ty:path) => {
-        use crate::modules::ModuleType;
-
-        #[used]
-        #[link_section = ".kernel_modules.init"]
-        static __MODULE_INIT: $crate::modules::Module = $crate::modules::Module {
-            init: $init_function as *const fn() -> (),
-            ty: $ty,
+macro_rules! initcall {
+    ($init_function:expr, $level:ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".kernel_modules.init"]
+            static __MODULE_INIT: $crate::modules::Module = $crate::modules::Module {
+                init: $init_function as *const fn() -> (),
+                level: $crate::modules::InitLevel::$level,
+            };
         };
     };
 }
 
-/// This function is responsible for initializing all of the kernel modules. Since currently
+#[macro_export]
+macro_rules! early_initcall {
+    ($init_function:expr) => {
+        $crate::initcall!($init_function, Early);
+    };
+}
+
+#[macro_export]
+macro_rules! core_initcall {
+    ($init_function:expr) => {
+        $crate::initcall!($init_function, Core);
+    };
+}
+
+#[macro_export]
+macro_rules! device_initcall {
+    ($init_function:expr) => {
+        $crate::initcall!($init_function, Device);
+    };
+}
+
+#[macro_export]
+macro_rules! late_initcall {
+    ($init_function:expr) => {
+        $crate::initcall!($init_function, Late);
+    };
+}
+
+/// Probes the buses for the devices of the drivers registered so far and mounts the root
+/// filesystem, which the devices are installed in.
+fn probe_devices() {
+    #[cfg(target_arch = "x86_64")]
+    crate::drivers::pci::init();
+    log::info!("loaded PCI driver");
+
+    fs::block::launch().unwrap();
+}
+
+/// This function is responsible for running all of the initcalls. Since currently
 /// we cannot read the ext2 root filesystem, we link all of the kernel modules into the kernel
 /// itself (this is temporary and modules will be loaded from the filesystem in the future).
 pub(crate) fn init() {
@@ -76,35 +128,26 @@ pub(crate) fn init() {
         static mut __kernel_modules_end: u8;
     }
 
-    unsafe {
+    let modules = unsafe {
         let size = &__kernel_modules_end as *const u8 as usize
             - &__kernel_modules_start as *const u8 as usize;
 
-        let modules = core::slice::from_raw_parts_mut(
-            &mut __kernel_modules_start as *mut u8 as *mut Module,
+        core::slice::from_raw_parts(
+            &__kernel_modules_start as *const u8 as *const Module,
             size / core::mem::size_of::<Module>(),
-        );
-
-        modules.sort_by(|e, a| e.ty.cmp(&a.ty));
-
-        let mut launched_fs = false;
-
-        for module in modules {
-            log::debug!("{module:?} {launched_fs}");
-
-            if module.ty != ModuleType::Block && !launched_fs {
-                let mut address_space = crate::mem::AddressSpace::this();
-                let mut offset_table = address_space.offset_page_table();
+        )
+    };
 
-                #[cfg(target_arch = "x86_64")]
-                drivers::pci::init(&mut offset_table);
-                log::info!("loaded PCI driver");
+    for level in InitLevel::ALL {
+        if level == InitLevel::Device {
+            probe_devices();
+        }
 
-                fs::block::launch().unwrap();
-                launched_fs = true;
-            }
+        for module in modules.iter().filter(|module| module.level == level) {
+            log::debug!("{module:?}");
 
-            let init = core::mem::transmute::<*const fn() -> (), fn() -> ()>(module.init);
+            let init =
+                unsafe { core::mem::transmute::<*const fn() -> (), fn() -> ()>(module.init) };
             init();
         }
     }
@@ -0,0 +1,349 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! ARP (Address Resolution Protocol) for IPv4 over ethernet.
+//!
+//! The resolved addresses are kept in the neighbor cache for [`REACHABLE_TIME`]. The
+//! packets sent to a neighbor that is not resolved yet are queued (up to
+//! [`MAX_PENDING`] of them) and sent once the reply arrives. The request is retried
+//! every second, and the neighbor and its queued packets are dropped after
+//! [`MAX_RETRIES`] unanswered requests.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc826>
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::timer::{self, Timer};
+use crate::utils::sync::Mutex;
+
+use super::ethernet::{EtherType, EthernetHeader};
+use super::{Interface, Ipv4Addr, MacAddr, NetError};
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+const PACKET_SIZE: usize = 28;
+
+/// How long a resolved neighbor stays in the cache, in nanoseconds.
+const REACHABLE_TIME: u64 = 60_000_000_000;
+/// The interval between the aging passes (and the request retries), in nanoseconds.
+const AGING_INTERVAL: u64 = 1_000_000_000;
+const MAX_RETRIES: usize = 3;
+/// The maximum amount of packets queued for a neighbor that is not resolved yet.
+const MAX_PENDING: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ArpPacket {
+    op: u16,
+    sender_mac: MacAddr,
+    sender_ip: Ipv4Addr,
+    target_mac: MacAddr,
+    target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_SIZE {
+            return None;
+        }
+
+        let htype = u16::from_be_bytes([data[0], data[1]]);
+        let ptype = u16::from_be_bytes([data[2], data[3]]);
+
+        // Only IPv4 over ethernet is supported.
+        if htype != HTYPE_ETHERNET
+            || EtherType::from(ptype) != EtherType::Ipv4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+
+        let mac = |offset: usize| {
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&data[offset..offset + 6]);
+            MacAddr(mac)
+        };
+
+        let ip = |offset: usize| {
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&data[offset..offset + 4]);
+            Ipv4Addr(ip)
+        };
+
+        Some(Self {
+            op: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PACKET_SIZE);
+
+        data.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        data.extend_from_slice(&u16::from(EtherType::Ipv4).to_be_bytes());
+        data.extend_from_slice(&[6, 4]);
+        data.extend_from_slice(&self.op.to_be_bytes());
+        data.extend_from_slice(&self.sender_mac.0);
+        data.extend_from_slice(&self.sender_ip.0);
+        data.extend_from_slice(&self.target_mac.0);
+        data.extend_from_slice(&self.target_ip.0);
+
+        data
+    }
+}
+
+/// A packet waiting for the address resolution of its destination.
+struct PendingPacket {
+    ether_type: EtherType,
+    payload: Vec<u8>,
+}
+
+enum Neighbor {
+    /// A request was sent and no reply was received yet.
+    Incomplete {
+        interface: Arc<Interface>,
+        retries: usize,
+        pending: Vec<PendingPacket>,
+    },
+    Reachable {
+        mac: MacAddr,
+        /// The monotonic time at which the entry expires, in nanoseconds.
+        expires: u64,
+    },
+}
+
+/// The neighbor cache, keyed by the interface ID and the IPv4 address.
+static NEIGHBORS: Mutex<BTreeMap<(usize, Ipv4Addr), Neighbor>> = Mutex::new(BTreeMap::new());
+
+fn send_request(interface: &Interface, target_ip: Ipv4Addr) -> Result<(), NetError> {
+    let sender_ip = interface
+        .addr()
+        .map_or(Ipv4Addr::UNSPECIFIED, |addr| addr.address);
+
+    let packet = ArpPacket {
+        op: OP_REQUEST,
+        sender_mac: interface.mac_address(),
+        sender_ip,
+        target_mac: MacAddr::default(),
+        target_ip,
+    };
+
+    interface.send(MacAddr::BROADCAST, EtherType::Arp, &packet.encode())
+}
+
+/// Returns the MAC address of the neighbor, if it is resolved.
+pub fn lookup(interface: &Interface, ip: Ipv4Addr) -> Option<MacAddr> {
+    match NEIGHBORS.lock_irq().get(&(interface.id(), ip)) {
+        Some(Neighbor::Reachable { mac, expires }) if *expires > timer::now() => Some(*mac),
+        _ => None,
+    }
+}
+
+/// Sends the payload to the neighbor with the provided IPv4 address. If the address is
+/// not resolved yet, the payload is queued and an ARP request is sent.
+pub fn send_to(
+    interface: &Arc<Interface>,
+    ip: Ipv4Addr,
+    ether_type: EtherType,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    if ip == Ipv4Addr::BROADCAST {
+        return interface.send(MacAddr::BROADCAST, ether_type, payload);
+    }
+
+    // The frames sent on the loopback interface come back to it.
+    if interface.is_loopback() {
+        return interface.send(interface.mac_address(), ether_type, payload);
+    }
+
+    if let Some(mac) = lookup(interface, ip) {
+        return interface.send(mac, ether_type, payload);
+    }
+
+    let packet = PendingPacket {
+        ether_type,
+        payload: payload.to_vec(),
+    };
+
+    let needs_request = {
+        let mut neighbors = NEIGHBORS.lock_irq();
+        let key = (interface.id(), ip);
+
+        match neighbors.get_mut(&key) {
+            Some(Neighbor::Incomplete { pending, .. }) => {
+                if pending.len() >= MAX_PENDING {
+                    return Err(NetError::QueueFull);
+                }
+
+                pending.push(packet);
+                false
+            }
+
+            // The entry is stale, resolve it again.
+            _ => {
+                neighbors.insert(
+                    key,
+                    Neighbor::Incomplete {
+                        interface: interface.clone(),
+                        retries: 0,
+                        pending: alloc::vec![packet],
+                    },
+                );
+
+                true
+            }
+        }
+    };
+
+    if needs_request {
+        send_request(interface, ip)?;
+    }
+
+    Ok(())
+}
+
+/// Records the MAC address of the neighbor and sends the packets that were waiting for
+/// it. If `create` is not set, only an existing entry is updated.
+fn update_neighbor(interface: &Interface, ip: Ipv4Addr, mac: MacAddr, create: bool) {
+    let key = (interface.id(), ip);
+    let entry = Neighbor::Reachable {
+        mac,
+        expires: timer::now() + REACHABLE_TIME,
+    };
+
+    let previous = {
+        let mut neighbors = NEIGHBORS.lock_irq();
+
+        if !create && !neighbors.contains_key(&key) {
+            return;
+        }
+
+        neighbors.insert(key, entry)
+    };
+
+    if let Some(Neighbor::Incomplete { pending, .. }) = previous {
+        for packet in pending {
+            // The packets are best effort, a failure is reported by the upper layers
+            // (for example, as a retransmission).
+            let _ = interface.send(mac, packet.ether_type, &packet.payload);
+        }
+    }
+}
+
+fn handle_packet(interface: &Arc<Interface>, _header: &EthernetHeader, data: &[u8]) {
+    let packet = match ArpPacket::decode(data) {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    let our_ip = interface.addr().map(|addr| addr.address);
+    let for_us = our_ip == Some(packet.target_ip);
+
+    // As in RFC 826, the sender is added to the cache only if the packet is for us, but
+    // an existing entry is always refreshed.
+    if !packet.sender_ip.is_unspecified() {
+        update_neighbor(interface, packet.sender_ip, packet.sender_mac, for_us);
+    }
+
+    if for_us && packet.op == OP_REQUEST {
+        let reply = ArpPacket {
+            op: OP_REPLY,
+            sender_mac: interface.mac_address(),
+            sender_ip: packet.target_ip,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+
+        let _ = interface.send(packet.sender_mac, EtherType::Arp, &reply.encode());
+    }
+}
+
+/// Expires the stale neighbors and retries the pending requests. Runs from the timer
+/// tick and re-arms itself.
+fn age_neighbors() {
+    let now = timer::now();
+    let mut retry = Vec::new();
+
+    NEIGHBORS
+        .lock_irq()
+        .retain(|(_, ip), neighbor| match neighbor {
+            Neighbor::Reachable { expires, .. } => *expires > now,
+
+            Neighbor::Incomplete {
+                interface, retries, ..
+            } => {
+                *retries += 1;
+
+                if *retries > MAX_RETRIES {
+                    log::debug!("arp: failed to resolve {}", ip);
+                    return false;
+                }
+
+                retry.push((interface.clone(), *ip));
+                true
+            }
+        });
+
+    for (interface, ip) in retry {
+        let _ = send_request(&interface, ip);
+    }
+
+    arm_aging_timer();
+}
+
+fn arm_aging_timer() {
+    let timer = Timer::new(timer::now() + AGING_INTERVAL, Box::new(age_neighbors));
+
+    // The timer re-arms itself, so it is never cancelled.
+    core::mem::forget(timer);
+}
+
+pub(super) fn init() {
+    super::ethernet::register_protocol(EtherType::Arp, handle_packet);
+    arm_aging_timer();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_packet_encode_decode() {
+        let packet = ArpPacket {
+            op: OP_REQUEST,
+            sender_mac: MacAddr([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
+            sender_ip: Ipv4Addr([10, 0, 2, 15]),
+            target_mac: MacAddr::default(),
+            target_ip: Ipv4Addr([10, 0, 2, 2]),
+        };
+
+        let data = packet.encode();
+
+        assert_eq!(data.len(), PACKET_SIZE);
+        assert_eq!(ArpPacket::decode(&data), Some(packet));
+    }
+}
@@ -0,0 +1,493 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! DHCP (Dynamic Host Configuration Protocol) client. A kernel thread configures the
+//! address, the default gateway and the DNS servers of the interfaces at boot and
+//! renews their leases.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc2131>
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::socket::udp::UdpSocket;
+use crate::timer;
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+use crate::utils::sync::Mutex;
+
+use super::{interfaces, ipv4, nettest, udp, Interface, InterfaceAddr, Ipv4Addr, MacAddr};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+
+const HTYPE_ETHERNET: u8 = 1;
+/// Asks the server to broadcast its replies, as the interface cannot receive unicast
+/// datagrams before it is configured.
+const FLAG_BROADCAST: u16 = 1 << 15;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// The offset of the magic cookie, after the fixed fields.
+const OPTIONS_OFFSET: usize = 236;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDR: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+/// The time waited for a reply, in nanoseconds.
+const REPLY_TIMEOUT: u64 = 4_000_000_000;
+/// The amount of discoveries sent before the interface is skipped until the next
+/// attempt.
+const MAX_ATTEMPTS: usize = 4;
+/// The time waited before retrying the interfaces that could not be configured, in
+/// nanoseconds.
+const RETRY_INTERVAL: u64 = 60_000_000_000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Message {
+    op: u8,
+    xid: u32,
+    flags: u16,
+    ciaddr: Ipv4Addr,
+    yiaddr: Ipv4Addr,
+    chaddr: MacAddr,
+    message_type: u8,
+    options: BTreeMap<u8, Vec<u8>>,
+}
+
+impl Message {
+    fn request(message_type: u8, xid: u32, chaddr: MacAddr) -> Self {
+        Self {
+            op: OP_REQUEST,
+            xid,
+            flags: FLAG_BROADCAST,
+            ciaddr: Ipv4Addr::UNSPECIFIED,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            chaddr,
+            message_type,
+            options: BTreeMap::new(),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < OPTIONS_OFFSET + MAGIC_COOKIE.len()
+            || data[OPTIONS_OFFSET..OPTIONS_OFFSET + 4] != MAGIC_COOKIE
+            || data[1] != HTYPE_ETHERNET
+            || data[2] != 6
+        {
+            return None;
+        }
+
+        let address = |offset: usize| Ipv4Addr(data[offset..offset + 4].try_into().unwrap());
+
+        let mut chaddr = [0; 6];
+        chaddr.copy_from_slice(&data[28..34]);
+
+        let mut options = BTreeMap::new();
+        let mut rest = &data[OPTIONS_OFFSET + 4..];
+
+        while let [code, tail @ ..] = rest {
+            match *code {
+                OPTION_END => break,
+                OPTION_PAD => rest = tail,
+
+                _ => {
+                    let len = *tail.first()? as usize;
+                    let value = tail.get(1..1 + len)?;
+
+                    options.insert(*code, value.to_vec());
+                    rest = &tail[1 + len..];
+                }
+            }
+        }
+
+        let message_type = *options.remove(&OPTION_MESSAGE_TYPE)?.first()?;
+
+        Some(Self {
+            op: data[0],
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: address(12),
+            yiaddr: address(16),
+            chaddr: MacAddr(chaddr),
+            message_type,
+            options,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(300);
+
+        data.extend_from_slice(&[self.op, HTYPE_ETHERNET, 6, 0]);
+        data.extend_from_slice(&self.xid.to_be_bytes());
+        data.extend_from_slice(&[0, 0]); // secs
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        data.extend_from_slice(&self.ciaddr.0);
+        data.extend_from_slice(&self.yiaddr.0);
+        data.extend_from_slice(&[0; 8]); // siaddr and giaddr
+        data.extend_from_slice(&self.chaddr.0);
+        data.resize(OPTIONS_OFFSET, 0); // chaddr padding, sname and file
+
+        data.extend_from_slice(&MAGIC_COOKIE);
+        data.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, self.message_type]);
+
+        for (code, value) in self.options.iter() {
+            data.push(*code);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        }
+
+        data.push(OPTION_END);
+        data
+    }
+
+    fn option_addr(&self, code: u8) -> Option<Ipv4Addr> {
+        let value = self.options.get(&code)?;
+        Some(Ipv4Addr(value.get(0..4)?.try_into().ok()?))
+    }
+
+    fn option_u32(&self, code: u8) -> Option<u32> {
+        let value = self.options.get(&code)?;
+        Some(u32::from_be_bytes(value.get(0..4)?.try_into().ok()?))
+    }
+}
+
+/// An address leased from a DHCP server.
+struct Lease {
+    addr: InterfaceAddr,
+    server: Ipv4Addr,
+    gateway: Option<Ipv4Addr>,
+    dns_servers: Vec<Ipv4Addr>,
+    /// The time at which the lease is to be renewed and at which it expires, in
+    /// nanoseconds since boot.
+    renew_at: u64,
+    expires_at: u64,
+}
+
+impl Lease {
+    fn from_ack(ack: &Message) -> Option<Self> {
+        let server = ack.option_addr(OPTION_SERVER_ID)?;
+        let prefix_len = ack
+            .option_addr(OPTION_SUBNET_MASK)
+            .map_or(24, |mask| mask.as_u32().count_ones() as u8);
+
+        let dns_servers = ack
+            .options
+            .get(&OPTION_DNS)
+            .map(|value| {
+                value
+                    .chunks_exact(4)
+                    .map(|addr| Ipv4Addr([addr[0], addr[1], addr[2], addr[3]]))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Without a lease time, the lease is assumed to last a day.
+        let lease_time = ack.option_u32(OPTION_LEASE_TIME).unwrap_or(86400) as u64;
+        let renewal_time = ack
+            .option_u32(OPTION_RENEWAL_TIME)
+            .map_or(lease_time / 2, |time| time as u64);
+
+        let now = timer::now();
+
+        Some(Self {
+            addr: InterfaceAddr {
+                address: ack.yiaddr,
+                prefix_len,
+            },
+            server,
+            gateway: ack.option_addr(OPTION_ROUTER),
+            dns_servers,
+            renew_at: now + renewal_time * NANOS_PER_SEC,
+            expires_at: now + lease_time * NANOS_PER_SEC,
+        })
+    }
+}
+
+static DNS_SERVERS: Mutex<Vec<Ipv4Addr>> = Mutex::new(Vec::new());
+
+/// Returns the DNS servers provided by the DHCP servers.
+pub fn dns_servers() -> Vec<Ipv4Addr> {
+    DNS_SERVERS.lock_irq().clone()
+}
+
+struct Client {
+    socket: Arc<UdpSocket>,
+    interface: Arc<Interface>,
+    lease: Option<Lease>,
+}
+
+impl Client {
+    fn new_xid(&self) -> u32 {
+        let mac = self.interface.mac_address().0;
+        (timer::now() as u32) ^ u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]])
+    }
+
+    fn send(&self, message: &Message) {
+        let _ = udp::send_on(
+            &self.interface,
+            message.ciaddr,
+            CLIENT_PORT,
+            Ipv4Addr::BROADCAST,
+            SERVER_PORT,
+            &message.encode(),
+        );
+    }
+
+    /// Waits for a reply to the transaction.
+    fn receive(&self, xid: u32) -> Option<Message> {
+        let deadline = timer::now() + REPLY_TIMEOUT;
+
+        loop {
+            let datagram = self.socket.recv_timeout(deadline).ok()??;
+
+            if !Arc::ptr_eq(&datagram.interface, &self.interface) {
+                continue;
+            }
+
+            match Message::decode(&datagram.data) {
+                Some(reply)
+                    if reply.op == OP_REPLY
+                        && reply.xid == xid
+                        && reply.chaddr == self.interface.mac_address() =>
+                {
+                    return Some(reply)
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends the request and waits for the ACK. Returns [`None`] if the request timed
+    /// out or was refused.
+    fn request(&self, request: &Message) -> Option<Lease> {
+        self.send(request);
+
+        let reply = self.receive(request.xid)?;
+
+        match reply.message_type {
+            DHCPACK => Lease::from_ack(&reply),
+            DHCPNAK => {
+                log::warn!("dhcp: {}: request refused", self.interface.name());
+                None
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Discovers the servers and requests an address from the first one that offers
+    /// one.
+    fn acquire(&self) -> Option<Lease> {
+        let mac = self.interface.mac_address();
+
+        for _ in 0..MAX_ATTEMPTS {
+            let xid = self.new_xid();
+            let mut discover = Message::request(DHCPDISCOVER, xid, mac);
+
+            discover.options.insert(
+                OPTION_PARAMETERS,
+                alloc::vec![
+                    OPTION_SUBNET_MASK,
+                    OPTION_ROUTER,
+                    OPTION_DNS,
+                    OPTION_LEASE_TIME
+                ],
+            );
+
+            self.send(&discover);
+
+            let offer = match self.receive(xid) {
+                Some(offer) if offer.message_type == DHCPOFFER => offer,
+                _ => continue,
+            };
+
+            let server = match offer.option_addr(OPTION_SERVER_ID) {
+                Some(server) => server,
+                None => continue,
+            };
+
+            let mut request = Message::request(DHCPREQUEST, xid, mac);
+
+            request.options = discover.options.clone();
+            request
+                .options
+                .insert(OPTION_REQUESTED_ADDR, offer.yiaddr.0.to_vec());
+            request.options.insert(OPTION_SERVER_ID, server.0.to_vec());
+
+            if let Some(lease) = self.request(&request) {
+                return Some(lease);
+            }
+        }
+
+        None
+    }
+
+    /// Extends the current lease.
+    fn renew(&self, lease: &Lease) -> Option<Lease> {
+        let mut request =
+            Message::request(DHCPREQUEST, self.new_xid(), self.interface.mac_address());
+        request.ciaddr = lease.addr.address;
+
+        self.request(&request)
+    }
+
+    fn configure(&mut self, lease: Lease) {
+        let interface = &self.interface;
+
+        log::info!(
+            "dhcp: {}: leased {}/{} from {}",
+            interface.name(),
+            lease.addr.address,
+            lease.addr.prefix_len,
+            lease.server
+        );
+
+        interface.set_addr(Some(lease.addr));
+
+        if let Some(gateway) = lease.gateway {
+            ipv4::set_default_gateway(interface.clone(), gateway);
+        }
+
+        if !lease.dns_servers.is_empty() {
+            *DNS_SERVERS.lock_irq() = lease.dns_servers.clone();
+        }
+
+        self.lease = Some(lease);
+    }
+
+    fn deconfigure(&mut self) {
+        log::warn!("dhcp: {}: lease expired", self.interface.name());
+
+        self.interface.set_addr(None);
+        self.lease = None;
+    }
+
+    /// Acquires or renews the lease of the interface, if needed. Returns the time at
+    /// which the client has to run again.
+    fn run(&mut self) -> u64 {
+        let now = timer::now();
+
+        let lease = match self.lease.as_ref() {
+            Some(lease) if now < lease.renew_at => return lease.renew_at,
+            Some(lease) => self.renew(lease),
+            None => self.acquire(),
+        };
+
+        match lease {
+            Some(lease) => self.configure(lease),
+            None => match self.lease.as_ref().map(|lease| lease.expires_at) {
+                Some(expires_at) if now >= expires_at => self.deconfigure(),
+                // Retry halfway to the expiration of the lease.
+                Some(expires_at) => {
+                    self.lease.as_mut().unwrap().renew_at = now + (expires_at - now) / 2;
+                }
+
+                None => {}
+            },
+        }
+
+        self.lease
+            .as_ref()
+            .map_or(timer::now() + RETRY_INTERVAL, |lease| lease.renew_at)
+    }
+}
+
+fn dhcp_thread() {
+    let socket = UdpSocket::new();
+
+    socket
+        .bind_port(CLIENT_PORT)
+        .expect("dhcp: failed to bind the client port");
+
+    let mut clients = interfaces()
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .map(|interface| {
+            interface.set_up(true);
+
+            Client {
+                socket: socket.clone(),
+                interface,
+                lease: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    loop {
+        let wake_at = clients.iter_mut().map(|client| client.run()).min().unwrap();
+
+        let _ = timer::sleep_until(wake_at);
+    }
+}
+
+/// Spawns the DHCP client thread, if there are network interfaces other than the
+/// loopback one. Called once the NIC drivers are loaded. The interfaces are configured
+/// statically when the network probes run (see [`nettest`]).
+pub fn init() {
+    if interfaces().iter().all(|interface| interface.is_loopback()) || nettest::is_enabled() {
+        return;
+    }
+
+    scheduler::get_scheduler().register_task(Task::new_kernel(dhcp_thread, true));
+}
+
+crate::late_initcall!(init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dhcp_message_encode_decode() {
+        let mut message = Message::request(
+            DHCPDISCOVER,
+            0xdeadbeef,
+            MacAddr([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+        );
+        message
+            .options
+            .insert(OPTION_REQUESTED_ADDR, alloc::vec![10, 0, 2, 15]);
+
+        let data = message.encode();
+
+        assert_eq!(Message::decode(&data), Some(message));
+    }
+}
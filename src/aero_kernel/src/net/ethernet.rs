@@ -0,0 +1,163 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Ethernet II frames. The protocol layers register a handler for their EtherType with
+//! [`register_protocol`], which is called with the payload of the received frames.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::utils::sync::Mutex;
+
+use super::{Interface, MacAddr};
+
+/// The size of the ethernet header (destination, source and EtherType).
+pub const HEADER_SIZE: usize = 14;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EtherType {
+    Ipv4,
+    Arp,
+    Ipv6,
+    Other(u16),
+}
+
+impl From<u16> for EtherType {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0800 => Self::Ipv4,
+            0x0806 => Self::Arp,
+            0x86dd => Self::Ipv6,
+            value => Self::Other(value),
+        }
+    }
+}
+
+impl From<EtherType> for u16 {
+    fn from(value: EtherType) -> Self {
+        match value {
+            EtherType::Ipv4 => 0x0800,
+            EtherType::Arp => 0x0806,
+            EtherType::Ipv6 => 0x86dd,
+            EtherType::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub dest: MacAddr,
+    pub src: MacAddr,
+    pub ether_type: EtherType,
+}
+
+/// Handles the payload of a received frame.
+pub type ProtocolHandler = fn(&Arc<Interface>, &EthernetHeader, &[u8]);
+
+static PROTOCOLS: Mutex<BTreeMap<EtherType, ProtocolHandler>> = Mutex::new(BTreeMap::new());
+
+/// Registers the handler of the frames with the provided EtherType, replacing the
+/// previous one.
+pub fn register_protocol(ether_type: EtherType, handler: ProtocolHandler) {
+    PROTOCOLS.lock_irq().insert(ether_type, handler);
+}
+
+/// Splits the frame into its header and payload. Returns [`None`] if the frame is too
+/// short.
+pub fn decode(frame: &[u8]) -> Option<(EthernetHeader, &[u8])> {
+    if frame.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let mut dest = [0; 6];
+    let mut src = [0; 6];
+
+    dest.copy_from_slice(&frame[0..6]);
+    src.copy_from_slice(&frame[6..12]);
+
+    let header = EthernetHeader {
+        dest: MacAddr(dest),
+        src: MacAddr(src),
+        ether_type: u16::from_be_bytes([frame[12], frame[13]]).into(),
+    };
+
+    Some((header, &frame[HEADER_SIZE..]))
+}
+
+/// Builds a frame from the header and the payload.
+pub fn encode(header: &EthernetHeader, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+
+    frame.extend_from_slice(&header.dest.0);
+    frame.extend_from_slice(&header.src.0);
+    frame.extend_from_slice(&u16::from(header.ether_type).to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+/// Decodes the frame and passes it to the handler of its EtherType. Returns whether the
+/// frame was handled.
+pub(super) fn demux(interface: &Arc<Interface>, frame: &[u8]) -> bool {
+    let (header, payload) = match decode(frame) {
+        Some(decoded) => decoded,
+        None => return false,
+    };
+
+    // Drop the frames addressed to other hosts, in case the NIC is in promiscuous mode.
+    let mac = interface.mac_address();
+
+    if header.dest != mac && !header.dest.is_broadcast() && !header.dest.is_multicast() {
+        return false;
+    }
+
+    // Copy the handler out, as it may send a reply which takes the lock again.
+    let handler = PROTOCOLS.lock_irq().get(&header.ether_type).copied();
+
+    match handler {
+        Some(handler) => {
+            handler(interface, &header, payload);
+            true
+        }
+
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ethernet_encode_decode() {
+        let header = EthernetHeader {
+            dest: MacAddr::BROADCAST,
+            src: MacAddr([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
+            ether_type: EtherType::Arp,
+        };
+
+        let frame = encode(&header, &[1, 2, 3]);
+        let (decoded, payload) = decode(&frame).unwrap();
+
+        assert_eq!(decoded, header);
+        assert_eq!(payload, &[1, 2, 3]);
+        assert!(decode(&frame[..HEADER_SIZE - 1]).is_none());
+    }
+}
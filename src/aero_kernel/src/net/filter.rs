@@ -0,0 +1,387 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A stateless packet filter. The received IPv4 packets (once reassembled) are checked
+//! against the input chain before being delivered and the sent packets are checked
+//! against the output chain before being fragmented. The first rule of the chain that
+//! matches the packet decides its verdict, or the policy of the chain if none does.
+//!
+//! The chains are configured by writing commands to `/proc/firewall`, one per line:
+//!
+//! * `append <chain> <accept|drop> [proto <tcp|udp|icmp|N>] [src <addr>[/len]]
+//!   [dst <addr>[/len]] [sport <port>] [dport <port>]`: appends a rule to the chain.
+//! * `delete <chain> <index>`: removes the rule at the index of the chain.
+//! * `policy <chain> <accept|drop>`: sets the policy of the chain.
+//! * `flush [chain]`: removes the rules of the chain, or of both chains.
+//!
+//! Where `<chain>` is `input` or `output`.
+
+use alloc::vec::Vec;
+
+use crate::utils::sync::Mutex;
+
+use super::ipv4::IpProtocol;
+use super::Ipv4Addr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Chain {
+    Input,
+    Output,
+}
+
+impl Chain {
+    pub const ALL: [Chain; 2] = [Chain::Input, Chain::Output];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Chain::Input => "input",
+            Chain::Output => "output",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|chain| chain.name() == name)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+}
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Accept => "accept",
+            Verdict::Drop => "drop",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "accept" => Some(Verdict::Accept),
+            "drop" => Some(Verdict::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// A subnet matched by a rule.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Subnet {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Subnet {
+    fn contains(&self, address: Ipv4Addr) -> bool {
+        self.address.same_subnet(address, self.prefix_len)
+    }
+
+    /// Parses an address with an optional prefix length (`10.0.2.0/24`). The address
+    /// alone matches only itself.
+    pub(super) fn parse(value: &str) -> Option<Self> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, prefix_len.parse().ok()?),
+            None => (value, 32),
+        };
+
+        if prefix_len > 32 {
+            return None;
+        }
+
+        let mut octets = [0; 4];
+        let mut parts = address.split('.');
+
+        for octet in octets.iter_mut() {
+            *octet = parts.next()?.parse().ok()?;
+        }
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            address: Ipv4Addr(octets),
+            prefix_len,
+        })
+    }
+}
+
+impl core::fmt::Display for Subnet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// A filter rule. The unset fields match any packet, and the ports only match the TCP
+/// and UDP packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub verdict: Verdict,
+    pub protocol: Option<IpProtocol>,
+    pub src: Option<Subnet>,
+    pub dest: Option<Subnet>,
+    pub src_port: Option<u16>,
+    pub dest_port: Option<u16>,
+    /// The amount of packets that matched the rule.
+    pub matches: u64,
+}
+
+impl Rule {
+    fn new(verdict: Verdict) -> Self {
+        Self {
+            verdict,
+            protocol: None,
+            src: None,
+            dest: None,
+            src_port: None,
+            dest_port: None,
+            matches: 0,
+        }
+    }
+
+    fn matches(&self, packet: &Packet) -> bool {
+        let ports_match = match (self.src_port, self.dest_port) {
+            (None, None) => true,
+            _ => packet.ports.map_or(false, |(src_port, dest_port)| {
+                self.src_port.map_or(true, |port| port == src_port)
+                    && self.dest_port.map_or(true, |port| port == dest_port)
+            }),
+        };
+
+        ports_match
+            && self
+                .protocol
+                .map_or(true, |protocol| protocol == packet.protocol)
+            && self.src.map_or(true, |subnet| subnet.contains(packet.src))
+            && self
+                .dest
+                .map_or(true, |subnet| subnet.contains(packet.dest))
+    }
+
+    /// Parses the matches of an `append` command.
+    fn parse<'a>(verdict: Verdict, mut words: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut rule = Self::new(verdict);
+
+        while let Some(key) = words.next() {
+            let value = words.next()?;
+
+            match key {
+                "proto" => {
+                    rule.protocol = Some(match value {
+                        "icmp" => IpProtocol::Icmp,
+                        "tcp" => IpProtocol::Tcp,
+                        "udp" => IpProtocol::Udp,
+                        value => IpProtocol::from(value.parse::<u8>().ok()?),
+                    })
+                }
+
+                "src" => rule.src = Some(Subnet::parse(value)?),
+                "dst" => rule.dest = Some(Subnet::parse(value)?),
+                "sport" => rule.src_port = Some(value.parse().ok()?),
+                "dport" => rule.dest_port = Some(value.parse().ok()?),
+
+                _ => return None,
+            }
+        }
+
+        Some(rule)
+    }
+}
+
+/// The fields of a packet that are matched by the rules.
+struct Packet {
+    protocol: IpProtocol,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    /// The source and destination ports, for the TCP and UDP packets.
+    ports: Option<(u16, u16)>,
+}
+
+impl Packet {
+    fn new(protocol: IpProtocol, src: Ipv4Addr, dest: Ipv4Addr, payload: &[u8]) -> Self {
+        // The ports are the first two fields of both the TCP and UDP headers.
+        let ports = match protocol {
+            IpProtocol::Tcp | IpProtocol::Udp if payload.len() >= 4 => Some((
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+            )),
+
+            _ => None,
+        };
+
+        Self {
+            protocol,
+            src,
+            dest,
+            ports,
+        }
+    }
+}
+
+struct ChainTable {
+    rules: Vec<Rule>,
+    policy: Verdict,
+}
+
+impl ChainTable {
+    const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            policy: Verdict::Accept,
+        }
+    }
+
+    fn check(&mut self, packet: &Packet) -> Verdict {
+        match self.rules.iter_mut().find(|rule| rule.matches(packet)) {
+            Some(rule) => {
+                rule.matches += 1;
+                rule.verdict
+            }
+
+            None => self.policy,
+        }
+    }
+}
+
+struct Filter {
+    input: ChainTable,
+    output: ChainTable,
+}
+
+impl Filter {
+    fn chain(&mut self, chain: Chain) -> &mut ChainTable {
+        match chain {
+            Chain::Input => &mut self.input,
+            Chain::Output => &mut self.output,
+        }
+    }
+
+    /// Runs a configuration command (see the module-level documentation).
+    fn configure(&mut self, command: &str) -> Option<()> {
+        let mut words = command.split_whitespace();
+
+        match words.next()? {
+            "append" => {
+                let chain = Chain::from_name(words.next()?)?;
+                let verdict = Verdict::from_name(words.next()?)?;
+                let rule = Rule::parse(verdict, words)?;
+
+                self.chain(chain).rules.push(rule);
+            }
+
+            "delete" => {
+                let chain = self.chain(Chain::from_name(words.next()?)?);
+                let index = words.next()?.parse::<usize>().ok()?;
+
+                if index >= chain.rules.len() {
+                    return None;
+                }
+
+                chain.rules.remove(index);
+            }
+
+            "policy" => {
+                let chain = Chain::from_name(words.next()?)?;
+                self.chain(chain).policy = Verdict::from_name(words.next()?)?;
+            }
+
+            "flush" => match words.next() {
+                Some(chain) => self.chain(Chain::from_name(chain)?).rules.clear(),
+                None => {
+                    self.input.rules.clear();
+                    self.output.rules.clear();
+                }
+            },
+
+            _ => return None,
+        }
+
+        Some(())
+    }
+}
+
+static FILTER: Mutex<Filter> = Mutex::new(Filter {
+    input: ChainTable::new(),
+    output: ChainTable::new(),
+});
+
+/// Checks the packet against the rules of the chain and returns its verdict.
+pub fn check(
+    chain: Chain,
+    protocol: IpProtocol,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    payload: &[u8],
+) -> Verdict {
+    let packet = Packet::new(protocol, src, dest, payload);
+    FILTER.lock_irq().chain(chain).check(&packet)
+}
+
+/// Runs the configuration commands, one per line. Returns [`None`] if a command is
+/// invalid, in which case the following commands are not run.
+pub fn configure(commands: &str) -> Option<()> {
+    let mut filter = FILTER.lock_irq();
+
+    commands
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .try_for_each(|command| filter.configure(command))
+}
+
+/// Returns the policy and the rules of the chain.
+pub fn rules(chain: Chain) -> (Verdict, Vec<Rule>) {
+    let mut filter = FILTER.lock_irq();
+    let chain = filter.chain(chain);
+
+    (chain.policy, chain.rules.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_first_matching_rule() {
+        let mut filter = Filter {
+            input: ChainTable::new(),
+            output: ChainTable::new(),
+        };
+
+        filter
+            .configure("append input accept proto tcp src 10.0.2.0/24 dport 22")
+            .unwrap();
+        filter.configure("append input drop proto tcp").unwrap();
+        assert!(filter.configure("append input drop dport").is_none());
+
+        let ssh = [0x12, 0x34, 0, 22];
+        let local = Ipv4Addr([10, 0, 2, 15]);
+
+        let allowed = Packet::new(IpProtocol::Tcp, Ipv4Addr([10, 0, 2, 2]), local, &ssh);
+        let denied = Packet::new(IpProtocol::Tcp, Ipv4Addr([192, 168, 1, 2]), local, &ssh);
+        let icmp = Packet::new(IpProtocol::Icmp, Ipv4Addr([192, 168, 1, 2]), local, &[]);
+
+        assert_eq!(filter.input.check(&allowed), Verdict::Accept);
+        assert_eq!(filter.input.check(&denied), Verdict::Drop);
+        assert_eq!(filter.input.check(&icmp), Verdict::Accept);
+        assert_eq!(filter.input.rules[1].matches, 1);
+    }
+}
@@ -0,0 +1,197 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! ICMP (Internet Control Message Protocol). The echo requests are answered and
+//! [`ping`] sends an echo request and waits for its reply.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc792>
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::timer::{self, Timer};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::ipv4::{self, IpProtocol, Ipv4Header};
+use super::{Interface, Ipv4Addr, NetError};
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+const HEADER_SIZE: usize = 8;
+
+/// An echo request or reply.
+#[derive(Debug, PartialEq, Eq)]
+struct Echo<'a> {
+    typ: u8,
+    id: u16,
+    seq: u16,
+    data: &'a [u8],
+}
+
+impl<'a> Echo<'a> {
+    fn decode(packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < HEADER_SIZE || ipv4::checksum(packet) != 0 {
+            return None;
+        }
+
+        Some(Self {
+            typ: packet[0],
+            id: u16::from_be_bytes([packet[4], packet[5]]),
+            seq: u16::from_be_bytes([packet[6], packet[7]]),
+            data: &packet[HEADER_SIZE..],
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + self.data.len());
+
+        packet.extend_from_slice(&[self.typ, 0, 0, 0]);
+        packet.extend_from_slice(&self.id.to_be_bytes());
+        packet.extend_from_slice(&self.seq.to_be_bytes());
+        packet.extend_from_slice(self.data);
+
+        let checksum = ipv4::checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        packet
+    }
+}
+
+/// The echo requests sent by [`ping`], keyed by their ID and sequence number. The value
+/// is the time at which the reply was received.
+static ECHO_REPLIES: Mutex<BTreeMap<(u16, u16), Option<u64>>> = Mutex::new(BTreeMap::new());
+static ECHO_WQ: WaitQueue = WaitQueue::new();
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Sends an echo request to `dest` and waits for the reply for up to `timeout`
+/// nanoseconds. Returns the round-trip time, in nanoseconds.
+pub fn ping(dest: Ipv4Addr, seq: u16, data: &[u8], timeout: u64) -> Result<u64, NetError> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let key = (id, seq);
+
+    let request = Echo {
+        typ: TYPE_ECHO_REQUEST,
+        id,
+        seq,
+        data,
+    };
+
+    ECHO_REPLIES.lock_irq().insert(key, None);
+
+    let start = timer::now();
+    let deadline = start + timeout;
+
+    if let Err(err) = ipv4::send(dest, IpProtocol::Icmp, &request.encode()) {
+        ECHO_REPLIES.lock_irq().remove(&key);
+        return Err(err);
+    }
+
+    let _timer = Timer::wake_current(deadline);
+    let result = ECHO_WQ.wait_until(&ECHO_REPLIES, |replies| {
+        replies.get(&key).map_or(true, Option::is_some) || timer::now() >= deadline
+    });
+
+    let received = match result {
+        Ok(mut replies) => replies.remove(&key).flatten(),
+        Err(_) => {
+            ECHO_REPLIES.lock_irq().remove(&key);
+            return Err(NetError::Interrupted);
+        }
+    };
+
+    received.map(|time| time - start).ok_or(NetError::TimedOut)
+}
+
+fn handle_packet(interface: &Arc<Interface>, header: &Ipv4Header, packet: &[u8]) {
+    let echo = match Echo::decode(packet) {
+        Some(echo) => echo,
+        None => return,
+    };
+
+    match echo.typ {
+        TYPE_ECHO_REQUEST => {
+            let reply = Echo {
+                typ: TYPE_ECHO_REPLY,
+                ..echo
+            };
+
+            // The request may have been broadcasted, so reply from the address of the
+            // interface.
+            let src = match interface.addr() {
+                Some(addr) => addr.address,
+                None => return,
+            };
+
+            let next_hop = match ipv4::route(header.src) {
+                Some((_, next_hop)) => next_hop,
+                None => header.src,
+            };
+
+            let _ = ipv4::send_on(
+                interface,
+                next_hop,
+                src,
+                header.src,
+                IpProtocol::Icmp,
+                &reply.encode(),
+            );
+        }
+
+        TYPE_ECHO_REPLY => {
+            let mut replies = ECHO_REPLIES.lock_irq();
+
+            if let Some(received @ None) = replies.get_mut(&(echo.id, echo.seq)) {
+                *received = Some(timer::now());
+
+                core::mem::drop(replies);
+                ECHO_WQ.wake_all();
+            }
+        }
+
+        _ => {}
+    }
+}
+
+pub(super) fn init() {
+    ipv4::register_protocol(IpProtocol::Icmp, handle_packet);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icmp_echo_encode_decode() {
+        let echo = Echo {
+            typ: TYPE_ECHO_REQUEST,
+            id: 1,
+            seq: 2,
+            data: &[0xaa; 5],
+        };
+
+        let packet = echo.encode();
+
+        assert_eq!(Echo::decode(&packet), Some(echo));
+    }
+}
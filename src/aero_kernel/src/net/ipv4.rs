@@ -0,0 +1,558 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! IPv4. The received packets are validated, reassembled if fragmented and handed to the
+//! protocol registered for their protocol number (see [`register_protocol`]).
+//!
+//! The outgoing packets are routed with the routing table: the route with the longest
+//! prefix that matches the destination is used, where the subnets of the interfaces are
+//! implicit routes without a gateway. The packets bigger than the MTU of the interface
+//! are fragmented.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc791>
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::timer;
+use crate::utils::sync::Mutex;
+
+use super::arp;
+use super::ethernet::{EtherType, EthernetHeader};
+use super::filter::{self, Chain, Verdict};
+use super::{Interface, Ipv4Addr, NetError};
+
+/// The size of a header without options.
+pub const HEADER_SIZE: usize = 20;
+
+const DEFAULT_TTL: u8 = 64;
+
+const FLAG_MORE_FRAGMENTS: u16 = 1 << 13;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+/// How long the fragments of a packet are kept waiting for the rest, in nanoseconds.
+const REASSEMBLY_TIMEOUT: u64 = 30_000_000_000;
+/// The maximum amount of packets being reassembled at once.
+const MAX_REASSEMBLIES: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IpProtocol {
+    Icmp,
+    Tcp,
+    Udp,
+    Other(u8),
+}
+
+impl From<u8> for IpProtocol {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Icmp,
+            6 => Self::Tcp,
+            17 => Self::Udp,
+            value => Self::Other(value),
+        }
+    }
+}
+
+impl From<IpProtocol> for u8 {
+    fn from(value: IpProtocol) -> Self {
+        match value {
+            IpProtocol::Icmp => 1,
+            IpProtocol::Tcp => 6,
+            IpProtocol::Udp => 17,
+            IpProtocol::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub tos: u8,
+    /// The size of the header and the payload.
+    pub total_len: u16,
+    pub id: u16,
+    /// The flags and the fragment offset (in units of 8 bytes).
+    pub flags_fragment: u16,
+    pub ttl: u8,
+    pub protocol: IpProtocol,
+    pub src: Ipv4Addr,
+    pub dest: Ipv4Addr,
+}
+
+impl Ipv4Header {
+    fn more_fragments(&self) -> bool {
+        self.flags_fragment & FLAG_MORE_FRAGMENTS != 0
+    }
+
+    /// Returns the offset of the fragment, in bytes.
+    fn fragment_offset(&self) -> usize {
+        (self.flags_fragment & FRAGMENT_OFFSET_MASK) as usize * 8
+    }
+
+    fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+
+    pub(super) fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut data = [0; HEADER_SIZE];
+
+        data[0] = 0x45; // version 4, 5 words
+        data[1] = self.tos;
+        data[2..4].copy_from_slice(&self.total_len.to_be_bytes());
+        data[4..6].copy_from_slice(&self.id.to_be_bytes());
+        data[6..8].copy_from_slice(&self.flags_fragment.to_be_bytes());
+        data[8] = self.ttl;
+        data[9] = self.protocol.into();
+        data[12..16].copy_from_slice(&self.src.0);
+        data[16..20].copy_from_slice(&self.dest.0);
+
+        let checksum = checksum(&data);
+        data[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        data
+    }
+}
+
+/// Computes the internet checksum (the ones' complement of the ones' complement sum of
+/// the 16-bit words) of the data.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold_checksum(sum_words(data, 0))
+}
+
+/// Adds the 16-bit words of the data to `sum`. Used to compute checksums that span
+/// multiple buffers (for example, a pseudo header and a payload).
+pub fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+
+    // Fold early so that the sum does not overflow for big buffers.
+    (sum & 0xffff) + (sum >> 16)
+}
+
+/// Folds the carries of the sum into 16 bits.
+pub fn fold_checksum(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum as u16
+}
+
+/// Validates the header of the packet and splits it into its header and payload.
+pub fn decode(data: &[u8]) -> Option<(Ipv4Header, &[u8])> {
+    if data.len() < HEADER_SIZE || data[0] >> 4 != 4 {
+        return None;
+    }
+
+    let header_len = (data[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+
+    // The frame may be padded, so the total length is used to find the payload.
+    if header_len < HEADER_SIZE || total_len < header_len || total_len > data.len() {
+        return None;
+    }
+
+    if checksum(&data[..header_len]) != 0 {
+        return None;
+    }
+
+    let mut src = [0; 4];
+    let mut dest = [0; 4];
+
+    src.copy_from_slice(&data[12..16]);
+    dest.copy_from_slice(&data[16..20]);
+
+    let header = Ipv4Header {
+        tos: data[1],
+        total_len: total_len as u16,
+        id: u16::from_be_bytes([data[4], data[5]]),
+        flags_fragment: u16::from_be_bytes([data[6], data[7]]),
+        ttl: data[8],
+        protocol: data[9].into(),
+        src: Ipv4Addr(src),
+        dest: Ipv4Addr(dest),
+    };
+
+    Some((header, &data[header_len..total_len]))
+}
+
+pub struct Route {
+    pub dest: Ipv4Addr,
+    pub prefix_len: u8,
+    /// The next hop, or [`None`] if the destination is on the link.
+    pub gateway: Option<Ipv4Addr>,
+    pub interface: Arc<Interface>,
+}
+
+static ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+
+/// Adds a route to the routing table, replacing the route to the same subnet.
+pub fn add_route(route: Route) {
+    let mut routes = ROUTES.lock_irq();
+
+    routes.retain(|other| other.dest != route.dest || other.prefix_len != route.prefix_len);
+    routes.push(route);
+}
+
+/// Removes the route to the provided subnet. Returns whether it existed.
+pub fn remove_route(dest: Ipv4Addr, prefix_len: u8) -> bool {
+    let mut routes = ROUTES.lock_irq();
+    let len = routes.len();
+
+    routes.retain(|route| route.dest != dest || route.prefix_len != prefix_len);
+    routes.len() != len
+}
+
+/// Sets the default route through the provided gateway.
+pub fn set_default_gateway(interface: Arc<Interface>, gateway: Ipv4Addr) {
+    add_route(Route {
+        dest: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+        gateway: Some(gateway),
+        interface,
+    });
+}
+
+/// Returns the interface and the next hop of the packets sent to `dest`.
+pub fn route(dest: Ipv4Addr) -> Option<(Arc<Interface>, Ipv4Addr)> {
+    let mut best: Option<(u8, Arc<Interface>, Ipv4Addr)> = None;
+
+    let mut consider = |prefix_len: u8, interface: &Arc<Interface>, next_hop: Ipv4Addr| {
+        if interface.is_up() && best.as_ref().map_or(true, |(len, ..)| prefix_len > *len) {
+            best = Some((prefix_len, interface.clone(), next_hop));
+        }
+    };
+
+    // The subnets of the interfaces.
+    for interface in super::interfaces() {
+        if let Some(addr) = interface.addr() {
+            if addr.address.same_subnet(dest, addr.prefix_len) {
+                consider(addr.prefix_len, &interface, dest);
+            }
+        }
+    }
+
+    for route in ROUTES.lock_irq().iter() {
+        if route.dest.same_subnet(dest, route.prefix_len) {
+            consider(
+                route.prefix_len,
+                &route.interface,
+                route.gateway.unwrap_or(dest),
+            );
+        }
+    }
+
+    best.map(|(_, interface, next_hop)| (interface, next_hop))
+}
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Sends the payload to `dest`, fragmenting it if it does not fit in the MTU of the
+/// outgoing interface.
+pub fn send(dest: Ipv4Addr, protocol: IpProtocol, payload: &[u8]) -> Result<(), NetError> {
+    send_with_ttl(dest, protocol, DEFAULT_TTL, payload)
+}
+
+/// Sends the payload to `dest` with the provided time to live. See [`send`].
+pub fn send_with_ttl(
+    dest: Ipv4Addr,
+    protocol: IpProtocol,
+    ttl: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    let (interface, next_hop) = route(dest).ok_or(NetError::NoRoute)?;
+    let src = interface
+        .addr()
+        .map(|addr| addr.address)
+        .ok_or(NetError::NoRoute)?;
+
+    transmit(&interface, next_hop, src, dest, protocol, ttl, payload)
+}
+
+/// Sends the payload out of the provided interface. See [`send`].
+pub fn send_on(
+    interface: &Arc<Interface>,
+    next_hop: Ipv4Addr,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    protocol: IpProtocol,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    transmit(
+        interface,
+        next_hop,
+        src,
+        dest,
+        protocol,
+        DEFAULT_TTL,
+        payload,
+    )
+}
+
+fn transmit(
+    interface: &Arc<Interface>,
+    next_hop: Ipv4Addr,
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    protocol: IpProtocol,
+    ttl: u8,
+    payload: &[u8],
+) -> Result<(), NetError> {
+    if filter::check(Chain::Output, protocol, src, dest, payload) == Verdict::Drop {
+        return Err(NetError::Filtered);
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    // The payload of every fragment but the last must be a multiple of 8 bytes.
+    let max_fragment = (interface.mtu() - HEADER_SIZE) & !7;
+    let mut offset = 0;
+
+    loop {
+        let size = core::cmp::min(max_fragment, payload.len() - offset);
+        let last = offset + size == payload.len();
+
+        let mut flags_fragment = (offset / 8) as u16;
+
+        if !last {
+            flags_fragment |= FLAG_MORE_FRAGMENTS;
+        }
+
+        let header = Ipv4Header {
+            tos: 0,
+            total_len: (HEADER_SIZE + size) as u16,
+            id,
+            flags_fragment,
+            ttl,
+            protocol,
+            src,
+            dest,
+        };
+
+        let mut packet = Vec::with_capacity(HEADER_SIZE + size);
+
+        packet.extend_from_slice(&header.encode());
+        packet.extend_from_slice(&payload[offset..offset + size]);
+
+        arp::send_to(interface, next_hop, EtherType::Ipv4, &packet)?;
+
+        if last {
+            return Ok(());
+        }
+
+        offset += size;
+    }
+}
+
+/// Handles the payload of a received packet.
+pub type ProtocolHandler = fn(&Arc<Interface>, &Ipv4Header, &[u8]);
+
+static PROTOCOLS: Mutex<BTreeMap<IpProtocol, ProtocolHandler>> = Mutex::new(BTreeMap::new());
+
+/// Registers the handler of the packets with the provided protocol number, replacing
+/// the previous one.
+pub fn register_protocol(protocol: IpProtocol, handler: ProtocolHandler) {
+    PROTOCOLS.lock_irq().insert(protocol, handler);
+}
+
+/// The key of a packet being reassembled, as in RFC 791.
+type ReassemblyKey = (Ipv4Addr, Ipv4Addr, IpProtocol, u16);
+
+struct Reassembly {
+    /// The fragments, keyed by their offset.
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// The size of the payload, known once the last fragment is received.
+    total_len: Option<usize>,
+    /// The header of the first fragment.
+    header: Option<Ipv4Header>,
+    expires: u64,
+}
+
+impl Reassembly {
+    /// Returns the reassembled payload if all of the fragments were received.
+    fn complete(&self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        let mut payload = Vec::with_capacity(total_len);
+
+        for (offset, data) in self.fragments.iter() {
+            // A hole between the fragments.
+            if *offset > payload.len() {
+                return None;
+            }
+
+            // Overlapping fragments, keep the data that was received first.
+            let skip = payload.len() - offset;
+
+            if skip < data.len() {
+                payload.extend_from_slice(&data[skip..]);
+            }
+        }
+
+        (payload.len() == total_len).then(|| payload)
+    }
+}
+
+static REASSEMBLIES: Mutex<BTreeMap<ReassemblyKey, Reassembly>> = Mutex::new(BTreeMap::new());
+
+/// Adds the fragment to its packet and returns the packet if it is complete.
+fn reassemble(header: &Ipv4Header, data: &[u8]) -> Option<(Ipv4Header, Vec<u8>)> {
+    let now = timer::now();
+    let key = (header.src, header.dest, header.protocol, header.id);
+
+    let mut reassemblies = REASSEMBLIES.lock_irq();
+
+    reassemblies.retain(|_, reassembly| reassembly.expires > now);
+
+    if !reassemblies.contains_key(&key) && reassemblies.len() >= MAX_REASSEMBLIES {
+        return None;
+    }
+
+    let reassembly = reassemblies.entry(key).or_insert_with(|| Reassembly {
+        fragments: BTreeMap::new(),
+        total_len: None,
+        header: None,
+        expires: now + REASSEMBLY_TIMEOUT,
+    });
+
+    let offset = header.fragment_offset();
+
+    if !header.more_fragments() {
+        reassembly.total_len = Some(offset + data.len());
+    }
+
+    if offset == 0 {
+        reassembly.header = Some(*header);
+    }
+
+    reassembly
+        .fragments
+        .entry(offset)
+        .or_insert_with(|| data.to_vec());
+
+    let payload = reassembly.complete()?;
+    let mut header = reassembly.header?;
+
+    reassemblies.remove(&key);
+
+    header.flags_fragment = 0;
+    header.total_len = (HEADER_SIZE + payload.len()) as u16;
+
+    Some((header, payload))
+}
+
+fn handle_packet(interface: &Arc<Interface>, _header: &EthernetHeader, data: &[u8]) {
+    let (header, payload) = match decode(data) {
+        Some(decoded) => decoded,
+        None => return,
+    };
+
+    // Forwarding is not supported, so only the packets addressed to the interface (or
+    // broadcasted) are accepted.
+    let accepted = match interface.addr() {
+        Some(addr) => {
+            header.dest == addr.address
+                || header.dest == Ipv4Addr::BROADCAST
+                || header.dest == addr.broadcast()
+        }
+
+        None => header.dest == Ipv4Addr::BROADCAST,
+    };
+
+    if !accepted {
+        return;
+    }
+
+    if header.is_fragment() {
+        if let Some((header, payload)) = reassemble(&header, payload) {
+            deliver(interface, &header, &payload);
+        }
+    } else {
+        deliver(interface, &header, payload);
+    }
+}
+
+/// Passes the payload of a received (and reassembled) packet to the raw sockets and to
+/// the handler of its protocol, unless it is dropped by the packet filter.
+fn deliver(interface: &Arc<Interface>, header: &Ipv4Header, payload: &[u8]) {
+    let verdict = filter::check(
+        Chain::Input,
+        header.protocol,
+        header.src,
+        header.dest,
+        payload,
+    );
+
+    if verdict == Verdict::Drop {
+        return;
+    }
+
+    super::raw::tap_packet(header, payload);
+
+    // Copy the handler out, as it may send a reply which takes the lock again.
+    let handler = PROTOCOLS.lock_irq().get(&header.protocol).copied();
+
+    if let Some(handler) = handler {
+        handler(interface, header, payload);
+    }
+}
+
+pub(super) fn init() {
+    super::ethernet::register_protocol(EtherType::Ipv4, handle_packet);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_header_encode_decode() {
+        let header = Ipv4Header {
+            tos: 0,
+            total_len: (HEADER_SIZE + 4) as u16,
+            id: 0x1234,
+            flags_fragment: 0,
+            ttl: DEFAULT_TTL,
+            protocol: IpProtocol::Icmp,
+            src: Ipv4Addr([10, 0, 2, 15]),
+            dest: Ipv4Addr([10, 0, 2, 2]),
+        };
+
+        let mut packet = header.encode().to_vec();
+        packet.extend_from_slice(&[1, 2, 3, 4]);
+
+        let (decoded, payload) = decode(&packet).unwrap();
+
+        assert_eq!(decoded, header);
+        assert_eq!(payload, &[1, 2, 3, 4]);
+
+        // A corrupted header fails the checksum.
+        packet[8] = 1;
+        assert!(decode(&packet).is_none());
+    }
+}
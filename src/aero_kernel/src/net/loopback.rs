@@ -0,0 +1,74 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The loopback device, which receives the frames it transmits.
+
+use alloc::sync::Arc;
+use spin::Once;
+
+use crate::workqueue;
+
+use super::{Interface, InterfaceAddr, Ipv4Addr, MacAddr, NetDevice, NetError};
+
+const MTU: usize = 16384;
+
+static LOOPBACK: Once<Arc<Interface>> = Once::new();
+
+struct Loopback;
+
+impl NetDevice for Loopback {
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        let frame = frame.to_vec();
+
+        // The frame is received from a work item, as the sender may hold the locks that
+        // the protocol takes to handle it (for example, to reply to it).
+        workqueue::schedule(move || {
+            if let Some(interface) = LOOPBACK.get() {
+                interface.receive(&frame);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn mac_address(&self) -> MacAddr {
+        MacAddr::default()
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+}
+
+/// Registers the loopback interface and brings it up.
+pub(super) fn init() {
+    let interface = super::register_device(Arc::new(Loopback));
+
+    interface.set_addr(Some(InterfaceAddr {
+        address: Ipv4Addr([127, 0, 0, 1]),
+        prefix_len: 8,
+    }));
+
+    interface.set_up(true);
+    LOOPBACK.call_once(|| interface);
+}
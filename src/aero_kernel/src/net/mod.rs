@@ -0,0 +1,375 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The networking stack. A NIC driver implements [`NetDevice`] and registers it with
+//! [`register_device`], which returns the [`Interface`] that the driver passes the
+//! received frames to (see [`Interface::receive`]). The frames are decoded by the
+//! ethernet layer and handed to the protocol registered for their EtherType.
+//!
+//! The loopback interface (`lo`, see [`loopback`]) is always registered, with the
+//! address `127.0.0.1/8`.
+
+pub mod arp;
+pub mod dhcp;
+pub mod ethernet;
+pub mod filter;
+pub mod icmp;
+pub mod ipv4;
+pub mod loopback;
+pub mod nettest;
+pub mod raw;
+pub mod tcp;
+pub mod udp;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::utils::sync::Mutex;
+
+use self::ethernet::{EtherType, EthernetHeader};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: Self = Self([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl core::fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([0xff; 4]);
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// Returns whether both addresses are in the same subnet, given its prefix length.
+    pub fn same_subnet(&self, other: Ipv4Addr, prefix_len: u8) -> bool {
+        let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+        self.as_u32() & mask == other.as_u32() & mask
+    }
+}
+
+impl From<u32> for Ipv4Addr {
+    fn from(value: u32) -> Self {
+        Self(value.to_be_bytes())
+    }
+}
+
+impl core::fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl InterfaceAddr {
+    /// Returns the broadcast address of the subnet.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        let mask = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        Ipv4Addr::from(self.address.as_u32() | !mask)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetError {
+    /// The interface is down.
+    InterfaceDown,
+    /// The packet does not fit in the MTU of the interface.
+    PacketTooBig,
+    /// The device failed to transmit the frame (for example, its ring is full).
+    DeviceBusy,
+    /// The packet is malformed.
+    InvalidPacket,
+    /// Too many packets are waiting for the address resolution of the destination.
+    QueueFull,
+    /// There is no route to the destination.
+    NoRoute,
+    /// No reply was received in time.
+    TimedOut,
+    /// The wait was interrupted by a signal.
+    Interrupted,
+    /// The port is already bound.
+    AddressInUse,
+    /// The packet was dropped by the packet filter.
+    Filtered,
+}
+
+/// A network interface card (or a virtual device, such as the loopback device).
+pub trait NetDevice: Send + Sync {
+    /// Transmits the ethernet frame. The frame does not include the frame check sequence.
+    fn transmit(&self, frame: &[u8]) -> Result<(), NetError>;
+
+    /// Returns the maximum size of the payload of a frame.
+    fn mtu(&self) -> usize;
+
+    fn mac_address(&self) -> MacAddr;
+
+    /// Returns whether the device is the loopback device, which does not need the
+    /// address resolution nor the DHCP configuration.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct InterfaceStats {
+    pub rx_packets: AtomicU64,
+    pub rx_bytes: AtomicU64,
+    pub rx_dropped: AtomicU64,
+    pub tx_packets: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub tx_errors: AtomicU64,
+}
+
+/// A registered network device, see [`register_device`].
+pub struct Interface {
+    id: usize,
+    name: String,
+    device: Arc<dyn NetDevice>,
+    up: AtomicBool,
+    addr: Mutex<Option<InterfaceAddr>>,
+    stats: InterfaceStats,
+}
+
+impl Interface {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the index of the interface, as used by the packet sockets. The indices
+    /// start at one, as zero means any interface.
+    pub fn index(&self) -> u32 {
+        self.id as u32 + 1
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+
+    pub fn mac_address(&self) -> MacAddr {
+        self.device.mac_address()
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.device.is_loopback()
+    }
+
+    /// Returns the IPv4 address assigned to the interface.
+    pub fn addr(&self) -> Option<InterfaceAddr> {
+        *self.addr.lock_irq()
+    }
+
+    pub fn set_addr(&self, addr: Option<InterfaceAddr>) {
+        *self.addr.lock_irq() = addr;
+    }
+
+    pub fn stats(&self) -> &InterfaceStats {
+        &self.stats
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    pub fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::SeqCst);
+        log::debug!("net: {} is {}", self.name, if up { "up" } else { "down" });
+    }
+
+    /// Wraps the payload in an ethernet frame addressed to `dest` and transmits it.
+    pub fn send(
+        &self,
+        dest: MacAddr,
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> Result<(), NetError> {
+        if !self.is_up() {
+            return Err(NetError::InterfaceDown);
+        }
+
+        if payload.len() > self.mtu() {
+            return Err(NetError::PacketTooBig);
+        }
+
+        let header = EthernetHeader {
+            dest,
+            src: self.mac_address(),
+            ether_type,
+        };
+
+        self.transmit(&ethernet::encode(&header, payload))
+    }
+
+    /// Transmits the complete ethernet frame. Used by the packet sockets to send the
+    /// frames they build themselves.
+    pub fn transmit(&self, frame: &[u8]) -> Result<(), NetError> {
+        if !self.is_up() {
+            return Err(NetError::InterfaceDown);
+        }
+
+        if frame.len() < ethernet::HEADER_SIZE || frame.len() > ethernet::HEADER_SIZE + self.mtu() {
+            return Err(NetError::PacketTooBig);
+        }
+
+        match self.device.transmit(frame) {
+            Ok(()) => {
+                self.stats.tx_packets.fetch_add(1, Ordering::Relaxed);
+                self.stats
+                    .tx_bytes
+                    .fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+                raw::tap_frame(self, frame, true);
+                Ok(())
+            }
+
+            Err(err) => {
+                self.stats.tx_errors.fetch_add(1, Ordering::Relaxed);
+                Err(err)
+            }
+        }
+    }
+
+    /// Handles a frame received by the device. Called by the NIC drivers.
+    pub fn receive(self: &Arc<Self>, frame: &[u8]) {
+        if !self.is_up() {
+            self.stats.rx_dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.stats.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .rx_bytes
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+        raw::tap_frame(self, frame, false);
+
+        if !ethernet::demux(self, frame) {
+            self.stats.rx_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+
+/// Registers the network device as a new interface named `eth<N>` (or `lo` for the
+/// loopback device). The interface starts down.
+pub fn register_device(device: Arc<dyn NetDevice>) -> Arc<Interface> {
+    let mut interfaces = INTERFACES.lock_irq();
+    let id = interfaces.len();
+
+    let name = if device.is_loopback() {
+        String::from("lo")
+    } else {
+        let nics = interfaces.iter().filter(|i| !i.is_loopback()).count();
+        alloc::format!("eth{}", nics)
+    };
+
+    let interface = Arc::new(Interface {
+        id,
+        name,
+        device,
+        up: AtomicBool::new(false),
+        addr: Mutex::new(None),
+        stats: InterfaceStats::default(),
+    });
+
+    log::info!(
+        "net: registered {} (mac={}, mtu={})",
+        interface.name,
+        interface.mac_address(),
+        interface.mtu()
+    );
+
+    interfaces.push(interface.clone());
+    interface
+}
+
+/// Returns the interface with the provided name.
+pub fn get_interface(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES
+        .lock_irq()
+        .iter()
+        .find(|interface| interface.name == name)
+        .cloned()
+}
+
+/// Returns the interface with the provided index (see [`Interface::index`]).
+pub fn get_interface_by_index(index: u32) -> Option<Arc<Interface>> {
+    INTERFACES
+        .lock_irq()
+        .iter()
+        .find(|interface| interface.index() == index)
+        .cloned()
+}
+
+/// Returns all of the registered interfaces.
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock_irq().clone()
+}
+
+pub fn init() {
+    arp::init();
+    ipv4::init();
+    icmp::init();
+    udp::init();
+    tcp::init();
+
+    loopback::init();
+}
+
+// The protocols are registered before the NIC drivers are loaded.
+crate::early_initcall!(init);
@@ -0,0 +1,184 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The network interoperability probes run by `./aero.py --nettest`.
+//!
+//! With `nettest=<address>/<prefix>[,<peer>]` on the kernel command line, the first NIC
+//! is configured with the static address and a kernel thread resolves the peer with ARP,
+//! pings it and opens a TCP connection to it. Each probe prints a `nettest: <probe> ok`
+//! or `nettest: <probe> failed` marker on the console, followed by `nettest: done`. Without
+//! a peer, the ping and TCP probes are run against the loopback interface.
+
+use core::mem::size_of;
+
+use aero_syscall::SocketAddrInet;
+use spin::Once;
+
+use crate::cmdline;
+use crate::fs::inode::INodeInterface;
+use crate::fs::FileSystemError;
+use crate::socket::tcp::TcpSocket;
+use crate::socket::{inet_to_sockaddr, SocketAddr};
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+
+use super::filter::Subnet;
+use super::{arp, icmp, interfaces, InterfaceAddr, Ipv4Addr};
+
+/// The port the TCP probe connects to. The probe passes whether the peer accepts the
+/// connection or refuses it, as both take a complete exchange of segments.
+const TCP_PORT: u16 = 7;
+
+/// The amount of echo requests sent before the ping probe fails. The peer may still be
+/// booting, so they are sent for up to a minute.
+const PING_ATTEMPTS: u16 = 60;
+/// The time waited for each echo reply, in nanoseconds.
+const PING_TIMEOUT: u64 = 1_000_000_000;
+
+struct Config {
+    addr: InterfaceAddr,
+    peer: Option<Ipv4Addr>,
+}
+
+static CONFIG: Once<Config> = Once::new();
+
+/// Parses the `nettest=` option (`10.0.0.1/24,10.0.0.2`).
+fn parse_config(value: &str) -> Option<Config> {
+    let (addr, peer) = match value.split_once(',') {
+        Some((addr, peer)) => (addr, Some(peer)),
+        None => (value, None),
+    };
+
+    let addr = Subnet::parse(addr)?;
+    let peer = match peer {
+        Some(peer) => Some(Subnet::parse(peer)?.address),
+        None => None,
+    };
+
+    Some(Config {
+        addr: InterfaceAddr {
+            address: addr.address,
+            prefix_len: addr.prefix_len,
+        },
+        peer,
+    })
+}
+
+/// Returns whether the probes are enabled on the kernel command line.
+pub fn is_enabled() -> bool {
+    cmdline::get_option("nettest").is_some()
+}
+
+/// Pings `dest` until it replies. Returns whether it did.
+fn probe_ping(dest: Ipv4Addr) -> bool {
+    for seq in 0..PING_ATTEMPTS {
+        match icmp::ping(dest, seq, b"nettest", PING_TIMEOUT) {
+            Ok(rtt) => {
+                log::info!("nettest: ping ok ({dest}, {}us)", rtt / 1000);
+                return true;
+            }
+
+            Err(err) => log::debug!("nettest: ping {dest}: {err:?}"),
+        }
+    }
+
+    log::error!("nettest: ping failed ({dest}, no reply)");
+    false
+}
+
+fn probe_tcp(dest: Ipv4Addr) {
+    let socket = TcpSocket::new();
+    let address = inet_to_sockaddr(dest, TCP_PORT);
+
+    match socket.connect(SocketAddr::INet(&address), size_of::<SocketAddrInet>()) {
+        Ok(()) => log::info!("nettest: tcp ok ({dest}:{TCP_PORT}, connected)"),
+        Err(FileSystemError::ConnectionRefused) => {
+            log::info!("nettest: tcp ok ({dest}:{TCP_PORT}, refused)")
+        }
+        Err(err) => log::error!("nettest: tcp failed ({dest}:{TCP_PORT}, {err:?})"),
+    }
+}
+
+fn nettest_thread() {
+    let config = CONFIG.get().unwrap();
+
+    match config.peer {
+        Some(peer) => {
+            let interface = interfaces()
+                .into_iter()
+                .find(|interface| !interface.is_loopback());
+
+            match interface {
+                Some(interface) => {
+                    interface.set_addr(Some(config.addr));
+                    interface.set_up(true);
+
+                    log::info!("nettest: {}: {}", interface.name(), config.addr.address);
+
+                    // The echo requests resolve the peer first.
+                    let replied = probe_ping(peer);
+
+                    match arp::lookup(&interface, peer) {
+                        Some(mac) => log::info!("nettest: arp ok ({peer} is at {mac})"),
+                        None => log::error!("nettest: arp failed ({peer} is unresolved)"),
+                    }
+
+                    if replied {
+                        probe_tcp(peer);
+                    }
+                }
+
+                None => log::error!("nettest: failed (no network interface)"),
+            }
+        }
+
+        None => {
+            let localhost = Ipv4Addr([127, 0, 0, 1]);
+
+            if probe_ping(localhost) {
+                probe_tcp(localhost);
+            }
+        }
+    }
+
+    log::info!("nettest: done");
+}
+
+/// Spawns the probe thread if the probes are enabled. Called once the NIC drivers are
+/// loaded.
+fn init() {
+    let value = match cmdline::get_option("nettest") {
+        Some(value) => value,
+        None => return,
+    };
+
+    match parse_config(value) {
+        Some(config) => {
+            CONFIG.call_once(|| config);
+            scheduler::get_scheduler().register_task(Task::new_kernel(nettest_thread, true));
+        }
+
+        None => {
+            log::error!("nettest: failed (invalid option '{value}')");
+            log::info!("nettest: done");
+        }
+    }
+}
+
+crate::late_initcall!(init);
@@ -0,0 +1,126 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Raw access to the network, for the diagnostic tools. The packet sockets (see
+//! [`crate::socket::packet`]) receive a copy of every frame sent or received on the
+//! interfaces and the raw sockets (see [`crate::socket::raw`]) receive a copy of every
+//! received IPv4 packet with their protocol.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use aero_syscall::{
+    PACKET_BROADCAST, PACKET_HOST, PACKET_MULTICAST, PACKET_OTHERHOST, PACKET_OUTGOING,
+};
+
+use crate::socket::packet::{Frame, PacketSocket};
+use crate::socket::raw::{RawPacket, RawSocket};
+use crate::utils::sync::Mutex;
+
+use super::ethernet;
+use super::ipv4::{self, Ipv4Header};
+use super::Interface;
+
+static PACKET_SOCKETS: Mutex<Vec<Weak<PacketSocket>>> = Mutex::new(Vec::new());
+static RAW_SOCKETS: Mutex<Vec<Weak<RawSocket>>> = Mutex::new(Vec::new());
+
+pub fn register_packet_socket(socket: Weak<PacketSocket>) {
+    PACKET_SOCKETS.lock_irq().push(socket);
+}
+
+pub fn register_raw_socket(socket: Weak<RawSocket>) {
+    RAW_SOCKETS.lock_irq().push(socket);
+}
+
+/// Returns the sockets that are still alive, forgetting the dropped ones. The sockets
+/// are collected so that they are not called with the lock held.
+fn live_sockets<T>(sockets: &Mutex<Vec<Weak<T>>>) -> Vec<Arc<T>> {
+    let mut sockets = sockets.lock_irq();
+
+    sockets.retain(|socket| socket.strong_count() != 0);
+    sockets
+        .iter()
+        .filter_map(|socket| socket.upgrade())
+        .collect()
+}
+
+/// Classifies the frame the same way as the `sll_pkttype` field of Linux.
+fn packet_type(interface: &Interface, frame: &[u8], outgoing: bool) -> u8 {
+    let (header, _) = match ethernet::decode(frame) {
+        Some(decoded) => decoded,
+        None => return PACKET_OTHERHOST,
+    };
+
+    if outgoing {
+        PACKET_OUTGOING
+    } else if header.dest == interface.mac_address() {
+        PACKET_HOST
+    } else if header.dest.is_broadcast() {
+        PACKET_BROADCAST
+    } else if header.dest.is_multicast() {
+        PACKET_MULTICAST
+    } else {
+        PACKET_OTHERHOST
+    }
+}
+
+/// Passes a copy of the frame sent or received on the interface to the packet sockets.
+pub(super) fn tap_frame(interface: &Interface, frame: &[u8], outgoing: bool) {
+    let sockets = live_sockets(&PACKET_SOCKETS);
+
+    if sockets.is_empty() || frame.len() < ethernet::HEADER_SIZE {
+        return;
+    }
+
+    let ether_type = u16::from_be_bytes([frame[12], frame[13]]);
+    let pkttype = packet_type(interface, frame, outgoing);
+
+    for socket in sockets {
+        if socket.accepts(interface.index(), ether_type) {
+            socket.deliver(Frame {
+                ifindex: interface.index(),
+                pkttype,
+                data: frame.to_vec(),
+            });
+        }
+    }
+}
+
+/// Passes a copy of the received packet to the raw sockets with its protocol. The raw
+/// sockets receive the packet with its header, without the options.
+pub(super) fn tap_packet(header: &Ipv4Header, payload: &[u8]) {
+    let mut header = *header;
+    header.total_len = (ipv4::HEADER_SIZE + payload.len()) as u16;
+
+    for socket in live_sockets(&RAW_SOCKETS) {
+        if socket.protocol() != header.protocol {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(header.total_len as usize);
+
+        data.extend_from_slice(&header.encode());
+        data.extend_from_slice(payload);
+
+        socket.deliver(RawPacket {
+            src: header.src,
+            data,
+        });
+    }
+}
@@ -0,0 +1,388 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! TCP (Transmission Control Protocol) segments and demultiplexing. A received segment
+//! is handed to the connection it belongs to or, for a new connection, to the socket
+//! listening on its destination port. The state machine of the connections lives in
+//! [`crate::socket::tcp`].
+//!
+//! The connections are kept in the connection table until they are fully closed, so a
+//! connection whose socket was closed by its owner still completes the closing
+//! handshake.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc793>
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::socket::tcp::TcpSocket;
+use crate::timer::{self, Timer};
+use crate::utils::sync::Mutex;
+
+use super::ipv4::{self, IpProtocol, Ipv4Header};
+use super::{Interface, Ipv4Addr, NetError};
+
+/// The size of a header without options.
+pub const HEADER_SIZE: usize = 20;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// The interval of the connection timers (retransmission and TIME-WAIT), in
+/// nanoseconds.
+const TIMER_INTERVAL: u64 = 100_000_000;
+
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+bitflags::bitflags! {
+    pub struct TcpFlags: u8 {
+        const FIN = 1 << 0;
+        const SYN = 1 << 1;
+        const RST = 1 << 2;
+        const PSH = 1 << 3;
+        const ACK = 1 << 4;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dest_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: TcpFlags,
+    pub window: u16,
+    /// The maximum segment size option, only sent with SYN.
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    /// Returns the amount of sequence numbers taken by the segment.
+    pub fn seq_len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+
+        if self.flags.contains(TcpFlags::SYN) {
+            len += 1;
+        }
+
+        if self.flags.contains(TcpFlags::FIN) {
+            len += 1;
+        }
+
+        len
+    }
+
+    /// Validates the checksum of the segment and decodes it.
+    pub fn decode(src: Ipv4Addr, dest: Ipv4Addr, data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || segment_checksum(src, dest, data) != 0 {
+            return None;
+        }
+
+        let header_len = (data[12] >> 4) as usize * 4;
+
+        if header_len < HEADER_SIZE || header_len > data.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &data[HEADER_SIZE..header_len];
+
+        while let [kind, rest @ ..] = options {
+            match *kind {
+                OPTION_END => break,
+                OPTION_NOP => options = rest,
+
+                _ => {
+                    let len = *rest.first()? as usize;
+
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+
+                    if *kind == OPTION_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+
+                    options = &options[len..];
+                }
+            }
+        }
+
+        Some(Self {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dest_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ack: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            flags: TcpFlags::from_bits_truncate(data[13]),
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            payload: &data[header_len..],
+        })
+    }
+
+    pub fn encode(&self, src: Ipv4Addr, dest: Ipv4Addr) -> Vec<u8> {
+        let header_len = if self.mss.is_some() {
+            HEADER_SIZE + 4
+        } else {
+            HEADER_SIZE
+        };
+        let mut data = Vec::with_capacity(header_len + self.payload.len());
+
+        data.extend_from_slice(&self.src_port.to_be_bytes());
+        data.extend_from_slice(&self.dest_port.to_be_bytes());
+        data.extend_from_slice(&self.seq.to_be_bytes());
+        data.extend_from_slice(&self.ack.to_be_bytes());
+        data.push(((header_len / 4) as u8) << 4);
+        data.push(self.flags.bits());
+        data.extend_from_slice(&self.window.to_be_bytes());
+        data.extend_from_slice(&[0; 4]); // checksum and urgent pointer
+
+        if let Some(mss) = self.mss {
+            data.extend_from_slice(&[OPTION_MSS, 4]);
+            data.extend_from_slice(&mss.to_be_bytes());
+        }
+
+        data.extend_from_slice(self.payload);
+
+        let checksum = segment_checksum(src, dest, &data);
+        data[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+        data
+    }
+}
+
+/// Computes the checksum of the segment, including the IPv4 pseudo header.
+fn segment_checksum(src: Ipv4Addr, dest: Ipv4Addr, segment: &[u8]) -> u16 {
+    let mut pseudo = [0; 12];
+
+    pseudo[0..4].copy_from_slice(&src.0);
+    pseudo[4..8].copy_from_slice(&dest.0);
+    pseudo[9] = IpProtocol::Tcp.into();
+    pseudo[10..12].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+
+    let sum = ipv4::sum_words(segment, ipv4::sum_words(&pseudo, 0));
+    !ipv4::fold_checksum(sum)
+}
+
+/// Returns whether the sequence number `a` is before `b`, taking the wrap around into
+/// account.
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// Returns an initial sequence number. As suggested by RFC 793, it is derived from a
+/// clock that ticks every 4 microseconds.
+pub fn initial_seq() -> u32 {
+    (timer::now() / 4000) as u32
+}
+
+/// Returns the source address and the outgoing interface of the segments sent to
+/// `dest`.
+pub fn route(dest: Ipv4Addr) -> Result<(Arc<Interface>, Ipv4Addr, Ipv4Addr), NetError> {
+    let (interface, next_hop) = ipv4::route(dest).ok_or(NetError::NoRoute)?;
+    let src = interface
+        .addr()
+        .map(|addr| addr.address)
+        .ok_or(NetError::NoRoute)?;
+
+    Ok((interface, next_hop, src))
+}
+
+pub fn send_segment(
+    src: Ipv4Addr,
+    dest: Ipv4Addr,
+    segment: &TcpSegment<'_>,
+) -> Result<(), NetError> {
+    let (interface, next_hop, _) = route(dest)?;
+    let data = segment.encode(src, dest);
+
+    ipv4::send_on(&interface, next_hop, src, dest, IpProtocol::Tcp, &data)
+}
+
+/// Answers a segment that does not belong to any connection with a reset.
+fn send_reset(header: &Ipv4Header, segment: &TcpSegment<'_>) {
+    if segment.flags.contains(TcpFlags::RST) {
+        return;
+    }
+
+    let reset = if segment.flags.contains(TcpFlags::ACK) {
+        TcpSegment {
+            src_port: segment.dest_port,
+            dest_port: segment.src_port,
+            seq: segment.ack,
+            ack: 0,
+            flags: TcpFlags::RST,
+            window: 0,
+            mss: None,
+            payload: &[],
+        }
+    } else {
+        TcpSegment {
+            src_port: segment.dest_port,
+            dest_port: segment.src_port,
+            seq: 0,
+            ack: segment.seq.wrapping_add(segment.seq_len()),
+            flags: TcpFlags::RST | TcpFlags::ACK,
+            window: 0,
+            mss: None,
+            payload: &[],
+        }
+    };
+
+    let _ = send_segment(header.dest, header.src, &reset);
+}
+
+/// The key of a connection: the local port and the remote address and port.
+pub type ConnectionKey = (u16, Ipv4Addr, u16);
+
+static CONNECTIONS: Mutex<BTreeMap<ConnectionKey, Arc<TcpSocket>>> = Mutex::new(BTreeMap::new());
+static LISTENERS: Mutex<BTreeMap<u16, Weak<TcpSocket>>> = Mutex::new(BTreeMap::new());
+/// The ports bound by the sockets (the accepted connections use the port of their
+/// listener and do not bind it).
+static PORTS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+/// Binds the provided port, or a free ephemeral port if `port` is zero. Returns the
+/// bound port.
+pub fn bind_port(port: u16) -> Result<u16, NetError> {
+    let mut ports = PORTS.lock_irq();
+
+    let port = if port == 0 {
+        EPHEMERAL_PORTS
+            .clone()
+            .find(|port| !ports.contains(port))
+            .ok_or(NetError::AddressInUse)?
+    } else {
+        port
+    };
+
+    if !ports.insert(port) {
+        return Err(NetError::AddressInUse);
+    }
+
+    Ok(port)
+}
+
+pub fn unbind_port(port: u16) {
+    PORTS.lock_irq().remove(&port);
+}
+
+pub fn register_listener(port: u16, socket: Weak<TcpSocket>) {
+    LISTENERS.lock_irq().insert(port, socket);
+}
+
+pub fn unregister_listener(port: u16) {
+    LISTENERS.lock_irq().remove(&port);
+}
+
+pub fn register_connection(key: ConnectionKey, socket: Arc<TcpSocket>) {
+    CONNECTIONS.lock_irq().insert(key, socket);
+}
+
+pub fn unregister_connection(key: ConnectionKey) {
+    // The socket is dropped after the lock is released.
+    let socket = CONNECTIONS.lock_irq().remove(&key);
+    core::mem::drop(socket);
+}
+
+fn handle_packet(_interface: &Arc<Interface>, header: &Ipv4Header, data: &[u8]) {
+    let segment = match TcpSegment::decode(header.src, header.dest, data) {
+        Some(segment) => segment,
+        None => return,
+    };
+
+    let key = (segment.dest_port, header.src, segment.src_port);
+
+    // The locks of the tables are not held while the segment is handled, as the
+    // handlers register and unregister the connections.
+    let connection = CONNECTIONS.lock_irq().get(&key).cloned();
+
+    if let Some(socket) = connection {
+        socket.handle_segment(&segment);
+        return;
+    }
+
+    let listener = LISTENERS
+        .lock_irq()
+        .get(&segment.dest_port)
+        .and_then(|socket| socket.upgrade());
+
+    match listener {
+        Some(listener) if listener.handle_listen(header, &segment) => {}
+        _ => send_reset(header, &segment),
+    }
+}
+
+/// Runs the timers of the connections. Runs from the timer tick and re-arms itself.
+fn run_timers() {
+    let now = timer::now();
+    let connections = CONNECTIONS.lock_irq().values().cloned().collect::<Vec<_>>();
+
+    for socket in connections {
+        socket.on_timer(now);
+    }
+
+    arm_timer();
+}
+
+fn arm_timer() {
+    let timer = Timer::new(timer::now() + TIMER_INTERVAL, Box::new(run_timers));
+
+    // The timer re-arms itself, so it is never cancelled.
+    core::mem::forget(timer);
+}
+
+pub(super) fn init() {
+    ipv4::register_protocol(IpProtocol::Tcp, handle_packet);
+    arm_timer();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tcp_segment_encode_decode() {
+        let src = Ipv4Addr([10, 0, 2, 15]);
+        let dest = Ipv4Addr([10, 0, 2, 2]);
+
+        let segment = TcpSegment {
+            src_port: 49152,
+            dest_port: 80,
+            seq: 0xffff_fff0,
+            ack: 0,
+            flags: TcpFlags::SYN,
+            window: 0xffff,
+            mss: Some(1460),
+            payload: &[],
+        };
+
+        let data = segment.encode(src, dest);
+
+        assert_eq!(TcpSegment::decode(src, dest, &data), Some(segment));
+        assert!(seq_lt(0xffff_fff0, 0x10));
+    }
+}
@@ -0,0 +1,185 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! UDP (User Datagram Protocol). The received datagrams are delivered to the socket
+//! bound to their destination port (see [`crate::socket::udp`]).
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc768>
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::socket::udp::{Datagram, UdpSocket};
+use crate::utils::sync::Mutex;
+
+use super::ipv4::{self, IpProtocol, Ipv4Header};
+use super::{Interface, Ipv4Addr, NetError};
+
+pub const HEADER_SIZE: usize = 8;
+
+/// The range of the ports allocated to the sockets that are not bound explicitly.
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// The sockets, keyed by their local port.
+static PORTS: Mutex<BTreeMap<u16, Weak<UdpSocket>>> = Mutex::new(BTreeMap::new());
+
+/// Binds the socket to the provided port, or to a free ephemeral port if `port` is zero.
+/// Returns the bound port.
+pub fn bind_port(port: u16, socket: Weak<UdpSocket>) -> Result<u16, NetError> {
+    let mut ports = PORTS.lock_irq();
+
+    // Forget the sockets that were dropped without unbinding.
+    ports.retain(|_, socket| socket.strong_count() != 0);
+
+    let port = if port == 0 {
+        EPHEMERAL_PORTS
+            .clone()
+            .find(|port| !ports.contains_key(port))
+            .ok_or(NetError::AddressInUse)?
+    } else if ports.contains_key(&port) {
+        return Err(NetError::AddressInUse);
+    } else {
+        port
+    };
+
+    ports.insert(port, socket);
+    Ok(port)
+}
+
+pub fn unbind_port(port: u16) {
+    PORTS.lock_irq().remove(&port);
+}
+
+/// Computes the checksum of the datagram, including the IPv4 pseudo header.
+fn datagram_checksum(src: Ipv4Addr, dest: Ipv4Addr, datagram: &[u8]) -> u16 {
+    let mut pseudo = [0; 12];
+
+    pseudo[0..4].copy_from_slice(&src.0);
+    pseudo[4..8].copy_from_slice(&dest.0);
+    pseudo[9] = IpProtocol::Udp.into();
+    pseudo[10..12].copy_from_slice(&(datagram.len() as u16).to_be_bytes());
+
+    let sum = ipv4::sum_words(datagram, ipv4::sum_words(&pseudo, 0));
+    !ipv4::fold_checksum(sum)
+}
+
+fn encode(src: Ipv4Addr, src_port: u16, dest: Ipv4Addr, dest_port: u16, data: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + data.len());
+
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dest_port.to_be_bytes());
+    datagram.extend_from_slice(&((HEADER_SIZE + data.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+
+    // A zero checksum means that the checksum was not computed, so it is sent as
+    // all ones instead.
+    let checksum = match datagram_checksum(src, dest, &datagram) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+/// Sends the datagram to `dest`, routed by the IPv4 layer.
+pub fn send(src_port: u16, dest: Ipv4Addr, dest_port: u16, data: &[u8]) -> Result<(), NetError> {
+    if HEADER_SIZE + data.len() > u16::MAX as usize {
+        return Err(NetError::PacketTooBig);
+    }
+
+    let (interface, next_hop) = ipv4::route(dest).ok_or(NetError::NoRoute)?;
+    let src = interface
+        .addr()
+        .map(|addr| addr.address)
+        .ok_or(NetError::NoRoute)?;
+
+    let datagram = encode(src, src_port, dest, dest_port, data);
+    ipv4::send_on(&interface, next_hop, src, dest, IpProtocol::Udp, &datagram)
+}
+
+/// Sends the datagram out of the provided interface, from the provided source address
+/// (which may be unspecified, for example, before the interface is configured by DHCP).
+pub fn send_on(
+    interface: &Arc<Interface>,
+    src: Ipv4Addr,
+    src_port: u16,
+    dest: Ipv4Addr,
+    dest_port: u16,
+    data: &[u8],
+) -> Result<(), NetError> {
+    let datagram = encode(src, src_port, dest, dest_port, data);
+    ipv4::send_on(interface, dest, src, dest, IpProtocol::Udp, &datagram)
+}
+
+fn handle_packet(interface: &Arc<Interface>, header: &Ipv4Header, datagram: &[u8]) {
+    if datagram.len() < HEADER_SIZE {
+        return;
+    }
+
+    let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+    let dest_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    let len = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    let checksum = u16::from_be_bytes([datagram[6], datagram[7]]);
+
+    if len < HEADER_SIZE || len > datagram.len() {
+        return;
+    }
+
+    let datagram = &datagram[..len];
+
+    if checksum != 0 && datagram_checksum(header.src, header.dest, datagram) != 0 {
+        return;
+    }
+
+    let socket = PORTS
+        .lock_irq()
+        .get(&dest_port)
+        .and_then(|socket| socket.upgrade());
+
+    if let Some(socket) = socket {
+        socket.deliver(Datagram {
+            src: header.src,
+            src_port,
+            interface: interface.clone(),
+            data: datagram[HEADER_SIZE..].to_vec(),
+        });
+    }
+}
+
+pub(super) fn init() {
+    ipv4::register_protocol(IpProtocol::Udp, handle_packet);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udp_checksum_verifies() {
+        let src = Ipv4Addr([10, 0, 2, 15]);
+        let dest = Ipv4Addr([10, 0, 2, 3]);
+        let datagram = encode(src, 49152, dest, 53, b"hello");
+
+        assert_eq!(datagram.len(), HEADER_SIZE + 5);
+        assert_eq!(datagram_checksum(src, dest, &datagram), 0);
+    }
+}
@@ -0,0 +1,505 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The kernel random number generator. The entropy sources (the timing of the
+//! interrupts, the jitter of the cycle counter and `rdseed`/`rdrand` when available) are
+//! mixed into an entropy pool, which (re)seeds a ChaCha20 based CSPRNG. The CSPRNG is
+//! seeded once the pool was credited with [`SEED_BITS`] bits of entropy, and reseeded at
+//! most every [`RESEED_INTERVAL`] afterwards.
+//!
+//! The seed normally comes from `rdseed`/`rdrand`. The jitter of the cycle counter is
+//! only credited if it passes the health tests, as it is deterministic under emulation.
+//! The interrupt timings are mixed into a per-CPU fast pool without taking a lock, which
+//! is spilled into the entropy pool every [`FAST_POOL_SPILL`] interrupts.
+//!
+//! The key of the CSPRNG is replaced after each request (fast key erasure), so that the
+//! previous outputs cannot be recovered from its state.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc7539>
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::arch::tls;
+use crate::timer;
+use crate::userland::signals::SignalResult;
+use crate::utils::sync::{Mutex, MutexGuard, WaitQueue};
+
+/// The amount of entropy (in bits) required to seed the CSPRNG.
+const SEED_BITS: usize = 256;
+/// The maximum amount of entropy (in bits) held by the pool.
+const POOL_BITS: usize = 512;
+/// The minimum time between two reseeds, in nanoseconds.
+const RESEED_INTERVAL: u64 = 60_000_000_000;
+/// The amount of bytes generated with the lock held, so that large requests do not
+/// keep the interrupts disabled for too long.
+const CHUNK_SIZE: usize = 4096;
+
+const MAX_CPUS: usize = 64;
+/// The amount of interrupts mixed into a fast pool before it is spilled into the entropy
+/// pool, which credits them with a single bit.
+const FAST_POOL_SPILL: usize = 64;
+
+/// The amount of cycle counter jitter samples per credited bit of entropy.
+const JITTER_SAMPLES_PER_BIT: usize = 8;
+/// The cutoffs of the health tests (NIST SP 800-90B, section 4.4) for a false positive
+/// probability of 2^-20, assuming one bit of entropy per sample (which is more than what
+/// is credited).
+const REPETITION_CUTOFF: usize = 21;
+const PROPORTION_WINDOW: usize = 512;
+const PROPORTION_CUTOFF: usize = 410;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Computes the ChaCha20 block of the key and the input (the block counter and the
+/// nonce).
+fn chacha20_block(key: &[u32; 8], input: &[u32; 4]) -> [u32; 16] {
+    let mut initial = [0; 16];
+
+    initial[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    initial[4..12].copy_from_slice(key);
+    initial[12..].copy_from_slice(input);
+
+    let mut state = initial;
+
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    for (word, initial) in state.iter_mut().zip(initial.iter()) {
+        *word = word.wrapping_add(*initial);
+    }
+
+    state
+}
+
+struct EntropyPool {
+    words: [u32; 16],
+    index: usize,
+    /// The estimated amount of entropy in the pool, in bits.
+    bits: usize,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self {
+            words: [0; 16],
+            index: 0,
+            bits: 0,
+        }
+    }
+
+    /// Mixes the sample into the pool and credits it with `bits` bits of entropy.
+    fn mix(&mut self, sample: u64, bits: usize) {
+        for half in [sample as u32, (sample >> 32) as u32] {
+            let i = self.index;
+            let neighbour = self.words[(i + 7) % 16];
+
+            self.words[i] = self.words[i].rotate_left(7) ^ half ^ neighbour.rotate_right(3);
+            self.index = (i + 1) % 16;
+        }
+
+        self.bits = core::cmp::min(self.bits + bits, POOL_BITS);
+    }
+
+    /// Extracts a seed from the pool, which is left without entropy. The pool is
+    /// updated with the output, so that the seed cannot be recovered from it.
+    fn extract(&mut self) -> [u32; 8] {
+        let mut key = [0; 8];
+        let mut input = [0; 4];
+
+        key.copy_from_slice(&self.words[..8]);
+        input.copy_from_slice(&self.words[8..12]);
+
+        let output = chacha20_block(&key, &input);
+
+        for (word, output) in self.words.iter_mut().zip(output.iter()) {
+            *word ^= *output;
+        }
+
+        self.bits = 0;
+
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&output[8..]);
+        seed
+    }
+}
+
+struct Crng {
+    key: [u32; 8],
+    counter: u64,
+    /// The time of the last reseed, in nanoseconds.
+    reseeded_at: u64,
+}
+
+impl Crng {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            counter: 0,
+            reseeded_at: 0,
+        }
+    }
+
+    fn reseed(&mut self, seed: &[u32; 8]) {
+        for (word, seed) in self.key.iter_mut().zip(seed.iter()) {
+            *word ^= *seed;
+        }
+
+        self.reseeded_at = timer::now();
+    }
+
+    fn next_block(&mut self) -> [u32; 16] {
+        let input = [self.counter as u32, (self.counter >> 32) as u32, 0, 0];
+
+        self.counter = self.counter.wrapping_add(1);
+        chacha20_block(&self.key, &input)
+    }
+
+    fn fill_bytes(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(64) {
+            let block = self.next_block();
+
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+
+        // Fast key erasure.
+        let block = self.next_block();
+        self.key.copy_from_slice(&block[..8]);
+    }
+}
+
+struct Random {
+    pool: EntropyPool,
+    crng: Crng,
+}
+
+impl Random {
+    /// Reseeds the CSPRNG if the pool has enough entropy and the last reseed is old
+    /// enough. Returns whether it was reseeded.
+    fn try_reseed(&mut self, now: u64) -> bool {
+        let due = !is_seeded() || now >= self.crng.reseeded_at + RESEED_INTERVAL;
+
+        if self.pool.bits < SEED_BITS || !due {
+            return false;
+        }
+
+        let seed = self.pool.extract();
+        self.crng.reseed(&seed);
+
+        true
+    }
+}
+
+static RANDOM: Mutex<Random> = Mutex::new(Random {
+    pool: EntropyPool::new(),
+    crng: Crng::new(),
+});
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEEDED_WQ: WaitQueue = WaitQueue::new();
+
+/// A pool the interrupt timings of a CPU are mixed into. It is only accessed by its CPU
+/// with the interrupts disabled, so it needs no lock.
+struct FastPool {
+    words: [AtomicU64; 2],
+    count: AtomicUsize,
+}
+
+impl FastPool {
+    const fn new() -> Self {
+        Self {
+            words: [AtomicU64::new(0), AtomicU64::new(0)],
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Mixes the sample into the pool and returns the amount of samples in it.
+    fn mix(&self, sample: u64) -> usize {
+        let a = self.words[0].load(Ordering::Relaxed);
+        let b = self.words[1].load(Ordering::Relaxed);
+
+        let a = (a ^ sample).rotate_left(13).wrapping_add(b);
+        let b = b.rotate_left(29) ^ a;
+
+        self.words[0].store(a, Ordering::Relaxed);
+        self.words[1].store(b, Ordering::Relaxed);
+
+        self.count.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+const FAST_POOL_EMPTY: FastPool = FastPool::new();
+static FAST_POOLS: [FastPool; MAX_CPUS] = [FAST_POOL_EMPTY; MAX_CPUS];
+
+/// The health tests of a noise source, which fail if it is stuck or biased.
+#[derive(Default)]
+struct HealthTests {
+    /// The last sample and the amount of times in a row it was seen.
+    last: u64,
+    repeated: usize,
+    /// The first sample of the window of the adaptive proportion test, the amount of
+    /// times it was seen in the window and the amount of samples in the window.
+    first: u64,
+    seen: usize,
+    window: usize,
+    failed: bool,
+}
+
+impl HealthTests {
+    fn feed(&mut self, sample: u64) {
+        // The repetition count test.
+        if self.repeated != 0 && sample == self.last {
+            self.repeated += 1;
+        } else {
+            self.last = sample;
+            self.repeated = 1;
+        }
+
+        // The adaptive proportion test.
+        if self.window == 0 {
+            self.first = sample;
+            self.seen = 1;
+        } else if sample == self.first {
+            self.seen += 1;
+        }
+
+        self.window = (self.window + 1) % PROPORTION_WINDOW;
+        self.failed |= self.repeated >= REPETITION_CUTOFF || self.seen >= PROPORTION_CUTOFF;
+    }
+}
+
+/// Returns whether the CSPRNG was seeded with enough entropy.
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::SeqCst)
+}
+
+/// Returns the value of a fast-running counter, whose low bits are used as timing
+/// entropy.
+fn cycle_counter() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    let counter = crate::arch::io::rdtsc();
+    #[cfg(not(target_arch = "x86_64"))]
+    let counter = timer::now();
+
+    counter
+}
+
+/// Seeds the CSPRNG if it is not seeded yet and the pool was credited with enough
+/// entropy.
+fn seed_if_ready(mut random: MutexGuard<Random>) {
+    if !is_seeded() && random.try_reseed(timer::now()) {
+        core::mem::drop(random);
+
+        SEEDED.store(true, Ordering::SeqCst);
+        SEEDED_WQ.wake_all();
+
+        log::debug!("random: crng seeded");
+    }
+}
+
+/// Mixes the sample into the entropy pool and credits it with `bits` bits of entropy.
+pub fn add_entropy(sample: u64, bits: usize) {
+    let mut random = RANDOM.lock_irq();
+    random.pool.mix(sample, bits);
+
+    seed_if_ready(random);
+}
+
+/// Mixes the timing of an interrupt into the fast pool of the current CPU. Called on
+/// every interrupt, with the interrupts disabled.
+pub fn add_interrupt_entropy(vector: u64) {
+    let cpu = tls::get_cpuid();
+
+    if cpu >= MAX_CPUS {
+        return;
+    }
+
+    let fast = &FAST_POOLS[cpu];
+
+    if fast.mix(cycle_counter() ^ (vector << 56)) < FAST_POOL_SPILL {
+        return;
+    }
+
+    // Only the low bits of the counter are unpredictable, so the spilled interrupts are
+    // only credited with one bit. If the entropy pool is busy, the next interrupt tries
+    // again.
+    if let Some(mut random) = RANDOM.try_lock() {
+        random.pool.mix(fast.words[0].load(Ordering::Relaxed), 0);
+        random.pool.mix(fast.words[1].load(Ordering::Relaxed), 1);
+
+        fast.count.store(0, Ordering::Relaxed);
+        seed_if_ready(random);
+    }
+}
+
+/// Mixes the data into the entropy pool without crediting it, as done when writing to
+/// `/dev/urandom`.
+pub fn add_data(data: &[u8]) {
+    for chunk in data.chunks(8) {
+        let mut sample = [0; 8];
+        sample[..chunk.len()].copy_from_slice(chunk);
+
+        RANDOM.lock_irq().pool.mix(u64::from_ne_bytes(sample), 0);
+    }
+}
+
+/// Fills the buffer with random bytes, whether the CSPRNG was seeded or not.
+pub fn fill_bytes(buffer: &mut [u8]) {
+    for chunk in buffer.chunks_mut(CHUNK_SIZE) {
+        let mut random = RANDOM.lock_irq();
+
+        random.try_reseed(timer::now());
+        random.crng.fill_bytes(chunk);
+    }
+}
+
+/// Fills the buffer with random bytes, waiting for the CSPRNG to be seeded first.
+pub fn fill_bytes_blocking(buffer: &mut [u8]) -> SignalResult<()> {
+    // The guard is dropped right away, as the buffer is filled by chunks.
+    core::mem::drop(SEEDED_WQ.wait_until(&RANDOM, |_| is_seeded())?);
+
+    fill_bytes(buffer);
+    Ok(())
+}
+
+pub fn get_u64() -> u64 {
+    let mut bytes = [0; 8];
+
+    fill_bytes(&mut bytes);
+    u64::from_ne_bytes(bytes)
+}
+
+/// Mixes the jitter of the cycle counter into the entropy pool. It is only credited if
+/// it passes the health tests, as the counter is deterministic under some emulators.
+fn add_jitter_entropy() {
+    let mut tests = HealthTests::default();
+    let mut previous = cycle_counter();
+    let mut value = 0u64;
+
+    for i in 0..SEED_BITS * JITTER_SAMPLES_PER_BIT {
+        // The same work is timed every time, so the time taken only varies with the state
+        // of the caches, the pipeline and the interrupts.
+        for _ in 0..16 {
+            value = core::hint::black_box(value.rotate_left(5) ^ i as u64);
+        }
+
+        let now = cycle_counter();
+        let delta = now.wrapping_sub(previous);
+
+        tests.feed(delta);
+        add_entropy(delta ^ value, 0);
+
+        previous = now;
+    }
+
+    if tests.failed {
+        log::warn!("random: the cycle counter jitter failed the health tests");
+        return;
+    }
+
+    let mut random = RANDOM.lock_irq();
+    random.pool.mix(value, SEED_BITS);
+
+    seed_if_ready(random);
+}
+
+/// Collects the initial entropy: the hardware random number generator when available,
+/// the jitter of the cycle counter and the realtime clock (which is not credited).
+pub fn init() {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::io;
+
+        for _ in 0..8 {
+            if let Some(value) = io::rdseed64() {
+                add_entropy(value, 64);
+            } else if let Some(value) = io::rdrand64() {
+                // The output of `rdrand` is whitened by a DRBG, so it is only partially
+                // credited.
+                add_entropy(value, 32);
+            }
+        }
+    }
+
+    add_jitter_entropy();
+
+    let clock = crate::arch::time::get_realtime_clock();
+    add_entropy(timer::timespec_to_nanoseconds(&clock), 0);
+
+    log::info!("random: seeded={}", is_seeded());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chacha20_block_test_vector() {
+        // RFC 7539, section 2.3.2.
+        let mut key = [0; 8];
+
+        for (i, word) in key.iter_mut().enumerate() {
+            let base = i as u32 * 4;
+            *word =
+                u32::from_le_bytes([base as u8, base as u8 + 1, base as u8 + 2, base as u8 + 3]);
+        }
+
+        let block = chacha20_block(&key, &[1, 0x09000000, 0x4a000000, 0]);
+
+        assert_eq!(block[..4], [0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3]);
+        assert_eq!(block[15], 0x4e3c50a2);
+    }
+
+    #[test]
+    fn jitter_health_tests() {
+        let mut stuck = HealthTests::default();
+        (0..REPETITION_CUTOFF).for_each(|_| stuck.feed(7));
+        assert!(stuck.failed);
+
+        // Mostly repeated samples pass the repetition count test but not the adaptive
+        // proportion test.
+        let mut biased = HealthTests::default();
+        (0..PROPORTION_WINDOW as u64).for_each(|i| biased.feed(if i % 10 == 9 { i } else { 7 }));
+        assert!(biased.failed);
+
+        let mut healthy = HealthTests::default();
+        (0..PROPORTION_WINDOW as u64 * 4).for_each(|i| healthy.feed(i % 13));
+        assert!(!healthy.failed);
+    }
+}
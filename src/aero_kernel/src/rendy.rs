@@ -23,8 +23,8 @@ use core::fmt;
 use core::u8;
 
 use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
 
-use limine::LimineFramebuffer;
 use spin::Once;
 
 use crate::cmdline::CommandLine;
@@ -33,8 +33,8 @@ use crate::mem::paging::align_up;
 
 use crate::utils::sync::Mutex;
 
-static FONT: &[[u8; FONT_HEIGHT]; FONT_GLYPHS] =
-    unsafe { &core::mem::transmute(*include_bytes!("../../font.bin")) };
+/// The built-in 8x16 VGA font, used when no font was provided on the kernel command line.
+static BUILTIN_FONT: &[u8; BUILTIN_FONT_SIZE] = include_bytes!("../../font.bin");
 
 // This is an example of how the rendered screen will look like:
 //
@@ -50,20 +50,36 @@ static FONT: &[[u8; FONT_HEIGHT]; FONT_GLYPHS] =
 // -----------------------------------------------------|
 // ```
 
-const FONT_WIDTH: usize = 8;
-const FONT_HEIGHT: usize = 16;
-const FONT_GLYPHS: usize = 256;
+const BUILTIN_FONT_WIDTH: usize = 8;
+const BUILTIN_FONT_HEIGHT: usize = 16;
+const BUILTIN_FONT_GLYPHS: usize = 256;
+const BUILTIN_FONT_SIZE: usize = BUILTIN_FONT_HEIGHT * BUILTIN_FONT_GLYPHS;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_MODESEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_STARTSEQ: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_STARTSEQ: u8 = 0xfe;
 
 const DEFAULT_MARGIN: usize = 64 / 2;
 const TAB_SIZE: usize = 4;
 
+/// The maximum amount of lines that are kept in the scrollback buffer.
+const SCROLLBACK_LINES: usize = 1000;
+
 /// The amount of VGA font glyphs.
 
 const MARGIN_GRADIENT: usize = 4;
 const DWORD_SIZE: usize = core::mem::size_of::<u32>();
 
-const DEFAULT_TEXT_BACKGROUND: u32 = u32::MAX;
-const DEFAULT_TEXT_FOREGROUND: u32 = 0xaaaaaa;
+pub const DEFAULT_TEXT_BACKGROUND: u32 = u32::MAX;
+pub const DEFAULT_TEXT_FOREGROUND: u32 = 0xaaaaaa;
 
 pub const DEFAULT_THEME_BACKGROUND: u32 = 0x50000000;
 
@@ -200,6 +216,228 @@ fn parse_bmp_image(data: &[u8]) -> Image {
     }
 }
 
+#[derive(Debug)]
+enum FontError {
+    InvalidMagic,
+    Truncated,
+    /// The font has no glyphs, or its glyphs have a zero width or height.
+    InvalidSize,
+}
+
+#[repr(C, packed)]
+struct Psf1Header {
+    magic: [u8; 2],
+    mode: u8,
+    charsize: u8,
+}
+
+#[repr(C, packed)]
+struct Psf2Header {
+    magic: [u8; 4],
+    version: u32,
+    headersize: u32,
+    flags: u32,
+    length: u32,
+    charsize: u32,
+    height: u32,
+    width: u32,
+}
+
+/// A bitmap console font. Each glyph is `height` rows of `bytes_per_row` bytes, with the
+/// most significant bit being the leftmost pixel.
+struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    glyph_size: usize,
+    bytes_per_row: usize,
+
+    width: usize,
+    height: usize,
+
+    /// Maps unicode code points to glyph indices. If the font does not have a unicode
+    /// table, the code point is used as the glyph index.
+    unicode: BTreeMap<char, usize>,
+}
+
+impl Font {
+    fn builtin() -> Self {
+        Self {
+            glyphs: BUILTIN_FONT,
+            // Only the ASCII half of the code page 437 glyphs matches the unicode code
+            // points.
+            glyph_count: 128,
+            glyph_size: BUILTIN_FONT_HEIGHT,
+            bytes_per_row: 1,
+
+            width: BUILTIN_FONT_WIDTH,
+            height: BUILTIN_FONT_HEIGHT,
+
+            unicode: BTreeMap::new(),
+        }
+    }
+
+    /// Parses a PC Screen Font (version 1 or 2).
+    ///
+    /// **Notes**: <https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html>
+    fn parse_psf(data: &'static [u8]) -> Result<Self, FontError> {
+        let font = if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)?
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)?
+        } else {
+            return Err(FontError::InvalidMagic);
+        };
+
+        if font.width == 0 || font.height == 0 || font.glyph_count == 0 {
+            return Err(FontError::InvalidSize);
+        }
+
+        Ok(font)
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.len() < core::mem::size_of::<Psf1Header>() {
+            return Err(FontError::Truncated);
+        }
+
+        let header = unsafe { &*(data.as_ptr() as *const Psf1Header) };
+
+        let glyph_count = if header.mode & PSF1_MODE512 != 0 {
+            512
+        } else {
+            256
+        };
+
+        let glyph_size = header.charsize as usize;
+        let start = core::mem::size_of::<Psf1Header>();
+        let end = start + glyph_count * glyph_size;
+
+        let glyphs = data.get(start..end).ok_or(FontError::Truncated)?;
+        let mut unicode = BTreeMap::new();
+
+        if header.mode & (PSF1_MODEHASTAB | PSF1_MODESEQ) != 0 {
+            // The table contains a list of little-endian UCS-2 code points for each glyph,
+            // terminated by `PSF1_SEPARATOR`. The sequences (starting with `PSF1_STARTSEQ`)
+            // are skipped as combining characters are not supported.
+            let mut entries = data[end..]
+                .chunks_exact(2)
+                .map(|e| u16::from_le_bytes([e[0], e[1]]));
+
+            for glyph in 0..glyph_count {
+                let mut in_sequence = false;
+
+                for entry in entries.by_ref() {
+                    match entry {
+                        PSF1_SEPARATOR => break,
+                        PSF1_STARTSEQ => in_sequence = true,
+
+                        _ if !in_sequence => {
+                            if let Some(char) = char::from_u32(entry as u32) {
+                                unicode.entry(char).or_insert(glyph);
+                            }
+                        }
+
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            glyphs,
+            glyph_count,
+            glyph_size,
+            bytes_per_row: 1,
+
+            width: 8,
+            height: glyph_size,
+
+            unicode,
+        })
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.len() < core::mem::size_of::<Psf2Header>() {
+            return Err(FontError::Truncated);
+        }
+
+        let header = unsafe { &*(data.as_ptr() as *const Psf2Header) };
+
+        let glyph_count = header.length as usize;
+        let glyph_size = header.charsize as usize;
+        let width = header.width as usize;
+        let height = header.height as usize;
+        let bytes_per_row = (width + 7) / 8;
+
+        if glyph_size < bytes_per_row * height {
+            return Err(FontError::Truncated);
+        }
+
+        let start = header.headersize as usize;
+        let end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(start))
+            .ok_or(FontError::Truncated)?;
+
+        let glyphs = data.get(start..end).ok_or(FontError::Truncated)?;
+        let mut unicode = BTreeMap::new();
+
+        if header.flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            // The table contains a list of UTF-8 encoded characters for each glyph,
+            // terminated by `PSF2_SEPARATOR`. The sequences (starting with `PSF2_STARTSEQ`)
+            // are skipped as combining characters are not supported.
+            let mut table = data[end..].split(|byte| *byte == PSF2_SEPARATOR);
+
+            for glyph in 0..glyph_count {
+                let entry = match table.next() {
+                    Some(entry) => entry,
+                    None => break,
+                };
+
+                let singles = entry
+                    .split(|byte| *byte == PSF2_STARTSEQ)
+                    .next()
+                    .unwrap_or(&[]);
+
+                if let Ok(singles) = core::str::from_utf8(singles) {
+                    for char in singles.chars() {
+                        unicode.entry(char).or_insert(glyph);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            glyphs,
+            glyph_count,
+            glyph_size,
+            bytes_per_row,
+
+            width,
+            height,
+
+            unicode,
+        })
+    }
+
+    /// Returns the bitmap of the glyph used to render `char`. Characters that are not
+    /// present in the font are rendered as `?`.
+    fn glyph(&self, char: char) -> &[u8] {
+        let lookup = |char: char| {
+            if self.unicode.is_empty() {
+                Some(char as usize).filter(|index| *index < self.glyph_count)
+            } else {
+                self.unicode.get(&char).copied()
+            }
+        };
+
+        let index = lookup(char).or_else(|| lookup('?')).unwrap_or(0);
+        let start = index * self.glyph_size;
+
+        &self.glyphs[start..start + self.glyph_size]
+    }
+}
+
 pub struct DebugRendy<'this> {
     /// The raw framebuffer pointer queried from the BIOS or UEFI firmware represented
     /// as a [u8] slice.
@@ -217,6 +455,7 @@ pub struct DebugRendy<'this> {
 
     color: ColorCode,
     theme_background: u32,
+    font: Font,
 
     queue: Box<[QueueCharacter]>,
     grid: Box<[Character]>,
@@ -225,6 +464,13 @@ pub struct DebugRendy<'this> {
 
     queue_cursor: usize,
 
+    /// The lines that were scrolled off the top of the screen, oldest first.
+    scrollback: VecDeque<Box<[Character]>>,
+    /// The amount of lines the view is scrolled back by. The live screen is shown when
+    /// this is zero.
+    view_offset: usize,
+    saved_position: (usize, usize),
+
     offset_x: usize,
     offset_y: usize,
 
@@ -239,11 +485,30 @@ impl<'this> DebugRendy<'this> {
         let width = info.horizontal_resolution;
         let height = info.vertical_resolution;
 
-        let offset_x = DEFAULT_MARGIN + ((width - DEFAULT_MARGIN * 2) % FONT_WIDTH) / 2;
-        let offset_y = DEFAULT_MARGIN + ((height - DEFAULT_MARGIN * 2) % FONT_HEIGHT) / 2;
+        let font = cmdline
+            .font
+            .and_then(|data| {
+                Font::parse_psf(data)
+                    .map_err(|err| log::warn!("rendy: failed to load font ({:?})", err))
+                    .ok()
+            })
+            .filter(|font| {
+                let fits = font.width <= width - DEFAULT_MARGIN * 2
+                    && font.height <= height - DEFAULT_MARGIN * 2;
+
+                if !fits {
+                    log::warn!("rendy: font is larger than the screen");
+                }
+
+                fits
+            })
+            .unwrap_or_else(Font::builtin);
 
-        let cols = (width - DEFAULT_MARGIN * 2) / FONT_WIDTH;
-        let rows = (height - DEFAULT_MARGIN * 2) / FONT_HEIGHT;
+        let offset_x = DEFAULT_MARGIN + ((width - DEFAULT_MARGIN * 2) % font.width) / 2;
+        let offset_y = DEFAULT_MARGIN + ((height - DEFAULT_MARGIN * 2) % font.height) / 2;
+
+        let cols = (width - DEFAULT_MARGIN * 2) / font.width;
+        let rows = (height - DEFAULT_MARGIN * 2) / font.height;
 
         let grid_size = rows * cols * core::mem::size_of::<Character>();
         let grid = mem::alloc_boxed_buffer::<Character>(grid_size);
@@ -272,6 +537,7 @@ impl<'this> DebugRendy<'this> {
 
             theme_background: cmdline.theme_background,
             color: ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND),
+            font,
 
             queue,
             grid,
@@ -280,6 +546,10 @@ impl<'this> DebugRendy<'this> {
 
             queue_cursor: 0,
 
+            scrollback: VecDeque::new(),
+            view_offset: 0,
+            saved_position: (0, 0),
+
             offset_x,
             offset_y,
 
@@ -420,11 +690,11 @@ impl<'this> DebugRendy<'this> {
         let height = self.info.vertical_resolution;
 
         if let Some(image) = image {
-            let frame_width = width / 2 - (FONT_WIDTH * self.cols) / 2;
-            let frame_height = height / 2 - (FONT_HEIGHT * self.rows) / 2;
+            let frame_width = width / 2 - (self.font.width * self.cols) / 2;
+            let frame_height = height / 2 - (self.font.height * self.rows) / 2;
 
-            let frame_width_end = frame_width + FONT_WIDTH * self.cols;
-            let frame_height_end = frame_height + FONT_HEIGHT * self.rows;
+            let frame_width_end = frame_width + self.font.width * self.cols;
+            let frame_height_end = frame_height + self.font.height * self.rows;
 
             let fheight = frame_height - MARGIN_GRADIENT;
             let fheight_end = frame_height_end + MARGIN_GRADIENT;
@@ -551,21 +821,18 @@ impl<'this> DebugRendy<'this> {
     }
 
     fn plot_char(&mut self, x: usize, y: usize, char: Character) {
-        let ch = match char.char {
-            ch if ch.is_ascii() => ch,
-            _ => '?' as char,
-        };
-
         if x >= self.cols || y >= self.rows {
             return;
         }
 
-        let x = self.offset_x + x * FONT_WIDTH;
-        let y = self.offset_y + y * FONT_HEIGHT;
-        let glyph = &FONT[ch as usize];
+        let x = self.offset_x + x * self.font.width;
+        let y = self.offset_y + y * self.font.height;
+
+        let glyph = self.font.glyph(char.char);
+        let bytes_per_row = self.font.bytes_per_row;
 
         // naming: fx, fy for font coordinates and gx, gy for glyph coordinates
-        for gy in 0..FONT_HEIGHT {
+        for gy in 0..self.font.height {
             let fb_line = unsafe {
                 self.buffer
                     .as_mut_ptr()
@@ -578,8 +845,8 @@ impl<'this> DebugRendy<'this> {
                     .add(x + (y + gy) * self.info.horizontal_resolution)
             };
 
-            for gx in 0..FONT_WIDTH {
-                let draw = glyph[gy] & (1 << (FONT_WIDTH - gx - 1)) != 0;
+            for gx in 0..self.font.width {
+                let draw = glyph[gy * bytes_per_row + gx / 8] & (0x80 >> (gx % 8)) != 0;
                 let color = if draw {
                     char.fg
                 } else if char.bg == u32::MAX {
@@ -596,6 +863,9 @@ impl<'this> DebugRendy<'this> {
     }
 
     fn double_buffer_flush(&mut self) {
+        // Any output returns the view to the live screen.
+        self.reset_view();
+
         if self.cursor_visibility {
             self.draw_cursor();
         }
@@ -682,17 +952,19 @@ impl<'this> DebugRendy<'this> {
     }
 
     fn scroll(&mut self) {
-        for i in self.cols..self.rows * self.cols {
-            let queue = self.map[i];
-            let res;
+        // Save the line that is scrolled off the screen in the scrollback buffer.
+        let line = (0..self.cols)
+            .map(|i| self.cell(i))
+            .collect::<Box<[Character]>>();
 
-            if let Some(char) = queue {
-                unsafe {
-                    res = (*char).char;
-                }
-            } else {
-                res = self.grid[i];
-            }
+        if self.scrollback.len() == SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        }
+
+        self.scrollback.push_back(line);
+
+        for i in self.cols..self.rows * self.cols {
+            let res = self.cell(i);
 
             self.push_to_queue(
                 &res,
@@ -738,6 +1010,82 @@ impl<'this> DebugRendy<'this> {
         self.y_pos = y;
         self.double_buffer_flush();
     }
+
+    /// Returns the character at the provided cell index, including the pending
+    /// changes that have not been flushed yet.
+    fn cell(&self, i: usize) -> Character {
+        match self.map[i] {
+            Some(char) => unsafe { (*char).char },
+            None => self.grid[i],
+        }
+    }
+
+    /// Erases the cells in the range `start..end` (cell indices) without moving the
+    /// cursor.
+    fn erase(&mut self, start: usize, end: usize) {
+        let empty = Character {
+            char: ' ',
+            fg: self.color.get_foreground(),
+            bg: self.color.get_background(),
+        };
+
+        for i in start..core::cmp::min(end, self.rows * self.cols) {
+            self.push_to_queue(&empty, i % self.cols, i / self.cols);
+        }
+
+        if self.auto_flush {
+            self.double_buffer_flush();
+        }
+    }
+
+    /// Scrolls the view `lines` lines back into the scrollback buffer (or forward if
+    /// `lines` is negative).
+    fn scroll_view(&mut self, lines: isize) {
+        if self.view_offset == 0 {
+            // Make sure the grid is up to date before it is drawn.
+            self.double_buffer_flush();
+        }
+
+        let offset = (self.view_offset as isize + lines).clamp(0, self.scrollback.len() as isize);
+
+        if offset as usize != self.view_offset {
+            self.view_offset = offset as usize;
+            self.redraw();
+        }
+    }
+
+    /// Returns to the live screen if the view is scrolled back.
+    fn reset_view(&mut self) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+    }
+
+    /// Redraws the whole screen from the scrollback buffer and the grid, depending
+    /// on the current view offset.
+    fn redraw(&mut self) {
+        let first = self.scrollback.len() - self.view_offset;
+
+        for y in 0..self.rows {
+            for x in 0..self.cols {
+                let line = first + y;
+
+                let char = if line < self.scrollback.len() {
+                    self.scrollback[line][x]
+                } else {
+                    self.grid[(line - self.scrollback.len()) * self.cols + x]
+                };
+
+                self.plot_char(x, y, char);
+            }
+        }
+
+        // The cursor is only shown on the live screen.
+        if self.view_offset == 0 && self.cursor_visibility {
+            self.draw_cursor();
+        }
+    }
 }
 
 impl<'this> fmt::Write for DebugRendy<'this> {
@@ -823,6 +1171,46 @@ pub fn reset_default() {
     set_text_color(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND)
 }
 
+/// Erases the cells in the range `start..end`, where the cells are indexed as `y * cols + x`.
+/// The cursor position does not change.
+pub fn erase(start: usize, end: usize) {
+    DEBUG_RENDY.get().map(|l| l.lock_irq().erase(start, end));
+}
+
+/// Clears the scrollback buffer.
+pub fn clear_scrollback() {
+    DEBUG_RENDY.get().map(|l| {
+        let mut this = l.lock_irq();
+
+        this.reset_view();
+        this.scrollback.clear();
+    });
+}
+
+/// Scrolls the view `lines` lines back into the scrollback buffer, or forward if `lines` is
+/// negative. Any output returns the view to the live screen.
+pub fn scroll_view(lines: isize) {
+    DEBUG_RENDY.get().map(|l| l.lock_irq().scroll_view(lines));
+}
+
+/// Saves the current cursor position, to be restored with [`restore_cursor_position`].
+pub fn save_cursor_position() {
+    DEBUG_RENDY.get().map(|l| {
+        let mut this = l.lock_irq();
+        this.saved_position = (this.x_pos, this.y_pos);
+    });
+}
+
+/// Restores the cursor position saved by [`save_cursor_position`].
+pub fn restore_cursor_position() {
+    DEBUG_RENDY.get().map(|l| {
+        let mut this = l.lock_irq();
+        let (x, y) = this.saved_position;
+
+        this.set_cursor_position(x, y);
+    });
+}
+
 /// Returns the terminal's resolution in the form of a `(horizontal_resolution, vertical_resolution)`
 /// tuple.
 ///
@@ -936,33 +1324,41 @@ pub unsafe fn force_unlock() {
     DEBUG_RENDY.get().map(|l| l.force_unlock());
 }
 
-pub fn init(framebuffer_tag: &LimineFramebuffer, cmdline: &CommandLine) {
-    let framebuffer_info = RendyInfo {
-        byte_len: framebuffer_tag.size(),
-        bits_per_pixel: framebuffer_tag.bpp as usize,
-        horizontal_resolution: framebuffer_tag.width as usize,
-        vertical_resolution: framebuffer_tag.height as usize,
-        pixel_format: PixelFormat::BGR,
-        stride: framebuffer_tag.pitch as usize,
-
-        red_mask_shift: framebuffer_tag.red_mask_shift,
-        red_mask_size: framebuffer_tag.red_mask_size,
-
-        green_mask_shift: framebuffer_tag.green_mask_shift,
-        green_mask_size: framebuffer_tag.green_mask_size,
+/// Initializes the debug renderer on the framebuffer provided by the bootloader.
+pub fn init(framebuffer: &'static mut [u32], framebuffer_info: RendyInfo, cmdline: &CommandLine) {
+    let rendy = DebugRendy::new(framebuffer, framebuffer_info, cmdline);
 
-        blue_mask_shift: framebuffer_tag.blue_mask_shift,
-        blue_mask_size: framebuffer_tag.blue_mask_size,
-    };
+    DEBUG_RENDY.call_once(|| Mutex::new(rendy));
+}
 
-    let framebuffer = unsafe {
-        core::slice::from_raw_parts_mut::<u32>(
-            framebuffer_tag.address.as_ptr().unwrap() as *mut u32,
-            framebuffer_info.byte_len,
-        )
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn psf_zero_size() {
+        // A PSF2 font with one 0x16 glyph.
+        static FONT: [u8; 48] = {
+            let mut font = [0; 48];
+            let fields = [0x864ab572u32, 0, 32, 0, 1, 16, 16, 0];
+
+            let mut i = 0;
+            while i < fields.len() {
+                let bytes = fields[i].to_le_bytes();
+
+                font[i * 4] = bytes[0];
+                font[i * 4 + 1] = bytes[1];
+                font[i * 4 + 2] = bytes[2];
+                font[i * 4 + 3] = bytes[3];
+                i += 1;
+            }
 
-    let rendy = DebugRendy::new(framebuffer, framebuffer_info, cmdline);
+            font
+        };
 
-    DEBUG_RENDY.call_once(|| Mutex::new(rendy));
+        assert!(matches!(
+            Font::parse_psf(&FONT),
+            Err(FontError::InvalidSize)
+        ));
+    }
 }
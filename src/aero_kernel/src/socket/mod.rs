@@ -19,19 +19,24 @@
 
 use aero_syscall::*;
 
+use crate::fs::{self, FileSystemError};
 use crate::mem::paging::VirtAddr;
+use crate::net::{Ipv4Addr, NetError};
+use crate::utils::validate_ptr;
 
 #[derive(Debug)]
 pub enum SocketAddr<'a> {
     Unix(&'a SocketAddrUnix),
     INet(&'a SocketAddrInet),
+    Packet(&'a SocketAddrPacket),
 }
 
 impl<'a> SocketAddr<'a> {
     pub fn from_family(address: VirtAddr, family: u32) -> Option<Self> {
         match family {
-            AF_UNIX => Some(SocketAddr::Unix(address.read_mut::<SocketAddrUnix>()?)),
-            AF_INET => Some(SocketAddr::INet(address.read_mut::<SocketAddrInet>()?)),
+            AF_UNIX => Some(SocketAddr::Unix(validate_ptr(address.as_ptr())?)),
+            AF_INET => Some(SocketAddr::INet(validate_ptr(address.as_ptr())?)),
+            AF_PACKET => Some(SocketAddr::Packet(validate_ptr(address.as_ptr())?)),
 
             _ => None,
         }
@@ -45,6 +50,203 @@ impl<'a> SocketAddr<'a> {
             _ => None,
         }
     }
+
+    /// Converts the socket address into an IPv4 address and a port. Returns [`None`] if
+    /// the address is not an internet socket address.
+    pub fn as_inet(&self) -> Option<(Ipv4Addr, u16)> {
+        match self {
+            SocketAddr::INet(address) => Some(inet_from_sockaddr(address)),
+            _ => None,
+        }
+    }
+
+    /// Converts the socket address into a packet socket address. Returns [`None`] if
+    /// the address is not a packet socket address.
+    pub fn as_packet(&self) -> Option<&'a SocketAddrPacket> {
+        match self {
+            SocketAddr::Packet(address) => Some(address),
+            _ => None,
+        }
+    }
+}
+
+pub fn inet_from_sockaddr(address: &SocketAddrInet) -> (Ipv4Addr, u16) {
+    (Ipv4Addr(address.address), u16::from_be_bytes(address.port))
 }
 
+pub fn inet_to_sockaddr(address: Ipv4Addr, port: u16) -> SocketAddrInet {
+    SocketAddrInet {
+        family: AF_INET,
+        port: port.to_be_bytes(),
+        address: address.0,
+        padding: [0; 8],
+    }
+}
+
+/// An owned socket address, as returned by `getsockname` and `getpeername`.
+pub enum SocketName {
+    Unix(SocketAddrUnix),
+    INet(SocketAddrInet),
+    Packet(SocketAddrPacket),
+}
+
+impl SocketName {
+    /// Writes the address to the user buffer and its size to `length`.
+    pub fn write_to(&self, address: VirtAddr, length: &mut u32) -> fs::Result<()> {
+        match self {
+            SocketName::Unix(name) => {
+                *address
+                    .read_mut::<SocketAddrUnix>()
+                    .ok_or(FileSystemError::NotSupported)? = name.clone();
+
+                *length = core::mem::size_of::<SocketAddrUnix>() as u32;
+            }
+
+            SocketName::INet(name) => {
+                *address
+                    .read_mut::<SocketAddrInet>()
+                    .ok_or(FileSystemError::NotSupported)? = name.clone();
+
+                *length = core::mem::size_of::<SocketAddrInet>() as u32;
+            }
+
+            SocketName::Packet(name) => {
+                *address
+                    .read_mut::<SocketAddrPacket>()
+                    .ok_or(FileSystemError::NotSupported)? = name.clone();
+
+                *length = core::mem::size_of::<SocketAddrPacket>() as u32;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the value of an integer (or boolean) socket option.
+pub fn read_int_option(value: &[u8]) -> fs::Result<i32> {
+    value
+        .get(..4)
+        .and_then(|value| value.try_into().ok())
+        .map(i32::from_ne_bytes)
+        .ok_or(FileSystemError::InvalidPath)
+}
+
+/// Writes the value of an integer (or boolean) socket option. Returns the size of the
+/// option.
+pub fn write_int_option(value: &mut [u8], option: i32) -> fs::Result<usize> {
+    value
+        .get_mut(..4)
+        .ok_or(FileSystemError::InvalidPath)?
+        .copy_from_slice(&option.to_ne_bytes());
+
+    Ok(4)
+}
+
+/// The generic (`SOL_SOCKET`) options of a socket.
+pub struct SocketOptions {
+    socket_type: usize,
+    reuse_addr: bool,
+    keep_alive: bool,
+    broadcast: bool,
+    send_buffer: usize,
+    recv_buffer: usize,
+}
+
+impl SocketOptions {
+    pub fn new(socket_type: usize, send_buffer: usize, recv_buffer: usize) -> Self {
+        Self {
+            socket_type,
+            reuse_addr: false,
+            keep_alive: false,
+            broadcast: false,
+            send_buffer,
+            recv_buffer,
+        }
+    }
+
+    /// Returns whether datagrams may be sent to broadcast addresses (`SO_BROADCAST`).
+    pub fn broadcast(&self) -> bool {
+        self.broadcast
+    }
+
+    /// Reads a generic option. The options that depend on the state of the socket
+    /// (`SO_ERROR` and `SO_ACCEPTCONN`) are handled by the sockets, and read as zero
+    /// here.
+    pub fn get(&self, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        let option = match name {
+            SO_TYPE => self.socket_type as i32,
+            SO_REUSEADDR => self.reuse_addr as i32,
+            SO_KEEPALIVE => self.keep_alive as i32,
+            SO_BROADCAST => self.broadcast as i32,
+            SO_SNDBUF => self.send_buffer as i32,
+            SO_RCVBUF => self.recv_buffer as i32,
+            SO_ERROR | SO_ACCEPTCONN => 0,
+
+            _ => return Err(FileSystemError::InvalidOption),
+        };
+
+        write_int_option(value, option)
+    }
+
+    /// Sets a generic option. The buffer sizes are recorded but the sockets keep their
+    /// fixed size buffers.
+    pub fn set(&mut self, name: u32, value: &[u8]) -> fs::Result<()> {
+        let option = read_int_option(value)?;
+
+        match name {
+            SO_REUSEADDR => self.reuse_addr = option != 0,
+            SO_KEEPALIVE => self.keep_alive = option != 0,
+            SO_BROADCAST => self.broadcast = option != 0,
+            SO_SNDBUF => self.send_buffer = option.max(0) as usize,
+            SO_RCVBUF => self.recv_buffer = option.max(0) as usize,
+
+            _ => return Err(FileSystemError::InvalidOption),
+        }
+
+        Ok(())
+    }
+}
+
+impl From<NetError> for FileSystemError {
+    fn from(error: NetError) -> Self {
+        match error {
+            NetError::InterfaceDown | NetError::NoRoute => Self::NetworkUnreachable,
+            NetError::PacketTooBig => Self::MessageTooLong,
+            NetError::DeviceBusy | NetError::QueueFull => Self::WouldBlock,
+            NetError::InvalidPacket => Self::NotSupported,
+            NetError::AddressInUse => Self::AddressInUse,
+            NetError::TimedOut => Self::TimedOut,
+            NetError::Interrupted => Self::Interrupted,
+            NetError::Filtered => Self::NotPermitted,
+        }
+    }
+}
+
+pub mod packet;
+pub mod raw;
+pub mod tcp;
+pub mod udp;
 pub mod unix;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_options_get_set() {
+        let mut options = SocketOptions::new(SOCK_STREAM, 4096, 4096);
+        let mut value = [0; 4];
+
+        options.set(SO_REUSEADDR, &1i32.to_ne_bytes()).unwrap();
+
+        assert_eq!(options.get(SO_REUSEADDR, &mut value), Ok(4));
+        assert_eq!(i32::from_ne_bytes(value), 1);
+        assert_eq!(options.get(SO_TYPE, &mut value), Ok(4));
+        assert_eq!(i32::from_ne_bytes(value), SOCK_STREAM as i32);
+        assert_eq!(
+            options.set(u32::MAX, &value),
+            Err(FileSystemError::InvalidOption)
+        );
+    }
+}
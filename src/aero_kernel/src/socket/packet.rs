@@ -0,0 +1,329 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Packet sockets (`AF_PACKET`, `SOCK_RAW`). The socket receives a copy of the ethernet
+//! frames sent and received on its interface (or on all of the interfaces, if it is not
+//! bound) with its EtherType, or all of them for `ETH_P_ALL`. The sent data is the
+//! complete frame, which is transmitted as is.
+
+use aero_syscall::socket::MessageHeader;
+use aero_syscall::*;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::*;
+use crate::fs::FileSystemError;
+
+use crate::net::{self, ethernet};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{SocketAddr, SocketName, SocketOptions};
+
+/// The maximum amount of frames queued on a socket. The frames received while the queue
+/// is full are dropped.
+const MAX_QUEUED: usize = 256;
+
+/// A captured frame.
+pub struct Frame {
+    /// The index of the interface the frame was sent or received on.
+    pub ifindex: u32,
+    /// The type of the frame (one of the `PACKET_*` constants).
+    pub pkttype: u8,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// Returns the address of the sender of the frame, as reported by `recvmsg`.
+    fn source(&self) -> SocketAddrPacket {
+        let mut addr = [0; 8];
+        addr[..6].copy_from_slice(&self.data[6..12]);
+
+        SocketAddrPacket {
+            family: AF_PACKET,
+            protocol: [self.data[12], self.data[13]],
+            hatype: ARPHRD_ETHER,
+            ifindex: self.ifindex,
+            pkttype: self.pkttype,
+            halen: 6,
+            addr,
+        }
+    }
+}
+
+struct PacketSocketInner {
+    /// The EtherType of the captured frames, `ETH_P_ALL` for all of them or zero for
+    /// none of them.
+    protocol: u16,
+    /// The index of the bound interface, or zero for all of the interfaces.
+    ifindex: u32,
+}
+
+pub struct PacketSocket {
+    inner: Mutex<PacketSocketInner>,
+    options: Mutex<SocketOptions>,
+    queue: Mutex<VecDeque<Frame>>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+}
+
+impl PacketSocket {
+    /// Creates a packet socket capturing the frames with the provided EtherType (in host
+    /// byte order).
+    pub fn new(protocol: u16) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            inner: Mutex::new(PacketSocketInner {
+                protocol,
+                ifindex: 0,
+            }),
+            options: Mutex::new(SocketOptions::new(
+                SOCK_RAW,
+                u16::MAX as usize,
+                u16::MAX as usize,
+            )),
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+        });
+
+        net::raw::register_packet_socket(Arc::downgrade(&socket));
+        socket
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle.get().map_or(false, |handle| {
+            handle.flags.read().contains(OpenFlags::O_NONBLOCK)
+        })
+    }
+
+    /// Returns whether the socket captures the frames with the provided EtherType on the
+    /// provided interface.
+    pub fn accepts(&self, ifindex: u32, ether_type: u16) -> bool {
+        let inner = self.inner.lock_irq();
+
+        (inner.ifindex == 0 || inner.ifindex == ifindex)
+            && (inner.protocol == ETH_P_ALL || inner.protocol == ether_type)
+    }
+
+    /// Queues a captured frame. Called by the network interfaces.
+    pub fn deliver(&self, frame: Frame) {
+        let mut queue = self.queue.lock_irq();
+
+        if queue.len() >= MAX_QUEUED {
+            return;
+        }
+
+        queue.push_back(frame);
+        core::mem::drop(queue);
+
+        self.wq.wake_all();
+    }
+
+    fn recv_frame(&self, non_block: bool) -> fs::Result<Frame> {
+        if non_block && self.queue.lock_irq().is_empty() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut queue = self.wq.wait_until(&self.queue, |queue| !queue.is_empty())?;
+        Ok(queue.pop_front().unwrap())
+    }
+
+    /// Transmits the frame on the provided interface, or on the bound interface if zero.
+    fn send_frame(&self, ifindex: u32, frame: &[u8]) -> fs::Result<usize> {
+        let ifindex = match ifindex {
+            0 => self.inner.lock_irq().ifindex,
+            ifindex => ifindex,
+        };
+
+        if ifindex == 0 {
+            return Err(FileSystemError::DestinationRequired);
+        }
+
+        let interface = net::get_interface_by_index(ifindex).ok_or(FileSystemError::InvalidPath)?;
+
+        if frame.len() < ethernet::HEADER_SIZE {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        interface.transmit(frame)?;
+        Ok(frame.len())
+    }
+}
+
+impl INodeInterface for PacketSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::Socket,
+            size: 0,
+            children_len: 0,
+        })
+    }
+
+    fn open(&self, _flags: OpenFlags, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn bind(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        let address = address.as_packet().ok_or(FileSystemError::NotSupported)?;
+
+        if address.ifindex != 0 && net::get_interface_by_index(address.ifindex).is_none() {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        let mut inner = self.inner.lock_irq();
+        let protocol = u16::from_be_bytes(address.protocol);
+
+        // A zero protocol keeps the protocol the socket was created with.
+        if protocol != 0 {
+            inner.protocol = protocol;
+        }
+
+        inner.ifindex = address.ifindex;
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let frame = self.recv_frame(self.is_non_block())?;
+        let size = core::cmp::min(buffer.len(), frame.data.len());
+
+        // The rest of the frame is discarded.
+        buffer[..size].copy_from_slice(&frame.data[..size]);
+        Ok(size)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.send_frame(0, buffer)
+    }
+
+    fn recv(&self, header: &mut MessageHeader, non_block: bool) -> fs::Result<usize> {
+        let frame = self.recv_frame(non_block)?;
+
+        if let Some(name) = header.name_mut::<SocketAddrPacket>() {
+            *name = frame.source();
+        }
+
+        let mut data = frame.data.as_slice();
+        let mut copied = 0;
+
+        for iovec in header.iovecs_mut() {
+            let buffer = iovec.as_mut_slice();
+            let size = core::cmp::min(buffer.len(), data.len());
+
+            buffer[..size].copy_from_slice(&data[..size]);
+            data = &data[size..];
+            copied += size;
+        }
+
+        Ok(copied)
+    }
+
+    fn send(&self, header: &MessageHeader, _non_block: bool) -> fs::Result<usize> {
+        let ifindex = header
+            .name::<SocketAddrPacket>()
+            .map_or(0, |name| name.ifindex);
+
+        let data = header
+            .iovecs()
+            .iter()
+            .flat_map(|iovec| iovec.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.send_frame(ifindex, &data)
+    }
+
+    fn get_option(&self, level: u32, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().get(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn set_option(&self, level: u32, name: u32, value: &[u8]) -> fs::Result<()> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().set(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn local_name(&self) -> fs::Result<SocketName> {
+        let inner = self.inner.lock_irq();
+        let interface = net::get_interface_by_index(inner.ifindex);
+
+        let mut addr = [0; 8];
+
+        if let Some(interface) = interface.as_ref() {
+            addr[..6].copy_from_slice(&interface.mac_address().0);
+        }
+
+        Ok(SocketName::Packet(SocketAddrPacket {
+            family: AF_PACKET,
+            protocol: inner.protocol.to_be_bytes(),
+            hatype: ARPHRD_ETHER,
+            ifindex: inner.ifindex,
+            pkttype: PACKET_HOST,
+            halen: if interface.is_some() { 6 } else { 0 },
+            addr,
+        }))
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        table.map(|table| table.insert(&self.wq));
+
+        let mut events = PollFlags::OUT;
+
+        if !self.queue.lock_irq().is_empty() {
+            events.insert(PollFlags::IN);
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_frame_source() {
+        let mut data = alloc::vec![0xff; 6];
+
+        data.extend_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        data.extend_from_slice(&[0x08, 0x06]);
+
+        let frame = Frame {
+            ifindex: 1,
+            pkttype: PACKET_BROADCAST,
+            data,
+        };
+
+        let source = frame.source();
+
+        assert_eq!(u16::from_be_bytes(source.protocol), 0x0806);
+        assert_eq!(source.ifindex, 1);
+        assert_eq!(&source.addr[..6], &[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    }
+}
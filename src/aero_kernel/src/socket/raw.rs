@@ -0,0 +1,291 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Raw IPv4 sockets (`AF_INET`, `SOCK_RAW`). The sent data is the payload of the packet,
+//! the header is built by the kernel (`IP_HDRINCL` is not supported). The received
+//! packets include their header.
+
+use aero_syscall::socket::MessageHeader;
+use aero_syscall::*;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::*;
+use crate::fs::FileSystemError;
+
+use crate::net::ipv4::{self, IpProtocol};
+use crate::net::{self, Ipv4Addr};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{
+    inet_from_sockaddr, inet_to_sockaddr, read_int_option, write_int_option, SocketAddr,
+    SocketName, SocketOptions,
+};
+
+/// The maximum amount of packets queued on a socket. The packets received while the
+/// queue is full are dropped.
+const MAX_QUEUED: usize = 64;
+
+const DEFAULT_TTL: u8 = 64;
+
+/// A received packet, including its header.
+pub struct RawPacket {
+    pub src: Ipv4Addr,
+    pub data: Vec<u8>,
+}
+
+struct RawSocketInner {
+    /// The default destination, set by `connect`.
+    remote: Option<Ipv4Addr>,
+    /// The time to live of the sent packets (`IP_TTL`).
+    ttl: u8,
+}
+
+pub struct RawSocket {
+    protocol: IpProtocol,
+    inner: Mutex<RawSocketInner>,
+    options: Mutex<SocketOptions>,
+    queue: Mutex<VecDeque<RawPacket>>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+}
+
+impl RawSocket {
+    pub fn new(protocol: u8) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            protocol: protocol.into(),
+            inner: Mutex::new(RawSocketInner {
+                remote: None,
+                ttl: DEFAULT_TTL,
+            }),
+            options: Mutex::new(SocketOptions::new(
+                SOCK_RAW,
+                u16::MAX as usize,
+                u16::MAX as usize,
+            )),
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+        });
+
+        net::raw::register_raw_socket(Arc::downgrade(&socket));
+        socket
+    }
+
+    /// Returns the protocol of the packets sent and received by the socket.
+    pub fn protocol(&self) -> IpProtocol {
+        self.protocol
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle.get().map_or(false, |handle| {
+            handle.flags.read().contains(OpenFlags::O_NONBLOCK)
+        })
+    }
+
+    fn send_to(&self, dest: Option<Ipv4Addr>, data: &[u8]) -> fs::Result<usize> {
+        let inner = self.inner.lock_irq();
+        let dest = dest
+            .or(inner.remote)
+            .ok_or(FileSystemError::DestinationRequired)?;
+        let ttl = inner.ttl;
+
+        core::mem::drop(inner);
+
+        if dest == Ipv4Addr::BROADCAST && !self.options.lock_irq().broadcast() {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        if ipv4::HEADER_SIZE + data.len() > u16::MAX as usize {
+            return Err(FileSystemError::MessageTooLong);
+        }
+
+        ipv4::send_with_ttl(dest, self.protocol, ttl, data)?;
+        Ok(data.len())
+    }
+
+    fn recv_from(&self, non_block: bool) -> fs::Result<RawPacket> {
+        if non_block && self.queue.lock_irq().is_empty() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut queue = self.wq.wait_until(&self.queue, |queue| !queue.is_empty())?;
+        Ok(queue.pop_front().unwrap())
+    }
+
+    /// Queues a packet received with the protocol of the socket. Called by the IPv4
+    /// layer.
+    pub fn deliver(&self, packet: RawPacket) {
+        // A connected socket only receives the packets from its peer.
+        if let Some(remote) = self.inner.lock_irq().remote {
+            if remote != packet.src {
+                return;
+            }
+        }
+
+        let mut queue = self.queue.lock_irq();
+
+        if queue.len() >= MAX_QUEUED {
+            return;
+        }
+
+        queue.push_back(packet);
+        core::mem::drop(queue);
+
+        self.wq.wake_all();
+    }
+}
+
+impl INodeInterface for RawSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::Socket,
+            size: 0,
+            children_len: 0,
+        })
+    }
+
+    fn open(&self, _flags: OpenFlags, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn bind(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        // The socket receives on all of the interfaces, whatever the bound address.
+        address.as_inet().ok_or(FileSystemError::NotSupported)?;
+        Ok(())
+    }
+
+    fn connect(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        let (remote, _) = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+
+        self.inner.lock_irq().remote = Some(remote);
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let packet = self.recv_from(self.is_non_block())?;
+        let size = core::cmp::min(buffer.len(), packet.data.len());
+
+        // The rest of the packet is discarded.
+        buffer[..size].copy_from_slice(&packet.data[..size]);
+        Ok(size)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.send_to(None, buffer)
+    }
+
+    fn recv(&self, header: &mut MessageHeader, non_block: bool) -> fs::Result<usize> {
+        let packet = self.recv_from(non_block)?;
+
+        if let Some(name) = header.name_mut::<SocketAddrInet>() {
+            *name = inet_to_sockaddr(packet.src, 0);
+        }
+
+        let mut data = packet.data.as_slice();
+        let mut copied = 0;
+
+        for iovec in header.iovecs_mut() {
+            let buffer = iovec.as_mut_slice();
+            let size = core::cmp::min(buffer.len(), data.len());
+
+            buffer[..size].copy_from_slice(&data[..size]);
+            data = &data[size..];
+            copied += size;
+        }
+
+        Ok(copied)
+    }
+
+    fn send(&self, header: &MessageHeader, _non_block: bool) -> fs::Result<usize> {
+        let dest = header
+            .name::<SocketAddrInet>()
+            .map(|name| inet_from_sockaddr(name).0);
+
+        let data = header
+            .iovecs()
+            .iter()
+            .flat_map(|iovec| iovec.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.send_to(dest, &data)
+    }
+
+    fn get_option(&self, level: u32, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        match (level, name) {
+            (SOL_SOCKET, _) => self.options.lock_irq().get(name, value),
+            (IPPROTO_IP, IP_TTL) => write_int_option(value, self.inner.lock_irq().ttl as i32),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn set_option(&self, level: u32, name: u32, value: &[u8]) -> fs::Result<()> {
+        match (level, name) {
+            (SOL_SOCKET, _) => self.options.lock_irq().set(name, value),
+            (IPPROTO_IP, IP_TTL) => {
+                let ttl = match read_int_option(value)? {
+                    // The default time to live.
+                    -1 => DEFAULT_TTL,
+                    ttl @ 1..=255 => ttl as u8,
+                    _ => return Err(FileSystemError::InvalidPath),
+                };
+
+                self.inner.lock_irq().ttl = ttl;
+                Ok(())
+            }
+
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn local_name(&self) -> fs::Result<SocketName> {
+        Ok(SocketName::INet(inet_to_sockaddr(Ipv4Addr::UNSPECIFIED, 0)))
+    }
+
+    fn peer_name(&self) -> fs::Result<SocketName> {
+        let remote = self
+            .inner
+            .lock_irq()
+            .remote
+            .ok_or(FileSystemError::NotConnected)?;
+
+        Ok(SocketName::INet(inet_to_sockaddr(remote, 0)))
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        table.map(|table| table.insert(&self.wq));
+
+        let mut events = PollFlags::OUT;
+
+        if !self.queue.lock_irq().is_empty() {
+            events.insert(PollFlags::IN);
+        }
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,1081 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! TCP sockets and the connection state machine. The segments are demultiplexed by
+//! [`crate::net::tcp`].
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use aero_syscall::socket::MessageHeader;
+use aero_syscall::*;
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::*;
+use crate::fs::FileSystemError;
+
+use crate::mem::paging::VirtAddr;
+use crate::net::ipv4::Ipv4Header;
+use crate::net::tcp::{self, seq_le, seq_lt, ConnectionKey, TcpFlags, TcpSegment};
+use crate::net::Ipv4Addr;
+use crate::timer;
+use crate::utils::sync::{Mutex, MutexGuard, WaitQueue};
+
+use super::{
+    inet_to_sockaddr, read_int_option, write_int_option, SocketAddr, SocketName, SocketOptions,
+};
+
+const SEND_BUFFER_SIZE: usize = 64 * 1024;
+/// The size of the receive buffer. It is the largest window that can be advertised
+/// without the window scale option.
+const RECV_BUFFER_SIZE: usize = u16::MAX as usize;
+
+/// The segment size advertised to the peers, for an ethernet MTU.
+const LOCAL_MSS: u16 = 1460;
+/// The segment size assumed when the peer does not advertise one.
+const DEFAULT_MSS: usize = 536;
+
+/// The initial and maximum retransmission timeouts, in nanoseconds.
+const INITIAL_RTO: u64 = 1_000_000_000;
+const MAX_RTO: u64 = 60_000_000_000;
+/// The amount of retransmissions after which the connection times out.
+const MAX_RETRIES: usize = 8;
+
+/// The time spent in TIME-WAIT (twice the maximum segment lifetime) and in FIN-WAIT-2
+/// after the socket was closed, in nanoseconds.
+const TIME_WAIT: u64 = 60_000_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// The reason a connection was torn down.
+#[derive(Debug, Copy, Clone)]
+enum TcpError {
+    Refused,
+    Reset,
+    TimedOut,
+}
+
+impl From<TcpError> for FileSystemError {
+    fn from(error: TcpError) -> Self {
+        match error {
+            TcpError::Refused => Self::ConnectionRefused,
+            TcpError::Reset => Self::ConnectionReset,
+            TcpError::TimedOut => Self::TimedOut,
+        }
+    }
+}
+
+/// The transmission control block of a connection.
+struct Tcb {
+    state: TcpState,
+    /// The local port, once bound.
+    port: Option<u16>,
+    /// Whether the socket bound its port. The accepted connections use the port of
+    /// their listener.
+    owns_port: bool,
+    local_addr: Ipv4Addr,
+    remote: Option<(Ipv4Addr, u16)>,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// The maximum segment size of the peer.
+    mss: usize,
+    /// The window advertised in the last segment sent.
+    advertised_window: u32,
+
+    /// The data that was not acknowledged by the peer yet, starting at `snd_una`.
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+    /// Set when the socket is closed, the FIN is sent once the send buffer drains.
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_received: bool,
+
+    rto: u64,
+    retransmit_at: Option<u64>,
+    retries: usize,
+    time_wait_until: Option<u64>,
+    error: Option<TcpError>,
+    /// Recorded for `TCP_NODELAY`, the data is always sent without delay.
+    no_delay: bool,
+
+    backlog: usize,
+    /// The established connections waiting to be accepted.
+    accept_queue: VecDeque<Arc<TcpSocket>>,
+    /// The listener that created the connection, until the connection is established.
+    parent: Option<Weak<TcpSocket>>,
+}
+
+impl Tcb {
+    fn new() -> Self {
+        Self {
+            state: TcpState::Closed,
+            port: None,
+            owns_port: false,
+            local_addr: Ipv4Addr::UNSPECIFIED,
+            remote: None,
+
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            mss: DEFAULT_MSS,
+            advertised_window: 0,
+
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+
+            rto: INITIAL_RTO,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: None,
+            error: None,
+            no_delay: false,
+
+            backlog: 0,
+            accept_queue: VecDeque::new(),
+            parent: None,
+        }
+    }
+
+    fn key(&self) -> Option<ConnectionKey> {
+        let (address, port) = self.remote?;
+        Some((self.port?, address, port))
+    }
+
+    fn recv_window(&self) -> u32 {
+        (RECV_BUFFER_SIZE - self.recv_buffer.len()) as u32
+    }
+
+    /// Initializes the send sequence space of a new connection.
+    fn init_sequence(&mut self) {
+        self.iss = tcp::initial_seq();
+        self.snd_una = self.iss;
+        self.snd_nxt = self.iss.wrapping_add(1);
+    }
+
+    fn transmit(&mut self, seq: u32, flags: TcpFlags, payload: &[u8]) {
+        let (remote, remote_port) = match self.remote {
+            Some(remote) => remote,
+            None => return,
+        };
+
+        let window = self.recv_window();
+        self.advertised_window = window;
+
+        let segment = TcpSegment {
+            src_port: self.port.unwrap_or(0),
+            dest_port: remote_port,
+            seq,
+            ack: if flags.contains(TcpFlags::ACK) {
+                self.rcv_nxt
+            } else {
+                0
+            },
+            flags,
+            window: window as u16,
+            mss: flags.contains(TcpFlags::SYN).then(|| LOCAL_MSS),
+            payload,
+        };
+
+        // The lost segments are retransmitted by the timer.
+        let _ = tcp::send_segment(self.local_addr, remote, &segment);
+    }
+
+    fn send_ack(&mut self) {
+        self.transmit(self.snd_nxt, TcpFlags::ACK, &[]);
+    }
+
+    fn arm_retransmit(&mut self) {
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(timer::now() + self.rto);
+        }
+    }
+
+    /// Sends the queued data allowed by the send window, followed by the FIN once all
+    /// of the data was sent.
+    fn output(&mut self) {
+        if !matches!(self.state, TcpState::Established | TcpState::CloseWait) {
+            return;
+        }
+
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buffer.len() - offset;
+            let window = (self.snd_wnd as usize).saturating_sub(offset);
+            let size = unsent.min(window).min(self.mss);
+
+            if size == 0 {
+                if unsent == 0 && self.fin_queued {
+                    self.transmit(self.snd_nxt, TcpFlags::FIN | TcpFlags::ACK, &[]);
+
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.fin_sent = true;
+                    self.state = match self.state {
+                        TcpState::Established => TcpState::FinWait1,
+                        _ => TcpState::LastAck,
+                    };
+
+                    self.arm_retransmit();
+                } else if unsent != 0 && offset == 0 {
+                    // The peer closed its window, probe it from the timer.
+                    self.arm_retransmit();
+                }
+
+                break;
+            }
+
+            let payload = self
+                .send_buffer
+                .range(offset..offset + size)
+                .copied()
+                .collect::<Vec<_>>();
+
+            self.transmit(self.snd_nxt, TcpFlags::ACK | TcpFlags::PSH, &payload);
+            self.snd_nxt = self.snd_nxt.wrapping_add(size as u32);
+            self.arm_retransmit();
+        }
+    }
+
+    /// Retransmits the first unacknowledged segment.
+    fn retransmit(&mut self) {
+        let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) - self.fin_sent as u32;
+        let size = (in_flight as usize).min(self.mss);
+
+        if size != 0 {
+            let payload = self.send_buffer.range(..size).copied().collect::<Vec<_>>();
+            self.transmit(self.snd_una, TcpFlags::ACK | TcpFlags::PSH, &payload);
+        } else if self.fin_sent {
+            let seq = self.snd_nxt.wrapping_sub(1);
+            self.transmit(seq, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        } else if let Some(&byte) = self.send_buffer.front() {
+            // Window probe: send a byte past the closed window.
+            self.transmit(self.snd_nxt, TcpFlags::ACK, &[byte]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        } else {
+            self.retransmit_at = None;
+        }
+    }
+
+    /// Processes the acknowledgment and the window of a segment. Returns whether our FIN
+    /// is acknowledged.
+    fn process_ack(&mut self, ack: u32, window: u16) -> bool {
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+
+            if self.fin_sent && ack == self.snd_nxt {
+                acked -= 1;
+            }
+
+            let acked = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..acked);
+
+            self.snd_una = ack;
+            self.retries = 0;
+            self.rto = INITIAL_RTO;
+            self.retransmit_at = if self.snd_una == self.snd_nxt {
+                None
+            } else {
+                Some(timer::now() + self.rto)
+            };
+        }
+
+        if seq_le(self.snd_una, ack) {
+            if self.snd_wnd == 0 && window != 0 {
+                self.retries = 0;
+            }
+
+            self.snd_wnd = window as u32;
+        }
+
+        self.fin_sent && self.snd_una == self.snd_nxt
+    }
+
+    /// Queues the in-order data of a segment and acknowledges it. The out-of-order
+    /// segments are dropped and the peer retransmits them.
+    fn process_data(&mut self, seq: u32, payload: &[u8], fin: bool) {
+        if payload.is_empty() && !fin {
+            return;
+        }
+
+        if self.fin_received || seq_lt(self.rcv_nxt, seq) {
+            self.send_ack();
+            return;
+        }
+
+        let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+
+        if skip > payload.len() {
+            self.send_ack();
+            return;
+        }
+
+        let data = &payload[skip..];
+        let size = data.len().min(self.recv_window() as usize);
+
+        self.recv_buffer.extend(&data[..size]);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(size as u32);
+
+        if fin && size == data.len() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+        }
+
+        self.send_ack();
+    }
+
+    fn enter_time_wait(&mut self) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = Some(timer::now() + TIME_WAIT);
+    }
+
+    /// Closes the connection and releases its port.
+    fn teardown(&mut self) {
+        self.state = TcpState::Closed;
+        self.retransmit_at = None;
+        self.time_wait_until = None;
+
+        if let Some(key) = self.key() {
+            tcp::unregister_connection(key);
+        }
+
+        if self.owns_port {
+            tcp::unbind_port(self.port.take().unwrap());
+            self.owns_port = false;
+        }
+    }
+}
+
+pub struct TcpSocket {
+    tcb: Mutex<Tcb>,
+    options: Mutex<SocketOptions>,
+    wq: WaitQueue,
+    /// The amount of file handles referring to the socket. The connection is closed
+    /// when the last one is closed.
+    handles: AtomicUsize,
+    handle: Once<Arc<FileHandle>>,
+    sref: Weak<Self>,
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            tcb: Mutex::new(Tcb::new()),
+            options: Mutex::new(SocketOptions::new(
+                SOCK_STREAM,
+                SEND_BUFFER_SIZE,
+                RECV_BUFFER_SIZE,
+            )),
+            wq: WaitQueue::new(),
+            handles: AtomicUsize::new(0),
+            handle: Once::new(),
+            sref: sref.clone(),
+        })
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle.get().map_or(false, |handle| {
+            handle.flags.read().contains(OpenFlags::O_NONBLOCK)
+        })
+    }
+
+    /// Waits until the condition is satisfied, unless `non_block` is set.
+    fn wait_until<F>(&self, non_block: bool, mut condition: F) -> fs::Result<MutexGuard<Tcb>>
+    where
+        F: FnMut(&mut MutexGuard<Tcb>) -> bool,
+    {
+        let mut tcb = self.tcb.lock_irq();
+
+        if condition(&mut tcb) {
+            return Ok(tcb);
+        } else if non_block {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        core::mem::drop(tcb);
+        Ok(self.wq.wait_until(&self.tcb, condition)?)
+    }
+
+    /// Handles a segment that belongs to the connection. Called by the TCP layer.
+    pub fn handle_segment(&self, segment: &TcpSegment<'_>) {
+        let mut tcb = self.tcb.lock_irq();
+        let mut established = false;
+
+        let flags = segment.flags;
+
+        match tcb.state {
+            TcpState::Closed | TcpState::Listen => return,
+
+            TcpState::SynSent => {
+                if flags.contains(TcpFlags::ACK)
+                    && !(seq_lt(tcb.iss, segment.ack) && seq_le(segment.ack, tcb.snd_nxt))
+                {
+                    if !flags.contains(TcpFlags::RST) {
+                        tcb.transmit(segment.ack, TcpFlags::RST, &[]);
+                    }
+
+                    return;
+                }
+
+                if flags.contains(TcpFlags::RST) {
+                    if flags.contains(TcpFlags::ACK) {
+                        tcb.error = Some(TcpError::Refused);
+                        tcb.teardown();
+                    }
+                } else if flags.contains(TcpFlags::SYN) {
+                    tcb.rcv_nxt = segment.seq.wrapping_add(1);
+                    tcb.mss = segment.mss.map_or(DEFAULT_MSS, |mss| mss as usize);
+                    tcb.snd_wnd = segment.window as u32;
+
+                    if flags.contains(TcpFlags::ACK) {
+                        tcb.snd_una = segment.ack;
+                        tcb.state = TcpState::Established;
+                        tcb.retransmit_at = None;
+                        tcb.retries = 0;
+                        tcb.send_ack();
+                    } else {
+                        // Simultaneous open.
+                        tcb.state = TcpState::SynReceived;
+                        let iss = tcb.iss;
+                        tcb.transmit(iss, TcpFlags::SYN | TcpFlags::ACK, &[]);
+                    }
+                }
+            }
+
+            _ => {
+                if flags.contains(TcpFlags::RST) {
+                    let offset = segment.seq.wrapping_sub(tcb.rcv_nxt);
+
+                    if offset <= tcb.recv_window() {
+                        if tcb.state != TcpState::SynReceived {
+                            tcb.error = Some(TcpError::Reset);
+                        }
+
+                        tcb.teardown();
+                    }
+                } else if flags.contains(TcpFlags::SYN) {
+                    if tcb.state == TcpState::SynReceived
+                        && segment.seq.wrapping_add(1) == tcb.rcv_nxt
+                    {
+                        // Our SYN-ACK was lost.
+                        let iss = tcb.iss;
+                        tcb.transmit(iss, TcpFlags::SYN | TcpFlags::ACK, &[]);
+                    } else {
+                        tcb.send_ack();
+                    }
+                } else if flags.contains(TcpFlags::ACK) {
+                    if tcb.state == TcpState::SynReceived {
+                        if segment.ack != tcb.snd_nxt {
+                            tcb.transmit(segment.ack, TcpFlags::RST, &[]);
+                            return;
+                        }
+
+                        tcb.state = TcpState::Established;
+                        established = true;
+                    }
+
+                    let fin_acked = tcb.process_ack(segment.ack, segment.window);
+
+                    if fin_acked {
+                        match tcb.state {
+                            TcpState::FinWait1 => {
+                                tcb.state = TcpState::FinWait2;
+                                tcb.time_wait_until = Some(timer::now() + TIME_WAIT);
+                            }
+
+                            TcpState::Closing => tcb.enter_time_wait(),
+
+                            TcpState::LastAck => {
+                                tcb.teardown();
+                                core::mem::drop(tcb);
+
+                                self.wq.wake_all();
+                                return;
+                            }
+
+                            _ => {}
+                        }
+                    }
+
+                    let fin_received = tcb.fin_received;
+                    tcb.process_data(segment.seq, segment.payload, flags.contains(TcpFlags::FIN));
+
+                    if !fin_received && tcb.fin_received {
+                        match tcb.state {
+                            TcpState::Established => tcb.state = TcpState::CloseWait,
+                            TcpState::FinWait1 => tcb.state = TcpState::Closing,
+                            TcpState::FinWait2 => tcb.enter_time_wait(),
+                            _ => {}
+                        }
+                    } else if tcb.state == TcpState::TimeWait && flags.contains(TcpFlags::FIN) {
+                        // The peer retransmitted its FIN, restart the TIME-WAIT timer.
+                        tcb.enter_time_wait();
+                    }
+
+                    tcb.output();
+                }
+            }
+        }
+
+        let parent = if established { tcb.parent.take() } else { None };
+        core::mem::drop(tcb);
+
+        self.wq.wake_all();
+
+        // The connection is queued on its listener after its lock is released, as the
+        // listener locks the queued connections when it is closed.
+        if let Some(parent) = parent {
+            let socket = self.sref.upgrade().unwrap();
+
+            match parent.upgrade() {
+                Some(parent) if parent.push_accepted(socket.clone()) => {}
+                _ => socket.abort(),
+            }
+        }
+    }
+
+    /// Handles a segment received on the port the socket listens on, that does not
+    /// belong to any connection. Returns [`false`] if the segment is to be answered with
+    /// a reset.
+    pub fn handle_listen(&self, header: &Ipv4Header, segment: &TcpSegment<'_>) -> bool {
+        let tcb = self.tcb.lock_irq();
+
+        if tcb.state != TcpState::Listen {
+            return false;
+        } else if segment.flags.contains(TcpFlags::RST) {
+            return true;
+        } else if segment.flags.contains(TcpFlags::ACK) {
+            return false;
+        } else if !segment.flags.contains(TcpFlags::SYN) {
+            return true;
+        }
+
+        if !tcb.local_addr.is_unspecified() && tcb.local_addr != header.dest {
+            return false;
+        }
+
+        // The SYN is dropped and retransmitted by the peer once the backlog drains.
+        if tcb.accept_queue.len() >= tcb.backlog {
+            return true;
+        }
+
+        let socket = Self::new();
+        let mut child = socket.tcb.lock_irq();
+
+        child.state = TcpState::SynReceived;
+        child.port = tcb.port;
+        child.local_addr = header.dest;
+        child.remote = Some((header.src, segment.src_port));
+        child.init_sequence();
+        child.rcv_nxt = segment.seq.wrapping_add(1);
+        child.snd_wnd = segment.window as u32;
+        child.mss = segment.mss.map_or(DEFAULT_MSS, |mss| mss as usize);
+        child.parent = Some(self.sref.clone());
+
+        tcp::register_connection(child.key().unwrap(), socket.clone());
+
+        let iss = child.iss;
+        child.transmit(iss, TcpFlags::SYN | TcpFlags::ACK, &[]);
+        child.arm_retransmit();
+
+        true
+    }
+
+    /// Queues an established connection to be accepted. Returns [`false`] if the socket
+    /// is not listening anymore or if its backlog is full.
+    fn push_accepted(&self, socket: Arc<TcpSocket>) -> bool {
+        let mut tcb = self.tcb.lock_irq();
+
+        if tcb.state != TcpState::Listen || tcb.accept_queue.len() >= tcb.backlog {
+            return false;
+        }
+
+        tcb.accept_queue.push_back(socket);
+        core::mem::drop(tcb);
+
+        self.wq.wake_all();
+        true
+    }
+
+    /// Runs the retransmission and TIME-WAIT timers. Called by the TCP layer.
+    pub fn on_timer(&self, now: u64) {
+        let mut tcb = self.tcb.lock_irq();
+
+        if matches!(tcb.time_wait_until, Some(until) if now >= until) {
+            tcb.teardown();
+        } else if matches!(tcb.retransmit_at, Some(at) if now >= at) {
+            tcb.retries += 1;
+
+            if tcb.retries > MAX_RETRIES {
+                tcb.error = Some(TcpError::TimedOut);
+                tcb.teardown();
+            } else {
+                tcb.rto = core::cmp::min(tcb.rto * 2, MAX_RTO);
+                tcb.retransmit_at = Some(now + tcb.rto);
+
+                let iss = tcb.iss;
+
+                match tcb.state {
+                    TcpState::SynSent => tcb.transmit(iss, TcpFlags::SYN, &[]),
+                    TcpState::SynReceived => tcb.transmit(iss, TcpFlags::SYN | TcpFlags::ACK, &[]),
+                    _ => tcb.retransmit(),
+                }
+
+                return;
+            }
+        } else {
+            return;
+        }
+
+        core::mem::drop(tcb);
+        self.wq.wake_all();
+    }
+
+    /// Resets the connection.
+    fn abort(&self) {
+        let mut tcb = self.tcb.lock_irq();
+
+        if tcb.state != TcpState::Closed {
+            let seq = tcb.snd_nxt;
+            tcb.transmit(seq, TcpFlags::RST, &[]);
+            tcb.teardown();
+        }
+
+        core::mem::drop(tcb);
+        self.wq.wake_all();
+    }
+
+    /// Closes the socket. The connections send their remaining data before the FIN.
+    fn shutdown(&self) {
+        let mut tcb = self.tcb.lock_irq();
+
+        match tcb.state {
+            TcpState::Listen => {
+                tcp::unregister_listener(tcb.port.unwrap());
+
+                let pending = core::mem::take(&mut tcb.accept_queue);
+                tcb.teardown();
+                core::mem::drop(tcb);
+
+                for socket in pending {
+                    socket.abort();
+                }
+            }
+
+            TcpState::Closed | TcpState::SynSent => tcb.teardown(),
+
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                tcb.fin_queued = true;
+                tcb.output();
+            }
+
+            _ => {}
+        }
+
+        self.wq.wake_all();
+    }
+
+    fn receive(&self, non_block: bool, buffers: &mut [&mut [u8]]) -> fs::Result<usize> {
+        let mut tcb = self.wait_until(non_block, |tcb| {
+            !tcb.recv_buffer.is_empty()
+                || tcb.fin_received
+                || matches!(tcb.state, TcpState::Closed | TcpState::Listen)
+        })?;
+
+        if tcb.recv_buffer.is_empty() {
+            return match tcb.error {
+                Some(error) => Err(error.into()),
+                None if tcb.fin_received => Ok(0),
+                None => Err(FileSystemError::NotConnected),
+            };
+        }
+
+        let mut copied = 0;
+
+        for buffer in buffers.iter_mut() {
+            let size = buffer.len().min(tcb.recv_buffer.len());
+
+            for (byte, data) in buffer.iter_mut().zip(tcb.recv_buffer.drain(..size)) {
+                *byte = data;
+            }
+
+            copied += size;
+        }
+
+        // Let the peer know that the window reopened, once it grew enough for the peer
+        // to send a full segment (or half of the buffer).
+        let threshold = (tcb.mss as u32).min(RECV_BUFFER_SIZE as u32 / 2);
+        let reopened = tcb.recv_window().saturating_sub(tcb.advertised_window);
+
+        let peer_sending = matches!(
+            tcb.state,
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+
+        if peer_sending && reopened >= threshold {
+            tcb.send_ack();
+        }
+
+        Ok(copied)
+    }
+
+    fn transmit(&self, non_block: bool, data: &[u8]) -> fs::Result<usize> {
+        let mut written = 0;
+
+        while written < data.len() {
+            let result = self.wait_until(non_block, |tcb| {
+                tcb.send_buffer.len() < SEND_BUFFER_SIZE
+                    || !matches!(
+                        tcb.state,
+                        TcpState::SynSent
+                            | TcpState::SynReceived
+                            | TcpState::Established
+                            | TcpState::CloseWait
+                    )
+            });
+
+            let mut tcb = match result {
+                Ok(tcb) => tcb,
+                Err(FileSystemError::WouldBlock) if written != 0 => break,
+                Err(error) => return Err(error),
+            };
+
+            if let Some(error) = tcb.error {
+                return Err(error.into());
+            } else if tcb.fin_queued
+                || !matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+            {
+                return Err(FileSystemError::NotConnected);
+            }
+
+            let size = (SEND_BUFFER_SIZE - tcb.send_buffer.len()).min(data.len() - written);
+
+            tcb.send_buffer.extend(&data[written..written + size]);
+            tcb.output();
+
+            written += size;
+        }
+
+        Ok(written)
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        let tcb = self.tcb.lock_irq();
+
+        if tcb.state == TcpState::Listen {
+            tcp::unregister_listener(tcb.port.unwrap());
+        }
+
+        if tcb.owns_port {
+            tcp::unbind_port(tcb.port.unwrap());
+        }
+    }
+}
+
+impl INodeInterface for TcpSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::Socket,
+            size: 0,
+            children_len: 0,
+        })
+    }
+
+    fn open(&self, _flags: OpenFlags, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handles.fetch_add(1, Ordering::SeqCst);
+        self.handle.call_once(|| handle);
+
+        Ok(None)
+    }
+
+    fn close(&self, _flags: OpenFlags) {
+        if self.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown();
+        }
+    }
+
+    fn bind(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        let (address, port) = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+        let mut tcb = self.tcb.lock_irq();
+
+        if tcb.port.is_some() {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        tcb.port = Some(tcp::bind_port(port)?);
+        tcb.owns_port = true;
+        tcb.local_addr = address;
+
+        Ok(())
+    }
+
+    fn listen(&self, backlog: usize) -> Result<(), SyscallError> {
+        let mut tcb = self.tcb.lock_irq();
+
+        match tcb.state {
+            TcpState::Closed => {}
+
+            TcpState::Listen => {
+                tcb.backlog = backlog.max(1);
+                return Ok(());
+            }
+
+            _ => return Err(SyscallError::EINVAL),
+        }
+
+        let port = match tcb.port {
+            Some(port) => port,
+            None => {
+                let port = tcp::bind_port(0).map_err(FileSystemError::from)?;
+
+                tcb.port = Some(port);
+                tcb.owns_port = true;
+                port
+            }
+        };
+
+        tcb.state = TcpState::Listen;
+        tcb.backlog = backlog.max(1);
+
+        tcp::register_listener(port, self.sref.clone());
+        Ok(())
+    }
+
+    fn connect(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        let remote = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+        let (_, _, local_addr) = tcp::route(remote.0)?;
+
+        let mut tcb = self.tcb.lock_irq();
+
+        if tcb.state != TcpState::Closed || tcb.error.is_some() {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        if tcb.port.is_none() {
+            tcb.port = Some(tcp::bind_port(0)?);
+            tcb.owns_port = true;
+        }
+
+        tcb.local_addr = local_addr;
+        tcb.remote = Some(remote);
+        tcb.state = TcpState::SynSent;
+        tcb.init_sequence();
+
+        tcp::register_connection(tcb.key().unwrap(), self.sref.upgrade().unwrap());
+
+        let iss = tcb.iss;
+        tcb.transmit(iss, TcpFlags::SYN, &[]);
+        tcb.arm_retransmit();
+
+        core::mem::drop(tcb);
+
+        let tcb = self
+            .wq
+            .wait_until(&self.tcb, |tcb| tcb.state != TcpState::SynSent)?;
+
+        match tcb.error {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> fs::Result<Arc<dyn INodeInterface>> {
+        let mut tcb = self.wait_until(self.is_non_block(), |tcb| {
+            !tcb.accept_queue.is_empty() || tcb.state != TcpState::Listen
+        })?;
+
+        let socket = tcb
+            .accept_queue
+            .pop_front()
+            .ok_or(FileSystemError::InvalidPath)?;
+
+        core::mem::drop(tcb);
+
+        if let Some((address, length)) = address {
+            let address = address
+                .read_mut::<SocketAddrInet>()
+                .ok_or(FileSystemError::NotSupported)?;
+
+            if let Some((remote, port)) = socket.tcb.lock_irq().remote {
+                *address = inet_to_sockaddr(remote, port);
+            }
+
+            *length = core::mem::size_of::<SocketAddrInet>() as u32;
+        }
+
+        Ok(socket)
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        self.receive(self.is_non_block(), &mut [buffer])
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.transmit(self.is_non_block(), buffer)
+    }
+
+    fn recv(&self, header: &mut MessageHeader, non_block: bool) -> fs::Result<usize> {
+        if let Some(name) = header.name_mut::<SocketAddrInet>() {
+            if let Some((remote, port)) = self.tcb.lock_irq().remote {
+                *name = inet_to_sockaddr(remote, port);
+            }
+        }
+
+        let mut buffers = header
+            .iovecs_mut()
+            .iter_mut()
+            .map(|iovec| iovec.as_mut_slice())
+            .collect::<Vec<_>>();
+
+        self.receive(non_block, &mut buffers)
+    }
+
+    fn send(&self, header: &MessageHeader, non_block: bool) -> fs::Result<usize> {
+        let data = header
+            .iovecs()
+            .iter()
+            .flat_map(|iovec| iovec.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.transmit(non_block, &data)
+    }
+
+    fn get_option(&self, level: u32, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        match (level, name) {
+            (SOL_SOCKET, SO_ERROR) => {
+                let error = self.tcb.lock_irq().error.take();
+                let errno = error.map_or(0, |error| {
+                    SyscallError::from(FileSystemError::from(error)) as i32
+                });
+
+                write_int_option(value, errno)
+            }
+
+            (SOL_SOCKET, SO_ACCEPTCONN) => {
+                let listening = self.tcb.lock_irq().state == TcpState::Listen;
+                write_int_option(value, listening as i32)
+            }
+
+            (SOL_SOCKET, _) => self.options.lock_irq().get(name, value),
+            (IPPROTO_TCP, TCP_NODELAY) => {
+                write_int_option(value, self.tcb.lock_irq().no_delay as i32)
+            }
+
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn set_option(&self, level: u32, name: u32, value: &[u8]) -> fs::Result<()> {
+        match (level, name) {
+            (SOL_SOCKET, _) => self.options.lock_irq().set(name, value),
+            (IPPROTO_TCP, TCP_NODELAY) => {
+                self.tcb.lock_irq().no_delay = read_int_option(value)? != 0;
+                Ok(())
+            }
+
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn local_name(&self) -> fs::Result<SocketName> {
+        let tcb = self.tcb.lock_irq();
+        let name = inet_to_sockaddr(tcb.local_addr, tcb.port.unwrap_or(0));
+
+        Ok(SocketName::INet(name))
+    }
+
+    fn peer_name(&self) -> fs::Result<SocketName> {
+        let (address, port) = self
+            .tcb
+            .lock_irq()
+            .remote
+            .ok_or(FileSystemError::NotConnected)?;
+
+        Ok(SocketName::INet(inet_to_sockaddr(address, port)))
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        table.map(|table| table.insert(&self.wq));
+
+        let tcb = self.tcb.lock_irq();
+        let mut events = PollFlags::empty();
+
+        if tcb.state == TcpState::Listen {
+            if !tcb.accept_queue.is_empty() {
+                events.insert(PollFlags::IN);
+            }
+
+            return Ok(events);
+        }
+
+        if !tcb.recv_buffer.is_empty() || tcb.fin_received {
+            events.insert(PollFlags::IN);
+        }
+
+        if matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+            && tcb.send_buffer.len() < SEND_BUFFER_SIZE
+        {
+            events.insert(PollFlags::OUT);
+        }
+
+        if tcb.error.is_some() {
+            events.insert(PollFlags::ERR);
+        }
+
+        // Both directions are closed.
+        if tcb.remote.is_some()
+            && (tcb.state == TcpState::Closed || tcb.fin_received && tcb.fin_sent)
+        {
+            events.insert(PollFlags::HUP);
+        }
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,306 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use aero_syscall::socket::MessageHeader;
+use aero_syscall::*;
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::*;
+use crate::fs::FileSystemError;
+
+use crate::net::{self, Interface, Ipv4Addr};
+use crate::timer::{self, Timer};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{inet_from_sockaddr, inet_to_sockaddr, SocketAddr, SocketName, SocketOptions};
+
+/// The maximum amount of datagrams queued on a socket. The datagrams received while the
+/// queue is full are dropped.
+const MAX_QUEUED: usize = 64;
+
+/// A received datagram.
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    /// The interface the datagram was received on.
+    pub interface: Arc<Interface>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct UdpSocketInner {
+    /// The local port, once bound.
+    port: Option<u16>,
+    /// The default destination, set by `connect`.
+    remote: Option<(Ipv4Addr, u16)>,
+}
+
+pub struct UdpSocket {
+    inner: Mutex<UdpSocketInner>,
+    options: Mutex<SocketOptions>,
+    queue: Mutex<VecDeque<Datagram>>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+    sref: Weak<Self>,
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            inner: Mutex::new(UdpSocketInner::default()),
+            options: Mutex::new(SocketOptions::new(
+                SOCK_DGRAM,
+                u16::MAX as usize,
+                u16::MAX as usize,
+            )),
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+            sref: sref.clone(),
+        })
+    }
+
+    fn is_non_block(&self) -> bool {
+        // The sockets created by the kernel are not bound to a file descriptor.
+        self.handle.get().map_or(false, |handle| {
+            handle.flags.read().contains(OpenFlags::O_NONBLOCK)
+        })
+    }
+
+    /// Binds the socket to the provided port, or to an ephemeral port if `port` is zero.
+    pub fn bind_port(&self, port: u16) -> fs::Result<u16> {
+        let mut inner = self.inner.lock_irq();
+
+        if inner.port.is_some() {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        let port = net::udp::bind_port(port, self.sref.clone())?;
+        inner.port = Some(port);
+
+        Ok(port)
+    }
+
+    /// Returns the local port, binding the socket to an ephemeral port if needed.
+    fn local_port(&self) -> fs::Result<u16> {
+        if let Some(port) = self.inner.lock_irq().port {
+            return Ok(port);
+        }
+
+        self.bind_port(0)
+    }
+
+    /// Sends the data to the provided destination, or to the connected address if
+    /// [`None`].
+    pub fn send_to(&self, dest: Option<(Ipv4Addr, u16)>, data: &[u8]) -> fs::Result<usize> {
+        let (address, port) = dest
+            .or(self.inner.lock_irq().remote)
+            .ok_or(FileSystemError::DestinationRequired)?;
+
+        if address == Ipv4Addr::BROADCAST && !self.options.lock_irq().broadcast() {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        net::udp::send(self.local_port()?, address, port, data)?;
+        Ok(data.len())
+    }
+
+    /// Removes the next datagram from the receive queue, waiting for one unless
+    /// `non_block` is set.
+    pub fn recv_from(&self, non_block: bool) -> fs::Result<Datagram> {
+        if non_block && self.queue.lock_irq().is_empty() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut queue = self.wq.wait_until(&self.queue, |queue| !queue.is_empty())?;
+        Ok(queue.pop_front().unwrap())
+    }
+
+    /// Removes the next datagram from the receive queue, waiting for one until the
+    /// deadline (in nanoseconds since boot). Returns [`None`] if the deadline passed.
+    pub fn recv_timeout(&self, deadline: u64) -> fs::Result<Option<Datagram>> {
+        let _timer = Timer::wake_current(deadline);
+        let mut queue = self.wq.wait_until(&self.queue, |queue| {
+            !queue.is_empty() || timer::now() >= deadline
+        })?;
+
+        Ok(queue.pop_front())
+    }
+
+    /// Queues a datagram received on the port of the socket. Called by the UDP layer.
+    pub fn deliver(&self, datagram: Datagram) {
+        // A connected socket only receives the datagrams from its peer.
+        if let Some(remote) = self.inner.lock_irq().remote {
+            if remote != (datagram.src, datagram.src_port) {
+                return;
+            }
+        }
+
+        let mut queue = self.queue.lock_irq();
+
+        if queue.len() >= MAX_QUEUED {
+            return;
+        }
+
+        queue.push_back(datagram);
+        core::mem::drop(queue);
+
+        self.wq.wake_all();
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(port) = self.inner.lock_irq().port {
+            net::udp::unbind_port(port);
+        }
+    }
+}
+
+impl INodeInterface for UdpSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::Socket,
+            size: 0,
+            children_len: 0,
+        })
+    }
+
+    fn open(&self, _flags: OpenFlags, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn bind(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        // The socket receives on all of the interfaces, whatever the bound address.
+        let (_, port) = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+
+        self.bind_port(port)?;
+        Ok(())
+    }
+
+    fn connect(&self, address: SocketAddr, _length: usize) -> fs::Result<()> {
+        let remote = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+
+        self.inner.lock_irq().remote = Some(remote);
+        self.local_port()?;
+
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let datagram = self.recv_from(self.is_non_block())?;
+        let size = core::cmp::min(buffer.len(), datagram.data.len());
+
+        // The rest of the datagram is discarded.
+        buffer[..size].copy_from_slice(&datagram.data[..size]);
+        Ok(size)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.send_to(None, buffer)
+    }
+
+    fn recv(&self, header: &mut MessageHeader, non_block: bool) -> fs::Result<usize> {
+        let datagram = self.recv_from(non_block)?;
+
+        if let Some(name) = header.name_mut::<SocketAddrInet>() {
+            *name = inet_to_sockaddr(datagram.src, datagram.src_port);
+        }
+
+        let mut data = datagram.data.as_slice();
+        let mut copied = 0;
+
+        for iovec in header.iovecs_mut() {
+            let buffer = iovec.as_mut_slice();
+            let size = core::cmp::min(buffer.len(), data.len());
+
+            buffer[..size].copy_from_slice(&data[..size]);
+            data = &data[size..];
+            copied += size;
+        }
+
+        Ok(copied)
+    }
+
+    fn send(&self, header: &MessageHeader, _non_block: bool) -> fs::Result<usize> {
+        let dest = header.name::<SocketAddrInet>().map(inet_from_sockaddr);
+        let data = header
+            .iovecs()
+            .iter()
+            .flat_map(|iovec| iovec.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.send_to(dest, &data)
+    }
+
+    fn get_option(&self, level: u32, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().get(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn set_option(&self, level: u32, name: u32, value: &[u8]) -> fs::Result<()> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().set(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn local_name(&self) -> fs::Result<SocketName> {
+        // The socket receives on all of the interfaces.
+        let port = self.inner.lock_irq().port.unwrap_or(0);
+        Ok(SocketName::INet(inet_to_sockaddr(
+            Ipv4Addr::UNSPECIFIED,
+            port,
+        )))
+    }
+
+    fn peer_name(&self) -> fs::Result<SocketName> {
+        let (address, port) = self
+            .inner
+            .lock_irq()
+            .remote
+            .ok_or(FileSystemError::NotConnected)?;
+
+        Ok(SocketName::INet(inet_to_sockaddr(address, port)))
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        table.map(|table| table.insert(&self.wq));
+
+        let mut events = PollFlags::OUT;
+
+        if !self.queue.lock_irq().is_empty() {
+            events.insert(PollFlags::IN);
+        }
+
+        Ok(events)
+    }
+}
@@ -17,7 +17,7 @@
  * along with Aero. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use aero_syscall::{OpenFlags, SocketAddrUnix, SyscallError, AF_UNIX};
+use aero_syscall::{OpenFlags, SocketAddrUnix, SyscallError, AF_UNIX, SOCK_STREAM, SOL_SOCKET};
 
 use aero_syscall::socket::MessageHeader;
 
@@ -34,9 +34,9 @@ use crate::fs::inode::*;
 use crate::fs::{FileSystemError, Path};
 
 use crate::mem::paging::VirtAddr;
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddr;
+use super::{SocketAddr, SocketName, SocketOptions};
 
 fn path_from_unix_sock<'sock>(address: &'sock SocketAddrUnix) -> fs::Result<&'sock Path> {
     // The abstract namespace socket allows the creation of a socket
@@ -192,8 +192,9 @@ struct UnixSocketInner {
 
 pub struct UnixSocket {
     inner: Mutex<UnixSocketInner>,
+    options: Mutex<SocketOptions>,
     buffer: Mutex<MessageQueue>,
-    wq: BlockQueue,
+    wq: WaitQueue,
     weak: Weak<UnixSocket>,
     handle: Once<Arc<FileHandle>>,
 }
@@ -202,9 +203,15 @@ impl UnixSocket {
     pub fn new() -> Arc<Self> {
         Arc::new_cyclic(|weak| Self {
             inner: Mutex::new(UnixSocketInner::default()),
+            // The UNIX sockets are connection oriented and their buffers are unbounded.
+            options: Mutex::new(SocketOptions::new(
+                SOCK_STREAM,
+                i32::MAX as usize,
+                i32::MAX as usize,
+            )),
 
             buffer: Mutex::new(MessageQueue::default()),
-            wq: BlockQueue::new(),
+            wq: WaitQueue::new(),
             weak: weak.clone(),
             handle: Once::new(),
         })
@@ -264,7 +271,7 @@ impl INodeInterface for UnixSocket {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let mut buffer = self.wq.wait_until(&self.buffer, |e| !e.is_empty())?;
 
         let read = buffer.read(user_buffer);
         Ok(read)
@@ -278,7 +285,7 @@ impl INodeInterface for UnixSocket {
         };
 
         peer.buffer.lock_irq().write(buffer);
-        peer.wq.notify_complete();
+        peer.wq.wake_all();
 
         Ok(buffer.len())
     }
@@ -339,15 +346,17 @@ impl INodeInterface for UnixSocket {
         };
 
         queue.push(self.sref()).unwrap();
-        target.wq.notify_complete();
+        target.wq.wake_all();
         core::mem::drop(itarget); // release the lock
 
-        let _ = self.wq.block_on(&self.inner, |e| e.state.is_connected())?;
+        let _ = self
+            .wq
+            .wait_until(&self.inner, |e| e.state.is_connected())?;
         Ok(())
     }
 
-    fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> fs::Result<Arc<UnixSocket>> {
-        let mut inner = self.wq.block_on(&self.inner, |e| {
+    fn accept(&self, address: Option<(VirtAddr, &mut u32)>) -> fs::Result<Arc<dyn INodeInterface>> {
+        let mut inner = self.wq.wait_until(&self.inner, |e| {
             e.state.queue().map(|x| !x.is_empty()).unwrap_or(false)
         })?;
 
@@ -384,7 +393,7 @@ impl INodeInterface for UnixSocket {
             *length = core::mem::size_of::<SocketAddrUnix>() as u32;
         }
 
-        peer.wq.notify_complete();
+        peer.wq.wake_all();
         Ok(sock)
     }
 
@@ -400,7 +409,7 @@ impl INodeInterface for UnixSocket {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let mut buffer = self.wq.wait_until(&self.buffer, |e| !e.is_empty())?;
 
         header
             .name_mut::<SocketAddrUnix>()
@@ -413,6 +422,37 @@ impl INodeInterface for UnixSocket {
             .sum::<usize>())
     }
 
+    fn get_option(&self, level: u32, name: u32, value: &mut [u8]) -> fs::Result<usize> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().get(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn set_option(&self, level: u32, name: u32, value: &[u8]) -> fs::Result<()> {
+        match level {
+            SOL_SOCKET => self.options.lock_irq().set(name, value),
+            _ => Err(FileSystemError::InvalidOption),
+        }
+    }
+
+    fn local_name(&self) -> fs::Result<SocketName> {
+        let address = self.inner.lock_irq().address.clone().unwrap_or_default();
+        Ok(SocketName::Unix(address))
+    }
+
+    fn peer_name(&self) -> fs::Result<SocketName> {
+        let inner = self.inner.lock_irq();
+
+        let peer = match &inner.state {
+            UnixSocketState::Connected(peer) => peer,
+            _ => return Err(FileSystemError::NotConnected),
+        };
+
+        let address = peer.inner.lock_irq().address.clone().unwrap_or_default();
+        Ok(SocketName::Unix(address))
+    }
+
     fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
         let buffer = self.buffer.lock_irq();
         let inner = self.inner.lock_irq();
@@ -19,7 +19,7 @@
 
 use aero_syscall::signal::SigProcMask;
 use aero_syscall::{prelude::*, TimeSpec};
-use aero_syscall::{OpenFlags, Stat, SyscallError};
+use aero_syscall::{OpenFlags, PerfEventAttr, Stat, SyscallError};
 
 use crate::fs::cache::DirCacheImpl;
 use crate::fs::epoll::EPoll;
@@ -27,7 +27,9 @@ use crate::fs::eventfd::EventFd;
 use crate::fs::file_table::DuplicateHint;
 use crate::fs::inode::{DirEntry, PollTable};
 use crate::fs::pipe::Pipe;
+use crate::fs::ramfs::RamFs;
 use crate::fs::{self, lookup_path, LookupMode};
+use crate::timer::{self, Timer};
 use crate::userland::scheduler;
 
 use crate::fs::Path;
@@ -157,6 +159,39 @@ pub fn chdir(path: &str) -> Result<usize, SyscallError> {
     Ok(0x00)
 }
 
+/// Mounts a new instance of the filesystem `fstype` at `target`, in the mount namespace
+/// of the current task. Only `ramfs` can be instantiated.
+#[syscall]
+pub fn mount(target: &Path, fstype: &str) -> Result<usize, SyscallError> {
+    let inode = fs::lookup_path(target)?;
+
+    if !inode.inode().metadata()?.is_directory() {
+        return Err(SyscallError::ENOTDIR);
+    }
+
+    let filesystem = match fstype {
+        "ramfs" => RamFs::new(),
+        _ => return Err(SyscallError::ENODEV),
+    };
+
+    fs::mount_namespace().mount(inode, filesystem)?;
+    Ok(0)
+}
+
+/// Changes the root directory of the current task to `path`. The children created
+/// afterwards inherit it.
+#[syscall]
+pub fn chroot(path: &Path) -> Result<usize, SyscallError> {
+    let inode = fs::lookup_path(path)?;
+
+    if !inode.inode().metadata()?.is_directory() {
+        return Err(SyscallError::ENOTDIR);
+    }
+
+    scheduler::get_scheduler().current_task().set_root(inode);
+    Ok(0)
+}
+
 #[syscall]
 pub fn mkdirat(dfd: usize, path: &Path) -> Result<usize, SyscallError> {
     // NOTE: If the pathname given in pathname is relative, then it is interpreted
@@ -530,6 +565,49 @@ pub fn event_fd(_initval: usize, flags: usize) -> Result<usize, SyscallError> {
         .open_file(entry, OpenFlags::O_RDWR)?)
 }
 
+/// Opens a hardware performance counter that counts while the calling task is running
+/// and returns a file descriptor for it. Reading the file descriptor returns a
+/// [`aero_syscall::PerfReadFormat`] followed by the samples recorded since the last read.
+#[syscall]
+pub fn perf_event_open(attr: &PerfEventAttr) -> Result<usize, SyscallError> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use aero_syscall::{
+            PERF_COUNT_HW_CACHE_MISSES, PERF_COUNT_HW_CPU_CYCLES, PERF_COUNT_HW_INSTRUCTIONS,
+        };
+
+        use crate::arch::pmu::{self, PmuEvent};
+        use crate::fs::perf_event::PerfEvent;
+
+        if !pmu::is_supported() {
+            return Err(SyscallError::ENODEV);
+        }
+
+        let event = match attr.config {
+            PERF_COUNT_HW_CPU_CYCLES => PmuEvent::Cycles,
+            PERF_COUNT_HW_INSTRUCTIONS => PmuEvent::Instructions,
+            PERF_COUNT_HW_CACHE_MISSES => PmuEvent::CacheMisses,
+            _ => return Err(SyscallError::EINVAL),
+        };
+
+        let perf_event = PerfEvent::new(event, attr.sample_period, attr.disabled == 0)
+            .ok_or(SyscallError::EBUSY)?;
+
+        let entry = DirEntry::from_inode(perf_event, String::from("<perf_event>"));
+        let current_task = scheduler::get_scheduler().current_task();
+
+        Ok(current_task
+            .file_table
+            .open_file(entry, OpenFlags::O_RDONLY)?)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        let _ = attr;
+        Err(SyscallError::ENOSYS)
+    }
+}
+
 /// Creates a new link (also known as a hard link) to an existing
 /// file.
 #[syscall]
@@ -555,15 +633,13 @@ pub fn link(src_path: &Path, dest_path: &Path) -> Result<usize, SyscallError> {
     Ok(0)
 }
 
-fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, SyscallError> {
+/// Polls all of the file descriptors once, registering the current task on the wait
+/// queues of their files. Returns the number of ready file descriptors.
+fn poll_once(fds: &mut [PollFd], table: &mut PollTable) -> Result<Option<usize>, SyscallError> {
     let current_task = scheduler::get_scheduler().current_task();
-
-    let mut poll_table = PollTable::default();
     let mut n = 0;
-    let mut refds = alloc::vec![];
 
-    // Iterate over all the registered events and check if they are ready.
-    for (i, fd) in fds.iter_mut().enumerate() {
+    for fd in fds.iter_mut() {
         fd.revents = PollEventFlags::empty();
 
         // TODO: If an invalid file descriptor is provided then return EBADFD. Not implemented currently,
@@ -574,51 +650,56 @@ fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, Sysc
         // is a kernel process?
         let handle = match current_task.file_table.get_handle(fd.fd as usize) {
             Some(v) => v,
-            None => {
-                return Ok(0);
-            }
+            None => return Ok(None),
         };
 
-        let ready: PollEventFlags = handle.inode().poll(None)?.into();
+        let ready: PollEventFlags = handle.inode().poll(Some(table))?.into();
+
+        // The error and hang up events are always reported, even if not requested.
+        let revents = ready & (fd.events | PollEventFlags::ERR | PollEventFlags::HUP);
 
-        if !(ready & fd.events).is_empty() {
-            // The registered event is ready; increment the number of ready events
-            // and update revents mask for this event.
-            fd.revents = ready & fd.events;
+        if !revents.is_empty() {
+            fd.revents = revents;
             n += 1;
-            continue;
         }
-
-        // Not ready; add the event to the poll table.
-        handle.inode().poll(Some(&mut poll_table))?;
-        refds.push((handle, i));
     }
 
-    // If all events are ready, we can return now.
-    if n > 0 {
-        return Ok(n);
-    }
+    Ok(Some(n))
+}
 
+fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, SyscallError> {
     // Start the timer if timeout specified, if not, we can block indefinitely.
-    if let Some(timeout) = timeout {
-        // If the timeout is zero, then we have to return without blocking.
-        if timeout.tv_nsec == 0 && timeout.tv_sec == 0 {
-            return Ok(0);
-        }
-    }
+    let timer = match timeout {
+        Some(timeout) if timeout.tv_nsec == 0 && timeout.tv_sec == 0 => None,
+        Some(timeout) => Some(Timer::wake_current(
+            timer::now() + timer::timespec_to_nanoseconds(timeout),
+        )),
+        None => None,
+    };
 
-    'search: loop {
-        scheduler::get_scheduler().inner.await_io()?;
+    loop {
+        // The task is registered on the wait queues before checking for readiness, so
+        // no wake up is missed between the check and going to sleep. It is removed from
+        // the queues when the table is dropped.
+        let mut poll_table = PollTable::default();
 
-        for (handle, index) in refds.iter() {
-            let pollfd = &mut fds[*index];
-            let ready: PollEventFlags = handle.inode().poll(None)?.into();
+        let n = match poll_once(fds, &mut poll_table)? {
+            Some(n) => n,
+            None => return Ok(0),
+        };
+
+        // If the timeout is zero, then we have to return without blocking.
+        if n > 0 || (timeout.is_some() && timer.is_none()) {
+            return Ok(n);
+        }
 
-            if !(ready & pollfd.events).is_empty() {
-                pollfd.revents = ready & pollfd.events;
-                break 'search Ok(1);
+        if let Some(timer) = timer.as_ref() {
+            if timer::now() >= timer.deadline() {
+                return Ok(0);
             }
         }
+
+        scheduler::get_scheduler().inner.await_io()?;
     }
 }
 
@@ -631,7 +712,7 @@ pub fn poll(fds: &mut [PollFd], timeout: usize, sigmask: usize) -> Result<usize,
 
     // The timeout can be NULL.
     let timeout = if timeout != 0x00 {
-        Some(crate::utils::validate_ptr(timeout as *const TimeSpec).ok_or(SyscallError::EINVAL)?)
+        Some(crate::utils::validate_ptr(timeout as *const TimeSpec).ok_or(SyscallError::EFAULT)?)
     } else {
         None
     };
@@ -26,10 +26,10 @@ use spin::Once;
 use crate::mem::paging::{PhysAddr, Translate, VirtAddr};
 use crate::mem::AddressSpace;
 use crate::userland::scheduler;
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
 pub struct FutexContainer {
-    futexes: Mutex<hashbrown::HashMap<PhysAddr, Arc<BlockQueue>>>,
+    futexes: Mutex<hashbrown::HashMap<PhysAddr, Arc<WaitQueue>>>,
 }
 
 impl FutexContainer {
@@ -60,20 +60,20 @@ impl FutexContainer {
     }
 
     /// Returns the futex at the given key; allocating it if it doesn't exist.
-    fn get_alloc(&self, key: PhysAddr) -> Arc<BlockQueue> {
+    fn get_alloc(&self, key: PhysAddr) -> Arc<WaitQueue> {
         let mut container = self.futexes.lock();
 
         if let Some(futex) = container.get(&key) {
             futex.clone()
         } else {
-            let futex = Arc::new(BlockQueue::new());
+            let futex = Arc::new(WaitQueue::new());
             container.insert(key, futex.clone());
             futex
         }
     }
 
     /// Returns the futex at the given key, or None if it doesn't exist.
-    fn get(&self, key: PhysAddr) -> Option<Arc<BlockQueue>> {
+    fn get(&self, key: PhysAddr) -> Option<Arc<WaitQueue>> {
         self.futexes.lock_irq().get(&key).map(|e| e.clone())
     }
 
@@ -98,14 +98,14 @@ impl FutexContainer {
             let current_task = scheduler.current_task();
 
             futex.insert(current_task.clone());
-            scheduler.inner.await_io()?;
+            let result = scheduler.inner.await_io();
             futex.remove(current_task);
 
             if futex.is_empty() {
                 self.futexes.lock().remove(&key);
             }
 
-            Ok(())
+            Ok(result?)
         } else {
             Err(SyscallError::EAGAIN)
         }
@@ -117,7 +117,7 @@ impl FutexContainer {
         let key = Self::addr_as_futex_key(uaddr).ok_or(SyscallError::EINVAL)?;
         let futex = self.get(key).ok_or(SyscallError::EINVAL)?;
 
-        futex.notify_complete();
+        futex.wake_all();
 
         // todo: early reschedule if the futex is not empty.
         Ok(())
@@ -20,7 +20,7 @@
 use crate::userland::scheduler::get_scheduler;
 use crate::userland::task::TaskId;
 
-use crate::utils::sync::{BlockQueue, Mutex};
+use crate::utils::sync::{Mutex, WaitQueue};
 
 use aero_syscall::SyscallError;
 use alloc::{collections::VecDeque, vec::Vec};
@@ -37,14 +37,14 @@ struct Message {
 
 pub struct MessageQueue {
     queue: Mutex<VecDeque<Message>>,
-    blockqueue: BlockQueue,
+    blockqueue: WaitQueue,
 }
 
 impl MessageQueue {
     pub fn new() -> MessageQueue {
         MessageQueue {
             queue: Mutex::new(VecDeque::new()),
-            blockqueue: BlockQueue::new(),
+            blockqueue: WaitQueue::new(),
         }
     }
 }
@@ -77,7 +77,7 @@ pub fn send(pid: usize, payload: &[u8]) -> Result<usize, SyscallError> {
     });
 
     // Notify the task that it has a new message if its awaiting for one!
-    message_queue.blockqueue.notify_complete();
+    message_queue.blockqueue.wake_one();
 
     Ok(0)
 }
@@ -104,7 +104,7 @@ pub fn recv(pid_ptr: &mut usize, output: &mut [u8], block: usize) -> Result<usiz
     let mq = &current.message_queue;
     let mut our_queue = mq
         .blockqueue
-        .block_on(&mq.queue, |msg| msg.front().is_some())
+        .wait_until(&mq.queue, |msg| msg.front().is_some())
         .unwrap();
 
     let msg = our_queue
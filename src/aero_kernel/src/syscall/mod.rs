@@ -74,8 +74,6 @@
 //! | 50     | fstat                   |
 //! | 51     | read_link               |
 
-use core::mem::MaybeUninit;
-
 use aero_syscall::prelude::*;
 
 mod fs;
@@ -86,6 +84,7 @@ mod process;
 mod time;
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 pub use fs::*;
@@ -93,6 +92,10 @@ pub use ipc::*;
 pub use process::*;
 pub use time::*;
 
+use crate::mem::paging::VirtAddr;
+use crate::mem::uaccess;
+use crate::trace::{self, TraceEvent};
+use crate::userland::scheduler;
 use crate::utils::StackHelper;
 
 #[derive(Default)]
@@ -127,35 +130,47 @@ impl ExecArgs {
     }
 }
 
-pub fn exec_args_from_slice(args: usize, size: usize) -> ExecArgs {
+/// Copies the array of `size` slice references at the userland address `args` and the
+/// strings they point to into kernel space.
+pub fn exec_args_from_slice(args: usize, size: usize) -> Result<ExecArgs, SyscallError> {
     // NOTE: Arguments must be moved into kernel space before we utilize them.
     //
     // struct SliceReference {
     //    ptr: *const usize,
     //    len: usize,
     // }
-    let data = args as *const [usize; 2];
-    let slice = unsafe { core::slice::from_raw_parts(data, size) };
+    let len = size
+        .checked_mul(core::mem::size_of::<[usize; 2]>())
+        .ok_or(SyscallError::EFAULT)?;
 
-    // todo(andy): use `with_capacity` to avoid reallocation.
-    let mut result = Vec::new();
+    let mut slice = alloc::vec![[0usize; 2]; size];
 
-    for inner in slice {
-        let mut boxed = Box::new_uninit_slice(inner[1]);
-        let ptr = inner[0] as *const MaybeUninit<u8>;
+    // SAFETY: `[usize; 2]` has no padding and any bit pattern is valid.
+    let bytes = unsafe { core::slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u8, len) };
+    uaccess::copy_from_user(bytes, VirtAddr::new(args as u64))?;
 
-        unsafe {
-            boxed.as_mut_ptr().copy_from(ptr, inner[1]);
+    let mut result = Vec::with_capacity(size);
 
-            let inner_slice = boxed.assume_init();
-            result.push(inner_slice);
-        }
+    for [ptr, len] in slice {
+        let mut boxed = alloc::vec![0u8; len].into_boxed_slice();
+        uaccess::copy_from_user(&mut boxed, VirtAddr::new(ptr as u64))?;
+
+        result.push(boxed);
     }
 
-    ExecArgs { inner: result }
+    Ok(ExecArgs { inner: result })
+}
+
+/// Returns whether the syscalls of the current task are logged, either because the
+/// `syslog` feature is enabled or because tracing was enabled for the task (see
+/// [`process::trace`]).
+pub(super) fn should_log() -> bool {
+    cfg!(feature = "syslog")
+        || scheduler::get_scheduler()
+            .current_task()
+            .is_syscall_traced()
 }
 
-#[cfg(feature = "syslog")]
 pub(super) struct SysLog {
     name: &'static str,
     /// The result of the syscall.
@@ -164,7 +179,6 @@ pub(super) struct SysLog {
     args: Vec<String>,
 }
 
-#[cfg(feature = "syslog")]
 impl SysLog {
     pub fn new(name: &'static str) -> Self {
         Self {
@@ -215,7 +229,17 @@ impl SysLog {
         }
 
         result.push_str(alloc::format!(") = {:?}", self.result.unwrap()).as_str());
-        log::trace!("{result}");
+
+        // The traced tasks are logged at the info level, so that they are not filtered
+        // out by the default console log level.
+        if scheduler::get_scheduler()
+            .current_task()
+            .is_syscall_traced()
+        {
+            log::info!("{result}");
+        } else {
+            log::trace!("{result}");
+        }
     }
 }
 
@@ -228,10 +252,13 @@ pub fn generic_do_syscall(
     f: usize,
     g: usize,
 ) -> usize {
+    trace::trace(TraceEvent::SyscallEnter, [a as u64, b as u64]);
+
     let result = match a {
         SYS_EXIT => process::exit(b),
         SYS_SHUTDOWN => process::shutdown(),
         SYS_FORK => process::fork(),
+        SYS_REBOOT => process::reboot(),
         SYS_MMAP => process::mmap(b, c, d, e, f, g),
         SYS_MUNMAP => process::munmap(b, c),
         SYS_EXEC => process::exec(b, c, d, e, f, g),
@@ -246,9 +273,25 @@ pub fn generic_do_syscall(
         SYS_INFO => process::info(b),
         SYS_SIGACTION => process::sigaction(b, c, d, e),
         SYS_SIGPROCMASK => process::sigprocmask(b, c, d),
-        SYS_CLONE => process::clone(b, c),
+        SYS_CLONE => process::clone(b, c, d),
         SYS_KILL => process::kill(b, c),
+        SYS_SETPGID => process::setpgid(b, c),
+        SYS_GETPGID => process::getpgid(b),
+        SYS_SETSID => process::setsid(),
+        SYS_GETSID => process::getsid(b),
+        SYS_SYSLOG => process::syslog(b, c, d),
         SYS_BACKTRACE => process::backtrace(),
+        SYS_GETRANDOM => process::getrandom(b, c, d),
+        SYS_TRACE => process::trace(b, c),
+        SYS_PTRACE => process::ptrace(b, c, d, e),
+        SYS_SECCOMP => process::seccomp(b, c, d),
+        SYS_PRCTL => process::prctl(b, c),
+        SYS_GETRLIMIT => process::getrlimit(b, c),
+        SYS_SETRLIMIT => process::setrlimit(b, c),
+        SYS_UNSHARE => process::unshare(b),
+        SYS_MOUNT => fs::mount(b, c, d, e),
+        SYS_CHROOT => fs::chroot(b, c),
+        SYS_GETCPU => process::getcpu(),
 
         SYS_READ => fs::read(b, c, d),
         SYS_OPEN => fs::open(b, c, d, e),
@@ -271,6 +314,7 @@ pub fn generic_do_syscall(
         SYS_FSTAT => fs::fstat(b, c),
         SYS_READ_LINK => fs::read_link(b, c, d, e),
         SYS_EVENT_FD => fs::event_fd(b, c),
+        SYS_PERF_EVENT_OPEN => fs::perf_event_open(b),
         SYS_LINK => fs::link(b, c, d, e),
         SYS_POLL => fs::poll(b, c, d, e),
         SYS_RENAME => fs::rename(b, c, d, e),
@@ -286,9 +330,15 @@ pub fn generic_do_syscall(
         SYS_LISTEN => net::listen(b, c),
         SYS_ACCEPT => net::accept(b, c, d),
         SYS_SOCK_RECV => net::sock_recv(b, c, d),
+        SYS_SOCK_SEND => net::sock_send(b, c, d),
         SYS_SOCKET_PAIR => net::socket_pair(b, c, d, e),
+        SYS_GETSOCKOPT => net::getsockopt(b, c, d, e, f),
+        SYS_SETSOCKOPT => net::setsockopt(b, c, d, e, f),
+        SYS_GETSOCKNAME => net::getsockname(b, c, d),
+        SYS_GETPEERNAME => net::getpeername(b, c, d),
 
         SYS_GETTIME => time::gettime(b, c),
+        SYS_SETTIME => time::settime(b, c),
         SYS_SLEEP => time::sleep(b),
 
         SYS_SETITIMER => time::setitimer(b, c, d),
@@ -311,5 +361,8 @@ pub fn generic_do_syscall(
         }
     };
 
-    aero_syscall::syscall_result_as_usize(result)
+    let result = aero_syscall::syscall_result_as_usize(result);
+
+    trace::trace(TraceEvent::SyscallExit, [a as u64, result as u64]);
+    result
 }
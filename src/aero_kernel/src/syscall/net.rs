@@ -1,22 +1,26 @@
 use aero_syscall::socket::MessageHeader;
 use aero_syscall::*;
+use alloc::sync::Arc;
 
 use crate::fs::cache::DirCacheItem;
-use crate::fs::inode::DirEntry;
+use crate::fs::inode::{DirEntry, INodeInterface};
 use crate::mem::paging::VirtAddr;
+use crate::mem::uaccess;
 
+use crate::socket::packet::PacketSocket;
+use crate::socket::raw::RawSocket;
+use crate::socket::tcp::TcpSocket;
+use crate::socket::udp::UdpSocket;
 use crate::socket::unix::*;
-use crate::socket::SocketAddr;
+use crate::socket::{SocketAddr, SocketName};
 
 use crate::userland::scheduler;
 
 /// Creates a [`SocketAddr`] from the provided userland socket structure address. This
 /// is done by looking at the family field present in every socket address structure.
 fn socket_addr_from_addr<'sys>(address: VirtAddr) -> Result<SocketAddr<'sys>, SyscallError> {
-    let family = address
-        .read_mut::<u32>()
-        .ok_or(SyscallError::EINVAL)?
-        .clone();
+    // SAFETY: Any bit pattern is a valid `u32`.
+    let family = unsafe { uaccess::read_user::<u32>(address)? };
 
     Ok(SocketAddr::from_family(address, family).ok_or(SyscallError::EINVAL)?)
 }
@@ -79,6 +83,24 @@ pub fn sock_recv(
     Ok(socket.inode().recv(header, non_block)?)
 }
 
+#[syscall]
+pub fn sock_send(
+    sockfd: usize,
+    header: &mut MessageHeader,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    assert!(flags == 0, "sock_send: flags are not currently supported");
+
+    let current_task = scheduler::get_scheduler().current_task();
+    let socket = current_task
+        .file_table
+        .get_handle(sockfd)
+        .ok_or(SyscallError::EINVAL)?;
+
+    let non_block = socket.flags.read().contains(OpenFlags::O_NONBLOCK);
+    Ok(socket.inode().send(header, non_block)?)
+}
+
 /// Marks the socket as a passive socket (i.e. as a socket that will be used to accept incoming
 /// connection requests).
 #[syscall]
@@ -93,19 +115,51 @@ pub fn listen(fd: usize, backlog: usize) -> Result<usize, SyscallError> {
     Ok(0)
 }
 
+/// Returns whether the current process may create raw and packet sockets.
+///
+/// TODO: There are no user credentials yet, so every process is privileged. Check that
+/// the process is owned by root once they are implemented.
+fn is_privileged() -> bool {
+    true
+}
+
 fn create_socket(
     domain: usize,
     socket_type: usize,
     protocol: usize,
 ) -> Result<DirCacheItem, SyscallError> {
-    let socket = match domain as u32 {
-        AF_UNIX => UnixSocket::new(),
+    // The socket type may have the socket flags set.
+    let typ = socket_type & !SocketFlags::all().bits();
+
+    if typ == SOCK_RAW && !is_privileged() {
+        return Err(SyscallError::EPERM);
+    }
+
+    let socket = match (domain as u32, typ) {
+        (AF_UNIX, _) => UnixSocket::new() as Arc<dyn INodeInterface>,
+        (AF_INET, SOCK_DGRAM) => UdpSocket::new() as Arc<dyn INodeInterface>,
+        (AF_INET, SOCK_STREAM) => TcpSocket::new() as Arc<dyn INodeInterface>,
+
+        // The protocol is required, as the IPv4 header cannot be provided by the user
+        // (`IP_HDRINCL`).
+        (AF_INET, SOCK_RAW) if protocol != 0 && protocol < IPPROTO_RAW as usize => {
+            RawSocket::new(protocol as u8) as Arc<dyn INodeInterface>
+        }
+
+        // The protocol is the EtherType, in network byte order.
+        (AF_PACKET, SOCK_RAW) if protocol <= u16::MAX as usize => {
+            PacketSocket::new(u16::from_be(protocol as u16)) as Arc<dyn INodeInterface>
+        }
+
         _ => {
             log::warn!(
                 "unsupported socket type: domain={domain}, socket_type={socket_type}, protocol={protocol}"
             );
 
-            return Err(SyscallError::EINVAL);
+            return match domain as u32 {
+                AF_INET | AF_PACKET => Err(SyscallError::EPROTONOSUPPORT),
+                _ => Err(SyscallError::EAFNOSUPPORT),
+            };
         }
     };
 
@@ -169,3 +223,74 @@ pub fn socket_pair(
     fds[1] = current_task.file_table.open_file(b, sockfd_flags)? as i32;
     Ok(0)
 }
+
+/// Reads the value of a socket option. Returns the size of the option.
+#[syscall]
+pub fn getsockopt(
+    fd: usize,
+    level: usize,
+    name: usize,
+    value: &mut [u8],
+) -> Result<usize, SyscallError> {
+    let file = scheduler::get_scheduler()
+        .current_task()
+        .file_table
+        .get_handle(fd)
+        .ok_or(SyscallError::EINVAL)?;
+
+    Ok(file.inode().get_option(level as u32, name as u32, value)?)
+}
+
+#[syscall]
+pub fn setsockopt(
+    fd: usize,
+    level: usize,
+    name: usize,
+    value: &[u8],
+) -> Result<usize, SyscallError> {
+    let file = scheduler::get_scheduler()
+        .current_task()
+        .file_table
+        .get_handle(fd)
+        .ok_or(SyscallError::EINVAL)?;
+
+    file.inode().set_option(level as u32, name as u32, value)?;
+    Ok(0)
+}
+
+fn write_socket_name(
+    name: SocketName,
+    address: usize,
+    length: usize,
+) -> Result<usize, SyscallError> {
+    let length = VirtAddr::new(length as u64)
+        .read_mut::<u32>()
+        .ok_or(SyscallError::EACCES)?;
+
+    name.write_to(VirtAddr::new(address as u64), length)?;
+    Ok(0)
+}
+
+/// Returns the address that the socket is bound to.
+#[syscall]
+pub fn getsockname(fd: usize, address: usize, length: usize) -> Result<usize, SyscallError> {
+    let file = scheduler::get_scheduler()
+        .current_task()
+        .file_table
+        .get_handle(fd)
+        .ok_or(SyscallError::EINVAL)?;
+
+    write_socket_name(file.inode().local_name()?, address, length)
+}
+
+/// Returns the address of the peer that the socket is connected to.
+#[syscall]
+pub fn getpeername(fd: usize, address: usize, length: usize) -> Result<usize, SyscallError> {
+    let file = scheduler::get_scheduler()
+        .current_task()
+        .file_table
+        .get_handle(fd)
+        .ok_or(SyscallError::EINVAL)?;
+
+    write_socket_name(file.inode().peer_name()?, address, length)
+}
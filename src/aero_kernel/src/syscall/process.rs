@@ -17,18 +17,32 @@
  * along with Aero. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use aero_syscall::signal::{SigAction, SigProcMask};
+use aero_syscall::prelude::{
+    PtraceRegs, RLimit, SeccompFilter, CLONE_NEWNS, CLONE_NEWPID, PR_GET_NO_NEW_PRIVS,
+    PR_SET_NO_NEW_PRIVS, PTRACE_ATTACH, PTRACE_CONT, PTRACE_DETACH, PTRACE_GETREGS, PTRACE_KILL,
+    PTRACE_PEEKDATA, PTRACE_POKEDATA, PTRACE_SETREGS, PTRACE_SYSCALL, PTRACE_TRACEME, RLIMIT_AS,
+    RLIMIT_NOFILE, RLIMIT_NPROC, SECCOMP_SET_MODE_FILTER,
+};
+use aero_syscall::signal::{self, SigAction, SigProcMask};
 use aero_syscall::*;
+use alloc::sync::Arc;
+use log::LevelFilter;
 use spin::{Mutex, Once};
 
 use crate::acpi::aml;
 use crate::fs;
 use crate::fs::Path;
+use crate::logger;
 
 use crate::mem::paging::VirtAddr;
+use crate::mem::uaccess;
+use crate::userland::cgroup;
+use crate::userland::pid_namespace::{self, PidNamespace};
+use crate::userland::ptrace;
+use crate::userland::rlimit;
 use crate::userland::scheduler;
 use crate::userland::signals::SignalEntry;
-use crate::userland::task::TaskId;
+use crate::userland::task::Task;
 use crate::utils::sync::IrqGuard;
 
 static HOSTNAME: Once<Mutex<String>> = Once::new();
@@ -81,39 +95,152 @@ pub fn uname(buffer: &mut Utsname) -> Result<usize, SyscallError> {
     Ok(0x00)
 }
 
+/// Fails with `EAGAIN` if creating a task would exceed `RLIMIT_NPROC`.
+fn check_nproc(task: &Task) -> Result<(), SyscallError> {
+    let count = scheduler::get_scheduler().user_task_count() as u64;
+
+    if rlimit::within(count + 1, task.rlimits().current(RLIMIT_NPROC)) {
+        Ok(())
+    } else {
+        Err(SyscallError::EAGAIN)
+    }
+}
+
 #[syscall]
 pub fn fork() -> Result<usize, SyscallError> {
     let scheduler = scheduler::get_scheduler();
-    let forked = scheduler.current_task().fork();
+    let current_task = scheduler.current_task();
+
+    check_nproc(&current_task)?;
+
+    // The pages the parent has resident are charged once more for the child.
+    cgroup::check_memory(&current_task, current_task.vm().resident() * 4096)?;
+
+    let forked = current_task.fork();
 
     scheduler.register_task(forked.clone());
-    Ok(forked.pid().as_usize())
+    Ok(forked.pid_in(current_task.pid_namespace()).unwrap_or(0))
 }
 
 #[syscall]
-pub fn clone(entry: usize, stack: usize) -> Result<usize, SyscallError> {
+pub fn clone(entry: usize, stack: usize, tls: usize) -> Result<usize, SyscallError> {
     let scheduler = scheduler::get_scheduler();
-    let cloned = scheduler.current_task().clone_process(entry, stack);
+    check_nproc(&scheduler.current_task())?;
+
+    let current_task = scheduler.current_task();
+    let cloned = current_task.clone_process(entry, stack, tls);
 
     scheduler.register_task(cloned.clone());
-    Ok(cloned.pid().as_usize())
+    Ok(cloned.pid_in(current_task.pid_namespace()).unwrap_or(0))
 }
 
 #[syscall]
 pub fn kill(pid: usize, signal: usize) -> Result<usize, SyscallError> {
+    let pid = pid as isize;
+
     // If pid is positive, then signal is sent to the process with that pid.
     if pid > 0 {
-        let task = scheduler::get_scheduler()
-            .find_task(TaskId::new(pid))
-            .ok_or(SyscallError::ESRCH)?;
+        let task = pid_namespace::find_task(pid as usize).ok_or(SyscallError::ESRCH)?;
 
         task.signal(signal);
+        Ok(0)
+    } else if pid == 0 || pid < -1 {
+        // If pid is 0, then signal is sent to every process in the process group of the
+        // calling process. If pid is less than -1, then signal is sent to every process in
+        // the process group whose ID is -pid.
+        let scheduler = scheduler::get_scheduler();
+        let current_task = scheduler.current_task();
+
+        let group = if pid == 0 {
+            scheduler.find_process_group(&PidNamespace::root(), current_task.pgid())
+        } else {
+            scheduler.find_process_group(current_task.pid_namespace(), (-pid) as usize)
+        };
+
+        if group.is_empty() {
+            return Err(SyscallError::ESRCH);
+        }
+
+        for task in group {
+            task.signal(signal);
+        }
+
         Ok(0)
     } else {
         unimplemented!()
     }
 }
 
+/// Returns the task with the provided `pid` or the current task if `pid` is zero.
+fn find_process(pid: usize) -> Result<Arc<Task>, SyscallError> {
+    let scheduler = scheduler::get_scheduler();
+
+    if pid == 0 {
+        Ok(scheduler.current_task())
+    } else {
+        pid_namespace::find_task(pid).ok_or(SyscallError::ESRCH)
+    }
+}
+
+/// The process group and session IDs are translated through the PID namespace of the
+/// caller, like the PIDs. They are zero if the leader is not visible from it.
+#[syscall]
+pub fn setpgid(pid: usize, pgid: usize) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+    let ns = current_task.pid_namespace();
+    let task = find_process(pid)?;
+
+    // A session leader cannot change its process group.
+    if task.is_session_leader() {
+        return Err(SyscallError::EPERM);
+    }
+
+    // If pgid is zero, then the PGID of the process is made the same as its PID.
+    let group = if pgid == 0 || task.pid_in(ns) == Some(pgid) {
+        task.pids()
+    } else {
+        // The process can only be moved into a process group in the same session.
+        scheduler::get_scheduler()
+            .find_process_group(ns, pgid)
+            .iter()
+            .find(|member| member.sid() == task.sid())
+            .map(|member| member.process_group())
+            .ok_or(SyscallError::EPERM)?
+    };
+
+    task.set_pgid(group);
+    Ok(0)
+}
+
+#[syscall]
+pub fn getpgid(pid: usize) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+    let task = find_process(pid)?;
+
+    Ok(task.pgid_in(current_task.pid_namespace()).unwrap_or(0))
+}
+
+#[syscall]
+pub fn setsid() -> Result<usize, SyscallError> {
+    let task = scheduler::get_scheduler().current_task();
+
+    // The calling process cannot already be a process group leader.
+    if task.pgid() == task.pid().as_usize() {
+        return Err(SyscallError::EPERM);
+    }
+
+    task.set_sid();
+    Ok(task.sid_in(task.pid_namespace()).unwrap_or(0))
+}
+
+#[syscall]
+pub fn getsid(pid: usize) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+    let task = find_process(pid)?;
+
+    Ok(task.sid_in(current_task.pid_namespace()).unwrap_or(0))
+}
+
 #[syscall(no_return)]
 pub fn exec(
     path: &Path,
@@ -131,12 +258,12 @@ pub fn exec(
     // NOTE: Neither args nor envs should be used after this point, the kernel
     // now has owned copies in args and environment variables.
     let argv = if argc > 0 {
-        Some(super::exec_args_from_slice(args, argc))
+        Some(super::exec_args_from_slice(args, argc)?)
     } else {
         None
     };
     let envv = if envc > 0 {
-        Some(super::exec_args_from_slice(envs, envc))
+        Some(super::exec_args_from_slice(envs, envc)?)
     } else {
         None
     };
@@ -190,8 +317,20 @@ pub fn mmap(
         );
     }
 
-    if let Some(alloc) = scheduler::get_scheduler()
-        .current_task()
+    let current_task = scheduler::get_scheduler().current_task();
+    let new_size = current_task
+        .vm()
+        .size()
+        .checked_add(size as u64)
+        .ok_or(SyscallError::ENOMEM)?;
+
+    if !rlimit::within(new_size, current_task.rlimits().current(RLIMIT_AS)) {
+        return Err(SyscallError::ENOMEM);
+    }
+
+    cgroup::check_memory(&current_task, size as u64)?;
+
+    if let Some(alloc) = current_task
         .vm()
         .mmap(address, size, protection, flags, offset, file)
     {
@@ -216,6 +355,165 @@ pub fn munmap(address: usize, size: usize) -> Result<usize, SyscallError> {
     }
 }
 
+/// Enables (if `enable` is non-zero) or disables the logging of the syscalls made by the
+/// task `pid` (or the calling task if zero), with their decoded arguments and results, to
+/// the kernel log. The children created afterwards inherit the setting, so a program can
+/// be traced by enabling it before `exec`.
+#[syscall]
+pub fn trace(pid: usize, enable: usize) -> Result<usize, SyscallError> {
+    let task = if pid == 0 {
+        scheduler::get_scheduler().current_task()
+    } else {
+        pid_namespace::find_task(pid).ok_or(SyscallError::ESRCH)?
+    };
+
+    task.set_syscall_trace(enable != 0);
+    Ok(0)
+}
+
+/// Traces the task `pid` (see [`crate::userland::ptrace`]). The requests follow the
+/// Linux ABI: `PTRACE_PEEKDATA` stores the word read at the `data` pointer, and the
+/// signal to resume the tracee with is passed in `data`.
+#[syscall]
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> Result<usize, SyscallError> {
+    let addr = VirtAddr::new(addr as u64);
+
+    match request {
+        PTRACE_TRACEME => ptrace::traceme()?,
+        PTRACE_ATTACH => ptrace::attach_to(pid)?,
+
+        PTRACE_PEEKDATA => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            let value = ptrace::peek(&tracee, addr)?;
+
+            uaccess::write_user(VirtAddr::new(data as u64), &value)?;
+        }
+
+        PTRACE_POKEDATA => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            ptrace::poke(&tracee, addr, data as u64)?;
+        }
+
+        PTRACE_GETREGS => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            let regs = ptrace::get_regs(&tracee)?;
+
+            uaccess::write_user(VirtAddr::new(data as u64), &regs)?;
+        }
+
+        PTRACE_SETREGS => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            let regs = unsafe { uaccess::read_user::<PtraceRegs>(VirtAddr::new(data as u64))? };
+
+            if !crate::arch::ptrace::validate_regs(&regs) {
+                return Err(SyscallError::EIO);
+            }
+
+            ptrace::set_regs(&tracee, regs)?;
+        }
+
+        PTRACE_CONT | PTRACE_SYSCALL => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            ptrace::resume(&tracee, data, request == PTRACE_SYSCALL)?;
+        }
+
+        PTRACE_KILL => {
+            let tracee = ptrace::get_tracee(pid, false)?;
+            tracee.signal(signal::SIGKILL);
+        }
+
+        PTRACE_DETACH => {
+            let tracee = ptrace::get_tracee(pid, true)?;
+            ptrace::detach_from(&tracee, data);
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
+/// Installs a syscall filter on the current task (see [`crate::userland::seccomp`]).
+#[syscall]
+pub fn seccomp(
+    operation: usize,
+    flags: usize,
+    filter: &SeccompFilter,
+) -> Result<usize, SyscallError> {
+    if flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    match operation {
+        SECCOMP_SET_MODE_FILTER => {
+            let current_task = scheduler::get_scheduler().current_task();
+            current_task.seccomp().add_filter(filter)?;
+
+            Ok(0)
+        }
+
+        _ => Err(SyscallError::EINVAL),
+    }
+}
+
+#[syscall]
+pub fn prctl(option: usize, arg: usize) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+
+    match option {
+        PR_SET_NO_NEW_PRIVS if arg == 1 => {
+            current_task.seccomp().set_no_new_privs();
+            Ok(0)
+        }
+
+        PR_GET_NO_NEW_PRIVS => Ok(current_task.seccomp().no_new_privs() as usize),
+        _ => Err(SyscallError::EINVAL),
+    }
+}
+
+#[syscall]
+pub fn getrlimit(resource: usize, limit: &mut RLimit) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+
+    *limit = current_task.rlimits().get(resource)?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn setrlimit(resource: usize, limit: &RLimit) -> Result<usize, SyscallError> {
+    let current_task = scheduler::get_scheduler().current_task();
+    current_task.rlimits().set(resource, *limit)?;
+
+    if resource == RLIMIT_NOFILE {
+        current_task
+            .file_table
+            .set_max_files(limit.rlim_cur as usize);
+    }
+
+    Ok(0)
+}
+
+/// Moves the current task to a private mount namespace (`CLONE_NEWNS`) and/or makes the
+/// children it creates afterwards the members of a new PID namespace (`CLONE_NEWPID`).
+#[syscall]
+pub fn unshare(flags: usize) -> Result<usize, SyscallError> {
+    if flags & !(CLONE_NEWNS | CLONE_NEWPID) != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = scheduler::get_scheduler().current_task();
+
+    if flags & CLONE_NEWPID != 0 {
+        task.unshare_pid_namespace()?;
+    }
+
+    if flags & CLONE_NEWNS != 0 {
+        task.unshare_mount_namespace();
+    }
+
+    Ok(0)
+}
+
 #[syscall]
 pub fn backtrace() -> Result<usize, SyscallError> {
     crate::unwind::unwind_stack_trace();
@@ -224,20 +522,24 @@ pub fn backtrace() -> Result<usize, SyscallError> {
 
 #[syscall]
 pub fn getpid() -> Result<usize, SyscallError> {
-    Ok(scheduler::get_scheduler().current_task().pid().as_usize())
+    Ok(scheduler::get_scheduler().current_task().ns_pid())
 }
 
 #[syscall]
 pub fn getppid() -> Result<usize, SyscallError> {
-    Ok(scheduler::get_scheduler()
-        .current_task()
-        .parent_pid()
-        .as_usize())
+    Ok(scheduler::get_scheduler().current_task().ns_parent_pid())
 }
 
 #[syscall]
 pub fn gettid() -> Result<usize, SyscallError> {
-    Ok(scheduler::get_scheduler().current_task().tid().as_usize())
+    Ok(scheduler::get_scheduler().current_task().ns_pid())
+}
+
+/// Returns the ID of the CPU the calling task is running on. The vDSO falls back to this
+/// on the CPUs without `rdtscp`.
+#[syscall]
+pub fn getcpu() -> Result<usize, SyscallError> {
+    Ok(crate::arch::tls::get_cpuid())
 }
 
 #[syscall]
@@ -254,6 +556,28 @@ pub fn gethostname(buffer: &mut [u8]) -> Result<usize, SyscallError> {
     }
 }
 
+/// Fills the buffer with random bytes from the kernel CSPRNG. Blocks until the CSPRNG is
+/// seeded, unless `GRND_NONBLOCK` or `GRND_INSECURE` is set.
+#[syscall]
+pub fn getrandom(buffer: &mut [u8], flags: usize) -> Result<usize, SyscallError> {
+    let flags = GetRandomFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if flags.contains(GetRandomFlags::GRND_INSECURE) {
+        // `GRND_INSECURE` cannot be combined with `GRND_RANDOM`.
+        if flags.contains(GetRandomFlags::GRND_RANDOM) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        crate::random::fill_bytes(buffer);
+    } else if flags.contains(GetRandomFlags::GRND_NONBLOCK) && !crate::random::is_seeded() {
+        return Err(SyscallError::EAGAIN);
+    } else {
+        crate::random::fill_bytes_blocking(buffer)?;
+    }
+
+    Ok(buffer.len())
+}
+
 #[syscall]
 pub fn info(struc: &mut SysInfo) -> Result<usize, SyscallError> {
     struc.uptime = crate::arch::time::get_uptime_ticks() as i64;
@@ -261,6 +585,83 @@ pub fn info(struc: &mut SysInfo) -> Result<usize, SyscallError> {
     Ok(0x00)
 }
 
+/// Reads or controls the kernel log ring buffer. The actions and their arguments are
+/// the same as the linux `syslog` syscall, except that `SYSLOG_ACTION_READ` does not block
+/// if there are no unread records.
+#[syscall]
+pub fn syslog(action: usize, buffer: *mut u8, size: usize) -> Result<usize, SyscallError> {
+    let copy_out = |records: String| {
+        if buffer.is_null() {
+            return Err(SyscallError::EINVAL);
+        }
+
+        // Only the most recent records are returned if they do not fit in the buffer.
+        let records = records.as_bytes();
+        let records = &records[records.len().saturating_sub(size)..];
+
+        uaccess::copy_to_user(VirtAddr::new(buffer as u64), records)?;
+        Ok(records.len())
+    };
+
+    match action {
+        SYSLOG_ACTION_READ => {
+            // The records are only marked as read once they were copied to the user.
+            let (records, until) = logger::peek_unread(size);
+            let result = copy_out(records);
+
+            if result.is_ok() {
+                logger::mark_read(until);
+            }
+
+            result
+        }
+
+        SYSLOG_ACTION_READ_ALL => copy_out(logger::get_log_buffer()),
+
+        SYSLOG_ACTION_READ_CLEAR => {
+            let result = copy_out(logger::get_log_buffer());
+            logger::clear();
+
+            result
+        }
+
+        SYSLOG_ACTION_CLEAR => {
+            logger::clear();
+            Ok(0)
+        }
+
+        SYSLOG_ACTION_CONSOLE_OFF => {
+            logger::set_console_level(LevelFilter::Error);
+            Ok(0)
+        }
+
+        SYSLOG_ACTION_CONSOLE_ON => {
+            logger::set_console_level(LevelFilter::Trace);
+            Ok(0)
+        }
+
+        // The syslog priority (1-8) is mapped to the closest log level.
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            let level = match size {
+                1..=3 => LevelFilter::Error,
+                4 => LevelFilter::Warn,
+                5..=6 => LevelFilter::Info,
+                7 => LevelFilter::Debug,
+                8 => LevelFilter::Trace,
+                _ => return Err(SyscallError::EINVAL),
+            };
+
+            logger::set_console_level(level);
+            Ok(0)
+        }
+
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(logger::read_unread_size()),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(logger::get_log_buffer().len()),
+
+        _ => Err(SyscallError::EINVAL),
+    }
+}
+
 #[syscall]
 pub fn sethostname(name: &[u8]) -> Result<usize, SyscallError> {
     match core::str::from_utf8(name) {
@@ -343,3 +744,11 @@ pub fn shutdown() -> Result<usize, SyscallError> {
 
     unreachable!("aml: failed to shutdown (enter state S5)")
 }
+
+#[syscall(no_return)]
+pub fn reboot() -> Result<usize, SyscallError> {
+    fs::cache::clear_inode_cache();
+    fs::cache::clear_dir_cache();
+
+    crate::arch::reboot::reboot()
+}
@@ -22,19 +22,21 @@ use aero_syscall::{SyscallError, TimeSpec};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+use crate::timer;
 use crate::userland::scheduler;
 use crate::userland::task::Task;
 use crate::utils::sync::{IrqGuard, Mutex};
-use crate::utils::CeilDiv;
 
 const CLOCK_TYPE_REALTIME: usize = 0;
 const CLOCK_TYPE_MONOTONIC: usize = 1;
 
 #[syscall]
 pub fn sleep(timespec: &TimeSpec) -> Result<usize, SyscallError> {
-    let duration = (timespec.tv_nsec as usize).ceil_div(1000000000) + timespec.tv_sec as usize;
+    if timespec.tv_sec < 0 || !(0..1000000000).contains(&timespec.tv_nsec) {
+        return Err(SyscallError::EINVAL);
+    }
 
-    scheduler::get_scheduler().inner.sleep(Some(duration))?;
+    timer::sleep(timer::timespec_to_nanoseconds(timespec))?;
 
     Ok(0x00)
 }
@@ -52,8 +54,7 @@ pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallEr
         }
 
         CLOCK_TYPE_MONOTONIC => {
-            // FIXME: implement
-            let clock = crate::arch::time::get_realtime_clock();
+            let clock = crate::arch::time::get_monotonic_clock();
 
             timespec.tv_sec = clock.tv_sec;
             timespec.tv_nsec = clock.tv_nsec;
@@ -65,6 +66,25 @@ pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallEr
     }
 }
 
+/// Sets the realtime clock, which is written back to the RTC.
+#[syscall]
+pub fn settime(clock: usize, timespec: &TimeSpec) -> Result<usize, SyscallError> {
+    if clock != CLOCK_TYPE_REALTIME {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if timespec.tv_sec < 0 || !(0..1000000000).contains(&timespec.tv_nsec) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    crate::arch::time::set_realtime_clock(timespec);
+
+    #[cfg(target_arch = "x86_64")]
+    crate::drivers::rtc::write(timespec.tv_sec as u64);
+
+    Ok(0x00)
+}
+
 static TIMERS: Mutex<Vec<Arc<Task>>> = Mutex::new(Vec::new());
 
 pub fn check_timers() {
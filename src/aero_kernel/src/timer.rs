@@ -0,0 +1,146 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Kernel timers. A timer runs its callback from the timer tick once the monotonic clock
+//! reaches its deadline. The pending timers are kept in a B-tree ordered by deadline, so
+//! the tick only has to look at the ones that expired.
+//!
+//! Blocking with a timeout is done by arming a timer that wakes up the current task (see
+//! [`Timer::wake_current`]) and awaiting I/O until the condition is satisfied or the
+//! deadline passes (see [`sleep_until`]).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aero_syscall::TimeSpec;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+
+use crate::userland::scheduler;
+use crate::userland::signals::SignalResult;
+use crate::utils::sync::Mutex;
+
+pub type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Orders the pending timers by deadline. The ID is only there to tell apart timers
+/// with the same deadline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct TimerKey {
+    deadline: u64,
+    id: u64,
+}
+
+static TIMERS: Mutex<BTreeMap<TimerKey, TimerCallback>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Converts the provided time into nanoseconds.
+pub fn timespec_to_nanoseconds(time: &TimeSpec) -> u64 {
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Returns the value of the monotonic clock, in nanoseconds.
+pub fn now() -> u64 {
+    timespec_to_nanoseconds(&crate::arch::time::get_monotonic_clock())
+}
+
+/// A pending timer. The timer is cancelled when dropped, unless it already expired.
+#[must_use = "the timer is cancelled when dropped"]
+pub struct Timer(TimerKey);
+
+impl Timer {
+    /// Arms a timer that runs `callback` (from interrupt context) once the monotonic
+    /// clock reaches `deadline` (in nanoseconds).
+    pub fn new(deadline: u64, callback: TimerCallback) -> Self {
+        let key = TimerKey {
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        };
+
+        TIMERS.lock_irq().insert(key, callback);
+        Self(key)
+    }
+
+    /// Arms a timer that wakes up the current task once the monotonic clock reaches
+    /// `deadline` (in nanoseconds).
+    pub fn wake_current(deadline: u64) -> Self {
+        let task = scheduler::get_scheduler().current_task();
+
+        Self::new(
+            deadline,
+            Box::new(move || scheduler::get_scheduler().inner.wake_up(task)),
+        )
+    }
+
+    /// Returns the deadline of the timer, in nanoseconds.
+    pub fn deadline(&self) -> u64 {
+        self.0.deadline
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        // The callback is dropped after the lock is released.
+        let callback = TIMERS.lock_irq().remove(&self.0);
+        core::mem::drop(callback);
+    }
+}
+
+/// Puts the current task to sleep until the monotonic clock reaches `deadline` (in
+/// nanoseconds) or a signal is received.
+pub fn sleep_until(deadline: u64) -> SignalResult<()> {
+    let _timer = Timer::wake_current(deadline);
+
+    while now() < deadline {
+        scheduler::get_scheduler().inner.await_io()?;
+    }
+
+    Ok(())
+}
+
+/// Puts the current task to sleep for `duration` nanoseconds or until a signal is
+/// received.
+pub fn sleep(duration: u64) -> SignalResult<()> {
+    sleep_until(now() + duration)
+}
+
+/// Runs the callbacks of the expired timers. Called from the timer tick.
+pub fn run_expired() {
+    let now = now();
+
+    loop {
+        // The lock is not held while running the callback, as it may arm or cancel
+        // timers itself.
+        let callback = {
+            let mut timers = TIMERS.lock_irq();
+
+            match timers.first_key_value() {
+                Some((key, _)) if key.deadline <= now => {
+                    let key = *key;
+                    timers.remove(&key)
+                }
+
+                _ => None,
+            }
+        };
+
+        match callback {
+            Some(callback) => callback(),
+            None => break,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Static kernel tracepoints. Each tracepoint records a fixed-size [`TraceRecord`] into
+//! the ring buffer of the CPU it fired on, if its event has been enabled. All events are
+//! disabled by default, in which case a tracepoint costs a single atomic load.
+//!
+//! The events are enabled through `/sys/kernel/trace/enable` and the records are read
+//! (and consumed) in their binary form from `/sys/kernel/trace/buffer`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::utils::sync::Mutex;
+
+/// The amount of records each of the per-CPU ring buffers can hold.
+const TRACE_RECORDS: usize = 4096;
+
+static ENABLED_EVENTS: AtomicU32 = AtomicU32::new(0);
+static TRACE_BUFFERS: Once<Vec<Mutex<TraceBuffer>>> = Once::new();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TraceEvent {
+    /// Arguments: the syscall number and its first argument.
+    SyscallEnter = 0,
+    /// Arguments: the syscall number and its return value.
+    SyscallExit = 1,
+    /// Arguments: the TIDs of the previous and the next task.
+    ContextSwitch = 2,
+    /// Arguments: the faulting address and the error code.
+    PageFault = 3,
+    /// Arguments: the interrupt vector.
+    Irq = 4,
+}
+
+impl TraceEvent {
+    pub const ALL: [TraceEvent; 5] = [
+        TraceEvent::SyscallEnter,
+        TraceEvent::SyscallExit,
+        TraceEvent::ContextSwitch,
+        TraceEvent::PageFault,
+        TraceEvent::Irq,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceEvent::SyscallEnter => "syscall_enter",
+            TraceEvent::SyscallExit => "syscall_exit",
+            TraceEvent::ContextSwitch => "context_switch",
+            TraceEvent::PageFault => "page_fault",
+            TraceEvent::Irq => "irq",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TraceEvent> {
+        Self::ALL.iter().copied().find(|event| event.name() == name)
+    }
+
+    #[inline]
+    fn mask(&self) -> u32 {
+        1 << *self as u32
+    }
+}
+
+/// The binary layout of a record, as read from `/sys/kernel/trace/buffer`.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// The time elapsed since boot, in nanoseconds.
+    pub timestamp: u64,
+    pub cpu: u32,
+    pub event: u32,
+    pub args: [u64; 2],
+}
+
+struct TraceBuffer {
+    records: Box<[TraceRecord]>,
+    /// The index of the oldest record.
+    head: usize,
+    len: usize,
+    /// The amount of records that were overwritten before they were read.
+    lost: usize,
+}
+
+impl TraceBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            records: alloc::vec![TraceRecord::default(); capacity].into_boxed_slice(),
+            head: 0,
+            len: 0,
+            lost: 0,
+        }
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        let capacity = self.records.len();
+
+        if self.len == capacity {
+            // Overwrite the oldest record.
+            self.records[self.head] = record;
+            self.head = (self.head + 1) % capacity;
+            self.lost += 1;
+        } else {
+            self.records[(self.head + self.len) % capacity] = record;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<TraceRecord> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let record = self.records[self.head];
+
+        self.head = (self.head + 1) % self.records.len();
+        self.len -= 1;
+
+        Some(record)
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.lost = 0;
+    }
+}
+
+/// Records the event with the provided arguments on the current CPU, if the event is
+/// enabled.
+#[inline]
+pub fn trace(event: TraceEvent, args: [u64; 2]) {
+    if ENABLED_EVENTS.load(Ordering::Relaxed) & event.mask() == 0 {
+        return;
+    }
+
+    trace_slow(event, args);
+}
+
+#[cold]
+fn trace_slow(event: TraceEvent, args: [u64; 2]) {
+    let buffers = match TRACE_BUFFERS.get() {
+        Some(buffers) => buffers,
+        None => return,
+    };
+
+    let cpu = crate::arch::tls::get_cpuid();
+    let clock = crate::arch::time::get_monotonic_clock();
+
+    let record = TraceRecord {
+        timestamp: clock.tv_sec as u64 * 1_000_000_000 + clock.tv_nsec as u64,
+        cpu: cpu as u32,
+        event: event as u32,
+        args,
+    };
+
+    if let Some(buffer) = buffers.get(cpu) {
+        buffer.lock_irq().push(record);
+    }
+}
+
+pub fn set_enabled(event: TraceEvent, enabled: bool) {
+    if enabled {
+        ENABLED_EVENTS.fetch_or(event.mask(), Ordering::SeqCst);
+    } else {
+        ENABLED_EVENTS.fetch_and(!event.mask(), Ordering::SeqCst);
+    }
+}
+
+pub fn is_enabled(event: TraceEvent) -> bool {
+    ENABLED_EVENTS.load(Ordering::Relaxed) & event.mask() != 0
+}
+
+/// Moves as many whole records as fit into `buffer` out of the per-CPU ring buffers and
+/// returns the amount of bytes written.
+pub fn read_records(buffer: &mut [u8]) -> usize {
+    let buffers = match TRACE_BUFFERS.get() {
+        Some(buffers) => buffers,
+        None => return 0,
+    };
+
+    let record_size = core::mem::size_of::<TraceRecord>();
+    let mut written = 0;
+
+    for cpu_buffer in buffers.iter() {
+        while written + record_size <= buffer.len() {
+            // The lock is not held while copying, as touching the (userland) buffer can
+            // page fault, which is itself a tracepoint.
+            let record = match cpu_buffer.lock_irq().pop() {
+                Some(record) => record,
+                None => break,
+            };
+
+            let bytes = crate::utils::slice_into_bytes(core::slice::from_ref(&record));
+
+            buffer[written..written + record_size].copy_from_slice(bytes);
+            written += record_size;
+        }
+    }
+
+    written
+}
+
+/// Returns the total amount of records that were overwritten before they were read.
+pub fn lost_records() -> usize {
+    TRACE_BUFFERS
+        .get()
+        .map(|buffers| buffers.iter().map(|buffer| buffer.lock_irq().lost).sum())
+        .unwrap_or(0)
+}
+
+/// Discards all of the recorded events.
+pub fn clear() {
+    if let Some(buffers) = TRACE_BUFFERS.get() {
+        for buffer in buffers.iter() {
+            buffer.lock_irq().clear();
+        }
+    }
+}
+
+/// Allocates the per-CPU ring buffers. The tracepoints that fire before this are
+/// dropped.
+pub fn init() {
+    TRACE_BUFFERS.call_once(|| {
+        (0..crate::utils::get_cpu_count())
+            .map(|_| Mutex::new(TraceBuffer::new(TRACE_RECORDS)))
+            .collect()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_buffer_overwrites_oldest() {
+        let mut buffer = TraceBuffer::new(2);
+
+        for i in 0..3 {
+            buffer.push(TraceRecord {
+                args: [i, 0],
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(buffer.lost, 1);
+        assert_eq!(buffer.pop().map(|r| r.args[0]), Some(1));
+        assert_eq!(buffer.pop().map(|r| r.args[0]), Some(2));
+        assert!(buffer.pop().is_none());
+    }
+}
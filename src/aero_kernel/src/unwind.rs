@@ -17,12 +17,13 @@
  * along with Aero. If not, see <https://www.gnu.org/licenses/>.
  */
 
+use core::fmt;
 use core::panic::PanicInfo;
 
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use xmas_elf::sections::{SectionData, ShType};
-use xmas_elf::symbol_table::Entry;
+use xmas_elf::symbol_table::{Entry, Entry64, Type};
 use xmas_elf::ElfFile;
 
 use crate::mem::paging::{Translate, VirtAddr};
@@ -62,44 +63,163 @@ pub fn prepare_panic() {
     }
 }
 
+/// The symbol table of the kernel, used to symbolize the backtraces.
 pub struct UnwindInfo {
-    kernel_elf: ElfFile<'static>,
+    symbol_table: &'static [Entry64],
+    /// The string table that the names of the symbols index into.
+    string_table: &'static [u8],
 }
 
 impl UnwindInfo {
     pub fn new(elf: ElfFile<'static>) -> Self {
-        Self { kernel_elf: elf }
+        let symbol_table = elf
+            .section_iter()
+            .find(|section| section.get_type() == Ok(ShType::SymTab));
+
+        let symbol_table = match symbol_table {
+            Some(section) => section,
+            // The kernel image is stripped.
+            None => return Self::from_tables(&[], &[]),
+        };
+
+        let symbols = match symbol_table.get_data(&elf) {
+            Ok(SectionData::SymbolTable64(symbols)) => symbols,
+            _ => &[],
+        };
+
+        let strings = elf
+            .section_header(symbol_table.link() as u16)
+            .map_or(&[][..], |section| section.raw_data(&elf));
+
+        Self::from_tables(symbols, strings)
+    }
+
+    /// Creates the unwind info from the symbol table and its string table, for example
+    /// the sections loaded by a Multiboot2 bootloader.
+    pub fn from_tables(symbol_table: &'static [Entry64], string_table: &'static [u8]) -> Self {
+        Self {
+            symbol_table,
+            string_table,
+        }
+    }
+
+    fn symbol_name(&self, symbol: &Entry64) -> Option<&'static str> {
+        let name = self.string_table.get(symbol.name() as usize..)?;
+        let len = name.iter().position(|&c| c == 0)?;
+
+        core::str::from_utf8(&name[..len]).ok()
     }
 }
 
-pub fn unwind_stack_trace() {
-    let _guard = IrqGuard::new();
+/// The maximum amount of frames printed in a backtrace.
+const MAX_BACKTRACE_DEPTH: usize = 64;
 
-    let mut address_space = AddressSpace::this();
-    let offset_table = address_space.offset_page_table();
+fn symbol_table() -> Option<&'static [Entry64]> {
+    let symbol_table = UNWIND_INFO.get()?.symbol_table;
+
+    if symbol_table.is_empty() {
+        None
+    } else {
+        Some(symbol_table)
+    }
+}
+
+/// Looks up `address` in a symbol map, which has one `<address> <size> <name>` line
+/// (with the address and size in hexadecimal) per function, sorted by address. The
+/// stripped boot images are symbolized with the map loaded through `symbols=<module>`.
+fn resolve_from_map(map: &[u8], address: usize) -> Option<(&str, usize)> {
+    let map = core::str::from_utf8(map).ok()?;
+    let mut best = None;
+
+    for line in map.lines() {
+        let mut fields = line.splitn(3, ' ');
+
+        let start = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let size = usize::from_str_radix(fields.next()?, 16).ok()?;
+        let name = fields.next()?;
+
+        // The map is sorted, so none of the following symbols can contain the address.
+        if start > address {
+            break;
+        }
+
+        if size == 0 || address < start + size {
+            best = Some((name, address - start));
+        }
+    }
+
+    best
+}
+
+/// Resolves `address` to the name of the function containing it and the offset of the
+/// address into the function. This does not allocate as it is used while panicking.
+fn resolve_symbol(address: usize) -> Option<(&'static str, usize)> {
+    let mut best: Option<&Entry64> = None;
+
+    let symbol_table = match symbol_table() {
+        Some(symbol_table) => symbol_table,
+        None => return resolve_from_map(crate::cmdline::try_get()?.symbols?, address),
+    };
+
+    for symbol in symbol_table {
+        if symbol.get_type() != Ok(Type::Func) {
+            continue;
+        }
+
+        let start = symbol.value() as usize;
+        let size = symbol.size() as usize;
+
+        // Symbols without a size (for example the ones defined in assembly) are matched
+        // if they are the closest symbol before the address.
+        if address < start || (size != 0 && address >= start + size) {
+            continue;
+        }
+
+        if best.map_or(true, |best| best.value() < symbol.value()) {
+            best = Some(symbol);
+        }
+    }
+
+    let symbol = best?;
+    let name = UNWIND_INFO.get()?.symbol_name(symbol)?;
+
+    Some((name, address - symbol.value() as usize))
+}
+
+/// Wrapper around an address that formats it together with the function it belongs to.
+pub struct SymbolizedAddress(pub usize);
 
-    let kernel_elf = &UNWIND_INFO.get().unwrap().kernel_elf;
-    let mut symbol_table = None;
+impl fmt::Display for SymbolizedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x} - ", self.0)?;
 
-    for section in kernel_elf.section_iter() {
-        if section.get_type() == Ok(ShType::SymTab) {
-            let section_data = section
-                .get_data(&kernel_elf)
-                .expect("Failed to get kernel section data information");
+        match resolve_symbol(self.0) {
+            // The alternate format omits the hash suffix from the demangled name.
+            Some((name, offset)) => write!(f, "{:#}+{:#x}", rustc_demangle::demangle(name), offset),
 
-            if let SectionData::SymbolTable64(symtab) = section_data {
-                symbol_table = Some(symtab);
-            }
+            None => write!(f, "<unknown>"),
         }
     }
+}
 
-    let symbol_table = symbol_table.unwrap();
-    let mut rbp: usize;
+pub fn unwind_stack_trace() {
+    let rbp: usize;
 
     unsafe {
         asm!("mov {}, rbp", out(reg) rbp);
     }
 
+    unwind_stack_trace_from(rbp);
+}
+
+/// Walks the frame pointer chain starting at the provided frame pointer and logs the
+/// return addresses along with the functions they belong to.
+pub fn unwind_stack_trace_from(mut rbp: usize) {
+    let _guard = IrqGuard::new();
+
+    let mut address_space = AddressSpace::this();
+    let offset_table = address_space.offset_page_table();
+
     // Make sure the RBP is not NULL. If it is then we cannot do the stack unwinding/tracing
     // as no frame pointers were emmited in this build. This should only occur if you
     // set the field `eliminate-frame-pointer` in the target file to true or manually resetting
@@ -112,47 +232,39 @@ pub fn unwind_stack_trace() {
 
     log::trace!("{:-^80}", " BACKTRACE ");
 
-    for depth in 0../*64*/16 {
-        if let Some(rip_rbp) = rbp.checked_add(core::mem::size_of::<usize>()) {
-            if offset_table
-                .translate_addr(VirtAddr::new(rip_rbp as u64))
-                .is_none()
-            {
-                log::trace!("{:>2}: <guard page>", depth);
-                break;
-            }
-
-            let rip = unsafe { *(rip_rbp as *const usize) };
-
-            if rip == 0 {
-                break;
-            }
-
-            unsafe {
-                rbp = *(rbp as *const usize);
-            }
-
-            let mut name = None;
-
-            for data in symbol_table {
-                let st_value = data.value() as usize;
-                let st_size = data.size() as usize;
-
-                if rip >= st_value && rip < (st_value + st_size) {
-                    let mangled_name = data.get_name(&kernel_elf).unwrap_or("<unknown>");
-                    let demangled_name = rustc_demangle::demangle(mangled_name);
-
-                    name = Some(demangled_name);
-                }
-            }
-
-            if let Some(name) = name {
-                log::trace!("{:>2}: 0x{:016x} - {}", depth, rip, name);
-            } else {
-                log::trace!("{:>2}: 0x{:016x} - <unknown>", depth, rip);
-            }
-        } else {
-            // RBP has been overflowed...
+    let is_mapped = |address: usize| {
+        offset_table
+            .translate_addr(VirtAddr::new(address as u64))
+            .is_some()
+    };
+
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        // The frame pointer has to be aligned and both the saved frame pointer and the
+        // return address have to be mapped.
+        let rip_rbp = match rbp.checked_add(core::mem::size_of::<usize>()) {
+            Some(rip_rbp) if rbp % core::mem::align_of::<usize>() == 0 => rip_rbp,
+            // RBP has been overflowed or is corrupted...
+            _ => break,
+        };
+
+        if !is_mapped(rbp) || !is_mapped(rip_rbp) {
+            log::trace!("{:>2}: <guard page>", depth);
+            break;
+        }
+
+        let rip = unsafe { *(rip_rbp as *const usize) };
+
+        if rip == 0 {
+            break;
+        }
+
+        unsafe {
+            rbp = *(rbp as *const usize);
+        }
+
+        log::trace!("{:>2}: {}", depth, SymbolizedAddress(rip));
+
+        if rbp == 0 {
             break;
         }
     }
@@ -189,8 +301,9 @@ extern "C" fn rust_begin_unwind(info: &PanicInfo) -> ! {
 
     unwind_stack_trace();
 
+    // A panic fails the test suite.
     #[cfg(feature = "ci")]
-    emu::exit_qemu(emu::ExitStatus::Success);
+    emu::exit_qemu(emu::ExitStatus::Failure);
 
     #[cfg(not(feature = "ci"))]
     unsafe {
@@ -232,3 +345,21 @@ extern "C" fn rust_eh_personality() -> ! {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_map_lookup() {
+        let map = b"1000 10 kernel_main\n1010 0 trampoline\n2000 20 rust_begin_unwind\n";
+
+        assert_eq!(resolve_from_map(map, 0x1004), Some(("kernel_main", 4)));
+        assert_eq!(resolve_from_map(map, 0x1800), Some(("trampoline", 0x7f0)));
+        assert_eq!(
+            resolve_from_map(map, 0x2010),
+            Some(("rust_begin_unwind", 0x10))
+        );
+        assert_eq!(resolve_from_map(map, 0x800), None);
+    }
+}
@@ -0,0 +1,410 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Task groups, in the spirit of the cgroup v2 controllers. The groups form a hierarchy
+//! rooted at `/` and each task belongs to one group. A child starts in the group of its
+//! parent. The controllers are:
+//!
+//! * `cpu.weight` (1 to 10000, 100 by default): the CPU time used by the tasks of the
+//!   group is accounted to it, divided by its weight. The scheduler runs the tasks of the
+//!   group with the least weighted runtime first, so the groups share the CPU by weight,
+//!   whatever their amount of tasks. The weights multiply along the ancestors, so a
+//!   nested group shares the weight of its parent.
+//! * `memory.max` (in bytes): the pages the processes of the group, nested groups
+//!   included, have resident are charged to it. A page fault that would exceed the limit
+//!   of the group or of one of its ancestors fails, and `mmap` and `fork` fail with
+//!   `ENOMEM` if the new mapping or the copied pages would exceed it.
+//!
+//! The groups are configured by writing commands to `/sys/kernel/cgroups`, one per line:
+//!
+//! * `create <path>`: creates a group, whose parent has to exist.
+//! * `remove <path>`: removes a group without tasks or nested groups.
+//! * `set <path> cpu.weight <weight>` and `set <path> memory.max <bytes|max>`.
+//! * `attach <path> <pid>`: moves the task `pid` into the group.
+//!
+//! Reading the file lists the groups with their settings and usage.
+
+use aero_syscall::SyscallError;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::utils::sync::Mutex;
+
+use super::pid_namespace;
+use super::scheduler;
+use super::task::Task;
+
+const DEFAULT_WEIGHT: usize = 100;
+const MAX_WEIGHT: usize = 10000;
+const PAGE_SIZE: u64 = 4096;
+
+pub struct TaskGroup {
+    /// The absolute path of the group.
+    path: String,
+    parent: Option<Arc<TaskGroup>>,
+    children: Mutex<BTreeMap<String, Arc<TaskGroup>>>,
+
+    cpu_weight: AtomicUsize,
+    /// The CPU time used by the tasks of the group and of its nested groups, and by the
+    /// tasks of the group divided by its weight, in nanoseconds.
+    runtime: AtomicU64,
+    vruntime: AtomicU64,
+
+    memory_max: AtomicU64,
+    /// The amount of pages charged to the group and its nested groups.
+    pages: AtomicU64,
+}
+
+impl TaskGroup {
+    fn new(parent: Option<Arc<TaskGroup>>, path: String) -> Self {
+        Self {
+            path,
+            parent,
+            children: Mutex::new(BTreeMap::new()),
+
+            cpu_weight: AtomicUsize::new(DEFAULT_WEIGHT),
+            runtime: AtomicU64::new(0),
+            vruntime: AtomicU64::new(0),
+
+            memory_max: AtomicU64::new(u64::MAX),
+            pages: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the root group, which all of the tasks start in.
+    pub fn root() -> Arc<TaskGroup> {
+        lazy_static::lazy_static! {
+            static ref ROOT: Arc<TaskGroup> = Arc::new(TaskGroup::new(None, String::from("/")));
+        }
+
+        ROOT.clone()
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item = &TaskGroup> {
+        core::iter::successors(Some(self), |group| group.parent.as_deref())
+    }
+
+    /// Returns whether `group` is this group or nested in it.
+    pub fn contains(&self, group: &TaskGroup) -> bool {
+        group
+            .ancestors()
+            .any(|ancestor| core::ptr::eq(ancestor, self))
+    }
+
+    fn find(self: &Arc<Self>, path: &str) -> Option<Arc<TaskGroup>> {
+        let mut group = self.clone();
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let child = group.children.lock_irq().get(name).cloned()?;
+            group = child;
+        }
+
+        Some(group)
+    }
+
+    fn create(self: &Arc<Self>, path: &str) -> Option<Arc<TaskGroup>> {
+        let (parent, name) = path.trim_end_matches('/').rsplit_once('/')?;
+        let parent = self.find(parent)?;
+
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut children = parent.children.lock_irq();
+
+        if children.contains_key(name) {
+            return None;
+        }
+
+        let path = if parent.parent.is_none() {
+            alloc::format!("/{name}")
+        } else {
+            alloc::format!("{}/{name}", parent.path)
+        };
+
+        let group = Arc::new(TaskGroup::new(Some(parent.clone()), path));
+        children.insert(String::from(name), group.clone());
+
+        Some(group)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Option<()> {
+        // The root group is not limited.
+        self.parent.as_ref()?;
+
+        match key {
+            "cpu.weight" => {
+                let weight = value.parse::<usize>().ok()?;
+
+                if !(1..=MAX_WEIGHT).contains(&weight) {
+                    return None;
+                }
+
+                self.cpu_weight.store(weight, Ordering::Relaxed);
+            }
+
+            "memory.max" => {
+                let max = match value {
+                    "max" => u64::MAX,
+                    _ => value.parse::<u64>().ok()?,
+                };
+
+                self.memory_max.store(max, Ordering::Relaxed);
+            }
+
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Returns the weight of the group, multiplied by the relative weights of its
+    /// ancestors.
+    fn effective_weight(&self) -> usize {
+        self.ancestors()
+            .filter(|group| group.parent.is_some())
+            .fold(DEFAULT_WEIGHT, |weight, group| {
+                let weight = weight * group.cpu_weight.load(Ordering::Relaxed) / DEFAULT_WEIGHT;
+                weight.min(MAX_WEIGHT)
+            })
+    }
+
+    /// Accounts `ns` nanoseconds of CPU time used by a task of the group.
+    pub fn charge_runtime(&self, ns: u64) {
+        let weighted = ns * DEFAULT_WEIGHT as u64 / self.effective_weight() as u64;
+        self.vruntime.fetch_add(weighted, Ordering::Relaxed);
+
+        for group in self.ancestors() {
+            group.runtime.fetch_add(ns, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the weighted runtime of the group. A group that has not run for a while
+    /// is moved up to `floor` first, so that it does not get the CPU to itself until it
+    /// catches up with the groups that kept running.
+    pub fn vruntime(&self, floor: u64) -> u64 {
+        self.vruntime.fetch_max(floor, Ordering::Relaxed).max(floor)
+    }
+
+    /// Charges `pages` pages to the group and its ancestors. Fails with `ENOMEM`, without
+    /// charging them, if that would exceed the memory limit of one of them.
+    pub fn try_charge(&self, pages: u64) -> Result<(), SyscallError> {
+        for (i, group) in self.ancestors().enumerate() {
+            let charged = group.pages.fetch_add(pages, Ordering::Relaxed) + pages;
+            let max = group.memory_max.load(Ordering::Relaxed);
+
+            if max != u64::MAX && charged.saturating_mul(PAGE_SIZE) > max {
+                self.ancestors()
+                    .take(i + 1)
+                    .for_each(|group| group.uncharge_one(pages));
+
+                return Err(SyscallError::ENOMEM);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Charges `pages` pages to the group and its ancestors, whatever their limits.
+    pub fn charge(&self, pages: u64) {
+        for group in self.ancestors() {
+            group.pages.fetch_add(pages, Ordering::Relaxed);
+        }
+    }
+
+    /// Releases `pages` pages charged to the group and its ancestors.
+    pub fn uncharge(&self, pages: u64) {
+        self.ancestors().for_each(|group| group.uncharge_one(pages));
+    }
+
+    fn uncharge_one(&self, pages: u64) {
+        self.pages.fetch_sub(pages, Ordering::Relaxed);
+    }
+
+    /// Returns the memory charged to the group and its nested groups, in bytes.
+    fn memory_current(&self) -> u64 {
+        self.pages.load(Ordering::Relaxed) * PAGE_SIZE
+    }
+}
+
+/// Fails with `ENOMEM` if charging `size` more bytes to the group of `task` would exceed
+/// its memory limit or the limit of one of the ancestors.
+pub fn check_memory(task: &Task, size: u64) -> Result<(), SyscallError> {
+    let group = task.vm().group();
+
+    for group in group.ancestors() {
+        let max = group.memory_max.load(Ordering::Relaxed);
+
+        if max != u64::MAX && group.memory_current().saturating_add(size) > max {
+            return Err(SyscallError::ENOMEM);
+        }
+    }
+
+    Ok(())
+}
+
+fn run(command: &str) -> Option<()> {
+    let root = TaskGroup::root();
+    let mut words = command.split_whitespace();
+
+    match (words.next()?, words.next()?) {
+        ("create", path) => {
+            root.create(path)?;
+        }
+
+        ("remove", path) => {
+            let group = root.find(path)?;
+            let parent = group.parent.as_ref()?;
+
+            let busy = !group.children.lock_irq().is_empty()
+                || !scheduler::get_scheduler()
+                    .find_task_group(&group)
+                    .is_empty();
+
+            if busy {
+                return None;
+            }
+
+            let name = path.trim_end_matches('/').rsplit_once('/')?.1;
+            parent.children.lock_irq().remove(name);
+        }
+
+        ("set", path) => {
+            let group = root.find(path)?;
+            group.set(words.next()?, words.next()?)?;
+        }
+
+        ("attach", path) => {
+            let group = root.find(path)?;
+            let pid = words.next()?.parse::<usize>().ok()?;
+
+            pid_namespace::find_task(pid)?.set_cgroup(group);
+        }
+
+        _ => return None,
+    }
+
+    words.next().is_none().then_some(())
+}
+
+/// Runs the configuration commands, one per line. Returns [`None`] if a command is
+/// invalid, in which case the following commands are not run.
+pub fn configure(commands: &str) -> Option<()> {
+    commands
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .try_for_each(run)
+}
+
+/// Returns the groups, one per line, with their settings and usage.
+pub fn describe() -> String {
+    fn describe_group(group: &Arc<TaskGroup>, output: &mut String) {
+        let max = group.memory_max.load(Ordering::Relaxed);
+        let tasks = scheduler::get_scheduler().find_task_group(group).len();
+
+        let _ = write!(
+            output,
+            "{} cpu.weight={} cpu.usage_usec={} memory.current={} memory.max=",
+            group.path,
+            group.cpu_weight.load(Ordering::Relaxed),
+            group.runtime.load(Ordering::Relaxed) / 1000,
+            group.memory_current(),
+        );
+
+        let _ = if max == u64::MAX {
+            writeln!(output, "max tasks={tasks}")
+        } else {
+            writeln!(output, "{max} tasks={tasks}")
+        };
+
+        for child in group.children.lock_irq().values() {
+            describe_group(child, output);
+        }
+    }
+
+    let mut output = String::new();
+    describe_group(&TaskGroup::root(), &mut output);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_group_weights() {
+        let root = Arc::new(TaskGroup::new(None, String::from("/")));
+
+        let build = root.create("/build").unwrap();
+        let jobs = root.create("/build/jobs").unwrap();
+
+        assert!(root.create("/missing/jobs").is_none());
+        assert!(root.create("/build").is_none());
+        assert!(root.set("cpu.weight", "50").is_none());
+
+        build.set("cpu.weight", "50").unwrap();
+        jobs.set("cpu.weight", "50").unwrap();
+        assert!(jobs.set("cpu.weight", "0").is_none());
+
+        assert_eq!(jobs.path, "/build/jobs");
+
+        // The same CPU time weighs twice as much in a group with half the weight.
+        root.charge_runtime(1000);
+        build.charge_runtime(1000);
+        jobs.charge_runtime(1000);
+
+        assert_eq!(root.vruntime(0), 1000);
+        assert_eq!(build.vruntime(0), 2000);
+        assert_eq!(jobs.vruntime(0), 4000);
+        assert_eq!(root.runtime.load(Ordering::Relaxed), 3000);
+
+        // A group that fell behind is moved up to the floor.
+        assert_eq!(root.vruntime(3000), 3000);
+
+        assert!(build.contains(&jobs));
+        assert!(!jobs.contains(&build));
+    }
+
+    #[test]
+    fn task_group_memory_charge() {
+        let root = Arc::new(TaskGroup::new(None, String::from("/")));
+        let build = root.create("/build").unwrap();
+        let jobs = root.create("/build/jobs").unwrap();
+
+        build.set("memory.max", "8192").unwrap();
+
+        jobs.try_charge(2).unwrap();
+        assert_eq!(build.memory_current(), 8192);
+        assert_eq!(root.memory_current(), 8192);
+
+        // Exceeding the limit of an ancestor does not charge anything.
+        assert!(jobs.try_charge(1).is_err());
+        assert_eq!(jobs.memory_current(), 8192);
+        assert_eq!(root.memory_current(), 8192);
+
+        jobs.uncharge(2);
+        assert_eq!(root.memory_current(), 0);
+    }
+}
@@ -20,15 +20,26 @@
 use crate::fs;
 use crate::fs::Path;
 
+pub mod cgroup;
+pub mod pid_namespace;
+pub mod ptrace;
+pub mod rlimit;
 pub mod scheduler;
+pub mod seccomp;
 pub mod signals;
 pub mod task;
+pub mod terminal;
+#[cfg(target_arch = "x86_64")]
+pub mod vdso;
 pub mod vm;
 
 pub fn run() -> fs::Result<()> {
-    let init_path = Path::new("/usr/bin/init");
+    let init_path = Path::new(crate::cmdline::get().init);
     let init_inode = fs::lookup_path(init_path)?;
 
+    // The last milestone of `./aero.py --bench-boot`.
+    log::info!("executing the init process {}", init_path.as_str());
+
     scheduler::get_scheduler().exec(init_inode, None, None);
     Ok(())
 }
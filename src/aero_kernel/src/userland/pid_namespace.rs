@@ -0,0 +1,207 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! PID namespaces. A task has a PID in its own namespace and in each of the ancestors of
+//! it, and only sees the tasks of its namespace and of the namespaces nested in it. The
+//! PIDs in the root namespace are the global task IDs.
+//!
+//! `unshare(CLONE_NEWPID)` creates a namespace for the children created afterwards, so
+//! the next child becomes the init (PID 1) of the new namespace. When it exits, the
+//! other tasks of the namespace are killed.
+//!
+//! The process group and session IDs are the PIDs of their leader, so they are
+//! translated the same way (see [`Pid`]). They stay valid after the leader exits.
+
+use aero_syscall::signal::SIGKILL;
+use aero_syscall::SyscallError;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils::sync::Mutex;
+
+use super::scheduler;
+use super::task::{Task, TaskId};
+
+/// The maximum nesting depth of the PID namespaces.
+const MAX_LEVEL: usize = 32;
+
+pub struct PidNamespace {
+    parent: Option<Arc<PidNamespace>>,
+    level: usize,
+    next_pid: AtomicUsize,
+    /// The tasks of this namespace and of the nested ones, by their PID in this namespace.
+    /// Unused in the root namespace, where the scheduler is used instead.
+    tasks: Mutex<BTreeMap<usize, Weak<Task>>>,
+}
+
+impl PidNamespace {
+    fn new(parent: Option<Arc<PidNamespace>>, level: usize) -> Self {
+        Self {
+            parent,
+            level,
+            next_pid: AtomicUsize::new(1),
+            tasks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the root PID namespace.
+    pub fn root() -> Arc<PidNamespace> {
+        lazy_static::lazy_static! {
+            static ref ROOT: Arc<PidNamespace> = Arc::new(PidNamespace::new(None, 0));
+        }
+
+        ROOT.clone()
+    }
+
+    /// Creates a namespace nested in this one.
+    pub fn new_child(self: &Arc<Self>) -> Result<Arc<PidNamespace>, SyscallError> {
+        if self.level == MAX_LEVEL {
+            return Err(SyscallError::ENOSPC);
+        }
+
+        Ok(Arc::new(Self::new(Some(self.clone()), self.level + 1)))
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item = &PidNamespace> {
+        core::iter::successors(Some(self), |ns| ns.parent.as_deref())
+    }
+
+    /// Returns whether the tasks of `ns` are visible from this namespace.
+    pub fn contains(&self, ns: &PidNamespace) -> bool {
+        ns.ancestors().any(|ancestor| core::ptr::eq(ancestor, self))
+    }
+
+    /// Allocates the PIDs of a task of this namespace with the global `pid`, indexed
+    /// by the level of the namespace.
+    pub(super) fn alloc_pids(&self, pid: TaskId) -> Box<[usize]> {
+        let mut pids = alloc::vec![0; self.level + 1];
+        pids[0] = pid.as_usize();
+
+        for ns in self.ancestors().filter(|ns| ns.level > 0) {
+            pids[ns.level] = ns.next_pid.fetch_add(1, Ordering::AcqRel);
+        }
+
+        pids.into_boxed_slice()
+    }
+
+    /// Makes the `task` visible in this namespace and in its ancestors.
+    pub(super) fn register(&self, task: &Arc<Task>, pids: &[usize]) {
+        for ns in self.ancestors().filter(|ns| ns.level > 0) {
+            let mut tasks = ns.tasks.lock_irq();
+
+            tasks.retain(|_, task| task.strong_count() > 0);
+            tasks.insert(pids[ns.level], Arc::downgrade(task));
+        }
+    }
+
+    /// Returns the task with the PID `pid` in this namespace.
+    pub fn find_task(&self, pid: usize) -> Option<Arc<Task>> {
+        if self.level == 0 {
+            scheduler::get_scheduler().find_task(TaskId::new(pid))
+        } else {
+            self.tasks.lock_irq().get(&pid).and_then(Weak::upgrade)
+        }
+    }
+
+    /// Returns the global task ID of the task with the PID `pid` in this namespace or
+    /// [`None`] if there is no such task.
+    pub fn global_pid(&self, pid: usize) -> Option<usize> {
+        if self.level == 0 {
+            Some(pid)
+        } else {
+            self.find_task(pid).map(|task| task.pid().as_usize())
+        }
+    }
+
+    /// Kills all of the tasks of the namespace (and of the nested ones) except `init`.
+    pub(super) fn kill_all(&self, init: &Task) {
+        let tasks = self.tasks.lock_irq();
+
+        for task in tasks.values().filter_map(Weak::upgrade) {
+            if !core::ptr::eq(&*task, init) {
+                task.signal(SIGKILL);
+            }
+        }
+    }
+}
+
+/// The PIDs of a task in its PID namespace and in the ancestors of it, indexed by the
+/// level of the namespace.
+#[derive(Clone)]
+pub struct Pid {
+    namespace: Arc<PidNamespace>,
+    pids: Box<[usize]>,
+}
+
+impl Pid {
+    pub(super) fn new(namespace: Arc<PidNamespace>, pids: Box<[usize]>) -> Self {
+        Self { namespace, pids }
+    }
+
+    /// Returns the global ID (the ID in the root namespace).
+    pub fn global(&self) -> usize {
+        self.pids[0]
+    }
+
+    /// Returns the ID in the namespace `ns` or [`None`] if it is not visible from it.
+    pub fn nr_in(&self, ns: &PidNamespace) -> Option<usize> {
+        ns.contains(&self.namespace).then(|| self.pids[ns.level])
+    }
+}
+
+/// Looks up the task `pid` in the PID namespace of the current task.
+pub fn find_task(pid: usize) -> Option<Arc<Task>> {
+    let current = scheduler::get_scheduler().current_task();
+    current.pid_namespace().find_task(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_namespace_pids() {
+        let root = PidNamespace::root();
+        let child = root.new_child().unwrap();
+        let grandchild = child.new_child().unwrap();
+
+        assert_eq!(&*child.alloc_pids(TaskId::new(42)), &[42, 1]);
+        assert_eq!(&*grandchild.alloc_pids(TaskId::new(43)), &[43, 2, 1]);
+
+        let pid = Pid::new(grandchild.clone(), grandchild.alloc_pids(TaskId::new(44)));
+
+        assert_eq!(pid.nr_in(&child), Some(3));
+        assert_eq!(pid.nr_in(&root), Some(44));
+
+        let outer = Pid::new(child.clone(), child.alloc_pids(TaskId::new(45)));
+        assert_eq!(outer.nr_in(&grandchild), None);
+
+        assert!(root.contains(&grandchild));
+        assert!(child.contains(&grandchild));
+        assert!(!grandchild.contains(&child));
+    }
+}
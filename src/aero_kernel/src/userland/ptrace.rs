@@ -0,0 +1,416 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Process tracing (`ptrace`), used by debuggers and `strace`.
+//!
+//! A traced task stops before a signal is delivered to it and, if the tracer resumed it
+//! with `PTRACE_SYSCALL`, at the entry and exit of each syscall. The stops are reported
+//! to the tracer through `waitpid` with the status of a stopped child (`(signal << 8) |
+//! 0x7f`), where the syscall-stops report `SIGTRAP | 0x80`. While the tracee is stopped,
+//! the tracer can access its registers and memory until it resumes the tracee with
+//! `PTRACE_CONT` or `PTRACE_SYSCALL`.
+//!
+//! The memory of the tracee is only accessible from its own address space, so the tracer
+//! posts the `PTRACE_PEEKDATA` and `PTRACE_POKEDATA` requests to the stopped tracee,
+//! which performs the access and replies.
+
+use aero_syscall::prelude::PtraceRegs;
+use aero_syscall::signal::{SIGKILL, SIGSTOP, SIGTRAP};
+use aero_syscall::SyscallError;
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::mem::paging::VirtAddr;
+use crate::mem::uaccess;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::pid_namespace::{self, PidNamespace};
+use super::scheduler;
+use super::task::Task;
+
+/// A memory access posted by the tracer to the stopped tracee.
+#[derive(Debug, Copy, Clone)]
+enum Request {
+    Peek(VirtAddr),
+    Poke(VirtAddr, u64),
+}
+
+struct Stop {
+    /// The wait status reported to the tracer.
+    status: u32,
+    /// Whether the stop was already reported through `waitpid`.
+    reported: bool,
+    /// The registers of the tracee at the stop, written back when it is resumed.
+    regs: PtraceRegs,
+    /// Set by the tracer to resume the tracee, with the signal to deliver (or 0).
+    resume: Option<usize>,
+}
+
+#[derive(Default)]
+struct PtraceState {
+    tracer: Option<Weak<Task>>,
+    stop: Option<Stop>,
+    request: Option<Request>,
+    reply: Option<Result<u64, SyscallError>>,
+}
+
+pub struct Ptrace {
+    state: Mutex<PtraceState>,
+    /// Set if the tracer asked for syscall-stops (`PTRACE_SYSCALL`), until the tracee is
+    /// resumed with `PTRACE_CONT`.
+    syscalls: AtomicBool,
+    /// The tasks traced by this task.
+    tracees: Mutex<Vec<Weak<Task>>>,
+    /// Woken when the tracer posts a request or resumes the tracee.
+    tracee_queue: WaitQueue,
+    /// Woken when the tracee replied to a request.
+    tracer_queue: WaitQueue,
+}
+
+impl Ptrace {
+    pub(super) fn new() -> Self {
+        Self {
+            state: Mutex::new(PtraceState::default()),
+            syscalls: AtomicBool::new(false),
+            tracees: Mutex::new(Vec::new()),
+            tracee_queue: WaitQueue::new(),
+            tracer_queue: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the task tracing this task.
+    pub fn tracer(&self) -> Option<Arc<Task>> {
+        self.state
+            .lock_irq()
+            .tracer
+            .as_ref()
+            .and_then(Weak::upgrade)
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.tracer().is_some()
+    }
+
+    /// Returns whether the task has to stop at the entry and exit of the syscalls.
+    pub fn traces_syscalls(&self) -> bool {
+        self.syscalls.load(Ordering::Relaxed)
+    }
+
+    /// Returns the pid and the wait status of a tracee of this task matching `pid` (or
+    /// any tracee if `pid` is -1) that stopped and whose stop was not reported yet.
+    pub(super) fn take_stopped(
+        &self,
+        pid: isize,
+        namespace: &PidNamespace,
+    ) -> Option<(usize, u32)> {
+        let tracees = self.tracees.lock_irq();
+
+        for tracee in tracees.iter().filter_map(Weak::upgrade) {
+            let tracee_pid = tracee.pid_in(namespace).unwrap_or(0);
+
+            if pid != -1 && tracee_pid != pid as usize {
+                continue;
+            }
+
+            let mut state = tracee.ptrace().state.lock_irq();
+
+            if let Some(stop) = state.stop.as_mut().filter(|stop| !stop.reported) {
+                stop.reported = true;
+                return Some((tracee_pid, stop.status));
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns the wait status of a task stopped by the `signal`.
+pub fn stop_status(signal: usize) -> u32 {
+    ((signal as u32) << 8) | 0x7f
+}
+
+/// Returns the wait status of a syscall-stop.
+pub fn syscall_stop_status() -> u32 {
+    stop_status(SIGTRAP | 0x80)
+}
+
+/// Stops the current task with the wait `status` and reports the stop to its tracer.
+/// The tracer can modify the `regs` until it resumes the task. Returns the signal the
+/// task was resumed with (0 if none).
+pub fn stop(status: u32, regs: &mut PtraceRegs) -> usize {
+    let task = scheduler::get_scheduler().current_task();
+    let ptrace = task.ptrace();
+
+    let tracer = match ptrace.tracer() {
+        Some(tracer) => tracer,
+        None => return 0,
+    };
+
+    ptrace.state.lock_irq().stop = Some(Stop {
+        status,
+        reported: false,
+        regs: *regs,
+        resume: None,
+    });
+
+    tracer.notify_waiters();
+
+    loop {
+        let result = ptrace.tracee_queue.wait_until(&ptrace.state, |state| {
+            state.request.is_some() || state.stop.as_ref().map_or(true, |s| s.resume.is_some())
+        });
+
+        let mut state = match result {
+            Ok(state) => state,
+            Err(_) => {
+                // Only SIGKILL ends the stop. The other signals are reported to the
+                // tracer once the task is resumed.
+                if task.signals().is_pending(SIGKILL as u64) {
+                    ptrace.state.lock_irq().stop = None;
+                    ptrace.tracer_queue.wake_all();
+
+                    task.signals().clear_pending(SIGKILL as u64);
+                    scheduler::get_scheduler().exit(1);
+                }
+
+                continue;
+            }
+        };
+
+        if let Some(request) = state.request.take() {
+            core::mem::drop(state);
+
+            let reply = match request {
+                Request::Peek(address) => unsafe { uaccess::read_user::<u64>(address) },
+                Request::Poke(address, value) => {
+                    if task.vm().write_forced(address, &value.to_ne_bytes()) {
+                        Ok(0)
+                    } else {
+                        Err(SyscallError::EIO)
+                    }
+                }
+            };
+
+            ptrace.state.lock_irq().reply = Some(reply);
+            ptrace.tracer_queue.wake_all();
+            continue;
+        }
+
+        return match state.stop.take() {
+            Some(stop) => {
+                *regs = stop.regs;
+                stop.resume.unwrap_or(0)
+            }
+
+            None => 0,
+        };
+    }
+}
+
+fn attach(tracee: &Arc<Task>, tracer: &Arc<Task>) -> Result<(), SyscallError> {
+    if tracee.pid() == tracer.pid() {
+        return Err(SyscallError::EPERM);
+    }
+
+    {
+        let mut state = tracee.ptrace().state.lock_irq();
+
+        if state.tracer.as_ref().and_then(Weak::upgrade).is_some() {
+            return Err(SyscallError::EPERM);
+        }
+
+        state.tracer = Some(Arc::downgrade(tracer));
+    }
+
+    tracee.ptrace().syscalls.store(false, Ordering::Relaxed);
+    tracer
+        .ptrace()
+        .tracees
+        .lock_irq()
+        .push(Arc::downgrade(tracee));
+
+    Ok(())
+}
+
+/// Stops tracing the `tracee`, resuming it with the `signal` if it is stopped.
+fn detach(tracee: &Task, signal: usize) {
+    let ptrace = tracee.ptrace();
+    let tracer = {
+        let mut state = ptrace.state.lock_irq();
+
+        if let Some(stop) = state.stop.as_mut() {
+            stop.resume = Some(signal);
+        }
+
+        state.tracer.take().and_then(|tracer| tracer.upgrade())
+    };
+
+    ptrace.syscalls.store(false, Ordering::Relaxed);
+    ptrace.tracee_queue.wake_all();
+
+    if let Some(tracer) = tracer {
+        tracer
+            .ptrace()
+            .tracees
+            .lock_irq()
+            .retain(|t| t.upgrade().map_or(false, |t| t.pid() != tracee.pid()));
+
+        tracer.notify_waiters();
+    }
+}
+
+/// Detaches the exiting `task` from its tracer and from its tracees.
+pub(super) fn exit(task: &Task) {
+    detach(task, 0);
+
+    let tracees = core::mem::take(&mut *task.ptrace().tracees.lock_irq());
+
+    for tracee in tracees.iter().filter_map(Weak::upgrade) {
+        detach(&tracee, 0);
+    }
+}
+
+/// Makes the current task traced by its parent (`PTRACE_TRACEME`).
+pub fn traceme() -> Result<(), SyscallError> {
+    let current = scheduler::get_scheduler().current_task();
+    let parent = current.get_parent().ok_or(SyscallError::EPERM)?;
+
+    attach(&current, &parent)
+}
+
+/// Attaches the current task to the task `pid` and stops it (`PTRACE_ATTACH`).
+pub fn attach_to(pid: usize) -> Result<(), SyscallError> {
+    let current = scheduler::get_scheduler().current_task();
+    let tracee = pid_namespace::find_task(pid).ok_or(SyscallError::ESRCH)?;
+
+    attach(&tracee, &current)?;
+    tracee.signal(SIGSTOP);
+
+    Ok(())
+}
+
+/// Returns the task `pid` if it is traced by the current task and, if `stopped` is
+/// set, stopped.
+pub fn get_tracee(pid: usize, stopped: bool) -> Result<Arc<Task>, SyscallError> {
+    let current = scheduler::get_scheduler().current_task();
+    let tracee = pid_namespace::find_task(pid).ok_or(SyscallError::ESRCH)?;
+
+    let state = tracee.ptrace().state.lock_irq();
+    let traced = state
+        .tracer
+        .as_ref()
+        .and_then(Weak::upgrade)
+        .map_or(false, |tracer| tracer.pid() == current.pid());
+
+    if !traced || (stopped && state.stop.is_none()) {
+        return Err(SyscallError::ESRCH);
+    }
+
+    core::mem::drop(state);
+    Ok(tracee)
+}
+
+/// Posts the `request` to the stopped `tracee` and waits for its reply.
+fn post(tracee: &Task, request: Request) -> Result<u64, SyscallError> {
+    let ptrace = tracee.ptrace();
+
+    {
+        let mut state = ptrace.state.lock_irq();
+
+        state.reply = None;
+        state.request = Some(request);
+    }
+
+    ptrace.tracee_queue.wake_all();
+
+    let mut state = ptrace.tracer_queue.wait_until(&ptrace.state, |state| {
+        state.reply.is_some() || state.stop.is_none()
+    })?;
+
+    state.reply.take().unwrap_or(Err(SyscallError::ESRCH))
+}
+
+/// Reads a word at `address` from the memory of the stopped `tracee`.
+pub fn peek(tracee: &Task, address: VirtAddr) -> Result<u64, SyscallError> {
+    post(tracee, Request::Peek(address))
+}
+
+/// Writes a word to `address` in the memory of the stopped `tracee`. Read-only private
+/// mappings (such as the text) are written too, so that breakpoints can be inserted.
+pub fn poke(tracee: &Task, address: VirtAddr, value: u64) -> Result<(), SyscallError> {
+    post(tracee, Request::Poke(address, value)).map(|_| ())
+}
+
+/// Returns the registers of the stopped `tracee`.
+pub fn get_regs(tracee: &Task) -> Result<PtraceRegs, SyscallError> {
+    let state = tracee.ptrace().state.lock_irq();
+    let stop = state.stop.as_ref().ok_or(SyscallError::ESRCH)?;
+
+    Ok(stop.regs)
+}
+
+/// Sets the registers of the stopped `tracee`, loaded when it is resumed.
+pub fn set_regs(tracee: &Task, regs: PtraceRegs) -> Result<(), SyscallError> {
+    let mut state = tracee.ptrace().state.lock_irq();
+    let stop = state.stop.as_mut().ok_or(SyscallError::ESRCH)?;
+
+    stop.regs = regs;
+    Ok(())
+}
+
+/// Resumes the stopped `tracee` with the `signal` (0 for none). If `syscalls` is set, the
+/// tracee stops at the next syscall entry or exit.
+pub fn resume(tracee: &Task, signal: usize, syscalls: bool) -> Result<(), SyscallError> {
+    let ptrace = tracee.ptrace();
+
+    {
+        let mut state = ptrace.state.lock_irq();
+        let stop = state.stop.as_mut().ok_or(SyscallError::ESRCH)?;
+
+        stop.resume = Some(signal);
+    }
+
+    ptrace.syscalls.store(syscalls, Ordering::Relaxed);
+    ptrace.tracee_queue.wake_all();
+
+    Ok(())
+}
+
+/// Stops tracing the stopped `tracee` and resumes it with the `signal` (`PTRACE_DETACH`).
+pub fn detach_from(tracee: &Task, signal: usize) {
+    detach(tracee, signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ptrace_stop_status() {
+        // WIFSTOPPED() and WSTOPSIG() as implemented by the C library.
+        let status = stop_status(SIGSTOP);
+        assert_eq!(status & 0xff, 0x7f);
+        assert_eq!((status >> 8) & 0xff, SIGSTOP as u32);
+
+        assert_eq!((syscall_stop_status() >> 8) & 0xff, (SIGTRAP | 0x80) as u32);
+    }
+}
@@ -0,0 +1,124 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Resource limits (`getrlimit` and `setrlimit`). The limits belong to the process: the
+//! threads share the limits of their process leader and the children inherit a copy.
+//! They are enforced where the resources are allocated:
+//!
+//! * `RLIMIT_NOFILE`: the file descriptor allocator fails with `EMFILE`.
+//! * `RLIMIT_STACK`: the size of the stack mapping created by `exec`, which the stack
+//!   grows into on demand.
+//! * `RLIMIT_AS`: `mmap` fails with `ENOMEM` if the address space would grow past it.
+//! * `RLIMIT_NPROC`: `fork` and `clone` fail with `EAGAIN` once there are as many userland
+//!   tasks. Aero has no users, so all of the tasks are counted.
+//!
+//! The other limits are stored but not enforced.
+
+use aero_syscall::prelude::*;
+
+use crate::utils::sync::Mutex;
+
+const DEFAULT_LIMITS: [(usize, RLimit); 3] = [
+    (RLIMIT_STACK, RLimit::new(8 * 1024 * 1024, RLIM_INFINITY)),
+    (RLIMIT_NPROC, RLimit::new(4096, 4096)),
+    (RLIMIT_NOFILE, RLimit::new(1024, 4096)),
+];
+
+pub struct RLimits(Mutex<[RLimit; RLIMIT_NLIMITS]>);
+
+impl RLimits {
+    pub(super) fn new() -> Self {
+        let mut limits = [RLimit::new(RLIM_INFINITY, RLIM_INFINITY); RLIMIT_NLIMITS];
+
+        for (resource, limit) in DEFAULT_LIMITS {
+            limits[resource] = limit;
+        }
+
+        Self(Mutex::new(limits))
+    }
+
+    /// Returns a copy of the limits, for a child process.
+    pub(super) fn fork(&self) -> Self {
+        Self(Mutex::new(*self.0.lock_irq()))
+    }
+
+    pub fn get(&self, resource: usize) -> Result<RLimit, SyscallError> {
+        self.0
+            .lock_irq()
+            .get(resource)
+            .copied()
+            .ok_or(SyscallError::EINVAL)
+    }
+
+    /// Returns the soft limit of the `resource`.
+    pub fn current(&self, resource: usize) -> u64 {
+        self.0.lock_irq()[resource].rlim_cur
+    }
+
+    /// Updates the limits of the `resource`. The soft limit cannot exceed the hard limit,
+    /// and the hard limit can only be lowered.
+    pub fn set(&self, resource: usize, limit: RLimit) -> Result<(), SyscallError> {
+        let mut limits = self.0.lock_irq();
+        let old = limits.get_mut(resource).ok_or(SyscallError::EINVAL)?;
+
+        if limit.rlim_cur > limit.rlim_max {
+            return Err(SyscallError::EINVAL);
+        }
+
+        if limit.rlim_max > old.rlim_max {
+            return Err(SyscallError::EPERM);
+        }
+
+        *old = limit;
+        Ok(())
+    }
+}
+
+/// Returns whether `value` is within the `limit`.
+pub fn within(value: u64, limit: u64) -> bool {
+    limit == RLIM_INFINITY || value <= limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rlimit_set() {
+        let limits = RLimits::new();
+        assert_eq!(limits.current(RLIMIT_NOFILE), 1024);
+
+        // The soft limit can be raised up to the hard limit.
+        assert!(limits.set(RLIMIT_NOFILE, RLimit::new(4096, 4096)).is_ok());
+        assert_eq!(
+            limits.set(RLIMIT_NOFILE, RLimit::new(8192, 4096)),
+            Err(SyscallError::EINVAL)
+        );
+
+        // The hard limit cannot be raised back once lowered.
+        assert!(limits.set(RLIMIT_NOFILE, RLimit::new(16, 32)).is_ok());
+        assert_eq!(
+            limits.set(RLIMIT_NOFILE, RLimit::new(16, 64)),
+            Err(SyscallError::EPERM)
+        );
+
+        assert!(limits.get(RLIMIT_NLIMITS).is_err());
+        assert!(within(1 << 40, limits.current(RLIMIT_AS)));
+    }
+}
@@ -21,6 +21,7 @@
 pub mod round_robin;
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 use crate::arch::interrupts::{self, InterruptStack};
 use crate::utils::sync::Mutex;
@@ -29,6 +30,8 @@ use crate::{fs::cache::DirCacheItem, syscall::ExecArgs};
 use spin::Once;
 
 use self::round_robin::RoundRobin;
+use super::cgroup::TaskGroup;
+use super::pid_namespace::PidNamespace;
 use super::signals::SignalResult;
 use super::task::{Task, TaskId};
 
@@ -50,7 +53,6 @@ pub trait SchedulerInterface: Send + Sync {
     fn wake_up(&self, task: Arc<Task>);
 
     fn await_io(&self) -> SignalResult<()>;
-    fn sleep(&self, duration: Option<usize>) -> SignalResult<()>;
 
     /// Yields execution to another task.
     fn preempt(&self);
@@ -137,6 +139,39 @@ impl Scheduler {
     pub fn find_task(&self, task_id: TaskId) -> Option<Arc<Task>> {
         self.tasks.0.lock().get(&task_id).map(|task| task.clone())
     }
+
+    /// Returns the amount of userland tasks.
+    pub fn user_task_count(&self) -> usize {
+        self.tasks
+            .0
+            .lock()
+            .values()
+            .filter(|task| task.arch_task().is_user())
+            .count()
+    }
+
+    /// Returns all of the tasks in the task `group` or in the groups nested in it.
+    pub fn find_task_group(&self, group: &TaskGroup) -> Vec<Arc<Task>> {
+        self.tasks
+            .0
+            .lock()
+            .values()
+            .filter(|task| group.contains(&task.cgroup()))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns all of the tasks in the process group with the provided `pgid` in the PID
+    /// namespace `ns`. Pass the root namespace for a global process group ID.
+    pub fn find_process_group(&self, ns: &PidNamespace, pgid: usize) -> Vec<Arc<Task>> {
+        self.tasks
+            .0
+            .lock()
+            .values()
+            .filter(|task| task.pgid_in(ns) == Some(pgid))
+            .cloned()
+            .collect()
+    }
 }
 
 /// Get a reference to the active scheduler.
@@ -154,7 +189,10 @@ pub fn is_initialized() -> bool {
 static SCHEDULER_VECTOR: Once<u8> = Once::new();
 const SCHEDULER_TIMER_US: usize = 20000;
 
-fn scheduler_irq_handler(_stack: &mut InterruptStack) {
+fn scheduler_irq_handler(stack: &mut InterruptStack) {
+    #[cfg(target_arch = "x86_64")]
+    crate::watchdog::tick(stack);
+
     #[cfg(target_arch = "x86_64")]
     {
         crate::arch::apic::get_local_apic()
@@ -166,12 +204,27 @@ fn scheduler_irq_handler(_stack: &mut InterruptStack) {
     self::get_scheduler().inner.preempt();
 }
 
+/// Rearms the scheduler timer, so that the task switched to gets a full time slice.
+fn start_time_slice() {
+    #[cfg(target_arch = "x86_64")]
+    if let Some(vector) = SCHEDULER_VECTOR.get() {
+        crate::arch::apic::get_local_apic().timer_oneshot(*vector, SCHEDULER_TIMER_US);
+    }
+}
+
 /// Initialize the scheduler and set up the scheduler interrupt.
 pub fn init() {
     SCHEDULER.call_once(|| Scheduler::new()).inner.init();
 
-    let scheduler_vector = interrupts::allocate_vector();
-    interrupts::register_handler(scheduler_vector, scheduler_irq_handler);
+    let scheduler_vector =
+        interrupts::allocate_vector().expect("scheduler: failed to allocate a vector");
+    interrupts::request_irq(
+        scheduler_vector,
+        "scheduler",
+        scheduler_irq_handler,
+        interrupts::IrqFlags::empty(),
+    )
+    .expect("scheduler: failed to request the scheduler IRQ");
 
     #[cfg(target_arch = "x86_64")]
     crate::arch::apic::get_local_apic().timer_oneshot(scheduler_vector, SCHEDULER_TIMER_US);
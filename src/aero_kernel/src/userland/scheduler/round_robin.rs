@@ -22,6 +22,8 @@ use alloc::sync::Arc;
 use intrusive_collections::LinkedList;
 
 use crate::arch;
+use crate::timer;
+use crate::trace::{self, TraceEvent};
 use crate::userland::signals::{SignalError, SignalResult};
 use crate::userland::task::{SchedTaskAdapter, Task, TaskState};
 
@@ -40,10 +42,14 @@ struct TaskQueue {
     preempt_task: Arc<Task>,
     current_task: Option<Arc<Task>>,
 
+    /// When the current task was switched to, in nanoseconds.
+    started_at: u64,
+    /// The lowest weighted runtime of a task group picked on this CPU so far.
+    min_vruntime: u64,
+
     runnable: LinkedList<SchedTaskAdapter>,
     dead: LinkedList<SchedTaskAdapter>,
     awaiting: LinkedList<SchedTaskAdapter>,
-    deadline_awaiting: LinkedList<SchedTaskAdapter>,
 }
 
 impl TaskQueue {
@@ -54,10 +60,12 @@ impl TaskQueue {
             preempt_task: Task::new_kernel(preempter, false),
             current_task: None,
 
+            started_at: 0,
+            min_vruntime: 0,
+
             runnable: LinkedList::new(SchedTaskAdapter::new()),
             dead: LinkedList::new(SchedTaskAdapter::new()),
             awaiting: LinkedList::new(SchedTaskAdapter::new()),
-            deadline_awaiting: LinkedList::new(SchedTaskAdapter::new()),
         }
     }
 
@@ -75,26 +83,38 @@ impl TaskQueue {
         self.dead.push_back(task);
     }
 
-    fn push_deadline_awaiting(&mut self, task: Arc<Task>, duration: usize) {
+    fn push_awaiting(&mut self, task: Arc<Task>) {
         debug_assert_eq!(task.link.is_linked(), false); // Make sure the task is not already linked
 
         task.update_state(TaskState::AwaitingIo);
-        task.set_sleep_duration(crate::arch::time::get_uptime_ticks() + duration);
-
-        self.deadline_awaiting.push_back(task);
+        self.awaiting.push_back(task);
     }
 
-    fn push_awaiting(&mut self, task: Arc<Task>) {
-        debug_assert_eq!(task.link.is_linked(), false); // Make sure the task is not already linked
+    /// Removes the runnable task whose task group has the lowest weighted runtime, the
+    /// one queued first on a tie.
+    fn pop_next(&mut self) -> Option<Arc<Task>> {
+        // A group that was idle may only get ahead of the others by one time slice.
+        let floor = self
+            .min_vruntime
+            .saturating_sub(super::SCHEDULER_TIMER_US as u64 * 1000);
 
-        task.update_state(TaskState::AwaitingIo);
-        self.awaiting.push_back(task);
+        let (task, vruntime) = self
+            .runnable
+            .iter()
+            .map(|task| (task as *const Task, task.cgroup().vruntime(floor)))
+            .min_by_key(|(_, vruntime)| *vruntime)?;
+
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+
+        // SAFETY: the task was just found in the runnable queue.
+        unsafe { self.runnable.cursor_mut_from_ptr(task) }.remove()
     }
 }
 
 /// Round Robin is the simplest algorithm for a preemptive scheduler. When the
 /// system timer fires, the next task in the queue is switched to, and the
-/// preempted task is put back into the queue.
+/// preempted task is put back into the queue. The next task is taken from the task
+/// group with the least weighted runtime, so the groups share the CPU by weight.
 ///
 /// ## Notes
 /// * <https://en.wikipedia.org/wiki/Round-robin_scheduling>
@@ -124,46 +144,46 @@ impl RoundRobin {
         }
     }
 
-    fn schedule_check_deadline(&self) {
-        let _guard = IrqGuard::new();
-        let queue = self.queue.get_mut();
-
-        let time = crate::arch::time::get_uptime_ticks();
-
-        let mut cursor = queue.deadline_awaiting.front_mut();
-
-        while let Some(task) = cursor.get() {
-            if task.load_sleep_duration() <= time {
-                let ptr = cursor.remove().unwrap();
+    fn schedule_next_task(&self) {
+        #[cfg(target_arch = "x86_64")]
+        crate::watchdog::touch();
 
-                assert_eq!(ptr.link.is_linked(), false);
+        let guard = IrqGuard::new();
+        let queue = self.queue.get_mut();
+        let now = timer::now();
 
-                ptr.update_state(TaskState::Runnable);
-                ptr.set_sleep_duration(0);
+        // Charge the time the preempted task ran to its task group and put it back into
+        // the runnable queue, so that it competes with the other tasks for the CPU.
+        if let Some(current_task) = queue.current_task.clone() {
+            current_task
+                .cgroup()
+                .charge_runtime(now.saturating_sub(queue.started_at));
 
-                queue.runnable.push_back(ptr);
-            } else {
-                cursor.move_next();
+            if !current_task.link.is_linked() && current_task.state() == TaskState::Runnable {
+                queue.push_runnable(current_task);
             }
         }
-    }
 
-    fn schedule_next_task(&self) {
-        let guard = IrqGuard::new();
-        let queue = self.queue.get_mut();
+        queue.started_at = now;
 
-        self.schedule_check_deadline();
+        // Switch to the runnable task of the task group that used the least CPU time.
+        if let Some(task) = queue.pop_next() {
+            let previous = queue
+                .current_task
+                .as_ref()
+                .map(|task| task.tid().as_usize());
 
-        // Switch to the next runnable task in the runnable queue, and put
-        // the preempted task back into the runnable queue.
-        if let Some(task) = queue.runnable.pop_front() {
-            if let Some(current_task) = queue.current_task.clone() {
-                if !current_task.link.is_linked() && current_task.pid() != task.pid() {
-                    queue.push_runnable(current_task);
-                }
-            }
+            trace::trace(
+                TraceEvent::ContextSwitch,
+                [previous.unwrap_or(0) as u64, task.tid().as_usize() as u64],
+            );
+
+            #[cfg(target_arch = "x86_64")]
+            arch::pmu::switch_task(task.tid().as_usize());
 
             queue.current_task = Some(task.clone());
+            super::start_time_slice();
+
             core::mem::drop(guard);
             arch::task::arch_task_spinup(queue.preempt_task.arch_task_mut(), task.arch_task());
         } else {
@@ -218,7 +238,10 @@ impl SchedulerInterface for RoundRobin {
         }
     }
 
-    fn sleep(&self, duration: Option<usize>) -> SignalResult<()> {
+    fn await_io(&self) -> SignalResult<()> {
+        #[cfg(feature = "lockdep")]
+        crate::utils::lockdep::might_sleep();
+
         let _guard = IrqGuard::new();
         let queue = self.queue.get_mut();
 
@@ -233,11 +256,7 @@ impl SchedulerInterface for RoundRobin {
             return Ok(());
         }
 
-        if let Some(duration) = duration {
-            queue.push_deadline_awaiting(task, duration);
-        } else {
-            queue.push_awaiting(task);
-        }
+        queue.push_awaiting(task);
 
         self.preempt();
 
@@ -279,10 +298,6 @@ impl SchedulerInterface for RoundRobin {
         }
     }
 
-    fn await_io(&self) -> SignalResult<()> {
-        self.sleep(None)
-    }
-
     fn exit(&self, status: isize) -> ! {
         let guard = IrqGuard::new();
         let queue = self.queue.get_mut();
@@ -0,0 +1,208 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Syscall filtering. A task can install filters that allow or deny syscalls by their
+//! number. The filters are inherited by the children, kept across `exec` and cannot be
+//! removed. Each syscall is checked against all of the filters of the task before its
+//! arguments are decoded, and the first filter rejecting it decides the action: either
+//! the task is killed or the syscall fails with the error chosen by the filter.
+//!
+//! Installing a filter requires the no-new-privileges latch, set with
+//! `prctl(PR_SET_NO_NEW_PRIVS)`. The latch cannot be cleared and is inherited as well.
+//!
+//! `sigreturn` and `arch_prctl` are handled by the architecture before the dispatcher,
+//! so they are always allowed.
+
+use aero_syscall::prelude::*;
+
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::RwLock;
+
+use super::scheduler;
+
+/// The maximum amount of filters installed on a task.
+const MAX_FILTERS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Action {
+    Kill,
+    Errno(u32),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Filter {
+    allow: bool,
+    action: Action,
+    syscalls: SeccompFilter,
+}
+
+impl Filter {
+    fn from_user(filter: &SeccompFilter) -> Result<Self, SyscallError> {
+        let allow = match filter.mode {
+            SECCOMP_FILTER_ALLOW => true,
+            SECCOMP_FILTER_DENY => false,
+            _ => return Err(SyscallError::EINVAL),
+        };
+
+        let action = match filter.action {
+            SECCOMP_RET_KILL => Action::Kill,
+            // The error is returned to the callers of the syscall, so it has to be a
+            // valid `SyscallError`.
+            SECCOMP_RET_ERRNO if SyscallError::try_from(filter.errno).is_ok() => {
+                Action::Errno(filter.errno)
+            }
+
+            _ => return Err(SyscallError::EINVAL),
+        };
+
+        if filter.reserved != 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        Ok(Self {
+            allow,
+            action,
+            syscalls: *filter,
+        })
+    }
+
+    fn allows(&self, number: usize) -> bool {
+        self.syscalls.contains(number) == self.allow
+    }
+}
+
+pub struct Seccomp {
+    filters: RwLock<Vec<Filter>>,
+    no_new_privs: AtomicBool,
+}
+
+impl Seccomp {
+    pub(super) fn new() -> Self {
+        Self {
+            filters: RwLock::new(Vec::new()),
+            no_new_privs: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a copy of the filters and the latch, for a child of the task.
+    pub(super) fn fork(&self) -> Self {
+        Self {
+            filters: RwLock::new(self.filters.read().clone()),
+            no_new_privs: AtomicBool::new(self.no_new_privs()),
+        }
+    }
+
+    pub fn no_new_privs(&self) -> bool {
+        self.no_new_privs.load(Ordering::SeqCst)
+    }
+
+    /// Sets the no-new-privileges latch. It cannot be cleared afterwards.
+    pub fn set_no_new_privs(&self) {
+        self.no_new_privs.store(true, Ordering::SeqCst)
+    }
+
+    /// Installs the `filter`, on top of the filters already installed.
+    pub fn add_filter(&self, filter: &SeccompFilter) -> Result<(), SyscallError> {
+        if !self.no_new_privs() {
+            return Err(SyscallError::EACCES);
+        }
+
+        let filter = Filter::from_user(filter)?;
+        let mut filters = self.filters.write();
+
+        if filters.len() >= MAX_FILTERS {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        filters.push(filter);
+        Ok(())
+    }
+
+    fn check(&self, number: usize) -> Option<Action> {
+        self.filters
+            .read()
+            .iter()
+            .find(|filter| !filter.allows(number))
+            .map(|filter| filter.action)
+    }
+}
+
+/// Checks the syscall `number` against the filters of the current task. Returns the
+/// result of the syscall if it was rejected with an error, and kills the task if it was
+/// rejected with `SECCOMP_RET_KILL`.
+pub fn check(number: usize) -> Option<usize> {
+    let task = scheduler::get_scheduler().current_task();
+
+    match task.seccomp().check(number)? {
+        Action::Errno(errno) => Some(-(errno as isize) as usize),
+        Action::Kill => {
+            log::warn!(
+                "seccomp: killing the task (pid={:?}) on syscall {number}",
+                task.pid()
+            );
+
+            core::mem::drop(task);
+            scheduler::get_scheduler().exit(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seccomp_filter() {
+        let mut allow = SeccompFilter::new(SECCOMP_FILTER_ALLOW, SECCOMP_RET_KILL, 0);
+        allow.add(SYS_READ);
+        allow.add(SYS_EXIT);
+
+        let allow = Filter::from_user(&allow).unwrap();
+        assert!(allow.allows(SYS_READ));
+        assert!(!allow.allows(SYS_WRITE));
+        assert!(!allow.allows(SECCOMP_MAX_SYSCALLS));
+
+        let mut deny = SeccompFilter::new(SECCOMP_FILTER_DENY, SECCOMP_RET_ERRNO, 1);
+        deny.add(SYS_FORK);
+
+        let deny = Filter::from_user(&deny).unwrap();
+        assert!(!deny.allows(SYS_FORK));
+        assert!(deny.allows(SECCOMP_MAX_SYSCALLS));
+        assert_eq!(deny.action, Action::Errno(1));
+
+        let invalid = SeccompFilter::new(SECCOMP_FILTER_DENY, SECCOMP_RET_ERRNO, 0);
+        assert!(Filter::from_user(&invalid).is_err());
+
+        // The error numbers that are not `SyscallError` variants are rejected.
+        for errno in [5, 1033, 4095] {
+            let invalid = SeccompFilter::new(SECCOMP_FILTER_DENY, SECCOMP_RET_ERRNO, errno);
+            assert!(Filter::from_user(&invalid).is_err());
+        }
+
+        let einval = SeccompFilter::new(
+            SECCOMP_FILTER_DENY,
+            SECCOMP_RET_ERRNO,
+            SyscallError::EINVAL as u32,
+        );
+        assert!(Filter::from_user(&einval).is_ok());
+    }
+}
@@ -29,6 +29,7 @@ use bit_field::BitField;
 use aero_syscall::SyscallError;
 
 use super::scheduler;
+use crate::arch::interrupts::InterruptStack;
 use crate::fs::FileSystemError;
 use crate::utils::sync::{Mutex, MutexGuard};
 
@@ -424,7 +425,17 @@ impl Signals {
     }
 }
 
-pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
+/// Kills the current task with `SIGSEGV`, whatever its handler. Used when a signal
+/// cannot be delivered, as the signal frame does not fit on the user stack.
+pub fn force_sigsegv() -> ! {
+    default::handle_default(SIGSEGV);
+    unreachable!("force_sigsegv: the task was not terminated")
+}
+
+/// Returns the pending signal to be handled by the current task, after running the
+/// default action of the others. If the task is traced, each signal is first reported
+/// to the tracer, which can deliver another signal instead or suppress it.
+pub fn check_for_signals(stack: &mut InterruptStack) -> Option<(usize, SignalEntry)> {
     let task = scheduler::get_scheduler().current_task();
     let signals = task.signals();
 
@@ -443,6 +454,16 @@ pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
         if !signals.is_blocked(i) && signals.is_pending(i as u64) {
             signals.clear_pending(i as u64);
 
+            let i = if task.ptrace().is_traced() {
+                match crate::arch::ptrace::signal_stop(stack, i) {
+                    0 => continue,
+                    signal if signal >= SIGNAL_COUNT => continue,
+                    signal => signal,
+                }
+            } else {
+                i
+            };
+
             let entries = signals.entries();
             let entry = entries[i];
 
@@ -456,9 +477,8 @@ pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
                     return Some((i, entry));
                 }
 
-                SignalHandler::Ignore => {
-                    unreachable!()
-                }
+                // Only reachable with a signal delivered by the tracer.
+                SignalHandler::Ignore => {}
             }
         }
     }
@@ -17,7 +17,9 @@
  * along with Aero. If not, see <https://www.gnu.org/licenses/>.
  */
 
-use aero_syscall::WaitPidFlags;
+use aero_syscall::prelude::{RLIMIT_NOFILE, RLIMIT_STACK};
+use aero_syscall::{SyscallError, WaitPidFlags};
+use alloc::boxed::Box;
 use alloc::sync::{Arc, Weak};
 
 use spin::RwLock;
@@ -26,19 +28,26 @@ use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicU8, AtomicUsize, Ordering};
 
 use crate::fs::cache::{DirCacheImpl, DirCacheItem};
-use crate::fs::{self, FileSystem};
+use crate::fs::{self, FileSystem, MountNamespace};
 use crate::mem::paging::*;
 
 use crate::arch::task::ArchTask;
 use crate::fs::file_table::FileTable;
 use crate::syscall::{ExecArgs, MessageQueue};
-use crate::utils::sync::{BlockQueue, Mutex};
+#[cfg(feature = "lockdep")]
+use crate::utils::lockdep::HeldLocks;
+use crate::utils::sync::{Mutex, WaitQueue};
 
 use crate::userland::signals::Signals;
 
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
 
+use super::cgroup::TaskGroup;
+use super::pid_namespace::{Pid, PidNamespace};
+use super::ptrace::{self, Ptrace};
+use super::rlimit::RLimits;
 use super::scheduler;
+use super::seccomp::Seccomp;
 use super::signals::{SignalResult, TriggerResult};
 use super::vm::Vm;
 
@@ -84,6 +93,8 @@ impl From<u8> for TaskState {
 struct Cwd {
     inode: DirCacheItem,
     filesystem: Arc<dyn FileSystem>,
+    /// The root directory of the task, changed with `chroot`.
+    root: DirCacheItem,
 }
 
 impl Cwd {
@@ -92,8 +103,9 @@ impl Cwd {
         let fs = root.inode().weak_filesystem().unwrap().upgrade().unwrap();
 
         Self {
-            inode: root,
+            inode: root.clone(),
             filesystem: fs,
+            root,
         }
     }
 
@@ -101,20 +113,21 @@ impl Cwd {
         Self {
             inode: self.inode.clone(),
             filesystem: self.filesystem.clone(),
+            root: self.root.clone(),
         }
     }
 }
 
 struct Zombies {
     list: Mutex<LinkedList<SchedTaskAdapter>>,
-    block: BlockQueue,
+    block: WaitQueue,
 }
 
 impl Zombies {
     fn new() -> Self {
         Self {
             list: Mutex::new(Default::default()),
-            block: BlockQueue::new(),
+            block: WaitQueue::new(),
         }
     }
 
@@ -127,24 +140,34 @@ impl Zombies {
         log::debug!("making process a zombie: (pid={:?})", zombie.pid());
 
         list.push_back(zombie);
-        self.block.notify_complete();
+        self.block.wake_all();
     }
 
+    /// Waits for one of the `pids` to become a zombie, or for `stopped` to return a
+    /// stopped tracee.
     fn waitpid(
         &self,
         pids: &[usize],
+        namespace: &PidNamespace,
         status: &mut u32,
         flags: WaitPidFlags,
+        mut stopped: impl FnMut() -> Option<(usize, u32)>,
     ) -> SignalResult<usize> {
         let mut captured = None;
 
-        self.block.block_on(&self.list, |l| {
+        self.block.wait_until(&self.list, |l| {
+            if let Some(stop) = stopped() {
+                captured = Some(stop);
+                return true;
+            }
+
             let mut cursor = l.front_mut();
 
             while let Some(t) = cursor.get() {
                 for pid in pids {
                     if t.pid().as_usize() == *pid {
-                        captured = Some((t.pid(), t.exit_status()));
+                        let pid = t.pid_in(namespace).unwrap_or(0);
+                        captured = Some((pid, t.exit_status() as u32));
                         cursor.remove();
 
                         return true;
@@ -163,8 +186,8 @@ impl Zombies {
 
         if let Some((tid, st)) = captured {
             log::debug!("waitpid: status = {st}");
-            *status = st as u32;
-            Ok(tid.as_usize())
+            *status = st;
+            Ok(tid)
         } else {
             // If `WNOHANG` was specified in flags and there were no children in a waitable
             // state, then waitid() returns 0 immediately.
@@ -187,16 +210,33 @@ pub struct Task {
     pid: TaskId,
     tid: TaskId,
 
+    pid_namespace: Arc<PidNamespace>,
+    /// The PIDs of the task in its PID namespace and in the ancestors of it, indexed by
+    /// the level of the namespace.
+    ns_pids: Box<[usize]>,
+    /// The PID namespace of the children created afterwards.
+    child_pid_namespace: RwLock<Arc<PidNamespace>>,
+    mount_namespace: RwLock<Arc<MountNamespace>>,
+
+    // Process group and session IDs, used for job control.
+    pgid: Mutex<Pid>,
+    sid: Mutex<Pid>,
+
     parent: Mutex<Option<Arc<Task>>>,
     children: Mutex<intrusive_collections::LinkedList<TaskAdapter>>,
 
     zombies: Zombies,
 
-    sleep_duration: AtomicUsize,
     signals: Signals,
 
     executable: Mutex<Option<DirCacheItem>>,
     pending_io: AtomicBool,
+    /// If set, the syscalls of the task are logged (see `syscall::SysLog`).
+    syscall_trace: AtomicBool,
+    ptrace: Ptrace,
+    seccomp: Seccomp,
+    rlimits: Arc<RLimits>,
+    cgroup: Mutex<Arc<TaskGroup>>,
 
     pub(super) link: intrusive_collections::LinkedListLink,
     pub(super) clink: intrusive_collections::LinkedListLink,
@@ -208,6 +248,10 @@ pub struct Task {
 
     cwd: RwLock<Option<Cwd>>,
 
+    /// The locks held by the task (see [`crate::utils::lockdep`]).
+    #[cfg(feature = "lockdep")]
+    held_locks: spin::Mutex<HeldLocks>,
+
     pub(super) exit_status: AtomicIsize,
 }
 
@@ -227,9 +271,17 @@ impl Task {
 
             message_queue: MessageQueue::new(),
 
+            pgid: Mutex::new(Pid::new(PidNamespace::root(), Box::new([pid.as_usize()]))),
+            sid: Mutex::new(Pid::new(PidNamespace::root(), Box::new([pid.as_usize()]))),
+
             tid: pid.clone(),
             pid,
 
+            pid_namespace: PidNamespace::root(),
+            ns_pids: Box::new([pid.as_usize()]),
+            child_pid_namespace: RwLock::new(PidNamespace::root()),
+            mount_namespace: RwLock::new(fs::INIT_MOUNT_NAMESPACE.clone()),
+
             executable: Mutex::new(None),
 
             vm: Arc::new(Vm::new()),
@@ -239,8 +291,12 @@ impl Task {
             clink: Default::default(),
 
             pending_io: AtomicBool::new(false),
+            syscall_trace: AtomicBool::new(false),
+            ptrace: Ptrace::new(),
+            seccomp: Seccomp::new(),
+            rlimits: Arc::new(RLimits::new()),
+            cgroup: Mutex::new(TaskGroup::root()),
 
-            sleep_duration: AtomicUsize::new(0),
             exit_status: AtomicIsize::new(0),
 
             children: Mutex::new(Default::default()),
@@ -248,6 +304,9 @@ impl Task {
 
             signals: Signals::new(),
             cwd: RwLock::new(None),
+
+            #[cfg(feature = "lockdep")]
+            held_locks: spin::Mutex::new(HeldLocks::new()),
         })
     }
 
@@ -268,28 +327,45 @@ impl Task {
             vm: Arc::new(Vm::new()),
             state: AtomicU8::new(TaskState::Runnable as _),
 
+            pgid: Mutex::new(Pid::new(PidNamespace::root(), Box::new([pid.as_usize()]))),
+            sid: Mutex::new(Pid::new(PidNamespace::root(), Box::new([pid.as_usize()]))),
+
             tid: pid.clone(),
             pid,
 
+            pid_namespace: PidNamespace::root(),
+            ns_pids: Box::new([pid.as_usize()]),
+            child_pid_namespace: RwLock::new(PidNamespace::root()),
+            mount_namespace: RwLock::new(fs::INIT_MOUNT_NAMESPACE.clone()),
+
             link: Default::default(),
             clink: Default::default(),
 
-            sleep_duration: AtomicUsize::new(0),
             exit_status: AtomicIsize::new(0),
 
             executable: Mutex::new(None),
             pending_io: AtomicBool::new(false),
+            syscall_trace: AtomicBool::new(false),
+            ptrace: Ptrace::new(),
+            seccomp: Seccomp::new(),
+            rlimits: Arc::new(RLimits::new()),
+            cgroup: Mutex::new(TaskGroup::root()),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             signals: Signals::new(),
             cwd: RwLock::new(None),
+
+            #[cfg(feature = "lockdep")]
+            held_locks: spin::Mutex::new(HeldLocks::new()),
         })
     }
 
     fn make_child(&self, arch_task: UnsafeCell<ArchTask>) -> Arc<Task> {
         let pid = TaskId::allocate();
+        let pid_namespace = self.child_pid_namespace();
+        let ns_pids = pid_namespace.alloc_pids(pid);
 
         let this = Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
@@ -304,22 +380,38 @@ impl Task {
             link: Default::default(),
             clink: Default::default(),
 
-            sleep_duration: AtomicUsize::new(0),
             exit_status: AtomicIsize::new(0),
 
+            pgid: Mutex::new(self.process_group()),
+            sid: Mutex::new(self.sid.lock_irq().clone()),
+
             tid: pid.clone(),
             pid,
 
+            pid_namespace: pid_namespace.clone(),
+            ns_pids: ns_pids.clone(),
+            child_pid_namespace: RwLock::new(pid_namespace.clone()),
+            mount_namespace: RwLock::new(self.mount_namespace()),
+
             executable: Mutex::new(self.executable.lock().clone()),
             pending_io: AtomicBool::new(false),
+            syscall_trace: AtomicBool::new(self.is_syscall_traced()),
+            ptrace: Ptrace::new(),
+            seccomp: self.seccomp.fork(),
+            rlimits: Arc::new(self.rlimits.fork()),
+            cgroup: Mutex::new(self.cgroup()),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
             signals: Signals::new(),
+
+            #[cfg(feature = "lockdep")]
+            held_locks: spin::Mutex::new(HeldLocks::new()),
         });
 
+        pid_namespace.register(&this, &ns_pids);
         self.add_child(this.clone());
         this.signals().copy_from(self.signals());
 
@@ -340,15 +432,69 @@ impl Task {
         &self.signals
     }
 
-    pub fn clone_process(&self, entry: usize, stack: usize) -> Arc<Task> {
+    /// Returns whether the syscalls of the task are logged.
+    pub fn is_syscall_traced(&self) -> bool {
+        self.syscall_trace.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables the logging of the syscalls of the task. The setting is
+    /// inherited by the children created afterwards.
+    pub fn set_syscall_trace(&self, enabled: bool) {
+        self.syscall_trace.store(enabled, Ordering::Relaxed)
+    }
+
+    pub fn ptrace(&self) -> &Ptrace {
+        &self.ptrace
+    }
+
+    pub fn seccomp(&self) -> &Seccomp {
+        &self.seccomp
+    }
+
+    /// Returns the resource limits of the process.
+    pub fn rlimits(&self) -> &RLimits {
+        &self.rlimits
+    }
+
+    /// Returns the locks held by the task (see [`crate::utils::lockdep`]).
+    #[cfg(feature = "lockdep")]
+    pub fn held_locks(&self) -> &spin::Mutex<HeldLocks> {
+        &self.held_locks
+    }
+
+    /// Returns the task group (see [`crate::userland::cgroup`]) of the task.
+    pub fn cgroup(&self) -> Arc<TaskGroup> {
+        self.cgroup.lock_irq().clone()
+    }
+
+    pub fn set_cgroup(&self, group: Arc<TaskGroup>) {
+        // The resident pages of a process are charged to the group of its leader.
+        if self.is_process_leader() {
+            self.vm.set_group(group.clone());
+        }
+
+        *self.cgroup.lock_irq() = group;
+    }
+
+    /// Wakes up the tasks waiting for a child of this task to change its state.
+    pub(super) fn notify_waiters(&self) {
+        let _list = self.zombies.list.lock_irq();
+        self.zombies.block.wake_all();
+    }
+
+    pub fn clone_process(&self, entry: usize, stack: usize, tls: usize) -> Arc<Task> {
         let arch_task = UnsafeCell::new(
             self.arch_task_mut()
-                .clone_process(entry, stack)
+                .clone_process(entry, stack, tls)
                 .expect("failed to fork arch task"),
         );
 
         let pid = TaskId::allocate();
 
+        // The threads stay in the PID namespace of the process.
+        let pid_namespace = self.pid_namespace.clone();
+        let ns_pids = pid_namespace.alloc_pids(pid);
+
         let this = Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
@@ -362,22 +508,38 @@ impl Task {
             link: Default::default(),
             clink: Default::default(),
 
-            sleep_duration: AtomicUsize::new(0),
             exit_status: AtomicIsize::new(0),
 
+            pgid: Mutex::new(self.process_group()),
+            sid: Mutex::new(self.sid.lock_irq().clone()),
+
             tid: pid.clone(),
             pid,
 
+            pid_namespace: pid_namespace.clone(),
+            ns_pids: ns_pids.clone(),
+            child_pid_namespace: RwLock::new(pid_namespace.clone()),
+            mount_namespace: RwLock::new(self.mount_namespace()),
+
             executable: Mutex::new(self.executable.lock().clone()),
             pending_io: AtomicBool::new(false),
+            syscall_trace: AtomicBool::new(self.is_syscall_traced()),
+            ptrace: Ptrace::new(),
+            seccomp: self.seccomp.fork(),
+            rlimits: self.process_leader().rlimits.clone(),
+            cgroup: Mutex::new(self.cgroup()),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
             signals: Signals::new(),
+
+            #[cfg(feature = "lockdep")]
+            held_locks: spin::Mutex::new(HeldLocks::new()),
         });
 
+        pid_namespace.register(&this, &ns_pids);
         self.add_child(this.clone());
         this.signals().copy_from(self.signals());
 
@@ -424,20 +586,15 @@ impl Task {
         self.exit_status.load(Ordering::SeqCst)
     }
 
-    pub fn set_sleep_duration(&self, duration: usize) {
-        self.sleep_duration.store(duration, Ordering::SeqCst);
-    }
-
-    pub fn load_sleep_duration(&self) -> usize {
-        self.sleep_duration.load(Ordering::SeqCst)
-    }
-
     pub fn waitpid(
         &self,
         pid: isize,
         status: &mut u32,
         flags: WaitPidFlags,
     ) -> SignalResult<usize> {
+        // The PIDs are the ones of the PID namespace of this task.
+        let namespace = &*self.pid_namespace;
+
         if pid == -1 {
             // wait for any child process if no specific process is requested.
             //
@@ -453,9 +610,17 @@ impl Task {
                 .collect::<alloc::vec::Vec<_>>();
 
             pids.extend(self.children.lock_irq().iter().map(|e| e.pid().as_usize()));
-            self.zombies.waitpid(&pids, status, flags)
+            self.zombies.waitpid(&pids, namespace, status, flags, || {
+                self.ptrace.take_stopped(pid, namespace)
+            })
         } else {
-            self.zombies.waitpid(&[pid as _], status, flags)
+            // The zombie and children lists are keyed by the global task IDs.
+            let global_pid = namespace.global_pid(pid as usize).unwrap_or(0);
+
+            self.zombies
+                .waitpid(&[global_pid], namespace, status, flags, || {
+                    self.ptrace.take_stopped(pid, namespace)
+                })
         }
     }
 
@@ -478,6 +643,8 @@ impl Task {
         }
 
         self.file_table.close_on_exec();
+        self.file_table
+            .set_max_files(self.rlimits.current(RLIMIT_NOFILE) as usize);
 
         self.file_table.log();
 
@@ -489,7 +656,14 @@ impl Task {
         // Clear the signals that are pending for this task on exec.
         self.signals().clear();
 
-        self.arch_task_mut().exec(vm, executable, argv, envv)
+        // Let the tracer inspect the new image before it starts executing.
+        if self.ptrace.is_traced() {
+            self.signal(aero_syscall::signal::SIGTRAP);
+        }
+
+        let stack_size = self.rlimits.current(RLIMIT_STACK);
+        self.arch_task_mut()
+            .exec(vm, executable, argv, envv, stack_size)
     }
 
     pub fn vm(&self) -> &Arc<Vm> {
@@ -541,10 +715,103 @@ impl Task {
         self.get_parent().unwrap().pid()
     }
 
+    pub fn pid_namespace(&self) -> &Arc<PidNamespace> {
+        &self.pid_namespace
+    }
+
+    /// Returns the PID of the task in its own PID namespace.
+    pub fn ns_pid(&self) -> usize {
+        self.ns_pids[self.pid_namespace.level()]
+    }
+
+    /// Returns the PID of the task in the PID namespace `ns` or [`None`] if the task is
+    /// not visible from it.
+    pub fn pid_in(&self, ns: &PidNamespace) -> Option<usize> {
+        ns.contains(&self.pid_namespace)
+            .then(|| self.ns_pids[ns.level()])
+    }
+
+    /// Returns the PID of the parent in the PID namespace of this task or zero if the
+    /// parent is outside of it.
+    pub fn ns_parent_pid(&self) -> usize {
+        self.get_parent()
+            .and_then(|parent| parent.pid_in(&self.pid_namespace))
+            .unwrap_or(0)
+    }
+
+    fn child_pid_namespace(&self) -> Arc<PidNamespace> {
+        self.child_pid_namespace.read().clone()
+    }
+
+    /// Makes the children created afterwards the members of a new PID namespace.
+    pub fn unshare_pid_namespace(&self) -> Result<(), SyscallError> {
+        let namespace = self.pid_namespace.new_child()?;
+
+        *self.child_pid_namespace.write() = namespace;
+        Ok(())
+    }
+
+    pub fn mount_namespace(&self) -> Arc<MountNamespace> {
+        self.mount_namespace.read().clone()
+    }
+
+    /// Moves the task to a private copy of its mount namespace.
+    pub fn unshare_mount_namespace(&self) {
+        let namespace = Arc::new(self.mount_namespace().fork());
+        *self.mount_namespace.write() = namespace;
+    }
+
     pub fn tid(&self) -> TaskId {
         self.tid
     }
 
+    /// Returns the PIDs of this task in each of the namespaces it is visible from.
+    pub fn pids(&self) -> Pid {
+        Pid::new(self.pid_namespace.clone(), self.ns_pids.clone())
+    }
+
+    /// Returns the global process group ID of this task.
+    pub fn pgid(&self) -> usize {
+        self.pgid.lock_irq().global()
+    }
+
+    /// Returns the process group ID of this task in the PID namespace `ns` or [`None`] if
+    /// the leader of the group is not visible from it.
+    pub fn pgid_in(&self, ns: &PidNamespace) -> Option<usize> {
+        self.pgid.lock_irq().nr_in(ns)
+    }
+
+    /// Returns the PIDs of the leader of the process group of this task.
+    pub fn process_group(&self) -> Pid {
+        self.pgid.lock_irq().clone()
+    }
+
+    pub fn set_pgid(&self, pgid: Pid) {
+        *self.pgid.lock_irq() = pgid;
+    }
+
+    /// Returns the global session ID of this task.
+    pub fn sid(&self) -> usize {
+        self.sid.lock_irq().global()
+    }
+
+    /// Returns the session ID of this task in the PID namespace `ns` or [`None`] if the
+    /// leader of the session is not visible from it.
+    pub fn sid_in(&self, ns: &PidNamespace) -> Option<usize> {
+        self.sid.lock_irq().nr_in(ns)
+    }
+
+    /// Returns [`true`] if this task is the leader of its session.
+    pub fn is_session_leader(&self) -> bool {
+        self.sid() == self.pid.as_usize()
+    }
+
+    /// Creates a new session (and a new process group) with this task as the leader.
+    pub fn set_sid(&self) {
+        *self.sid.lock_irq() = self.pids();
+        self.set_pgid(self.pids());
+    }
+
     pub fn cwd_dirent(&self) -> DirCacheItem {
         self.cwd.read().as_ref().unwrap().inode.clone()
     }
@@ -560,7 +827,20 @@ impl Task {
         self.cwd.write().as_mut().unwrap().filesystem = filesystem;
     }
 
-    fn get_parent(&self) -> Option<Arc<Task>> {
+    /// Returns the root directory of the task or [`None`] if it has not been set up yet
+    /// (before the first exec).
+    pub fn root_dirent(&self) -> Option<DirCacheItem> {
+        self.cwd.read().as_ref().map(|cwd| cwd.root.clone())
+    }
+
+    /// Changes the root directory of the task, that the absolute paths are resolved
+    /// against, and its current working directory to `root`.
+    pub fn set_root(&self, root: DirCacheItem) {
+        self.set_cwd(root.clone());
+        self.cwd.write().as_mut().unwrap().root = root;
+    }
+
+    pub(super) fn get_parent(&self) -> Option<Arc<Task>> {
         let parent = self.parent.lock();
         parent.clone()
     }
@@ -591,6 +871,14 @@ impl Task {
                 true
             }
 
+            // The ignored signals are still reported to the tracer, which decides whether
+            // they are delivered.
+            TriggerResult::Ignored if self.ptrace.is_traced() => {
+                self.signals().set_pending(signal as u64, false);
+                self.wake_up();
+                true
+            }
+
             TriggerResult::Ignored => false,
 
             TriggerResult::Blocked => {
@@ -624,6 +912,17 @@ impl Task {
     pub(super) fn into_zombie(&self) {
         self.arch_task_mut().dealloc();
 
+        if self.is_process_leader() {
+            self.vm.uncharge_all();
+        }
+
+        ptrace::exit(self);
+
+        // The namespace does not outlive its init process.
+        if self.pid_namespace.level() > 0 && self.ns_pid() == 1 {
+            self.pid_namespace.kill_all(self);
+        }
+
         if let Some(parent) = self.get_parent() {
             parent.remove_child(self);
             parent.zombies.add_zombie(self.this());
@@ -0,0 +1,788 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The terminal core is shared by all of the terminal devices (the console TTY and the
+//! PTY pairs). It holds the terminal attributes (termios), the window size, the line
+//! discipline and handles the job control state of the terminal: the session the terminal
+//! is the controlling terminal of and the foreground process group of that session.
+//!
+//! The line discipline is an implementation of the N_TTY line discipline. It is responsible
+//! for the input processing (line editing in canonical mode, the `VMIN`/`VTIME` semantics in
+//! non-canonical mode, signal generation and flow control) and the output processing of the
+//! terminal.
+//!
+//! **Notes**:
+//! * <https://www.gnu.org/software/libc/manual/html_node/Job-Control.html>
+//! * <https://man7.org/linux/man-pages/man3/termios.3.html>
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use aero_syscall::signal::{SIGINT, SIGQUIT, SIGTSTP};
+use aero_syscall::{Termios, TermiosCFlag, TermiosIFlag, TermiosLFlag, TermiosOFlag, WinSize};
+use aero_syscall::{VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VMIN, VQUIT, VREPRINT};
+use aero_syscall::{VSTART, VSTOP, VSUSP, VTIME, VWERASE};
+
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::timer::{self, Timer};
+use crate::utils::sync::{Mutex, WaitQueue};
+use crate::workqueue;
+
+use super::pid_namespace::PidNamespace;
+use super::scheduler;
+
+/// Returns the termios of a newly created terminal.
+fn default_termios() -> Termios {
+    let mut c_cc = [0; 32];
+
+    c_cc[VINTR] = 0x03; // ^C
+    c_cc[VQUIT] = 0x1c; // ^\
+    c_cc[VERASE] = 0x7f; // DEL
+    c_cc[VKILL] = 0x15; // ^U
+    c_cc[VEOF] = 0x04; // ^D
+    c_cc[VTIME] = 0;
+    c_cc[VMIN] = 1;
+    c_cc[VSTART] = 0x11; // ^Q
+    c_cc[VSTOP] = 0x13; // ^S
+    c_cc[VSUSP] = 0x1a; // ^Z
+    c_cc[VREPRINT] = 0x12; // ^R
+    c_cc[VWERASE] = 0x17; // ^W
+    c_cc[VLNEXT] = 0x16; // ^V
+
+    Termios {
+        c_iflag: TermiosIFlag::ICRNL | TermiosIFlag::IXON,
+        c_oflag: TermiosOFlag::OPOST | TermiosOFlag::ONLCR,
+        c_cflag: TermiosCFlag::CS8 | TermiosCFlag::CREAD,
+        c_lflag: TermiosLFlag::ISIG
+            | TermiosLFlag::ICANON
+            | TermiosLFlag::ECHO
+            | TermiosLFlag::ECHOE
+            | TermiosLFlag::ECHOK
+            | TermiosLFlag::ECHOCTL
+            | TermiosLFlag::ECHOKE
+            | TermiosLFlag::IEXTEN,
+        c_line: 0,
+        c_cc,
+        c_ispeed: 38400,
+        c_ospeed: 38400,
+    }
+}
+
+/// Returns whether `byte` matches the special character at `index` of `c_cc`. A special
+/// character set to zero is disabled.
+fn is_special(termios: &Termios, index: usize, byte: u8) -> bool {
+    termios.c_cc[index] != 0 && termios.c_cc[index] == byte
+}
+
+/// Returns whether `byte` is echoed in the `^X` notation.
+fn is_echoed_as_control(termios: &Termios, byte: u8) -> bool {
+    termios.c_lflag.contains(TermiosLFlag::ECHOCTL)
+        && ((byte < 0x20 && byte != b'\t' && byte != b'\n') || byte == 0x7f)
+}
+
+struct LineDiscipline {
+    /// The line that is currently being edited (canonical mode only).
+    line: Vec<u8>,
+    /// The input that is ready to be read.
+    input: VecDeque<u8>,
+    /// The lengths of the complete lines in `input` (canonical mode only). A line of
+    /// length zero marks an end-of-file.
+    lines: VecDeque<usize>,
+    /// Set when the output has been stopped by the `VSTOP` character.
+    stopped: bool,
+    /// Set when the next character should be inserted literally (`VLNEXT`).
+    literal_next: bool,
+    /// The current output column, used for output processing and erasing.
+    column: usize,
+}
+
+impl LineDiscipline {
+    fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            input: VecDeque::new(),
+            lines: VecDeque::new(),
+            stopped: false,
+            literal_next: false,
+            column: 0,
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.input.clear();
+        self.lines.clear();
+        self.literal_next = false;
+    }
+
+    fn can_read(&self, termios: &Termios) -> bool {
+        if termios.c_lflag.contains(TermiosLFlag::ICANON) {
+            !self.lines.is_empty()
+        } else {
+            !self.input.is_empty()
+        }
+    }
+
+    /// Applies the output processing to `bytes` and appends the result to `output`.
+    fn output(&mut self, termios: &Termios, bytes: &[u8], output: &mut Vec<u8>) {
+        let oflag = termios.c_oflag;
+
+        if !oflag.contains(TermiosOFlag::OPOST) {
+            output.extend_from_slice(bytes);
+            return;
+        }
+
+        for &byte in bytes {
+            match byte {
+                b'\n' => {
+                    if oflag.contains(TermiosOFlag::ONLCR) {
+                        // ONLCR: Convert NL to CR + NL
+                        output.extend_from_slice(&[b'\r', b'\n']);
+                        self.column = 0;
+                        continue;
+                    }
+
+                    if oflag.contains(TermiosOFlag::ONLRET) {
+                        self.column = 0;
+                    }
+                }
+
+                b'\r' => {
+                    // ONOCR: Do not output CR at column zero.
+                    if oflag.contains(TermiosOFlag::ONOCR) && self.column == 0 {
+                        continue;
+                    }
+
+                    if oflag.contains(TermiosOFlag::OCRNL) {
+                        output.push(b'\n');
+
+                        if oflag.contains(TermiosOFlag::ONLRET) {
+                            self.column = 0;
+                        }
+
+                        continue;
+                    }
+
+                    self.column = 0;
+                }
+
+                b'\t' => self.column = (self.column | 7) + 1,
+                0x08 => self.column = self.column.saturating_sub(1),
+                _ if !byte.is_ascii_control() => self.column += 1,
+                _ => {}
+            }
+
+            output.push(byte);
+        }
+    }
+
+    fn echo(&mut self, termios: &Termios, byte: u8, echo: &mut Vec<u8>) {
+        if is_echoed_as_control(termios, byte) {
+            self.output(termios, &[b'^', byte ^ 0x40], echo);
+        } else {
+            self.output(termios, &[byte], echo);
+        }
+    }
+
+    /// Removes the last character of the line and visually erases it if `ECHOE` is set.
+    /// Returns `false` if the line is empty.
+    fn erase_char(&mut self, termios: &Termios, echo: &mut Vec<u8>) -> bool {
+        let byte = match self.line.pop() {
+            Some(byte) => byte,
+            None => return false,
+        };
+
+        if termios
+            .c_lflag
+            .contains(TermiosLFlag::ECHO | TermiosLFlag::ECHOE)
+        {
+            let width = if is_echoed_as_control(termios, byte) {
+                2
+            } else {
+                1
+            };
+
+            for _ in 0..width {
+                self.output(termios, b"\x08 \x08", echo);
+            }
+        }
+
+        true
+    }
+
+    /// Moves the line that is being edited to the input queue.
+    fn end_line(&mut self) {
+        self.lines.push_back(self.line.len());
+        self.input.extend(self.line.drain(..));
+    }
+
+    fn push(&mut self, termios: &Termios, byte: u8, echo: &mut Vec<u8>) {
+        if termios.c_lflag.contains(TermiosLFlag::ICANON) {
+            self.line.push(byte);
+        } else {
+            self.input.push_back(byte);
+        }
+
+        if termios.c_lflag.contains(TermiosLFlag::ECHO) {
+            self.echo(termios, byte, echo);
+        }
+    }
+
+    /// Processes a single input byte. Returns the signal that has to be sent to the
+    /// foreground process group, if any.
+    fn receive(&mut self, termios: &Termios, mut byte: u8, echo: &mut Vec<u8>) -> Option<usize> {
+        let iflag = termios.c_iflag;
+        let lflag = termios.c_lflag;
+
+        if iflag.contains(TermiosIFlag::ISTRIP) {
+            byte &= 0x7f;
+        }
+
+        if self.literal_next {
+            self.literal_next = false;
+
+            if lflag.contains(TermiosLFlag::ECHO | TermiosLFlag::ECHOCTL) {
+                // Erase the `^` that was echoed for the VLNEXT character.
+                self.output(termios, b"\x08", echo);
+            }
+
+            self.push(termios, byte, echo);
+            return None;
+        }
+
+        if iflag.contains(TermiosIFlag::IXON) {
+            if is_special(termios, VSTOP, byte) {
+                self.stopped = true;
+                return None;
+            }
+
+            if is_special(termios, VSTART, byte) {
+                self.stopped = false;
+                return None;
+            }
+
+            if iflag.contains(TermiosIFlag::IXANY) {
+                self.stopped = false;
+            }
+        }
+
+        if lflag.contains(TermiosLFlag::ISIG) {
+            let signal = if is_special(termios, VINTR, byte) {
+                Some(SIGINT)
+            } else if is_special(termios, VQUIT, byte) {
+                Some(SIGQUIT)
+            } else if is_special(termios, VSUSP, byte) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+
+            if let Some(signal) = signal {
+                if !lflag.contains(TermiosLFlag::NOFLSH) {
+                    self.flush_input();
+                }
+
+                if lflag.contains(TermiosLFlag::ECHO) {
+                    self.echo(termios, byte, echo);
+                }
+
+                return Some(signal);
+            }
+        }
+
+        match byte {
+            b'\r' if iflag.contains(TermiosIFlag::IGNCR) => return None,
+            b'\r' if iflag.contains(TermiosIFlag::ICRNL) => byte = b'\n',
+            b'\n' if iflag.contains(TermiosIFlag::INLCR) => byte = b'\r',
+            _ => {}
+        }
+
+        if !lflag.contains(TermiosLFlag::ICANON) {
+            self.push(termios, byte, echo);
+            return None;
+        }
+
+        let echo_enabled = lflag.contains(TermiosLFlag::ECHO);
+        let extended = lflag.contains(TermiosLFlag::IEXTEN);
+
+        if extended && is_special(termios, VLNEXT, byte) {
+            self.literal_next = true;
+
+            if lflag.contains(TermiosLFlag::ECHO | TermiosLFlag::ECHOCTL) {
+                self.output(termios, b"^", echo);
+            }
+        } else if is_special(termios, VERASE, byte) {
+            if self.erase_char(termios, echo)
+                && echo_enabled
+                && !lflag.contains(TermiosLFlag::ECHOE)
+            {
+                self.echo(termios, byte, echo);
+            }
+        } else if extended && is_special(termios, VWERASE, byte) {
+            while self.line.last().map_or(false, |c| c.is_ascii_whitespace()) {
+                self.erase_char(termios, echo);
+            }
+
+            while self.line.last().map_or(false, |c| !c.is_ascii_whitespace()) {
+                self.erase_char(termios, echo);
+            }
+        } else if is_special(termios, VKILL, byte) {
+            if lflag.contains(TermiosLFlag::ECHO | TermiosLFlag::ECHOE | TermiosLFlag::ECHOKE) {
+                while self.erase_char(termios, echo) {}
+            } else {
+                self.line.clear();
+
+                if echo_enabled {
+                    self.echo(termios, byte, echo);
+
+                    if lflag.contains(TermiosLFlag::ECHOK) {
+                        self.output(termios, b"\n", echo);
+                    }
+                }
+            }
+        } else if extended && is_special(termios, VREPRINT, byte) {
+            if echo_enabled {
+                self.echo(termios, byte, echo);
+                self.output(termios, b"\n", echo);
+
+                for byte in self.line.clone() {
+                    self.echo(termios, byte, echo);
+                }
+            }
+        } else if is_special(termios, VEOF, byte) {
+            // The end-of-file character is not added to the line. If the line is empty, the
+            // reader is returned zero bytes which indicates an end-of-file.
+            self.end_line();
+        } else if byte == b'\n'
+            || is_special(termios, VEOL, byte)
+            || is_special(termios, VEOL2, byte)
+        {
+            self.line.push(byte);
+
+            if echo_enabled || (byte == b'\n' && lflag.contains(TermiosLFlag::ECHONL)) {
+                self.echo(termios, byte, echo);
+            }
+
+            self.end_line();
+        } else {
+            self.push(termios, byte, echo);
+        }
+
+        None
+    }
+
+    /// Reads (a part of) the first complete line from the input queue.
+    fn read_line(&mut self, buffer: &mut [u8]) -> usize {
+        let remaining = match self.lines.front_mut() {
+            Some(remaining) => remaining,
+            None => return 0,
+        };
+
+        let size = core::cmp::min(*remaining, buffer.len());
+
+        for (i, byte) in self.input.drain(..size).enumerate() {
+            buffer[i] = byte;
+        }
+
+        // A line of length zero is an end-of-file marker and is consumed by the read as
+        // well.
+        *remaining -= size;
+
+        if *remaining == 0 {
+            self.lines.pop_front();
+        }
+
+        size
+    }
+
+    fn read_raw(&mut self, buffer: &mut [u8]) -> usize {
+        let size = core::cmp::min(self.input.len(), buffer.len());
+
+        for (i, byte) in self.input.drain(..size).enumerate() {
+            buffer[i] = byte;
+        }
+
+        size
+    }
+}
+
+struct JobControl {
+    /// The session this terminal is the controlling terminal of.
+    session: Option<usize>,
+    /// The foreground process group of the session.
+    foreground: Option<usize>,
+}
+
+pub struct Terminal {
+    termios: Mutex<Termios>,
+    window_size: Mutex<WinSize>,
+    job_control: Mutex<JobControl>,
+
+    discipline: Mutex<LineDiscipline>,
+    read_queue: WaitQueue,
+    write_queue: WaitQueue,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        Self {
+            termios: Mutex::new(default_termios()),
+            window_size: Mutex::new(WinSize::default()),
+            job_control: Mutex::new(JobControl {
+                session: None,
+                foreground: None,
+            }),
+
+            discipline: Mutex::new(LineDiscipline::new()),
+            read_queue: WaitQueue::new(),
+            write_queue: WaitQueue::new(),
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        *self.termios.lock_irq()
+    }
+
+    pub fn set_termios(&self, termios: Termios) {
+        let mut discipline = self.discipline.lock_irq();
+        let old = core::mem::replace(&mut *self.termios.lock_irq(), termios);
+
+        let was_canonical = old.c_lflag.contains(TermiosLFlag::ICANON);
+        let canonical = termios.c_lflag.contains(TermiosLFlag::ICANON);
+
+        if was_canonical && !canonical {
+            // The line that was being edited becomes readable as-is.
+            let line = core::mem::take(&mut discipline.line);
+
+            discipline.input.extend(line);
+            discipline.lines.clear();
+        } else if !was_canonical && canonical && !discipline.input.is_empty() {
+            let pending = discipline.input.len();
+            discipline.lines.push_back(pending);
+        }
+
+        if !termios.c_iflag.contains(TermiosIFlag::IXON) {
+            discipline.stopped = false;
+        }
+
+        core::mem::drop(discipline);
+
+        self.read_queue.wake_all();
+        self.write_queue.wake_all();
+    }
+
+    pub fn window_size(&self) -> WinSize {
+        *self.window_size.lock_irq()
+    }
+
+    pub fn set_window_size(&self, window_size: WinSize) {
+        *self.window_size.lock_irq() = window_size;
+    }
+
+    /// Returns the block queue of the readers of the terminal.
+    pub fn read_queue(&self) -> &WaitQueue {
+        &self.read_queue
+    }
+
+    /// Returns whether a read from the terminal would not block.
+    pub fn can_read(&self) -> bool {
+        let termios = self.termios();
+        self.discipline.lock_irq().can_read(&termios)
+    }
+
+    /// Discards the pending input.
+    pub fn flush_input(&self) {
+        self.discipline.lock_irq().flush_input();
+    }
+
+    /// Passes the input `bytes`, received from the device, through the line discipline.
+    /// The bytes that have to be echoed back are appended to `echo`.
+    pub fn receive(&self, bytes: &[u8], echo: &mut Vec<u8>) {
+        let termios = self.termios();
+        let mut signals = Vec::new();
+
+        {
+            let mut discipline = self.discipline.lock_irq();
+
+            for &byte in bytes {
+                if let Some(signal) = discipline.receive(&termios, byte, echo) {
+                    signals.push(signal);
+                }
+            }
+        }
+
+        for signal in signals {
+            self.signal_foreground(signal);
+        }
+
+        self.read_queue.wake_all();
+        self.write_queue.wake_all();
+    }
+
+    /// Applies the output processing to the `bytes` written to the terminal. Blocks while
+    /// the output is stopped by flow control.
+    pub fn process_output(&self, bytes: &[u8]) -> fs::Result<Vec<u8>> {
+        let termios = self.termios();
+        let mut discipline = self
+            .write_queue
+            .wait_until(&self.discipline, |discipline| !discipline.stopped)?;
+
+        let mut output = Vec::with_capacity(bytes.len());
+        discipline.output(&termios, bytes, &mut output);
+
+        Ok(output)
+    }
+
+    /// Reads from the input queue of the terminal, blocking as described by the
+    /// canonical mode or the `VMIN` and `VTIME` values in non-canonical mode.
+    pub fn read(&self, buffer: &mut [u8]) -> fs::Result<usize> {
+        let termios = self.termios();
+
+        if termios.c_lflag.contains(TermiosLFlag::ICANON) {
+            let mut discipline = self
+                .read_queue
+                .wait_until(&self.discipline, |discipline| !discipline.lines.is_empty())?;
+
+            return Ok(discipline.read_line(buffer));
+        }
+
+        let min = core::cmp::min(termios.c_cc[VMIN] as usize, buffer.len());
+        let time = termios.c_cc[VTIME] as usize;
+
+        if time == 0 {
+            // MIN > 0, TIME == 0: block until MIN bytes are available.
+            // MIN == 0, TIME == 0: return whatever is available.
+            let mut discipline = self
+                .read_queue
+                .wait_until(&self.discipline, |discipline| discipline.input.len() >= min)?;
+
+            return Ok(discipline.read_raw(buffer));
+        }
+
+        if min > 0 {
+            // MIN > 0, TIME > 0: the timer is only started after the first byte has
+            // been received.
+            core::mem::drop(
+                self.read_queue
+                    .wait_until(&self.discipline, |discipline| !discipline.input.is_empty())?,
+            );
+        }
+
+        self.wait_input(time, |discipline| {
+            discipline.input.len() >= core::cmp::max(min, 1)
+        })?;
+        Ok(self.discipline.lock_irq().read_raw(buffer))
+    }
+
+    /// Waits until `condition` is satisfied or `time` (in tenths of a second) passes
+    /// without any new input.
+    fn wait_input<F>(&self, time: usize, condition: F) -> fs::Result<()>
+    where
+        F: Fn(&LineDiscipline) -> bool,
+    {
+        let timeout = time as u64 * 100_000_000;
+
+        let mut received = self.discipline.lock_irq().input.len();
+        let mut timer = Timer::wake_current(timer::now() + timeout);
+
+        self.read_queue.wait_until(&self.discipline, |discipline| {
+            if condition(discipline) {
+                return true;
+            }
+
+            // The timer is restarted every time a byte is received.
+            if discipline.input.len() != received {
+                received = discipline.input.len();
+                timer = Timer::wake_current(timer::now() + timeout);
+            }
+
+            timer::now() >= timer.deadline()
+        })?;
+
+        Ok(())
+    }
+
+    /// Returns the global ID of the foreground process group of the terminal (if any).
+    pub fn foreground_group(&self) -> Option<usize> {
+        self.job_control.lock_irq().foreground
+    }
+
+    /// Sends the provided `signal` to every process in the foreground process group. The
+    /// bytes may be received from the keyboard interrupt handler, which cannot take the
+    /// lock of the task list, so the signal is delivered from a work item.
+    pub fn signal_foreground(&self, signal: usize) {
+        if let Some(pgid) = self.foreground_group() {
+            workqueue::schedule(move || {
+                let root = PidNamespace::root();
+
+                for task in scheduler::get_scheduler().find_process_group(&root, pgid) {
+                    task.signal(signal);
+                }
+            });
+        }
+    }
+
+    /// Handles the terminal ioctls that are common between all of the terminal
+    /// devices.
+    pub fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            aero_syscall::TIOCGWINSZ => {
+                let winsize = VirtAddr::new(arg as u64)
+                    .read_mut::<WinSize>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                *winsize = self.window_size();
+                Ok(0)
+            }
+
+            aero_syscall::TIOCSWINSZ => {
+                let winsize = VirtAddr::new(arg as u64)
+                    .read_mut::<WinSize>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                self.set_window_size(*winsize);
+                Ok(0)
+            }
+
+            aero_syscall::TCGETS => {
+                let termios = VirtAddr::new(arg as u64)
+                    .read_mut::<Termios>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                *termios = self.termios();
+                Ok(0)
+            }
+
+            aero_syscall::TCSETS | aero_syscall::TCSETSW | aero_syscall::TCSETSF => {
+                let termios = VirtAddr::new(arg as u64)
+                    .read_mut::<Termios>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                // TCSETSF: Discard the pending input before setting the attributes.
+                if command == aero_syscall::TCSETSF {
+                    self.flush_input();
+                }
+
+                self.set_termios(*termios);
+                Ok(0)
+            }
+
+            aero_syscall::TIOCSCTTY => {
+                let task = scheduler::get_scheduler().current_task();
+                let mut job_control = self.job_control.lock_irq();
+
+                // Only a session leader can acquire a controlling terminal and the terminal
+                // cannot be the controlling terminal of another session.
+                if !task.is_session_leader() {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                if let Some(session) = job_control.session {
+                    if session != task.sid() {
+                        return Err(FileSystemError::NotSupported);
+                    }
+                }
+
+                job_control.session = Some(task.sid());
+                job_control.foreground = Some(task.pgid());
+                Ok(0)
+            }
+
+            aero_syscall::TIOCNOTTY => {
+                let task = scheduler::get_scheduler().current_task();
+                let mut job_control = self.job_control.lock_irq();
+
+                if job_control.session != Some(task.sid()) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                if task.is_session_leader() {
+                    job_control.session = None;
+                    job_control.foreground = None;
+                }
+
+                Ok(0)
+            }
+
+            aero_syscall::TIOCGPGRP => {
+                let pgid = VirtAddr::new(arg as u64)
+                    .read_mut::<u32>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                // Translate the ID through the PID namespace of the caller.
+                let task = scheduler::get_scheduler().current_task();
+                let foreground = self.foreground_group().and_then(|group| {
+                    scheduler::get_scheduler()
+                        .find_process_group(&PidNamespace::root(), group)
+                        .first()
+                        .and_then(|member| member.pgid_in(task.pid_namespace()))
+                });
+
+                *pgid = foreground.unwrap_or(0) as u32;
+                Ok(0)
+            }
+
+            aero_syscall::TIOCSPGRP => {
+                let pgid = *VirtAddr::new(arg as u64)
+                    .read_mut::<u32>()
+                    .ok_or(FileSystemError::NotSupported)? as usize;
+
+                let task = scheduler::get_scheduler().current_task();
+                let mut job_control = self.job_control.lock_irq();
+
+                // The process group must be in the same session as the caller and the
+                // terminal must be the controlling terminal of that session.
+                if job_control.session != Some(task.sid()) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                let group = scheduler::get_scheduler()
+                    .find_process_group(task.pid_namespace(), pgid)
+                    .iter()
+                    .find(|member| member.sid() == task.sid())
+                    .map(|member| member.pgid())
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                job_control.foreground = Some(group);
+                Ok(0)
+            }
+
+            aero_syscall::TIOCGSID => {
+                let sid = VirtAddr::new(arg as u64)
+                    .read_mut::<u32>()
+                    .ok_or(FileSystemError::NotSupported)?;
+
+                let task = scheduler::get_scheduler().current_task();
+                let session = self.job_control.lock_irq().session;
+
+                // Only the controlling terminal of the caller reports its session, which
+                // is translated through the PID namespace of the caller.
+                if session != Some(task.sid()) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                *sid = task.sid_in(task.pid_namespace()).unwrap_or(0) as u32;
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotTty),
+        }
+    }
+}
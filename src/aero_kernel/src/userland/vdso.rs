@@ -0,0 +1,160 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The vDSO is a small image (see `arch/x86_64/vdso.asm`) that is mapped into every user
+//! address space along with the vvar page, which holds the kernel-updated clocks. This
+//! allows timing-heavy programs to read the clocks without the overhead of a system call.
+//!
+//! Both of the pages are allocated once at boot and are shared between all of the address
+//! spaces.
+//!
+//! **Notes**: <https://man7.org/linux/man-pages/man7/vdso.7.html>
+
+use core::sync::atomic::{fence, Ordering};
+
+use aero_syscall::vdso::{VdsoData, VVAR_SIZE};
+use aero_syscall::{MMapFlags, MMapProt, TimeSpec};
+
+use alloc::sync::Arc;
+use spin::Once;
+
+use crate::fs;
+use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::fs::FileSystemError;
+use crate::mem::paging::*;
+
+use super::vm::Vm;
+
+struct Vdso {
+    vvar: PhysFrame,
+    image: PhysFrame,
+}
+
+static VDSO: Once<Vdso> = Once::new();
+
+/// Inode backing the vDSO mapping. Offset `0` is the vvar page and offset [`VVAR_SIZE`]
+/// is the vDSO image.
+struct VdsoINode;
+
+impl INodeInterface for VdsoINode {
+    fn mmap(&self, offset: usize, _size: usize, _flags: MMapFlags) -> fs::Result<PhysFrame> {
+        let vdso = VDSO.get().expect("vdso: not initialized");
+
+        match offset {
+            0 => Ok(vdso.vvar),
+            VVAR_SIZE => Ok(vdso.image),
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+}
+
+fn vvar_data() -> Option<&'static mut VdsoData> {
+    VDSO.get().map(|vdso| {
+        let ptr = vdso
+            .vvar
+            .start_address()
+            .as_hhdm_virt()
+            .as_mut_ptr::<VdsoData>();
+
+        // SAFETY: The vvar frame is allocated in [`init`] and is never deallocated.
+        unsafe { &mut *ptr }
+    })
+}
+
+/// Updates the clocks in the vvar page. This function is called from the timer IRQ
+/// handler, which is the only writer of the vvar page.
+pub fn update_clocks(realtime: &TimeSpec, monotonic: &TimeSpec) {
+    if let Some(data) = vvar_data() {
+        unsafe {
+            let seq = core::ptr::read_volatile(&data.seq);
+
+            // Mark the page as being updated (ie. make the sequence counter odd).
+            core::ptr::write_volatile(&mut data.seq, seq.wrapping_add(1));
+            fence(Ordering::Release);
+
+            core::ptr::write_volatile(&mut data.realtime, realtime.clone());
+            core::ptr::write_volatile(&mut data.monotonic, monotonic.clone());
+
+            fence(Ordering::Release);
+            core::ptr::write_volatile(&mut data.seq, seq.wrapping_add(2));
+        }
+    }
+}
+
+/// Maps the vDSO into the provided `vm` and returns the base address of the
+/// mapping (ie. the address of the vvar page).
+pub fn map(vm: &Vm) -> Option<VirtAddr> {
+    VDSO.get()?;
+
+    let file = DirEntry::from_inode(Arc::new(VdsoINode), String::from("<vdso>"));
+
+    vm.mmap(
+        VirtAddr::zero(),
+        VVAR_SIZE + Size4KiB::SIZE as usize,
+        MMapProt::PROT_READ | MMapProt::PROT_EXEC,
+        MMapFlags::MAP_SHARED,
+        0,
+        Some(file),
+    )
+}
+
+pub fn init() {
+    extern "C" {
+        static vdso_image_start: u8;
+        static vdso_image_end: u8;
+    }
+
+    let image = unsafe {
+        let start = &vdso_image_start as *const u8;
+        let end = &vdso_image_end as *const u8;
+
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+
+    assert!(image.len() <= Size4KiB::SIZE as usize);
+
+    let vvar: PhysFrame = FRAME_ALLOCATOR
+        .allocate_frame()
+        .expect("vdso: failed to allocate the vvar frame");
+
+    let frame: PhysFrame = FRAME_ALLOCATOR
+        .allocate_frame()
+        .expect("vdso: failed to allocate the image frame");
+
+    frame.as_slice_mut::<u8>()[..image.len()].copy_from_slice(image);
+
+    // The frames are shared between all of the address spaces. Hold a reference to them so,
+    // they are not deallocated when they are unmapped from the last address space.
+    for frame in [vvar, frame] {
+        frame
+            .start_address()
+            .as_vm_frame()
+            .expect("vdso: frame is not tracked")
+            .inc_ref_count();
+    }
+
+    VDSO.call_once(|| Vdso { vvar, image: frame });
+
+    // The CPU ID is only stored in `IA32_TSC_AUX` if `rdtscp` is supported.
+    #[cfg(target_arch = "x86_64")]
+    if crate::arch::features::cpu_has!(Rdtscp) {
+        vvar_data().unwrap().flags |= aero_syscall::vdso::VDSO_FLAG_RDTSCP;
+    }
+    log::debug!("vdso: image size {:#x}", image.len());
+}
@@ -18,12 +18,14 @@
  */
 
 use core::fmt::Write;
+use core::ops::Range;
 
 use aero_syscall::{MMapFlags, MMapProt};
 
 use alloc::boxed::Box;
 use alloc::collections::linked_list::CursorMut;
 use alloc::collections::LinkedList;
+use alloc::sync::Arc;
 
 use xmas_elf::header::*;
 use xmas_elf::program::*;
@@ -42,11 +44,20 @@ use crate::mem::AddressSpace;
 use crate::syscall::ExecArgs;
 use crate::utils::sync::Mutex;
 
+use super::cgroup::TaskGroup;
+
 const ELF_HEADER_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
 const ELF_PT1_SIZE: usize = core::mem::size_of::<HeaderPt1>();
 const ELF_PT2_64_SIZE: usize = core::mem::size_of::<HeaderPt2_<P64>>();
 
+/// Base address of position independent executables (`ET_DYN`).
+const ELF_PIE_BASE: u64 = 0x5555_0000_0000;
+/// Base address of the program interpreter (`PT_INTERP`).
+const ELF_INTERP_BASE: u64 = 0x6000_0000_0000;
+/// Number of pages the base addresses above are randomized by (1 TiB).
+const ELF_ASLR_PAGES: u64 = 1 << 28;
+
 #[derive(Debug)]
 pub enum ElfLoadError {
     /// Unexpected file system error occured on an IO operation on the file.
@@ -60,6 +71,8 @@ pub enum ElfLoadError {
     /// Unexpected file system error occured when memory mapping an
     /// ELF segment.
     MemoryMapError,
+    /// The program interpreter path (`PT_INTERP`) is not a valid UTF-8 string.
+    InvalidInterpreter,
 }
 
 fn parse_elf_header<'header>(file: DirCacheItem) -> Result<Header<'header>, ElfLoadError> {
@@ -316,11 +329,55 @@ enum UnmapResult {
     End,
 }
 
+/// Returns a random page-aligned offset used to randomize the load address of
+/// position independent executables and the program interpreter. The randomization is
+/// disabled with the `nokaslr` kernel command line option.
+fn aslr_offset() -> u64 {
+    if crate::cmdline::get().nokaslr {
+        return 0;
+    }
+
+    (crate::random::get_u64() % ELF_ASLR_PAGES) * Size4KiB::SIZE
+}
+
+/// Reads the program interpreter path from the provided `PT_INTERP` program header.
+fn parse_interpreter(file: DirCacheItem, header: ProgramHeader) -> Result<String, ElfLoadError> {
+    let mut buffer = mem::alloc_boxed_buffer::<u8>(header.file_size() as usize);
+
+    file.inode()
+        .read_at(header.offset() as usize, &mut buffer)
+        .map_err(|err| ElfLoadError::IOError(err))?;
+
+    // The path is NUL terminated.
+    let path = buffer.split(|c| *c == 0).next().unwrap_or(&[]);
+
+    core::str::from_utf8(path)
+        .map(String::from)
+        .map_err(|_| ElfLoadError::InvalidInterpreter)
+}
+
+/// Result of mapping the segments of a single ELF image.
+struct ElfImage {
+    entry_point: VirtAddr,
+    base_addr: VirtAddr,
+    /// Address of the program headers in memory.
+    phdr: VirtAddr,
+    interpreter: Option<String>,
+}
+
 pub struct LoadedBinary<'header> {
     pub elf: Elf<'header>,
 
+    /// Address where the execution starts. This is the entry point of the program
+    /// interpreter, if the executable requested one.
     pub entry_point: VirtAddr,
+    /// Entry point of the executable itself.
+    pub program_entry: VirtAddr,
     pub base_addr: VirtAddr,
+    /// Address of the program headers of the executable in memory.
+    pub phdr: VirtAddr,
+    /// Base address of the program interpreter (if any).
+    pub interp_base: Option<VirtAddr>,
 
     pub argv: Option<ExecArgs>,
     pub envv: Option<ExecArgs>,
@@ -552,22 +609,32 @@ impl Mapping {
         }
     }
 
+    /// Unmaps the `start..end` range of the mapping, subtracting the amount of pages that
+    /// were actually mapped from `resident`.
     fn unmap(
         &mut self,
         offset_table: &mut OffsetPageTable,
         start: VirtAddr,
         end: VirtAddr,
+        resident: &mut u64,
     ) -> Result<UnmapResult, UnmapError> {
-        let mut unmap_range_inner = |range| -> Result<(), UnmapError> {
-            match offset_table.unmap_range(range, Size4KiB::SIZE) {
-                Ok(_) => Ok(()),
-
-                // its fine since technically we are not actually allocating the range
-                // and they are just allocated on faults. So there might be a chance where we
-                // try to unmap a region that is mapped but not actually allocated.
-                Err(UnmapError::PageNotMapped) => Ok(()),
-                Err(err) => return Err(err),
+        let mut unmap_range_inner = |range: Range<VirtAddr>| -> Result<(), UnmapError> {
+            for addr in range.step_by(Size4KiB::SIZE as usize) {
+                match offset_table.unmap(Page::<Size4KiB>::containing_address(addr)) {
+                    Ok((_, flush)) => {
+                        flush.flush();
+                        *resident = resident.saturating_sub(1);
+                    }
+
+                    // its fine since technically we are not actually allocating the range
+                    // and they are just allocated on faults. So there might be a chance where we
+                    // try to unmap a region that is mapped but not actually allocated.
+                    Err(UnmapError::PageNotMapped) => {}
+                    Err(err) => return Err(err),
+                }
             }
+
+            Ok(())
         };
 
         if end <= self.start_addr || start >= self.end_addr {
@@ -626,15 +693,116 @@ impl Mapping {
 
 struct VmProtected {
     mappings: LinkedList<Mapping>,
+    /// The amount of pages mapped in the VM.
+    resident: u64,
 }
 
 impl VmProtected {
     fn new() -> Self {
         Self {
             mappings: LinkedList::new(),
+            resident: 0,
         }
     }
 
+    /// Returns whether the range `start..end` is covered by mappings with the
+    /// `protection`. The mappings are sorted by their address.
+    fn is_range_mapped(&self, start: VirtAddr, end: VirtAddr, protection: MMapProt) -> bool {
+        let mut cursor = start;
+
+        for map in self.mappings.iter().filter(|map| map.end_addr > start) {
+            if map.start_addr > cursor || !map.protection.contains(protection) {
+                return false;
+            }
+
+            cursor = map.end_addr;
+
+            if cursor >= end {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Writes the `bytes` to `address` regardless of the protection of the private
+    /// mappings covering it, on behalf of a debugger. The written pages are made private
+    /// copies first, so that the other tasks mapping the same file are not affected.
+    fn write_forced(&mut self, address: VirtAddr, bytes: &[u8]) -> bool {
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let address = address + offset as u64;
+            let page = address.align_down(Size4KiB::SIZE);
+            let size = core::cmp::min(
+                bytes.len() - offset,
+                (page + Size4KiB::SIZE - address) as usize,
+            );
+
+            let map = match self
+                .mappings
+                .iter_mut()
+                .find(|e| address >= e.start_addr && address < e.end_addr)
+            {
+                Some(map) => map,
+                None => return false,
+            };
+
+            if map.protection.is_empty() || !map.flags.contains(MMapFlags::MAP_PRIVATE) {
+                return false;
+            }
+
+            let is_annon = map.flags.contains(MMapFlags::MAP_ANONYOMUS);
+
+            // Map the page in if it was not accessed yet.
+            if offset_table.translate_addr(address).is_none() {
+                let reason = PageFaultErrorCode::empty();
+                let mapped = if is_annon {
+                    map.handle_pf_private_anon(&mut offset_table, reason, address)
+                } else {
+                    map.handle_pf_file(&mut offset_table, reason, address)
+                };
+
+                if !mapped {
+                    return false;
+                }
+            }
+
+            if !map.handle_cow(&mut offset_table, page, !is_annon) {
+                return false;
+            }
+
+            let phys = match offset_table.translate_addr(address) {
+                Some(phys) => phys,
+                None => return false,
+            };
+
+            // The page might not be writable, so write through the higher half direct map.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    bytes[offset..].as_ptr(),
+                    phys.as_hhdm_virt().as_mut_ptr::<u8>(),
+                    size,
+                );
+            }
+
+            offset += size;
+        }
+
+        true
+    }
+
+    /// Returns the size of the address space, in bytes.
+    fn size(&self) -> u64 {
+        self.mappings
+            .iter()
+            .map(|map| map.end_addr - map.start_addr)
+            .sum()
+    }
+
     fn log(&self) {
         for mmap in &self.mappings {
             if let Some(file) = mmap.file.as_ref() {
@@ -903,22 +1071,58 @@ impl VmProtected {
         }
 
         let elf = Elf::new(bin.clone())?;
+        let image = self.load_elf(&elf, ELF_PIE_BASE)?;
+
+        let (entry_point, interp_base) = if let Some(interpreter) = image.interpreter {
+            log::debug!("interpreter: {}", interpreter);
+
+            let ld = fs::lookup_path(fs::Path::new(&interpreter))
+                .map_err(|err| ElfLoadError::IOError(err))?;
+
+            let ld = Elf::new(ld)?;
+            let ld_image = self.load_elf(&ld, ELF_INTERP_BASE)?;
+
+            (ld_image.entry_point, Some(ld_image.base_addr))
+        } else {
+            (image.entry_point, None)
+        };
+
+        Ok(LoadedBinary {
+            elf,
+            entry_point,
+
+            program_entry: image.entry_point,
+            base_addr: image.base_addr,
+            phdr: image.phdr,
+            interp_base,
+
+            argv,
+            envv,
+        })
+    }
+
+    /// Maps the segments of the provided `elf` image into the VM. Position independent
+    /// images (`ET_DYN`) are loaded at a random offset from `pie_base`.
+    fn load_elf(&mut self, elf: &Elf, pie_base: u64) -> Result<ElfImage, ElfLoadError> {
+        let bin = elf.file.clone();
         let header = &elf.header;
 
         let load_offset = VirtAddr::new(
             if header.pt2.type_().as_type() == header::Type::SharedObject {
-                0x40000000u64
+                pie_base + aslr_offset()
             } else {
                 0u64
             },
         );
 
-        let mut entry_point = load_offset + header.pt2.entry_point();
+        let entry_point = load_offset + header.pt2.entry_point();
 
         log::debug!("entry point: {:#x}", entry_point);
         log::debug!("entry point type: {:?}", header.pt2.type_().as_type());
 
         let mut base_addr = VirtAddr::zero();
+        let mut phdr = None;
+        let mut interpreter = None;
 
         for header in elf.program_iter() {
             let header_type = header
@@ -1013,29 +1217,29 @@ impl VmProtected {
                     .ok_or(ElfLoadError::MemoryMapError)?;
                 }
             } else if header_type == xmas_elf::program::Type::Tls {
+            } else if header_type == xmas_elf::program::Type::Phdr {
+                phdr = Some(load_offset + header.virtual_addr());
             } else if header_type == xmas_elf::program::Type::Interp {
-                let ld = fs::lookup_path(fs::Path::new("/usr/lib/ld.so")).unwrap();
-
-                let res = self.load_bin(ld, None, None)?;
-                entry_point = res.entry_point;
+                interpreter = Some(parse_interpreter(bin.clone(), header)?);
             }
         }
 
-        Ok(LoadedBinary {
-            elf,
+        Ok(ElfImage {
             entry_point,
-
             base_addr,
 
-            argv,
-            envv,
+            // If the executable does not have a `PT_PHDR` segment, assume that the program
+            // headers are mapped as a part of the first loadable segment.
+            phdr: phdr.unwrap_or(base_addr + header.pt2.ph_offset()),
+            interpreter,
         })
     }
 
     /// Clears all of the mappings without unmapping them. The caller is responsible
     /// for going through the page table and unmapping all of the pages.
     fn clear(&mut self) {
-        self.mappings.clear()
+        self.mappings.clear();
+        self.resident = 0;
     }
 
     fn munmap(&mut self, address: VirtAddr, size: usize) -> bool {
@@ -1054,7 +1258,7 @@ impl VmProtected {
             if map.end_addr <= start {
                 cursor.move_next();
             } else {
-                match map.unmap(&mut offset_table, start, end) {
+                match map.unmap(&mut offset_table, start, end, &mut self.resident) {
                     Ok(result) => match result {
                         UnmapResult::None => return success,
                         UnmapResult::Start => return true,
@@ -1088,11 +1292,14 @@ impl VmProtected {
 
         // Copy over all of the mappings from the parent into the child.
         self.mappings = data.mappings.clone();
+        self.resident = data.resident;
     }
 }
 
 pub struct Vm {
     inner: Mutex<VmProtected>,
+    /// The task group the resident pages of the VM are charged to.
+    group: Mutex<Arc<TaskGroup>>,
 }
 
 impl Vm {
@@ -1100,9 +1307,20 @@ impl Vm {
     pub(super) fn new() -> Self {
         Self {
             inner: Mutex::new(VmProtected::new()),
+            group: Mutex::new(TaskGroup::root()),
         }
     }
 
+    /// Runs `f` on the VM and releases the charge of the pages it unmapped.
+    fn update<R>(&self, f: impl FnOnce(&mut VmProtected) -> R) -> R {
+        let mut inner = self.inner.lock_irq();
+        let resident = inner.resident;
+        let result = f(&mut inner);
+
+        self.group().uncharge(resident - inner.resident);
+        result
+    }
+
     pub fn mmap(
         &self,
         address: VirtAddr,
@@ -1112,17 +1330,52 @@ impl Vm {
         offset: usize,
         file: Option<DirCacheItem>,
     ) -> Option<VirtAddr> {
-        self.inner
-            .lock_irq()
-            .mmap(address, size, protection, flags, offset, file)
+        self.update(|vm| vm.mmap(address, size, protection, flags, offset, file))
     }
 
     pub fn munmap(&self, address: VirtAddr, size: usize) -> bool {
-        self.inner.lock_irq().munmap(address, size)
+        self.update(|vm| vm.munmap(address, size))
     }
 
+    /// Copies the mappings of the `parent` VM. The pages it has resident are shared
+    /// copy-on-write, and are charged to the task group of the parent once more.
     pub(super) fn fork_from(&self, parent: &Vm) {
-        self.inner.lock_irq().fork_from(parent)
+        let mut inner = self.inner.lock_irq();
+        let group = parent.group();
+
+        inner.fork_from(parent);
+        group.charge(inner.resident);
+
+        *self.group.lock_irq() = group;
+    }
+
+    /// Returns the task group the resident pages of the VM are charged to.
+    pub fn group(&self) -> Arc<TaskGroup> {
+        self.group.lock_irq().clone()
+    }
+
+    /// Moves the charge of the resident pages of the VM to the `group`.
+    pub(super) fn set_group(&self, group: Arc<TaskGroup>) {
+        let inner = self.inner.lock_irq();
+        let mut current = self.group.lock_irq();
+
+        current.uncharge(inner.resident);
+        group.charge(inner.resident);
+        *current = group;
+    }
+
+    /// Returns the amount of pages mapped in the VM.
+    pub fn resident(&self) -> u64 {
+        self.inner.lock_irq().resident
+    }
+
+    /// Releases the charge of all of the resident pages of the VM, once the process that
+    /// owns it exited.
+    pub(super) fn uncharge_all(&self) {
+        let mut inner = self.inner.lock_irq();
+
+        self.group().uncharge(inner.resident);
+        inner.resident = 0;
     }
 
     /// Mapping the provided `bin` file into the VM.
@@ -1132,12 +1385,12 @@ impl Vm {
         argv: Option<ExecArgs>,
         envv: Option<ExecArgs>,
     ) -> Result<LoadedBinary, ElfLoadError> {
-        self.inner.lock_irq().load_bin(bin, argv, envv)
+        self.update(|vm| vm.load_bin(bin, argv, envv))
     }
 
     /// Clears and unmaps all of the mappings in the VM.
     pub(super) fn clear(&self) {
-        self.inner.lock_irq().clear()
+        self.update(|vm| vm.clear())
     }
 
     /// This function is responsible for handling page faults occured in
@@ -1148,9 +1401,44 @@ impl Vm {
         reason: PageFaultErrorCode,
         accessed_address: VirtAddr,
     ) -> bool {
+        // A fault on a page that is not present maps a new page, charge it to the task
+        // group first.
+        let maps_page = !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+        let group = self.group();
+
+        if maps_page && group.try_charge(1).is_err() {
+            log::warn!("vm: memory limit of the task group reached");
+            return false;
+        }
+
+        let mut inner = self.inner.lock_irq();
+        let result = inner.handle_page_fault(reason, accessed_address);
+
+        if maps_page && result {
+            inner.resident += 1;
+        } else if maps_page {
+            group.uncharge(1);
+        }
+
+        result
+    }
+
+    /// Returns whether the range `start..end` is mapped with the `protection`.
+    pub fn is_range_mapped(&self, start: VirtAddr, end: VirtAddr, protection: MMapProt) -> bool {
         self.inner
             .lock_irq()
-            .handle_page_fault(reason, accessed_address)
+            .is_range_mapped(start, end, protection)
+    }
+
+    /// Writes the `bytes` to `address` in the current address space, ignoring the
+    /// protection of the private mappings (see `PTRACE_POKEDATA`).
+    pub fn write_forced(&self, address: VirtAddr, bytes: &[u8]) -> bool {
+        self.inner.lock_irq().write_forced(address, bytes)
+    }
+
+    /// Returns the size of the address space, in bytes.
+    pub fn size(&self) -> u64 {
+        self.inner.lock_irq().size()
     }
 
     pub(crate) fn log(&self) {
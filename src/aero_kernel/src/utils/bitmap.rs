@@ -150,6 +150,37 @@ impl<A: Allocator> Bitmap<A> {
 
         None
     }
+
+    /// Returns the index of the first set bit at or after `start`.
+    ///
+    /// ## Example
+    /// ```rust
+    /// use alloc::alloc::Global;
+    ///
+    /// let mut bitmap = Bitmap::new_in(Global, 4096);
+    ///
+    /// bitmap.set(69, true);
+    /// bitmap.set(420, true);
+    /// assert_eq!(bitmap.find_next_set(70), Some(420));
+    /// ```
+    pub fn find_next_set(&self, start: usize) -> Option<usize> {
+        let first_block = start / BLOCK_BITS;
+
+        for (i, block) in self.bitmap.iter().enumerate().skip(first_block) {
+            let mut block_value = *block;
+
+            // Ignore the bits before `start` in the first block.
+            if i == first_block {
+                block_value &= !0usize << (start % BLOCK_BITS);
+            }
+
+            if block_value != 0 {
+                return Some((i * BLOCK_BITS) + block_value.trailing_zeros() as usize);
+            }
+        }
+
+        None
+    }
 }
 
 #[cfg(test)]
@@ -176,6 +207,19 @@ mod test {
         assert_eq!(bitmap.find_first_set(), Some(69));
     }
 
+    #[test]
+    fn bitmap_next_set_idx() {
+        let mut bitmap = Bitmap::new_in(Global, 4096);
+
+        bitmap.set(69, true);
+        bitmap.set(420, true);
+
+        assert_eq!(bitmap.find_next_set(0), Some(69));
+        assert_eq!(bitmap.find_next_set(69), Some(69));
+        assert_eq!(bitmap.find_next_set(70), Some(420));
+        assert_eq!(bitmap.find_next_set(421), None);
+    }
+
     #[test]
     fn bitmap_set_and_test() {
         let mut bitmap = Bitmap::new_in(Global, 4096);
@@ -0,0 +1,395 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Lock dependency tracking, enabled by the `lockdep` feature.
+//!
+//! Every [`Mutex`] belongs to a lock class, which is the location it was created at (so,
+//! for example, all of the wait queues share a class). When a lock is acquired while
+//! other locks are held, an edge from each of the held classes to the acquired class is
+//! added to the dependency graph. If the acquired class already (transitively) depends
+//! on one of the held classes, the two orders can deadlock and both chains are reported.
+//!
+//! Sleeping (see [`might_sleep`]) while holding a spinlock or with interrupts disabled
+//! is reported as well.
+//!
+//! The held locks are tracked per task, so that a task that is preempted while holding a
+//! lock does not leave it held for the next task on its CPU. Before the scheduler runs a
+//! task on a CPU (early in the boot and in the idle task), they are tracked per CPU.
+//!
+//! The tracking starts once [`init`] is called. The graph is stored in fixed-size
+//! tables, as the allocator itself is protected by a lock.
+//!
+//! [`Mutex`]: crate::utils::sync::Mutex
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::arch::interrupts;
+use crate::userland::scheduler;
+
+use super::sync::IrqGuard;
+
+pub type LockClass = &'static Location<'static>;
+
+const MAX_CPUS: usize = 64;
+const MAX_HELD_LOCKS: usize = 32;
+const MAX_CLASSES: usize = 1024;
+const MAX_CHAIN: usize = 16;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The stack of the locks held by a task or a CPU.
+pub struct HeldLocks {
+    classes: [Option<LockClass>; MAX_HELD_LOCKS],
+    len: usize,
+    /// Set while a report is being logged, as logging takes locks itself.
+    reporting: bool,
+}
+
+impl HeldLocks {
+    pub const fn new() -> Self {
+        Self {
+            classes: [None; MAX_HELD_LOCKS],
+            len: 0,
+            reporting: false,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = LockClass> + '_ {
+        self.classes[..self.len].iter().flatten().copied()
+    }
+}
+
+struct Graph {
+    /// Open addressing hash table of the lock classes. The index of a class in this
+    /// table is its index in the adjacency matrix.
+    classes: [Option<LockClass>; MAX_CLASSES],
+    /// `edges[a]` has bit `b` set if `b` was acquired while `a` was held.
+    edges: [[u64; MAX_CLASSES / 64]; MAX_CLASSES],
+}
+
+impl Graph {
+    const fn new() -> Self {
+        Self {
+            classes: [None; MAX_CLASSES],
+            edges: [[0; MAX_CLASSES / 64]; MAX_CLASSES],
+        }
+    }
+
+    /// Returns the index of the class, adding it if needed. Returns [`None`] if the
+    /// table is full.
+    fn index_of(&mut self, class: LockClass) -> Option<usize> {
+        let hash = (class as *const Location as usize >> 3) % MAX_CLASSES;
+
+        for probe in 0..MAX_CLASSES {
+            let index = (hash + probe) % MAX_CLASSES;
+
+            match self.classes[index] {
+                Some(this) if core::ptr::eq(this, class) => return Some(index),
+                Some(_) => continue,
+                None => {
+                    self.classes[index] = Some(class);
+                    return Some(index);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn has_edge(&self, from: usize, to: usize) -> bool {
+        self.edges[from][to / 64] & (1 << (to % 64)) != 0
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        self.edges[from][to / 64] |= 1 << (to % 64);
+    }
+
+    /// Finds a path from `from` to `to` (with a breadth-first search, so the shortest
+    /// one) and returns it, truncated to [`MAX_CHAIN`] classes.
+    fn find_path(&self, from: usize, to: usize) -> Option<Chain> {
+        let mut parent = [u16::MAX; MAX_CLASSES];
+        let mut queue = [0u16; MAX_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+
+        queue[0] = from as u16;
+        parent[from] = from as u16;
+
+        while head < tail {
+            let node = queue[head] as usize;
+            head += 1;
+
+            if node == to {
+                let mut chain = Chain::default();
+                let mut node = to;
+
+                // Walk the path backwards and then reverse it.
+                loop {
+                    chain.push(self.classes[node].unwrap());
+
+                    if node == from {
+                        break;
+                    }
+
+                    node = parent[node] as usize;
+                }
+
+                chain.classes[..chain.len].reverse();
+                return Some(chain);
+            }
+
+            for next in 0..MAX_CLASSES {
+                if parent[next] == u16::MAX && self.has_edge(node, next) {
+                    parent[next] = node as u16;
+                    queue[tail] = next as u16;
+                    tail += 1;
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Default)]
+struct Chain {
+    classes: [Option<LockClass>; MAX_CHAIN],
+    len: usize,
+}
+
+impl Chain {
+    fn push(&mut self, class: LockClass) {
+        if self.len < MAX_CHAIN {
+            self.classes[self.len] = Some(class);
+            self.len += 1;
+        }
+    }
+}
+
+impl core::fmt::Display for Chain {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, class) in self.classes[..self.len].iter().flatten().enumerate() {
+            if i != 0 {
+                write!(f, " -> ")?;
+            }
+
+            write!(f, "{}", class)?;
+        }
+
+        Ok(())
+    }
+}
+
+static GRAPH: spin::Mutex<Graph> = spin::Mutex::new(Graph::new());
+
+const HELD_EMPTY: spin::Mutex<HeldLocks> = spin::Mutex::new(HeldLocks::new());
+static HELD: [spin::Mutex<HeldLocks>; MAX_CPUS] = [HELD_EMPTY; MAX_CPUS];
+
+/// Returns the ID of the current CPU, or [`None`] if its TLS is not set up yet.
+fn current_cpu() -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::io;
+
+        if unsafe { io::rdmsr(io::IA32_GS_BASE) } == 0 {
+            return None;
+        }
+
+        Some(crate::arch::tls::get_cpuid()).filter(|cpu| *cpu < MAX_CPUS)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    None
+}
+
+/// Runs `f` with the locks held by the task running on the current CPU, or by the CPU
+/// itself if it does not run a task yet.
+fn with_held<R, F: FnOnce(&mut HeldLocks) -> R>(cpu: usize, f: F) -> R {
+    let task = if scheduler::is_initialized() {
+        scheduler::get_scheduler().inner.current_task_optional()
+    } else {
+        None
+    };
+
+    match task {
+        Some(task) => f(&mut task.held_locks().lock()),
+        None => f(&mut HELD[cpu].lock()),
+    }
+}
+
+/// Runs `report` with the reports of the current task disabled, so that the locks taken
+/// while logging are not tracked.
+fn report<F: FnOnce()>(cpu: usize, report: F) {
+    with_held(cpu, |held| held.reporting = true);
+
+    report();
+    crate::unwind::unwind_stack_trace();
+
+    with_held(cpu, |held| held.reporting = false);
+}
+
+/// Records the acquisition of a lock of the provided class. Must be called before
+/// spinning on the lock, so that a deadlock is reported before it happens.
+pub fn acquire(class: LockClass) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let _guard = IrqGuard::new();
+
+    let cpu = match current_cpu() {
+        Some(cpu) => cpu,
+        None => return,
+    };
+
+    let conflict = with_held(cpu, |held| {
+        if held.reporting {
+            return None;
+        }
+
+        let mut conflict = None;
+
+        {
+            let mut graph = GRAPH.lock();
+
+            let to = graph.index_of(class)?;
+
+            for holding in held.iter() {
+                // Locks of the same class are allowed to nest.
+                if core::ptr::eq(holding, class) {
+                    continue;
+                }
+
+                let from = graph.index_of(holding)?;
+
+                if graph.has_edge(from, to) {
+                    continue;
+                }
+
+                if conflict.is_none() {
+                    conflict = graph.find_path(to, from).map(|chain| (holding, chain));
+                }
+
+                graph.add_edge(from, to);
+            }
+        }
+
+        if held.len < MAX_HELD_LOCKS {
+            let len = held.len;
+
+            held.classes[len] = Some(class);
+            held.len += 1;
+        }
+
+        conflict
+    });
+
+    if let Some((holding, chain)) = conflict {
+        report(cpu, || {
+            log::error!("lockdep: possible deadlock detected on CPU {}", cpu);
+            log::error!("lockdep: acquiring {} while holding {}", class, holding);
+            log::error!("lockdep: but the opposite order was seen before: {}", chain);
+        });
+    }
+}
+
+/// Records the release of a lock of the provided class.
+pub fn release(class: LockClass) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let _guard = IrqGuard::new();
+
+    let cpu = match current_cpu() {
+        Some(cpu) => cpu,
+        None => return,
+    };
+
+    with_held(cpu, |held| {
+        let len = held.len;
+
+        // The locks are usually released in the reverse order, so search from the top.
+        if let Some(index) = (0..len)
+            .rev()
+            .find(|i| held.classes[*i].map_or(false, |this| core::ptr::eq(this, class)))
+        {
+            held.classes.copy_within(index + 1..len, index);
+            held.classes[len - 1] = None;
+            held.len -= 1;
+        }
+    });
+}
+
+/// Reports if the current CPU is about to sleep while holding a spinlock or with the
+/// interrupts disabled.
+pub fn might_sleep() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let interrupts_enabled = interrupts::is_enabled();
+    let _guard = IrqGuard::new();
+
+    let cpu = match current_cpu() {
+        Some(cpu) => cpu,
+        None => return,
+    };
+
+    let mut chain = Chain::default();
+
+    let skip = with_held(cpu, |held| {
+        held.iter().for_each(|class| chain.push(class));
+        held.reporting || (held.len == 0 && interrupts_enabled)
+    });
+
+    if skip {
+        return;
+    }
+
+    report(cpu, || {
+        log::error!("lockdep: sleeping in atomic context on CPU {}", cpu);
+        log::error!("lockdep: interrupts enabled: {}", interrupts_enabled);
+        log::error!("lockdep: held locks: {}", chain);
+    });
+}
+
+/// Starts tracking the locks. Has to be called after the TLS of the BSP is set up.
+pub fn init() {
+    ENABLED.store(true, Ordering::SeqCst);
+    log::info!("lockdep: enabled");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockdep_finds_reverse_path() {
+        let mut graph = Graph::new();
+
+        let a = graph.index_of(Location::caller()).unwrap();
+        let b = graph.index_of(Location::caller()).unwrap();
+
+        graph.add_edge(a, b);
+
+        assert!(graph.find_path(b, a).is_none());
+        assert_eq!(graph.find_path(a, b).map(|chain| chain.len), Some(2));
+    }
+}
@@ -20,13 +20,16 @@
 use alloc::{alloc::alloc_zeroed, sync::Arc};
 use core::{alloc::Layout, any::Any, cell::UnsafeCell, mem, ptr::Unique};
 
+use aero_syscall::MMapProt;
+
 use crate::mem::paging::{align_down, VirtAddr};
+use crate::mem::uaccess;
 
 #[cfg(target_arch = "x86_64")]
-use crate::arch::apic::get_cpu_count;
+pub use crate::arch::apic::get_cpu_count;
 
 #[cfg(target_arch = "aarch64")]
-fn get_cpu_count() -> usize {
+pub fn get_cpu_count() -> usize {
     1
 }
 
@@ -34,32 +37,53 @@ pub mod bitmap;
 pub mod buffer;
 pub mod sync;
 
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+
+// The following functions validate the pointers provided by userland to the syscall
+// handlers (see `mem::uaccess`), so that the memory they point to can be accessed directly.
+
 pub fn validate_mut_ptr<T>(ptr: *mut T) -> Option<&'static mut T> {
-    VirtAddr::new(ptr as _).read_mut::<T>()
+    let address = VirtAddr::new(ptr as _);
+    let protection = MMapProt::PROT_READ | MMapProt::PROT_WRITE;
+
+    uaccess::check_range(address, mem::size_of::<T>(), protection).ok()?;
+    address.read_mut::<T>()
 }
 
 pub fn validate_ptr<T>(ptr: *const T) -> Option<&'static T> {
-    // SAFETY: Safe to cast const pointer to mutable since the pointer is not
-    // mutated and the returned reference is immutable.
-    validate_mut_ptr(ptr as *mut T).map(|e| &*e)
+    let address = VirtAddr::new(ptr as _);
+
+    uaccess::check_range(address, mem::size_of::<T>(), MMapProt::PROT_READ).ok()?;
+    address.read_mut::<T>().map(|e| &*e)
 }
 
-pub fn validate_slice_mut<T>(ptr: *mut T, len: usize) -> Option<&'static mut [T]> {
+fn validate_slice_with<T>(
+    ptr: *mut T,
+    len: usize,
+    protection: MMapProt,
+) -> Option<&'static mut [T]> {
     if len == 0 {
         Some(&mut [])
     } else {
-        let _ = validate_ptr(ptr)?; // ensure non-null and in-range
-        let _ = validate_ptr(unsafe { ptr.add(len) })?; // ensure in-range
+        let size = mem::size_of::<T>().checked_mul(len)?;
+
+        // ensure non-null, in-range and mapped
+        uaccess::check_range(VirtAddr::new(ptr as _), size, protection).ok()?;
 
         // SAFETY: We have validated the pointer above.
         Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
     }
 }
 
+pub fn validate_slice_mut<T>(ptr: *mut T, len: usize) -> Option<&'static mut [T]> {
+    validate_slice_with(ptr, len, MMapProt::PROT_READ | MMapProt::PROT_WRITE)
+}
+
 pub fn validate_slice<T>(ptr: *const T, len: usize) -> Option<&'static [T]> {
     // SAFETY: Safe to cast const pointer to mutable since the pointer is not
     // mutated and the returned reference is immutable.
-    validate_slice_mut(ptr as *mut T, len).map(|e| &*e)
+    validate_slice_with(ptr as *mut T, len, MMapProt::PROT_READ).map(|e| &*e)
 }
 
 pub fn validate_str(ptr: *const u8, len: usize) -> Option<&'static str> {
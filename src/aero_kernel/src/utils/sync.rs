@@ -1,3 +1,6 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -6,43 +9,69 @@ use crate::userland::scheduler;
 use crate::userland::signals::SignalResult;
 use crate::userland::task::Task;
 
+#[cfg(feature = "lockdep")]
+use super::lockdep;
+
 /// Used to manage and block threads that are waiting for a condition to be true.
-pub struct BlockQueue {
+///
+/// The waiting tasks are woken up in FIFO order. A woken up task re-checks its condition
+/// and goes back to sleep if it is still not satisfied, so spurious wake ups are harmless.
+pub struct WaitQueue {
     queue: Mutex<Vec<Arc<Task>>>,
+    /// The number of times the queue was woken up.
+    wakeups: AtomicUsize,
 }
 
-impl BlockQueue {
-    /// Creates a new block queue.
+impl WaitQueue {
+    /// Creates a new wait queue.
     #[inline]
-    pub fn new() -> Self {
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new() -> Self {
         Self {
             queue: Mutex::new(Vec::new()),
+            wakeups: AtomicUsize::new(0),
         }
     }
 
-    /// Run a future to completion on the current task. This function will block
-    /// the caller until the given future has completed.
-    pub fn block_on<'future, T, F: FnMut(&mut MutexGuard<T>) -> bool>(
+    /// Blocks the current task until `condition` returns `true` for the data protected by
+    /// `mutex` and returns the guard of `mutex`. The condition is checked (with the lock
+    /// held) before sleeping and every time the task is woken up.
+    ///
+    /// Returns [`SignalError::Interrupted`] if a signal was received while waiting.
+    ///
+    /// [`SignalError::Interrupted`]: crate::userland::signals::SignalError::Interrupted
+    pub fn wait_until<'a, T, F: FnMut(&mut MutexGuard<T>) -> bool>(
         &self,
-        mutex: &'future Mutex<T>,
-        mut future: F,
-    ) -> SignalResult<MutexGuard<'future, T>> {
+        mutex: &'a Mutex<T>,
+        mut condition: F,
+    ) -> SignalResult<MutexGuard<'a, T>> {
         let mut lock = mutex.lock_irq();
 
-        // Check if the future was already completed.
-        if future(&mut lock) {
+        // Check if the condition is already satisfied.
+        if condition(&mut lock) {
             return Ok(lock);
         }
 
         let scheduler = scheduler::get_scheduler();
         let task = scheduler.current_task();
 
-        self.queue.lock_irq().push(task.clone());
-
-        // Wait until the future is completed.
-        while !future(&mut lock) {
+        // Wait until the condition is satisfied.
+        while !condition(&mut lock) {
+            // A waker dequeues the task it wakes up, so (re-)insert ourselves while `mutex`
+            // is still held. Otherwise a wake up sent between dropping the lock and going
+            // to sleep could be lost.
+            self.insert(task.clone());
             core::mem::drop(lock); // Drop the IRQ lock and await for IO to complete.
-            scheduler.inner.await_io()?;
+
+            if let Err(err) = scheduler.inner.await_io() {
+                // If we were already dequeued, the wake up was meant for us. Pass it on
+                // so that another waiter does not miss it.
+                if !self.remove(task) {
+                    self.wake_one();
+                }
+
+                return Err(err);
+            }
 
             // Re-acquire the lock.
             lock = mutex.lock_irq();
@@ -52,29 +81,49 @@ impl BlockQueue {
         Ok(lock)
     }
 
+    /// Adds `task` to the queue, unless it is already queued.
     pub fn insert(&self, task: Arc<Task>) {
-        self.queue.lock_irq().push(task);
+        let mut tasks = self.queue.lock_irq();
+
+        if !tasks.iter().any(|this| Arc::ptr_eq(this, &task)) {
+            tasks.push(task);
+        }
     }
 
-    pub fn remove(&self, task: Arc<Task>) {
+    /// Removes `task` from the queue. Returns `false` if it was not queued.
+    pub fn remove(&self, task: Arc<Task>) -> bool {
         let mut tasks = self.queue.lock_irq();
 
-        tasks
-            .iter()
-            .enumerate()
-            .find_map(|(id, this)| {
-                if this.pid() == task.pid() {
-                    Some(id)
-                } else {
-                    None
-                }
-            })
-            .map(|i| tasks.remove(i));
+        if let Some(i) = tasks.iter().position(|this| Arc::ptr_eq(this, &task)) {
+            tasks.remove(i);
+            true
+        } else {
+            false
+        }
     }
 
-    /// Notify's all of the tasks in the blocker's queue that the future has been
-    /// completed.
-    pub fn notify_complete(&self) {
+    /// Dequeues and wakes up the task that has been waiting for the longest time, if any.
+    pub fn wake_one(&self) {
+        self.wakeups.fetch_add(1, Ordering::AcqRel);
+
+        let task = {
+            let mut this = self.queue.lock_irq();
+
+            if this.is_empty() {
+                return;
+            }
+
+            this.remove(0)
+        };
+
+        scheduler::get_scheduler().inner.wake_up(task);
+    }
+
+    /// Wakes up all of the tasks in the queue. The tasks stay in the queue until they
+    /// remove themselves.
+    pub fn wake_all(&self) {
+        self.wakeups.fetch_add(1, Ordering::AcqRel);
+
         let scheduler = scheduler::get_scheduler();
         let this = self.queue.lock_irq();
 
@@ -86,6 +135,11 @@ impl BlockQueue {
     pub fn is_empty(&self) -> bool {
         self.queue.lock_irq().is_empty()
     }
+
+    /// Returns the number of times the queue was woken up. The counter wraps around.
+    pub fn wakeups(&self) -> usize {
+        self.wakeups.load(Ordering::Acquire)
+    }
 }
 
 /// Helper guard structure used to lock interrupts. When dropped, interrupts
@@ -121,13 +175,20 @@ impl Drop for IrqGuard {
 /// A spin-based lock providing mutually exclusive access to data.
 pub struct Mutex<T> {
     inner: spin::Mutex<T>,
+    #[cfg(feature = "lockdep")]
+    class: lockdep::LockClass,
 }
 
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
+    ///
+    /// With the `lockdep` feature, the caller's location is the lock class of the mutex.
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(value: T) -> Self {
         Self {
             inner: spin::Mutex::new(value),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
         }
     }
 
@@ -136,9 +197,14 @@ impl<T> Mutex<T> {
     /// The returned value may be dereferenced for data access and the lock will be dropped
     /// when the guard falls out of scope.
     pub fn lock(&self) -> MutexGuard<T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class);
+
         MutexGuard {
             guard: core::mem::ManuallyDrop::new(self.inner.lock()),
             irq_lock: false,
+            #[cfg(feature = "lockdep")]
+            class: self.class,
         }
     }
 
@@ -156,12 +222,32 @@ impl<T> Mutex<T> {
             interrupts::disable_interrupts();
         }
 
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class);
+
         MutexGuard {
             guard: core::mem::ManuallyDrop::new(self.inner.lock()),
             irq_lock,
+            #[cfg(feature = "lockdep")]
+            class: self.class,
         }
     }
 
+    /// Tries to lock the [`Mutex`] without spinning. Returns [`None`] if it is held.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let guard = self.inner.try_lock()?;
+
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.class);
+
+        Some(MutexGuard {
+            guard: core::mem::ManuallyDrop::new(guard),
+            irq_lock: false,
+            #[cfg(feature = "lockdep")]
+            class: self.class,
+        })
+    }
+
     /// Force unlock this [`Mutex`].
     ///
     /// # Safety
@@ -170,6 +256,9 @@ impl<T> Mutex<T> {
     /// can be useful in some instances for exposing the lock to FFI that doesn't know how to deal
     /// with RAII.
     pub unsafe fn force_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.class);
+
         self.inner.force_unlock()
     }
 }
@@ -177,6 +266,8 @@ impl<T> Mutex<T> {
 pub struct MutexGuard<'a, T: ?Sized + 'a> {
     guard: core::mem::ManuallyDrop<spin::MutexGuard<'a, T>>,
     irq_lock: bool,
+    #[cfg(feature = "lockdep")]
+    class: lockdep::LockClass,
 }
 
 impl<'a, T: ?Sized> core::ops::Deref for MutexGuard<'a, T> {
@@ -202,6 +293,9 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
             core::mem::ManuallyDrop::drop(&mut self.guard);
         }
 
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.class);
+
         if self.irq_lock {
             unsafe {
                 interrupts::enable_interrupts();
@@ -209,3 +303,261 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
         }
     }
 }
+
+/// Returns whether the current context is allowed to sleep. The sleeping locks fall back
+/// to spinning when it is not (before the scheduler is initialized or in the idle task).
+///
+/// A task may sleep with interrupts disabled: the interrupt state is saved when it goes to
+/// sleep and restored when it is resumed, so other tasks (and the task holding the lock)
+/// keep running in the meantime. Spinning instead would never let the holder release it
+/// if it is sleeping on the same CPU.
+fn can_sleep() -> bool {
+    scheduler::is_initialized()
+        && scheduler::get_scheduler()
+            .inner
+            .current_task_optional()
+            .is_some()
+}
+
+/// Blocks (or spins, if the current context cannot sleep) until `acquire` returns `true`.
+/// The current task is not interruptible by signals while waiting.
+fn wait_uninterruptible<T, F>(queue: &WaitQueue, state: &Mutex<T>, mut acquire: F)
+where
+    F: FnMut(&mut T) -> bool,
+{
+    loop {
+        if can_sleep() {
+            // If a signal interrupted the wait, the condition is checked again.
+            if queue.wait_until(state, |state| acquire(state)).is_ok() {
+                return;
+            }
+        } else if acquire(&mut state.lock_irq()) {
+            return;
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// A lock providing mutually exclusive access to data that puts the waiting tasks to
+/// sleep instead of spinning. It should be used instead of [`Mutex`] for long critical
+/// sections (for example, ones that do I/O) and can not be used from interrupt handlers.
+pub struct BlockingMutex<T: ?Sized> {
+    locked: Mutex<bool>,
+    queue: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for BlockingMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for BlockingMutex<T> {}
+
+impl<T> BlockingMutex<T> {
+    /// Creates a new [`BlockingMutex`] wrapping the supplied data.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: Mutex::new(false),
+            queue: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> BlockingMutex<T> {
+    /// Locks the [`BlockingMutex`], sleeping until it is available, and returns a guard
+    /// that permits access to the inner data.
+    pub fn lock(&self) -> BlockingMutexGuard<T> {
+        wait_uninterruptible(&self.queue, &self.locked, |locked| {
+            if *locked {
+                false
+            } else {
+                *locked = true;
+                true
+            }
+        });
+
+        BlockingMutexGuard { lock: self }
+    }
+
+    fn unlock(&self) {
+        *self.locked.lock_irq() = false;
+        self.queue.wake_one();
+    }
+}
+
+pub struct BlockingMutexGuard<'a, T: ?Sized> {
+    lock: &'a BlockingMutex<T>,
+}
+
+impl<'a, T: ?Sized> core::ops::Deref for BlockingMutexGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> core::ops::DerefMut for BlockingMutexGuard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for BlockingMutexGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// A reader-writer lock that puts the waiting tasks to sleep instead of spinning. (See
+/// the [`BlockingMutex`] documentation for more information).
+pub struct BlockingRwLock<T: ?Sized> {
+    /// The amount of readers holding the lock, or `-1` if a writer is holding it.
+    state: Mutex<isize>,
+    queue: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for BlockingRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for BlockingRwLock<T> {}
+
+impl<T> BlockingRwLock<T> {
+    /// Creates a new [`BlockingRwLock`] wrapping the supplied data.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: Mutex::new(0),
+            queue: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> BlockingRwLock<T> {
+    /// Locks the [`BlockingRwLock`] with shared read access, sleeping until there are no
+    /// writers holding it.
+    pub fn read(&self) -> BlockingRwLockReadGuard<T> {
+        wait_uninterruptible(&self.queue, &self.state, |readers| {
+            if *readers < 0 {
+                false
+            } else {
+                *readers += 1;
+                true
+            }
+        });
+
+        BlockingRwLockReadGuard { lock: self }
+    }
+
+    /// Locks the [`BlockingRwLock`] with exclusive write access, sleeping until there
+    /// are no readers or writers holding it.
+    pub fn write(&self) -> BlockingRwLockWriteGuard<T> {
+        wait_uninterruptible(&self.queue, &self.state, |readers| {
+            if *readers != 0 {
+                false
+            } else {
+                *readers = -1;
+                true
+            }
+        });
+
+        BlockingRwLockWriteGuard { lock: self }
+    }
+}
+
+pub struct BlockingRwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a BlockingRwLock<T>,
+}
+
+impl<'a, T: ?Sized> core::ops::Deref for BlockingRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for BlockingRwLockReadGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        let readers = {
+            let mut readers = self.lock.state.lock_irq();
+            *readers -= 1;
+            *readers
+        };
+
+        if readers == 0 {
+            self.lock.queue.wake_all();
+        }
+    }
+}
+
+pub struct BlockingRwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a BlockingRwLock<T>,
+}
+
+impl<'a, T: ?Sized> core::ops::Deref for BlockingRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> core::ops::DerefMut for BlockingRwLockWriteGuard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for BlockingRwLockWriteGuard<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        *self.lock.state.lock_irq() = 0;
+        self.lock.queue.wake_all();
+    }
+}
+
+/// A counting semaphore. Tasks waiting for a permit are put to sleep.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Creates a new [`Semaphore`] with the provided amount of permits.
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a permit, sleeping until one is available. Returns an error if a signal was
+    /// received while waiting.
+    pub fn down(&self) -> SignalResult<()> {
+        self.queue
+            .wait_until(&self.permits, |permits| {
+                if **permits == 0 {
+                    false
+                } else {
+                    **permits -= 1;
+                    true
+                }
+            })
+            .map(|_| ())
+    }
+
+    /// Returns a permit and wakes up one of the waiting tasks.
+    pub fn up(&self) {
+        *self.permits.lock_irq() += 1;
+        self.queue.wake_one();
+    }
+}
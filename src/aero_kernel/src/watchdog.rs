@@ -0,0 +1,251 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The lockup detector. Each CPU touches its watchdog when it schedules and counts its
+//! timer ticks:
+//!
+//! * A soft lockup is when a CPU keeps servicing interrupts but has not scheduled for
+//!   the threshold, for example because of an interrupt storm or a stuck scheduler.
+//!   It is checked from the timer tick and the backtrace of the interrupted code is
+//!   logged.
+//! * A hard lockup is when a CPU has not serviced a timer tick for the threshold, for
+//!   example because it loops with the interrupts disabled. Each CPU checks itself from
+//!   the NMI the PMU sends it about once a second (see [`pmu::start_nmi_watchdog`]) and
+//!   logs the backtrace of the interrupted code, so it also works with a single CPU.
+//!   Hard lockups are not detected if the PMU has no counter to spare.
+//!
+//! Only the CPUs that schedule are checked, from the first time they do.
+//!
+//! The threshold is set in seconds with `watchdog_thresh=<seconds>` on the kernel
+//! command line (10 seconds by default) and `nowatchdog` disables the detector.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::interrupts::InterruptStack;
+use crate::arch::{pmu, time, tls};
+use crate::{cmdline, unwind};
+
+const DEFAULT_THRESHOLD: u64 = 10;
+
+static WATCHDOGS: Once<Vec<Watchdog>> = Once::new();
+static THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD);
+
+struct Watchdog {
+    /// Set once the CPU scheduled for the first time.
+    online: AtomicBool,
+    /// Set once the NMI watchdog counter was started on the CPU.
+    nmi_armed: AtomicBool,
+    /// The uptime (in seconds) when the CPU last scheduled.
+    touched: AtomicU64,
+    /// The amount of timer ticks serviced by the CPU.
+    ticks: AtomicU64,
+    /// The tick count seen by the last watchdog NMI and the amount of watchdog NMIs
+    /// since it changed.
+    seen_ticks: AtomicU64,
+    stalled_nmis: AtomicU64,
+    /// Set once a lockup was reported, until the CPU recovers from it.
+    soft_reported: AtomicBool,
+    hard_reported: AtomicBool,
+}
+
+impl Watchdog {
+    fn new(now: u64) -> Self {
+        Self {
+            online: AtomicBool::new(false),
+            nmi_armed: AtomicBool::new(false),
+            touched: AtomicU64::new(now),
+            ticks: AtomicU64::new(0),
+            seen_ticks: AtomicU64::new(0),
+            stalled_nmis: AtomicU64::new(0),
+            soft_reported: AtomicBool::new(false),
+            hard_reported: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self, now: u64) {
+        self.touched.store(now, Ordering::Relaxed);
+        self.stalled_nmis.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Returns whether the amount of seconds elapsed since `since` exceeds the threshold.
+fn is_stalled(now: u64, since: u64, threshold: u64) -> bool {
+    now.saturating_sub(since) > threshold
+}
+
+fn current() -> Option<(usize, &'static Watchdog)> {
+    let cpu = tls::get_cpuid();
+    WATCHDOGS.get()?.get(cpu).map(|watchdog| (cpu, watchdog))
+}
+
+/// Marks the current CPU as having scheduled and starts its NMI watchdog the first time.
+pub fn touch() {
+    if let Some((_, watchdog)) = current() {
+        watchdog.touch(time::get_uptime_ticks() as u64);
+        watchdog.soft_reported.store(false, Ordering::Relaxed);
+        watchdog.online.store(true, Ordering::Relaxed);
+
+        if !watchdog.nmi_armed.swap(true, Ordering::Relaxed) {
+            pmu::start_nmi_watchdog();
+        }
+    }
+}
+
+/// Marks every CPU as having scheduled, after the clock jumped forward or the CPUs were
+/// reset (for example on resume from a suspend to RAM). The NMI watchdogs are started
+/// again the next time the CPUs schedule.
+pub fn touch_all() {
+    let now = time::get_uptime_ticks() as u64;
+
+    for watchdog in WATCHDOGS.get().into_iter().flatten() {
+        watchdog.touch(now);
+        watchdog.nmi_armed.store(false, Ordering::Relaxed);
+    }
+}
+
+fn log_backtrace(stack: &InterruptStack) {
+    log::error!(
+        "RIP: {}",
+        unwind::SymbolizedAddress(stack.iret.rip as usize)
+    );
+
+    if !stack.iret.is_user() {
+        unwind::unwind_stack_trace_from(stack.preserved.rbp as usize);
+    }
+}
+
+/// Called from the timer tick of the current CPU: counts the tick and checks the
+/// current CPU for a soft lockup.
+pub fn tick(stack: &InterruptStack) {
+    let (cpu, watchdog) = match current() {
+        Some(current) => current,
+        None => return,
+    };
+
+    let now = time::get_uptime_ticks() as u64;
+    let threshold = THRESHOLD.load(Ordering::Relaxed);
+
+    watchdog.ticks.fetch_add(1, Ordering::Relaxed);
+
+    if !watchdog.online.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let touched = watchdog.touched.load(Ordering::Relaxed);
+
+    if is_stalled(now, touched, threshold) && !watchdog.soft_reported.swap(true, Ordering::Relaxed)
+    {
+        log::error!(
+            "watchdog: soft lockup on CPU {cpu} (not scheduled for {}s)",
+            now - touched
+        );
+
+        log_backtrace(stack);
+    }
+}
+
+/// Called from the NMI handler. Returns whether the NMI was sent by the PMU, in which
+/// case the current CPU is checked for a hard lockup if the watchdog counter overflowed.
+pub fn handle_nmi(stack: &InterruptStack) -> bool {
+    match pmu::handle_nmi() {
+        None => return false,
+        Some(false) => return true,
+        Some(true) => {}
+    }
+
+    let (cpu, watchdog) = match current() {
+        Some(current) => current,
+        None => return true,
+    };
+
+    if !watchdog.online.load(Ordering::Relaxed) {
+        return true;
+    }
+
+    let ticks = watchdog.ticks.load(Ordering::Relaxed);
+
+    if watchdog.seen_ticks.swap(ticks, Ordering::Relaxed) != ticks {
+        watchdog.stalled_nmis.store(0, Ordering::Relaxed);
+        watchdog.hard_reported.store(false, Ordering::Relaxed);
+        return true;
+    }
+
+    // The watchdog NMIs are about a second apart.
+    let stalled = watchdog.stalled_nmis.fetch_add(1, Ordering::Relaxed) + 1;
+
+    if is_stalled(stalled, 0, THRESHOLD.load(Ordering::Relaxed))
+        && !watchdog.hard_reported.swap(true, Ordering::Relaxed)
+    {
+        log::error!("watchdog: hard lockup on CPU {cpu} (no timer tick for {stalled}s)");
+        log_backtrace(stack);
+    }
+
+    true
+}
+
+/// Starts the watchdogs of the CPUs, unless disabled on the kernel command line.
+pub fn init() {
+    if cmdline::has_flag("nowatchdog") {
+        log::info!("watchdog: disabled");
+        return;
+    }
+
+    if let Some(value) = cmdline::get_option("watchdog_thresh") {
+        match value.parse::<u64>() {
+            Ok(threshold) if threshold > 0 => THRESHOLD.store(threshold, Ordering::Relaxed),
+            _ => log::warn!("watchdog: invalid threshold '{value}'"),
+        }
+    }
+
+    let now = time::get_uptime_ticks() as u64;
+
+    WATCHDOGS.call_once(|| {
+        (0..crate::utils::get_cpu_count())
+            .map(|_| Watchdog::new(now))
+            .collect()
+    });
+
+    log::info!(
+        "watchdog: lockup threshold is {}s",
+        THRESHOLD.load(Ordering::Relaxed)
+    );
+
+    if !pmu::has_nmi_watchdog() {
+        log::warn!("watchdog: no PMU counter for the NMI watchdog, hard lockups are not detected");
+    }
+}
+
+crate::early_initcall!(init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_stall_threshold() {
+        assert!(!is_stalled(10, 0, 10));
+        assert!(is_stalled(11, 0, 10));
+
+        // The clock going backwards is not a stall.
+        assert!(!is_stalled(0, 11, 10));
+    }
+}
@@ -0,0 +1,72 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Work deferred from interrupt handlers.
+//!
+//! An interrupt handler must not take a lock that is held with the interrupts enabled
+//! (such as the lock of the scheduler's task list), as it would deadlock if it interrupted
+//! the holder. It queues a work item instead, which the `kworker` thread runs later on.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use spin::Once;
+
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+use crate::utils::sync::Mutex;
+
+type Work = Box<dyn FnOnce() + Send>;
+
+static QUEUE: Mutex<VecDeque<Work>> = Mutex::new(VecDeque::new());
+static KWORKER: Once<Arc<Task>> = Once::new();
+
+/// Queues `work` to be run by the `kworker` thread. Can be called from any context.
+pub fn schedule(work: impl FnOnce() + Send + 'static) {
+    QUEUE.lock_irq().push_back(Box::new(work));
+
+    if let Some(kworker) = KWORKER.get() {
+        scheduler::get_scheduler().inner.wake_up(kworker.clone());
+    }
+}
+
+fn kworker() {
+    loop {
+        // The lock is not held while the work runs, so it can queue more work.
+        let work = QUEUE.lock_irq().pop_front();
+
+        match work {
+            Some(work) => work(),
+            // Signals are not delivered to kernel threads.
+            None => {
+                let _ = scheduler::get_scheduler().inner.await_io();
+            }
+        }
+    }
+}
+
+/// Spawns the `kworker` thread, which also runs the work queued before it started.
+fn init() {
+    let task = Task::new_kernel(kworker, true);
+
+    KWORKER.call_once(|| task.clone());
+    scheduler::get_scheduler().register_task(task);
+}
+
+crate::early_initcall!(init);
@@ -45,6 +45,11 @@ pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
 
 /// Validates input buffers, structures, path and strings auto-magically.
 ///
+/// The referenced userland data is copied into kernel buffers (see `UserBuffer` in the
+/// kernel's `mem::uaccess` module) before the function is called, so it never touches
+/// userland memory directly. The mutable buffers are copied back once it returns
+/// successfully.
+///
 /// Functions that use this macro are not allowed to be `async`, `unsafe`, or `const` and must
 /// have a valid return-type of `Result<usize, AeroSyscallError>`. In addition, the function cannot
 /// have generic parameters.
@@ -24,7 +24,7 @@ use quote::quote;
 
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
-use syn::{Expr, FnArg, NestedMeta, Pat, Type};
+use syn::{Expr, FnArg, NestedMeta, Pat, Stmt, Type};
 
 enum ArgType {
     Array(bool),     // mutable?
@@ -135,9 +135,8 @@ pub fn parse(attr: TokenStream, item: TokenStream) -> TokenStream {
                                 quote::quote!(.add_argument(alloc::format!("*{:#x}", #ident as usize)))
                             }
 
-                            ArgType::Reference(_) => {
-                                quote::quote!(.add_argument(alloc::format!("&{:#x}", #ident as *const _ as usize)))
-                            },
+                            // The syscall function is passed a kernel copy of the value.
+                            ArgType::Reference(_) => quote::quote!(.add_argument("<ref>")),
 
                             ArgType::String | ArgType::Path => quote::quote!(.add_argument_dbg(#ident)), 
                         }
@@ -152,17 +151,28 @@ pub fn parse(attr: TokenStream, item: TokenStream) -> TokenStream {
         .collect::<Vec<_>>();
 
     let syslog = quote::quote! {
-        #[cfg(feature = "syslog")]
-        crate::syscall::SysLog::new(stringify!(#name))
-            #(#syslog_args)*
-            .set_result(result)
-            .flush();
+        if crate::syscall::should_log() {
+            crate::syscall::SysLog::new(stringify!(#name))
+                #(#syslog_args)*
+                .set_result(result)
+                .flush();
+        }
     };
 
+    let CallArgs {
+        copy_in,
+        args: call_args,
+        copy_out,
+    } = call_args;
+
     let compiled_body = if config.no_return {
         quote::quote! {
             fn even_inner(#orig_args) #ret #body
-            even_inner(#(#call_args),*)
+
+            #(#copy_in)*
+            let result = even_inner(#(#call_args),*);
+            #(#copy_out)*
+            result
         }
     } else {
         quote::quote! {
@@ -176,7 +186,10 @@ pub fn parse(attr: TokenStream, item: TokenStream) -> TokenStream {
                 result
             }
 
-            syscall_inner(#(#call_args),*)
+            #(#copy_in)*
+            let result = syscall_inner(#(#call_args),*);
+            #(#copy_out)*
+            result
         }
     };
 
@@ -262,8 +275,53 @@ fn process_args(args: &Punctuated<FnArg, syn::Token![,]>) -> Vec<FnArg> {
     result
 }
 
-fn process_call_args(args: &Punctuated<FnArg, syn::Token![,]>) -> Vec<Expr> {
-    let mut result = Vec::new();
+/// The arguments passed to the syscall function and the statements that copy the
+/// userland buffers in before the call and back out after it.
+#[derive(Default)]
+struct CallArgs {
+    copy_in: Vec<Stmt>,
+    args: Vec<Expr>,
+    copy_out: Vec<Stmt>,
+}
+
+/// Copies `len` values of type `elem` at the userland address in `address` into the
+/// kernel buffer `buf`. If `is_mut` is set, the buffer is copied back out after a
+/// successful call.
+fn copy_in(
+    result: &mut CallArgs,
+    buf: &Ident,
+    address: &Ident,
+    len: Expr,
+    elem: Type,
+    is_mut: bool,
+) {
+    let protection: Expr = if is_mut {
+        syn::parse_quote!(aero_syscall::MMapProt::PROT_READ | aero_syscall::MMapProt::PROT_WRITE)
+    } else {
+        syn::parse_quote!(aero_syscall::MMapProt::PROT_READ)
+    };
+
+    let mutability = if is_mut { quote!(mut) } else { quote!() };
+
+    // SAFETY: The syscall arguments are plain data types, for which any bit pattern is
+    // valid.
+    result.copy_in.push(syn::parse_quote! {
+        let #mutability #buf = unsafe {
+            crate::mem::uaccess::UserBuffer::<#elem>::copy_in(#address, #len, #protection)?
+        };
+    });
+
+    if is_mut {
+        result.copy_out.push(syn::parse_quote! {
+            if result.is_ok() {
+                #buf.copy_out()?;
+            }
+        });
+    }
+}
+
+fn process_call_args(args: &Punctuated<FnArg, syn::Token![,]>) -> CallArgs {
+    let mut result = CallArgs::default();
 
     for arg in args {
         match arg {
@@ -275,31 +333,50 @@ fn process_call_args(args: &Punctuated<FnArg, syn::Token![,]>) -> Vec<Expr> {
                     if let Some(arg_type) = determine_arg_type(ty) {
                         let data_ident = Ident::new(&format!("{}_data", ident), Span::call_site());
                         let len_ident = Ident::new(&format!("{}_len", ident), Span::call_site());
+                        let buf_ident = Ident::new(&format!("{}_buf", ident), Span::call_site());
+
+                        // The type of the values in the userland buffer.
+                        let elem = match ty.as_ref() {
+                            Type::Reference(reference) => match reference.elem.as_ref() {
+                                Type::Slice(slice) => Some(slice.elem.as_ref().clone()),
+                                elem => Some(elem.clone()),
+                            },
+                            _ => None,
+                        };
 
                         match arg_type {
                             ArgType::Slice(is_mut) => {
-                                let slice_expr: Expr = if is_mut {
-                                    syn::parse_quote! {
-                                        crate::utils::validate_slice_mut(#data_ident as *mut _, #len_ident).ok_or(SyscallError::EINVAL)?
-                                    }
+                                copy_in(
+                                    &mut result,
+                                    &buf_ident,
+                                    &data_ident,
+                                    syn::parse_quote!(#len_ident),
+                                    elem.unwrap(),
+                                    is_mut,
+                                );
+
+                                result.args.push(if is_mut {
+                                    syn::parse_quote!(#buf_ident.as_mut_slice())
                                 } else {
-                                    syn::parse_quote! {
-                                        crate::utils::validate_slice(#data_ident as *const _, #len_ident).ok_or(SyscallError::EINVAL)?
-                                    }
-                                };
-
-                                result.push(slice_expr);
+                                    syn::parse_quote!(#buf_ident.as_slice())
+                                });
                             }
                             ArgType::Array(is_mut) => {
-                                let array_expr: Expr = if is_mut {
-                                    syn::parse_quote! {
-                                        crate::utils::validate_array_mut(#data_ident as *mut _).ok_or(SyscallError::EINVAL)?
-                                    }
-                                } else {
+                                if !is_mut {
                                     unimplemented!()
-                                };
+                                }
 
-                                result.push(array_expr);
+                                copy_in(
+                                    &mut result,
+                                    &buf_ident,
+                                    &data_ident,
+                                    syn::parse_quote!(1),
+                                    elem.unwrap(),
+                                    is_mut,
+                                );
+                                result
+                                    .args
+                                    .push(syn::parse_quote!(&mut #buf_ident.as_mut_slice()[0]));
                             }
                             ArgType::Pointer(is_mut) => {
                                 let ptr_expr: Expr = if is_mut {
@@ -308,34 +385,59 @@ fn process_call_args(args: &Punctuated<FnArg, syn::Token![,]>) -> Vec<Expr> {
                                     syn::parse_quote!(#ident as *const _)
                                 };
 
-                                result.push(ptr_expr);
+                                result.args.push(ptr_expr);
                             }
                             ArgType::Reference(is_mut) => {
-                                let ref_expr: Expr = if is_mut {
-                                    syn::parse_quote!({
-                                        crate::utils::validate_mut_ptr(#ident as *mut _).ok_or(SyscallError::EINVAL)?
-                                    })
+                                copy_in(
+                                    &mut result,
+                                    &buf_ident,
+                                    ident,
+                                    syn::parse_quote!(1),
+                                    elem.unwrap(),
+                                    is_mut,
+                                );
+
+                                result.args.push(if is_mut {
+                                    syn::parse_quote!(&mut #buf_ident.as_mut_slice()[0])
                                 } else {
-                                    syn::parse_quote!({
-                                        crate::utils::validate_ptr(#ident as *const _).ok_or(SyscallError::EINVAL)?
-                                    })
-                                };
-
-                                result.push(ref_expr);
+                                    syn::parse_quote!(&#buf_ident.as_slice()[0])
+                                });
+                            }
+                            ArgType::String => {
+                                copy_in(
+                                    &mut result,
+                                    &buf_ident,
+                                    &data_ident,
+                                    syn::parse_quote!(#len_ident),
+                                    syn::parse_quote!(u8),
+                                    false,
+                                );
+
+                                result.args.push(syn::parse_quote! {
+                                    core::str::from_utf8(#buf_ident.as_slice()).map_err(|_| SyscallError::EINVAL)?
+                                });
+                            }
+                            ArgType::Path => {
+                                copy_in(
+                                    &mut result,
+                                    &buf_ident,
+                                    &data_ident,
+                                    syn::parse_quote!(#len_ident),
+                                    syn::parse_quote!(u8),
+                                    false,
+                                );
+
+                                result.args.push(syn::parse_quote! {
+                                    {
+                                        let string = core::str::from_utf8(#buf_ident.as_slice()).map_err(|_| SyscallError::EINVAL)?;
+                                        let path = Path::new(string);
+                                        path
+                                    }
+                                });
                             }
-                            ArgType::String => result.push(syn::parse_quote! {
-                                crate::utils::validate_str(#data_ident as *const _, #len_ident).ok_or(SyscallError::EINVAL)?
-                            }),
-                            ArgType::Path => result.push(syn::parse_quote! {
-                                {
-                                    let string = crate::utils::validate_str(#data_ident as *const _, #len_ident).ok_or(SyscallError::EINVAL)?;
-                                    let path = Path::new(string);
-                                    path
-                                }
-                            }),
                         }
                     } else {
-                        result.push(syn::parse_quote!(#ident));
+                        result.args.push(syn::parse_quote!(#ident));
                     }
                 }
                 _ => {}
@@ -89,6 +89,29 @@ pub const SYS_GETITIMER: usize = 65;
 pub const SYS_GETPPID: usize = 66;
 pub const SYS_SOCKET_PAIR: usize = 67;
 pub const SYS_RENAME: usize = 68;
+pub const SYS_SETPGID: usize = 69;
+pub const SYS_GETPGID: usize = 70;
+pub const SYS_SETSID: usize = 71;
+pub const SYS_GETSID: usize = 72;
+pub const SYS_SYSLOG: usize = 73;
+pub const SYS_PERF_EVENT_OPEN: usize = 74;
+pub const SYS_SETTIME: usize = 75;
+pub const SYS_SOCK_SEND: usize = 76;
+pub const SYS_GETSOCKOPT: usize = 77;
+pub const SYS_SETSOCKOPT: usize = 78;
+pub const SYS_GETSOCKNAME: usize = 79;
+pub const SYS_GETPEERNAME: usize = 80;
+pub const SYS_GETRANDOM: usize = 81;
+pub const SYS_TRACE: usize = 82;
+pub const SYS_PTRACE: usize = 83;
+pub const SYS_SECCOMP: usize = 84;
+pub const SYS_PRCTL: usize = 85;
+pub const SYS_GETRLIMIT: usize = 86;
+pub const SYS_SETRLIMIT: usize = 87;
+pub const SYS_UNSHARE: usize = 88;
+pub const SYS_MOUNT: usize = 89;
+pub const SYS_CHROOT: usize = 90;
+pub const SYS_GETCPU: usize = 91;
 
 // constants for fcntl()'s command argument:
 pub const F_DUPFD: usize = 1;
@@ -103,6 +126,146 @@ pub const F_SETLKW: usize = 9;
 pub const F_GETOWN: usize = 10;
 pub const F_SETOWN: usize = 11;
 
+// constants for ptrace()'s request argument:
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_POKEDATA: usize = 5;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_GETREGS: usize = 12;
+pub const PTRACE_SETREGS: usize = 13;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+
+/// The register set of a stopped tracee, as read and written by `PTRACE_GETREGS` and
+/// `PTRACE_SETREGS`. The layout matches Linux's `user_regs_struct` so existing debuggers
+/// can be ported without changes.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PtraceRegs {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    /// The syscall number at a syscall-stop.
+    pub orig_rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub eflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub ds: u64,
+    pub es: u64,
+    pub fs: u64,
+    pub gs: u64,
+}
+
+// constants for prctl()'s option argument:
+pub const PR_SET_NO_NEW_PRIVS: usize = 38;
+pub const PR_GET_NO_NEW_PRIVS: usize = 39;
+
+// constants for unshare()'s flags argument:
+pub const CLONE_NEWNS: usize = 0x00020000;
+pub const CLONE_NEWPID: usize = 0x20000000;
+
+// constants for seccomp()'s operation argument:
+pub const SECCOMP_SET_MODE_FILTER: usize = 1;
+
+// constants for the mode of a [`SeccompFilter`]:
+pub const SECCOMP_FILTER_ALLOW: u32 = 0;
+pub const SECCOMP_FILTER_DENY: u32 = 1;
+
+// constants for the action of a [`SeccompFilter`]:
+pub const SECCOMP_RET_KILL: u32 = 0;
+pub const SECCOMP_RET_ERRNO: u32 = 1;
+
+/// The amount of syscall numbers a [`SeccompFilter`] can list.
+pub const SECCOMP_MAX_SYSCALLS: usize = 256;
+
+/// A syscall filter installed with `seccomp(SECCOMP_SET_MODE_FILTER)`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SeccompFilter {
+    /// Whether the listed syscalls are the only allowed ones (`SECCOMP_FILTER_ALLOW`) or
+    /// the denied ones (`SECCOMP_FILTER_DENY`).
+    pub mode: u32,
+    /// The action taken when a syscall is rejected (one of the `SECCOMP_RET_*`
+    /// constants).
+    pub action: u32,
+    /// The error returned by the rejected syscalls with `SECCOMP_RET_ERRNO`.
+    pub errno: u32,
+    pub reserved: u32,
+    /// A bitmap of the listed syscall numbers.
+    pub syscalls: [u64; SECCOMP_MAX_SYSCALLS / 64],
+}
+
+impl SeccompFilter {
+    pub const fn new(mode: u32, action: u32, errno: u32) -> Self {
+        Self {
+            mode,
+            action,
+            errno,
+            reserved: 0,
+            syscalls: [0; SECCOMP_MAX_SYSCALLS / 64],
+        }
+    }
+
+    /// Adds the syscall `number` to the list of the filter.
+    pub fn add(&mut self, number: usize) {
+        self.syscalls[number / 64] |= 1 << (number % 64);
+    }
+
+    /// Returns whether the syscall `number` is listed by the filter.
+    pub fn contains(&self, number: usize) -> bool {
+        number < SECCOMP_MAX_SYSCALLS && self.syscalls[number / 64] & (1 << (number % 64)) != 0
+    }
+}
+
+// constants for getrlimit()'s and setrlimit()'s resource argument:
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIMIT_NLIMITS: usize = 16;
+
+/// The value of a limit that is not enforced.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RLimit {
+    /// The soft limit, enforced by the kernel.
+    pub rlim_cur: u64,
+    /// The hard limit, the ceiling for the soft limit.
+    pub rlim_max: u64,
+}
+
+impl RLimit {
+    pub const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+}
+
 // constants for fcntl()'s additional argument of F_GETFD and F_SETFD:
 bitflags::bitflags! {
     pub struct FdFlags: usize {
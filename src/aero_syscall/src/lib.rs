@@ -25,6 +25,7 @@ pub mod signal;
 pub mod socket;
 pub mod syscall;
 pub mod time;
+pub mod vdso;
 
 pub use crate::syscall::*;
 
@@ -53,6 +54,18 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    pub struct GetRandomFlags: usize {
+        /// Return `EAGAIN` instead of blocking if the random number generator is not
+        /// seeded yet.
+        const GRND_NONBLOCK = 0x1;
+        /// Same as the default, there is no separate blocking pool.
+        const GRND_RANDOM = 0x2;
+        /// Never block, even if the random number generator is not seeded yet.
+        const GRND_INSECURE = 0x4;
+    }
+}
+
 bitflags::bitflags! {
     pub struct OpenFlags: usize {
         // reserve 3 bits for the access mode
@@ -96,9 +109,34 @@ bitflags::bitflags! {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
-#[repr(isize)]
-pub enum SyscallError {
+/// Defines [`SyscallError`] along with the conversion from the error number, so that both
+/// are generated from the same list.
+macro_rules! syscall_errors {
+    ($($name:ident = $errno:literal,)*) => {
+        #[derive(Copy, Clone, PartialEq, Debug)]
+        #[repr(isize)]
+        pub enum SyscallError {
+            $($name = $errno,)*
+
+            Unknown = isize::MAX,
+        }
+
+        impl TryFrom<u32> for SyscallError {
+            type Error = ();
+
+            /// Converts an error number to the error, failing if it is not a valid error
+            /// number.
+            fn try_from(errno: u32) -> Result<Self, Self::Error> {
+                match errno {
+                    $($errno => Ok(Self::$name),)*
+                    _ => Err(()),
+                }
+            }
+        }
+    };
+}
+
+syscall_errors! {
     EDOM = 1,
     EILSEQ = 2,
     ERANGE = 3,
@@ -184,8 +222,6 @@ pub enum SyscallError {
     EBADFD = 1081,
     ENOMEDIUM = 1082,
     ENOTBLK = 1083,
-
-    Unknown = isize::MAX,
 }
 
 #[derive(Debug)]
@@ -285,8 +321,14 @@ impl From<usize> for SeekWhence {
 pub const TIOCGWINSZ: usize = 0x5413;
 pub const TIOCSWINSZ: usize = 0x5414;
 pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSW: usize = 0x5403;
 pub const TCSETSF: usize = 0x5404;
 pub const TIOCSCTTY: usize = 0x540e;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+pub const TIOCNOTTY: usize = 0x5422;
+pub const TIOCGSID: usize = 0x5429;
 
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
@@ -328,7 +370,9 @@ bitflags::bitflags! {
         const ISIG    = 0x1;
         const NOFLSH  = 0x80;
         const TOSTOP  = 0x100;
+        const ECHOCTL = 0x200;
         const ECHOPRT = 0x400;
+        const ECHOKE  = 0x800;
     }
 }
 
@@ -385,6 +429,23 @@ bitflags::bitflags! {
     }
 }
 
+// Indices into the `c_cc` array of [`Termios`].
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
 #[derive(Debug, Default, Copy, Clone)]
 #[repr(C)]
 pub struct Termios {
@@ -400,6 +461,61 @@ pub struct Termios {
 
 pub const AT_FDCWD: isize = -100;
 
+// Actions of the `syslog` syscall.
+pub const SYSLOG_ACTION_READ: usize = 2;
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+pub const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+pub const SYSLOG_ACTION_CONSOLE_OFF: usize = 6;
+pub const SYSLOG_ACTION_CONSOLE_ON: usize = 7;
+pub const SYSLOG_ACTION_CONSOLE_LEVEL: usize = 8;
+pub const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+pub const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+
+// Hardware events that can be counted with the `perf_event_open` syscall.
+pub const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+pub const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+pub const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+
+// Commands for the `ioctl` syscall on a performance counter file descriptor.
+pub const PERF_EVENT_IOC_ENABLE: usize = 0x2400;
+pub const PERF_EVENT_IOC_DISABLE: usize = 0x2401;
+pub const PERF_EVENT_IOC_RESET: usize = 0x2403;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PerfEventAttr {
+    /// The hardware event to count (one of the `PERF_COUNT_HW_*` constants).
+    pub config: u64,
+    /// If non-zero, a sample is recorded each time this many events have occurred.
+    pub sample_period: u64,
+    /// If non-zero, the counter is created disabled and has to be enabled with
+    /// `PERF_EVENT_IOC_ENABLE`.
+    pub disabled: u64,
+}
+
+/// Reading a performance counter file descriptor returns this header followed by the
+/// samples recorded since the last read (as many as fit in the buffer).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PerfReadFormat {
+    /// The amount of events counted.
+    pub value: u64,
+    /// The amount of [`PerfSample`]s following the header.
+    pub samples: u64,
+    /// The amount of samples that were dropped because the sample buffer was full.
+    pub lost: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PerfSample {
+    /// The instruction pointer at the time of the counter overflow.
+    pub ip: u64,
+    /// The thread that was running at the time of the counter overflow.
+    pub tid: u64,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct SysInfo {
@@ -570,6 +686,11 @@ pub fn sys_shutdown() -> ! {
     unreachable!()
 }
 
+pub fn sys_reboot() -> ! {
+    syscall0(prelude::SYS_REBOOT);
+    unreachable!()
+}
+
 pub fn sys_access(fd: usize, path: &str) -> Result<usize, SyscallError> {
     let value = syscall5(
         prelude::SYS_ACCESS,
@@ -672,8 +793,24 @@ pub struct SocketAddrInet {
     pub padding: [u8; 8],
 }
 
+/// The address of a packet socket (`AF_PACKET`).
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct SocketAddrPacket {
+    pub family: u32,
+    /// The EtherType of the frames, in network byte order.
+    pub protocol: [u8; 2],
+    pub hatype: u16,
+    /// The index of the interface, or zero for any interface.
+    pub ifindex: u32,
+    pub pkttype: u8,
+    pub halen: u8,
+    pub addr: [u8; 8],
+}
+
 impl SocketAddr for SocketAddrUnix {}
 impl SocketAddr for SocketAddrInet {}
+impl SocketAddr for SocketAddrPacket {}
 
 // constants for the socket types:
 //
@@ -713,6 +850,7 @@ pub const PF_LOCAL: u32 = 3;
 pub const PF_UNSPEC: u32 = 4;
 pub const PF_NETLINK: u32 = 5;
 pub const PF_BRIDGE: u32 = 6;
+pub const PF_PACKET: u32 = 7;
 
 pub const AF_INET: u32 = PF_INET;
 pub const AF_INET6: u32 = PF_INET6;
@@ -721,6 +859,45 @@ pub const AF_LOCAL: u32 = PF_LOCAL;
 pub const AF_UNSPEC: u32 = PF_UNSPEC;
 pub const AF_NETLINK: u32 = PF_NETLINK;
 pub const AF_BRIDGE: u32 = PF_BRIDGE;
+pub const AF_PACKET: u32 = PF_PACKET;
+
+// constants for the packet sockets:
+//
+// mlibc/abis/linux/packet.h and linux/if_ether.h
+pub const ETH_P_ALL: u16 = 0x0003;
+pub const ETH_P_IP: u16 = 0x0800;
+
+pub const PACKET_HOST: u8 = 0;
+pub const PACKET_BROADCAST: u8 = 1;
+pub const PACKET_MULTICAST: u8 = 2;
+pub const PACKET_OTHERHOST: u8 = 3;
+pub const PACKET_OUTGOING: u8 = 4;
+
+/// The hardware address type of the ethernet interfaces.
+pub const ARPHRD_ETHER: u16 = 1;
+
+// constants for the socket option levels and names:
+//
+// mlibc/abis/mlibc/socket.h and mlibc/options/posix/include/netinet/tcp.h
+pub const SOL_SOCKET: u32 = 1;
+pub const IPPROTO_IP: u32 = 0;
+pub const IPPROTO_ICMP: u32 = 1;
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+pub const IPPROTO_RAW: u32 = 255;
+
+pub const SO_ACCEPTCONN: u32 = 1;
+pub const SO_BROADCAST: u32 = 2;
+pub const SO_ERROR: u32 = 5;
+pub const SO_KEEPALIVE: u32 = 6;
+pub const SO_RCVBUF: u32 = 9;
+pub const SO_REUSEADDR: u32 = 12;
+pub const SO_SNDBUF: u32 = 13;
+pub const SO_TYPE: u32 = 16;
+
+pub const IP_TTL: u32 = 2;
+
+pub const TCP_NODELAY: u32 = 1;
 
 pub fn sys_socket(
     domain: usize,
@@ -753,6 +930,11 @@ pub fn sys_gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, Sysca
     isize_as_syscall_result(value as _)
 }
 
+pub fn sys_settime(clock: usize, timespec: &TimeSpec) -> Result<usize, SyscallError> {
+    let value = syscall2(prelude::SYS_SETTIME, clock, timespec as *const _ as usize);
+    isize_as_syscall_result(value as _)
+}
+
 pub fn sys_seek(fd: usize, offset: usize, whence: SeekWhence) -> Result<usize, SyscallError> {
     let value = syscall3(prelude::SYS_SEEK, fd, offset, whence as usize);
     isize_as_syscall_result(value as _)
@@ -773,8 +955,124 @@ pub fn sys_info(struc: &mut SysInfo) -> Result<usize, SyscallError> {
     isize_as_syscall_result(value as _)
 }
 
-pub fn sys_clone(entry: usize, stack: usize) -> Result<usize, SyscallError> {
-    let value = syscall2(prelude::SYS_CLONE, entry, stack);
+pub fn sys_setpgid(pid: usize, pgid: usize) -> Result<usize, SyscallError> {
+    let value = syscall2(prelude::SYS_SETPGID, pid, pgid);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_getpgid(pid: usize) -> Result<usize, SyscallError> {
+    let value = syscall1(prelude::SYS_GETPGID, pid);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_setsid() -> Result<usize, SyscallError> {
+    let value = syscall0(prelude::SYS_SETSID);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_getsid(pid: usize) -> Result<usize, SyscallError> {
+    let value = syscall1(prelude::SYS_GETSID, pid);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_syslog(action: usize, buffer: &mut [u8]) -> Result<usize, SyscallError> {
+    let value = syscall3(
+        prelude::SYS_SYSLOG,
+        action,
+        buffer.as_mut_ptr() as usize,
+        buffer.len(),
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_perf_event_open(attr: &PerfEventAttr) -> Result<usize, SyscallError> {
+    let value = syscall1(
+        prelude::SYS_PERF_EVENT_OPEN,
+        attr as *const PerfEventAttr as usize,
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_ptrace(
+    request: usize,
+    pid: usize,
+    addr: usize,
+    data: usize,
+) -> Result<usize, SyscallError> {
+    let value = syscall4(prelude::SYS_PTRACE, request, pid, addr, data);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_seccomp(
+    operation: usize,
+    filter: &prelude::SeccompFilter,
+) -> Result<usize, SyscallError> {
+    let value = syscall3(
+        prelude::SYS_SECCOMP,
+        operation,
+        0,
+        filter as *const prelude::SeccompFilter as usize,
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_prctl(option: usize, arg: usize) -> Result<usize, SyscallError> {
+    let value = syscall2(prelude::SYS_PRCTL, option, arg);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_getrlimit(resource: usize, limit: &mut prelude::RLimit) -> Result<usize, SyscallError> {
+    let value = syscall2(
+        prelude::SYS_GETRLIMIT,
+        resource,
+        limit as *mut prelude::RLimit as usize,
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_setrlimit(resource: usize, limit: &prelude::RLimit) -> Result<usize, SyscallError> {
+    let value = syscall2(
+        prelude::SYS_SETRLIMIT,
+        resource,
+        limit as *const prelude::RLimit as usize,
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_unshare(flags: usize) -> Result<usize, SyscallError> {
+    let value = syscall1(prelude::SYS_UNSHARE, flags);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_mount(target: &str, fstype: &str) -> Result<usize, SyscallError> {
+    let value = syscall4(
+        prelude::SYS_MOUNT,
+        target.as_ptr() as usize,
+        target.len(),
+        fstype.as_ptr() as usize,
+        fstype.len(),
+    );
+
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_chroot(path: &str) -> Result<usize, SyscallError> {
+    let value = syscall2(prelude::SYS_CHROOT, path.as_ptr() as usize, path.len());
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_getcpu() -> Result<usize, SyscallError> {
+    let value = syscall0(prelude::SYS_GETCPU);
+    isize_as_syscall_result(value as _)
+}
+
+pub fn sys_clone(entry: usize, stack: usize, tls: usize) -> Result<usize, SyscallError> {
+    let value = syscall3(prelude::SYS_CLONE, entry, stack, tls);
     isize_as_syscall_result(value as _)
 }
 
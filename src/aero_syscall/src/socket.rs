@@ -32,6 +32,17 @@ impl MessageHeader {
         unsafe { Some(&mut *(self.name as *mut T)) }
     }
 
+    pub fn name<T: SocketAddr>(&self) -> Option<&T> {
+        if self.name.is_null() {
+            return None;
+        }
+
+        assert!(self.name_len == core::mem::size_of::<T>());
+
+        // SAFETY: See `name_mut`.
+        unsafe { Some(&*(self.name as *const T)) }
+    }
+
     pub fn iovecs(&self) -> &[IoVec] {
         // SAFETY: We know that the `iovec` pointer is valid, initialized.
         unsafe { core::slice::from_raw_parts(self.iovec, self.iovec_len as usize) }
@@ -59,6 +70,11 @@ impl IoVec {
         unsafe { core::slice::from_raw_parts_mut(self.base, self.len) }
     }
 
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: We know that the `base` pointer is valid and initialized.
+        unsafe { core::slice::from_raw_parts(self.base, self.len) }
+    }
+
     /// Returns the length of the I/O vector.
     pub fn len(&self) -> usize {
         self.len
@@ -0,0 +1,84 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The vDSO (virtual dynamic shared object) is a small image that the kernel maps into
+//! every user address space. It exports routines that can be called directly from userland
+//! without entering the kernel, backed by a data page (the *vvar* page) that is updated by
+//! the kernel.
+//!
+//! ## Layout
+//!
+//! ```text
+//! base + 0x0000: vvar page (read-only, see [`VdsoData`])
+//! base + 0x1000: vDSO image (read + execute, starts with a [`VdsoHeader`])
+//! ```
+//!
+//! The base address of the mapping is passed to the program through the auxiliary vector
+//! with the [`AT_AERO_VDSO`] key.
+
+use crate::TimeSpec;
+
+/// Auxiliary vector key that holds the base address of the vDSO mapping.
+pub const AT_AERO_VDSO: usize = 0x1000;
+
+/// Magic number at the start of the [`VdsoHeader`] (`"AEROVDSO"`).
+pub const VDSO_MAGIC: u64 = 0x4f53_4456_4f52_4541;
+pub const VDSO_VERSION: u64 = 1;
+
+/// Size of the vvar page. The vDSO image is located right after it.
+pub const VVAR_SIZE: usize = 0x1000;
+
+/// Set in [`VdsoData::flags`] if the CPU supports `rdtscp` (CPUID.80000001h:EDX[27]). The
+/// kernel then stores the CPU ID in `IA32_TSC_AUX` and `getcpu` does not have to enter the
+/// kernel.
+pub const VDSO_FLAG_RDTSCP: u32 = 1 << 0;
+
+/// Header located at the start of the vDSO image. All of the function offsets
+/// are relative to the start of the image.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct VdsoHeader {
+    pub magic: u64,
+    pub version: u64,
+
+    /// `fn(clock: usize, timespec: *mut TimeSpec) -> usize`
+    ///
+    /// Has the same semantics and return value as [`crate::consts::SYS_GETTIME`].
+    /// Unsupported clocks fall back to the system call.
+    pub clock_gettime: u64,
+
+    /// `fn(cpu: *mut u32) -> usize`
+    ///
+    /// Stores the ID of the CPU the caller is running on in `cpu` (if non-null).
+    pub getcpu: u64,
+}
+
+/// Kernel-updated data shared with the vDSO. The clocks are protected by a sequence
+/// counter: it is odd while the kernel is updating the page, and readers must retry if
+/// it changed (or was odd) while they were reading.
+#[derive(Debug)]
+#[repr(C)]
+pub struct VdsoData {
+    pub seq: u32,
+    /// The `VDSO_FLAG_*` flags, set once at boot.
+    pub flags: u32,
+
+    pub realtime: TimeSpec,
+    pub monotonic: TimeSpec,
+}
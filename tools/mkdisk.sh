@@ -0,0 +1,49 @@
+#!/bin/bash
+
+# Creates a bootable GPT disk image with a FAT32 ESP (Limine and the kernel) and an
+# ext2 root partition populated from the sysroot. The image can be booted with
+# `-drive format=raw` or written to a USB stick with dd(1). Unlike mkimage.sh, this
+# does not require root privileges (it uses mtools and `mkfs.ext2 -d`).
+#
+# usage: mkdisk.sh <image> <iso root> [size in MiB]
+
+set -x -e
+
+IMAGE_PATH=$1
+BOOT_ROOT=$2
+IMAGE_SIZE=${3:-4096}
+
+# The ESP spans [1MiB, 65MiB) and the root partition the rest of the image.
+ESP_START=1
+ESP_END=65
+
+# sync the sysroot
+echo "sysroot: syncing base-files"
+cp -r base-files/. sysroot/system-root/
+
+rm -f $IMAGE_PATH
+dd if=/dev/zero bs=1M count=0 seek=$IMAGE_SIZE of=$IMAGE_PATH
+
+parted -s $IMAGE_PATH mklabel gpt
+parted -s $IMAGE_PATH mkpart ESP fat32 ${ESP_START}MiB ${ESP_END}MiB
+parted -s $IMAGE_PATH set 1 esp on
+parted -s $IMAGE_PATH mkpart root ext2 ${ESP_END}MiB 100%
+
+# populate the ESP
+ESP="$IMAGE_PATH@@${ESP_START}M"
+
+# limit the filesystem to the ESP (in 512-byte sectors), it would span the root partition
+# otherwise
+mformat -i $ESP -F -T $(( (ESP_END - ESP_START) * 2048 )) ::
+# copy the whole boot root, so that the modules (initramfs, symbol map...) are included
+mcopy -s -i $ESP $BOOT_ROOT/* ::/
+
+# populate the root partition
+ROOT_DIR=$(mktemp -d)
+cp -r sysroot/system-root/. $ROOT_DIR/
+mkdir -p $ROOT_DIR/{dev,home,tmp,proc,sys,var,mnt}
+
+ROOT_SIZE=$(( IMAGE_SIZE - ESP_END - 1 ))
+mkfs.ext2 -F -I128 -d $ROOT_DIR -E offset=$(( ESP_END * 1024 * 1024 )) $IMAGE_PATH ${ROOT_SIZE}M
+
+rm -rf $ROOT_DIR
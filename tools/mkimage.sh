@@ -39,6 +39,7 @@ $SUID_BINARY mkdir -p dev
 $SUID_BINARY mkdir -p home
 $SUID_BINARY mkdir -p tmp
 $SUID_BINARY mkdir -p proc
+$SUID_BINARY mkdir -p sys
 $SUID_BINARY mkdir -p var
 $SUID_BINARY mkdir -p mnt
 popd
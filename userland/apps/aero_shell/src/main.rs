@@ -171,6 +171,7 @@ fn repl(history: &mut Vec<String>) -> Result<(), SyscallError> {
             }
 
             "shutdown" => sys_shutdown(),
+            "reboot" => sys_reboot(),
 
             "doom" => {
                 let child = sys_fork()?;
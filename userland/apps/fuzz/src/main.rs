@@ -0,0 +1,190 @@
+/*
+ * Copyright (C) 2021-2022 The Aero Project Developers.
+ *
+ * This file is part of The Aero Project.
+ *
+ * Aero is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Aero is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Aero. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A system call fuzzer, booted as the init process with `./aero.py --fuzz`.
+//!
+//! Each round runs in a forked child, which makes [`CALLS_PER_ROUND`] system calls with
+//! random numbers and arguments and then exits. A child killed by one of its calls only
+//! ends its round, while a kernel panic ends the run. The seed of the run is read from the
+//! `fuzz.seed=<seed>` kernel command line option and every call is printed before it is
+//! made, so that the calls leading to a crash can be found in the serial log.
+
+use std::io::Write;
+
+use aero_syscall::prelude::*;
+use aero_syscall::*;
+
+const CALLS_PER_ROUND: usize = 1000;
+const SCRATCH_SIZE: usize = 0x2000;
+
+/// The system calls that are never made: the ones that end the process or the system,
+/// replace the process image, create processes, block indefinitely or change the state
+/// of the whole system.
+const SKIPPED: &[usize] = &[
+    SYS_READ,
+    SYS_SHUTDOWN,
+    SYS_EXIT,
+    SYS_FORK,
+    SYS_REBOOT,
+    SYS_EXEC,
+    SYS_WAITPID,
+    SYS_ACCEPT,
+    SYS_SLEEP,
+    SYS_CLONE,
+    SYS_SIGRETURN,
+    SYS_IPC_RECV,
+    SYS_IPC_BECOME_ROOT,
+    SYS_EPOLL_PWAIT,
+    SYS_KILL,
+    SYS_FUTEX_WAIT,
+    SYS_POLL,
+    SYS_EXIT_THREAD,
+    SYS_SOCK_RECV,
+    SYS_SETTIME,
+    SYS_PTRACE,
+    SYS_SECCOMP,
+    SYS_MOUNT,
+    SYS_CHROOT,
+];
+
+/// Prints to stdout, ignoring the errors (the fuzzed calls may close it).
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        let _ = writeln!(std::io::stdout(), $($arg)*);
+    }};
+}
+
+/// A xorshift64 pseudo-random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero.
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Returns a random argument, biased towards the values that exercise the argument
+/// checks of the system calls.
+fn argument(rng: &mut Rng, scratch: &mut [u8]) -> usize {
+    let scratch_start = scratch.as_mut_ptr() as usize;
+
+    match rng.below(10) {
+        0 => 0,
+        1 => usize::MAX,
+        2 => rng.below(64) as usize,
+        3 => 1 << rng.below(64),
+        // Pointers into (and around the end of) a valid buffer.
+        4 | 5 => scratch_start + rng.below(SCRATCH_SIZE as u64 + 0x100) as usize,
+        // A kernel address and a non-canonical address.
+        6 => 0xffff_8000_0000_0000 + (rng.below(0x1000_0000) as usize & !0xfff),
+        7 => 0x8000_0000_0000_0000,
+        _ => rng.next() as usize,
+    }
+}
+
+fn run_round(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let mut scratch = vec![0u8; SCRATCH_SIZE];
+
+    for _ in 0..CALLS_PER_ROUND {
+        // Also make the calls past the last system call number.
+        let number = rng.below(SYS_GETCPU as u64 + 8) as usize;
+
+        if SKIPPED.contains(&number) {
+            continue;
+        }
+
+        let mut args = [0; 6];
+
+        for arg in args.iter_mut() {
+            *arg = argument(&mut rng, &mut scratch);
+        }
+
+        trace!("fuzz: call {} {:x?}", number, args);
+
+        let [a, b, c, d, e, f] = args;
+        let result = syscall6(number, a, b, c, d, e, f);
+
+        trace!("fuzz: returned {:#x}", result);
+    }
+}
+
+/// Returns the value of the `fuzz.seed=<seed>` option from `/proc/cmdline`, which holds the
+/// command line as a JSON object (`{"cmdline":"..."}`).
+fn read_seed() -> u64 {
+    let cmdline = std::fs::read_to_string("/proc/cmdline").expect("failed to read /proc/cmdline");
+    let cmdline = cmdline
+        .trim()
+        .strip_prefix("{\"cmdline\":\"")
+        .and_then(|cmdline| cmdline.strip_suffix("\"}"))
+        .unwrap_or_else(|| panic!("unexpected /proc/cmdline contents: {}", cmdline));
+
+    let seed = cmdline
+        .split_whitespace()
+        .find_map(|arg| arg.strip_prefix("fuzz.seed="))
+        .expect("`fuzz.seed=<seed>` is missing from the kernel command line");
+
+    seed.parse()
+        .unwrap_or_else(|_| panic!("invalid `fuzz.seed` value: {}", seed))
+}
+
+fn main() {
+    sys_open("/dev/tty", OpenFlags::O_RDONLY).expect("Failed to open stdin");
+    sys_open("/dev/tty", OpenFlags::O_WRONLY).expect("Failed to open stdout");
+    sys_open("/dev/tty", OpenFlags::O_WRONLY).expect("Failed to open stderr");
+
+    let seed = read_seed();
+    let mut rng = Rng::new(seed);
+
+    trace!("fuzz: seed {}", seed);
+
+    for round in 0.. {
+        let round_seed = rng.next();
+        trace!("fuzz: round {} seed {}", round, round_seed);
+
+        match sys_fork() {
+            Ok(0) => {
+                run_round(round_seed);
+                sys_exit(0);
+            }
+
+            Ok(pid) => {
+                let mut status = 0;
+
+                if sys_waitpid(pid, &mut status, 0).is_ok() && status != 0 {
+                    trace!("fuzz: round {} exited with status {:#x}", round, status);
+                }
+            }
+
+            Err(err) => trace!("fuzz: fork failed: {:?}", err),
+        }
+    }
+}
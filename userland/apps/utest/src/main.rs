@@ -275,7 +275,7 @@ fn clone_process() -> Result<(), SyscallError> {
     }
 
     // Create the child process.
-    let child = sys_clone(cloned_process_start as usize, stack_ptr as usize)?;
+    let child = sys_clone(cloned_process_start as usize, stack_ptr as usize, 0)?;
 
     let mut status = 0;
     sys_waitpid(child, &mut status, 0)?;